@@ -1,10 +1,17 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 
-use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use stacked_errors::{ensure, ensure_eq, Error, Result, StackableErr};
 use super_orchestrator::{
-    docker::{Container, ContainerNetwork, Dockerfile},
+    assertions::expect_stdout,
+    docker::{
+        up, Container, ContainerNetwork, Dockerfile, PullPolicy, ReadinessProbe,
+        ResourceConstraints, TerminateOutcome, UpOptions,
+    },
+    docker_helpers::{check_checkpoint_support, pull_image, pull_image_if_not_present},
     net_message::wait_for_ok_lookup_host,
+    sh, wait_for_ok, FileOptions,
 };
+use tokio::time::{sleep, timeout};
 use tracing::info;
 
 const BASE_CONTAINER: &str = "fedora:40";
@@ -97,6 +104,13 @@ async fn main() -> Result<()> {
             .entrypoint("/usr/bin/sleep", ["3"]),
     )
     .stack()?;
+    // infrastructure containers are not waited on by `wait_with_timeout_all`
+    cn.add_container(
+        Container::new("example5_infra", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sleep", ["infinity"])
+            .infrastructure(true),
+    )
+    .stack()?;
     // run all containers
     cn.run_all().await.stack()?;
 
@@ -111,6 +125,15 @@ async fn main() -> Result<()> {
         .stack()?;
     dbg!(&host_ip);
 
+    // the uptime of active containers can be tracked
+    let uptime0 = cn.container_uptime("example5").stack()?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let uptime1 = cn.container_uptime("example5").stack()?;
+    ensure!(uptime1 > uptime0);
+    ensure_eq!(cn.oldest_active().stack()?.0, "example5");
+    // `docker inspect` results are batched and cached by the network
+    ensure_eq!(cn.status("example5").await.stack()?, "running");
+
     // use port 0 to just detect that the host container exists
     wait_for_ok_lookup_host(2, Duration::from_millis(300), &format!("{host_ip:?}:0"))
         .await
@@ -118,8 +141,770 @@ async fn main() -> Result<()> {
 
     // wait for all containers to stop
     cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    // `try_terminate` reports what happened to each name
+    let outcomes = cn
+        .try_terminate(["example5", "example5_infra", "nonexistent"])
+        .await;
+    ensure!(matches!(
+        outcomes["example5"],
+        TerminateOutcome::WasNotActive
+    ));
+    ensure!(matches!(
+        outcomes["example5_infra"],
+        TerminateOutcome::Terminated(_)
+    ));
+    ensure!(matches!(
+        outcomes["nonexistent"],
+        TerminateOutcome::NotFound
+    ));
     // always run this at the end, ensuring the containers are logically terminated
     cn.terminate_all().await;
 
+    info!("\n\nexample 5b\n");
+
+    // containers can also be polled for completion in custom loops
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example5b", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sleep", ["1"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    ensure!(!cn.all_containers_finished());
+    let start = std::time::Instant::now();
+    let mut finished = vec![];
+    while finished.is_empty() {
+        ensure!(start.elapsed() < TIMEOUT);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        finished = cn.try_collect_finished().await;
+    }
+    ensure_eq!(finished, ["example5b"]);
+    ensure!(cn.all_containers_finished());
+    ensure!(matches!(
+        cn.get_result("example5b"),
+        Some(Ok(comres)) if comres.successful()
+    ));
+    cn.terminate_all().await;
+
+    info!("\n\nexample 5c\n");
+
+    // the wait functions are cancel safe, an outer timeout can interrupt them at
+    // any point and they can be called again
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example5c_fast", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sleep", ["1"]),
+    )
+    .stack()?;
+    cn.add_container(
+        Container::new("example5c_slow", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sleep", ["3"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let wait = cn.wait_with_timeout(["example5c_fast", "example5c_slow"], true, TIMEOUT);
+        if let Ok(res) = timeout(Duration::from_millis(200), wait).await {
+            res.stack()?;
+            break
+        }
+    }
+    ensure!(attempts > 1);
+    ensure!(matches!(
+        cn.get_result("example5c_slow"),
+        Some(Ok(comres)) if comres.successful()
+    ));
+    cn.terminate_all().await;
+
+    // failures found by a cancelled wait are not lost
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example5c_fail", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sh", ["-c", "sleep 1; exit 1"]),
+    )
+    .stack()?;
+    cn.add_container(
+        Container::new("example5c_other", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sleep", ["infinity"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    // increasing delays so that cancellation happens at many different points
+    let mut delay = Duration::from_millis(50);
+    let res = loop {
+        match timeout(delay, cn.wait_with_timeout_all(true, TIMEOUT)).await {
+            Ok(res) => break res,
+            Err(_) => delay += Duration::from_millis(50),
+        }
+    };
+    ensure!(res.is_err());
+    ensure!(cn.active_names().is_empty());
+    cn.terminate_all().await;
+
+    info!("\n\nexample 5d\n");
+
+    // the results of finished containers can be inspected through the accessors
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example5d", Dockerfile::name_tag(BASE_CONTAINER))
+            .allow_unsuccessful(true)
+            .entrypoint("/usr/bin/sh", ["-c", "echo result; exit 5"]),
+    )
+    .stack()?;
+    ensure!(cn.get_result("example5d").is_none());
+    ensure!(cn.get_result("nonexistent").is_none());
+    cn.run_all().await.stack()?;
+    ensure!(cn.results().is_empty());
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let comres = cn.get_result("example5d").unwrap().as_ref().unwrap();
+    ensure_eq!(comres.status.unwrap().code(), Some(5));
+    ensure_eq!(comres.stdout_as_utf8_lossy().trim(), "result");
+    let results = cn.results();
+    ensure_eq!(results.keys().collect::<Vec<_>>(), ["example5d"]);
+    ensure_eq!(
+        results["example5d"]
+            .as_ref()
+            .unwrap()
+            .status
+            .unwrap()
+            .code(),
+        Some(5)
+    );
+    cn.terminate_all().await;
+
+    info!("\n\nexample 5e\n");
+
+    // containers are attached without a tty, so docker keeps the streams separate
+    // and stdout can be parsed without stderr noise
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example5e", Dockerfile::name_tag(BASE_CONTAINER)).entrypoint(
+            "/usr/bin/sh",
+            ["-c", "echo '{\"marker\": 1}'; echo stderr_marker >&2"],
+        ),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let comres = cn.get_result("example5e").unwrap().as_ref().unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&comres.stdout).stack()?;
+    ensure_eq!(json["marker"], 1);
+    ensure_eq!(comres.stderr_as_utf8().stack()?, "stderr_marker\n");
+    cn.terminate_all().await;
+
+    info!("\n\nexample 6\n");
+
+    // files can be extracted from images right after they are built
+    let mut cn = ContainerNetwork::new("test", Some("./dockerfiles"), logs_dir);
+    cn.add_container(
+        Container::new(
+            "example6",
+            Dockerfile::contents(format!(
+                "FROM {BASE_CONTAINER}\nRUN echo -n \"hello from artifact.txt\" > /artifact.txt\n"
+            )),
+        )
+        .entrypoint("/usr/bin/cat", ["/artifact.txt"])
+        .extract_after_build("/artifact.txt", logs_dir),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    let extracted = cn.extracted_paths("example6").stack()?;
+    ensure_eq!(
+        FileOptions::read_to_string(&extracted[0]).await.stack()?,
+        "hello from artifact.txt"
+    );
+
+    info!("\n\nexample 7\n");
+
+    // crash artifacts can be captured from containers that exit with a signal
+    // status, here a child process writes to the crash directory and aborts
+    let crash_dir = format!("{logs_dir}/example7_crash");
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example7", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sh", [
+                "-c",
+                "sh -c 'echo crashed > $SUPER_ORCHESTRATOR_CRASH_DIR/crash.txt; kill -ABRT $$'; \
+                 exit $?",
+            ])
+            .allow_unsuccessful(true)
+            .capture_crash_artifacts(&crash_dir),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    let crash_artifacts = cn.crash_artifacts("example7").stack()?;
+    ensure!(crash_artifacts.iter().any(|p| p.ends_with("crash.txt")));
+
+    info!("\n\nexample 8\n");
+
+    // exit codes other than 0 can be expected outcomes
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example8", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sh", ["-c", "exit 42"])
+            .success_codes([0, 42]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    // the actual exit code is still reported
+    ensure!(matches!(
+        cn.get_result("example8"),
+        Some(Ok(comres)) if comres.status.and_then(|s| s.code()) == Some(42)
+    ));
+    cn.terminate_all().await;
+
+    // but other codes still fail the network
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example8", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sh", ["-c", "exit 42"])
+            .success_codes([0, 42]),
+    )
+    .stack()?;
+    cn.add_container(
+        Container::new("example8_sibling", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sh", ["-c", "exit 1"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    ensure!(cn.wait_with_timeout_all(true, TIMEOUT).await.is_err());
+    cn.terminate_all().await;
+
+    info!("\n\nexample 9\n");
+
+    // output from verbose containers can be capped network-wide
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.max_log_size("1KiB")
+        .stack()?
+        .max_record_size(1024)
+        .stack()?;
+    cn.add_container(
+        Container::new("example9", Dockerfile::name_tag(BASE_CONTAINER))
+            .log(true)
+            .entrypoint("/usr/bin/sh", [
+                "-c",
+                "head -c 100000 /dev/zero | tr '\\0' e",
+            ]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    ensure!(matches!(
+        cn.get_result("example9"),
+        Some(Ok(comres)) if comres.stdout.len() == 1024
+    ));
+    ensure!(
+        FileOptions::read_to_string(&format!("{logs_dir}/example9_stdout.log"))
+            .await
+            .stack()?
+            .len()
+            <= 1024
+    );
+
+    info!("\n\nexample 10\n");
+
+    // built images can be archived and loaded again later
+    let mut cn = ContainerNetwork::new("test", Some("./dockerfiles"), logs_dir);
+    cn.add_container(
+        Container::new(
+            "example10",
+            Dockerfile::contents(format!(
+                "FROM {BASE_CONTAINER}\nRUN echo -n \"hello from the archive\" > /archive.txt\n"
+            )),
+        )
+        .entrypoint("/usr/bin/cat", ["/archive.txt"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    let tar_path = format!("{logs_dir}/example10_images.tar");
+    cn.export_images_tar(&["example10"], &tar_path)
+        .await
+        .stack()?;
+    let image = cn
+        .get_container("example10")
+        .stack()?
+        .build_tag
+        .clone()
+        .stack()?;
+    sh(["docker rmi", &image]).await.stack()?;
+    let loaded = ContainerNetwork::import_images_tar(&tar_path)
+        .await
+        .stack()?;
+    ensure!(loaded.iter().any(|s| s.starts_with(&image)));
+    let comres = Container::new("example10", Dockerfile::name_tag(&image))
+        .entrypoint("/usr/bin/cat", ["/archive.txt"])
+        .run(None, TIMEOUT, logs_dir, false)
+        .await
+        .stack()?;
+    ensure_eq!(comres.stdout_as_utf8().stack()?, "hello from the archive");
+
+    info!("\n\nexample 11\n");
+
+    // errors from `run` say which phase failed and what was cleaned up
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    for (name, create_args) in [
+        ("example11_a", vec![]),
+        ("example11_b", vec!["--nonexistent-flag"]),
+        ("example11_c", vec![]),
+    ] {
+        cn.add_container(
+            Container::new(name, Dockerfile::name_tag(BASE_CONTAINER))
+                .create_args(create_args)
+                .entrypoint("/usr/bin/sleep", ["infinity"]),
+        )
+        .stack()?;
+    }
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("failed in phase Create(\"example11_b\")"));
+    ensure!(e.contains("containers cleaned up: [\"example11_a\"]"));
+    ensure!(cn.active_names().is_empty());
+    cn.terminate_all().await;
+
+    info!("\n\nexample 11b\n");
+
+    // resource usage can be monitored while containers run, `tail` holds the
+    // whole line in memory while waiting for the end of its input
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example11b", Dockerfile::name_tag(BASE_CONTAINER)).entrypoint(
+            "/usr/bin/sh",
+            ["-c", "(head -c 200m /dev/zero; sleep 5) | tail"],
+        ),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    let monitor = cn.monitor_resource_usage(Duration::from_millis(500));
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let report = monitor.stop().await;
+    cn.terminate_all().await;
+    ensure!(report.containers["example11b"].peak_memory > 100_000_000);
+    ensure!(report.containers["example11b"].average_cpu().is_some());
+
+    info!("\n\nexample 12\n");
+
+    // containers can be suspended and resumed with checkpoints if the docker daemon
+    // is experimental and CRIU is installed
+    if let Err(e) = check_checkpoint_support().await {
+        info!("skipping example 12, checkpoints are not supported: {e:?}");
+    } else {
+        let mut cn = ContainerNetwork::new("test", None, logs_dir);
+        cn.add_container(
+            Container::new("example12", Dockerfile::name_tag(BASE_CONTAINER))
+                .log(true)
+                .checkpointable(true)
+                .entrypoint("/usr/bin/sh", [
+                    "-c",
+                    "i=0; while [ $i -lt 6 ]; do echo $i; i=$((i+1)); sleep 1; done",
+                ]),
+        )
+        .stack()?;
+        cn.add_container(
+            Container::new("example12_other", Dockerfile::name_tag(BASE_CONTAINER))
+                .entrypoint("/usr/bin/sleep", ["infinity"]),
+        )
+        .stack()?;
+        cn.run_all().await.stack()?;
+        sleep(Duration::from_secs(2)).await;
+        let report = cn.checkpoint_all("example12_checkpoint").await.stack()?;
+        ensure_eq!(report.checkpointed, ["example12"]);
+        ensure_eq!(report.skipped, ["example12_other"]);
+        ensure!(cn.active_names() == ["example12_other"]);
+        ensure!(cn.run(["example12"]).await.is_err());
+        ensure_eq!(cn.restore_all("example12_checkpoint").await.stack()?, [
+            "example12"
+        ]);
+        cn.wait_with_timeout(["example12"], true, TIMEOUT)
+            .await
+            .stack()?;
+        cn.terminate_all().await;
+        // the count continues after the restore instead of starting over
+        let stdout = FileOptions::read_to_string(&format!("{logs_dir}/example12_stdout.log"))
+            .await
+            .stack()?;
+        ensure!(stdout.contains('5'));
+        ensure_eq!(stdout.matches('0').count(), 1);
+    }
+
+    info!("\n\nexample 13\n");
+
+    // a single run can use different configurations for some containers
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    let stage = |stage: &str| {
+        Container::new("example13", Dockerfile::name_tag(BASE_CONTAINER))
+            .environment_vars([("STAGE", stage)])
+            .entrypoint("/usr/bin/sh", ["-c", "echo $STAGE"])
+    };
+    let original = stage("production");
+    cn.add_container(original.clone()).stack()?;
+    let overrides = BTreeMap::from([("example13".to_owned(), stage("staging"))]);
+    cn.run_with_overrides(["example13"], overrides)
+        .await
+        .stack()?;
+    ensure_eq!(cn.get_container("example13"), Some(&original));
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let comres = cn.get_result("example13").unwrap().as_ref().unwrap();
+    ensure_eq!(comres.stdout_as_utf8_lossy().trim(), "staging");
+    cn.terminate_all().await;
+
+    info!("\n\nexample 14\n");
+
+    // images can be pulled ahead of time, this only pulls if the image is missing
+    pull_image_if_not_present(BASE_CONTAINER).await.stack()?;
+    pull_image(BASE_CONTAINER, true).await.stack()?;
+
+    info!("\n\nexample 15\n");
+
+    // the root filesystem can be read-only, with tmpfs mounts for writable paths
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    let read_only = |name: &str, path: &str| {
+        Container::new(name, Dockerfile::name_tag(BASE_CONTAINER))
+            .read_only_rootfs(true)
+            .tmpfs("/tmp")
+            .allow_unsuccessful(true)
+            .entrypoint("/usr/bin/touch", [path])
+    };
+    cn.add_container(read_only("example15_root", "/file"))
+        .stack()?;
+    cn.add_container(read_only("example15_tmp", "/tmp/file"))
+        .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let successful = |name| {
+        cn.get_result(name)
+            .unwrap()
+            .as_ref()
+            .is_ok_and(|comres| comres.successful())
+    };
+    ensure!(!successful("example15_root"));
+    ensure!(successful("example15_tmp"));
+    cn.terminate_all().await;
+
+    info!("\n\nexample 16\n");
+
+    // containers from other networks can be connected to a network
+    let mut server_cn = ContainerNetwork::new("example16_network", None, logs_dir);
+    server_cn
+        .add_container(
+            Container::new("example16_server", Dockerfile::name_tag(BASE_CONTAINER))
+                .entrypoint("/usr/bin/python3", ["-m", "http.server", "8080"]),
+        )
+        .stack()?;
+    server_cn.run_all().await.stack()?;
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example16_client", Dockerfile::name_tag(BASE_CONTAINER)).entrypoint(
+            "/usr/bin/python3",
+            [
+                "-c",
+                "import time, urllib.request\nfor _ in range(60):\n    try:\n        \
+                 urllib.request.urlopen('http://server:8080')\n        break\n    except \
+                 OSError:\n        time.sleep(0.5)\nelse:\n    raise SystemExit(1)",
+            ],
+        ),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.connect_external_container("example16_server", Some("server"))
+        .await
+        .stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.disconnect_external_container("example16_server")
+        .await
+        .stack()?;
+    cn.terminate_all().await;
+    server_cn.terminate_all().await;
+
+    info!("\n\nexample 17\n");
+
+    // fake hostnames can be resolved without DNS
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example17", Dockerfile::name_tag(BASE_CONTAINER))
+            .add_host("fake.example", "10.1.2.3".parse().unwrap())
+            .entrypoint("/usr/bin/getent", ["hosts", "fake.example"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    expect_stdout(cn.expect("example17").stack()?)
+        .contains("10.1.2.3")
+        .stack()?;
+    cn.terminate_all().await;
+
+    info!("\n\nexample 18\n");
+
+    // a run can have resource limits without changing the container
+    // configurations, here `tail` goes over the memory limit and is OOM killed
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    cn.add_container(
+        Container::new("example18", Dockerfile::name_tag(BASE_CONTAINER)).entrypoint(
+            "/usr/bin/sh",
+            ["-c", "(head -c 200m /dev/zero; sleep 5) | tail"],
+        ),
+    )
+    .stack()?;
+    let limits = ResourceConstraints {
+        max_memory_mb: Some(32),
+        ..Default::default()
+    };
+    cn.run_with_resource_limits(&["example18"], limits)
+        .await
+        .stack()?;
+    ensure!(cn.wait_with_timeout_all(true, TIMEOUT).await.is_err());
+    let comres = cn.get_result("example18").unwrap().as_ref().unwrap();
+    ensure_eq!(comres.status.and_then(|status| status.code()), Some(137));
+    ensure!(cn
+        .get_container("example18")
+        .unwrap()
+        .create_args
+        .is_empty());
+    cn.terminate_all().await;
+
+    info!("\n\nexample 19\n");
+
+    // readiness can be checked from the host side, which also works for images
+    // without a shell
+    let mut cn = ContainerNetwork::new("test", None, logs_dir);
+    let interval = Duration::from_millis(300);
+    cn.add_container(
+        Container::new("example19_http", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/python3", ["-m", "http.server", "8000"])
+            .readiness(
+                ReadinessProbe::HttpGet {
+                    port: 8000,
+                    path: "/".to_owned(),
+                    expect_status: 200,
+                },
+                interval,
+                Duration::from_secs(2),
+            ),
+    )
+    .stack()?;
+    cn.add_container(
+        Container::new("example19_exec", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sh", ["-c", "sleep 2; touch /ready; sleep 100"])
+            .readiness(
+                ReadinessProbe::Exec(vec![
+                    "/usr/bin/test".to_owned(),
+                    "-f".to_owned(),
+                    "/ready".to_owned(),
+                ]),
+                interval,
+                Duration::from_secs(2),
+            ),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_ready(&["example19_http", "example19_exec"], TIMEOUT)
+        .await
+        .stack()?;
+    cn.terminate_all().await;
+
+    info!("\n\nexample 20\n");
+
+    // containers on `internal` networks cannot reach external addresses, this is
+    // skipped if the host itself cannot
+    let external = tokio::net::TcpStream::connect("example.com:80");
+    if matches!(tokio::time::timeout(TIMEOUT, external).await, Ok(Ok(_))) {
+        let curl = |name: &str| {
+            Container::new(name, Dockerfile::name_tag(BASE_CONTAINER))
+                .allow_unsuccessful(true)
+                .entrypoint("/usr/bin/curl", [
+                    "-sS",
+                    "-m",
+                    "5",
+                    "-o",
+                    "/dev/null",
+                    "http://example.com",
+                ])
+        };
+        let mut cn = ContainerNetwork::new("example20_external", None, logs_dir);
+        cn.add_container(curl("example20_external")).stack()?;
+        let mut internal_cn = ContainerNetwork::new("example20_internal", None, logs_dir);
+        internal_cn.internal(true);
+        internal_cn
+            .add_container(curl("example20_internal"))
+            .stack()?;
+        cn.run_all().await.stack()?;
+        internal_cn.run_all().await.stack()?;
+        cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+        internal_cn
+            .wait_with_timeout_all(true, TIMEOUT)
+            .await
+            .stack()?;
+        ensure!(cn.expect("example20_external").stack()?.successful());
+        ensure!(!internal_cn
+            .expect("example20_internal")
+            .stack()?
+            .successful());
+        cn.terminate_all().await;
+        internal_cn.terminate_all().await;
+    } else {
+        info!("skipping example 20 because example.com is not reachable from the host");
+    }
+
+    info!("\n\nexample 21\n");
+
+    // inspecting what a running container wrote to its filesystem
+    let mut cn = ContainerNetwork::new("example21", None, logs_dir);
+    cn.add_container(
+        Container::new("example21", Dockerfile::name_tag(BASE_CONTAINER)).entrypoint("/bin/sh", [
+            "-c",
+            "mkdir -p /srv && touch /srv/app.toml && sleep 60",
+        ]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    wait_for_ok(20, Duration::from_millis(500), || {
+        cn.assert_created("example21", "/srv/app.toml")
+    })
+    .await
+    .stack()?;
+    ensure!(cn
+        .fs_diff("example21")
+        .await
+        .stack()?
+        .iter()
+        .all(|change| change.path() != "/srv/other.toml"));
+    cn.terminate_all().await;
+
+    // containers are created with `--rm`, so docker removes them once they exit
+    // and there is nothing left to diff
+    let mut cn = ContainerNetwork::new("example21", None, logs_dir);
+    cn.add_container(
+        Container::new("example21b", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/bin/sh", ["-c", "touch /srv_file"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    // the removal happens asynchronously after the exit
+    let e = wait_for_ok(20, Duration::from_millis(500), || async {
+        match cn.fs_diff("example21b").await {
+            Ok(diff) => Err(Error::from(format!("still exists with diff {diff:?}"))),
+            Err(e) => Ok(format!("{e:?}")),
+        }
+    })
+    .await
+    .stack()?;
+    ensure!(e.contains("no longer exists"), "{e}");
+    cn.terminate_all().await;
+
+    info!("\n\nexample 22\n");
+
+    // `PullPolicy::Always` pulls in the build phase on every run, which must not
+    // fail when the image is already up to date
+    let mut cn = ContainerNetwork::new("example22", None, logs_dir);
+    cn.add_container(
+        Container::new("example22", Dockerfile::name_tag(BASE_CONTAINER))
+            .pull_policy(PullPolicy::Always)
+            .entrypoint_args(["echo", "pulled"]),
+    )
+    .stack()?;
+    for _ in 0..2 {
+        cn.run_all().await.stack()?;
+        cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    }
+    cn.terminate_all().await;
+
+    info!("\n\nexample 23\n");
+
+    // streaming a fixture into a container and back out without host temp files
+    let mut cn = ContainerNetwork::new("example23", None, logs_dir);
+    cn.add_container(
+        Container::new("example23", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint_args(["sleep", "60"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    let fixture = vec![7u8; 16 * 1024 * 1024];
+    let sent = wait_for_ok(20, Duration::from_millis(500), || {
+        cn.stream_to_file("example23", &fixture[..], "/tmp/fixture.bin", 0o644)
+    })
+    .await
+    .stack()?;
+    ensure_eq!(sent.bytes, fixture.len() as u64);
+    cn.verify_sha256("example23", "/tmp/fixture.bin", &sent.sha256)
+        .await
+        .stack()?;
+    let mut received = vec![];
+    let transfer = cn
+        .stream_from_file("example23", "/tmp/fixture.bin", &mut received)
+        .await
+        .stack()?;
+    ensure_eq!(transfer, sent);
+    ensure!(received == fixture);
+    cn.terminate_all().await;
+
+    info!("\n\nexample 24\n");
+
+    // `up` is the recommended entry point for simple environments, it creates the
+    // network, runs the containers, and waits for their readiness probes in one
+    // call
+    let env = up(
+        vec![
+            Container::new("example24_server", Dockerfile::name_tag(BASE_CONTAINER))
+                .entrypoint_args(["python3", "-m", "http.server", "8080"])
+                .readiness(
+                    ReadinessProbe::TcpPort(8080),
+                    Duration::from_millis(500),
+                    Duration::from_secs(5),
+                ),
+            Container::new("example24_client", Dockerfile::name_tag(BASE_CONTAINER))
+                .entrypoint_args(["sleep", "60"]),
+        ],
+        UpOptions::new().network_name("example24"),
+    )
+    .await
+    .stack()?;
+    let url = env.url_for("example24_server", 8080).stack()?;
+    let comres = env
+        .exec("example24_client", ["curl", "-sf", &url])
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    env.down().await.stack()?;
+
+    info!("\n\nexample 25\n");
+
+    // detached containers that have their logs followed are not created with
+    // `--rm`, so their exit code can be inspected and they can be diffed after
+    // they exit
+    let mut cn = ContainerNetwork::new("example25", None, logs_dir);
+    cn.add_container(
+        Container::new("example25", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint_args([
+                "sh",
+                "-c",
+                "touch /tmp/example25 && echo detached && exit 3",
+            ])
+            .detached(true)
+            .log(true)
+            .success_codes([3]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let comres = cn.get_result("example25").unwrap().as_ref().unwrap();
+    ensure_eq!(comres.status.and_then(|status| status.code()), Some(3));
+    ensure_eq!(comres.stdout_as_utf8().stack()?, "detached\n");
+    ensure!(cn
+        .fs_diff("example25")
+        .await
+        .stack()?
+        .iter()
+        .any(|change| change.path() == "/tmp/example25"));
+    cn.terminate_all().await;
+
     Ok(())
 }
@@ -1,9 +1,17 @@
-use std::{io::Write, iter, time::Duration};
+use std::{io::Write, iter, process::Stdio, time::Duration};
 
+use bytes::Bytes;
 use clap::Parser;
 use stacked_errors::{ensure, ensure_eq, StackableErr};
-use super_orchestrator::{remove_files_in_dir, stacked_errors::Result, Command, FileOptions};
-use tokio::time::sleep;
+use super_orchestrator::{
+    emit_json_line, remove_files_in_dir, set_strictness, stacked_errors::Result,
+    take_strictness_violations, Command, FileOptions, Strictness, ENV_PASSTHROUGH_VAR,
+};
+use tokio::{
+    io::AsyncReadExt,
+    sync::mpsc,
+    time::{sleep, timeout},
+};
 use tracing::info;
 
 // this program calls itself to get stdout and stderr examples
@@ -16,8 +24,14 @@ struct Args {
     to_stdout: String,
     #[arg(long, default_value_t = String::new())]
     to_stderr: String,
+    #[arg(long, default_value_t = 1)]
+    repeat: usize,
     #[arg(long)]
     nonutf8: bool,
+    #[arg(long)]
+    echo: bool,
+    #[arg(long)]
+    json_lines: bool,
 }
 
 #[tokio::main]
@@ -68,9 +82,30 @@ async fn main() -> Result<()> {
         return Ok(())
     }
 
+    if args.echo {
+        // echo each line of stdin until it is closed
+        for line in std::io::stdin().lines() {
+            println!("echo: {}", line.stack()?);
+        }
+        return Ok(())
+    }
+
+    if args.json_lines {
+        // what an entrypoint would do for `ContainerNetwork::json_lines_stream`
+        for i in 0..3 {
+            emit_json_line(&serde_json::json!({"i": i, "text": "multi\nline"})).stack()?;
+            sleep(Duration::from_millis(100)).await;
+        }
+        return Ok(())
+    }
+
     if args.print {
-        print!("{}", args.to_stdout);
-        eprint!("{}", args.to_stderr);
+        let mut stdout = std::io::stdout().lock();
+        let mut stderr = std::io::stderr().lock();
+        for _ in 0..args.repeat {
+            stdout.write_all(args.to_stdout.as_bytes()).stack()?;
+            stderr.write_all(args.to_stderr.as_bytes()).stack()?;
+        }
         return Ok(())
     }
 
@@ -102,6 +137,8 @@ async fn main() -> Result<()> {
     .stack()?;
 
     // strange "filename or extension is too long" issue with Windows
+    // (`iter::repeat_n` needs Rust 1.82, which is above the MSRV)
+    #[allow(clippy::manual_repeat_n)]
     let many_bytes = if cfg!(windows) {
         String::from_iter(iter::repeat('e').take(10 * 1024))
     } else {
@@ -122,6 +159,7 @@ async fn main() -> Result<()> {
         .await
         .stack()?;
     comres.assert_success().stack()?;
+    #[allow(clippy::manual_repeat_n)]
     let expected = String::from_iter(iter::repeat('e').take(10 * 1024));
     ensure_eq!(comres.stdout, expected.as_bytes());
     ensure_eq!(comres.stderr, expected.as_bytes());
@@ -164,6 +202,191 @@ async fn main() -> Result<()> {
     dbg!(comres.stderr_as_utf8_lossy());
     dbg!(comres).assert_success().stack()?;
 
+    // large outputs can be written to files or used with other async I/O
+    let comres = Command::new("cargo r --example commands --quiet -- --print")
+        .args(["--to-stdout", "0123456789", "--to-stderr", "e", "--repeat"])
+        .arg("1000000")
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    ensure_eq!(comres.stdout.len(), 10_000_000);
+    comres.stdout_to_file("./logs/stdout.log").await.stack()?;
+    comres.stderr_to_file("./logs/stderr.log").await.stack()?;
+    ensure!(
+        FileOptions::read_to_vec("./logs/stdout.log")
+            .await
+            .stack()?
+            == comres.stdout
+    );
+    ensure!(
+        FileOptions::read_to_vec("./logs/stderr.log")
+            .await
+            .stack()?
+            == comres.stderr
+    );
+    let expected = comres.stdout.clone();
+    let mut read = vec![];
+    comres
+        .into_stdout_reader()
+        .read_to_end(&mut read)
+        .await
+        .stack()?;
+    ensure!(read == expected);
+
+    // `emit_json_line` keeps each value on its own line
+    let comres = Command::new("cargo r --example commands --quiet -- --json-lines")
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    let lines: Vec<&str> = comres.stdout_as_utf8().stack()?.lines().collect();
+    ensure_eq!(lines.len(), 3);
+    for (i, line) in lines.into_iter().enumerate() {
+        let v: serde_json::Value = serde_json::from_str(line).stack()?;
+        ensure_eq!(v["i"], i);
+        ensure_eq!(v["text"], "multi\nline");
+    }
+
+    // stdin can be fed dynamically from a channel, the stdin is closed when the
+    // sender is dropped
+    let (sender, receiver) = mpsc::channel(4);
+    let runner = Command::new("cargo r --example commands --quiet -- --echo")
+        .run_with_channel_stdin(receiver)
+        .await
+        .stack()?;
+    for msg in ["hello\n", "incremental\n", "world\n"] {
+        sender.send(Bytes::from(msg)).await.stack()?;
+        sleep(Duration::from_millis(100)).await;
+    }
+    drop(sender);
+    let comres = runner.wait_with_output().await.stack()?;
+    comres.assert_success().stack()?;
+    ensure_eq!(
+        comres.stdout_as_utf8().stack()?,
+        "echo: hello\necho: incremental\necho: world\n"
+    );
+
+    // `CommandRunner::wait_with_timeout` is cancel safe
+    let mut runner = Command::new("sleep 1").run().await.stack()?;
+    loop {
+        if let Ok(res) = timeout(
+            Duration::from_millis(10),
+            runner.wait_with_timeout(Duration::from_secs(10)),
+        )
+        .await
+        {
+            res.stack()?;
+            break
+        }
+    }
+    runner
+        .take_command_result()
+        .stack()?
+        .assert_success()
+        .stack()?;
+
+    // a stopped process makes no progress until it is resumed with `SIGCONT`
+    #[cfg(feature = "nix_support")]
+    {
+        let mut runner = Command::new("sleep 0.5").run().await.stack()?;
+        runner
+            .send_unix_signal(nix::sys::signal::Signal::SIGSTOP)
+            .stack()?;
+        let e = runner
+            .wait_with_timeout(Duration::from_secs(2))
+            .await
+            .unwrap_err();
+        ensure!(e.is_timeout());
+        runner.send_unix_sigcont().stack()?;
+        runner
+            .wait_with_timeout(Duration::from_secs(10))
+            .await
+            .stack()?;
+        runner
+            .take_command_result()
+            .stack()?
+            .assert_success()
+            .stack()?;
+    }
+
+    // in strict mode, leaking a runner is recorded as a violation
+    set_strictness(Strictness::Strict);
+    let runner = Command::new("cargo r --example commands --quiet -- --echo")
+        .run_with_stdin(Stdio::piped())
+        .await
+        .stack()?;
+    drop(runner);
+    let violations = take_strictness_violations();
+    ensure_eq!(violations.len(), 1);
+    ensure!(violations[0].contains("`CommandRunner` was dropped"));
+    // but only warned about in lenient mode
+    set_strictness(Strictness::Lenient);
+    let runner = Command::new("cargo r --example commands --quiet -- --echo")
+        .run_with_stdin(Stdio::piped())
+        .await
+        .stack()?;
+    drop(runner);
+    ensure!(take_strictness_violations().is_empty());
+
+    // only selected environment variables can be inherited
+    std::env::set_var("SUPER_ORCHESTRATOR_TEST_KEEP", "keep");
+    std::env::set_var("SUPER_ORCHESTRATOR_TEST_DROP", "drop");
+    let comres = Command::new("env")
+        .env_passthrough(&[
+            "SUPER_ORCHESTRATOR_TEST_KEEP",
+            "SUPER_ORCHESTRATOR_TEST_MISSING",
+        ])
+        .env("EXPLICIT", "explicit")
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    let mut vars: Vec<&str> = comres.stdout_as_utf8().stack()?.lines().collect();
+    vars.sort_unstable();
+    ensure_eq!(vars, [
+        "EXPLICIT=explicit",
+        "SUPER_ORCHESTRATOR_TEST_KEEP=keep"
+    ]);
+    // or selected for every command
+    std::env::set_var(ENV_PASSTHROUGH_VAR, "SUPER_ORCHESTRATOR_TEST_DROP, PATH");
+    let comres = Command::new("env").run_to_completion().await.stack()?;
+    std::env::remove_var(ENV_PASSTHROUGH_VAR);
+    comres.assert_success().stack()?;
+    let mut vars: Vec<&str> = comres.stdout_as_utf8().stack()?.lines().collect();
+    vars.sort_unstable();
+    ensure_eq!(vars.len(), 2);
+    ensure!(vars[0].starts_with("PATH="));
+    ensure_eq!(vars[1], "SUPER_ORCHESTRATOR_TEST_DROP=drop");
+
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    {
+        use super_orchestrator::{acquire_dir_path, SandboxConfig};
+
+        // sandboxed commands can only write to the allowed paths
+        let allowed = acquire_dir_path("./logs").await.stack()?;
+        let allowed = allowed.to_str().stack()?;
+        let config = SandboxConfig::new().allowed_paths([allowed]);
+        let comres = Command::new("sh -c")
+            .arg(format!("echo hello > {allowed}/sandbox.txt"))
+            .run_with_sandbox(config.clone())
+            .await
+            .stack()?
+            .wait_with_output()
+            .await
+            .stack()?;
+        comres.assert_success().stack()?;
+        let comres = Command::new("sh -c")
+            .arg("echo hello > ./sandbox.txt")
+            .run_with_sandbox(config)
+            .await
+            .stack()?
+            .wait_with_output()
+            .await
+            .stack()?;
+        ensure!(!comres.successful());
+    }
+
     // check command debug
     let command = Command::new("ls")
         .arg("-la")
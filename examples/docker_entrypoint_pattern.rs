@@ -16,8 +16,9 @@ use stacked_errors::{ensure_eq, Error, Result, StackableErr};
 use super_orchestrator::{
     ctrlc_init,
     docker::{Container, ContainerNetwork, Dockerfile},
+    find_binary, load_topology,
     net_message::NetMessenger,
-    sh, FileOptions,
+    sh, FileOptions, FindBinaryOptions,
 };
 use tokio::time::sleep;
 use tracing::info;
@@ -128,8 +129,12 @@ async fn container_runner(args: &Args) -> Result<()> {
     //])
     //.await
     //.stack()?;
-    //let entrypoint =
-    // &format!("./target/{container_target}/release/{bin_entrypoint}");
+    //let entrypoint = find_binary(
+    //    bin_entrypoint,
+    //    &FindBinaryOptions::new().target(Some(container_target)),
+    //)
+    //.await
+    //.stack()?;
 
     // because this is an example we need a slightly different path
     sh([
@@ -140,7 +145,16 @@ async fn container_runner(args: &Args) -> Result<()> {
     ])
     .await
     .stack()?;
-    let entrypoint = &format!("./target/{container_target}/release/examples/{bin_entrypoint}");
+    // `find_binary` handles `CARGO_TARGET_DIR` and workspace target directories
+    let entrypoint = find_binary(
+        bin_entrypoint,
+        &FindBinaryOptions::new()
+            .example(true)
+            .target(Some(container_target)),
+    )
+    .await
+    .stack()?;
+    let entrypoint = entrypoint.to_str().unwrap();
 
     let mut cn = ContainerNetwork::new("test", Some(dockerfiles_dir), logs_dir);
 
@@ -162,6 +176,8 @@ async fn container_runner(args: &Args) -> Result<()> {
             "container1",
             Dockerfile::path(format!("{dockerfiles_dir}/example.dockerfile")),
         )
+        // other containers find this one by its role in the topology
+        .label("role", "receiver")
         .external_entrypoint(entrypoint, ["--entry-name", "container1"])
         .await
         .stack()?,
@@ -207,6 +223,8 @@ async fn container_runner(args: &Args) -> Result<()> {
     let uuid = cn.uuid_as_string();
     // passing UUID information through common arguments
     cn.add_common_entrypoint_args(["--uuid", &uuid]);
+    // lets the entrypoints discover their peers through `load_topology`
+    cn.inject_topology(true);
 
     // Whenever using the docker entrypoint pattern or similar setup where there is
     // a dedicated container runner function that is just calling
@@ -243,9 +261,18 @@ async fn container0_runner(_args: &Args) -> Result<()> {
     // nontrivial async `tokio` usage, the backtraces get clobbered with task runner
     // functions, which is why I designed `stacked_errors` to enable programmed
     // backtraces.
-    let mut nm = NetMessenger::connect(STD_TRIES, STD_DELAY, "container1:26000")
-        .await
-        .stack()?;
+    let topology = load_topology().stack()?;
+    let receiver = topology
+        .with_label("role", "receiver")
+        .next()
+        .stack_err(|| "no container has the receiver role")?;
+    let mut nm = NetMessenger::connect(
+        STD_TRIES,
+        STD_DELAY,
+        &format!("{}:26000", receiver.container_name),
+    )
+    .await
+    .stack()?;
     let s = "hello world".to_owned();
 
     // check out the results of returning `stack_errors::Error`
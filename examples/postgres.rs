@@ -10,9 +10,9 @@ use clap::Parser;
 use super_orchestrator::{
     acquire_dir_path,
     docker::{Container, ContainerNetwork, Dockerfile},
-    sh,
+    find_binary, sh,
     stacked_errors::{Error, Result, StackableErr},
-    wait_for_ok, Command,
+    wait_for_ok, Command, FindBinaryOptions,
 };
 use tokio::{fs, time::sleep};
 use tracing::info;
@@ -77,8 +77,12 @@ async fn container_runner(args: &Args) -> Result<()> {
     //    container_target,
     //])
     //.await.stack()?;
-    //let entrypoint =
-    // &format!("./target/{container_target}/release/{bin_entrypoint}");
+    //let entrypoint = find_binary(
+    //    bin_entrypoint,
+    //    &FindBinaryOptions::new().target(Some(container_target)),
+    //)
+    //.await
+    //.stack()?;
 
     // for this example we need this command
     sh([
@@ -89,7 +93,16 @@ async fn container_runner(args: &Args) -> Result<()> {
     ])
     .await
     .stack()?;
-    let entrypoint = &format!("./target/{container_target}/release/examples/{bin_entrypoint}");
+    // `find_binary` handles `CARGO_TARGET_DIR` and workspace target directories
+    let entrypoint = find_binary(
+        bin_entrypoint,
+        &FindBinaryOptions::new()
+            .example(true)
+            .target(Some(container_target)),
+    )
+    .await
+    .stack()?;
+    let entrypoint = entrypoint.to_str().unwrap();
 
     // we can't put the directory in source control with the .gitignore trick,
     // because postgres doesn't like the .gitignore
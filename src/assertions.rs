@@ -0,0 +1,312 @@
+use std::{borrow::Cow, fmt::Write, sync::OnceLock};
+
+use regex::Regex;
+use serde_json::Value;
+use stacked_errors::{Error, Result, StackableErr};
+
+use crate::CommandResult;
+
+/// The maximum number of lines shown in excerpts of the output
+const EXCERPT_LINES: usize = 7;
+/// Lines longer than this are cut off in excerpts
+const EXCERPT_LINE_WIDTH: usize = 160;
+
+/// Returns an [OutputExpectation] on the stdout of `comres`
+pub fn expect_stdout(comres: &CommandResult) -> OutputExpectation<'_> {
+    OutputExpectation::new("stdout", &comres.stdout)
+}
+
+/// Returns an [OutputExpectation] on the stderr of `comres`
+pub fn expect_stderr(comres: &CommandResult) -> OutputExpectation<'_> {
+    OutputExpectation::new("stderr", &comres.stderr)
+}
+
+/// Matchers on command output for use in tests. Instead of a plain boolean,
+/// failures return errors with a bounded, line-numbered excerpt of the output
+/// around the closest partial match. Each matcher returns `&Self` so that
+/// several can be chained with `?`.
+///
+/// ```
+/// use super_orchestrator::{assertions::expect_stdout, Command, CommandResult};
+///
+/// let comres = CommandResult {
+///     command: Command::new("example"),
+///     status: None,
+///     stdout: b"starting\nlistening on port 8080\n{\"ready\": true}\n".to_vec(),
+///     stderr: vec![],
+/// };
+/// expect_stdout(&comres)
+///     .contains("port 8080")
+///     .unwrap()
+///     .matches_regex(r"port \d+")
+///     .unwrap();
+/// let e = expect_stdout(&comres).contains("port 9090").unwrap_err();
+/// // the error shows where "port " was found
+/// assert!(format!("{e:?}").contains(">2 | listening on port 8080"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct OutputExpectation<'a> {
+    name: &'a str,
+    output: Cow<'a, str>,
+}
+
+impl<'a> OutputExpectation<'a> {
+    /// Creates an expectation on any `output`, `name` is what the output is
+    /// called in error messages
+    pub fn new(name: &'a str, output: &'a [u8]) -> Self {
+        Self {
+            name,
+            output: String::from_utf8_lossy(output),
+        }
+    }
+
+    /// Returns the output that is being matched on
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Expects the output to contain `expected`. On failure, the excerpt is
+    /// around the longest prefix of `expected` that was found.
+    pub fn contains(&self, expected: &str) -> Result<&Self> {
+        if self.output.contains(expected) {
+            return Ok(self)
+        }
+        let name = self.name;
+        let partial = char_boundaries_descending(expected)
+            .find_map(|i| Some((&expected[..i], self.output.find(&expected[..i])?)));
+        let msg = if let Some((prefix, byte_i)) = partial {
+            format!(
+                "OutputExpectation::contains -> expected {name} to contain {expected:?}, the \
+                 longest prefix found is {prefix:?}:\n{}",
+                excerpt(&self.output, line_of(&self.output, byte_i))
+            )
+        } else {
+            format!(
+                "OutputExpectation::contains -> expected {name} to contain {expected:?}, but no \
+                 prefix of it was found:\n{}",
+                excerpt(&self.output, 0)
+            )
+        };
+        Err(Error::from_kind_locationless(msg))
+    }
+
+    /// Expects the output to match the regular expression `pattern`. On
+    /// failure, the excerpt is around the match of the longest prefix of
+    /// `pattern` that is a valid regular expression with a match.
+    pub fn matches_regex(&self, pattern: &str) -> Result<&Self> {
+        let regex = Regex::new(pattern).stack_err_locationless(|| {
+            format!("OutputExpectation::matches_regex -> invalid regex {pattern:?}")
+        })?;
+        if regex.is_match(&self.output) {
+            return Ok(self)
+        }
+        let name = self.name;
+        let partial = char_boundaries_descending(pattern).find_map(|i| {
+            let m = Regex::new(&pattern[..i]).ok()?.find(&self.output)?;
+            // empty matches are not useful
+            (!m.is_empty()).then_some((&pattern[..i], m.start()))
+        });
+        let msg = if let Some((prefix, byte_i)) = partial {
+            format!(
+                "OutputExpectation::matches_regex -> expected {name} to match {pattern:?}, the \
+                 longest matching prefix is {prefix:?}:\n{}",
+                excerpt(&self.output, line_of(&self.output, byte_i))
+            )
+        } else {
+            format!(
+                "OutputExpectation::matches_regex -> expected {name} to match {pattern:?}, but no \
+                 prefix of it matched:\n{}",
+                excerpt(&self.output, 0)
+            )
+        };
+        Err(Error::from_kind_locationless(msg))
+    }
+
+    /// Expects the output to equal `expected` after both are normalized by
+    /// removing ANSI escape sequences, carriage returns before newlines,
+    /// trailing whitespace on each line, and trailing empty lines. On failure,
+    /// the excerpt is around the first line that differs.
+    pub fn equals_normalized(&self, expected: &str) -> Result<&Self> {
+        let actual = normalize(&self.output);
+        let expected = normalize(expected);
+        if actual == expected {
+            return Ok(self)
+        }
+        let actual_lines: Vec<&str> = actual.lines().collect();
+        let expected_lines: Vec<&str> = expected.lines().collect();
+        let line = (0..actual_lines.len().max(expected_lines.len()))
+            .find(|i| actual_lines.get(*i) != expected_lines.get(*i))
+            .unwrap_or(0);
+        let show = |line: Option<&&str>| match line {
+            Some(line) => format!("{:?}", truncate_line(line)),
+            None => "(no line)".to_owned(),
+        };
+        Err(Error::from_kind_locationless(format!(
+            "OutputExpectation::equals_normalized -> the normalized {} differs from the expected \
+             text first on line {}\nexpected: {}\nactual:   {}\n{}",
+            self.name,
+            line + 1,
+            show(expected_lines.get(line)),
+            show(actual_lines.get(line)),
+            excerpt(&actual, line)
+        )))
+    }
+
+    /// Parses the whole output as JSON and expects the value at `path` to
+    /// equal `expected`. `path` starts with `$` followed by any number of
+    /// `.field` and `[index]` parts, e.x. `"$.peers[0].addr"`. On failure, the
+    /// excerpt is of the parse error location or of the value at the longest
+    /// part of `path` that exists.
+    pub fn json_path(&self, path: &str, expected: impl Into<Value>) -> Result<&Self> {
+        let expected = expected.into();
+        let name = self.name;
+        let segments = parse_json_path(path)?;
+        let root: Value = match serde_json::from_str(&self.output) {
+            Ok(root) => root,
+            Err(e) => {
+                return Err(Error::from_kind_locationless(format!(
+                    "OutputExpectation::json_path -> {name} is not valid JSON: {e}\n{}",
+                    excerpt(&self.output, e.line().saturating_sub(1))
+                )))
+            }
+        };
+        let mut value = &root;
+        let mut found = "$".to_owned();
+        for segment in &segments {
+            let next = match segment {
+                JsonPathSegment::Field(field) => value.get(field),
+                JsonPathSegment::Index(i) => value.get(i),
+            };
+            let Some(next) = next else {
+                return Err(Error::from_kind_locationless(format!(
+                    "OutputExpectation::json_path -> {name} has no value at {path:?}, the value \
+                     at {found:?} is:\n{}",
+                    excerpt(&pretty(value), 0)
+                )))
+            };
+            value = next;
+            match segment {
+                JsonPathSegment::Field(field) => write!(found, ".{field}").unwrap(),
+                JsonPathSegment::Index(i) => write!(found, "[{i}]").unwrap(),
+            }
+        }
+        if *value != expected {
+            return Err(Error::from_kind_locationless(format!(
+                "OutputExpectation::json_path -> the value at {path:?} in {name} is not the \
+                 expected value\nexpected: {}\nactual:\n{}",
+                truncate_line(&expected.to_string()),
+                excerpt(&pretty(value), 0)
+            )))
+        }
+        Ok(self)
+    }
+}
+
+enum JsonPathSegment {
+    Field(String),
+    Index(usize),
+}
+
+fn parse_json_path(path: &str) -> Result<Vec<JsonPathSegment>> {
+    let invalid = || {
+        Error::from_kind_locationless(format!(
+            "OutputExpectation::json_path -> invalid path {path:?}, expected `$` followed by \
+             `.field` and `[index]` parts"
+        ))
+    };
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut segments = vec![];
+    while !rest.is_empty() {
+        if let Some(s) = rest.strip_prefix('.') {
+            let end = s.find(['.', '[']).unwrap_or(s.len());
+            if end == 0 {
+                return Err(invalid())
+            }
+            segments.push(JsonPathSegment::Field(s[..end].to_owned()));
+            rest = &s[end..];
+        } else if let Some(s) = rest.strip_prefix('[') {
+            let end = s.find(']').ok_or_else(invalid)?;
+            let i = s[..end].trim().parse().map_err(|_| invalid())?;
+            segments.push(JsonPathSegment::Index(i));
+            rest = &s[(end + 1)..];
+        } else {
+            return Err(invalid())
+        }
+    }
+    Ok(segments)
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+/// The nonzero char boundaries of `s`, from the largest to the smallest
+fn char_boundaries_descending(s: &str) -> impl Iterator<Item = usize> + '_ {
+    s.char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .rev()
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// The zero based line that the byte at `byte_i` is on
+fn line_of(s: &str, byte_i: usize) -> usize {
+    s[..byte_i].matches('\n').count()
+}
+
+fn ansi_regex() -> &'static Regex {
+    static ANSI: OnceLock<Regex> = OnceLock::new();
+    ANSI.get_or_init(|| Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]").unwrap())
+}
+
+fn normalize(s: &str) -> String {
+    let s = ansi_regex().replace_all(s, "");
+    let mut res = String::new();
+    for line in s.lines() {
+        res.push_str(line.trim_end());
+        res.push('\n');
+    }
+    res.truncate(res.trim_end().len());
+    res
+}
+
+fn truncate_line(line: &str) -> Cow<'_, str> {
+    match line.char_indices().nth(EXCERPT_LINE_WIDTH) {
+        Some((i, _)) => Cow::Owned(format!("{}...", &line[..i])),
+        None => Cow::Borrowed(line),
+    }
+}
+
+/// Returns up to `EXCERPT_LINES` lines of `text` around the zero based `line`,
+/// with one based line numbers and the `line` marked with '>'
+fn excerpt(text: &str, line: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.is_empty() {
+        return "(the output is empty)".to_owned()
+    }
+    let line = line.min(lines.len() - 1);
+    let start = line
+        .saturating_sub(EXCERPT_LINES / 2)
+        .min(lines.len().saturating_sub(EXCERPT_LINES));
+    let end = (start + EXCERPT_LINES).min(lines.len());
+    let width = end.to_string().len();
+    let mut res = String::new();
+    if start > 0 {
+        writeln!(res, "... ({start} lines before)").unwrap();
+    }
+    for (i, text_line) in lines.iter().enumerate().take(end).skip(start) {
+        let marker = if i == line { '>' } else { ' ' };
+        writeln!(
+            res,
+            "{marker}{:>width$} | {}",
+            i + 1,
+            truncate_line(text_line)
+        )
+        .unwrap();
+    }
+    if end < lines.len() {
+        writeln!(res, "... ({} lines after)", lines.len() - end).unwrap();
+    }
+    res.pop();
+    res
+}
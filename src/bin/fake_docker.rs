@@ -0,0 +1,5 @@
+//! A docker CLI test double, see `super_orchestrator::test_doubles`
+
+fn main() {
+    std::process::exit(super_orchestrator::test_doubles::fake_docker_main())
+}
@@ -0,0 +1,202 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use stacked_errors::{Error, Result, StackableErr};
+
+use crate::{
+    docker::{
+        builder_state::Set, Container, ContainerNetwork, ContainerNetworkBuilder, Dockerfile,
+    },
+    CommandResult,
+};
+
+/// Calls `f` with `cn` and then calls [ContainerNetwork::terminate_all]
+/// regardless of whether `f` succeeded, returning the result of `f`. If the
+/// future is dropped or `f` panics, the `Drop` impl of `ContainerNetwork` does
+/// the teardown instead.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use stacked_errors::{Result, StackableErr};
+/// use super_orchestrator::{
+///     combinators::with_network,
+///     docker::{Container, ContainerNetwork, Dockerfile},
+/// };
+///
+/// async fn test() -> Result<()> {
+///     let mut cn = ContainerNetwork::new("test", None, "./logs");
+///     cn.add_container(Container::new("job", Dockerfile::name_tag("alpine:3.20")))?;
+///     with_network(cn, async |cn| {
+///         cn.run_all().await?;
+///         cn.wait_with_timeout_all(true, Duration::from_secs(60))
+///             .await
+///     })
+///     .await
+///     .stack()
+/// }
+/// ```
+pub async fn with_network<T>(
+    mut cn: ContainerNetwork,
+    f: impl AsyncFnOnce(&mut ContainerNetwork) -> Result<T>,
+) -> Result<T> {
+    let res = f(&mut cn).await;
+    cn.terminate_all().await;
+    res.stack_err_locationless(|| "with_network")
+}
+
+/// The outcome of [run_until]
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// All of the jobs finished successfully, with their results
+    Success(BTreeMap<String, CommandResult>),
+    /// One of the services did not become ready in time, the jobs were not
+    /// started
+    NotReady(Error),
+    /// One of the jobs failed or the timeout was reached, the network has been
+    /// terminated
+    JobFailed(Error),
+}
+
+impl RunOutcome {
+    /// Returns the results if the outcome is `Success`, or else the error
+    pub fn into_success(self) -> Result<BTreeMap<String, CommandResult>> {
+        match self {
+            RunOutcome::Success(results) => Ok(results),
+            RunOutcome::NotReady(e) => Err(e.add_kind_locationless("RunOutcome::NotReady")),
+            RunOutcome::JobFailed(e) => Err(e.add_kind_locationless("RunOutcome::JobFailed")),
+        }
+    }
+}
+
+/// Runs the `services`, waits for them to be ready with the probes set by
+/// [Container::readiness], then runs the `jobs` and waits for them to finish.
+/// The services are left running unless a job failed. `timeout` applies to the
+/// readiness and the jobs separately.
+///
+/// # Errors
+///
+/// Only if running the containers fails, failures of the services and jobs are
+/// reported in the [RunOutcome]
+pub async fn run_until(
+    cn: &mut ContainerNetwork,
+    services: &[&str],
+    jobs: &[&str],
+    timeout: Duration,
+) -> Result<RunOutcome> {
+    cn.run(services)
+        .await
+        .stack_err_locationless(|| "run_until -> when running the services")?;
+    if let Err(e) = cn.wait_ready(services, timeout).await {
+        return Ok(RunOutcome::NotReady(e))
+    }
+    cn.run(jobs)
+        .await
+        .stack_err_locationless(|| "run_until -> when running the jobs")?;
+    if let Err(e) = cn.wait_with_timeout(jobs, true, timeout).await {
+        return Ok(RunOutcome::JobFailed(e))
+    }
+    let mut results = BTreeMap::new();
+    for job in jobs {
+        let comres = cn
+            .expect(job)
+            .stack_err_locationless(|| "run_until -> when collecting the results of the jobs")?;
+        results.insert((*job).to_owned(), comres.clone());
+    }
+    Ok(RunOutcome::Success(results))
+}
+
+/// Overrides applied to the container with `name` for one variant of
+/// [matrix_run]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerPatch {
+    /// The name of the container to patch
+    pub name: String,
+    /// If set, the container uses `Dockerfile::NameTag` with this image
+    pub image: Option<String>,
+    /// Environment variables that are added to or replace those of the
+    /// container
+    pub environment_vars: Vec<(String, String)>,
+}
+
+impl ContainerPatch {
+    /// Creates a patch for the container with `name` that changes nothing
+    pub fn new(name: impl AsRef<str>) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the `image`
+    pub fn image(mut self, image: impl AsRef<str>) -> Self {
+        self.image = Some(image.as_ref().to_owned());
+        self
+    }
+
+    /// Adds an environment variable
+    pub fn env(mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+        self.environment_vars
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Returns `container` with the patch applied
+    pub fn apply(&self, mut container: Container) -> Container {
+        if let Some(ref image) = self.image {
+            container.dockerfile = Dockerfile::name_tag(image);
+        }
+        for (key, value) in &self.environment_vars {
+            container.environment_vars.retain(|(k, _)| k != key);
+            container
+                .environment_vars
+                .push((key.clone(), value.clone()));
+        }
+        container
+    }
+}
+
+/// Runs `f` once for each of the `variants`, each time with a fresh network
+/// built from `base` with the [ContainerPatch] applied. Each network is
+/// terminated with [with_network] before the next variant starts, and a
+/// failing variant does not stop the later ones. Returns the result of each
+/// variant in order.
+///
+/// Use [ContainerNetworkBuilder::uuid] on `base` so that leftover resources
+/// from one variant cannot conflict with the next.
+pub async fn matrix_run<T>(
+    base: &ContainerNetworkBuilder<Set, Set>,
+    variants: Vec<ContainerPatch>,
+    mut f: impl AsyncFnMut(&ContainerPatch, &mut ContainerNetwork) -> Result<T>,
+) -> Vec<Result<T>> {
+    let mut results = vec![];
+    for patch in variants {
+        let res = async {
+            let cn = patched_network(base, &patch).await?;
+            with_network(cn, async |cn| f(&patch, cn).await).await
+        }
+        .await
+        .stack_err_locationless(|| format!("matrix_run -> variant {patch:?}"));
+        results.push(res);
+    }
+    results
+}
+
+async fn patched_network(
+    base: &ContainerNetworkBuilder<Set, Set>,
+    patch: &ContainerPatch,
+) -> Result<ContainerNetwork> {
+    let mut cn = base.clone().build()?;
+    let container = cn
+        .get_container(&patch.name)
+        .cloned()
+        .stack_err_locationless(|| {
+            format!(
+                "matrix_run -> the patched container \"{}\" is not in the network",
+                patch.name
+            )
+        })?;
+    cn.try_remove_container(&patch.name).await?;
+    cn.add_container(patch.apply(container))?;
+    Ok(cn)
+}
@@ -3,20 +3,143 @@ use std::{
     borrow::{Borrow, Cow},
     ffi::{OsStr, OsString},
     fmt::{Debug, Display},
+    io::Cursor,
     path::{Path, PathBuf},
     process::{ExitStatus, Stdio},
     str::Utf8Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
-use stacked_errors::{DisplayStr, Error, Result, StackableErr};
-use tokio::io::AsyncWriteExt;
+use sha2::{Digest, Sha256};
+use stacked_errors::{Error, Result, StackableErr};
+use tempfile::TempDir;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
 
-use crate::{command_runner, CommandRunner, FileOptions};
+use crate::{
+    close_file, command_runner,
+    docker_helpers::{daemon_unreachable, docker_binary},
+    Backoff, CommandRunner, FileOptions,
+};
 
 const DEFAULT_READ_LOOP_TIMEOUT: Duration = Duration::from_millis(300);
 
+/// The default [Command::wait_backoff]
+pub const DEFAULT_WAIT_BACKOFF: Backoff = Backoff::new(Duration::from_millis(1))
+    .multiplier(2.0)
+    .max_interval(Duration::from_millis(128));
+
+/// If this environment variable is set to a comma separated list of keys,
+/// every command is run as if [Command::env_passthrough] was called with those
+/// keys (before the command's own `envs` are applied)
+pub const ENV_PASSTHROUGH_VAR: &str = "SUPER_ORCHESTRATOR_ENV_PASSTHROUGH";
+
+/// The default of [set_error_output_limit]
+pub const DEFAULT_ERROR_OUTPUT_LIMIT: usize = 64 * 1024;
+
+static ERROR_OUTPUT_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_ERROR_OUTPUT_LIMIT);
+
+/// Sets the process-global limit on the number of bytes of stdout and of
+/// stderr that are included in the errors from
+/// [CommandResult::assert_success]. Output past the limit is replaced by a
+/// "(truncated, N bytes total)" suffix, the `CommandResult` itself is not
+/// modified.
+pub fn set_error_output_limit(limit: usize) {
+    ERROR_OUTPUT_LIMIT.store(limit, Ordering::SeqCst);
+}
+
+/// Returns the process-global limit set by [set_error_output_limit]
+pub fn error_output_limit() -> usize {
+    ERROR_OUTPUT_LIMIT.load(Ordering::SeqCst)
+}
+
+/// Where the standard streams of a `Command` are forwarded to when
+/// `stdout_debug` or `stderr_debug` is set
+#[derive(
+    Debug, Default, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum DebugOutput {
+    /// Lines are written with their debug line prefix to the standard streams
+    /// of this process
+    #[default]
+    Std,
+    /// Each line is emitted as an `info` level `tracing` event with a `stream`
+    /// field of "stdout" or "stderr", within the span that was current when
+    /// the `Command` was run
+    Tracing,
+    /// Both `Std` and `Tracing`
+    Both,
+}
+
+impl DebugOutput {
+    pub(crate) fn has_std(self) -> bool {
+        matches!(self, DebugOutput::Std | DebugOutput::Both)
+    }
+
+    pub(crate) fn has_tracing(self) -> bool {
+        matches!(self, DebugOutput::Tracing | DebugOutput::Both)
+    }
+}
+
+/// Buffering of the standard stream debug forwarding of a [Command], see
+/// [Command::debug_buffering]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BufferPolicy {
+    /// Buffered lines are written once the oldest has waited this long
+    pub max_delay: Duration,
+    /// Buffered lines are written once there are this many bytes
+    pub max_bytes: usize,
+}
+
+/// Returns the key value pairs of the current process environment for `keys`,
+/// skipping keys that are not present
+pub(crate) fn passthrough_envs<I, S>(keys: I) -> Vec<(OsString, OsString)>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    keys.into_iter()
+        .filter_map(|key| {
+            let key = key.as_ref();
+            std::env::var_os(key).map(|val| (key.to_owned(), val))
+        })
+        .collect()
+}
+
+/// Returns the keys listed by [ENV_PASSTHROUGH_VAR] if it is set
+pub(crate) fn env_passthrough_var_keys() -> Option<Vec<String>> {
+    let keys = std::env::var(ENV_PASSTHROUGH_VAR).ok()?;
+    Some(
+        keys.split(',')
+            .map(|key| key.trim().to_owned())
+            .filter(|key| !key.is_empty())
+            .collect(),
+    )
+}
+
+/// A callback for each line of output of a [Command], see
+/// [Command::line_callback]
+pub type LineCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Configuration for spilling the older parts of the stdout and stderr records
+/// to files, see [Command::record_spill]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RecordSpill {
+    /// The records keep at most this many of the newest bytes in memory
+    pub threshold: u64,
+    /// The directory that the spill files are created in
+    pub dir: PathBuf,
+    /// Set when the command is run, the older stdout is appended to this file
+    pub stdout_path: Option<PathBuf>,
+    /// Set when the command is run, the older stderr is appended to this file
+    pub stderr_path: Option<PathBuf>,
+}
+
 /// An OS Command, this is `tokio::process::Command` wrapped in a bunch of
 /// helping functionality.
 #[derive(Clone, Serialize, Deserialize)]
@@ -41,10 +164,11 @@ pub struct Command {
     /// be accessed from `stderr_record` in the runner or `stderr` in the
     /// command result later
     pub stderr_recording: bool,
-    /// If set, the command will copy the `stdout` to the file
-    pub stdout_log: Option<FileOptions>,
-    /// If set, the command will copy the `stderr` to the file
-    pub stderr_log: Option<FileOptions>,
+    /// The command will copy the `stdout` to each of these files. A file that
+    /// is also in `stderr_logs` becomes a combined log of both streams.
+    pub stdout_logs: Vec<FileOptions>,
+    /// The command will copy the `stderr` to each of these files
+    pub stderr_logs: Vec<FileOptions>,
     /// Forward stdout to the current process stdout
     pub stdout_debug: bool,
     /// Forward stderr to the current process stderr
@@ -53,13 +177,32 @@ pub struct Command {
     pub stdout_debug_line_prefix: Option<String>,
     /// If the default stderr debug line prefix should be overridden
     pub stderr_debug_line_prefix: Option<String>,
+    /// Where `stdout_debug` and `stderr_debug` forward to
+    pub debug_output: DebugOutput,
+    /// If set, the forwarding to the standard streams of this process is
+    /// buffered, see [Command::debug_buffering]
+    pub debug_buffering: Option<BufferPolicy>,
+    /// If set, limits the lines per second of each stream that are forwarded
+    /// to the standard streams of this process, see
+    /// [Command::max_debug_lines_per_sec]
+    pub max_debug_lines_per_sec: Option<u64>,
+    /// The stdout is binary, this disables `stdout_debug` forwarding and makes
+    /// the `Debug` formatting of the `CommandResult` show the length and digest
+    /// of the stdout instead of its text
+    pub stdout_binary: bool,
+    /// The stderr is binary, the same as `stdout_binary` but for stderr
+    pub stderr_binary: bool,
     /// Sets a limit on the number of bytes recorded by the stdout and stderr
     /// records separately, after which the records become circular buffers.
     /// This limits the potential memory used by a long running command. `None`
     /// means there is no limit.
     pub record_limit: Option<u64>,
-    /// Sets a limit on the size of log files. Each time the limit is reached,
-    /// the file is truncated.
+    /// If set, the records keep only the newest bytes in memory and the older
+    /// bytes are appended to spill files instead of being dropped. This takes
+    /// precedence over `record_limit`.
+    pub record_spill: Option<RecordSpill>,
+    /// Sets a limit on the size of each log file. Each time the limit is
+    /// reached, the file is truncated.
     pub log_limit: Option<u64>,
     /// When recording the standard streams for a long running command, reading
     /// buffers should be paused periodically to copy data to records, debug,
@@ -67,11 +210,26 @@ pub struct Command {
     /// memory can increase without bound for cases that should be limited. This
     /// defaults to 300 ms.
     pub read_loop_timeout: Duration,
+    /// The schedule that [CommandRunner::wait_with_timeout] polls the process
+    /// with, which defaults to [DEFAULT_WAIT_BACKOFF]. Jitter spreads out the
+    /// polls of many runners, `max_elapsed` additionally bounds each wait, and
+    /// a wait times out once `max_retries` intervals have passed.
+    pub wait_backoff: Backoff,
     /// If `false`, then killing the command on drop is enabled. NOTE: this
     /// being true or false should not be relied upon in normal program
     /// operation, `CommandRunner`s should be properly finished so that the
     /// child process is cleaned up properly.
     pub forget_on_drop: bool,
+    /// If set, dropping an unfinished `CommandRunner` of this command does not
+    /// warn or emit a [CrateEvent::DroppedRunner](crate::CrateEvent), for
+    /// commands that are intentionally left running. Unlike `forget_on_drop`,
+    /// this does not change whether the child is killed. See also
+    /// [CommandRunner::detach](crate::CommandRunner::detach).
+    pub suppress_drop_warning: bool,
+    /// If set, this is called with each line of the stdout and stderr (without
+    /// the newline) as the command runs. This is not serialized.
+    #[serde(skip)]
+    pub line_callback: Option<LineCallback>,
 }
 
 impl Default for Command {
@@ -84,16 +242,25 @@ impl Default for Command {
             cwd: Default::default(),
             stderr_recording: true,
             stdout_recording: true,
-            stdout_log: Default::default(),
-            stderr_log: Default::default(),
+            stdout_logs: Default::default(),
+            stderr_logs: Default::default(),
             stdout_debug: Default::default(),
             stderr_debug: Default::default(),
             stdout_debug_line_prefix: None,
             stderr_debug_line_prefix: None,
+            debug_output: DebugOutput::Std,
+            debug_buffering: None,
+            max_debug_lines_per_sec: None,
+            stdout_binary: false,
+            stderr_binary: false,
             record_limit: Default::default(),
+            record_spill: None,
             log_limit: Default::default(),
             read_loop_timeout: DEFAULT_READ_LOOP_TIMEOUT,
+            wait_backoff: DEFAULT_WAIT_BACKOFF,
             forget_on_drop: Default::default(),
+            suppress_drop_warning: false,
+            line_callback: None,
         }
     }
 }
@@ -101,8 +268,8 @@ impl Default for Command {
 impl Debug for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!(
-            "Command {{\nprogram: {:?}\n,",
-            DisplayStr(&self.get_unified_command()),
+            "Command {{\nprogram: {}\n,",
+            self.display_compact(),
         ))?;
         if self.env_clear {
             f.write_fmt(format_args!(" env_clear: true,",))?;
@@ -120,10 +287,10 @@ impl Debug for Command {
                 self.stdout_recording, self.stderr_recording
             ))?;
         }
-        if let Some(log) = self.stdout_log.as_ref().map(|x| &x.path) {
+        for log in self.stdout_logs.iter().map(|x| &x.path) {
             f.write_fmt(format_args!(" stdout_log: {log:?},"))?;
         }
-        if let Some(log) = self.stderr_log.as_ref().map(|x| &x.path) {
+        for log in self.stderr_logs.iter().map(|x| &x.path) {
             f.write_fmt(format_args!(" stderr_log: {log:?},"))?;
         }
         if self.stdout_debug || self.stderr_debug {
@@ -131,21 +298,96 @@ impl Debug for Command {
                 " debug: ({}, {}),",
                 self.stdout_debug, self.stderr_debug
             ))?;
+            if self.debug_output != DebugOutput::Std {
+                f.write_fmt(format_args!(" debug_output: {:?},", self.debug_output))?;
+            }
+            if let Some(policy) = self.debug_buffering {
+                f.write_fmt(format_args!(" debug_buffering: {policy:?},"))?;
+            }
+            if let Some(max) = self.max_debug_lines_per_sec {
+                f.write_fmt(format_args!(" max_debug_lines_per_sec: {max},"))?;
+            }
+        }
+        if self.stdout_binary || self.stderr_binary {
+            f.write_fmt(format_args!(
+                " binary: ({}, {}),",
+                self.stdout_binary, self.stderr_binary
+            ))?;
         }
         if let Some(limit) = self.record_limit {
             f.write_fmt(format_args!(" record_limit: {limit},"))?;
         }
+        if let Some(spill) = self.record_spill.as_ref() {
+            f.write_fmt(format_args!(
+                " record_spill: ({}, {:?}),",
+                spill.threshold, spill.dir
+            ))?;
+        }
         if let Some(limit) = self.log_limit {
             f.write_fmt(format_args!(" log_limit: {limit},"))?;
         }
+        if self.wait_backoff != DEFAULT_WAIT_BACKOFF {
+            f.write_fmt(format_args!(" wait_backoff: {:?},", self.wait_backoff))?;
+        }
         if self.forget_on_drop {
             f.write_fmt(format_args!(" forget_on_drop: true,"))?;
         }
+        if self.suppress_drop_warning {
+            f.write_fmt(format_args!(" suppress_drop_warning: true,"))?;
+        }
+        if self.line_callback.is_some() {
+            f.write_fmt(format_args!(" line_callback: Some(..),"))?;
+        }
         f.write_fmt(format_args!("}}",))
     }
 }
 
+/// The first item is the program and the rest are the args, see
+/// [Command::from_parts]. An empty iterator results in an empty program.
+impl FromIterator<OsString> for Command {
+    fn from_iter<I: IntoIterator<Item = OsString>>(iter: I) -> Self {
+        let mut iter = iter.into_iter();
+        Self {
+            program: iter.next().unwrap_or_default(),
+            args: iter.collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The first element is the program and the rest are the args
+impl From<&[&str]> for Command {
+    fn from(parts: &[&str]) -> Self {
+        parts.iter().map(OsString::from).collect()
+    }
+}
+
+/// The first element is the program and the rest are the args
+impl From<Vec<String>> for Command {
+    fn from(parts: Vec<String>) -> Self {
+        parts.into_iter().map(OsString::from).collect()
+    }
+}
+
 impl Command {
+    /// Creates a new `Command` for launching the `program` with `args`, with
+    /// no splitting or other preprocessing of either. This is preferred over
+    /// [Command::new] when the arguments are assembled programmatically.
+    ///
+    /// ```
+    /// use super_orchestrator::Command;
+    ///
+    /// let command = Command::from_parts("echo", ["hello world", ""]);
+    /// assert_eq!(command.args, ["hello world", ""]);
+    /// ```
+    pub fn from_parts<I, S>(program: impl AsRef<OsStr>, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Self::new_os_str(program).args(args)
+    }
+
     /// Creates a new `Command` for launching the `program`. This has no
     /// preprocessing of the input like [Command::new] does.
     ///
@@ -183,6 +425,95 @@ impl Command {
         }
     }
 
+    /// Same as [Command::new], but `program_with_args` is split into words
+    /// like a POSIX shell would with the `shlex` crate, so that single and
+    /// double quotes and escapes can be used for arguments with spaces.
+    /// Returns an error if the quoting is invalid or there is no program.
+    ///
+    /// ```
+    /// use super_orchestrator::Command;
+    ///
+    /// let command = Command::new_with_shlex("echo 'hello world' \"a \\\"b\\\"\"").unwrap();
+    /// assert_eq!(command.program, "echo");
+    /// assert_eq!(command.args, ["hello world", "a \"b\""]);
+    /// assert!(Command::new_with_shlex("echo 'unterminated").is_err());
+    /// ```
+    pub fn new_with_shlex(program_with_args: impl AsRef<str>) -> Result<Self> {
+        let program_with_args = program_with_args.as_ref();
+        let mut words = shlex::split(program_with_args)
+            .stack_err_locationless(|| {
+                format!(
+                    "Command::new_with_shlex -> could not split {program_with_args:?} into words"
+                )
+            })?
+            .into_iter();
+        let program = words.next().stack_err_locationless(|| {
+            format!("Command::new_with_shlex -> {program_with_args:?} has no program")
+        })?;
+        Ok(Self::new_os_str(program).args(words))
+    }
+
+    /// Creates a `Command` for `docker run --rm` of `cmd` in a new container
+    /// from `image`, with each of the `volumes` passed as `--volume
+    /// host_path:container_path`. This is for one-off commands that do not
+    /// need a [ContainerNetwork](crate::docker::ContainerNetwork). The
+    /// [docker_binary](crate::docker_helpers::docker_binary) is used.
+    ///
+    /// ```
+    /// use super_orchestrator::Command;
+    ///
+    /// let volumes = [("./data", "/data")];
+    /// let command = Command::in_docker_image("alpine:3.20", &volumes, &["ls", "/data"]);
+    /// assert_eq!(
+    ///     command.display_compact().to_string(),
+    ///     "docker run --rm --volume ./data:/data alpine:3.20 ls /data"
+    /// );
+    /// ```
+    pub fn in_docker_image(image: &str, volumes: &[(&str, &str)], cmd: &[&str]) -> Self {
+        let mut command = Self::new_os_str(docker_binary()).args(["run", "--rm"]);
+        for (host_path, container_path) in volumes {
+            command = command.args(["--volume", &format!("{host_path}:{container_path}")]);
+        }
+        command.arg(image).args(cmd)
+    }
+
+    /// Creates a `Command` for `docker exec` of `cmd` in the running container
+    /// with `container_id` (or name)
+    ///
+    /// ```
+    /// use super_orchestrator::Command;
+    ///
+    /// let command = Command::in_docker_container("0123abcd", &["cat", "/etc/hosts"]);
+    /// assert_eq!(
+    ///     command.display_compact().to_string(),
+    ///     "docker exec 0123abcd cat /etc/hosts"
+    /// );
+    /// ```
+    pub fn in_docker_container(container_id: &str, cmd: &[&str]) -> Self {
+        Self::new_os_str(docker_binary())
+            .args(["exec", container_id])
+            .args(cmd)
+    }
+
+    /// Adds the arguments from splitting `args` with the `shlex` crate, see
+    /// [Command::new_with_shlex]
+    ///
+    /// ```
+    /// use super_orchestrator::Command;
+    ///
+    /// let command = Command::new("git")
+    ///     .args_from_shlex("commit -m 'a message'")
+    ///     .unwrap();
+    /// assert_eq!(command.args, ["commit", "-m", "a message"]);
+    /// ```
+    pub fn args_from_shlex(self, args: impl AsRef<str>) -> Result<Self> {
+        let args = args.as_ref();
+        let words = shlex::split(args).stack_err_locationless(|| {
+            format!("Command::args_from_shlex -> could not split {args:?} into words")
+        })?;
+        Ok(self.args(words))
+    }
+
     /// Adds an argument
     pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
         self.args.push(arg.as_ref().into());
@@ -206,6 +537,31 @@ impl Command {
         self
     }
 
+    /// Creates a new temporary directory and sets it as `self.cwd`. The
+    /// directory is removed when the returned `TempDir` is dropped, so it
+    /// should be kept alive until the command has finished.
+    pub fn with_tempdir_cwd(self) -> Result<(Self, TempDir)> {
+        let dir = TempDir::new().stack_err_locationless(|| {
+            "Command::with_tempdir_cwd -> failed to create temporary directory"
+        })?;
+        Ok((self.cwd(dir.path()), dir))
+    }
+
+    /// Same as [Command::with_tempdir_cwd], but the temporary directory name
+    /// starts with `prefix`
+    pub fn with_named_tempdir(self, prefix: &str) -> Result<(Self, TempDir)> {
+        let dir = tempfile::Builder::new()
+            .prefix(prefix)
+            .tempdir()
+            .stack_err_locationless(|| {
+                format!(
+                    "Command::with_named_tempdir(prefix: {prefix}) -> failed to create temporary \
+                     directory"
+                )
+            })?;
+        Ok((self.cwd(dir.path()), dir))
+    }
+
     /// Set if environment variables should be cleared
     pub fn env_clear(mut self, env_clear: bool) -> Self {
         self.env_clear = env_clear;
@@ -219,6 +575,15 @@ impl Command {
         self
     }
 
+    /// Sets `env_clear` and then adds the environment variables with `keys`
+    /// from the current process environment, so that only they are inherited.
+    /// Keys not present in the current environment are skipped.
+    pub fn env_passthrough(mut self, keys: &[&str]) -> Self {
+        self.env_clear = true;
+        self.envs.extend(passthrough_envs(keys));
+        self
+    }
+
     /// Adds environment variables
     pub fn envs<I, K, V>(mut self, envs: I) -> Self
     where
@@ -241,6 +606,36 @@ impl Command {
         self
     }
 
+    /// Sets `debug_output`, which selects if the debug forwarding goes to the
+    /// standard streams of this process, to `tracing` events, or both
+    pub fn debug_output(mut self, debug_output: DebugOutput) -> Self {
+        self.debug_output = debug_output;
+        self
+    }
+
+    /// Sets `debug_buffering`. When set, the prefixed lines forwarded to the
+    /// standard streams of this process are accumulated and written together
+    /// once the oldest has waited `max_delay` or there are `max_bytes` of
+    /// them. This amortizes the writes and flushes when many commands are very
+    /// chatty, which would otherwise slow down the commands as their pipes back
+    /// up. Whole lines are written together when possible, so lines of
+    /// different commands do not run into each other.
+    pub fn debug_buffering(mut self, debug_buffering: Option<BufferPolicy>) -> Self {
+        self.debug_buffering = debug_buffering;
+        self
+    }
+
+    /// Sets `max_debug_lines_per_sec`. When more lines of a stream than this
+    /// are forwarded to the standard streams of this process within a second,
+    /// the rest of the lines of the stream in that second are dropped, and a
+    /// single "… N lines suppressed …" line is written in their place. The
+    /// records, log files, line callback, and `tracing` forwarding still get
+    /// everything.
+    pub fn max_debug_lines_per_sec(mut self, max_debug_lines_per_sec: Option<u64>) -> Self {
+        self.max_debug_lines_per_sec = max_debug_lines_per_sec;
+        self
+    }
+
     /// Sets `stdout_recording`
     pub fn stdout_recording(mut self, stdout_recording: bool) -> Self {
         self.stdout_recording = stdout_recording;
@@ -274,26 +669,67 @@ impl Command {
         self
     }
 
-    /// Sets `stdout_log` and `stderr_log` for copying command standard streams
-    /// to the same file
+    /// Sets `stdout_binary`, which disables stdout debug forwarding regardless
+    /// of `stdout_debug` (so that the terminal is not corrupted), and makes the
+    /// `CommandResult` show `<binary, N bytes, sha256=...>` instead of lossy
+    /// text in its `Debug` formatting and in [CommandResult::assert_success]
+    /// errors.
+    pub fn stdout_binary(mut self, stdout_binary: bool) -> Self {
+        self.stdout_binary = stdout_binary;
+        self
+    }
+
+    /// Sets `stderr_binary`, the same as [Command::stdout_binary] but for
+    /// stderr
+    pub fn stderr_binary(mut self, stderr_binary: bool) -> Self {
+        self.stderr_binary = stderr_binary;
+        self
+    }
+
+    /// Replaces `stdout_logs` and `stderr_logs` with the same file, for copying
+    /// the command standard streams to one combined file. Does nothing if
+    /// `std_stream_log` is `None`.
     pub fn log<F: Borrow<FileOptions>>(mut self, std_stream_log: Option<F>) -> Self {
         if let Some(f) = std_stream_log {
             let f = f.borrow();
-            self.stdout_log = Some(f.clone());
-            self.stderr_log = Some(f.clone());
+            self.stdout_logs = vec![f.clone()];
+            self.stderr_logs = vec![f.clone()];
         }
         self
     }
 
-    /// Sets `stdout_log` for copying command stdout to a file
+    /// Replaces `stdout_logs` with the file if it is `Some`, or clears them,
+    /// for copying command stdout to a file
     pub fn stdout_log<F: Borrow<FileOptions>>(mut self, stdout_log: Option<F>) -> Self {
-        self.stdout_log = stdout_log.map(|f| f.borrow().clone());
+        self.stdout_logs = stdout_log.map(|f| f.borrow().clone()).into_iter().collect();
         self
     }
 
-    /// Sets `stderr_log` for copying command stderr to a file
+    /// Replaces `stderr_logs` with the file if it is `Some`, or clears them,
+    /// for copying command stderr to a file
     pub fn stderr_log<F: Borrow<FileOptions>>(mut self, stderr_log: Option<F>) -> Self {
-        self.stderr_log = stderr_log.map(|f| f.borrow().clone());
+        self.stderr_logs = stderr_log.map(|f| f.borrow().clone()).into_iter().collect();
+        self
+    }
+
+    /// Adds a file to `stdout_logs`, keeping the others
+    pub fn add_stdout_log<F: Borrow<FileOptions>>(mut self, stdout_log: F) -> Self {
+        self.stdout_logs.push(stdout_log.borrow().clone());
+        self
+    }
+
+    /// Adds a file to `stderr_logs`, keeping the others
+    pub fn add_stderr_log<F: Borrow<FileOptions>>(mut self, stderr_log: F) -> Self {
+        self.stderr_logs.push(stderr_log.borrow().clone());
+        self
+    }
+
+    /// Adds a file to both `stdout_logs` and `stderr_logs`, which becomes a
+    /// combined log of both streams
+    pub fn add_log<F: Borrow<FileOptions>>(mut self, std_stream_log: F) -> Self {
+        let f = std_stream_log.borrow();
+        self.stdout_logs.push(f.clone());
+        self.stderr_logs.push(f.clone());
         self
     }
 
@@ -303,6 +739,22 @@ impl Command {
         self
     }
 
+    /// Makes the stdout and stderr records keep only the newest `threshold`
+    /// bytes in memory, with the older bytes appended to spill files created
+    /// in `dir` when the command is run. Unlike `record_limit`, nothing is
+    /// lost, and the full output can be read with [CommandResult::full_stdout]
+    /// and [CommandResult::full_stderr]. The spill files are not removed
+    /// automatically.
+    pub fn record_spill(mut self, threshold: u64, dir: impl AsRef<Path>) -> Self {
+        self.record_spill = Some(RecordSpill {
+            threshold,
+            dir: dir.as_ref().to_owned(),
+            stdout_path: None,
+            stderr_path: None,
+        });
+        self
+    }
+
     /// Sets `log_limit` for limiting stdout and stderr log file byte lengths
     pub fn log_limit(mut self, log_limit: Option<u64>) -> Self {
         self.log_limit = log_limit;
@@ -322,12 +774,24 @@ impl Command {
         self
     }
 
+    /// Sets `wait_backoff`
+    pub fn wait_backoff(mut self, wait_backoff: Backoff) -> Self {
+        self.wait_backoff = wait_backoff;
+        self
+    }
+
     /// Sets `forget_on_drop`
     pub fn forget_on_drop(mut self, forget_on_drop: bool) -> Self {
         self.forget_on_drop = forget_on_drop;
         self
     }
 
+    /// Sets `suppress_drop_warning`
+    pub fn suppress_drop_warning(mut self, suppress_drop_warning: bool) -> Self {
+        self.suppress_drop_warning = suppress_drop_warning;
+        self
+    }
+
     /// Changes the debug line prefix for stdout lines. If `None`, then the
     /// default of the command name and process ID is used.
     pub fn stdout_debug_line_prefix(mut self, line_prefix: Option<String>) -> Self {
@@ -342,19 +806,62 @@ impl Command {
         self
     }
 
+    /// Sets a callback that is called with each line of the stdout and stderr
+    /// as the command runs, e.x. for parsing progress. Lines are passed
+    /// through [String::from_utf8_lossy] and do not include the newline.
+    pub fn line_callback(mut self, line_callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.line_callback = Some(Arc::new(line_callback));
+        self
+    }
+
+    /// Checks that the program can be found on `PATH` (or at its path if it
+    /// has path separators) and is executable, so that missing tools can give
+    /// a clear error before anything is run. The error names the program.
+    ///
+    /// ```
+    /// use super_orchestrator::Command;
+    ///
+    /// Command::new("sh -c true").check_program_exists().unwrap();
+    /// let e = Command::new("nonexistent_program_1234 --version")
+    ///     .check_program_exists()
+    ///     .unwrap_err();
+    /// assert!(format!("{e:?}").contains("\"nonexistent_program_1234\""));
+    /// ```
+    pub fn check_program_exists(&self) -> Result<()> {
+        which::which(&self.program)
+            .map(|_| ())
+            .stack_err_locationless(|| {
+                format!(
+                    "Command::check_program_exists -> the program {:?} was not found on `PATH` or \
+                     is not executable",
+                    self.program
+                )
+            })
+    }
+
+    /// Calls [Command::check_program_exists] and returns `self` if it succeeds
+    pub fn require_program(self) -> Result<Self> {
+        self.check_program_exists()?;
+        Ok(self)
+    }
+
+    /// Returns a `Display`able of just the program and args interspersed with
+    /// spaces. This does not allocate (unless the program or args are not valid
+    /// UTF-8) and is what should be used for logging commands.
+    ///
+    /// ```
+    /// use super_orchestrator::Command;
+    ///
+    /// let command = Command::new("docker").args(["network", "ls"]);
+    /// assert_eq!(command.display_compact().to_string(), "docker network ls");
+    /// ```
+    pub fn display_compact(&self) -> impl Display + '_ {
+        CompactCommand(self)
+    }
+
     /// Gets the program and args interspersed with spaces
     pub(crate) fn get_unified_command(&self) -> String {
-        let mut command = self.program.to_string_lossy().into_owned();
-        if !self.args.is_empty() {
-            command += " ";
-            for (i, arg) in self.args.iter().enumerate() {
-                command += arg.to_string_lossy().as_ref();
-                if i != (self.args.len() - 1) {
-                    command += " ";
-                }
-            }
-        }
-        command
+        self.display_compact().to_string()
     }
 
     /// Runs the command with a standard input, returning a `CommandRunner`
@@ -392,6 +899,31 @@ impl Command {
         drop(stdin);
         runner.wait_with_output().await
     }
+
+    /// Runs the command with a piped stdin that is fed by `receiver`. A task is
+    /// spawned that writes each received `Bytes` chunk to the process stdin,
+    /// and the stdin is closed when the channel is closed (all senders are
+    /// dropped). If writing fails (e.x. the process closed its stdin or
+    /// exited), the task stops draining the channel.
+    pub async fn run_with_channel_stdin(
+        self,
+        mut receiver: mpsc::Receiver<Bytes>,
+    ) -> Result<CommandRunner> {
+        let mut runner = self
+            .run_with_stdin(Stdio::piped())
+            .await
+            .stack_err_locationless(|| "Command::run_with_channel_stdin")?;
+        let mut stdin = runner.child_process.as_mut().unwrap().stdin.take().unwrap();
+        tokio::task::spawn(async move {
+            while let Some(bytes) = receiver.recv().await {
+                if stdin.write_all(&bytes).await.is_err() || stdin.flush().await.is_err() {
+                    break
+                }
+            }
+            // `stdin` is dropped and thus closed here
+        });
+        Ok(runner)
+    }
 }
 
 /// The result of a [Command](crate::Command)
@@ -412,13 +944,19 @@ impl Debug for CommandResult {
             self.command, self.status
         ))?;
         // move the commas out of the way of the stdout and stderr
-        let stdout = self.stdout_as_utf8_lossy();
-        if !stdout.is_empty() {
-            f.write_fmt(format_args!("stdout: {}\n,", stdout))?;
+        if !self.stdout.is_empty() {
+            if self.command.stdout_binary {
+                f.write_fmt(format_args!("stdout: {}\n,", BinaryOutput(&self.stdout)))?;
+            } else {
+                f.write_fmt(format_args!("stdout: {}\n,", self.stdout_as_utf8_lossy()))?;
+            }
         }
-        let stderr = self.stderr_as_utf8_lossy();
-        if !stderr.is_empty() {
-            f.write_fmt(format_args!("stderr: {}\n,", stderr))?;
+        if !self.stderr.is_empty() {
+            if self.command.stderr_binary {
+                f.write_fmt(format_args!("stderr: {}\n,", BinaryOutput(&self.stderr)))?;
+            } else {
+                f.write_fmt(format_args!("stderr: {}\n,", self.stderr_as_utf8_lossy()))?;
+            }
         }
         f.write_fmt(format_args!("}}"))
     }
@@ -430,6 +968,76 @@ impl Display for CommandResult {
     }
 }
 
+struct CompactCommand<'a>(&'a Command);
+
+impl Display for CompactCommand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.program.to_string_lossy())?;
+        for arg in &self.0.args {
+            f.write_str(" ")?;
+            f.write_str(&arg.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays binary output as `<binary, N bytes, sha256=...>`
+struct BinaryOutput<'a>(&'a [u8]);
+
+impl Display for BinaryOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "<binary, {} bytes, sha256={}>",
+            self.0.len(),
+            sha256_hex(self.0)
+        ))
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The `Debug` format of a `CommandResult` with the stdout and stderr cut off
+/// at [error_output_limit]
+struct LimitedOutput<'a>(&'a CommandResult);
+
+impl Debug for LimitedOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = error_output_limit();
+        f.write_fmt(format_args!(
+            "CommandResult {{\ncommand: {:?},\nstatus: {:?},\n",
+            self.0.command, self.0.status
+        ))?;
+        let command = &self.0.command;
+        for (name, output, binary) in [
+            ("stdout", &self.0.stdout, command.stdout_binary),
+            ("stderr", &self.0.stderr, command.stderr_binary),
+        ] {
+            if output.is_empty() {
+                continue
+            }
+            if binary {
+                f.write_fmt(format_args!("{name}: {}\n,", BinaryOutput(output)))?;
+                continue
+            }
+            let shown = &output[..output.len().min(limit)];
+            f.write_fmt(format_args!("{name}: {}", String::from_utf8_lossy(shown)))?;
+            if shown.len() < output.len() {
+                f.write_fmt(format_args!(
+                    "... (truncated, {} bytes total)",
+                    output.len()
+                ))?;
+            }
+            f.write_str("\n,")?;
+        }
+        f.write_fmt(format_args!("}}"))
+    }
+}
+
 impl CommandResult {
     /// Returns a `CommandResultNoDebug` version of `self`
     pub fn no_debug(self) -> CommandResultNoDebug {
@@ -451,6 +1059,16 @@ impl CommandResult {
         }
     }
 
+    /// Returns if the command completed (not terminated early) with an exit
+    /// code in `success_codes`
+    pub fn successful_with(&self, success_codes: &[i32]) -> bool {
+        if let Some(code) = self.status.as_ref().and_then(|status| status.code()) {
+            success_codes.contains(&code)
+        } else {
+            false
+        }
+    }
+
     /// Returns if the command completed with a successful return status or was
     /// terminated early
     pub fn successful_or_terminated(&self) -> bool {
@@ -462,19 +1080,28 @@ impl CommandResult {
     }
 
     /// Returns a formatted error with relevant information if the command was
-    /// not successful
+    /// not successful. The stdout and stderr in the error are cut off at
+    /// [error_output_limit]. If this is a docker CLI command that failed
+    /// because the daemon could not be reached, the error has a boxed
+    /// [DaemonUnreachableError](crate::docker_helpers::DaemonUnreachableError).
     pub fn assert_success(&self) -> Result<()> {
         if let Some(status) = self.status.as_ref() {
             if status.success() {
                 Ok(())
             } else {
-                Err(Error::from_kind_locationless(format!(
-                    "{self:#?}.assert_success() -> unsuccessful"
+                let e = match daemon_unreachable(self) {
+                    Some(unreachable) => Error::box_from_locationless(unreachable),
+                    None => Error::empty(),
+                };
+                Err(e.add_kind_locationless(format!(
+                    "{:#?}.assert_success() -> unsuccessful",
+                    LimitedOutput(self)
                 )))
             }
         } else {
             Err(Error::from_kind_locationless(format!(
-                "{self:#?}.assert_success() -> termination was called before completion"
+                "{:#?}.assert_success() -> termination was called before completion",
+                LimitedOutput(self)
             )))
         }
     }
@@ -490,14 +1117,119 @@ impl CommandResult {
     }
 
     /// Returns `String::from_utf8_lossy(&self.stdout)`
-    pub fn stdout_as_utf8_lossy(&self) -> Cow<str> {
+    pub fn stdout_as_utf8_lossy(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.stdout)
     }
 
     /// Returns `String::from_utf8_lossy(&self.stderr)`
-    pub fn stderr_as_utf8_lossy(&self) -> Cow<str> {
+    pub fn stderr_as_utf8_lossy(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.stderr)
     }
+
+    /// Returns the path of the stdout spill file if [Command::record_spill] was
+    /// used
+    pub fn stdout_spill_path(&self) -> Option<&Path> {
+        self.command
+            .record_spill
+            .as_ref()
+            .and_then(|spill| spill.stdout_path.as_deref())
+    }
+
+    /// Returns the path of the stderr spill file if [Command::record_spill] was
+    /// used
+    pub fn stderr_spill_path(&self) -> Option<&Path> {
+        self.command
+            .record_spill
+            .as_ref()
+            .and_then(|spill| spill.stderr_path.as_deref())
+    }
+
+    /// Returns the full stdout, which is the contents of the spill file (if
+    /// [Command::record_spill] was used) followed by `self.stdout`
+    pub fn full_stdout(&self) -> Result<Vec<u8>> {
+        full_output(self.stdout_spill_path(), &self.stdout)
+            .stack_err_locationless(|| "CommandResult::full_stdout")
+    }
+
+    /// Returns the full stderr, which is the contents of the spill file (if
+    /// [Command::record_spill] was used) followed by `self.stderr`
+    pub fn full_stderr(&self) -> Result<Vec<u8>> {
+        full_output(self.stderr_spill_path(), &self.stderr)
+            .stack_err_locationless(|| "CommandResult::full_stderr")
+    }
+
+    /// Returns the lowercase hex SHA-256 digest of `self.stdout`
+    pub fn stdout_digest(&self) -> String {
+        sha256_hex(&self.stdout)
+    }
+
+    /// Returns the lowercase hex SHA-256 digest of `self.stderr`
+    pub fn stderr_digest(&self) -> String {
+        sha256_hex(&self.stderr)
+    }
+
+    /// Writes the full stdout (see [CommandResult::full_stdout]) to the file at
+    /// `file_path` unaltered, using the [FileOptions::write] defaults
+    pub async fn write_stdout_to(&self, file_path: impl AsRef<Path>) -> Result<()> {
+        let full = self
+            .full_stdout()
+            .stack_err_locationless(|| "CommandResult::write_stdout_to")?;
+        bytes_to_file(&full, file_path.as_ref())
+            .await
+            .stack_err_locationless(|| "CommandResult::write_stdout_to")
+    }
+
+    /// Writes the full stderr (see [CommandResult::full_stderr]) to the file at
+    /// `file_path` unaltered, using the [FileOptions::write] defaults
+    pub async fn write_stderr_to(&self, file_path: impl AsRef<Path>) -> Result<()> {
+        let full = self
+            .full_stderr()
+            .stack_err_locationless(|| "CommandResult::write_stderr_to")?;
+        bytes_to_file(&full, file_path.as_ref())
+            .await
+            .stack_err_locationless(|| "CommandResult::write_stderr_to")
+    }
+
+    /// Consumes `self` and returns the stdout as a `Cursor`, which implements
+    /// `AsyncRead` for chaining with other async I/O
+    pub fn into_stdout_reader(self) -> Cursor<Vec<u8>> {
+        Cursor::new(self.stdout)
+    }
+
+    /// Writes the stdout to the file at `file_path` with `tokio::io::copy`,
+    /// using the [FileOptions::write] defaults
+    pub async fn stdout_to_file(&self, file_path: impl AsRef<Path>) -> Result<()> {
+        bytes_to_file(&self.stdout, file_path.as_ref())
+            .await
+            .stack_err_locationless(|| "CommandResult::stdout_to_file")
+    }
+
+    /// Writes the stderr to the file at `file_path` with `tokio::io::copy`,
+    /// using the [FileOptions::write] defaults
+    pub async fn stderr_to_file(&self, file_path: impl AsRef<Path>) -> Result<()> {
+        bytes_to_file(&self.stderr, file_path.as_ref())
+            .await
+            .stack_err_locationless(|| "CommandResult::stderr_to_file")
+    }
+}
+
+async fn bytes_to_file(mut bytes: &[u8], file_path: &Path) -> Result<()> {
+    let mut file = FileOptions::write(file_path).acquire_file().await?;
+    tokio::io::copy(&mut bytes, &mut file)
+        .await
+        .stack_err_locationless(|| format!("when copying to {file_path:?}"))?;
+    close_file(file).await
+}
+
+fn full_output(spill_path: Option<&Path>, tail: &[u8]) -> Result<Vec<u8>> {
+    let mut full = if let Some(spill_path) = spill_path {
+        std::fs::read(spill_path)
+            .stack_err_locationless(|| format!("when reading spill file {spill_path:?}"))?
+    } else {
+        vec![]
+    };
+    full.extend_from_slice(tail);
+    Ok(full)
 }
 
 /// The same as a [CommandResult](crate::CommandResult), but the stdout and
@@ -546,6 +1278,16 @@ impl CommandResultNoDebug {
         }
     }
 
+    /// Returns if the command completed (not terminated early) with an exit
+    /// code in `success_codes`
+    pub fn successful_with(&self, success_codes: &[i32]) -> bool {
+        if let Some(code) = self.status.as_ref().and_then(|status| status.code()) {
+            success_codes.contains(&code)
+        } else {
+            false
+        }
+    }
+
     /// Returns if the command completed with a successful return status or was
     /// terminated early
     pub fn successful_or_terminated(&self) -> bool {
@@ -585,12 +1327,12 @@ impl CommandResultNoDebug {
     }
 
     /// Returns `String::from_utf8_lossy(&self.stdout)`
-    pub fn stdout_as_utf8_lossy(&self) -> Cow<str> {
+    pub fn stdout_as_utf8_lossy(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.stdout)
     }
 
     /// Returns `String::from_utf8_lossy(&self.stderr)`
-    pub fn stderr_as_utf8_lossy(&self) -> Cow<str> {
+    pub fn stderr_as_utf8_lossy(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.stderr)
     }
 }
@@ -1,5 +1,15 @@
 use core::fmt;
-use std::{collections::VecDeque, fmt::Debug, process::Stdio, sync::Arc, time::Duration};
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    path::Path,
+    process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use stacked_errors::{Error, Result, StackableErr};
 use tokio::{
@@ -8,20 +18,225 @@ use tokio::{
     process::{self, Child},
     sync::Mutex,
     task::{self, JoinHandle},
-    time::{sleep, timeout},
+    time::{sleep, timeout, Instant},
 };
-use tracing::warn;
+use tracing::{debug, info, Instrument, Span};
 
-use crate::{acquire_dir_path, next_terminal_color, Command, CommandResult};
+use crate::{
+    acquire_dir_path,
+    command::{env_passthrough_var_keys, passthrough_envs},
+    deadline_after,
+    misc::{emit_crate_event, strictness_violation},
+    next_terminal_color,
+    paths::resolve,
+    strictness, Backoff, BufferPolicy, Command, CommandResult, CrateEvent, FileOptions,
+    LineCallback, DEFAULT_WAIT_BACKOFF,
+};
 
 // note that most things should use `_locationless`, especially if they are
 // expected to be able to error under normal `Command` running circumstances,
 // the string info should be enough
 
-// TODO IIRC starting lines could be cutoff, this may be because both recorders
-// both truncate the file, stepping on each other's first output. Perhaps have
-// an `Arc<AtomicBool>` or something to communicate, and change one of the
-// `FileOptions` to not truncate?.
+/// A log file shared between the recorders that write to it, so that a
+/// combined log of stdout and stderr is opened (and truncated) only once and
+/// `log_limit` applies to the whole file
+struct LogSink {
+    file: File,
+    // for tracking how much has been written to the file
+    len: u64,
+}
+
+type SharedLogSink = Arc<Mutex<LogSink>>;
+
+/// Opens the `stdout_logs` and `stderr_logs` of `command`, with each distinct
+/// path opened once
+async fn open_log_sinks(command: &Command) -> Result<(Vec<SharedLogSink>, Vec<SharedLogSink>)> {
+    let mut opened: Vec<(&Path, SharedLogSink)> = vec![];
+    let mut sinks = (vec![], vec![]);
+    for (options, is_stdout) in command
+        .stdout_logs
+        .iter()
+        .map(|options| (options, true))
+        .chain(command.stderr_logs.iter().map(|options| (options, false)))
+    {
+        let sink = if let Some((_, sink)) = opened.iter().find(|(path, _)| *path == options.path) {
+            Arc::clone(sink)
+        } else {
+            let file = options.acquire_file().await.stack_err_locationless(|| {
+                format!(
+                    "{command:?}.run() -> failed to open the log file {:?}",
+                    options.path
+                )
+            })?;
+            let sink = Arc::new(Mutex::new(LogSink { file, len: 0 }));
+            opened.push((&options.path, Arc::clone(&sink)));
+            sink
+        };
+        let stream_sinks = if is_stdout {
+            &mut sinks.0
+        } else {
+            &mut sinks.1
+        };
+        // the same file listed twice for one stream is only written once
+        if !stream_sinks.iter().any(|other| Arc::ptr_eq(other, &sink)) {
+            stream_sinks.push(sink);
+        }
+    }
+    Ok(sinks)
+}
+
+/// Writes the prefixed lines of debug forwarding to a standard stream of this
+/// process, see [Command::debug_buffering] and
+/// [Command::max_debug_lines_per_sec]
+struct DebugForwarder<W> {
+    std_forward: W,
+    prefix: String,
+    buffering: Option<BufferPolicy>,
+    buf: Vec<u8>,
+    // when the oldest bytes in `buf` were buffered
+    buf_start: Option<Instant>,
+    max_lines_per_sec: Option<u64>,
+    window_start: Instant,
+    window_lines: u64,
+    suppressed: u64,
+    // if the current line is being dropped
+    dropping: bool,
+}
+
+impl<W: AsyncWrite + Unpin> DebugForwarder<W> {
+    fn new(
+        std_forward: W,
+        prefix: String,
+        buffering: Option<BufferPolicy>,
+        max_lines_per_sec: Option<u64>,
+    ) -> Self {
+        Self {
+            std_forward,
+            prefix,
+            buffering,
+            buf: vec![],
+            buf_start: None,
+            max_lines_per_sec,
+            window_start: Instant::now(),
+            window_lines: 0,
+            suppressed: 0,
+            dropping: false,
+        }
+    }
+
+    /// Writes `bytes`, which start with the prefix if `starts_line` is set
+    async fn write(&mut self, bytes: &[u8], starts_line: bool) {
+        if starts_line {
+            self.dropping = !self.admit_line().await;
+        }
+        if !self.dropping {
+            self.push(bytes).await;
+        }
+    }
+
+    /// Returns if a new line can be forwarded under `max_lines_per_sec`
+    async fn admit_line(&mut self) -> bool {
+        let Some(max) = self.max_lines_per_sec else {
+            return true
+        };
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.write_suppressed().await;
+            self.window_start = Instant::now();
+            self.window_lines = 0;
+        }
+        if self.window_lines < max {
+            self.window_lines += 1;
+            true
+        } else {
+            self.suppressed += 1;
+            false
+        }
+    }
+
+    /// Writes the marker for suppressed lines if there are any, this must
+    /// only be called between lines
+    async fn write_suppressed(&mut self) {
+        if self.suppressed != 0 {
+            let marker = format!(
+                "{}\u{2026} {} lines suppressed \u{2026}\n",
+                self.prefix, self.suppressed
+            );
+            self.suppressed = 0;
+            self.push(marker.as_bytes()).await;
+        }
+    }
+
+    async fn push(&mut self, bytes: &[u8]) {
+        let Some(policy) = self.buffering else {
+            self.std_forward
+                .write_all(bytes)
+                .await
+                .expect(FORWARDING_FAILED);
+            return
+        };
+        self.buf.extend_from_slice(bytes);
+        self.buf_start.get_or_insert_with(Instant::now);
+        if self.buf.len() >= policy.max_bytes {
+            // keep an incomplete last line for the next write if possible
+            let end = self
+                .buf
+                .iter()
+                .rposition(|byte| *byte == b'\n')
+                .map_or(self.buf.len(), |i| i + 1);
+            self.write_buffered(end).await;
+        }
+    }
+
+    /// Writes the first `end` bytes of `buf`
+    async fn write_buffered(&mut self, end: usize) {
+        self.std_forward
+            .write_all(&self.buf[..end])
+            .await
+            .expect(FORWARDING_FAILED);
+        self.std_forward.flush().await.expect(FORWARDING_FAILED);
+        self.buf.drain(..end);
+        self.buf_start = if self.buf.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        };
+        // if set excessively large by some burst, shrink
+        if self.buf.capacity() > (64 * 1024) {
+            self.buf.shrink_to_fit();
+        }
+    }
+
+    /// Called after each read and when reading times out, this flushes
+    /// according to the buffering and writes the marker for suppressed lines
+    /// once their second has passed
+    async fn tick(&mut self) {
+        if self.dropping && (self.window_start.elapsed() >= Duration::from_secs(1)) {
+            self.write_suppressed().await;
+            // the next line starts a new window
+            self.window_start = Instant::now();
+            self.window_lines = 0;
+        }
+        match (self.buffering, self.buf_start) {
+            (None, _) => self.std_forward.flush().await.expect(FORWARDING_FAILED),
+            (Some(policy), Some(buf_start)) if buf_start.elapsed() >= policy.max_delay => {
+                self.write_buffered(self.buf.len()).await;
+            }
+            _ => (),
+        }
+    }
+
+    /// Writes everything remaining
+    async fn finish(&mut self) {
+        if self.dropping {
+            self.write_suppressed().await;
+        }
+        let len = self.buf.len();
+        self.write_buffered(len).await;
+    }
+}
+
+const FORWARDING_FAILED: &str =
+    "`super_orchestrator::Command` stdout or stderr recording failed on write";
 
 /// Used as the engine in the stdout and stderr recording tasks. `unwrap`s only
 /// are used in here because it is spawned as a separate task.
@@ -31,15 +246,15 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
     mut std_read: BufReader<R>,
     mut std_record: Option<Arc<Mutex<VecDeque<u8>>>>,
     record_limit: Option<u64>,
-    mut std_log: Option<File>,
+    // the threshold and file for `Command::record_spill`
+    mut std_spill: Option<(u64, File)>,
+    std_logs: Vec<SharedLogSink>,
     log_limit: Option<u64>,
-    // write point and prefix
-    mut std_forward: Option<(W, String)>,
+    mut std_forward: Option<DebugForwarder<W>>,
+    // the `stream` field if lines should be emitted as `tracing` events
+    tracing_stream: Option<&'static str>,
+    line_callback: Option<LineCallback>,
 ) {
-    const FORWARDING_FAILED: &str =
-        "`super_orchestrator::Command` stdout or stderr recording failed on write";
-    // for tracking how much has been written to the file
-    let mut log_len = 0u64;
     // if the previous read had a newline on the end (for forwarding to stdout)
     let mut previous_newline = false;
     // if no bytes have been written (for forwarding to stdout)
@@ -47,35 +262,80 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
     let mut line_buf = Vec::new();
     // when a utf8 codepoint is cut up across reads, we need to store it here
     let mut cut_up: Option<Vec<u8>> = None;
+    // the incomplete last line for `tracing_stream` and `line_callback`
+    let mut tracing_line = Vec::new();
+    // the bytes drained from the record that need to be appended to the spill file
+    let mut spill_buf = Vec::new();
+    let split_lines = tracing_stream.is_some() || line_callback.is_some();
+    let emit_line = |line: &[u8]| {
+        let line = String::from_utf8_lossy(line);
+        if let Some(stream) = tracing_stream {
+            info!(stream, "{line}");
+        }
+        if let Some(ref line_callback) = line_callback {
+            line_callback(&line);
+        }
+    };
     // 8 KB, like BufReader
     let mut buf = [0u8; 8 * 1024];
     loop {
         match timeout(read_loop_timeout, std_read.read(&mut buf)).await {
             Ok(Ok(bytes_read)) => {
                 if bytes_read == 0 {
+                    if split_lines && !tracing_line.is_empty() {
+                        emit_line(&tracing_line);
+                    }
                     // if there has been nonempty output with no ending newline insert one upon
                     // completion
-                    if (!empty) && (!previous_newline) {
-                        if let Some((ref mut std_forward, _)) = std_forward {
+                    if let Some(ref mut std_forward) = std_forward {
+                        if (!empty) && (!previous_newline) {
                             if cut_up.is_some() {
                                 // the outside precondition is always met in case of an incomplete
-                                std_forward
-                                    .write_all("\u{fffd}\n".as_bytes())
-                                    .await
-                                    .expect(FORWARDING_FAILED);
+                                std_forward.write("\u{fffd}\n".as_bytes(), false).await;
                             } else {
-                                std_forward.write_all(b"\n").await.expect(FORWARDING_FAILED);
+                                std_forward.write(b"\n", false).await;
                             }
-                            std_forward.flush().await.unwrap();
                         }
+                        std_forward.finish().await;
+                    }
+                    // tokio finishes file writes in the background, so the logs are flushed for
+                    // them to be complete when the command is
+                    for std_log in &std_logs {
+                        std_log
+                            .lock()
+                            .await
+                            .file
+                            .flush()
+                            .await
+                            .expect(FORWARDING_FAILED);
                     }
                     break
                 }
                 let mut bytes = &buf[..bytes_read];
+                if split_lines {
+                    for byte in bytes {
+                        if *byte == b'\n' {
+                            emit_line(&tracing_line);
+                            tracing_line.clear();
+                        } else {
+                            tracing_line.push(*byte);
+                        }
+                    }
+                    if tracing_line.capacity() > (8 * 1024) && tracing_line.is_empty() {
+                        tracing_line.shrink_to_fit();
+                    }
+                }
                 // copying to record
                 if let Some(ref mut arc) = std_record {
                     let mut deque = arc.lock().await;
-                    if let Some(limit) = record_limit {
+                    if let Some((threshold, _)) = std_spill {
+                        let threshold = usize::try_from(threshold).unwrap();
+                        deque.extend(bytes);
+                        if deque.len() > threshold {
+                            let excess = deque.len().wrapping_sub(threshold);
+                            spill_buf.extend(deque.drain(..excess));
+                        }
+                    } else if let Some(limit) = record_limit {
                         let limit = usize::try_from(limit).unwrap();
                         if deque.len().saturating_add(bytes.len()) > limit {
                             // we would overflow the limit if all the `bytes` were inserted
@@ -96,34 +356,50 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
                         deque.extend(bytes);
                     }
                 }
-                // copying to file
-                if let Some(ref mut std_log) = std_log {
+                // spilling to file, this is done after the lock is released
+                if let Some((_, ref mut spill_file)) = std_spill {
+                    if !spill_buf.is_empty() {
+                        spill_file
+                            .write_all(&spill_buf)
+                            .await
+                            .expect(FORWARDING_FAILED);
+                        spill_file.flush().await.expect(FORWARDING_FAILED);
+                        spill_buf.clear();
+                    }
+                }
+                // copying to files
+                for std_log in &std_logs {
+                    let mut std_log = std_log.lock().await;
+                    let LogSink {
+                        file: ref mut log_file,
+                        len: ref mut log_len,
+                    } = *std_log;
                     let mut reset = false;
                     let len = u64::try_from(bytes.len()).unwrap();
-                    log_len = log_len.checked_add(len).unwrap();
+                    *log_len = log_len.checked_add(len).unwrap();
                     if let Some(limit) = log_limit {
-                        if log_len > limit {
+                        if *log_len > limit {
                             reset = true;
-                            std_log.set_len(0).await.unwrap();
-                            std_log.seek(std::io::SeekFrom::Start(0)).await.unwrap();
+                            log_file.set_len(0).await.unwrap();
+                            log_file.seek(std::io::SeekFrom::Start(0)).await.unwrap();
                             let start = if len > limit {
                                 len.wrapping_sub(limit)
                             } else {
                                 0
                             };
-                            std_log
+                            log_file
                                 .write_all(&bytes[usize::try_from(start).unwrap()..])
                                 .await
                                 .expect(FORWARDING_FAILED);
-                            log_len = len.wrapping_sub(start);
+                            *log_len = len.wrapping_sub(start);
                         }
                     }
                     if !reset {
-                        std_log.write_all(bytes).await.expect(FORWARDING_FAILED);
+                        log_file.write_all(bytes).await.expect(FORWARDING_FAILED);
                     }
                 }
                 // copying to std stream
-                if let Some((ref mut std_forward, ref prefix)) = std_forward {
+                if let Some(ref mut std_forward) = std_forward {
                     let mut tmp = Vec::new();
                     if let Some(cut_up) = cut_up.take() {
                         // prepend the possibly cut up bytes, this should be very rare
@@ -147,15 +423,13 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
 
                                 // if there has been no writing yet, or the last writing had a
                                 // newline, then insert the terminal prefix
-                                if empty || previous_newline {
-                                    line_buf.extend_from_slice(prefix.as_bytes());
+                                let starts_line = empty || previous_newline;
+                                if starts_line {
+                                    line_buf.extend_from_slice(std_forward.prefix.as_bytes());
                                 }
                                 previous_newline = line.last() == Some(&b'\n');
                                 line_buf.extend_from_slice(line);
-                                std_forward
-                                    .write_all(&line_buf)
-                                    .await
-                                    .expect(FORWARDING_FAILED);
+                                std_forward.write(&line_buf, starts_line).await;
                                 line_buf.clear();
                                 empty = false;
                             }
@@ -163,8 +437,9 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
                         let invalid = utf8_chunk.invalid();
                         if !invalid.is_empty() {
                             // need to have this again
-                            if empty || previous_newline {
-                                line_buf.extend_from_slice(prefix.as_bytes());
+                            let starts_line = empty || previous_newline;
+                            if starts_line {
+                                line_buf.extend_from_slice(std_forward.prefix.as_bytes());
                             }
                             if utf8_chunk.incomplete() {
                                 // the next read pass or ending will pick this up
@@ -175,10 +450,7 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
                                 line_buf.extend_from_slice("\u{fffd}".as_bytes());
                             }
                             if !line_buf.is_empty() {
-                                std_forward
-                                    .write_all(&line_buf)
-                                    .await
-                                    .expect(FORWARDING_FAILED);
+                                std_forward.write(&line_buf, starts_line).await;
                                 line_buf.clear();
                             }
                             previous_newline = false;
@@ -189,7 +461,7 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
                     if line_buf.capacity() > (8 * 1024) {
                         line_buf.shrink_to_fit();
                     }
-                    std_forward.flush().await.unwrap();
+                    std_forward.tick().await;
                 }
             }
             Ok(Err(e)) => {
@@ -199,7 +471,11 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
                 )
             }
             // timeout
-            Err(_) => (),
+            Err(_) => {
+                if let Some(ref mut std_forward) = std_forward {
+                    std_forward.tick().await;
+                }
+            }
         }
     }
 }
@@ -218,17 +494,16 @@ async fn recorder<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
 /// The `Default` impl is for if an empty runner not attached to anything is
 /// needed for some reason.
 #[must_use]
-#[derive(Default)]
 pub struct CommandRunner {
     // this information is kept around for failures
     /// The command this runner was started with
     command: Option<Command>,
     /// The handle to the `Child` process. The `ChildStdout` was taken if there
-    /// was any kind of recording. `stdout_recording`, `stdout_debug`, and
-    /// `stdout_log.is_some()` should all be false in the `Command` if you
-    /// want direct access to the `ChildStdout`. Likewise, `stderr_recording`,
-    /// `stderr_debug`, and `stderr_log.is_some()` should be all false if
-    /// you want `ChildStderr`.
+    /// was any kind of recording. `stdout_recording` and `stdout_debug`
+    /// should be false and `stdout_logs` should be empty in the `Command` if
+    /// you want direct access to the `ChildStdout`. Likewise,
+    /// `stderr_recording` and `stderr_debug` should be false and
+    /// `stderr_logs` should be empty if you want `ChildStderr`.
     pub child_process: Option<Child>,
     handles: Vec<tokio::task::JoinHandle<()>>,
 
@@ -245,6 +520,21 @@ pub struct CommandRunner {
     /// `VecDeque` operations.
     pub stderr_record: Arc<Mutex<VecDeque<u8>>>,
     result: Option<CommandResult>,
+    wait_backoff: Backoff,
+}
+
+impl Default for CommandRunner {
+    fn default() -> Self {
+        Self {
+            command: None,
+            child_process: None,
+            handles: vec![],
+            stdout_record: Default::default(),
+            stderr_record: Default::default(),
+            result: None,
+            wait_backoff: DEFAULT_WAIT_BACKOFF,
+        }
+    }
 }
 
 impl Debug for CommandRunner {
@@ -255,39 +545,73 @@ impl Debug for CommandRunner {
             .field("child_process", &self.child_process)
             .field("handles", &self.handles)
             .field("result", &self.result)
+            .field("wait_backoff", &self.wait_backoff)
             .finish()
     }
 }
 
 impl Drop for CommandRunner {
     fn drop(&mut self) {
-        // we could call `try_wait` and see if the process has actually exited or not,
-        // but the user should have called one of the consuming functions
+        // the user should have called one of the consuming functions
 
         // we purposely parenthesize in this way to avoid calling `panicking` in the
         // normal case
-        if self.child_process.is_some() && (!std::thread::panicking()) {
-            warn!(
-                "A `CommandRunner` was dropped without being properly finished, the command was: \
-                 {}",
-                self.command
-                    .as_ref()
-                    .map(|c| c.get_unified_command())
-                    .unwrap_or_default()
-            )
+        let detached = self
+            .command
+            .as_ref()
+            .is_some_and(|command| command.suppress_drop_warning);
+        if (!detached) && (!std::thread::panicking()) {
+            let Some(child) = self.child_process.as_mut() else {
+                return
+            };
+            let state = match child.try_wait() {
+                Ok(Some(status)) => format!("had exited with {status}"),
+                Ok(None) => "was still running".to_owned(),
+                Err(e) => format!("had an unknown state ({e})"),
+            };
+            let command = self
+                .command
+                .as_ref()
+                .map(|c| c.get_unified_command())
+                .unwrap_or_default();
+            strictness_violation(
+                strictness(),
+                format!(
+                    "A `CommandRunner` was dropped without being properly finished, the child \
+                     process {state}, the command was: {command}"
+                ),
+            );
+            emit_crate_event(|| CrateEvent::DroppedRunner { command });
         }
     }
 }
 
+// for unique spill file names within this process
+static SPILL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 pub(crate) async fn command_runner<C: Into<Stdio>>(
     this: Command,
     stdin_cfg: C,
+) -> Result<CommandRunner> {
+    command_runner_with(this, stdin_cfg, |_| ()).await
+}
+
+/// Same as `command_runner`, but `configure` is called on the process command
+/// right before it is spawned
+pub(crate) async fn command_runner_with<C: Into<Stdio>, F: FnOnce(&mut process::Command)>(
+    mut this: Command,
+    stdin_cfg: C,
+    configure: F,
 ) -> Result<CommandRunner> {
     let mut cmd = process::Command::new(&this.program);
-    if this.env_clear {
+    let passthrough_keys = env_passthrough_var_keys();
+    if this.env_clear || passthrough_keys.is_some() {
         // must happen before the `envs` call
         cmd.env_clear();
     }
+    if let Some(keys) = passthrough_keys {
+        cmd.envs(passthrough_envs(keys));
+    }
     if let Some(ref cwd) = this.cwd {
         let cwd = acquire_dir_path(cwd).await.stack_err_locationless(|| {
             format!("{this:?}.run() -> failed to acquire current working directory")
@@ -295,16 +619,7 @@ pub(crate) async fn command_runner<C: Into<Stdio>>(
         cmd.current_dir(cwd);
     }
     // do as much as possible before spawning the process
-    let stdout_log = if let Some(ref options) = this.stdout_log {
-        Some(options.acquire_file().await?)
-    } else {
-        None
-    };
-    let stderr_log = if let Some(ref options) = this.stderr_log {
-        Some(options.acquire_file().await?)
-    } else {
-        None
-    };
+    let (stdout_logs, stderr_logs) = open_log_sinks(&this).await?;
     let stdout_record = Arc::new(Mutex::new(VecDeque::new()));
     let stdout_record_clone = if this.stdout_recording && (this.record_limit != Some(0)) {
         Some(Arc::clone(&stdout_record))
@@ -317,6 +632,43 @@ pub(crate) async fn command_runner<C: Into<Stdio>>(
     } else {
         None
     };
+    let (stdout_spill, stderr_spill) = if let Some(ref mut spill) = this.record_spill {
+        let (dir, base) = resolve(&spill.dir);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "Command::run -> could not create the `record_spill` directory {:?}{base}",
+                    spill.dir
+                )
+            })?;
+        // the files are created before spawning, so the pid is not available
+        let n = SPILL_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let name = Path::new(&this.program)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = format!("{name}_{}_{n}", std::process::id());
+        let stdout_path = dir.join(format!("{name}_stdout.spill"));
+        let stderr_path = dir.join(format!("{name}_stderr.spill"));
+        let stdout_spill = if stdout_record_clone.is_some() {
+            let file = FileOptions::write(&stdout_path).acquire_file().await?;
+            spill.stdout_path = Some(stdout_path);
+            Some((spill.threshold, file))
+        } else {
+            None
+        };
+        let stderr_spill = if stderr_record_clone.is_some() {
+            let file = FileOptions::write(&stderr_path).acquire_file().await?;
+            spill.stderr_path = Some(stderr_path);
+            Some((spill.threshold, file))
+        } else {
+            None
+        };
+        (stdout_spill, stderr_spill)
+    } else {
+        (None, None)
+    };
     let record_limit = this.record_limit;
     let log_limit = this.log_limit;
     let program_name = this.program.to_string_lossy();
@@ -325,6 +677,7 @@ pub(crate) async fn command_runner<C: Into<Stdio>>(
     cmd.args(&this.args)
         .envs(this.envs.iter().map(|x| (&x.0, &x.1)))
         .kill_on_drop(!this.forget_on_drop);
+    configure(&mut cmd);
     let mut child = cmd
         .stdin(stdin_cfg)
         .stdout(Stdio::piped())
@@ -332,12 +685,27 @@ pub(crate) async fn command_runner<C: Into<Stdio>>(
         .spawn()
         .stack_err_locationless(|| format!("{this:?}.run() -> failed to spawn child process"))?;
     let child_id = child.id().unwrap();
-    let terminal_color = if this.stdout_debug || this.stderr_debug {
+    // binary streams are never forwarded, because they would corrupt the terminal
+    let stdout_debug = this.stdout_debug && !this.stdout_binary;
+    let stderr_debug = this.stderr_debug && !this.stderr_binary;
+    for (name, debug, binary) in [
+        ("stdout", this.stdout_debug, this.stdout_binary),
+        ("stderr", this.stderr_debug, this.stderr_binary),
+    ] {
+        if debug && binary {
+            debug!(
+                "Command::run -> {name} debug forwarding of {program_name} is disabled because \
+                 `{name}_binary` is set"
+            );
+        }
+    }
+    let terminal_color = if stdout_debug || stderr_debug {
         next_terminal_color()
     } else {
         owo_colors::AnsiColors::Default
     };
-    let stdout_forward = if this.stdout_debug {
+    let debug_output = this.debug_output;
+    let stdout_forward = if stdout_debug && debug_output.has_std() {
         let stdout = tokio::io::stdout();
         // TODO tokio does not support `IsTerminal` yet
         let prefix = if let Some(prefix) = &this.stdout_debug_line_prefix {
@@ -349,11 +717,16 @@ pub(crate) async fn command_runner<C: Into<Stdio>>(
             )
             .to_string()
         };
-        Some((stdout, prefix))
+        Some(DebugForwarder::new(
+            stdout,
+            prefix,
+            this.debug_buffering,
+            this.max_debug_lines_per_sec,
+        ))
     } else {
         None
     };
-    let stderr_forward = if this.stderr_debug {
+    let stderr_forward = if stderr_debug && debug_output.has_std() {
         let stderr = tokio::io::stderr();
         let prefix = if let Some(prefix) = &this.stderr_debug_line_prefix {
             prefix.clone()
@@ -364,39 +737,65 @@ pub(crate) async fn command_runner<C: Into<Stdio>>(
             )
             .to_string()
         };
-        Some((stderr, prefix))
+        Some(DebugForwarder::new(
+            stderr,
+            prefix,
+            this.debug_buffering,
+            this.max_debug_lines_per_sec,
+        ))
     } else {
         None
     };
     // dropping the stdout and stderr handles actually results in an error, we keep
     // all the stuff anyway in `child_process` if there is not any kind of recording
-    if this.stdout_recording || this.stdout_debug || this.stdout_log.is_some() {
+    if this.stdout_recording
+        || stdout_debug
+        || !this.stdout_logs.is_empty()
+        || this.line_callback.is_some()
+    {
         let stdout = child.stdout.take().unwrap();
         let stdout_read = BufReader::new(stdout);
-        handles.push(task::spawn(recorder(
-            read_loop_timeout,
-            stdout_read,
-            stdout_record_clone,
-            record_limit,
-            stdout_log,
-            log_limit,
-            stdout_forward,
-        )));
-    }
-    if this.stderr_recording || this.stderr_debug || this.stderr_log.is_some() {
+        handles.push(task::spawn(
+            recorder(
+                read_loop_timeout,
+                stdout_read,
+                stdout_record_clone,
+                record_limit,
+                stdout_spill,
+                stdout_logs,
+                log_limit,
+                stdout_forward,
+                (stdout_debug && debug_output.has_tracing()).then_some("stdout"),
+                this.line_callback.clone(),
+            )
+            .instrument(Span::current()),
+        ));
+    }
+    if this.stderr_recording
+        || stderr_debug
+        || !this.stderr_logs.is_empty()
+        || this.line_callback.is_some()
+    {
         let stderr = child.stderr.take().unwrap();
         let stderr_read = BufReader::new(stderr);
-        handles.push(task::spawn(recorder(
-            read_loop_timeout,
-            stderr_read,
-            stderr_record_clone,
-            record_limit,
-            stderr_log,
-            log_limit,
-            stderr_forward,
-        )));
+        handles.push(task::spawn(
+            recorder(
+                read_loop_timeout,
+                stderr_read,
+                stderr_record_clone,
+                record_limit,
+                stderr_spill,
+                stderr_logs,
+                log_limit,
+                stderr_forward,
+                (stderr_debug && debug_output.has_tracing()).then_some("stderr"),
+                this.line_callback.clone(),
+            )
+            .instrument(Span::current()),
+        ));
     }
     Ok(CommandRunner {
+        wait_backoff: this.wait_backoff,
         command: Some(this),
         child_process: Some(child),
         handles,
@@ -406,7 +805,7 @@ pub(crate) async fn command_runner<C: Into<Stdio>>(
     })
 }
 
-/// Note: there are `send_unix_signal` and `send_unix_sigterm` function that can
+/// Note: there are `send_unix_*` functions for sending signals that can
 /// be enabled by the "nix_support" feature
 impl CommandRunner {
     /// Attempts to force the command to exit, but does not wait for the request
@@ -432,9 +831,10 @@ impl CommandRunner {
             child_process.kill().await.stack_err(|| {
                 "CommandRunner::terminate -> running `kill` on the child process failed"
             })?;
-            drop(self.child_process.take().unwrap());
+            // the child is only dropped after the last await point for cancel safety
             let stdout = self.stdout_record.lock().await.iter().cloned().collect();
             let stderr = self.stderr_record.lock().await.iter().cloned().collect();
+            drop(self.child_process.take().unwrap());
             self.result = Some(CommandResult {
                 command: self.command.take().unwrap(),
                 status: None,
@@ -449,6 +849,17 @@ impl CommandRunner {
         }
     }
 
+    /// Marks the runner as intentionally unmanaged, so that dropping it
+    /// without finishing it does not warn (see
+    /// [Command::suppress_drop_warning]). The child is still killed on drop
+    /// unless [Command::forget_on_drop] was set.
+    pub fn detach(&mut self) -> &mut Self {
+        if let Some(command) = self.command.as_mut() {
+            command.suppress_drop_warning = true;
+        }
+        self
+    }
+
     /// Returns the `pid` of the child process. Returns `None` if the command
     /// has been terminated or the internal `id` call returned `None`.
     pub fn pid(&self) -> Option<u32> {
@@ -485,32 +896,95 @@ impl CommandRunner {
         self.send_unix_signal(nix::sys::signal::Signal::SIGTERM)
     }
 
+    /// Sends `SIGCONT`, which resumes a process that was stopped with
+    /// `SIGSTOP` or `SIGTSTP`
+    #[cfg(feature = "nix_support")]
+    pub fn send_unix_sigcont(&self) -> Result<()> {
+        self.send_unix_signal(nix::sys::signal::Signal::SIGCONT)
+    }
+
+    /// Sends `SIGWINCH`, which notifies the process of a terminal window size
+    /// change
+    #[cfg(feature = "nix_support")]
+    pub fn send_unix_sigwinch(&self) -> Result<()> {
+        self.send_unix_signal(nix::sys::signal::Signal::SIGWINCH)
+    }
+
+    /// Sends the user defined `SIGUSR1`
+    #[cfg(feature = "nix_support")]
+    pub fn send_unix_sigusr1(&self) -> Result<()> {
+        self.send_unix_signal(nix::sys::signal::Signal::SIGUSR1)
+    }
+
+    /// Sends the user defined `SIGUSR2`
+    #[cfg(feature = "nix_support")]
+    pub fn send_unix_sigusr2(&self) -> Result<()> {
+        self.send_unix_signal(nix::sys::signal::Signal::SIGUSR2)
+    }
+
+    /// Sends a Unix `Signal` to the process group whose ID is the PID of the
+    /// process. Note that the process is only the leader of such a group if
+    /// it or its `Command` set up a new process group.
+    #[cfg(feature = "nix_support")]
+    pub fn send_unix_signal_to_process_group(
+        &self,
+        unix_signal: nix::sys::signal::Signal,
+    ) -> Result<()> {
+        nix::sys::signal::killpg(
+            nix::unistd::Pid::from_raw(
+                i32::try_from(self.pid().stack_err(|| {
+                    "CommandRunner::send_unix_signal_to_process_group -> PID overflow"
+                })?)
+                .stack_err(|| {
+                    "CommandRunner::send_unix_signal_to_process_group -> PID creation fail"
+                })?,
+            ),
+            unix_signal,
+        )
+        .stack_err(|| {
+            "CommandRunner::send_unix_signal_to_process_group -> `nix::sys::signal::killpg` failed"
+        })?;
+        Ok(())
+    }
+
     // TODO for ridiculous output sizes, we may want something that only looks at
     // the exit status from `try_wait`, so keep the `_with_output` functions in case
     // we want a plain `wait` function
 
+    // This is cancel safe, everything is only taken from `self` after the last
+    // await point so that it can be called again if its future is dropped
     async fn wait_with_output_internal(&mut self) -> Result<()> {
-        let output = self
-            .child_process
-            .take()
-            .stack_err_locationless(|| {
-                "`CommandRunner` has already had some termination method called"
-            })?
-            .wait_with_output()
-            .await
-            .stack_err_locationless(|| {
-                format!("{self:?}.wait_with_output() -> failed when waiting on child process")
-            })?;
-        while let Some(handle) = self.handles.pop() {
+        let child_process = self.child_process.as_mut().stack_err_locationless(|| {
+            "`CommandRunner` has already had some termination method called"
+        })?;
+        // any pipes that are not being recorded need to be drained like
+        // `wait_with_output` does, or else the child can block on a full pipe. The
+        // draining tasks are kept in `handles` so that they survive cancellation.
+        if let Some(mut stdout) = child_process.stdout.take() {
+            self.handles.push(task::spawn(async move {
+                let _ = tokio::io::copy(&mut stdout, &mut tokio::io::sink()).await;
+            }));
+        }
+        if let Some(mut stderr) = child_process.stderr.take() {
+            self.handles.push(task::spawn(async move {
+                let _ = tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await;
+            }));
+        }
+        let status = child_process.wait().await.stack_err_locationless(|| {
+            format!("{self:?}.wait_with_output() -> failed when waiting on child process")
+        })?;
+        while let Some(handle) = self.handles.last_mut() {
             handle.await.stack_err_locationless(|| {
                 format!("{self:?}.wait_with_output() -> `Command` task panicked")
             })?;
+            self.handles.pop();
         }
         let stdout = self.stdout_record.lock().await.iter().copied().collect();
         let stderr = self.stderr_record.lock().await.iter().copied().collect();
+        drop(self.child_process.take());
         self.result = Some(CommandResult {
             command: self.command.take().unwrap(),
-            status: Some(output.status),
+            status: Some(status),
             stdout,
             stderr,
         });
@@ -528,16 +1002,38 @@ impl CommandRunner {
         Ok(self.result.take().unwrap())
     }
 
+    /// Overrides the [Command::wait_backoff] that this was started with for
+    /// subsequent waits
+    pub fn wait_backoff(&mut self, wait_backoff: Backoff) -> &mut Self {
+        self.wait_backoff = wait_backoff;
+        self
+    }
+
     /// If the command does not complete after `duration`, returns a timeout
     /// error. After `Ok(())` is returned, the `CommandRunner` is finished and
     /// you can call `get_command_result`. Call [Error::is_timeout()] on the
-    /// error to see if it was a timeout or another kind of error.
+    /// error to see if it was a timeout or another kind of error. The process
+    /// is polled according to [Command::wait_backoff].
     ///
     /// Note: use `Duration::ZERO` if you want a single attempt
+    ///
+    /// This is cancel safe, if the future is dropped at any point then calling
+    /// this again or calling [CommandRunner::terminate] works as usual.
     pub async fn wait_with_timeout(&mut self, duration: Duration) -> Result<()> {
-        // backoff control
-        let mut interval = Duration::from_millis(1);
-        let mut elapsed = Duration::ZERO;
+        self.wait_until(deadline_after(duration)).await
+    }
+
+    /// The same as [CommandRunner::wait_with_timeout] but with a `deadline`.
+    /// If the deadline has already passed, this still checks for completion
+    /// like with `Duration::ZERO`.
+    pub async fn wait_until(&mut self, deadline: Instant) -> Result<()> {
+        let mut deadline = match self.wait_backoff.max_elapsed {
+            Some(max_elapsed) => deadline.min(deadline_after(max_elapsed)),
+            None => deadline,
+        };
+        let mut intervals = self.wait_backoff.intervals();
+        // give one extra round after the deadline passes
+        let mut skip_fail = true;
         loop {
             match self
                 .child_process
@@ -560,26 +1056,65 @@ impl CommandRunner {
                     })
                 }
             }
-            if elapsed > duration {
-                return Err(Error::timeout())
+            if Instant::now() >= deadline {
+                if skip_fail {
+                    skip_fail = false;
+                } else {
+                    return Err(Error::timeout())
+                }
             }
-            sleep(interval).await;
-            elapsed = elapsed.checked_add(interval).unwrap();
-            // TODO is this a good default maximum interval?
-            if interval < Duration::from_millis(128) {
-                interval = interval.checked_mul(2).unwrap();
+            match intervals.next() {
+                Some(interval) => sleep(interval).await,
+                // `max_retries` ran out
+                None => deadline = Instant::now(),
             }
         }
         self.wait_with_output_internal().await?;
         Ok(())
     }
 
+    /// Synchronously checks if the OS command has exited, returning the exit
+    /// status if it has. This does not finish the `CommandResult`, call
+    /// [CommandRunner::wait_with_timeout] afterwards to do that.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        self.child_process
+            .as_mut()
+            .stack_err_locationless(|| {
+                "CommandRunner::try_wait -> some termination method has already been called"
+            })?
+            .try_wait()
+            .map_err(Error::from_kind_locationless)
+            .stack_err_locationless(|| "CommandRunner::try_wait -> `try_wait` failed")
+    }
+
     /// After [CommandRunner::wait_with_timeout] is successful, this will return
     /// a reference to the `CommandResult`
     pub fn get_command_result(&mut self) -> Option<&CommandResult> {
         self.result.as_ref()
     }
 
+    /// Returns the path of the stdout spill file if
+    /// [Command::record_spill](crate::Command::record_spill) was used. This is
+    /// `None` after the `CommandResult` is produced, use
+    /// [CommandResult::stdout_spill_path] then.
+    pub fn stdout_spill_path(&self) -> Option<&Path> {
+        self.command
+            .as_ref()
+            .and_then(|command| command.record_spill.as_ref())
+            .and_then(|spill| spill.stdout_path.as_deref())
+    }
+
+    /// Returns the path of the stderr spill file if
+    /// [Command::record_spill](crate::Command::record_spill) was used. This is
+    /// `None` after the `CommandResult` is produced, use
+    /// [CommandResult::stderr_spill_path] then.
+    pub fn stderr_spill_path(&self) -> Option<&Path> {
+        self.command
+            .as_ref()
+            .and_then(|command| command.record_spill.as_ref())
+            .and_then(|spill| spill.stderr_path.as_deref())
+    }
+
     /// After [CommandRunner::wait_with_timeout] is successful, this will take
     /// the `CommandResult` from `self`, replacing it with `None`.
     pub fn take_command_result(&mut self) -> Option<CommandResult> {
@@ -0,0 +1,275 @@
+use std::{
+    fmt::{self, Debug, Display},
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use stacked_errors::{Error, Result, StackableErr};
+
+use crate::{
+    docker::{NetworkMode, NotOnNetworkError},
+    docker_helpers::{docker_command, ip_addr_from_inspect},
+    middleware::CommandMiddleware,
+    stacked_get, wait_for_ok, CommandResult,
+};
+
+/// The future returned by a hook
+pub type HookFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// The function wrapped by [ContainerHooks]
+pub type HookFn = dyn Fn(HookContext) -> HookFuture + Send + Sync;
+
+/// When a hook set by [ContainerHooks] is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HookPhase {
+    /// Just before `docker create`
+    BeforeCreate,
+    /// Just after `docker start`
+    AfterStart,
+    /// When a wait function finds that the container has exited
+    AfterExit,
+}
+
+impl Display for HookPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookPhase::BeforeCreate => f.write_str("before_create"),
+            HookPhase::AfterStart => f.write_str("after_start"),
+            HookPhase::AfterExit => f.write_str("after_exit"),
+        }
+    }
+}
+
+/// What happens when a hook returns an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HookFailurePolicy {
+    /// The error is returned like the failure of a docker command in the same
+    /// phase would be
+    #[default]
+    Fatal,
+    /// The error is logged as a warning and orchestration continues
+    Log,
+}
+
+/// What a hook gets to work with. This is detached from the
+/// `ContainerNetwork`, so it can be moved into the future of the hook.
+#[derive(Debug, Clone)]
+pub struct HookContext {
+    /// The phase that the hook is run in
+    pub phase: HookPhase,
+    /// The name of the container in the `ContainerNetwork`
+    pub name: String,
+    /// The name of the docker container
+    pub container_name: String,
+    /// The name of the docker network
+    pub network_name: String,
+    /// The [Container::network_mode](crate::docker::Container::network_mode)
+    pub network_mode: NetworkMode,
+    /// The docker ID of the container, `None` for `HookPhase::BeforeCreate`
+    pub id: Option<String>,
+    /// The result of the container, only for `HookPhase::AfterExit`
+    pub result: Option<CommandResult>,
+    pub(crate) command_middleware: Option<CommandMiddleware>,
+}
+
+impl HookContext {
+    fn running_id(&self, fn_name: &str) -> Result<&str> {
+        match (self.phase, &self.id) {
+            (HookPhase::AfterStart, Some(id)) => Ok(id),
+            _ => Err(Error::from_kind_locationless(format!(
+                "HookContext::{fn_name} -> container \"{}\" is not running in the {} phase",
+                self.name, self.phase
+            ))),
+        }
+    }
+
+    /// Runs `docker exec` with `argv` in the container, returning the
+    /// `CommandResult` which can be unsuccessful. This is only possible in
+    /// `HookPhase::AfterStart`.
+    pub async fn exec<I, S>(&self, argv: I) -> Result<CommandResult>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let id = self.running_id("exec")?;
+        let command = docker_command("exec")
+            .arg(id)
+            .args(argv.into_iter().map(|s| s.as_ref().to_owned()));
+        let command = match self.command_middleware {
+            Some(ref middleware) => middleware.apply(command),
+            None => command,
+        };
+        command
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "HookContext::exec -> when running `docker exec` for \"{}\"",
+                    self.name
+                )
+            })
+    }
+
+    /// Waits for the IP address of the container on the network to be
+    /// assigned, retrying `docker inspect` up to `num_retries` times. This is
+    /// only possible in `HookPhase::AfterStart`, and returns a
+    /// [NotOnNetworkError] if the container is not attached to the network.
+    pub async fn wait_get_ip_addr(&self, num_retries: u64, delay: Duration) -> Result<IpAddr> {
+        let id = self.running_id("wait_get_ip_addr")?;
+        if !self.network_mode.is_default() {
+            let e = NotOnNetworkError {
+                name: self.name.clone(),
+                network_mode: self.network_mode,
+            };
+            let msg = format!("HookContext::wait_get_ip_addr -> {e}");
+            return Err(Error::box_from_locationless(e).add_kind_locationless(msg))
+        }
+        wait_for_ok(num_retries, delay, || async {
+            let command = docker_command("inspect").arg(id);
+            let command = match self.command_middleware {
+                Some(ref middleware) => middleware.apply(command),
+                None => command,
+            };
+            let comres = command.run_to_completion().await?;
+            comres.assert_success()?;
+            let v: serde_json::Value =
+                serde_json::from_str(comres.stdout_as_utf8().stack()?).stack()?;
+            ip_addr_from_inspect(stacked_get!(v[0]))
+        })
+        .await
+        .stack_err_locationless(|| format!("HookContext::wait_get_ip_addr for \"{}\"", self.name))
+    }
+}
+
+/// Host-side closures that a `ContainerNetwork` runs at points in the
+/// lifecycle of one container, set with
+/// [ContainerNetwork::set_hooks](crate::docker::ContainerNetwork::set_hooks).
+/// These are kept out of `Container` so that it stays `Clone` and serde-able.
+/// Multiple hooks for the same phase are run in the order they were added.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use stacked_errors::StackableErr;
+/// use super_orchestrator::docker::{ContainerHooks, HookFailurePolicy};
+///
+/// let hooks = ContainerHooks::new()
+///     .on_after_start(|cx| async move {
+///         let ip = cx
+///             .wait_get_ip_addr(10, Duration::from_millis(100))
+///             .await
+///             .stack()?;
+///         println!("{} is at {ip}", cx.name);
+///         Ok(())
+///     })
+///     .on_after_exit(|cx| async move {
+///         println!("{} exited with {:?}", cx.name, cx.result.unwrap().status);
+///         Ok(())
+///     })
+///     .hook_failure_policy(HookFailurePolicy::Log);
+/// ```
+#[derive(Clone, Default)]
+pub struct ContainerHooks {
+    before_create: Vec<Arc<HookFn>>,
+    after_start: Vec<Arc<HookFn>>,
+    after_exit: Vec<Arc<HookFn>>,
+    /// What happens when a hook returns an error, `HookFailurePolicy::Fatal`
+    /// by default
+    pub failure_policy: HookFailurePolicy,
+}
+
+fn boxed<F, Fut>(f: F) -> Arc<HookFn>
+where
+    F: Fn(HookContext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Arc::new(move |cx| -> HookFuture { Box::pin(f(cx)) })
+}
+
+impl ContainerHooks {
+    /// No hooks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a hook run just before the container is created. `HookContext::id`
+    /// is `None`.
+    pub fn on_before_create<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(HookContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.before_create.push(boxed(f));
+        self
+    }
+
+    /// Adds a hook run just after the container is started, which can use
+    /// `HookContext::exec` and `HookContext::wait_get_ip_addr`
+    pub fn on_after_start<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(HookContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.after_start.push(boxed(f));
+        self
+    }
+
+    /// Adds a hook run when a wait function of the `ContainerNetwork` finds
+    /// that the container has exited, with the `CommandResult` of the
+    /// container in `HookContext::result`. This is run before an unsuccessful
+    /// container causes the network to be terminated. Containers that are
+    /// terminated, or that are collected by `try_collect_finished`, do not run
+    /// this hook.
+    pub fn on_after_exit<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(HookContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.after_exit.push(boxed(f));
+        self
+    }
+
+    /// Sets `failure_policy`
+    pub fn hook_failure_policy(mut self, failure_policy: HookFailurePolicy) -> Self {
+        self.failure_policy = failure_policy;
+        self
+    }
+
+    /// If there are no hooks for `phase`
+    pub fn is_empty(&self, phase: HookPhase) -> bool {
+        self.hooks(phase).is_empty()
+    }
+
+    fn hooks(&self, phase: HookPhase) -> &[Arc<HookFn>] {
+        match phase {
+            HookPhase::BeforeCreate => &self.before_create,
+            HookPhase::AfterStart => &self.after_start,
+            HookPhase::AfterExit => &self.after_exit,
+        }
+    }
+
+    /// Runs the hooks for `cx.phase` in order, stopping at the first error.
+    /// The `failure_policy` is applied by the caller.
+    pub(crate) async fn run(&self, cx: &HookContext) -> Result<()> {
+        for (i, hook) in self.hooks(cx.phase).iter().enumerate() {
+            hook(cx.clone()).await.stack_err_locationless(|| {
+                format!("{} hook {i} of container \"{}\" failed", cx.phase, cx.name)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for ContainerHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContainerHooks")
+            .field("before_create", &self.before_create.len())
+            .field("after_start", &self.after_start.len())
+            .field("after_exit", &self.after_exit.len())
+            .field("failure_policy", &self.failure_policy)
+            .finish()
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+/// The kinds of resources recorded in [CreatedResources]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ResourceKind {
+    /// A docker network, the `name` is the network name
+    Network,
+    /// A docker container, the `name` is the `container_name`
+    Container,
+    /// An image built by the network, the `name` is the tag
+    Image,
+    /// A temporary file written by the network, such as the ".tmp.dockerfile"
+    /// of a `Dockerfile::Contents` or the file of
+    /// [Container::entrypoint_args_file](crate::docker::Container::entrypoint_args_file),
+    /// the `name` is the host path
+    TempFile,
+    /// A log file, the `name` is the host path
+    LogFile,
+    /// A host directory that did not exist before the network created it, the
+    /// `name` is the host path
+    HostDir,
+}
+
+/// What happened to a [CreatedResource] after its creation
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ResourceStatus {
+    /// The resource was created and has not been torn down
+    Present,
+    /// The resource was removed by a teardown function
+    Removed,
+    /// The resource was intentionally left in place by teardown, with the
+    /// reason (e.x. built images are kept for reuse)
+    Kept(String),
+    /// Removing the resource failed with this error, so it may still exist
+    RemovalError(String),
+}
+
+/// An entry of [CreatedResources]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CreatedResource {
+    pub kind: ResourceKind,
+    /// The name or path of the resource, see [ResourceKind]
+    pub name: String,
+    /// The docker ID of a network, container, or built image
+    pub id: Option<String>,
+    pub created_at: SystemTime,
+    pub status: ResourceStatus,
+    /// When `status` last changed
+    pub status_changed_at: SystemTime,
+    /// If the resource is a container with access to the host docker daemon,
+    /// see [Container::with_docker_socket](crate::docker::Container::with_docker_socket)
+    pub daemon_access: bool,
+}
+
+/// A ledger of everything a `ContainerNetwork` created on the machine, for
+/// auditing that teardown cleaned up, see
+/// [ContainerNetwork::resources](crate::docker::ContainerNetwork::resources).
+/// Entries are in creation order, and recreating a resource with the same kind
+/// and name (e.x. when a container is run again) adds another entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatedResources {
+    entries: Vec<CreatedResource>,
+}
+
+impl CreatedResources {
+    /// All the entries in creation order
+    pub fn entries(&self) -> &[CreatedResource] {
+        &self.entries
+    }
+
+    /// The entries with `kind`
+    pub fn of_kind(&self, kind: ResourceKind) -> impl Iterator<Item = &CreatedResource> {
+        self.entries.iter().filter(move |entry| entry.kind == kind)
+    }
+
+    /// The entries that may still exist on the machine, meaning those that are
+    /// `Present` or had a `RemovalError`. `Kept` entries are not included.
+    pub fn outstanding(&self) -> impl Iterator<Item = &CreatedResource> {
+        self.entries.iter().filter(|entry| {
+            matches!(
+                entry.status,
+                ResourceStatus::Present | ResourceStatus::RemovalError(_)
+            )
+        })
+    }
+
+    /// Returns the most recent entry with `kind` and `name`
+    pub fn get(&self, kind: ResourceKind, name: &str) -> Option<&CreatedResource> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| (entry.kind == kind) && (entry.name == name))
+    }
+
+    pub(crate) fn record(&mut self, kind: ResourceKind, name: impl AsRef<str>, id: Option<&str>) {
+        let now = SystemTime::now();
+        self.entries.push(CreatedResource {
+            kind,
+            name: name.as_ref().to_owned(),
+            id: id.map(|id| id.to_owned()),
+            created_at: now,
+            status: ResourceStatus::Present,
+            status_changed_at: now,
+            daemon_access: false,
+        });
+    }
+
+    /// Records the entry unless a `Present` one with the same kind and name
+    /// already exists, for files that are reused between runs
+    pub(crate) fn record_once(&mut self, kind: ResourceKind, name: impl AsRef<str>) {
+        let name = name.as_ref();
+        let present = self
+            .get(kind, name)
+            .is_some_and(|entry| entry.status == ResourceStatus::Present);
+        if !present {
+            self.record(kind, name, None);
+        }
+    }
+
+    /// The container entries with `daemon_access`, for auditing
+    pub fn with_daemon_access(&self) -> impl Iterator<Item = &CreatedResource> {
+        self.entries.iter().filter(|entry| entry.daemon_access)
+    }
+
+    /// Sets `daemon_access` on the most recent entry with `kind` and `name`
+    pub(crate) fn set_daemon_access(&mut self, kind: ResourceKind, name: &str) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| (entry.kind == kind) && (entry.name == name))
+        {
+            entry.daemon_access = true;
+        }
+    }
+
+    /// Sets the `id` of the most recent entry with `kind` and `name`
+    pub(crate) fn set_id(&mut self, kind: ResourceKind, name: &str, id: &str) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| (entry.kind == kind) && (entry.name == name))
+        {
+            entry.id = Some(id.to_owned());
+        }
+    }
+
+    /// Sets the status of the most recent `Present` or `RemovalError` entry
+    /// with `kind` and `name`
+    pub(crate) fn mark(&mut self, kind: ResourceKind, name: &str, status: ResourceStatus) {
+        if let Some(entry) = self.entries.iter_mut().rev().find(|entry| {
+            (entry.kind == kind)
+                && (entry.name == name)
+                && matches!(
+                    entry.status,
+                    ResourceStatus::Present | ResourceStatus::RemovalError(_)
+                )
+        }) {
+            entry.status = status;
+            entry.status_changed_at = SystemTime::now();
+        }
+    }
+
+    /// Marks all `Present` entries that teardown does not remove as `Kept`
+    pub(crate) fn mark_kept(&mut self) {
+        let now = SystemTime::now();
+        for entry in &mut self.entries {
+            if entry.status != ResourceStatus::Present {
+                continue
+            }
+            let reason = match entry.kind {
+                ResourceKind::Network | ResourceKind::Container => continue,
+                ResourceKind::Image => "built images are kept for reuse",
+                ResourceKind::TempFile => "temporary files are kept for debugging",
+                ResourceKind::LogFile => "log files are kept",
+                ResourceKind::HostDir => "host directories may contain outputs",
+            };
+            entry.status = ResourceStatus::Kept(reason.to_owned());
+            entry.status_changed_at = now;
+        }
+    }
+}
+
+/// A teardown command that failed for a reason other than the resource
+/// already being gone, see
+/// [ContainerNetwork::last_teardown_report](crate::docker::ContainerNetwork::last_teardown_report)
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TeardownFailure {
+    pub kind: ResourceKind,
+    /// The name of the resource, see [ResourceKind]
+    pub name: String,
+    /// The docker command that failed
+    pub command: String,
+    /// The stderr of the last attempt, or the error if the command could not
+    /// be run at all
+    pub stderr: String,
+    /// How many times the command was run
+    pub attempts: u32,
+}
+
+/// The teardown commands that failed, see
+/// [ContainerNetwork::last_teardown_report](crate::docker::ContainerNetwork::last_teardown_report)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TeardownReport {
+    pub failures: Vec<TeardownFailure>,
+}
+
+impl TeardownReport {
+    /// If nothing failed
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
@@ -1,13 +1,34 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    cmp::Ordering,
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use serde::{Deserialize, Serialize};
 use stacked_errors::{Error, Result, StackableErr};
-use tracing::debug;
+use tracing::{debug, warn};
 use uuid::Uuid;
 
 use crate::{
-    acquire_file_path, acquire_path, docker::ContainerNetwork, next_terminal_color, Command,
-    CommandResult, CommandRunner, FileOptions,
+    acquire_dir_path, acquire_file_path, acquire_path,
+    docker::SingleContainerRunner,
+    docker_helpers::{
+        check_buildkit_support, docker_binary, docker_command, docker_socket_path, parse_digest,
+        pinned_reference, DEFAULT_DOCKER_SOCKET,
+    },
+    find_binary,
+    middleware::CommandMiddleware,
+    misc::emit_crate_event,
+    next_terminal_color, parse_build_progress,
+    paths::resolve,
+    Backoff, BufferPolicy, BuildProgress, BuildStats, ByAddress, ByteSize, Command, CommandResult,
+    CommandRunner, CrateEvent, DebugOutput, FileOptions, FindBinaryOptions, ReadOrWrite,
+    RecordSpill,
 };
 
 // No `OsString`s or `PathBufs` for these structs, it introduces too many issues
@@ -48,6 +69,545 @@ impl Dockerfile {
     }
 }
 
+/// The image pull policy for containers using [Dockerfile::NameTag]. With
+/// `Always` and `Newer`, the `ContainerNetwork` runs `docker pull` during the
+/// build phase so that the pull time does not count against starting the
+/// container. The policy is otherwise passed as `--pull` to `docker create`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PullPolicy {
+    /// Always pull the image before creating the container, failing if the
+    /// pull fails
+    Always,
+    /// Pull the image before creating the container to pick up newer
+    /// versions, but fall back to the local image if the pull fails (e.x.
+    /// because the registry is unreachable)
+    Newer,
+    /// Only pull the image if it is missing locally (Docker's default)
+    Missing,
+    /// Never pull the image, creation fails if it is missing locally
+    Never,
+}
+
+impl PullPolicy {
+    /// Returns the value used with `docker create --pull`. `Newer` is the same
+    /// as `Always` here.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PullPolicy::Always | PullPolicy::Newer => "always",
+            PullPolicy::Missing => "missing",
+            PullPolicy::Never => "never",
+        }
+    }
+
+    /// If the image is pulled in the build phase
+    pub fn pulls_before_create(&self) -> bool {
+        matches!(self, PullPolicy::Always | PullPolicy::Newer)
+    }
+}
+
+/// A mount option appended to a volume by [Container::volume_with_options]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum VolumeOption {
+    /// "ro", mounts the volume read-only
+    ReadOnly,
+    /// "rw", mounts the volume read-write (the default)
+    ReadWrite,
+    /// "z", relabels the host path with a SELinux label that is shared among
+    /// containers
+    SelinuxShared,
+    /// "Z", relabels the host path with a SELinux label that is private to the
+    /// container, which rootless podman commonly needs for data directories
+    SelinuxPrivate,
+    /// "shared" bind propagation
+    Shared,
+    /// "slave" bind propagation
+    Slave,
+    /// "private" bind propagation
+    Private,
+    /// "rshared" bind propagation
+    RShared,
+    /// "rslave" bind propagation
+    RSlave,
+    /// "rprivate" bind propagation (the default)
+    RPrivate,
+    /// Any other option, passed verbatim
+    Other(String),
+}
+
+impl VolumeOption {
+    /// Returns the option as it appears in a `--volume` argument
+    pub fn as_str(&self) -> &str {
+        match self {
+            VolumeOption::ReadOnly => "ro",
+            VolumeOption::ReadWrite => "rw",
+            VolumeOption::SelinuxShared => "z",
+            VolumeOption::SelinuxPrivate => "Z",
+            VolumeOption::Shared => "shared",
+            VolumeOption::Slave => "slave",
+            VolumeOption::Private => "private",
+            VolumeOption::RShared => "rshared",
+            VolumeOption::RSlave => "rslave",
+            VolumeOption::RPrivate => "rprivate",
+            VolumeOption::Other(s) => s,
+        }
+    }
+}
+
+impl Display for VolumeOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether the local part of a volume is a file or a directory, see
+/// [Container::volume_kinds]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum VolumeKind {
+    File,
+    Dir,
+}
+
+/// Extensions that make a container path look like it is meant for a file
+const FILE_EXTENSIONS: [&str; 20] = [
+    "cfg",
+    "conf",
+    "crt",
+    "env",
+    "ini",
+    "json",
+    "key",
+    "lock",
+    "log",
+    "pem",
+    "properties",
+    "py",
+    "sh",
+    "sql",
+    "toml",
+    "txt",
+    "xml",
+    "yaml",
+    "yml",
+    "zip",
+];
+
+impl VolumeKind {
+    /// Guesses the kind of a volume from the form of its container path
+    /// (with any mount options after a ':' ignored). Returns `Dir` for paths
+    /// ending with '/', `File` for paths ending with a common file extension
+    /// such as ".conf" or ".json", and `None` otherwise.
+    ///
+    /// ```
+    /// use super_orchestrator::docker::VolumeKind;
+    ///
+    /// assert_eq!(
+    ///     VolumeKind::guess_from_target("/etc/app/config.toml:ro"),
+    ///     Some(VolumeKind::File)
+    /// );
+    /// assert_eq!(
+    ///     VolumeKind::guess_from_target("/dockerfile_resources/"),
+    ///     Some(VolumeKind::Dir)
+    /// );
+    /// assert_eq!(VolumeKind::guess_from_target("/data"), None);
+    /// assert_eq!(VolumeKind::guess_from_target("/usr/bin/app"), None);
+    /// ```
+    pub fn guess_from_target(target: &str) -> Option<VolumeKind> {
+        let target = volume_target(target);
+        if target.ends_with('/') {
+            return Some(VolumeKind::Dir)
+        }
+        let extension = Path::new(target).extension()?.to_str()?;
+        FILE_EXTENSIONS
+            .contains(&extension.to_ascii_lowercase().as_str())
+            .then_some(VolumeKind::File)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            VolumeKind::File => "a file",
+            VolumeKind::Dir => "a directory",
+        }
+    }
+}
+
+/// The container path of the container part of a volume, without mount
+/// options
+fn volume_target(container_part: &str) -> &str {
+    container_part.split(':').next().unwrap()
+}
+
+/// Checks the `actual` kind of the local part `source` of a volume of the
+/// container `name` against the `expected` kind, returning an error naming the
+/// mismatch if they differ. Without an expectation, the
+/// [VolumeKind::guess_from_target] of the container part `target` is
+/// compared instead, and a warning message is returned if it differs. This is
+/// the check that [Container::precheck] applies to every volume.
+///
+/// ```
+/// use super_orchestrator::docker::{check_volume_kind, VolumeKind};
+///
+/// let check =
+///     |actual, expected| check_volume_kind("app", "/host/bin", "/app:ro", actual, expected);
+/// assert_eq!(
+///     check(VolumeKind::File, Some(VolumeKind::File)).unwrap(),
+///     None
+/// );
+/// assert!(check(VolumeKind::Dir, Some(VolumeKind::File)).is_err());
+/// // no expectation and nothing to guess from
+/// assert_eq!(check(VolumeKind::Dir, None).unwrap(), None);
+/// ```
+pub fn check_volume_kind(
+    name: &str,
+    source: &str,
+    target: &str,
+    actual: VolumeKind,
+    expected: Option<VolumeKind>,
+) -> Result<Option<String>> {
+    let target = volume_target(target);
+    match expected {
+        Some(expected) if expected != actual => Err(Error::from_kind_locationless(format!(
+            "the volume source {source:?} of container \"{name}\" is {}, but it is expected to be \
+             {} for the container path \"{target}\"",
+            actual.as_str(),
+            expected.as_str()
+        ))),
+        Some(_) => Ok(None),
+        None => match VolumeKind::guess_from_target(target) {
+            Some(guess) if guess != actual => Ok(Some(format!(
+                "the volume source {source:?} of container \"{name}\" is {}, but the container \
+                 path \"{target}\" looks like it is meant for {}, use `Container::volume_file` or \
+                 `Container::volume_dir` if this is intended",
+                actual.as_str(),
+                guess.as_str()
+            ))),
+            _ => Ok(None),
+        },
+    }
+}
+
+/// Which docker network a container is attached to, see
+/// [Container::network_mode]
+#[derive(
+    Debug, Default, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum NetworkMode {
+    /// Attached to the network of the `ContainerNetwork`, with its hostname
+    /// resolvable by the other containers
+    #[default]
+    Default,
+    /// Shares the network stack of the host (`--network host`), so its ports
+    /// are bound directly on the host
+    Host,
+    /// Has only a loopback interface (`--network none`)
+    None,
+}
+
+impl NetworkMode {
+    /// If the container is attached to the network of the `ContainerNetwork`
+    pub fn is_default(&self) -> bool {
+        matches!(self, NetworkMode::Default)
+    }
+}
+
+/// Returned (boxed in the error stack) when the IP address or hostname of a
+/// container that is not attached to the network of its `ContainerNetwork` is
+/// queried, see [Container::network_mode]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotOnNetworkError {
+    /// The name of the container
+    pub name: String,
+    /// The `network_mode` of the container, which is not
+    /// `NetworkMode::Default`
+    pub network_mode: NetworkMode,
+}
+
+impl Display for NotOnNetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "container \"{}\" has the `network_mode` `NetworkMode::{:?}`, so it is not attached \
+             to the network and has no IP address or hostname on it",
+            self.name, self.network_mode
+        )?;
+        if self.network_mode == NetworkMode::Host {
+            f.write_str(", its ports are reachable through the host instead")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for NotOnNetworkError {}
+
+/// Resource limits passed to `docker create`, see
+/// [ContainerNetwork::run_with_resource_limits]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceConstraints {
+    /// Passed as `--memory` and `--memory-swap` (so that the container cannot
+    /// use swap to get around the limit), the container is OOM killed if it
+    /// uses more
+    pub max_memory_mb: Option<u64>,
+    /// Passed as `--cpus`, the number of CPUs worth of time the container can
+    /// use, e.x. `0.5` for half of one CPU
+    pub max_cpu_fraction: Option<f64>,
+    /// Passed as `--pids-limit`
+    pub max_pids: Option<u32>,
+}
+
+impl ResourceConstraints {
+    /// Returns the arguments for `docker create`
+    ///
+    /// # Errors
+    ///
+    /// If `max_memory_mb` is less than 6 (Docker's minimum), if
+    /// `max_cpu_fraction` is not a positive number, or if `max_pids` is 0
+    pub fn create_args(&self) -> Result<Vec<String>> {
+        let mut args = vec![];
+        if let Some(mb) = self.max_memory_mb {
+            if mb < 6 {
+                return Err(Error::from_kind_locationless(format!(
+                    "ResourceConstraints::create_args -> `max_memory_mb` is {mb}, but Docker \
+                     requires at least 6"
+                )))
+            }
+            args.extend(["--memory".to_owned(), format!("{mb}m")]);
+            args.extend(["--memory-swap".to_owned(), format!("{mb}m")]);
+        }
+        if let Some(cpus) = self.max_cpu_fraction {
+            if !(cpus.is_finite() && (cpus > 0.0)) {
+                return Err(Error::from_kind_locationless(format!(
+                    "ResourceConstraints::create_args -> `max_cpu_fraction` is {cpus}, but it \
+                     needs to be a positive number"
+                )))
+            }
+            args.extend(["--cpus".to_owned(), cpus.to_string()]);
+        }
+        if let Some(pids) = self.max_pids {
+            if pids == 0 {
+                return Err(Error::from_kind_locationless(
+                    "ResourceConstraints::create_args -> `max_pids` is 0",
+                ))
+            }
+            args.extend(["--pids-limit".to_owned(), pids.to_string()]);
+        }
+        Ok(args)
+    }
+}
+
+/// A readiness check that [ContainerNetwork::wait_ready] evaluates from the
+/// host side, which works for images without a shell or healthcheck binaries
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ReadinessProbe {
+    /// Ready when a TCP connection to the port on the IP address of the
+    /// container succeeds
+    TcpPort(u16),
+    /// Ready when an HTTP/1.1 `GET` of `path` on the port on the IP address of
+    /// the container responds with `expect_status`
+    HttpGet {
+        port: u16,
+        path: String,
+        expect_status: u16,
+    },
+    /// Ready when `docker exec` of the argv (run directly, not through a shell)
+    /// exits successfully
+    Exec(Vec<String>),
+    /// Ready when the stdout or stderr of the container contains the string
+    LogLine(String),
+}
+
+/// A [ReadinessProbe] with how often it is evaluated and the timeout of each
+/// evaluation, see [Container::readiness]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Readiness {
+    pub probe: ReadinessProbe,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// How a supervised container is restarted when it exits, see
+/// [Container::supervise].
+///
+/// Each exit restarts the container after the next `backoff` interval, unless
+/// it has already been restarted `max_restarts` times within the last
+/// `window`, in which case the policy is exhausted. Comparisons and hashing
+/// use the bits of the `backoff` floats.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SupervisePolicy {
+    /// The number of restarts allowed within `window`
+    pub max_restarts: u64,
+    /// The sliding window that restarts are counted in
+    pub window: Duration,
+    /// The delay before each restart, the interval used is indexed by the
+    /// number of restarts within `window`. If the intervals run out because
+    /// of `Backoff::max_retries`, the policy is exhausted.
+    pub backoff: Backoff,
+}
+
+impl SupervisePolicy {
+    fn key(&self) -> impl Ord + Hash {
+        let b = &self.backoff;
+        (
+            self.max_restarts,
+            self.window,
+            b.initial,
+            b.multiplier.to_bits(),
+            b.max_interval,
+            b.max_elapsed,
+            b.max_retries,
+            b.jitter.to_bits(),
+        )
+    }
+}
+
+impl Eq for SupervisePolicy {}
+
+impl Hash for SupervisePolicy {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl PartialOrd for SupervisePolicy {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SupervisePolicy {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// A BuildKit secret passed as `--secret id={id},src={host_path}` to `docker
+/// build`, see [Container::build_secret]. The `Debug` impl does not show the
+/// `host_path`.
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BuildSecret {
+    /// The ID used by `RUN --mount=type=secret,id=...` in the dockerfile
+    pub id: String,
+    /// The file with the secret, canonicalized by [Container::precheck]
+    pub host_path: String,
+}
+
+impl Debug for BuildSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BuildSecret")
+            .field("id", &self.id)
+            .field("host_path", &"<redacted>")
+            .finish()
+    }
+}
+
+/// A callback for the progress of `docker build`, see
+/// [Container::on_build_progress]
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BuildProgressCallback(pub ByAddress<BuildProgressFn>);
+
+/// The function wrapped by [BuildProgressCallback]
+pub type BuildProgressFn = dyn Fn(&BuildProgress, &str) + Send + Sync;
+
+impl BuildProgressCallback {
+    /// Wraps `f`, which is called with the parsed progress and the raw line
+    pub fn new(f: impl Fn(&BuildProgress, &str) + Send + Sync + 'static) -> Self {
+        Self(ByAddress(Arc::new(f)))
+    }
+}
+
+impl Debug for BuildProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BuildProgressCallback(..)")
+    }
+}
+
+/// The path inside the container that
+/// [Container::capture_crash_artifacts] mounts the host directory at
+pub const CRASH_ARTIFACTS_DIR: &str = "/crash";
+
+/// The path inside the container that [Container::entrypoint_args_file] mounts
+/// the generated arguments file at
+pub const ENTRYPOINT_ARGS_FILE: &str = "/super_orchestrator.args";
+
+/// The image used by [Container::dind]
+pub const DIND_IMAGE: &str = "docker:dind";
+
+/// The default [Container::max_create_argv_size], about 100 KB
+pub const DEFAULT_MAX_CREATE_ARGV_SIZE: u64 = 100 * 1024;
+
+/// Returned (boxed in the error stack) by [Container::create] when the total
+/// size of the `docker create` arguments exceeds
+/// [Container::max_create_argv_size], since the OS would otherwise fail the
+/// invocation with a confusing `E2BIG` or "Argument list too long"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgvTooLongError {
+    /// The name of the container
+    pub name: String,
+    /// The total size in bytes of the arguments (including nul terminators)
+    pub size: u64,
+    /// The `max_create_argv_size` that was exceeded
+    pub limit: u64,
+}
+
+impl Display for ArgvTooLongError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the `docker create` arguments for container \"{}\" total {} bytes, which exceeds the \
+             `max_create_argv_size` of {} bytes. Consider using `Container::entrypoint_args_file` \
+             to pass the entrypoint args through a mounted file, or passing large data through \
+             volumes or environment files",
+            self.name, self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for ArgvTooLongError {}
+
+/// Returned (boxed in the error stack) by [Container::create] when the
+/// `container_name` is already in use by another container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameConflictError {
+    /// The name of the container
+    pub name: String,
+    /// The `container_name` that is in use
+    pub container_name: String,
+}
+
+impl Display for NameConflictError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the `container_name` \"{}\" is already in use by another container, if it is from \
+             another `ContainerNetwork` then consider `Container::uuid_in_container_name`",
+            self.container_name
+        )
+    }
+}
+
+impl std::error::Error for NameConflictError {}
+
+/// Returned (boxed in the error stack) by [Container::create] when the image
+/// is not present locally and docker could not pull it because it does not
+/// exist in its registry (e.x. a typo in a `Dockerfile::NameTag`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageNotFoundError {
+    /// The name of the container
+    pub name: String,
+    /// The image reference that was passed to `docker create`
+    pub image: String,
+}
+
+impl Display for ImageNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the image \"{}\" of container \"{}\" does not exist locally or in its registry",
+            self.image, self.name
+        )
+    }
+}
+
+impl std::error::Error for ImageNotFoundError {}
+
 /// Configuration for running a container.
 ///
 /// The `docker run` command can be split into separate `docker build`, `docker
@@ -87,33 +647,110 @@ pub struct Container {
     /// [wait_get_ip_addr](crate::docker_helpers::wait_get_ip_addr)). Usually,
     /// this should be the same as `name`.
     pub host_name: String,
+    /// If set, the `ContainerNetwork` appends "_{uuid}" to the
+    /// `container_name`, see [Container::uuid_in_container_name]
+    pub uuid_in_container_name: bool,
+    /// If set, the `ContainerNetwork` appends "_{uuid}" to the `host_name`,
+    /// see [Container::uuid_in_host_name]
+    pub uuid_in_host_name: bool,
+    /// Which docker network the container is attached to, see
+    /// [Container::network_mode]
+    pub network_mode: NetworkMode,
     /// The dockerfile
     pub dockerfile: Dockerfile,
+    /// If set and `dockerfile` is a [Dockerfile::NameTag], this is passed as
+    /// `--pull` to `docker create`. If unset, the `ContainerNetwork` may apply
+    /// its default.
+    pub pull_policy: Option<PullPolicy>,
+    /// If set and `dockerfile` is a [Dockerfile::NameTag], the container is
+    /// created from "repository@digest" and `ContainerNetwork::run` fails if
+    /// the local tag resolves to a different digest
+    pub pinned_digest: Option<String>,
     /// Any flags and args passed to to `docker build`
     pub build_args: Vec<String>,
     /// The tag used for images, this is set automatically by `ContainerNetwork`
     /// but can be set to override the image it would automatically build
     pub build_tag: Option<String>,
+    /// If the `ContainerNetwork` has a `build_cache_dir`, this overrides the
+    /// image tag as the name of the cache
+    pub build_cache_key: Option<String>,
+    /// BuildKit secrets passed to `docker build`. Unlike `build_args`, these
+    /// are not part of what determines if builds can be deduplicated.
+    pub build_secrets: Vec<BuildSecret>,
+    /// If set, passed as `--ssh default` (or `--ssh default={socket path}`) to
+    /// `docker build` for BuildKit ssh forwarding
+    pub build_ssh: Option<String>,
     /// Any flags and args passed to to `docker create`
     pub create_args: Vec<String>,
     /// Passed as `--volume string0:string1` to the create args, but these have
-    /// the advantage of being canonicalized and prechecked
+    /// the advantage of being canonicalized and prechecked. Mount options are
+    /// part of `string1`, see [Container::volume_with_options].
     pub volumes: Vec<(String, String)>,
+    /// The expected kinds of the local parts of `volumes`, keyed by container
+    /// path, which [Container::precheck] enforces. See [Container::volume_file]
+    /// and [Container::volume_dir].
+    pub volume_kinds: Vec<(String, VolumeKind)>,
+    /// Passed as `--tmpfs` to the create args, see [Container::tmpfs]
+    pub tmpfs: Vec<String>,
+    /// If set, the container is created with `--read-only`, see
+    /// [Container::read_only_rootfs]
+    pub read_only_rootfs: bool,
     /// Working directory inside the container
     pub workdir: Option<String>,
     /// Environment variable mappings passed to docker
     pub environment_vars: Vec<(String, String)>,
+    /// If set, this host path of the docker daemon socket is mounted at
+    /// [DEFAULT_DOCKER_SOCKET], see [Container::with_docker_socket]
+    pub docker_socket: Option<String>,
+    /// If the group owning the `docker_socket` is added to the container
+    /// with `--group-add` (only on unix)
+    pub docker_socket_group_add: bool,
+    /// Labels passed to docker as `--label key=value`, these are also listed
+    /// in the [NetworkTopology](crate::docker::NetworkTopology) so that they
+    /// can be used for roles
+    pub labels: Vec<(String, String)>,
     /// When set, this indicates that the container should run an entrypoint
     /// using this path to a binary in the container
     pub entrypoint_file: Option<String>,
     /// Passed in as ["arg1", "arg2", ...] with the bracket and quotations being
     /// added
     pub entrypoint_args: Vec<String>,
+    /// If set, the `entrypoint_args` are written to a file that is mounted at
+    /// [ENTRYPOINT_ARGS_FILE], see [Container::entrypoint_args_file]
+    pub entrypoint_args_file: bool,
+    /// The host file that the `entrypoint_args` are written to if
+    /// `entrypoint_args_file` is set. `ContainerNetwork` sets this to a file
+    /// in its log directory unless it is explicitly set.
+    pub entrypoint_args_write_file: Option<String>,
+    /// The maximum total size in bytes of the `docker create` arguments before
+    /// [Container::create] returns an [ArgvTooLongError],
+    /// [DEFAULT_MAX_CREATE_ARGV_SIZE] by default
+    pub max_create_argv_size: u64,
     /// Changes what some functions allow to fail when running the container
     pub allow_unsuccessful: bool,
+    /// The exit codes that count as successful for the `ContainerNetwork`,
+    /// `[0]` by default. The actual exit code is still reported in the
+    /// `CommandResult`.
+    pub success_codes: Vec<i32>,
+    /// Marks the container as infrastructure (e.g. a database) that is expected
+    /// to run until teardown. These are excluded from the containers that
+    /// [ContainerNetwork::wait_with_timeout_all] waits on, but are still
+    /// monitored, see [ContainerNetwork::fail_on_infrastructure_exit].
+    pub infrastructure: bool,
+    /// If set, the container is started without attaching to it, see
+    /// [Container::detached]
+    pub detached: bool,
     /// Set by default, this tells the `ContainerNetwork` to forward
     /// stdout/stderr from `docker start`
     pub debug: bool,
+    /// Where the `debug` forwarding goes, see [Container::debug_output]
+    pub debug_output: DebugOutput,
+    /// Passed to [Command::debug_buffering], takes precedence over the
+    /// `ContainerNetwork::debug_buffering`
+    pub debug_buffering: Option<BufferPolicy>,
+    /// Passed to [Command::max_debug_lines_per_sec], takes precedence over the
+    /// `ContainerNetwork::max_debug_lines_per_sec`
+    pub max_debug_lines_per_sec: Option<u64>,
     /// Unset by default, this tells the `ContainerNetwork` to copy
     /// stdout/stderr to log files in the log directory
     pub log: bool,
@@ -123,9 +760,72 @@ pub struct Container {
     /// If `log` is set, then this will override the file that the
     /// `ContainerNetwork` chooses
     pub stderr_log: Option<FileOptions>,
+    /// If `log` is set, both stdout and stderr are also copied to this file,
+    /// in addition to the separate log files
+    pub combined_log: Option<FileOptions>,
+    /// Passed to [Command::log_limit], takes precedence over the
+    /// `ContainerNetwork::max_log_size`
+    pub log_limit: Option<u64>,
+    /// Passed to [Command::record_limit], takes precedence over the
+    /// `ContainerNetwork::max_record_size`
+    pub record_limit: Option<u64>,
+    /// Passed to [Command::record_spill], takes precedence over `record_limit`
+    pub record_spill: Option<RecordSpill>,
     /// This can be explicitly set to override the default temporary file that
     /// `ContainerNetwork` uses
     pub dockerfile_write_file: Option<String>,
+    /// Pairs of paths in the container image and host destinations that the
+    /// `ContainerNetwork` extracts with
+    /// [extract_from_image](crate::docker_helpers::extract_from_image) after
+    /// the image is built
+    pub extract_after_build: Vec<(String, String)>,
+    /// If set, the host directory that crash artifacts are captured to, see
+    /// [Container::capture_crash_artifacts]
+    pub crash_artifacts_dir: Option<String>,
+    /// If set, the container can be checkpointed by
+    /// [ContainerNetwork::checkpoint_all]. This means it is not created with
+    /// `--rm`, since checkpointing stops the container.
+    pub checkpointable: bool,
+    /// The readiness probe used by [ContainerNetwork::wait_ready]
+    pub readiness: Option<Readiness>,
+    /// If set, the `ContainerNetwork` restarts the container when it exits,
+    /// see [Container::supervise]
+    pub supervise: Option<SupervisePolicy>,
+    /// The group the container starts in, see [Container::start_group]
+    pub start_group: u32,
+    /// If set, [Container::build] calls this with each line of build output
+    /// that [parse_build_progress] recognizes
+    #[serde(skip)]
+    pub on_build_progress: Option<BuildProgressCallback>,
+    /// If set, applied to the docker commands that [Container::build],
+    /// [Container::create], and [Container::start] run, see
+    /// [ContainerNetwork::command_middleware]
+    #[serde(skip)]
+    pub command_middleware: Option<CommandMiddleware>,
+}
+
+/// The `ExitStatus` of a process that exited normally with `code`
+fn exit_status_from_code(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw((code & 0xff) << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+/// Aggregates the build output of both the classic builder (on stdout) and
+/// BuildKit (on stderr)
+fn build_stats(comres: &CommandResult, duration: Duration) -> BuildStats {
+    let stdout = comres.stdout_as_utf8_lossy();
+    let stderr = comres.stderr_as_utf8_lossy();
+    let mut stats = BuildStats::from_lines(stdout.lines().chain(stderr.lines()));
+    stats.duration = duration;
+    stats
 }
 
 fn apply_debug(command: Command, name: &str, debug: bool) -> Command {
@@ -155,25 +855,88 @@ impl Container {
         Self {
             name: name.to_owned(),
             build_tag: None,
+            build_cache_key: None,
+            build_secrets: vec![],
+            build_ssh: None,
             container_name: name.to_owned(),
             host_name: name.to_owned(),
+            uuid_in_container_name: false,
+            uuid_in_host_name: false,
+            network_mode: NetworkMode::Default,
             dockerfile,
+            pull_policy: None,
+            pinned_digest: None,
             build_args: vec![],
             create_args: vec![],
             volumes: vec![],
+            volume_kinds: vec![],
+            tmpfs: vec![],
+            read_only_rootfs: false,
             workdir: None,
             environment_vars: vec![],
+            docker_socket: None,
+            docker_socket_group_add: true,
+            labels: vec![],
             entrypoint_file: None,
             entrypoint_args: vec![],
+            entrypoint_args_file: false,
+            entrypoint_args_write_file: None,
+            max_create_argv_size: DEFAULT_MAX_CREATE_ARGV_SIZE,
             allow_unsuccessful: false,
+            success_codes: vec![0],
+            infrastructure: false,
+            detached: false,
             debug: true,
+            debug_output: DebugOutput::Std,
+            debug_buffering: None,
+            max_debug_lines_per_sec: None,
             log: false,
             stdout_log: None,
             stderr_log: None,
+            combined_log: None,
+            log_limit: None,
+            record_limit: None,
+            record_spill: None,
             dockerfile_write_file: None,
+            extract_after_build: vec![],
+            crash_artifacts_dir: None,
+            checkpointable: false,
+            readiness: None,
+            supervise: None,
+            start_group: 0,
+            on_build_progress: None,
+            command_middleware: None,
         }
     }
 
+    /// A preset for running a separate docker daemon inside of the container
+    /// with the privileged [DIND_IMAGE] image. The daemon storage is the named
+    /// volume "super_orchestrator_dind_{name}" so that its image cache persists
+    /// between runs (it is not removed by teardown, but can be removed with
+    /// [prune](crate::docker_helpers::prune)), and the `readiness` probe runs
+    /// `docker info` so that [ContainerNetwork::wait_ready] waits for the inner
+    /// daemon. The container is `infrastructure` since the daemon runs until
+    /// teardown.
+    ///
+    /// Unlike [Container::with_docker_socket], the host daemon is not
+    /// accessible, but `--privileged` still gives the container full access
+    /// to the host kernel.
+    pub fn dind(name: impl AsRef<str>) -> Self {
+        let name = name.as_ref();
+        Self::new(name, Dockerfile::name_tag(DIND_IMAGE))
+            .create_args([
+                "--privileged".to_owned(),
+                "--volume".to_owned(),
+                format!("super_orchestrator_dind_{name}:/var/lib/docker"),
+            ])
+            .readiness(
+                ReadinessProbe::Exec(vec!["docker".to_owned(), "info".to_owned()]),
+                Duration::from_millis(500),
+                Duration::from_secs(5),
+            )
+            .infrastructure(true)
+    }
+
     /// This is used in the entrypoint pattern where an externally compiled
     /// binary is used as the entrypoint for the container. This adds a volume
     /// from `entrypoint_binary` to "/{binary_file_name}_{uuid}" (the UUID is
@@ -206,13 +969,35 @@ impl Container {
         self.entrypoint_file = Some(entrypoint_file.clone());
         self.volumes.push((
             binary_path.as_os_str().to_str().unwrap().to_owned(),
-            entrypoint_file,
+            entrypoint_file.clone(),
         ));
+        // docker creates a directory in place of a missing binary
+        self.volume_kinds.push((entrypoint_file, VolumeKind::File));
         self.entrypoint_args
             .extend(entrypoint_args.into_iter().map(|s| s.as_ref().to_string()));
         Ok(self)
     }
 
+    /// The same as [Container::external_entrypoint], but the binary is located
+    /// by its `bin_name` with [find_binary](crate::find_binary)
+    pub async fn external_entrypoint_bin<I, S>(
+        self,
+        bin_name: &str,
+        options: &FindBinaryOptions,
+        entrypoint_args: I,
+    ) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let binary_path = find_binary(bin_name, options)
+            .await
+            .stack_err_locationless(|| "Container::external_entrypoint_bin")?;
+        self.external_entrypoint(binary_path.to_string_lossy(), entrypoint_args)
+            .await
+            .stack_err_locationless(|| "Container::external_entrypoint_bin")
+    }
+
     /// Sets `entrypoint_file` and adds to `entrypoint_args`
     pub fn entrypoint<I, S>(mut self, entrypoint_file: impl AsRef<str>, entrypoint_args: I) -> Self
     where
@@ -239,6 +1024,75 @@ impl Container {
         self
     }
 
+    /// Adds a volume like [Container::volume] where the local part is expected
+    /// to be a file, which [Container::precheck] enforces. Docker would
+    /// otherwise mount a directory over the container path or fail to start.
+    pub fn volume_file(self, local: impl AsRef<str>, container: impl AsRef<str>) -> Self {
+        self.volume(local, container.as_ref())
+            .expect_volume_kind(container, VolumeKind::File)
+    }
+
+    /// Adds a volume like [Container::volume] where the local part is expected
+    /// to be a directory, which [Container::precheck] enforces
+    pub fn volume_dir(self, local: impl AsRef<str>, container: impl AsRef<str>) -> Self {
+        self.volume(local, container.as_ref())
+            .expect_volume_kind(container, VolumeKind::Dir)
+    }
+
+    /// Sets the expected kind of the local part of the volume at `container`
+    /// (mount options are ignored), for volumes added in other ways such as
+    /// [Container::volume_with_options]
+    pub fn expect_volume_kind(mut self, container: impl AsRef<str>, kind: VolumeKind) -> Self {
+        let target = volume_target(container.as_ref()).to_owned();
+        self.volume_kinds.retain(|(other, _)| *other != target);
+        self.volume_kinds.push((target, kind));
+        self
+    }
+
+    /// Adds a volume like [Container::volume] with mount `options`, which are
+    /// appended to the container part as ":opt0,opt1,...". The local path is
+    /// still canonicalized by [Container::precheck].
+    ///
+    /// ```
+    /// use super_orchestrator::docker::{Container, Dockerfile, VolumeOption};
+    ///
+    /// let container = Container::new("postgres", Dockerfile::name_tag("postgres:16"))
+    ///     .volume_with_options("./data", "/var/lib/postgresql/data", [
+    ///         VolumeOption::SelinuxPrivate,
+    ///     ])
+    ///     .volume_with_options("./postgres.conf", "/etc/postgres.conf", [
+    ///         VolumeOption::ReadOnly,
+    ///         VolumeOption::SelinuxShared,
+    ///     ]);
+    /// assert_eq!(container.volumes, [
+    ///     ("./data".to_owned(), "/var/lib/postgresql/data:Z".to_owned()),
+    ///     (
+    ///         "./postgres.conf".to_owned(),
+    ///         "/etc/postgres.conf:ro,z".to_owned()
+    ///     ),
+    /// ]);
+    /// ```
+    pub fn volume_with_options<I>(
+        self,
+        local: impl AsRef<str>,
+        container: impl AsRef<str>,
+        options: I,
+    ) -> Self
+    where
+        I: IntoIterator<Item = VolumeOption>,
+    {
+        let options: Vec<String> = options
+            .into_iter()
+            .map(|option| option.as_str().to_owned())
+            .collect();
+        if options.is_empty() {
+            self.volume(local, container)
+        } else {
+            let container = format!("{}:{}", container.as_ref(), options.join(","));
+            self.volume(local, container)
+        }
+    }
+
     /// Adds multiple volumes
     pub fn volumes<I, K, V>(mut self, volumes: I) -> Self
     where
@@ -265,6 +1119,13 @@ impl Container {
         self
     }
 
+    /// Sets the `build_cache_key` used instead of the image tag when the
+    /// `ContainerNetwork` has a `build_cache_dir`
+    pub fn build_cache_key(mut self, key: &str) -> Self {
+        self.build_cache_key = Some(key.to_owned());
+        self
+    }
+
     /// Add arguments to be passed to `docker create`
     pub fn create_args<I, S>(mut self, create_args: I) -> Self
     where
@@ -276,6 +1137,13 @@ impl Container {
         self
     }
 
+    /// Adds an `/etc/hosts` entry resolving `hostname` to `ip` in the
+    /// container, passed as `--add-host` to `docker create`
+    pub fn add_host(self, hostname: impl AsRef<str>, ip: IpAddr) -> Self {
+        let hostname = hostname.as_ref();
+        self.create_args(["--add-host".to_owned(), format!("{hostname}:{ip}")])
+    }
+
     /// Adds environment vars to be passed
     pub fn environment_vars<I, K, V>(mut self, environment_vars: I) -> Self
     where
@@ -291,6 +1159,58 @@ impl Container {
         self
     }
 
+    /// Gives the container access to the host docker daemon by mounting the
+    /// socket found by [docker_socket_path] at [DEFAULT_DOCKER_SOCKET], so
+    /// that e.x. the `docker` CLI works inside the container. On unix the
+    /// group owning the socket is added with `--group-add` unless
+    /// `docker_socket_group_add` is unset, so that non-root users can use it.
+    ///
+    /// # Note
+    ///
+    /// Access to the daemon is equivalent to root access on the host. The
+    /// container is flagged with `daemon_access` in
+    /// [ContainerNetwork::resources], and validation warns when this is used
+    /// with a `Dockerfile::NameTag` image without a `pinned_digest`.
+    pub fn with_docker_socket(mut self) -> Self {
+        self.docker_socket = Some(
+            docker_socket_path()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DOCKER_SOCKET))
+                .to_string_lossy()
+                .into_owned(),
+        );
+        self
+    }
+
+    /// Sets `docker_socket_group_add`
+    pub fn docker_socket_group_add(mut self, docker_socket_group_add: bool) -> Self {
+        self.docker_socket_group_add = docker_socket_group_add;
+        self
+    }
+
+    /// Adds a label, see [Container::labels]
+    pub fn label(mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+        self.labels
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Adds a tmpfs mount at `container_path` (which can have options like
+    /// `"/tmp:size=64m"`), passed as `--tmpfs` to `docker create`. This is the
+    /// way to have writable paths along with [Container::read_only_rootfs].
+    pub fn tmpfs(mut self, container_path: impl AsRef<str>) -> Self {
+        self.tmpfs.push(container_path.as_ref().to_owned());
+        self
+    }
+
+    /// Sets if the root filesystem of the container is read-only, passed as
+    /// `--read-only` to `docker create`. Volumes and [Container::tmpfs] mounts
+    /// are still writable, e.x. `read_only_rootfs(true).tmpfs("/tmp")`.
+    /// [Container::precheck] warns if the `workdir` would be read-only.
+    pub fn read_only_rootfs(mut self, read_only_rootfs: bool) -> Self {
+        self.read_only_rootfs = read_only_rootfs;
+        self
+    }
+
     /// Sets the working directory inside the container
     pub fn workdir(mut self, workdir: impl AsRef<str>) -> Self {
         self.workdir = Some(workdir.as_ref().to_string());
@@ -308,24 +1228,457 @@ impl Container {
         self
     }
 
+    /// Instead of passing the `entrypoint_args` on the command line (where
+    /// thousands of arguments can exceed OS argument list limits), writes them
+    /// as newline separated entries to a generated file that is mounted
+    /// read-only at [ENTRYPOINT_ARGS_FILE]. The entrypoint is then passed the
+    /// two arguments `--args-file {ENTRYPOINT_ARGS_FILE}`, which the entrypoint
+    /// binary can expand with
+    /// [load_entrypoint_args](crate::load_entrypoint_args). Arguments
+    /// containing newlines cause an error from [Container::create].
+    pub fn entrypoint_args_file(mut self) -> Self {
+        self.entrypoint_args_file = true;
+        self
+    }
+
+    /// Sets the `max_create_argv_size`
+    ///
+    /// # Errors
+    ///
+    /// If `max_create_argv_size` is a string that cannot be parsed by
+    /// [parse_size](crate::parse_size)
+    pub fn max_create_argv_size<B>(mut self, max_create_argv_size: B) -> Result<Self>
+    where
+        B: TryInto<ByteSize>,
+        B::Error: Debug,
+    {
+        self.max_create_argv_size = ByteSize::convert(max_create_argv_size)
+            .stack_err_locationless(|| "Container::max_create_argv_size")?
+            .0;
+        Ok(self)
+    }
+
     /// Sets whether a container is allowed to have an unsuccesful output
     pub fn allow_unsuccessful(mut self, allow_unsuccessful: bool) -> Self {
         self.allow_unsuccessful = allow_unsuccessful;
         self
     }
 
+    /// Sets the exit codes that count as successful, e.x. `[0, 42]` when exit
+    /// code 42 is an expected outcome that should not trigger
+    /// `terminate_on_failure` or error compilation
+    pub fn success_codes(mut self, success_codes: impl IntoIterator<Item = i32>) -> Self {
+        self.success_codes = success_codes.into_iter().collect();
+        self
+    }
+
+    /// Sets whether the container is an `infrastructure` container
+    pub fn infrastructure(mut self, infrastructure: bool) -> Self {
+        self.infrastructure = infrastructure;
+        self
+    }
+
     /// Sets whether container stdout/stderr should be forwarded
     pub fn debug(mut self, debug: bool) -> Self {
         self.debug = debug;
         self
     }
 
+    /// Sets where the `debug` forwarding of container stdout/stderr goes. With
+    /// `DebugOutput::Tracing`, each line is emitted as a `tracing` event within
+    /// a "container" span with `container.name` and `network.name` fields
+    /// (created by the `ContainerNetwork` when the container is started), so
+    /// that subscribers such as JSON formatters can attribute and parse the
+    /// output.
+    pub fn debug_output(mut self, debug_output: DebugOutput) -> Self {
+        self.debug_output = debug_output;
+        self
+    }
+
+    /// Sets the `debug_buffering` of the `debug` forwarding, see
+    /// [Command::debug_buffering]
+    pub fn debug_buffering(mut self, debug_buffering: Option<BufferPolicy>) -> Self {
+        self.debug_buffering = debug_buffering;
+        self
+    }
+
+    /// Sets the `max_debug_lines_per_sec` of the `debug` forwarding, see
+    /// [Command::max_debug_lines_per_sec]
+    pub fn max_debug_lines_per_sec(mut self, max_debug_lines_per_sec: Option<u64>) -> Self {
+        self.max_debug_lines_per_sec = max_debug_lines_per_sec;
+        self
+    }
+
+    /// If set, [Container::start] runs `docker start` without `--attach` and
+    /// the returned `CommandRunner` only tracks the exit of the container. If
+    /// the output is wanted (`debug` or `log` is set, or the `readiness` probe
+    /// is a [ReadinessProbe::LogLine]), the runner is `docker logs --follow`
+    /// and the exit code is found with `docker inspect` after it finishes.
+    /// Otherwise, the runner is `docker wait` which has no output to record
+    /// or forward.
+    ///
+    /// The `ContainerNetwork` replaces the status of the runner with the exit
+    /// code of the container, so waiting works the same as with attached
+    /// containers, but the `CommandResult` has empty stdout and stderr unless
+    /// the logs are followed. This is for networks with many containers where
+    /// the log files or nothing at all are enough, since `docker wait` is much
+    /// lighter than an attached `docker start` with its stream copying.
+    /// Detached containers are created without `--rm` so that their exit code
+    /// can still be inspected, they are removed when they are terminated or
+    /// before they are run again.
+    pub fn detached(mut self, detached: bool) -> Self {
+        self.detached = detached;
+        self
+    }
+
+    /// If `detached` containers have their logs followed, see
+    /// [Container::detached]
+    pub(crate) fn follows_logs(&self) -> bool {
+        self.debug
+            || self.log
+            || matches!(
+                self.readiness,
+                Some(Readiness {
+                    probe: ReadinessProbe::LogLine(_),
+                    ..
+                })
+            )
+    }
+
+    /// If `detached`, finds the exit code of the container after the runner
+    /// from [Container::start] has finished, before its `CommandResult` is
+    /// taken
+    pub(crate) async fn detached_exit_code(
+        &self,
+        container_id: &str,
+        runner: &mut CommandRunner,
+    ) -> Result<Option<i32>> {
+        if !self.detached {
+            return Ok(None)
+        }
+        let code = if self.follows_logs() {
+            let inspect = self
+                .apply_command_middleware(
+                    docker_command("inspect --format {{.State.ExitCode}}").arg(container_id),
+                )
+                .run_to_completion()
+                .await
+                .stack_err_locationless(|| {
+                    "Container::detached_exit_code -> when inspecting the exit code"
+                })?;
+            inspect.assert_success().stack_err_locationless(|| {
+                "Container::detached_exit_code -> when inspecting the exit code"
+            })?;
+            inspect.stdout_as_utf8_lossy().trim().to_owned()
+        } else {
+            // `docker wait` prints the exit code
+            runner
+                .get_command_result()
+                .stack_err_locationless(|| {
+                    "Container::detached_exit_code -> the runner has not finished"
+                })?
+                .stdout_as_utf8_lossy()
+                .trim()
+                .to_owned()
+        };
+        let code = code.parse::<i32>().stack_err_locationless(|| {
+            format!(
+                "Container::detached_exit_code -> could not parse the exit code of the detached \
+                 container \"{}\" from {code:?}",
+                self.name
+            )
+        })?;
+        Ok(Some(code))
+    }
+
+    /// Replaces the status of `comres` with the `exit_code` from
+    /// [Container::detached_exit_code], dropping the output of `docker wait`
+    pub(crate) fn apply_detached_exit_code(
+        &self,
+        comres: &mut CommandResult,
+        exit_code: Option<i32>,
+    ) {
+        if let Some(exit_code) = exit_code {
+            comres.status = Some(exit_status_from_code(exit_code));
+            if !self.follows_logs() {
+                comres.stdout = vec![];
+            }
+        }
+    }
+
+    /// Sets if the `ContainerNetwork` appends "_{uuid}" (with the UUID of the
+    /// network) to the `container_name` when creating the container. This
+    /// allows the same containers to run in multiple networks at the same
+    /// time, since docker requires container names to be unique. Unset by
+    /// default. The final name is returned by
+    /// [ContainerNetwork::container_name], see also
+    /// [final_names](crate::docker::final_names).
+    pub fn uuid_in_container_name(mut self, uuid_in_container_name: bool) -> Self {
+        self.uuid_in_container_name = uuid_in_container_name;
+        self
+    }
+
+    /// Sets if the `ContainerNetwork` appends "_{uuid}" to the `host_name`
+    /// when creating the container. Unset by default, which keeps the
+    /// hostname fixed for things like TLS certificates that are issued for
+    /// it. Hostnames only need to be unique within a network. The final
+    /// hostname is returned by [ContainerNetwork::host_name], see also
+    /// [final_names](crate::docker::final_names).
+    pub fn uuid_in_host_name(mut self, uuid_in_host_name: bool) -> Self {
+        self.uuid_in_host_name = uuid_in_host_name;
+        self
+    }
+
+    /// Sets the `network_mode`. Containers with `NetworkMode::Host` or
+    /// `NetworkMode::None` are created with `--network host` or `--network
+    /// none` instead of the network of the `ContainerNetwork` and without a
+    /// `--hostname`. They still take part in waits, logs, and teardown, but
+    /// they have no IP address or hostname on the network, so
+    /// [ContainerNetwork::wait_get_ip_addr] returns a [NotOnNetworkError] for
+    /// them and they are left out of the
+    /// [ContainerNetwork::topology].
+    /// [ContainerNetwork::validate] reports uses of features that need the
+    /// network, such as `{{hostname:<name>}}` templates of them.
+    pub fn network_mode(mut self, network_mode: NetworkMode) -> Self {
+        self.network_mode = network_mode;
+        self
+    }
+
     /// Sets whether container stdout/stderr should be written to log files
     pub fn log(mut self, log: bool) -> Self {
         self.log = log;
         self
     }
 
+    /// Sets `combined_log`, a file that both stdout and stderr are copied to in
+    /// addition to the separate log files if `log` is set
+    pub fn combined_log(mut self, combined_log: Option<FileOptions>) -> Self {
+        self.combined_log = combined_log;
+        self
+    }
+
+    /// Sets the `log_limit` for the log files
+    ///
+    /// # Errors
+    ///
+    /// If `log_limit` is a string that cannot be parsed by
+    /// [parse_size](crate::parse_size)
+    pub fn log_limit<B>(mut self, log_limit: B) -> Result<Self>
+    where
+        B: TryInto<ByteSize>,
+        B::Error: Debug,
+    {
+        self.log_limit = Some(
+            ByteSize::convert(log_limit)
+                .stack_err_locationless(|| "Container::log_limit")?
+                .0,
+        );
+        Ok(self)
+    }
+
+    /// Sets the `record_limit` for the stdout and stderr records
+    ///
+    /// # Errors
+    ///
+    /// If `record_limit` is a string that cannot be parsed by
+    /// [parse_size](crate::parse_size)
+    pub fn record_limit<B>(mut self, record_limit: B) -> Result<Self>
+    where
+        B: TryInto<ByteSize>,
+        B::Error: Debug,
+    {
+        self.record_limit = Some(
+            ByteSize::convert(record_limit)
+                .stack_err_locationless(|| "Container::record_limit")?
+                .0,
+        );
+        Ok(self)
+    }
+
+    /// Sets the `record_spill` for the stdout and stderr records, see
+    /// [Command::record_spill]. A relative `dir` is relative to the
+    /// [ContainerNetwork::base_dir] when run by a network.
+    ///
+    /// # Errors
+    ///
+    /// If `threshold` is a string that cannot be parsed by
+    /// [parse_size](crate::parse_size)
+    pub fn record_spill<B>(mut self, threshold: B, dir: &str) -> Result<Self>
+    where
+        B: TryInto<ByteSize>,
+        B::Error: Debug,
+    {
+        self.record_spill = Some(RecordSpill {
+            threshold: ByteSize::convert(threshold)
+                .stack_err_locationless(|| "Container::record_spill")?
+                .0,
+            dir: PathBuf::from(dir),
+            stdout_path: None,
+            stderr_path: None,
+        });
+        Ok(self)
+    }
+
+    /// Sets the `pull_policy` used if the `dockerfile` is a
+    /// `Dockerfile::NameTag`
+    pub fn pull_policy(mut self, pull_policy: PullPolicy) -> Self {
+        self.pull_policy = Some(pull_policy);
+        self
+    }
+
+    /// Sets the `pinned_digest` ("sha256:..."), which is only allowed for a
+    /// `Dockerfile::NameTag`
+    pub fn pinned_digest(mut self, digest: impl AsRef<str>) -> Self {
+        self.pinned_digest = Some(digest.as_ref().to_owned());
+        self
+    }
+
+    /// Adds a path to be extracted from the container image to `host_dest`
+    /// after the image is built, see
+    /// [extract_from_image](crate::docker_helpers::extract_from_image)
+    pub fn extract_after_build(
+        mut self,
+        container_path: impl AsRef<str>,
+        host_dest: impl AsRef<str>,
+    ) -> Self {
+        self.extract_after_build.push((
+            container_path.as_ref().to_owned(),
+            host_dest.as_ref().to_owned(),
+        ));
+        self
+    }
+
+    /// Opts into capturing crash artifacts from the container to `host_dir`
+    /// (which is created if it does not exist). This mounts `host_dir` at
+    /// [CRASH_ARTIFACTS_DIR], creates the container with `--ulimit core=-1`,
+    /// and sets the `RUST_BACKTRACE=full` and
+    /// `SUPER_ORCHESTRATOR_CRASH_DIR={CRASH_ARTIFACTS_DIR}` environment
+    /// variables. Core dumps land in the crash directory only if the host's
+    /// `kernel.core_pattern` permits it, otherwise the entrypoint should write
+    /// whatever it can (e.x. backtraces from a panic hook) to
+    /// `SUPER_ORCHESTRATOR_CRASH_DIR`.
+    ///
+    /// Because the directory is a volume, artifacts are on the host even though
+    /// the container is removed after exiting. If the container exits with a
+    /// status indicating a signal (greater than 128, e.x. 139 for a segfault),
+    /// the `ContainerNetwork` records the paths in the crash directory, see
+    /// [ContainerNetwork::crash_artifacts].
+    pub fn capture_crash_artifacts(mut self, host_dir: impl AsRef<str>) -> Self {
+        self.crash_artifacts_dir = Some(host_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Adds a BuildKit secret with `id` from the file at `host_path`, used by
+    /// `RUN --mount=type=secret,id={id}` in the dockerfile. The path is
+    /// canonicalized by [Container::precheck], and secrets are not part of
+    /// what determines if builds can be deduplicated (so different machines
+    /// with different secret paths do not miss the cache). This requires
+    /// BuildKit, see
+    /// [check_buildkit_support](crate::docker_helpers::check_buildkit_support).
+    pub fn build_secret(mut self, id: impl AsRef<str>, host_path: impl AsRef<str>) -> Self {
+        self.build_secrets.push(BuildSecret {
+            id: id.as_ref().to_owned(),
+            host_path: host_path.as_ref().to_owned(),
+        });
+        self
+    }
+
+    /// Enables BuildKit ssh forwarding for `RUN --mount=type=ssh` in the
+    /// dockerfile. Use "default" to forward the default agent
+    /// (`SSH_AUTH_SOCK`), or a path to an agent socket or key. Paths are
+    /// canonicalized by [Container::precheck].
+    pub fn build_ssh(mut self, default_or_socket_path: impl AsRef<str>) -> Self {
+        self.build_ssh = Some(default_or_socket_path.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `checkpointable`, allowing the container to be checkpointed by
+    /// [ContainerNetwork::checkpoint_all] and restored by
+    /// [ContainerNetwork::restore_all]. The container is not created with
+    /// `--rm`, so it is only removed when terminated by the network.
+    pub fn checkpointable(mut self, checkpointable: bool) -> Self {
+        self.checkpointable = checkpointable;
+        self
+    }
+
+    /// Sets the `readiness` probe used by [ContainerNetwork::wait_ready]. The
+    /// `probe` is evaluated every `interval`, and an evaluation that takes
+    /// longer than `timeout` counts as not ready.
+    pub fn readiness(
+        mut self,
+        probe: ReadinessProbe,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Self {
+        self.readiness = Some(Readiness {
+            probe,
+            interval,
+            timeout,
+        });
+        self
+    }
+
+    /// Sets the `supervise` policy. When the wait functions of a
+    /// `ContainerNetwork` find that the container exited (successfully or
+    /// not), it is removed, created, and started again with its logs appended
+    /// to, instead of the exit counting as finished or as a failure. Only when
+    /// the policy is exhausted is the exit handled normally, with the last
+    /// result being treated as unsuccessful. The results of every run are kept
+    /// in [ContainerNetwork::supervise_history].
+    ///
+    /// The restart happens on a later polling pass of the wait once the
+    /// `backoff` interval has passed, so the backoff does not hold up waiting
+    /// on other containers. A restart that is not due before the wait returns
+    /// is done by the next wait.
+    ///
+    /// Unlike `docker create --restart`, each restart goes through the same
+    /// attach, log, and record handling as the first start.
+    pub fn supervise(mut self, policy: SupervisePolicy) -> Self {
+        self.supervise = Some(policy);
+        self
+    }
+
+    /// Sets the `start_group`, 0 by default. When a `ContainerNetwork` runs
+    /// containers, every container of a lower group is started before any
+    /// container of a higher group, and with
+    /// [ContainerNetwork::start_group_ready_timeout] the lower groups are
+    /// also waited on to be ready. See [ContainerNetwork::start_order] for the
+    /// order within a group.
+    pub fn start_group(mut self, start_group: u32) -> Self {
+        self.start_group = start_group;
+        self
+    }
+
+    /// Sets `on_build_progress`, which is called with the [BuildProgress]
+    /// parsed from each line of `docker build` output along with the raw line.
+    /// BuildKit only outputs lines with `--progress=plain`, which can be added
+    /// with [Container::build_args].
+    pub fn on_build_progress(
+        mut self,
+        on_build_progress: impl Fn(&BuildProgress, &str) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_build_progress = Some(BuildProgressCallback::new(on_build_progress));
+        self
+    }
+
+    /// Sets `command_middleware`, which transforms the docker commands of the
+    /// container just before they are run
+    pub fn command_middleware(
+        mut self,
+        command_middleware: impl Fn(Command) -> Command + Send + Sync + 'static,
+    ) -> Self {
+        self.command_middleware = Some(CommandMiddleware::new(command_middleware));
+        self
+    }
+
+    /// Applies the `command_middleware` if it is set
+    pub(crate) fn apply_command_middleware(&self, command: Command) -> Command {
+        match self.command_middleware {
+            Some(ref command_middleware) => command_middleware.apply(command),
+            None => command,
+        }
+    }
+
     /// Sets the `dockerfile_write_file` used for the `Dockerfile::Contents`
     /// option explicitly
     pub fn dockerfile_write_file(mut self, file_path: Option<String>) -> Self {
@@ -333,6 +1686,13 @@ impl Container {
         self
     }
 
+    /// Returns a [SingleContainerRunner] for running this container by itself
+    /// in an implicit `ContainerNetwork`, with builder control over the
+    /// network and separate build and run timeouts
+    pub fn runner(self) -> SingleContainerRunner {
+        SingleContainerRunner::new(self)
+    }
+
     /// Runs this container by itself in a default `ContainerNetwork` with
     /// "super_orchestrator_{uuid}" as the network name, waiting for completion
     /// with a timeout. Setting `debug` is equivalent to setting `debug_build`
@@ -340,6 +1700,8 @@ impl Container {
     /// `allow_unsuccessful`, so the `CommandResult` has to be checked if there
     /// was an unsuccessful error return status from within the container
     /// itself.
+    ///
+    /// This is a shorthand for [Container::runner], which has more options.
     pub async fn run(
         self,
         dockerfile_write_dir: Option<&str>,
@@ -347,32 +1709,68 @@ impl Container {
         log_dir: &str,
         debug: bool,
     ) -> Result<CommandResult> {
-        let mut cn =
-            ContainerNetwork::new_with_uuid("super_orchestrator", dockerfile_write_dir, log_dir);
-        cn.debug_build(debug).debug_create(debug);
-        let name = self.name.clone();
-        cn.add_container(self.allow_unsuccessful(true))
-            .stack_err_locationless(|| {
-                "Container::run when trying to create a `ContainerNetwork`"
-            })?;
-
-        // in order to get unsuccesful `CommandResult`s, we do not terminate on failure
-        // and need to remember to `terminate_all` before returning other kinds of
-        // errors
-        cn.run_all()
-            .await
-            .stack_err_locationless(|| "Container::run when trying to run a `ContainerNetwork`")?;
-        cn.wait_with_timeout_all(true, timeout)
+        let mut runner = self.runner().timeout(timeout).log_dir(log_dir).debug(debug);
+        if let Some(dockerfile_write_dir) = dockerfile_write_dir {
+            runner = runner.dockerfile_write_dir(dockerfile_write_dir);
+        }
+        runner
+            .run()
             .await
-            .stack_err_locationless(|| "Container::run when waiting on its `ContainerNetwork`")?;
-        cn.terminate_all().await;
+            .stack_err_locationless(|| "Container::run")
+    }
 
-        cn.remove_container(name)
-            .await
-            .unwrap()
-            .stack_err_locationless(|| {
-                "Container::run could not get `CommandResult` because of some internal bug or error"
-            })
+    /// Joins the relative host paths of `self` onto `base_dir`, used for
+    /// [ContainerNetwork::base_dir]
+    pub(crate) fn rebase_paths(&mut self, base_dir: &Path) {
+        let rebase = |path: &mut String| {
+            if Path::new(path.as_str()).is_relative() {
+                *path = base_dir.join(path.as_str()).to_string_lossy().into_owned();
+            }
+        };
+        if let Dockerfile::Path(ref mut path) = self.dockerfile {
+            rebase(path);
+        }
+        for (local_volume, _) in &mut self.volumes {
+            rebase(local_volume);
+        }
+        for secret in &mut self.build_secrets {
+            rebase(&mut secret.host_path);
+        }
+        if let Some(ref mut build_ssh) = self.build_ssh {
+            if build_ssh != "default" {
+                rebase(build_ssh);
+            }
+        }
+        for (_, host_dest) in &mut self.extract_after_build {
+            rebase(host_dest);
+        }
+        for path in [
+            &mut self.crash_artifacts_dir,
+            &mut self.dockerfile_write_file,
+            &mut self.entrypoint_args_write_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            rebase(path);
+        }
+        for file_options in [
+            &mut self.stdout_log,
+            &mut self.stderr_log,
+            &mut self.combined_log,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if file_options.path.is_relative() {
+                file_options.path = base_dir.join(&file_options.path);
+            }
+        }
+        if let Some(ref mut spill) = self.record_spill {
+            if spill.dir.is_relative() {
+                spill.dir = base_dir.join(&spill.dir);
+            }
+        }
     }
 
     /// Prechecks several things needed to successfully run `self`, and
@@ -382,6 +1780,29 @@ impl Container {
     /// `Dockerfile::Contents` but does not preacquire
     /// `dockerfile_write_dir`.
     pub async fn precheck(&mut self) -> Result<()> {
+        self.precheck_without_docker(true).await?;
+        if (!self.build_secrets.is_empty()) || self.build_ssh.is_some() {
+            check_buildkit_support().await.stack_err_locationless(|| {
+                "Container::precheck -> `build_secrets` or `build_ssh` are set"
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The part of [Container::precheck] that does not run any docker
+    /// commands, used by [ContainerNetwork::validate]. The crash artifacts
+    /// directory is only created if `create_dirs`, otherwise it is left as is
+    /// if it does not exist yet.
+    pub(crate) async fn precheck_without_docker(&mut self, create_dirs: bool) -> Result<()> {
+        if let Some(ref mut digest) = self.pinned_digest {
+            if !matches!(self.dockerfile, Dockerfile::NameTag(_)) {
+                return Err(Error::from_kind_locationless(
+                    "Container::precheck -> a `pinned_digest` requires a `Dockerfile::NameTag`",
+                ))
+            }
+            *digest = parse_digest(digest)
+                .stack_err_locationless(|| "Container::precheck -> invalid `pinned_digest`")?;
+        }
         match self.dockerfile {
             Dockerfile::NameTag(_) => (),
             Dockerfile::Path(ref path) => {
@@ -399,7 +1820,64 @@ impl Container {
             }
         }
 
-        for (local_volume, _) in &mut self.volumes {
+        if let Some(ref mut crash_artifacts_dir) = self.crash_artifacts_dir {
+            let (resolved, base) = resolve(Path::new(crash_artifacts_dir));
+            if create_dirs {
+                tokio::fs::create_dir_all(&resolved)
+                    .await
+                    .stack_err_locationless(|| {
+                        format!(
+                            "Container::precheck -> could not create the crash artifacts \
+                             directory {crash_artifacts_dir:?}{base}"
+                        )
+                    })?;
+            }
+            // otherwise a directory that does not exist yet is left for `run` to create
+            if resolved.exists() {
+                let path = acquire_dir_path(&crash_artifacts_dir)
+                    .await
+                    .stack_err_locationless(|| {
+                        "Container::precheck -> could not acquire the crash artifacts directory"
+                    })?;
+                path.to_str()
+                    .stack_err_locationless(|| "Container::precheck -> path was not UTF-8")?
+                    .clone_into(crash_artifacts_dir);
+            }
+        }
+
+        if ((!self.build_secrets.is_empty()) || self.build_ssh.is_some())
+            && matches!(self.dockerfile, Dockerfile::NameTag(_))
+        {
+            return Err(Error::from_kind_locationless(
+                "Container::precheck -> `build_secrets` or `build_ssh` are set, but the \
+                 `Dockerfile::NameTag` is not built",
+            ))
+        }
+        for secret in &mut self.build_secrets {
+            let id = &secret.id;
+            let path = acquire_file_path(&secret.host_path)
+                .await
+                .stack_err_locationless(|| {
+                    format!(
+                        "Container::precheck -> could not acquire the file of build secret {id}"
+                    )
+                })?;
+            path.to_str()
+                .stack_err_locationless(|| "Container::precheck -> path was not UTF-8")?
+                .clone_into(&mut secret.host_path);
+        }
+        if let Some(ref mut build_ssh) = self.build_ssh {
+            if build_ssh != "default" {
+                let path = acquire_path(&build_ssh).await.stack_err_locationless(|| {
+                    "Container::precheck -> could not acquire the `build_ssh` path"
+                })?;
+                path.to_str()
+                    .stack_err_locationless(|| "Container::precheck -> path was not UTF-8")?
+                    .clone_into(build_ssh);
+            }
+        }
+
+        for (local_volume, container_volume) in &mut self.volumes {
             let path = acquire_path(&local_volume)
                 .await
                 .stack_err_locationless(|| {
@@ -408,35 +1886,86 @@ impl Container {
             path.to_str()
                 .stack_err_locationless(|| "Container::precheck -> path was not UTF-8")?
                 .clone_into(local_volume);
+            let actual = if path.is_dir() {
+                VolumeKind::Dir
+            } else {
+                VolumeKind::File
+            };
+            let target = volume_target(container_volume);
+            let expected = self
+                .volume_kinds
+                .iter()
+                .find(|(other, _)| other == target)
+                .map(|(_, kind)| *kind);
+            let warning = check_volume_kind(&self.name, local_volume, target, actual, expected)
+                .stack_err_locationless(|| "Container::precheck")?;
+            if let Some(message) = warning {
+                warn!("Container::precheck -> {message}");
+                emit_crate_event(|| CrateEvent::SuspiciousVolumeKind {
+                    name: self.name.clone(),
+                    source: local_volume.clone(),
+                    target: target.to_owned(),
+                });
+            }
         }
 
         Ok(())
     }
 
+    /// The `--secret` and `--ssh` args for `docker build`
+    fn buildkit_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        for secret in &self.build_secrets {
+            args.push("--secret".to_owned());
+            args.push(format!("id={},src={}", secret.id, secret.host_path));
+        }
+        if let Some(ref build_ssh) = self.build_ssh {
+            args.push("--ssh".to_owned());
+            if build_ssh == "default" {
+                args.push("default".to_owned());
+            } else {
+                args.push(format!("default={build_ssh}"));
+            }
+        }
+        args
+    }
+
+    fn apply_build_progress(&self, command: Command) -> Command {
+        if let Some(BuildProgressCallback(ref on_build_progress)) = self.on_build_progress {
+            let on_build_progress = on_build_progress.clone();
+            command.line_callback(move |line| {
+                if let Some(progress) = parse_build_progress(line) {
+                    on_build_progress(&progress, line);
+                }
+            })
+        } else {
+            command
+        }
+    }
+
     /// Runs `docker build` to create a container corresponding to `self`
     /// (preferably after [Container::precheck] is run). `build_tag` needs to be
     /// set unless `Dockerfile::NameTag` was used.
     pub async fn build(&self, debug_build: bool) -> Result<()> {
+        self.build_with_stats(debug_build).await.map(|_| ())
+    }
+
+    /// The same as [Container::build] but also returns the [BuildStats] parsed
+    /// from the build output, or `None` if `Dockerfile::NameTag` was used and
+    /// nothing was built
+    pub async fn build_with_stats(&self, debug_build: bool) -> Result<Option<BuildStats>> {
         // NOTE: `ContainerNetwork::run_internal` assumes that builds are uniquely
         // determined from `dockerfile` and `build_args`.
         let build_tag = &self
             .build_tag
             .as_ref()
             .stack_err_locationless(|| "Container::build -> the `build_tag` needs to be set")?;
+        let buildkit_args = self.buildkit_args();
         match self.dockerfile {
-            Dockerfile::NameTag(ref _name_tag) => {
-                // adds unnecessary time to common case, just catch it at
-                // build time or else we should add a flag to do this step
-                // (which does update the image if it has new commits)
-                /*let comres = Command::new("docker pull", &[&name_tag])
-                    .debug(debug)
-                    .stdout_log(&debug_log)
-                    .stderr_log(&debug_log)
-                    .run_to_completion()
-                    .await?;
-                comres.assert_success().stack_err(|| {
-                    format!("could not pull image for `Dockerfile::Image({name_tag})`")
-                })?;*/
+            Dockerfile::NameTag(_) => {
+                // pulling is controlled by the `pull_policy`, see
+                // `ContainerNetwork::run_internal`
+                Ok(None)
             }
             Dockerfile::Path(ref path) => {
                 let mut dockerfile = acquire_file_path(path).await?;
@@ -445,67 +1974,93 @@ impl Container {
                 let mut build_args = vec!["build", "-t", build_tag, "--file", &dockerfile_full];
                 dockerfile.pop();
                 let dockerfile_dir = dockerfile.to_str().unwrap().to_owned();
-                let mut tmp = vec![];
-                for arg in &self.build_args {
-                    tmp.push(arg);
-                }
-                for s in &tmp {
-                    build_args.push(s);
-                }
+                build_args.extend(self.build_args.iter().map(|s| s.as_str()));
+                build_args.extend(buildkit_args.iter().map(|s| s.as_str()));
                 build_args.push(&dockerfile_dir);
-                let command = apply_debug(
-                    Command::new("docker").args(build_args),
-                    &self.name,
-                    debug_build,
-                );
+                let command =
+                    self.apply_command_middleware(self.apply_build_progress(apply_debug(
+                        Command::from_parts(docker_binary(), build_args),
+                        &self.name,
+                        debug_build,
+                    )));
                 if debug_build {
-                    debug!("Container::build command: {command:#?}");
+                    debug!("Container::build command: {}", command.display_compact());
                 }
-                command
-                    .run_to_completion()
-                    .await?
-                    .assert_success()
-                    .stack_err_locationless(|| {
-                        format!("Container::build -> when using the dockerfile at {path:?}")
-                    })?;
+                let start = Instant::now();
+                let comres = command.run_to_completion().await?;
+                comres.assert_success().stack_err_locationless(|| {
+                    format!("Container::build -> when using the dockerfile at {path:?}")
+                })?;
+                Ok(Some(build_stats(&comres, start.elapsed())))
             }
             Dockerfile::Contents(ref contents) => {
                 let dockerfile_write_file = self.dockerfile_write_file.as_ref().stack()?;
                 FileOptions::write_str(&dockerfile_write_file, contents).await?;
                 let mut build_args: Vec<&str> =
                     vec!["build", "-t", build_tag, "--file", &dockerfile_write_file];
-                let mut tmp: Vec<&str> = vec![];
-                for arg in &self.build_args {
-                    tmp.push(arg);
-                }
-                for s in &tmp {
-                    build_args.push(s);
-                }
+                build_args.extend(self.build_args.iter().map(|s| s.as_str()));
+                build_args.extend(buildkit_args.iter().map(|s| s.as_str()));
                 let mut dockerfile_write_dir = PathBuf::from(dockerfile_write_file.to_owned());
                 dockerfile_write_dir.pop();
                 build_args.push(dockerfile_write_dir.to_str().unwrap());
-                let command = apply_debug(
-                    Command::new("docker").args(build_args),
-                    &self.name,
-                    debug_build,
-                );
+                let command =
+                    self.apply_command_middleware(self.apply_build_progress(apply_debug(
+                        Command::from_parts(docker_binary(), build_args),
+                        &self.name,
+                        debug_build,
+                    )));
                 if debug_build {
-                    debug!("Container::build command: {command:#?}");
+                    debug!("Container::build command: {}", command.display_compact());
                 }
-                command
-                    .run_to_completion()
-                    .await?
-                    .assert_success()
-                    .stack_err_locationless(|| {
-                        format!(
-                            "Container::build -> when using the `Dockerfile::Contents` written to \
-                             \"{dockerfile_write_file:?}\":\n{contents}\n"
-                        )
-                    })?;
+                let start = Instant::now();
+                let comres = command.run_to_completion().await?;
+                comres.assert_success().stack_err_locationless(|| {
+                    format!(
+                        "Container::build -> when using the `Dockerfile::Contents` written to \
+                         \"{dockerfile_write_file:?}\":\n{contents}\n"
+                    )
+                })?;
+                Ok(Some(build_stats(&comres, start.elapsed())))
             }
         }
+    }
 
-        Ok(())
+    /// Writes the `entrypoint_args` to the `entrypoint_args_write_file`,
+    /// returning the canonicalized path
+    async fn write_entrypoint_args_file(&self) -> Result<PathBuf> {
+        let path = self
+            .entrypoint_args_write_file
+            .as_ref()
+            .stack_err_locationless(|| {
+                "Container::create -> `entrypoint_args_file` is set but \
+                 `entrypoint_args_write_file` needs to be set"
+            })?;
+        let mut contents = String::new();
+        for arg in &self.entrypoint_args {
+            if arg.contains('\n') {
+                return Err(Error::from_kind_locationless(format!(
+                    "Container::create -> entrypoint arg {arg:?} contains a newline, which cannot \
+                     be passed through the `entrypoint_args_file`"
+                )))
+            }
+            contents.push_str(arg);
+            contents.push('\n');
+        }
+        tokio::fs::write(path, contents)
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "Container::create -> when writing the `entrypoint_args_write_file` {path:?}"
+                )
+            })?;
+        tokio::fs::canonicalize(path)
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "Container::create -> when canonicalizing the `entrypoint_args_write_file` \
+                     {path:?}"
+                )
+            })
     }
 
     /// Runs `docker create` to create a container corresponding to `self`
@@ -519,16 +2074,21 @@ impl Container {
     ) -> Result<String> {
         let container_name = &self.container_name;
         let hostname = &self.host_name;
-        let mut args = vec![
-            "create",
-            "--rm",
-            "--network",
-            &network_name,
-            "--hostname",
-            &hostname,
-            "--name",
-            &container_name,
-        ];
+        let pinned;
+        let mut args = vec!["create"];
+        // checkpointing stops the container, which would remove it, and the exit code
+        // of a detached container is inspected after it exits
+        if !(self.checkpointable || self.detached) {
+            args.push("--rm");
+        }
+        match self.network_mode {
+            NetworkMode::Default => {
+                args.extend(["--network", network_name, "--hostname", hostname])
+            }
+            NetworkMode::Host => args.extend(["--network", "host"]),
+            NetworkMode::None => args.extend(["--network", "none"]),
+        }
+        args.extend(["--name", container_name]);
 
         if let Some(workdir) = self.workdir.as_ref() {
             args.push("-w");
@@ -544,6 +2104,16 @@ impl Container {
             args.push(tmp);
         }
 
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        for label in &labels {
+            args.push("--label");
+            args.push(label);
+        }
+
         // volumes
         let mut combined_volumes = vec![];
         for (local_volume, virtual_volume) in &self.volumes {
@@ -555,40 +2125,137 @@ impl Container {
             args.push(volume);
         }
 
+        let crash_volume;
+        let crash_env;
+        if let Some(ref crash_artifacts_dir) = self.crash_artifacts_dir {
+            crash_volume = format!("{crash_artifacts_dir}:{CRASH_ARTIFACTS_DIR}");
+            crash_env = format!("SUPER_ORCHESTRATOR_CRASH_DIR={CRASH_ARTIFACTS_DIR}");
+            args.extend([
+                "--volume",
+                &crash_volume,
+                "--ulimit",
+                "core=-1",
+                "-e",
+                "RUST_BACKTRACE=full",
+                "-e",
+                &crash_env,
+            ]);
+        }
+
+        let socket_volume;
+        #[cfg(unix)]
+        let socket_group;
+        if let Some(ref socket) = self.docker_socket {
+            socket_volume = format!("{socket}:{DEFAULT_DOCKER_SOCKET}");
+            args.extend(["--volume", &socket_volume]);
+            #[cfg(unix)]
+            if self.docker_socket_group_add {
+                use std::os::unix::fs::MetadataExt;
+                let metadata = tokio::fs::metadata(socket)
+                    .await
+                    .stack_err_locationless(|| {
+                        format!(
+                            "Container::create -> could not get the group of the `docker_socket` \
+                             {socket:?}, `docker_socket_group_add` can be unset if it is not \
+                             needed"
+                        )
+                    })?;
+                socket_group = metadata.gid().to_string();
+                args.extend(["--group-add", &socket_group]);
+            }
+        }
+
+        let args_volume;
+        if self.entrypoint_args_file {
+            let path = self.write_entrypoint_args_file().await?;
+            args_volume = format!("{}:{ENTRYPOINT_ARGS_FILE}:ro", path.display());
+            args.extend(["--volume", &args_volume]);
+        }
+
+        let pull_arg;
+        if let (Dockerfile::NameTag(_), Some(pull_policy)) = (&self.dockerfile, self.pull_policy) {
+            // the image was already pulled during the build phase
+            let pull_policy = if pull_policy.pulls_before_create() {
+                PullPolicy::Missing
+            } else {
+                pull_policy
+            };
+            pull_arg = format!("--pull={}", pull_policy.as_str());
+            args.push(&pull_arg);
+        }
+
+        // other creation args
+        for tmpfs in &self.tmpfs {
+            args.push("--tmpfs");
+            args.push(tmpfs);
+        }
+        if self.read_only_rootfs {
+            args.push("--read-only");
+        }
+
         // other creation args
         for create_arg in &self.create_args {
             args.push(create_arg);
         }
 
-        match self.dockerfile {
+        let image = match self.dockerfile {
             Dockerfile::NameTag(ref name_tag) => {
-                // tag using `name_tag`
-                args.push(name_tag);
+                if let Some(ref digest) = self.pinned_digest {
+                    pinned = pinned_reference(name_tag, digest);
+                    &pinned
+                } else {
+                    // tag using `name_tag`
+                    name_tag
+                }
             }
             Dockerfile::Path(_) | Dockerfile::Contents(_) => {
                 // use the tag of the build image
-                args.push(self.build_tag.as_ref().stack_err_locationless(|| {
-                    "Container::create -> `build_tag` needs to be set"
-                })?);
+                self.build_tag
+                    .as_ref()
+                    .stack_err_locationless(|| "Container::create -> `build_tag` needs to be set")?
             }
-        }
+        };
+        args.push(image);
 
         // the binary
         if let Some(s) = self.entrypoint_file.as_ref() {
             args.push(s);
         }
         // entrypoint args
-        let mut tmp = vec![];
-        for arg in &self.entrypoint_args {
-            tmp.push(arg.to_owned());
+        if self.entrypoint_args_file {
+            args.extend(["--args-file", ENTRYPOINT_ARGS_FILE]);
+        } else {
+            for arg in &self.entrypoint_args {
+                args.push(arg);
+            }
         }
-        for s in &tmp {
-            args.push(s);
+
+        let command = self.apply_command_middleware(
+            apply_debug(
+                Command::from_parts(docker_binary(), args),
+                &self.name,
+                debug_create,
+            )
+            .log(log_file),
+        );
+        let size = (command.program.len() as u64)
+            + 1
+            + command
+                .args
+                .iter()
+                .map(|arg| (arg.len() as u64) + 1)
+                .sum::<u64>();
+        if size > self.max_create_argv_size {
+            let e = ArgvTooLongError {
+                name: self.name.clone(),
+                size,
+                limit: self.max_create_argv_size,
+            };
+            let msg = format!("Container::create -> {e}");
+            return Err(Error::box_from_locationless(e).add_kind_locationless(msg))
         }
-        let command =
-            apply_debug(Command::new("docker").args(args), &self.name, debug_create).log(log_file);
         if debug_create {
-            debug!("Container::create command: {command:#?}");
+            debug!("Container::create command: {}", command.display_compact());
         }
         match command.run_to_completion().await {
             Ok(output) => {
@@ -602,7 +2269,38 @@ impl Container {
                             Err(e) => Err(Error::from_kind_locationless(e)),
                         }
                     }
-                    Err(e) => Err(e),
+                    Err(e) => {
+                        let stderr = output.stderr_as_utf8_lossy();
+                        let lowercase = stderr.to_lowercase();
+                        if stderr.contains("is already in use") {
+                            let conflict = NameConflictError {
+                                name: self.name.clone(),
+                                container_name: container_name.clone(),
+                            };
+                            let msg = format!("Container::create -> {conflict}");
+                            Err(e
+                                .box_and_add_locationless(conflict)
+                                .add_kind_locationless(msg))
+                        } else if [
+                            "pull access denied",
+                            "manifest unknown",
+                            "repository does not exist",
+                        ]
+                        .iter()
+                        .any(|marker| lowercase.contains(marker))
+                        {
+                            let not_found = ImageNotFoundError {
+                                name: self.name.clone(),
+                                image: image.clone(),
+                            };
+                            let msg = format!("Container::create -> {not_found}");
+                            Err(e
+                                .box_and_add_locationless(not_found)
+                                .add_kind_locationless(msg))
+                        } else {
+                            Err(e)
+                        }
+                    }
                 }
             }
             Err(e) => {
@@ -618,20 +2316,84 @@ impl Container {
         container_id: &str,
         stdout_log: Option<&FileOptions>,
         stderr_log: Option<&FileOptions>,
+    ) -> Result<CommandRunner> {
+        self.start_internal(container_id, None, stdout_log, stderr_log)
+            .await
+            .stack_err_locationless(|| "Container::start")
+    }
+
+    /// Same as [Container::start] but restores the container from the
+    /// checkpoint with `checkpoint_name`, see [ContainerNetwork::restore_all]
+    pub async fn start_from_checkpoint(
+        &self,
+        container_id: &str,
+        checkpoint_name: &str,
+        stdout_log: Option<&FileOptions>,
+        stderr_log: Option<&FileOptions>,
+    ) -> Result<CommandRunner> {
+        self.start_internal(container_id, Some(checkpoint_name), stdout_log, stderr_log)
+            .await
+            .stack_err_locationless(|| {
+                format!("Container::start_from_checkpoint(checkpoint_name: {checkpoint_name})")
+            })
+    }
+
+    async fn start_internal(
+        &self,
+        container_id: &str,
+        checkpoint_name: Option<&str>,
+        stdout_log: Option<&FileOptions>,
+        stderr_log: Option<&FileOptions>,
     ) -> Result<CommandRunner> {
         let name = &self.name;
-        let mut command = apply_debug(
-            Command::new("docker start --attach").arg(container_id),
-            name,
-            self.debug,
-        );
+        let mut command = if self.detached {
+            docker_command("start")
+        } else {
+            docker_command("start --attach")
+        };
+        if let Some(checkpoint_name) = checkpoint_name {
+            command = command.arg("--checkpoint").arg(checkpoint_name);
+        }
+        let mut command = command.arg(container_id);
+        if self.detached {
+            self.apply_command_middleware(command)
+                .run_to_completion()
+                .await
+                .stack_err_locationless(|| "when starting the detached container")?
+                .assert_success()
+                .stack_err_locationless(|| "when starting the detached container")?;
+            if !self.follows_logs() {
+                let runner = self
+                    .apply_command_middleware(docker_command("wait").arg(container_id))
+                    .run()
+                    .await
+                    .stack()?;
+                return Ok(runner)
+            }
+            command = docker_command("logs --follow").arg(container_id);
+        }
+        let mut command = apply_debug(command, name, self.debug)
+            .debug_output(self.debug_output)
+            .debug_buffering(self.debug_buffering)
+            .max_debug_lines_per_sec(self.max_debug_lines_per_sec);
         if self.log {
             command = command.stdout_log(stdout_log).stderr_log(stderr_log);
+            if let Some(ref combined_log) = self.combined_log {
+                let mut combined_log = combined_log.clone();
+                // like the separate log files, appended to when restoring
+                if checkpoint_name.is_some() {
+                    if let ReadOrWrite::Write(ref mut options) = combined_log.options {
+                        options.append = true;
+                    }
+                }
+                command = command.add_log(combined_log);
+            }
         }
-        let runner = command
-            .run()
-            .await
-            .stack_err_locationless(|| "Container::start")?;
+        let mut command = command
+            .log_limit(self.log_limit)
+            .record_limit(self.record_limit);
+        command.record_spill.clone_from(&self.record_spill);
+        let runner = self.apply_command_middleware(command).run().await.stack()?;
         Ok(runner)
     }
 }
@@ -1,14 +1,116 @@
-use std::{net::IpAddr, process::Stdio, time::Duration};
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Mutex,
+    time::Duration,
+};
 
+use serde::{Deserialize, Serialize};
 use stacked_errors::{Error, Result, StackableErr};
 use tokio::time::sleep;
 use tracing::{info, warn};
 
-use crate::{ctrlc_issued_reset, sh, stacked_get, wait_for_ok, Command};
+use crate::{
+    acquire_dir_path, ctrlc_issued_reset, middleware::CommandMiddleware, misc::emit_crate_event,
+    parse_docker_size, stacked_get, wait_for_ok, Command, CommandResult, CrateEvent, FileOptions,
+};
 
 const STD_DELAY: Duration = Duration::from_millis(300);
 const IP_RETRIES: u64 = 10;
 
+static DOCKER_BINARY: Mutex<Option<OsString>> = Mutex::new(None);
+
+/// Sets the process-global program that is run for all docker CLI
+/// invocations, `"docker"` by default. This can be used to point to a
+/// compatible engine or a test double.
+pub fn set_docker_binary(program: impl AsRef<OsStr>) {
+    *DOCKER_BINARY.lock().unwrap_or_else(|e| e.into_inner()) = Some(program.as_ref().to_owned());
+}
+
+/// Returns the process-global program that is run for all docker CLI
+/// invocations, see [set_docker_binary]
+pub fn docker_binary() -> OsString {
+    DOCKER_BINARY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(|| "docker".into())
+}
+
+/// Returned (boxed in the error stack) by [CommandResult::assert_success]
+/// when a docker CLI command failed because the docker daemon could not be
+/// reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaemonUnreachableError {
+    /// The stderr of the docker CLI
+    pub stderr: String,
+}
+
+impl Display for DaemonUnreachableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the docker daemon could not be reached: {}",
+            self.stderr.trim()
+        )
+    }
+}
+
+impl std::error::Error for DaemonUnreachableError {}
+
+/// Returns a [DaemonUnreachableError] if `comres` is from the docker CLI and
+/// its stderr shows that the daemon could not be reached
+pub(crate) fn daemon_unreachable(comres: &CommandResult) -> Option<DaemonUnreachableError> {
+    if comres.command.program != docker_binary() {
+        return None
+    }
+    let stderr = String::from_utf8_lossy(&comres.stderr);
+    [
+        "Cannot connect to the Docker daemon",
+        "error during connect",
+        "i/o timeout",
+        "context deadline exceeded",
+        "connection reset by peer",
+    ]
+    .iter()
+    .any(|marker| stderr.contains(marker))
+    .then(|| DaemonUnreachableError {
+        stderr: stderr.into_owned(),
+    })
+}
+
+/// The default path of the docker daemon socket
+pub const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Returns the host path of the docker daemon socket. If `DOCKER_HOST` is a
+/// "unix://" address its path is used, otherwise the rootless socket at
+/// "$XDG_RUNTIME_DIR/docker.sock" is used if it exists, falling back to
+/// [DEFAULT_DOCKER_SOCKET]. Returns `None` if `DOCKER_HOST` is set to a
+/// non-unix address, in which case there is no socket to mount.
+pub fn docker_socket_path() -> Option<PathBuf> {
+    if let Some(docker_host) = std::env::var_os("DOCKER_HOST").filter(|s| !s.is_empty()) {
+        let docker_host = docker_host.to_string_lossy();
+        return docker_host.strip_prefix("unix://").map(PathBuf::from)
+    }
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        let rootless = Path::new(&runtime_dir).join("docker.sock");
+        if rootless.exists() {
+            return Some(rootless)
+        }
+    }
+    Some(PathBuf::from(DEFAULT_DOCKER_SOCKET))
+}
+
+/// Returns a `Command` running [docker_binary] with the whitespace separated
+/// `args`
+pub(crate) fn docker_command(args: &str) -> Command {
+    Command::from_parts(docker_binary(), args.split_whitespace())
+}
+
 /// Uses `docker inspect` to find the IP address of the container. There is a
 /// delay between a container starting and an IP address being assigned, which
 /// is why this has a retry mechanism.
@@ -18,7 +120,7 @@ pub async fn wait_get_ip_addr(
     container_id: &str,
 ) -> Result<IpAddr> {
     async fn f(container_id: &str) -> Result<IpAddr> {
-        let comres = Command::new("docker inspect")
+        let comres = docker_command("inspect")
             .arg(container_id)
             .run_to_completion()
             .await
@@ -29,22 +131,1005 @@ pub async fn wait_get_ip_addr(
         //println!("{}", comres.stdout_as_utf8().stack()?);
         let v: serde_json::Value =
             serde_json::from_str(comres.stdout_as_utf8().stack()?).stack()?;
-        let networks = stacked_get!(v[0]["NetworkSettings"]["Networks"])
-            .as_object()
-            .stack()?;
-        let network = networks.iter().next().stack()?.1;
-        let addr = stacked_get!(network["IPAddress"]).as_str().stack()?;
-        if addr.is_empty() {
-            return Err(Error::from("IP address has not been assigned yet"))
-        }
-        let ip_addr: std::result::Result<IpAddr, _> = addr.parse();
-        ip_addr.stack()
+        ip_addr_from_inspect(stacked_get!(v[0]))
     }
     wait_for_ok(num_retries, delay, || f(container_id))
         .await
         .stack_err(|| format!("wait_get_ip_addr(container_id: {container_id})"))
 }
 
+/// Gets the IP address from the `docker inspect` JSON of a single container
+pub(crate) fn ip_addr_from_inspect(v: &serde_json::Value) -> Result<IpAddr> {
+    let networks = stacked_get!(v["NetworkSettings"]["Networks"])
+        .as_object()
+        .stack()?;
+    let network = networks.iter().next().stack()?.1;
+    let addr = stacked_get!(network["IPAddress"]).as_str().stack()?;
+    if addr.is_empty() {
+        return Err(Error::from("IP address has not been assigned yet"))
+    }
+    let ip_addr: std::result::Result<IpAddr, _> = addr.parse();
+    ip_addr.stack()
+}
+
+/// Extracts `container_path` from the image `image_tag` to `host_dest` without
+/// running a container. This creates a container from the image without
+/// starting it, uses `docker cp`, and then removes the container. If
+/// `host_dest` is an existing directory, the file or directory is copied into
+/// it, otherwise `host_dest` is the path copied to (and its parent directory
+/// must exist). Returns the host path of the extracted file or directory.
+///
+/// # Errors
+///
+/// If `container_path` does not exist in the image, the error says so
+/// specifically. Other errors can come from acquiring `host_dest` or from
+/// failing docker commands, except that failing to remove the temporary
+/// container after a successful copy is only warned about.
+pub async fn extract_from_image(
+    image_tag: &str,
+    container_path: &str,
+    host_dest: &str,
+) -> Result<PathBuf> {
+    let dest = if let Ok(mut dir) = acquire_dir_path(host_dest).await {
+        let file_name = Path::new(container_path)
+            .file_name()
+            .stack_err_locationless(|| {
+                format!(
+                    "extract_from_image(image_tag: {image_tag}, container_path: {container_path}, \
+                     host_dest: {host_dest}) -> `container_path` has no file name to copy into \
+                     the `host_dest` directory"
+                )
+            })?;
+        dir.push(file_name);
+        dir
+    } else {
+        FileOptions::write(host_dest)
+            .preacquire()
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "extract_from_image(image_tag: {image_tag}, container_path: {container_path}, \
+                     host_dest: {host_dest}) -> could not acquire the parent directory of \
+                     `host_dest`"
+                )
+            })?
+    };
+    let dest_str = dest
+        .to_str()
+        .stack_err_locationless(|| "extract_from_image -> destination path was not UTF-8")?;
+
+    // the command is never run, it is only needed in case the image has no
+    // default command
+    let comres = docker_command("create")
+        .arg(image_tag)
+        .arg("true")
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| "extract_from_image -> when running `docker create`")?;
+    comres.assert_success().stack_err_locationless(|| {
+        format!("extract_from_image(image_tag: {image_tag}, ..) -> `docker create` failed")
+    })?;
+    let id = comres
+        .stdout_as_utf8()
+        .stack_err_locationless(|| "extract_from_image -> container ID was not UTF-8")?
+        .trim()
+        .to_owned();
+
+    let cp_res = docker_command("cp")
+        .arg(format!("{id}:{container_path}"))
+        .arg(dest_str)
+        .run_to_completion()
+        .await;
+    // always remove the temporary container before handling errors
+    let rm_res = docker_command("rm").arg(&id).run_to_completion().await;
+
+    let cp_res =
+        cp_res.stack_err_locationless(|| "extract_from_image -> when running `docker cp`")?;
+    if !cp_res.successful() {
+        let stderr = cp_res.stderr_as_utf8_lossy();
+        if stderr.contains("Could not find the file") || stderr.contains("No such container:path") {
+            return Err(Error::from_kind_locationless(format!(
+                "extract_from_image(image_tag: {image_tag}, container_path: {container_path}, \
+                 host_dest: {host_dest}) -> path does not exist in the image"
+            )))
+        }
+        cp_res.assert_success().stack_err_locationless(|| {
+            format!(
+                "extract_from_image(image_tag: {image_tag}, container_path: {container_path}, \
+                 host_dest: {host_dest}) -> `docker cp` failed"
+            )
+        })?;
+    }
+    // the extraction already succeeded, so a leftover container is only warned
+    // about
+    if let Err(e) = rm_res
+        .stack_err_locationless(|| "extract_from_image -> when running `docker rm`")
+        .and_then(|comres| comres.assert_success())
+    {
+        warn!("extract_from_image -> failed to remove temporary container {id}: {e:?}");
+        emit_crate_event(|| CrateEvent::Other {
+            message: format!("extract_from_image -> failed to remove temporary container {id}"),
+        });
+    }
+    Ok(dest)
+}
+
+/// Totals for one category of `docker system df`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsageEntry {
+    /// The number of objects in the category
+    pub total_count: u64,
+    /// The number of objects that are in use (e.x. images used by a container)
+    pub active: u64,
+    /// Size in bytes
+    pub size: u64,
+    /// Reclaimable size in bytes
+    pub reclaimable: u64,
+}
+
+/// The typed output of `docker system df`, see [system_df]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    /// The "Images" row
+    pub images: DiskUsageEntry,
+    /// The "Containers" row
+    pub containers: DiskUsageEntry,
+    /// The "Local Volumes" row
+    pub volumes: DiskUsageEntry,
+    /// The "Build Cache" row
+    pub build_cache: DiskUsageEntry,
+}
+
+impl DiskUsage {
+    /// The sum of all the `size`s
+    pub fn total_size(&self) -> u64 {
+        self.images.size + self.containers.size + self.volumes.size + self.build_cache.size
+    }
+
+    /// The sum of all the `reclaimable`s
+    pub fn total_reclaimable(&self) -> u64 {
+        self.images.reclaimable
+            + self.containers.reclaimable
+            + self.volumes.reclaimable
+            + self.build_cache.reclaimable
+    }
+}
+
+/// Parses the lines output by `docker system df --format '{{json .}}'`
+fn parse_system_df(stdout: &str) -> Result<DiskUsage> {
+    fn count(v: &serde_json::Value, key: &str) -> Result<u64> {
+        // docker outputs these as strings, some versions output numbers
+        let v = stacked_get!(v[key]);
+        if let Some(n) = v.as_u64() {
+            return Ok(n)
+        }
+        let s = v.as_str().stack()?.trim();
+        // build cache "Active" can be empty
+        if s.is_empty() {
+            Ok(0)
+        } else {
+            s.parse::<u64>().stack()
+        }
+    }
+
+    let mut res = DiskUsage::default();
+    for line in stdout.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        let v: serde_json::Value = serde_json::from_str(line).stack()?;
+        let entry = DiskUsageEntry {
+            total_count: count(&v, "TotalCount")?,
+            active: count(&v, "Active")?,
+            size: parse_docker_size(stacked_get!(v["Size"]).as_str().stack()?)?,
+            reclaimable: parse_docker_size(stacked_get!(v["Reclaimable"]).as_str().stack()?)?,
+        };
+        match stacked_get!(v["Type"]).as_str().stack()? {
+            "Images" => res.images = entry,
+            "Containers" => res.containers = entry,
+            "Local Volumes" => res.volumes = entry,
+            "Build Cache" => res.build_cache = entry,
+            other => {
+                warn!("system_df -> ignoring unknown type \"{other}\"");
+                emit_crate_event(|| CrateEvent::Other {
+                    message: format!("system_df -> ignoring unknown type \"{other}\""),
+                });
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Runs `docker system df` and returns the typed totals
+pub async fn system_df() -> Result<DiskUsage> {
+    let comres = docker_command("system df --format")
+        .arg("{{json .}}")
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| "system_df -> when running `docker system df`")?;
+    comres
+        .assert_success()
+        .stack_err_locationless(|| "system_df -> `docker system df` failed")?;
+    parse_system_df(
+        comres
+            .stdout_as_utf8()
+            .stack_err_locationless(|| "system_df -> output was not UTF-8")?,
+    )
+    .stack_err_locationless(|| "system_df -> could not parse output of `docker system df`")
+}
+
+/// A change to the filesystem of a container relative to its image, as
+/// reported by `docker diff`. See
+/// [ContainerNetwork::fs_diff](crate::docker::ContainerNetwork::fs_diff).
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FsChange {
+    /// The path was added ("A")
+    Added(String),
+    /// The path was changed ("C"), note that directories are reported as
+    /// changed when anything inside of them changes
+    Changed(String),
+    /// The path was deleted ("D")
+    Deleted(String),
+}
+
+impl FsChange {
+    /// Returns the path of the change
+    pub fn path(&self) -> &str {
+        match self {
+            FsChange::Added(path) | FsChange::Changed(path) | FsChange::Deleted(path) => path,
+        }
+    }
+}
+
+/// Parses the output of `docker diff`, which has lines of a kind letter and a
+/// path
+///
+/// ```
+/// use super_orchestrator::docker_helpers::{parse_docker_diff, FsChange};
+///
+/// let output = "C /etc\nA /etc/app.toml\nD /tmp/old file\n";
+/// assert_eq!(parse_docker_diff(output).unwrap(), vec![
+///     FsChange::Changed("/etc".to_owned()),
+///     FsChange::Added("/etc/app.toml".to_owned()),
+///     FsChange::Deleted("/tmp/old file".to_owned()),
+/// ]);
+/// assert_eq!(parse_docker_diff("").unwrap(), vec![]);
+/// assert!(parse_docker_diff("X /etc").is_err());
+/// assert!(parse_docker_diff("A").is_err());
+/// ```
+pub fn parse_docker_diff(output: &str) -> Result<Vec<FsChange>> {
+    let mut res = vec![];
+    for line in output.lines() {
+        if line.trim().is_empty() {
+            continue
+        }
+        let change = match line.split_once(' ') {
+            Some(("A", path)) => FsChange::Added(path.to_owned()),
+            Some(("C", path)) => FsChange::Changed(path.to_owned()),
+            Some(("D", path)) => FsChange::Deleted(path.to_owned()),
+            _ => {
+                return Err(Error::from_kind_locationless(format!(
+                    "parse_docker_diff -> unrecognized line \"{line}\""
+                )))
+            }
+        };
+        res.push(change);
+    }
+    Ok(res)
+}
+
+/// Options for [prune]. The default prunes only dangling images, stopped
+/// containers, and unused networks, just like `docker system prune`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PruneOptions {
+    /// Remove all unused images instead of only dangling ones, and all unused
+    /// build cache instead of only dangling build cache
+    pub all: bool,
+    /// Only prune objects with these labels, each is of the form `key` or
+    /// `key=value` and is passed as `--filter label=...`. Build cache has no
+    /// labels, so these are not passed to `docker builder prune`.
+    pub label_filters: Vec<String>,
+    /// Only prune objects created before this long ago. Not supported by
+    /// `docker volume prune`, so the volume prune is skipped if this is
+    /// set.
+    pub until: Option<Duration>,
+    /// Also run `docker volume prune`
+    pub volumes: bool,
+    /// Also run `docker builder prune`
+    pub build_cache: bool,
+    /// Do not actually prune anything, only return the commands that would be
+    /// run
+    pub dry_run: bool,
+}
+
+impl PruneOptions {
+    /// The same as `PruneOptions::default()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets [PruneOptions::all]
+    pub fn all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    /// Adds a label to [PruneOptions::label_filters]
+    pub fn label_filter<S: AsRef<str>>(mut self, label: S) -> Self {
+        self.label_filters.push(label.as_ref().to_owned());
+        self
+    }
+
+    /// Sets [PruneOptions::until]
+    pub fn until(mut self, until: Duration) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Sets [PruneOptions::volumes]
+    pub fn volumes(mut self, volumes: bool) -> Self {
+        self.volumes = volumes;
+        self
+    }
+
+    /// Sets [PruneOptions::build_cache]
+    pub fn build_cache(mut self, build_cache: bool) -> Self {
+        self.build_cache = build_cache;
+        self
+    }
+
+    /// Sets [PruneOptions::dry_run]
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Returns the commands that [prune] runs with these options
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use super_orchestrator::docker_helpers::PruneOptions;
+    ///
+    /// let commands = PruneOptions::new()
+    ///     .all(true)
+    ///     .label_filter("test=1")
+    ///     .volumes(true)
+    ///     .commands();
+    /// assert_eq!(commands.len(), 2);
+    /// assert_eq!(commands[0].args, [
+    ///     "system",
+    ///     "prune",
+    ///     "--force",
+    ///     "--all",
+    ///     "--filter",
+    ///     "label=test=1"
+    /// ]);
+    /// assert_eq!(commands[1].args, [
+    ///     "volume",
+    ///     "prune",
+    ///     "--force",
+    ///     "--all",
+    ///     "--filter",
+    ///     "label=test=1"
+    /// ]);
+    ///
+    /// // volume pruning does not support `until`, and build cache pruning does
+    /// // not support labels
+    /// let commands = PruneOptions::new()
+    ///     .label_filter("test=1")
+    ///     .until(Duration::from_secs(3600))
+    ///     .volumes(true)
+    ///     .build_cache(true)
+    ///     .commands();
+    /// assert_eq!(commands.len(), 2);
+    /// assert_eq!(commands[1].args, [
+    ///     "builder",
+    ///     "prune",
+    ///     "--force",
+    ///     "--filter",
+    ///     "until=3600s"
+    /// ]);
+    /// ```
+    pub fn commands(&self) -> Vec<Command> {
+        let labels: Vec<String> = self
+            .label_filters
+            .iter()
+            .map(|l| format!("label={l}"))
+            .collect();
+        let until = self.until.map(|d| format!("until={}s", d.as_secs()));
+        let with_filters = |mut cmd: Command, include_labels: bool, include_until: bool| {
+            if include_labels {
+                for label in &labels {
+                    cmd = cmd.arg("--filter").arg(label);
+                }
+            }
+            if include_until {
+                if let Some(ref until) = until {
+                    cmd = cmd.arg("--filter").arg(until);
+                }
+            }
+            cmd
+        };
+
+        let mut res = vec![];
+        let mut system = docker_command("system prune --force");
+        if self.all {
+            system = system.arg("--all");
+        }
+        res.push(with_filters(system, true, true));
+        if self.volumes && self.until.is_none() {
+            let mut volume = docker_command("volume prune --force");
+            if self.all {
+                volume = volume.arg("--all");
+            }
+            res.push(with_filters(volume, true, false));
+        }
+        if self.build_cache {
+            let mut builder = docker_command("builder prune --force");
+            if self.all {
+                builder = builder.arg("--all");
+            }
+            res.push(with_filters(builder, false, true));
+        }
+        res
+    }
+}
+
+/// The result of [prune]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PruneReport {
+    /// The commands that were run (or would have been run if `dry_run`)
+    pub commands: Vec<String>,
+    /// The sum of the "Total reclaimed space" reported by each command, always
+    /// 0 if `dry_run`
+    pub reclaimed: u64,
+    /// If this was a [PruneOptions::dry_run], in which case nothing was
+    /// pruned
+    pub dry_run: bool,
+}
+
+/// Runs `docker system prune` and optionally `docker volume prune` and `docker
+/// builder prune` according to `options`
+pub async fn prune(options: &PruneOptions) -> Result<PruneReport> {
+    let mut res = PruneReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+    for command in options.commands() {
+        let command_string = command.get_unified_command();
+        res.commands.push(command_string.clone());
+        if options.dry_run {
+            continue
+        }
+        let comres = command
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                format!("prune(options: {options:?}) -> when running `{command_string}`")
+            })?;
+        comres.assert_success().stack_err_locationless(|| {
+            format!("prune(options: {options:?}) -> `{command_string}` failed")
+        })?;
+        let stdout = comres.stdout_as_utf8_lossy();
+        for line in stdout.lines() {
+            if let Some(size) = line.trim().strip_prefix("Total reclaimed space:") {
+                match parse_docker_size(size.trim()) {
+                    Ok(size) => res.reclaimed += size,
+                    Err(e) => {
+                        warn!("prune -> could not parse reclaimed space: {e:?}");
+                        emit_crate_event(|| CrateEvent::Other {
+                            message: format!("prune -> could not parse reclaimed space: {e:?}"),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Runs `docker pull` on `name_tag` if it is not present locally, see
+/// [pull_image]
+pub async fn pull_image_if_not_present(name_tag: &str) -> Result<()> {
+    pull_image(name_tag, false)
+        .await
+        .stack_err_locationless(|| "pull_image_if_not_present")
+}
+
+/// Runs `docker pull` on `name_tag`. Unless `always` is set, `docker image
+/// inspect` is run first and the pull only happens if it fails with a "No
+/// such image" error, any other failure is returned as an error.
+pub async fn pull_image(name_tag: &str, always: bool) -> Result<()> {
+    if !always {
+        let comres = docker_command("image inspect")
+            .arg(name_tag)
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                format!("pull_image(name_tag: {name_tag}) -> when running `docker image inspect`")
+            })?;
+        if comres.successful() {
+            return Ok(())
+        }
+        if !comres
+            .stderr_as_utf8_lossy()
+            .to_lowercase()
+            .contains("no such image")
+        {
+            return comres.assert_success().stack_err_locationless(|| {
+                format!("pull_image(name_tag: {name_tag}) -> `docker image inspect` failed")
+            })
+        }
+    }
+    let comres = docker_command("pull")
+        .arg(name_tag)
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| {
+            format!("pull_image(name_tag: {name_tag}) -> when running `docker pull`")
+        })?;
+    comres.assert_success().stack_err_locationless(|| {
+        format!("pull_image(name_tag: {name_tag}) -> `docker pull` failed")
+    })
+}
+
+/// The registry digest and ID of a local image, see [inspect_image_digest]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ImageDigest {
+    /// The image reference that was inspected
+    pub image: String,
+    /// The "sha256:..." digest of the first of the `RepoDigests`, locally
+    /// built images do not have one
+    pub repo_digest: Option<String>,
+    /// The "sha256:..." image ID
+    pub id: String,
+}
+
+impl ImageDigest {
+    /// The `repo_digest` if there is one, otherwise the `id`
+    pub fn digest(&self) -> &str {
+        self.repo_digest.as_deref().unwrap_or(&self.id)
+    }
+}
+
+/// Parses a "sha256:{64 hex digits}" digest, which may be prefixed by the
+/// repository as in the `RepoDigests` of `docker image inspect`
+/// ("repository@sha256:..."). Returns the lowercase "sha256:..." part.
+pub fn parse_digest(s: &str) -> Result<String> {
+    let digest = s.trim();
+    let digest = digest.rsplit_once('@').map_or(digest, |(_, digest)| digest);
+    let hex = digest.strip_prefix("sha256:").stack_err_locationless(|| {
+        format!("parse_digest -> \"{s}\" does not have the form \"sha256:...\"")
+    })?;
+    if (hex.len() != 64) || (!hex.bytes().all(|byte| byte.is_ascii_hexdigit())) {
+        return Err(Error::from_kind_locationless(format!(
+            "parse_digest -> \"{s}\" does not have 64 hex digits after \"sha256:\""
+        )))
+    }
+    Ok(format!("sha256:{}", hex.to_ascii_lowercase()))
+}
+
+/// Returns "repository@digest" for the `name_tag` with the tag (or digest)
+/// removed
+pub(crate) fn pinned_reference(name_tag: &str, digest: &str) -> String {
+    let repository = name_tag.split_once('@').map_or(name_tag, |(repo, _)| repo);
+    // a colon before the last slash is the port of the registry
+    let repository = match repository.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => repo,
+        _ => repository,
+    };
+    format!("{repository}@{digest}")
+}
+
+pub(crate) async fn inspect_image_digest_with(
+    command_middleware: Option<&CommandMiddleware>,
+    image: &str,
+) -> Result<Option<ImageDigest>> {
+    let command = docker_command("image inspect").arg(image);
+    let command = match command_middleware {
+        Some(middleware) => middleware.apply(command),
+        None => command,
+    };
+    let comres = command
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| {
+            format!("inspect_image_digest(image: {image}) -> when running `docker image inspect`")
+        })?;
+    if !comres.successful()
+        && comres
+            .stderr_as_utf8_lossy()
+            .to_lowercase()
+            .contains("no such image")
+    {
+        return Ok(None)
+    }
+    comres.assert_success().stack_err_locationless(|| {
+        format!("inspect_image_digest(image: {image}) -> `docker image inspect` failed")
+    })?;
+    let v: serde_json::Value = serde_json::from_str(comres.stdout_as_utf8().stack()?)
+        .stack_err_locationless(|| {
+            format!("inspect_image_digest(image: {image}) -> could not parse the JSON")
+        })?;
+    let v = stacked_get!(v[0]);
+    let id = stacked_get!(v["Id"]).as_str().stack()?.to_owned();
+    let repo_digest = match v.get("RepoDigests").and_then(|digests| digests.get(0)) {
+        Some(digest) => Some(parse_digest(digest.as_str().stack()?)?),
+        None => None,
+    };
+    Ok(Some(ImageDigest {
+        image: image.to_owned(),
+        repo_digest,
+        id,
+    }))
+}
+
+/// Runs `docker image inspect` on `image`, returning `None` if it is not
+/// present locally
+pub async fn inspect_image_digest(image: &str) -> Result<Option<ImageDigest>> {
+    inspect_image_digest_with(None, image).await
+}
+
+/// Returns the registry digest ("sha256:...") that `name_tag` resolves to,
+/// pulling it first if it is not present locally
+///
+/// # Errors
+///
+/// If the image has no registry digest, which is the case for images that were
+/// only built locally
+pub async fn resolve_digest(name_tag: &str) -> Result<String> {
+    let mut image = inspect_image_digest(name_tag)
+        .await
+        .stack_err_locationless(|| "resolve_digest")?;
+    if image.is_none() {
+        pull_image(name_tag, true)
+            .await
+            .stack_err_locationless(|| "resolve_digest")?;
+        image = inspect_image_digest(name_tag)
+            .await
+            .stack_err_locationless(|| "resolve_digest")?;
+    }
+    let image = image.stack_err_locationless(|| {
+        format!("resolve_digest(name_tag: {name_tag}) -> the image is missing after pulling")
+    })?;
+    image.repo_digest.stack_err_locationless(|| {
+        format!(
+            "resolve_digest(name_tag: {name_tag}) -> the image has no registry digest, it may \
+             have only been built locally"
+        )
+    })
+}
+
+/// Parses a lock file written by [format_lock_file], which is the subset of
+/// TOML with an `[images]` table of quoted container names to quoted digests
+pub fn parse_lock_file(s: &str) -> Result<BTreeMap<String, String>> {
+    let mut res = BTreeMap::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (line == "[images]") {
+            continue
+        }
+        let parse_line = || -> Option<(String, String)> {
+            let (key, value) = line.split_once('=')?;
+            let unquote = |s: &str| -> Option<String> {
+                let s = s.trim();
+                let s = s.strip_prefix('"')?.strip_suffix('"')?;
+                (!s.contains('"')).then(|| s.to_owned())
+            };
+            let key = key.trim();
+            let key = if key.starts_with('"') {
+                unquote(key)?
+            } else {
+                key.to_owned()
+            };
+            Some((key, unquote(value)?))
+        };
+        let (name, digest) = parse_line().stack_err_locationless(|| {
+            format!(
+                "parse_lock_file -> line {} is not `\"name\" = \"digest\"`",
+                i + 1
+            )
+        })?;
+        let digest = parse_digest(&digest)
+            .stack_err_locationless(|| format!("parse_lock_file -> line {}", i + 1))?;
+        res.insert(name, digest);
+    }
+    Ok(res)
+}
+
+/// Formats a lock file of container names to digests, see [parse_lock_file]
+pub fn format_lock_file(digests: &BTreeMap<String, String>) -> String {
+    let mut s = "# image digests pinned by super_orchestrator\n[images]\n".to_owned();
+    for (name, digest) in digests {
+        s += &format!("\"{name}\" = \"{digest}\"\n");
+    }
+    s
+}
+
+/// The container engine behind [docker_binary]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EngineFlavor {
+    Docker,
+    /// Podman, e.x. through the `podman-docker` compatibility package
+    Podman,
+}
+
+/// A feature of the container engine that can be checked with [require]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Capability {
+    /// BuildKit can be used by `docker build`, needed for `--secret`, `--ssh`,
+    /// and the local build cache
+    BuildKit,
+    /// `docker buildx` is installed
+    Buildx,
+    /// The daemon has experimental features enabled
+    Experimental,
+    /// `docker checkpoint` can be used, which needs experimental features and
+    /// CRIU
+    Checkpoint,
+    /// The engine runs rootless
+    Rootless,
+    /// The engine uses cgroup v2
+    CgroupV2,
+}
+
+/// What the container engine supports, see [capabilities]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineCapabilities {
+    pub flavor: EngineFlavor,
+    /// The version of the client
+    pub client_version: Option<String>,
+    /// The version of the engine
+    pub server_version: Option<String>,
+    /// The API version of the engine
+    pub api_version: Option<String>,
+    /// If BuildKit can be used by `docker build`
+    pub buildkit: bool,
+    /// The buildx version if `docker buildx version` succeeded
+    pub buildx_version: Option<String>,
+    /// If the daemon has experimental features enabled
+    pub experimental: bool,
+    /// If `criu --version` succeeded
+    pub criu: bool,
+    pub rootless: bool,
+    pub cgroup_v2: bool,
+}
+
+impl EngineCapabilities {
+    /// Parses the outputs of `docker version --format '{{json .}}'`,
+    /// `docker info --format '{{json .}}'`, and `docker buildx version` (if it
+    /// succeeded). This supports both Docker and Podman. `criu` is set to
+    /// false.
+    pub fn parse(
+        version_json: &str,
+        info_json: &str,
+        buildx_version: Option<&str>,
+    ) -> Result<Self> {
+        let version: serde_json::Value = serde_json::from_str(version_json)
+            .stack_err_locationless(|| "EngineCapabilities::parse -> parsing the version JSON")?;
+        let info: serde_json::Value = serde_json::from_str(info_json)
+            .stack_err_locationless(|| "EngineCapabilities::parse -> parsing the info JSON")?;
+        let get_str = |value: &serde_json::Value, pointer: &str| {
+            value
+                .pointer(pointer)
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_owned())
+        };
+        // Podman has a `host` section in its info and uses `APIVersion` instead of
+        // `ApiVersion`, and when it is behind a compatibility API it names itself in
+        // the platform or components
+        let mut names = vec![];
+        for section in ["/Client", "/Server"] {
+            names.extend(get_str(&version, &format!("{section}/Platform/Name")));
+            if let Some(components) = version
+                .pointer(&format!("{section}/Components"))
+                .and_then(|v| v.as_array())
+            {
+                names.extend(components.iter().filter_map(|c| get_str(c, "/Name")));
+            }
+        }
+        let flavor = if info.get("host").is_some()
+            || names
+                .iter()
+                .any(|name| name.to_lowercase().contains("podman"))
+        {
+            EngineFlavor::Podman
+        } else {
+            EngineFlavor::Docker
+        };
+        let client_version = get_str(&version, "/Client/Version");
+        let server_version = get_str(&version, "/Server/Version")
+            .or_else(|| get_str(&info, "/ServerVersion"))
+            .or_else(|| get_str(&info, "/version/Version"));
+        let api_version = get_str(&version, "/Server/ApiVersion")
+            .or_else(|| get_str(&version, "/Server/APIVersion"))
+            .or_else(|| get_str(&info, "/version/APIVersion"));
+        let buildx_version = buildx_version.and_then(|output| {
+            // e.x. "github.com/docker/buildx v0.12.1 30feaa1"
+            let mut words = output.split_whitespace();
+            if words.next()?.contains("buildx") {
+                Some(words.next()?.to_owned())
+            } else {
+                None
+            }
+        });
+        let (experimental, rootless, cgroup_v2) = match flavor {
+            EngineFlavor::Docker => {
+                let experimental = info.get("ExperimentalBuild").and_then(|v| v.as_bool());
+                let rootless = info
+                    .get("SecurityOptions")
+                    .and_then(|v| v.as_array())
+                    .is_some_and(|options| {
+                        options
+                            .iter()
+                            .any(|option| option.as_str() == Some("name=rootless"))
+                    });
+                let cgroup_v2 = get_str(&info, "/CgroupVersion").as_deref() == Some("2");
+                (experimental.unwrap_or(false), rootless, cgroup_v2)
+            }
+            EngineFlavor::Podman => {
+                let rootless = info
+                    .pointer("/host/security/rootless")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let cgroup_v2 = get_str(&info, "/host/cgroupVersion").as_deref() == Some("v2");
+                (false, rootless, cgroup_v2)
+            }
+        };
+        Ok(Self {
+            flavor,
+            client_version,
+            server_version,
+            api_version,
+            buildkit: (flavor == EngineFlavor::Docker) && buildx_version.is_some(),
+            buildx_version,
+            experimental,
+            criu: false,
+            rootless,
+            cgroup_v2,
+        })
+    }
+
+    /// Returns if `capability` is supported
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.require(capability).is_ok()
+    }
+
+    /// Returns an error describing what is needed if `capability` is not
+    /// supported
+    pub fn require(&self, capability: Capability) -> Result<()> {
+        let podman = self.flavor == EngineFlavor::Podman;
+        let missing = match capability {
+            Capability::BuildKit if podman => Some("Podman does not support BuildKit".to_owned()),
+            Capability::BuildKit if self.buildx_version.is_some() && !self.buildkit => {
+                Some("BuildKit is disabled by `DOCKER_BUILDKIT=0`".to_owned())
+            }
+            Capability::BuildKit | Capability::Buildx if self.buildx_version.is_none() => Some(
+                "buildx not found (`docker buildx version` failed), install the \
+                 docker-buildx-plugin package"
+                    .to_owned(),
+            ),
+            Capability::Checkpoint if podman => {
+                Some("Podman does not support `docker checkpoint`".to_owned())
+            }
+            Capability::Experimental | Capability::Checkpoint if !self.experimental => Some(
+                "the docker daemon does not have experimental features enabled (set \
+                 `\"experimental\": true` in the daemon.json)"
+                    .to_owned(),
+            ),
+            Capability::Checkpoint if !self.criu => {
+                Some("CRIU does not appear to be installed (`criu --version` failed)".to_owned())
+            }
+            Capability::Rootless if !self.rootless => {
+                Some("the engine is not running rootless".to_owned())
+            }
+            Capability::CgroupV2 if !self.cgroup_v2 => {
+                Some("the engine is not using cgroup v2".to_owned())
+            }
+            _ => None,
+        };
+        if let Some(missing) = missing {
+            Err(Error::from_kind_locationless(format!(
+                "EngineCapabilities::require(Capability::{capability:?}) -> UnsupportedError, \
+                 {missing}"
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+type CapabilitiesCache = Mutex<Vec<(OsString, &'static EngineCapabilities)>>;
+
+static CAPABILITIES: CapabilitiesCache = Mutex::new(vec![]);
+
+/// Returns the [EngineCapabilities] of the [docker_binary]. This is probed
+/// once per binary by running `docker version`, `docker info`,
+/// `docker buildx version`, and `criu --version`, and then cached (including
+/// whether `DOCKER_BUILDKIT=0` was set at that time). Errors are not cached.
+pub async fn capabilities() -> Result<&'static EngineCapabilities> {
+    let binary = docker_binary();
+    if let Some((_, cached)) = CAPABILITIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(b, _)| *b == binary)
+    {
+        return Ok(cached)
+    }
+    let probe = |args: &str| {
+        Command::new_os_str(&binary)
+            .args(args.split_whitespace())
+            .arg("--format")
+            .arg("{{json .}}")
+    };
+    // `docker version` fails if the daemon is unreachable but still prints the
+    // client
+    let comres = probe("version")
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| "capabilities -> when running `docker version`")?;
+    let version_json = comres.stdout_as_utf8_lossy().into_owned();
+    let comres = probe("info")
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| "capabilities -> when running `docker info`")?;
+    comres
+        .assert_success()
+        .stack_err_locationless(|| "capabilities -> `docker info` failed")?;
+    let info_json = comres.stdout_as_utf8_lossy().into_owned();
+    let buildx = Command::new_os_str(&binary)
+        .args(["buildx", "version"])
+        .run_to_completion()
+        .await
+        .ok()
+        .filter(|comres| comres.successful())
+        .map(|comres| comres.stdout_as_utf8_lossy().into_owned());
+    let mut capabilities = EngineCapabilities::parse(&version_json, &info_json, buildx.as_deref())
+        .stack_err_locationless(|| "capabilities")?;
+    if std::env::var("DOCKER_BUILDKIT").is_ok_and(|v| v.trim() == "0") {
+        capabilities.buildkit = false;
+    }
+    let criu = Command::new("criu --version").run_to_completion().await;
+    capabilities.criu = matches!(criu, Ok(ref comres) if comres.successful());
+    let mut cache = CAPABILITIES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, cached)) = cache.iter().find(|(b, _)| *b == binary) {
+        return Ok(cached)
+    }
+    let capabilities: &'static EngineCapabilities = Box::leak(Box::new(capabilities));
+    cache.push((binary, capabilities));
+    Ok(capabilities)
+}
+
+/// Returns an error describing what is needed if the [docker_binary] does not
+/// support `capability`, see [capabilities]
+pub async fn require(capability: Capability) -> Result<()> {
+    capabilities()
+        .await
+        .stack_err_locationless(|| "require")?
+        .require(capability)
+}
+
+/// Checks that `docker checkpoint` can be used, which requires the docker
+/// daemon to have experimental features enabled and CRIU to be installed.
+/// Returns an error describing what is missing otherwise.
+pub async fn check_checkpoint_support() -> Result<()> {
+    require(Capability::Checkpoint)
+        .await
+        .stack_err_locationless(|| "check_checkpoint_support")
+}
+
+/// Checks that BuildKit can be used by `docker build`, which is needed for
+/// `--secret` and `--ssh`. Returns an error if `DOCKER_BUILDKIT=0` disables
+/// it or if `docker buildx version` fails.
+pub async fn check_buildkit_support() -> Result<()> {
+    require(Capability::BuildKit)
+        .await
+        .stack_err_locationless(|| "check_buildkit_support")
+}
+
 /// Intended to be called from the main() of a standalone binary, or run from
 /// this repo `cargo r --example auto_exec -- --container-name main`
 ///
@@ -80,10 +1165,7 @@ where
         if ctrlc_issued_reset() {
             break
         }
-        let comres = Command::new("docker ps")
-            .run_to_completion()
-            .await
-            .stack()?;
+        let comres = docker_command("ps").run_to_completion().await.stack()?;
         comres.assert_success()?;
         let mut name_id = None;
         for line in comres.stdout_as_utf8().stack()?.lines().skip(1) {
@@ -95,6 +1177,9 @@ where
                     let id = &line[..line.find(' ').stack()?];
                     if name_id.is_some() {
                         warn!("Found multiple containers with same {name} prefix");
+                        emit_crate_event(|| CrateEvent::Other {
+                            message: format!("Found multiple containers with same {name} prefix"),
+                        });
                         name_id = None;
                         break
                     }
@@ -112,7 +1197,7 @@ where
             total_args.push(id.to_string());
             total_args.extend(container_args.clone());
             docker_exec(total_args).await.stack()?;
-            let _ = sh(["docker rm -f", &id]).await;
+            let _ = docker_command("rm -f").arg(&id).run_to_completion().await;
             info!("\nTerminated container {id}\n");
         }
         sleep(STD_DELAY).await;
@@ -125,7 +1210,7 @@ where
     I: IntoIterator<Item = S>,
     S: AsRef<str>,
 {
-    let mut runner = Command::new("docker exec")
+    let mut runner = docker_command("exec")
         .args(args.into_iter().map(|s| s.as_ref().to_string()))
         .debug(true)
         .run_with_stdin(Stdio::inherit())
@@ -1,24 +1,55 @@
 use std::{
     collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    fmt::{self, Debug, Display},
     mem,
-    net::IpAddr,
-    sync::atomic::Ordering,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{atomic::Ordering, Arc},
     time::Duration,
 };
 
-use stacked_errors::{Error, Result, StackableErr};
-use tokio::time::{sleep, Instant};
-use tracing::{debug, warn};
+use bstr::ByteSlice;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use stacked_errors::{Error, ErrorKind, Result, StackableErr};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    time::{sleep, sleep_until, timeout, Instant},
+};
+use tracing::{debug, info_span, warn, Instrument, Span};
 use uuid::Uuid;
 
 use crate::{
-    docker::{Container, Dockerfile},
-    docker_helpers::wait_get_ip_addr,
-    Command, CommandResult, CommandRunner, FileOptions, CTRLC_ISSUED,
+    acquire_dir_path, deadline_after,
+    docker::{
+        ArgvTooLongError, BuildProgressCallback, Container, ContainerHooks, CreatedResources,
+        Dockerfile, HookContext, HookFailurePolicy, HookPhase, ImageNotFoundError,
+        NameConflictError, NetworkMode, NotOnNetworkError, PullPolicy, ReadinessProbe,
+        ResourceConstraints, ResourceKind, ResourceStatus, TeardownFailure, TeardownReport,
+    },
+    docker_helpers::{
+        check_buildkit_support, check_checkpoint_support, docker_binary, docker_command,
+        extract_from_image, format_lock_file, inspect_image_digest_with, ip_addr_from_inspect,
+        parse_docker_diff, parse_lock_file, pinned_reference, prune, DaemonUnreachableError,
+        FsChange, ImageDigest, PruneOptions, PruneReport,
+    },
+    error_output_limit, is_oom_kill, is_signal_exit,
+    middleware::CommandMiddleware,
+    misc::{clone_error, emit_crate_event, strictness_check, strictness_violation},
+    paths::resolve,
+    port_forward::ForwardStop,
+    shutdown_signal_received, signal_from_exit_code, stacked_get, strictness,
+    templates::{expand_templates, ip_dependencies, placeholders, Placeholder},
+    topology::{TOPOLOGY_ENV_VAR, TOPOLOGY_FILE},
+    try_parse_exit_code_from_stderr,
+    validation::{start_order_findings, static_findings, template_findings, ValidationReport},
+    wait_for_ok, Backoff, BufferPolicy, BuildProgress, BuildStats, ByteSize, Command,
+    CommandResult, CommandRunner, CrateEvent, FileOptions, ReadOrWrite, Strictness, CTRLC_ISSUED,
 };
 
-// TODO reintroduce UUID capability
-
 #[derive(Debug, Default)]
 #[allow(clippy::large_enum_variant)]
 enum RunState {
@@ -28,9 +59,34 @@ enum RunState {
     PostActive(Result<CommandResult>),
 }
 
+/// An image planned by `run`
+#[derive(Debug, Clone)]
+struct PlannedBuild {
+    image: String,
+    built: bool,
+}
+
+/// The result of checking containers on prepared copies, see
+/// `ContainerNetwork::check_names`
+#[derive(Debug)]
+struct CheckedNames {
+    report: ValidationReport,
+    // the error of the first failed check
+    first_error: Option<Error>,
+    // the network paths rebased onto the `base_dir`
+    log_dir: String,
+    dockerfile_write_dir: Option<String>,
+    build_cache_dir: Option<String>,
+    lock_file: Option<String>,
+    // the prepared copies of the containers that passed their checks
+    passed: Vec<(String, Container)>,
+    // crash artifacts directories that were created and need to be recorded as resources
+    new_crash_dirs: Vec<String>,
+}
+
 #[derive(Debug)]
-struct ContainerState {
-    container: Container,
+pub(crate) struct ContainerState {
+    pub(crate) container: Container,
     run_state: RunState,
     // NOTE: logically, only the `Active` state should have actual containers that should be
     // removed before program exit, but in the run function there is a loop that first creates all
@@ -39,6 +95,32 @@ struct ContainerState {
     // variable and assume that panicking is happening or the state is cleaned up before giving
     // back to a user.
     active_container_id: Option<String>,
+    // the final `container_name` that the container with `active_container_id` was created with
+    active_container_name: Option<String>,
+    // host paths from `Container::extract_after_build`
+    extracted_paths: Vec<PathBuf>,
+    // from the last build of the image of the container
+    build_stats: Option<BuildStats>,
+    // set when the container transitions to `RunState::Active`
+    start_time: Option<Instant>,
+    // host paths from `Container::capture_crash_artifacts`
+    crash_artifacts: Vec<PathBuf>,
+    // the name of the checkpoint if the container is suspended by `checkpoint_all`
+    checkpoint: Option<String>,
+    // set when the container passes the validation in `run` and its prepared copy is stored, and
+    // reset when it is modified
+    validated: bool,
+    // the result of every exit of a `Container::supervise` container
+    supervise_history: Vec<CommandResult>,
+    // when each restart of a supervised container happened
+    restart_times: Vec<Instant>,
+    // when a supervised container that exited is due to be restarted, a later pass of a wait
+    // loop does the restart so that the backoff does not block the other containers
+    restart_at: Option<Instant>,
+    // copied from the network when the container is started
+    result_retention: ResultRetention,
+    // the stdout and stderr byte counts of the last result, unless discarded
+    output_bytes: Option<(u64, u64)>,
     already_tried_drop: bool,
 }
 
@@ -50,53 +132,483 @@ impl Drop for ContainerState {
         }
         self.already_tried_drop = true;
         if let Some(id) = self.active_container_id.take() {
-            let _ = std::process::Command::new("docker")
-                .arg("rm")
-                .arg("-f")
-                .arg(id)
-                .output();
+            run_drop_teardown_command(
+                std::process::Command::new(docker_binary())
+                    .arg("rm")
+                    .arg("-f")
+                    .arg(id),
+            );
+        }
+    }
+}
+
+/// The default [ContainerNetwork::wait_backoff]
+pub const DEFAULT_NETWORK_WAIT_BACKOFF: Backoff = Backoff::constant(Duration::from_millis(256));
+
+/// How often [ContainerNetwork::wait_for_label] polls the labels
+const LABEL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often [ContainerNetwork::json_lines_stream] polls the stdout record
+const JSON_LINES_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Caches `docker inspect` results for all the active containers of a
+/// `ContainerNetwork`, so that many queries in a polling round only need one
+/// `docker inspect id0 id1 ...` subprocess
+#[derive(Debug, Default)]
+struct InspectCache {
+    // when the snapshot was taken, and the inspect JSON of each container by ID
+    snapshot: Option<(Instant, BTreeMap<String, serde_json::Value>)>,
+}
+
+impl InspectCache {
+    /// Returns the inspect JSON for the container with `id`, refreshing the
+    /// snapshot with all of `ids` if it is older than `max_staleness`, was
+    /// taken before `fresh_since`, or does not have `id`
+    // `Option::is_none_or` needs Rust 1.82, which is above the MSRV
+    #[allow(clippy::unnecessary_map_or)]
+    async fn get(
+        &mut self,
+        ids: &[String],
+        id: &str,
+        max_staleness: Duration,
+        fresh_since: Option<Instant>,
+        command_middleware: Option<&CommandMiddleware>,
+    ) -> Result<serde_json::Value> {
+        if let Some((time, ref map)) = self.snapshot {
+            if (time.elapsed() <= max_staleness) && fresh_since.map_or(true, |t| time >= t) {
+                if let Some(v) = map.get(id) {
+                    return Ok(v.clone())
+                }
+            }
+        }
+        // we don't check the status, because if any one of the containers no longer
+        // exists the command fails but still outputs the others
+        let comres = apply_middleware(command_middleware, docker_command("inspect").args(ids))
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| "InspectCache -> could not run `docker inspect`")?;
+        let v: Vec<serde_json::Value> = serde_json::from_str(comres.stdout_as_utf8().stack()?)
+            .stack_err_locationless(|| {
+                format!("InspectCache -> could not parse `docker inspect` output of {comres:?}")
+            })?;
+        let mut map = BTreeMap::new();
+        for container in v {
+            if let Some(id) = container.get("Id").and_then(|id| id.as_str()) {
+                map.insert(id.to_owned(), container);
+            }
+        }
+        let res = map.get(id).cloned();
+        self.snapshot = Some((Instant::now(), map));
+        res.stack_err_locationless(|| {
+            format!(
+                "InspectCache -> `docker inspect` did not return the container with ID {id}, \
+                 stderr: {}",
+                comres.stderr_as_utf8_lossy()
+            )
+        })
+    }
+}
+
+/// What happened to a container when it was terminated, see
+/// [ContainerNetwork::try_terminate]. Cloning a `RunnerError` keeps the error
+/// stack and locations, but boxed errors in it are replaced by their `Debug`
+/// output.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum TerminateOutcome {
+    /// The container was active and was terminated with this result
+    Terminated(CommandResult),
+    /// The container was in the network but was not active
+    WasNotActive,
+    /// The name was not found in the network
+    NotFound,
+    /// The container was active, but its `CommandRunner` errored while
+    /// terminating
+    RunnerError(Error),
+}
+
+impl Clone for TerminateOutcome {
+    fn clone(&self) -> Self {
+        match self {
+            TerminateOutcome::Terminated(comres) => TerminateOutcome::Terminated(comres.clone()),
+            TerminateOutcome::WasNotActive => TerminateOutcome::WasNotActive,
+            TerminateOutcome::NotFound => TerminateOutcome::NotFound,
+            TerminateOutcome::RunnerError(e) => TerminateOutcome::RunnerError(clone_error(e)),
+        }
+    }
+}
+
+/// Credentials for a private registry, see [ContainerNetwork::pull_secrets].
+/// The `Debug` impl does not show the `password`.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PullSecret {
+    /// The registry host as it appears in image names (e.x.
+    /// "registry.example.com:5000"), or "docker.io" for Docker Hub
+    pub registry: String,
+    pub username: String,
+    /// Passed to `docker login` with `--password-stdin`
+    pub password: String,
+}
+
+impl fmt::Debug for PullSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PullSecret")
+            .field("registry", &self.registry)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Returns the registry of an image name, "docker.io" if there is no registry
+/// component
+fn registry_of_name_tag(name_tag: &str) -> &str {
+    match name_tag.split_once('/') {
+        Some((first, _))
+            if first.contains('.') || first.contains(':') || (first == "localhost") =>
+        {
+            first
+        }
+        _ => "docker.io",
+    }
+}
+
+/// The phase of [ContainerNetwork::run] that an error happened in, included
+/// in the error message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunPhase {
+    /// Argument verification, directory acquisition, and
+    /// [Container::precheck]
+    Precheck,
+    /// Logging into the registry with this name, see
+    /// [ContainerNetwork::pull_secrets]
+    Login(String),
+    /// Checking that the `Dockerfile::NameTag` image of the container with
+    /// this name exists, see [ContainerNetwork::pull_before_create]
+    ImagePreflight(String),
+    /// Building, pulling, or extracting from the image of the container with
+    /// this name
+    Build(String),
+    /// Creating the docker network
+    NetworkCreate,
+    /// Creating the container with this name
+    Create(String),
+    /// Starting the container with this name
+    Start(String),
+    /// Waiting for the containers of this start group to be ready, see
+    /// [ContainerNetwork::start_group_ready_timeout]
+    StartGroupReady(u32),
+}
+
+impl Display for RunPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunPhase::Precheck => f.write_str("Precheck"),
+            RunPhase::Login(registry) => write!(f, "Login(\"{registry}\")"),
+            RunPhase::ImagePreflight(name) => write!(f, "ImagePreflight(\"{name}\")"),
+            RunPhase::Build(name) => write!(f, "Build(\"{name}\")"),
+            RunPhase::NetworkCreate => f.write_str("NetworkCreate"),
+            RunPhase::Create(name) => write!(f, "Create(\"{name}\")"),
+            RunPhase::Start(name) => write!(f, "Start(\"{name}\")"),
+            RunPhase::StartGroupReady(group) => write!(f, "StartGroupReady({group})"),
+        }
+    }
+}
+
+/// What a [ContainerNetwork] keeps of the [CommandResult] of each container
+/// that finishes, see [ContainerNetwork::result_retention]
+#[derive(
+    Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum ResultRetention {
+    /// Everything is kept
+    #[default]
+    Full,
+    /// The exit status and the byte counts of the output (see
+    /// [ContainerNetwork::output_bytes]) are kept, but the recorded output is
+    /// dropped
+    StatusOnly,
+    /// Only the exit status and what is needed to find the log files are
+    /// kept, the recorded output, byte counts, and the rest of the `Command`
+    /// are dropped
+    Discard,
+}
+
+/// The result of [ContainerNetwork::checkpoint_all]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckpointReport {
+    pub checkpoint_name: String,
+    /// The containers that were checkpointed and are now suspended
+    pub checkpointed: Vec<String>,
+    /// Active containers that were not checkpointed because they are not
+    /// `checkpointable`, these continue running
+    pub skipped: Vec<String>,
+}
+
+/// The status of a container in a [NetworkStateSnapshot]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ContainerStatus {
+    /// The container has not been run
+    Inactive,
+    /// The container is running
+    Active,
+    /// The container ran and its result has been collected
+    PostActive,
+    /// The container is suspended by [ContainerNetwork::checkpoint_all]
+    Suspended,
+}
+
+/// The state of a single container in a [NetworkStateSnapshot]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerSnapshot {
+    pub status: ContainerStatus,
+    /// The IP address of an active container, `None` if the container is not
+    /// active or no address has been assigned yet
+    pub ip_addr: Option<IpAddr>,
+}
+
+/// The state of all containers of a network at one point in time, see
+/// [ContainerNetwork::take_snapshot]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkStateSnapshot {
+    pub containers: BTreeMap<String, ContainerSnapshot>,
+}
+
+/// The result of [ContainerNetwork::stream_to_file] or
+/// [ContainerNetwork::stream_from_file]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamTransfer {
+    /// The number of bytes transferred
+    pub bytes: u64,
+    /// The lowercase hex SHA-256 of the transferred bytes, computed while
+    /// streaming
+    pub sha256: String,
+}
+
+// run with `sh -c STREAM_TO_FILE_SCRIPT sh dest mode`, so that the path does
+// not need escaping
+const STREAM_TO_FILE_SCRIPT: &str = "cat > \"$1\" && chmod \"$2\" \"$1\" && wc -c < \"$1\"";
+
+/// The differences between two [NetworkStateSnapshot]s, see
+/// [ContainerNetwork::diff]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkDiff {
+    /// Containers that became active
+    pub started: Vec<String>,
+    /// Containers that stopped being active
+    pub stopped: Vec<String>,
+    /// Containers that were active in both snapshots but with a different IP
+    /// address, along with the before and after addresses
+    pub ip_changed: Vec<(String, Option<IpAddr>, Option<IpAddr>)>,
+}
+
+impl NetworkDiff {
+    /// Returns if no differences were found
+    pub fn is_empty(&self) -> bool {
+        self.started.is_empty() && self.stopped.is_empty() && self.ip_changed.is_empty()
+    }
+}
+
+/// Returns the `container_name` and `host_name` that a [ContainerNetwork] with
+/// `uuid` creates `container` with, which are the fields of `container` with
+/// "_{uuid}" appended if [Container::uuid_in_container_name] or
+/// [Container::uuid_in_host_name] are set respectively. This is the only place
+/// that the final names are derived.
+pub fn final_names(container: &Container, uuid: Uuid) -> (String, String) {
+    let with_uuid = |name: &str, uuid_in_name: bool| {
+        if uuid_in_name {
+            format!("{name}_{uuid}")
+        } else {
+            name.to_owned()
+        }
+    };
+    (
+        with_uuid(&container.container_name, container.uuid_in_container_name),
+        with_uuid(&container.host_name, container.uuid_in_host_name),
+    )
+}
+
+/// If the stack of `e` has a boxed `E`
+fn has_boxed_error<E: std::error::Error + 'static>(e: &Error) -> bool {
+    e.stack.iter().any(|(kind, _)| match kind {
+        ErrorKind::BoxedError(e) => e.downcast_ref::<E>().is_some(),
+        _ => false,
+    })
+}
+
+/// If a failure of [ContainerNetwork::run] in `phase` could succeed when
+/// retried, see [ContainerNetwork::run_all_with_retries]
+fn is_retryable_failure(phase: &RunPhase, e: &Error) -> bool {
+    // these fail the same way every time
+    if has_boxed_error::<ArgvTooLongError>(e)
+        || has_boxed_error::<NameConflictError>(e)
+        || has_boxed_error::<ImageNotFoundError>(e)
+        || has_boxed_error::<NotOnNetworkError>(e)
+    {
+        return false
+    }
+    match phase {
+        // these only depend on the environment if the docker daemon could not be reached
+        RunPhase::Precheck
+        | RunPhase::ImagePreflight(_)
+        | RunPhase::Build(_)
+        | RunPhase::StartGroupReady(_) => has_boxed_error::<DaemonUnreachableError>(e),
+        RunPhase::Login(_) | RunPhase::NetworkCreate | RunPhase::Create(_) | RunPhase::Start(_) => {
+            true
+        }
+    }
+}
+
+/// Returns the stdout and stderr log files of `container` if it has `log` set
+fn container_log_files(
+    container: &Container,
+    log_dir: &str,
+    append: bool,
+) -> (Option<FileOptions>, Option<FileOptions>) {
+    if !container.log {
+        return (None, None)
+    }
+    let name = &container.name;
+    let with_append = |mut file_options: FileOptions| {
+        if let ReadOrWrite::Write(ref mut options) = file_options.options {
+            options.append |= append;
+        }
+        file_options
+    };
+    (
+        Some(with_append(container.stdout_log.clone().unwrap_or_else(
+            || FileOptions::write2(log_dir, format!("{name}_stdout.log")),
+        ))),
+        Some(with_append(container.stderr_log.clone().unwrap_or_else(
+            || FileOptions::write2(log_dir, format!("{name}_stderr.log")),
+        ))),
+    )
+}
+
+/// Returns the full stdout or stderr of `comres`, or if the `result_retention`
+/// dropped it, the last [error_output_limit] bytes of the first log file of
+/// the stream that can be read
+fn retained_or_logged(
+    comres: &CommandResult,
+    result_retention: ResultRetention,
+    stderr: bool,
+) -> String {
+    let full = if stderr {
+        comres.full_stderr()
+    } else {
+        comres.full_stdout()
+    };
+    let mut output = full.unwrap_or_else(|_| {
+        if stderr {
+            comres.stderr.clone()
+        } else {
+            comres.stdout.clone()
+        }
+    });
+    if output.is_empty() && (result_retention != ResultRetention::Full) {
+        let logs = if stderr {
+            &comres.command.stderr_logs
+        } else {
+            &comres.command.stdout_logs
+        };
+        if let Some(log) = logs.iter().find_map(|log| std::fs::read(&log.path).ok()) {
+            let start = log.len().saturating_sub(error_output_limit());
+            output = log[start..].to_vec();
         }
     }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+fn apply_middleware(command_middleware: Option<&CommandMiddleware>, command: Command) -> Command {
+    match command_middleware {
+        Some(command_middleware) => command_middleware.apply(command),
+        None => command,
+    }
+}
+
+/// The span that the output of a container with `DebugOutput::Tracing` is
+/// emitted in
+fn container_span(name: &str, network_name: &str) -> Span {
+    info_span!(
+        "container",
+        container.name = name,
+        network.name = network_name
+    )
+}
+
+/// What a container was when it was removed, see
+/// [ContainerNetwork::try_remove_container]
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum RemovedContainer {
+    /// The container ran and produced this result
+    Ran(CommandResult),
+    /// The container ran but its handling produced an error
+    RunnerError(Error),
+    /// The container was registered but never ran
+    NeverRan,
 }
 
 impl ContainerState {
-    // returns if there was an error from a `CommandRunner`.
-    #[must_use]
-    pub async fn terminate(&mut self) -> bool {
-        if let Some(id) = self.active_container_id.take() {
-            let _ = Command::new("docker rm -f")
-                .arg(id)
-                .run_to_completion()
-                .await;
+    // This is cancel safe, the ID is only cleared after the container is removed
+    // and the runner is terminated in place, so that this can be called again (or
+    // `Drop` can clean up) if the future is dropped
+    pub async fn terminate_with_outcome(
+        &mut self,
+        resources: &mut CreatedResources,
+        report: &mut TeardownReport,
+    ) -> TerminateOutcome {
+        self.checkpoint = None;
+        self.restart_at = None;
+        if let Some(ref id) = self.active_container_id {
+            let container_name = self
+                .active_container_name
+                .as_ref()
+                .unwrap_or(&self.container.container_name);
+            // `--rm` containers are removed by docker when they exit
+            let status = match run_teardown_command(
+                self.container
+                    .apply_command_middleware(docker_command("rm -f").arg(id)),
+                ResourceKind::Container,
+                container_name,
+                report,
+            )
+            .await
+            {
+                Ok(()) => ResourceStatus::Removed,
+                Err(stderr) => ResourceStatus::RemovalError(stderr),
+            };
+            resources.mark(ResourceKind::Container, container_name, status);
+            self.active_container_id = None;
+            self.active_container_name = None;
         }
-        let state = mem::take(&mut self.run_state);
-        match state {
-            RunState::PreActive => false,
-            RunState::Active(mut runner) => match runner.terminate().await {
+        let res = match self.run_state {
+            RunState::PreActive | RunState::PostActive(_) => return TerminateOutcome::WasNotActive,
+            RunState::Active(ref mut runner) => match runner.terminate().await {
                 Ok(()) => {
                     if let Some(comres) = runner.take_command_result() {
-                        let err = !comres.successful();
-                        self.run_state = RunState::PostActive(Ok(comres));
-                        err
+                        Ok(comres)
                     } else {
-                        self.run_state = RunState::PostActive(Err(Error::from_kind_locationless(
+                        Err(Error::from_kind_locationless(
                             "ContainerNetwork -> when terminating a `CommandRunner` attached to a \
                              container, did not find a command result for some reason",
-                        )));
-                        true
+                        ))
                     }
                 }
-                Err(e) => {
-                    self.run_state = RunState::PostActive(Err(e.add_kind_locationless(
-                        "ContainerNetwork -> when terminating a `CommandRunner` attached to a \
-                         container, encountered an unexpected error",
-                    )));
-                    true
-                }
+                Err(e) => Err(e.add_kind_locationless(
+                    "ContainerNetwork -> when terminating a `CommandRunner` attached to a \
+                     container, encountered an unexpected error",
+                )),
             },
-            RunState::PostActive(x) => {
-                self.run_state = RunState::PostActive(x);
-                false
+        };
+        match res {
+            Ok(comres) => {
+                self.run_state = RunState::PostActive(Ok(self.retained(comres.clone())));
+                TerminateOutcome::Terminated(comres)
+            }
+            Err(e) => {
+                self.run_state = RunState::PostActive(Err(clone_error(&e)));
+                TerminateOutcome::RunnerError(e)
             }
         }
     }
@@ -106,21 +618,238 @@ impl ContainerState {
             container,
             run_state: RunState::PreActive,
             active_container_id: None,
+            active_container_name: None,
+            extracted_paths: vec![],
+            build_stats: None,
+            start_time: None,
+            crash_artifacts: vec![],
+            checkpoint: None,
+            validated: false,
+            supervise_history: vec![],
+            restart_times: vec![],
+            restart_at: None,
+            result_retention: ResultRetention::Full,
+            output_bytes: None,
             already_tried_drop: false,
         }
     }
 
+    /// Drops what the `result_retention` does not keep from `comres`
+    pub(crate) fn retained(&mut self, mut comres: CommandResult) -> CommandResult {
+        let bytes = (comres.stdout.len() as u64, comres.stderr.len() as u64);
+        match self.result_retention {
+            ResultRetention::Full => self.output_bytes = Some(bytes),
+            ResultRetention::StatusOnly => {
+                self.output_bytes = Some(bytes);
+                comres.stdout = vec![];
+                comres.stderr = vec![];
+            }
+            ResultRetention::Discard => {
+                self.output_bytes = None;
+                let command = mem::take(&mut comres.command);
+                comres.command = Command {
+                    program: command.program,
+                    stdout_logs: command.stdout_logs,
+                    stderr_logs: command.stderr_logs,
+                    ..Default::default()
+                };
+                comres.stdout = vec![];
+                comres.stderr = vec![];
+            }
+        }
+        comres
+    }
+
     pub fn container(&self) -> &Container {
         &self.container
     }
 
     pub fn container_mut(&mut self) -> &mut Container {
+        self.validated = false;
         &mut self.container
     }
 
     pub fn is_active(&self) -> bool {
         matches!(self.run_state, RunState::Active(_))
     }
+
+    pub fn status(&self) -> ContainerStatus {
+        match self.run_state {
+            _ if self.checkpoint.is_some() => ContainerStatus::Suspended,
+            RunState::PreActive => ContainerStatus::Inactive,
+            RunState::Active(_) => ContainerStatus::Active,
+            RunState::PostActive(_) => ContainerStatus::PostActive,
+        }
+    }
+
+    /// If the container has a `crash_artifacts_dir`, records the paths in it.
+    /// This is best-effort and only warns on failure.
+    pub async fn collect_crash_artifacts(&mut self) {
+        let Some(ref crash_artifacts_dir) = self.container.crash_artifacts_dir else {
+            return
+        };
+        let mut read_dir = match tokio::fs::read_dir(crash_artifacts_dir).await {
+            Ok(read_dir) => read_dir,
+            Err(e) => {
+                warn!(
+                    "ContainerNetwork -> could not read the crash artifacts directory \
+                     {crash_artifacts_dir:?}: {e}"
+                );
+                emit_crate_event(|| CrateEvent::CrashArtifactsUnreadable {
+                    name: self.container.name.clone(),
+                    error: e.to_string(),
+                });
+                return
+            }
+        };
+        self.crash_artifacts.clear();
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            self.crash_artifacts.push(entry.path());
+        }
+        self.crash_artifacts.sort();
+        warn!(
+            "container \"{}\" crashed, crash artifacts: {:?}",
+            self.container.name, self.crash_artifacts
+        );
+        emit_crate_event(|| CrateEvent::ContainerCrashed {
+            name: self.container.name.clone(),
+            crash_artifacts: self.crash_artifacts.clone(),
+        });
+    }
+}
+
+/// Returns the exit code if `comres` indicates that the process was ended by a
+/// signal. Docker reports this as an exit code of 128 plus the signal number.
+fn crash_exit_code(comres: &CommandResult) -> Option<i32> {
+    let code = comres.status.as_ref()?.code()?;
+    (code > 128).then_some(code)
+}
+
+/// How many times a teardown command that really failed is retried
+const TEARDOWN_RETRIES: u32 = 2;
+
+/// The delay before retrying a teardown command
+const TEARDOWN_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// If the stderr of a teardown command says that the resource is already gone,
+/// which is expected for races such as `--rm` containers removing themselves
+fn is_not_found(stderr: &str) -> bool {
+    stderr.contains("No such container")
+        || stderr.contains("No such network")
+        || stderr.contains("not found")
+        || stderr.contains("is not connected")
+}
+
+/// Runs the teardown `command`, retrying failures that are not
+/// [is_not_found] ones. If it still fails, the failure is recorded in `report`
+/// (and logged) with `kind` and `name`, and the stderr is returned.
+async fn run_teardown_command(
+    command: Command,
+    kind: ResourceKind,
+    name: &str,
+    report: &mut TeardownReport,
+) -> std::result::Result<(), String> {
+    let mut attempts = 0;
+    let stderr = loop {
+        attempts += 1;
+        let stderr = match command.clone().run_to_completion().await {
+            Ok(comres) => {
+                let stderr = comres.stderr_as_utf8_lossy();
+                if comres.successful() || is_not_found(&stderr) {
+                    return Ok(())
+                }
+                stderr.trim().to_owned()
+            }
+            Err(e) => format!("{e:?}"),
+        };
+        if attempts > TEARDOWN_RETRIES {
+            break stderr
+        }
+        sleep(TEARDOWN_RETRY_DELAY).await;
+    };
+    let failure = TeardownFailure {
+        kind,
+        name: name.to_owned(),
+        command: command.display_compact().to_string(),
+        stderr: stderr.clone(),
+        attempts,
+    };
+    warn!("ContainerNetwork -> teardown command failed: {failure:?}");
+    emit_crate_event(|| CrateEvent::TeardownFailed {
+        name: name.to_owned(),
+        command: failure.command.clone(),
+        stderr: stderr.clone(),
+    });
+    report.failures.push(failure);
+    Err(stderr)
+}
+
+/// Runs `command` from a `Drop` impl, logging its stderr if it fails for a
+/// reason other than the resource already being gone
+fn run_drop_teardown_command(command: &mut std::process::Command) {
+    match command.output() {
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if (!output.status.success()) && (!is_not_found(&stderr)) {
+                warn!(
+                    "ContainerNetwork -> teardown command {command:?} failed while dropping, \
+                     stderr: {}",
+                    stderr.trim()
+                );
+            }
+        }
+        Err(e) => warn!("ContainerNetwork -> could not run {command:?} while dropping: {e}"),
+    }
+}
+
+/// Reads the lock file at `path` if it is set and exists
+async fn read_lock_file(path: Option<&str>) -> Result<BTreeMap<String, String>> {
+    let Some(path) = path else {
+        return Ok(BTreeMap::new())
+    };
+    match tokio::fs::read_to_string(path).await {
+        Ok(s) => parse_lock_file(&s).stack_err_locationless(|| {
+            format!("ContainerNetwork::run -> when reading the lock file {path:?}")
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(Error::from_kind_locationless(format!(
+            "ContainerNetwork::run -> could not read the lock file {path:?}: {e}"
+        ))),
+    }
+}
+
+/// Sends an HTTP/1.1 `GET` of `path` to `addr` and returns the status code of
+/// the response
+async fn http_get_status(addr: SocketAddr, path: &str) -> Result<u16> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .stack_err_locationless(|| format!("could not connect to {addr}"))?;
+    stream
+        .write_all(
+            format!("GET {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n").as_bytes(),
+        )
+        .await
+        .stack_err_locationless(|| format!("could not send the request to {addr}"))?;
+    // only the status line is needed
+    let mut response = vec![];
+    let mut buf = [0u8; 256];
+    while !response.contains(&b'\n') {
+        let n = stream
+            .read(&mut buf)
+            .await
+            .stack_err_locationless(|| format!("could not read the response from {addr}"))?;
+        if n == 0 {
+            break
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    status_line
+        .strip_prefix("HTTP/")
+        .and_then(|rest| rest.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .stack_err_locationless(|| format!("invalid HTTP status line {status_line:?} from {addr}"))
 }
 
 /// A controlled network of containers.
@@ -145,16 +874,107 @@ pub struct ContainerNetwork {
     /// Arguments passed to `docker network create` when any container is first
     /// run
     pub network_args: Vec<String>,
-    set: BTreeMap<String, ContainerState>,
+    // if the network is created with `--internal`
+    internal: bool,
+    pub(crate) set: BTreeMap<String, ContainerState>,
     dockerfile_write_dir: Option<String>,
-    log_dir: String,
+    pub(crate) log_dir: String,
     network_active: bool,
+    // set while `docker network create` is in flight, so that if `run` is cancelled the
+    // network is still removed and an "already exists" error of the next attempt is not
+    // treated as a conflict
+    network_create_pending: bool,
+    // the images planned by `run` keyed by what determines a build, this is at the
+    // struct level so that separate `run`s share builds
+    planned_builds: BTreeMap<(Dockerfile, Vec<String>), PlannedBuild>,
+    // containers connected by `connect_external_container`
+    external_containers: BTreeSet<String>,
     /// If build commands should be `debug`
     pub debug_build: bool,
     /// If create commands should be `debug`
     pub debug_create: bool,
     /// If extra debug output should be enabled
     pub debug_extra: bool,
+    /// The `PullPolicy` applied to `Dockerfile::NameTag` containers added
+    /// without their own `pull_policy`
+    pub default_pull_policy: Option<PullPolicy>,
+    /// Used for builds of containers without their own `on_build_progress`,
+    /// see [ContainerNetwork::on_build_progress]
+    pub on_build_progress: Option<BuildProgressCallback>,
+    /// Applied to the docker commands of the network and of the containers
+    /// without their own, see [ContainerNetwork::command_middleware]
+    pub command_middleware: Option<CommandMiddleware>,
+    /// If an `infrastructure` container exiting during a wait is an error
+    pub fail_on_infrastructure_exit: bool,
+    /// The schedule that the waits poll the containers with, see
+    /// [ContainerNetwork::wait_backoff]
+    pub wait_backoff: Backoff,
+    /// If set, BuildKit layer caches are exported to and imported from this
+    /// directory
+    pub build_cache_dir: Option<String>,
+    /// If set, relative paths of the network and the containers it runs are
+    /// resolved against this, see [ContainerNetwork::base_dir]
+    pub base_dir: Option<String>,
+    last_teardown: BTreeMap<String, TerminateOutcome>,
+    last_teardown_report: TeardownReport,
+    pub(crate) resources: CreatedResources,
+    // when `run` was first called
+    first_run: Option<Instant>,
+    /// If set, overrides the global `Strictness` for this network
+    pub strictness: Option<Strictness>,
+    /// The default `log_limit` for containers that do not set their own
+    pub max_log_size: Option<u64>,
+    /// The default `record_limit` for containers that do not set their own
+    pub max_record_size: Option<u64>,
+    /// The default `debug_buffering` for containers that do not set their own,
+    /// see [Command::debug_buffering]
+    pub debug_buffering: Option<BufferPolicy>,
+    /// The default `max_debug_lines_per_sec` for containers that do not set
+    /// their own, see [Command::max_debug_lines_per_sec]
+    pub max_debug_lines_per_sec: Option<u64>,
+    /// How old cached `docker inspect` results can be before they are
+    /// refreshed, 500 ms by default. Retries in polling loops (such as
+    /// `wait_get_ip_addr`) always get results from after they started.
+    pub inspect_max_staleness: Duration,
+    /// If set, [prune](crate::docker_helpers::prune) is run with these options
+    /// at the end of `terminate_all` and `try_terminate_all`
+    pub prune_after_teardown: Option<PruneOptions>,
+    /// Credentials that `run` logs in with for pulling `Dockerfile::NameTag`
+    /// images from private registries, see [ContainerNetwork::pull_secrets]
+    pub pull_secrets: Vec<PullSecret>,
+    /// If `run` should pull missing `Dockerfile::NameTag` images before
+    /// anything is built, see [ContainerNetwork::pull_before_create]
+    pub pull_before_create: bool,
+    /// If `run` should check that missing `Dockerfile::NameTag` images exist
+    /// remotely, see [ContainerNetwork::verify_remote_images]
+    pub verify_remote_images: bool,
+    // registries logged into by `run` that still need to be logged out of
+    pull_secret_logins: Vec<String>,
+    last_prune_report: Option<Result<PruneReport>>,
+    // a failure found by a wait function that was cancelled before reporting it
+    unreported_failure: Option<String>,
+    // set by `set_hooks`, these are kept out of `Container` so that it stays serde-able
+    hooks: BTreeMap<String, ContainerHooks>,
+    /// If set, image digests are verified against and written to this lock
+    /// file, see [ContainerNetwork::lock_file]
+    pub lock_file: Option<String>,
+    image_digests: BTreeMap<String, ImageDigest>,
+    pub(crate) forwards: Vec<ForwardStop>,
+    start_order: Vec<String>,
+    reserved_ports: BTreeMap<String, u16>,
+    /// If set, the containers with readiness probes in each start group are
+    /// waited on for up to this long before the next group is started, see
+    /// [Container::start_group]
+    pub start_group_ready_timeout: Option<Duration>,
+    /// What is kept of the results of finished containers, see
+    /// [ContainerNetwork::result_retention]
+    pub result_retention: ResultRetention,
+    /// If the topology is injected into containers, see
+    /// [ContainerNetwork::inject_topology]
+    pub inject_topology: bool,
+    // the topology file written by the last `run`
+    topology_file: Option<String>,
+    inspect_cache: tokio::sync::Mutex<InspectCache>,
     already_tried_drop: bool,
 }
 
@@ -173,24 +993,148 @@ impl Drop for ContainerNetwork {
             // we purposely order in this way to avoid calling `panicking` in the
             // normal case
             if state.is_active() && (!std::thread::panicking()) {
-                warn!(
-                    "A `ContainerNetwork` was dropped without all active containers being \
-                     properly terminated"
+                strictness_violation(
+                    self.effective_strictness(),
+                    format!(
+                        "A `ContainerNetwork` with network name \"{}\" was dropped without all \
+                         active containers being properly terminated",
+                        self.network_name
+                    ),
                 );
+                emit_crate_event(|| CrateEvent::DroppedNetwork {
+                    network_name: self.network_name.clone(),
+                });
                 break
             }
         }
         for (_, state) in removed_set {
             drop(state);
         }
+        for id in self.take_forwards() {
+            run_drop_teardown_command(
+                std::process::Command::new(docker_binary())
+                    .arg("rm")
+                    .arg("-f")
+                    .arg(id),
+            );
+        }
         // all the containers should be removed now
-        if self.network_active {
-            let _ = std::process::Command::new("docker")
-                .arg("network")
-                .arg("rm")
-                .arg(self.network_name())
-                .output();
+        if self.network_active || self.network_create_pending {
+            for name in &self.external_containers {
+                run_drop_teardown_command(std::process::Command::new(docker_binary()).args([
+                    "network",
+                    "disconnect",
+                    "--force",
+                    self.network_name(),
+                    name,
+                ]));
+            }
+            run_drop_teardown_command(
+                std::process::Command::new(docker_binary())
+                    .arg("network")
+                    .arg("rm")
+                    .arg(self.network_name()),
+            );
+        }
+    }
+}
+
+/// A table of the containers with their state, short container ID, image,
+/// and notable flags, along with the network name, UUID, and log directory.
+/// This is what [ContainerNetwork::describe] returns, the `Debug` impl has
+/// everything.
+impl Display for ContainerNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "ContainerNetwork \"{}\" (UUID {}, log dir \"{}\", network {})",
+            self.network_name,
+            self.uuid,
+            self.log_dir,
+            if self.network_active {
+                "active"
+            } else {
+                "inactive"
+            }
+        )?;
+        let mut rows = vec![[
+            "NAME".to_owned(),
+            "STATE".to_owned(),
+            "ID".to_owned(),
+            "IMAGE".to_owned(),
+            "FLAGS".to_owned(),
+        ]];
+        for (name, state) in &self.set {
+            let container = &state.container;
+            let status = match state.run_state {
+                RunState::PostActive(Err(_)) => "PostActive(error)".to_owned(),
+                RunState::PostActive(Ok(ref comres)) if comres.status.is_none() => {
+                    "PostActive(terminated)".to_owned()
+                }
+                RunState::PostActive(Ok(ref comres)) if !comres.successful() => {
+                    "PostActive(failed)".to_owned()
+                }
+                _ => format!("{:?}", state.status()),
+            };
+            let id = state
+                .active_container_id
+                .as_ref()
+                .map(|id| id.chars().take(12).collect())
+                .unwrap_or_else(|| "-".to_owned());
+            let image = match container.dockerfile {
+                Dockerfile::NameTag(ref name_tag) => name_tag.clone(),
+                Dockerfile::Path(_) | Dockerfile::Contents(_) => container
+                    .build_tag
+                    .clone()
+                    .unwrap_or_else(|| "-".to_owned()),
+            };
+            let mut flags = vec![];
+            if container.infrastructure {
+                flags.push("infrastructure");
+            }
+            if container.allow_unsuccessful {
+                flags.push("allow_unsuccessful");
+            }
+            if container.checkpointable {
+                flags.push("checkpointable");
+            }
+            if container.read_only_rootfs {
+                flags.push("read_only_rootfs");
+            }
+            if container.readiness.is_some() {
+                flags.push("readiness");
+            }
+            if container.supervise.is_some() {
+                flags.push("supervise");
+            }
+            if container.docker_socket.is_some() {
+                flags.push("docker_socket");
+            }
+            if container.detached {
+                flags.push("detached");
+            }
+            match container.network_mode {
+                NetworkMode::Default => (),
+                NetworkMode::Host => flags.push("network_host"),
+                NetworkMode::None => flags.push("network_none"),
+            }
+            rows.push([name.clone(), status, id, image, flags.join(",")]);
+        }
+        let mut widths = [0; 4];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
         }
+        for row in &rows {
+            let mut line = String::new();
+            for (width, cell) in widths.iter().zip(row) {
+                line.push_str(&format!("{cell:width$}  "));
+            }
+            line.push_str(&row[4]);
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
     }
 }
 
@@ -229,17 +1173,72 @@ impl ContainerNetwork {
             uuid: Uuid::new_v4(),
             network_name: network_name.as_ref().to_owned(),
             network_args: vec![],
+            internal: false,
             set: BTreeMap::new(),
             dockerfile_write_dir: dockerfile_write_dir.map(|s| s.to_owned()),
             log_dir: log_dir.as_ref().to_owned(),
             network_active: false,
+            network_create_pending: false,
+            planned_builds: BTreeMap::new(),
+            external_containers: BTreeSet::new(),
             debug_build: false,
             debug_create: false,
             debug_extra: false,
+            default_pull_policy: None,
+            on_build_progress: None,
+            command_middleware: None,
+            fail_on_infrastructure_exit: true,
+            wait_backoff: DEFAULT_NETWORK_WAIT_BACKOFF,
+            build_cache_dir: None,
+            base_dir: None,
+            last_teardown: BTreeMap::new(),
+            last_teardown_report: TeardownReport::default(),
+            resources: CreatedResources::default(),
+            first_run: None,
+            strictness: None,
+            max_log_size: None,
+            max_record_size: None,
+            debug_buffering: None,
+            max_debug_lines_per_sec: None,
+            inspect_max_staleness: Duration::from_millis(500),
+            prune_after_teardown: None,
+            pull_secrets: vec![],
+            pull_before_create: false,
+            verify_remote_images: false,
+            pull_secret_logins: vec![],
+            last_prune_report: None,
+            unreported_failure: None,
+            hooks: BTreeMap::new(),
+            lock_file: None,
+            image_digests: BTreeMap::new(),
+            forwards: vec![],
+            start_order: vec![],
+            reserved_ports: BTreeMap::new(),
+            start_group_ready_timeout: None,
+            result_retention: ResultRetention::Full,
+            inject_topology: false,
+            topology_file: None,
+            inspect_cache: tokio::sync::Mutex::new(InspectCache::default()),
             already_tried_drop: false,
         }
     }
 
+    /// Checks that the [docker_binary](crate::docker_helpers::docker_binary)
+    /// exists, and if `buildx` is set that `docker buildx` (needed for
+    /// `Container::build_secret` and `Container::build_ssh`) can be used. This
+    /// does not check that the docker daemon is running.
+    pub async fn check_docker_available(buildx: bool) -> Result<()> {
+        Command::new_os_str(docker_binary())
+            .check_program_exists()
+            .stack_err_locationless(|| "ContainerNetwork::check_docker_available")?;
+        if buildx {
+            check_buildkit_support()
+                .await
+                .stack_err_locationless(|| "ContainerNetwork::check_docker_available")?;
+        }
+        Ok(())
+    }
+
     /// Same as [ContainerNetwork::new], but it adds a UUID suffix to the
     /// `network_name``
     pub fn new_with_uuid<S0, S1>(
@@ -268,6 +1267,149 @@ impl ContainerNetwork {
         self
     }
 
+    /// Sets whether the network is created with `--internal`, which prevents
+    /// the containers from reaching anything outside of the network. This is
+    /// `false` by default. `network_args` that set `--internal` to a different
+    /// value cause an error when the network is run.
+    ///
+    /// Note that `--internal` does not have the intended effect on all
+    /// platforms (e.x. some WSL 2 Linux distributions), since it depends on
+    /// the libnetwork driver setting up the isolating firewall rules. Tests
+    /// that depend on isolation should check it from inside a container.
+    pub fn internal(&mut self, internal: bool) -> &mut Self {
+        self.internal = internal;
+        self
+    }
+
+    /// Pins the order that containers are started in. The `names` are started
+    /// in the given order before the unlisted containers of the same start
+    /// group, and the unlisted containers keep the order that they were
+    /// passed to [ContainerNetwork::run] in (which is alphabetical by name
+    /// for [ContainerNetwork::run_all]). Containers are only ever started in
+    /// order within a [Container::start_group], and listing a container
+    /// before one of a lower group is a validation error. This replaces any
+    /// previous order, and names that are not in the network are validation
+    /// errors when the network is run.
+    pub fn start_order<I, S>(&mut self, names: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.start_order = names.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Picks a free TCP port on the host for `key`, which containers can use
+    /// with the `{{port:<key>}}` template placeholder (see
+    /// [ContainerNetwork::run]). The same port is returned for the same `key`
+    /// on later calls. The port is only free when it is picked, something
+    /// else may take it before it is used.
+    pub fn reserve_port(&mut self, key: impl AsRef<str>) -> Result<u16> {
+        let key = key.as_ref();
+        if let Some(port) = self.reserved_ports.get(key) {
+            return Ok(*port)
+        }
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::reserve_port(key: {key}) -> could not pick a free port")
+            })?
+            .port();
+        self.reserved_ports.insert(key.to_owned(), port);
+        Ok(port)
+    }
+
+    /// Returns the port reserved by [ContainerNetwork::reserve_port] for `key`
+    pub fn reserved_port(&self, key: &str) -> Option<u16> {
+        self.reserved_ports.get(key).copied()
+    }
+
+    /// Sets `start_group_ready_timeout`, see [Container::start_group]
+    pub fn start_group_ready_timeout(&mut self, ready_timeout: Option<Duration>) -> &mut Self {
+        self.start_group_ready_timeout = ready_timeout;
+        self
+    }
+
+    /// Returns whether the network is created with `--internal`, see
+    /// [ContainerNetwork::internal]
+    pub fn is_internal(&self) -> bool {
+        self.internal
+    }
+
+    // checks that any `--internal` in the `network_args` agrees with `internal`,
+    // returns if there is one
+    fn check_internal_network_args(&self) -> Result<bool> {
+        let mut found = false;
+        for arg in &self.network_args {
+            let value = match arg.as_str() {
+                "--internal" | "--internal=true" | "--internal=1" => true,
+                "--internal=false" | "--internal=0" => false,
+                arg if arg.starts_with("--internal") => {
+                    return Err(Error::from_kind_locationless(format!(
+                        "ContainerNetwork::run -> `network_args` contains an unrecognized \
+                         \"{arg}\", use `ContainerNetwork::internal` instead"
+                    )))
+                }
+                _ => continue,
+            };
+            if value != self.internal {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::run -> `network_args` contains \"{arg}\", which conflicts \
+                     with `ContainerNetwork::internal({})`",
+                    self.internal
+                )))
+            }
+            found = true;
+        }
+        Ok(found)
+    }
+
+    /// Adds `--driver ipvlan --opt parent={parent}` to the `network_args`, so
+    /// that the containers appear as separate hosts on the physical network of
+    /// the host `parent` interface (sharing its MAC address). The `--subnet`
+    /// and `--gateway` of that network usually also need to be added.
+    ///
+    /// This requires privileges on the host network, and is not available in
+    /// Docker Desktop environments (where the daemon runs in a VM).
+    pub fn ipvlan_network(&mut self, parent: impl AsRef<str>) -> &mut Self {
+        self.add_network_args([
+            "--driver",
+            "ipvlan",
+            "--opt",
+            &format!("parent={}", parent.as_ref()),
+        ])
+    }
+
+    /// The same as [ContainerNetwork::ipvlan_network] except with the `macvlan`
+    /// driver, which gives each container its own MAC address
+    pub fn macvlan_network(&mut self, parent: impl AsRef<str>) -> &mut Self {
+        self.add_network_args([
+            "--driver",
+            "macvlan",
+            "--opt",
+            &format!("parent={}", parent.as_ref()),
+        ])
+    }
+
+    // checks that the `network_args` do not have more than one driver
+    fn check_driver_network_args(&self) -> Result<()> {
+        let mut drivers = vec![];
+        let mut args = self.network_args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--driver" || arg == "-d" {
+                drivers.push(args.next().map(|s| s.as_str()).unwrap_or(""));
+            } else if let Some(driver) = arg.strip_prefix("--driver=") {
+                drivers.push(driver);
+            }
+        }
+        if drivers.len() > 1 {
+            return Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::run -> `network_args` set more than one driver: {drivers:?}"
+            )))
+        }
+        Ok(())
+    }
+
     /// Returns the common UUID
     pub fn uuid(&self) -> Uuid {
         self.uuid
@@ -278,20 +1420,214 @@ impl ContainerNetwork {
         self.uuid.to_string()
     }
 
+    /// Returns the final `container_name` that the container with `name` is
+    /// created with, see [final_names]. Returns `None` if `name` is not in the
+    /// network.
+    pub fn container_name(&self, name: &str) -> Option<String> {
+        Some(final_names(&self.set.get(name)?.container, self.uuid).0)
+    }
+
+    /// Returns the final `host_name` that the container with `name` is
+    /// created with and that other containers in the network can resolve, see
+    /// [final_names]. Returns `None` if `name` is not in the network or it is
+    /// not attached to it because of its [Container::network_mode].
+    pub fn host_name(&self, name: &str) -> Option<String> {
+        let container = &self.set.get(name)?.container;
+        container
+            .network_mode
+            .is_default()
+            .then(|| final_names(container, self.uuid).1)
+    }
+
+    /// Returns a [NotOnNetworkError] if the container with `name` is not
+    /// attached to the network
+    pub(crate) fn check_on_network(&self, name: &str) -> Result<()> {
+        match self.set.get(name) {
+            Some(state) if !state.container.network_mode.is_default() => {
+                let e = NotOnNetworkError {
+                    name: name.to_owned(),
+                    network_mode: state.container.network_mode,
+                };
+                let msg = e.to_string();
+                Err(Error::box_from_locationless(e).add_kind_locationless(msg))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Returns the network name
     pub fn network_name(&self) -> &str {
         &self.network_name
     }
 
-    /// Adds the container to the inactive set
-    pub fn add_container(&mut self, container: Container) -> Result<&mut Self> {
-        if self.dockerfile_write_dir.is_none()
-            && matches!(container.dockerfile, Dockerfile::Contents(_))
-        {
-            return Err(Error::from_kind_locationless(
-                "ContainerNetwork::add_container -> a container is built with \
-                 `Dockerfile::Contents`, but `dockerfile_write_dir` is unset",
-            ))
+    /// Returns the `Display` of the network, a table of the containers that is
+    /// more useful for triage than the `Debug` output
+    pub fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    /// Sets the `PullPolicy` that is applied to `Dockerfile::NameTag`
+    /// containers subsequently added with [ContainerNetwork::add_container]
+    /// that do not have their own `pull_policy` set
+    pub fn default_pull_policy(&mut self, policy: PullPolicy) -> &mut Self {
+        self.default_pull_policy = Some(policy);
+        self
+    }
+
+    /// Sets the `pull_policy` of all `Dockerfile::NameTag` containers currently
+    /// in the network that have not been run yet
+    pub fn apply_pull_policy(&mut self, policy: PullPolicy) -> &mut Self {
+        for state in self.set.values_mut() {
+            if matches!(state.run_state, RunState::PreActive)
+                && matches!(state.container.dockerfile, Dockerfile::NameTag(_))
+            {
+                state.container_mut().pull_policy = Some(policy);
+            }
+        }
+        self
+    }
+
+    /// Sets the [ContainerHooks] of the container with `name`, replacing any
+    /// previous hooks. The hooks are kept if the container is removed and
+    /// added again.
+    pub fn set_hooks(&mut self, name: &str, hooks: ContainerHooks) -> Result<&mut Self> {
+        if !self.set.contains_key(name) {
+            return Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::set_hooks -> name \"{name}\" is not contained in the network"
+            )))
+        }
+        self.hooks.insert(name.to_owned(), hooks);
+        Ok(self)
+    }
+
+    /// Runs the hooks of container `name` for `phase`, applying the
+    /// `HookFailurePolicy`
+    async fn run_hooks(
+        &self,
+        name: &str,
+        phase: HookPhase,
+        result: Option<CommandResult>,
+    ) -> Result<()> {
+        let Some(hooks) = self.hooks.get(name).filter(|hooks| !hooks.is_empty(phase)) else {
+            return Ok(())
+        };
+        let state = &self.set[name];
+        let cx = HookContext {
+            phase,
+            name: name.to_owned(),
+            container_name: final_names(&state.container, self.uuid).0,
+            network_name: self.network_name.clone(),
+            network_mode: state.container.network_mode,
+            id: state.active_container_id.clone(),
+            result,
+            command_middleware: state
+                .container
+                .command_middleware
+                .clone()
+                .or_else(|| self.command_middleware.clone()),
+        };
+        match hooks.run(&cx).await {
+            Ok(()) => Ok(()),
+            Err(e) => match hooks.failure_policy {
+                HookFailurePolicy::Fatal => Err(e),
+                HookFailurePolicy::Log => {
+                    warn!("ContainerNetwork -> ignoring a failed hook: {e:?}");
+                    emit_crate_event(|| CrateEvent::HookFailed {
+                        name: name.to_owned(),
+                        phase: phase.to_string(),
+                        error: format!("{e:?}"),
+                    });
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Adds the digests of the `Dockerfile::NameTag` containers in `names` to
+    /// the `lock_file`, writing it if anything changed
+    async fn write_lock_file(&self, names: &[String]) -> Result<()> {
+        let path = self.lock_file.as_ref().unwrap();
+        let mut digests = read_lock_file(Some(path)).await?;
+        let before = digests.clone();
+        for name in names {
+            let container = &self.set[name].container;
+            if !matches!(container.dockerfile, Dockerfile::NameTag(_)) {
+                continue
+            }
+            let digest = container.pinned_digest.clone().or_else(|| {
+                self.image_digests
+                    .get(name)
+                    .and_then(|image| image.repo_digest.clone())
+            });
+            if let Some(digest) = digest {
+                digests.insert(name.clone(), digest);
+            }
+        }
+        if (digests != before) || (!Path::new(path).exists()) {
+            tokio::fs::write(path, format_lock_file(&digests))
+                .await
+                .stack_err_locationless(|| {
+                    format!("ContainerNetwork::run -> could not write the lock file {path:?}")
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Records the digests of the images that the containers in `names` were
+    /// created from, failures are only warned about
+    async fn record_image_digests(&mut self, names: &[String]) {
+        let mut inspected = BTreeMap::<String, Option<ImageDigest>>::new();
+        for name in names {
+            let container = &self.set[name].container;
+            let image = match (&container.dockerfile, &container.pinned_digest) {
+                (Dockerfile::NameTag(name_tag), Some(digest)) => pinned_reference(name_tag, digest),
+                (Dockerfile::NameTag(name_tag), None) => name_tag.clone(),
+                _ => match container.build_tag {
+                    Some(ref build_tag) => build_tag.clone(),
+                    None => continue,
+                },
+            };
+            if !inspected.contains_key(&image) {
+                let res = inspect_image_digest_with(self.command_middleware.as_ref(), &image).await;
+                let digest = match res {
+                    Ok(digest) => digest,
+                    Err(e) => {
+                        warn!(
+                            "ContainerNetwork::run -> could not record the digest of \"{image}\": \
+                             {e:?}"
+                        );
+                        emit_crate_event(|| CrateEvent::Other {
+                            message: format!("could not record the digest of \"{image}\": {e:?}"),
+                        });
+                        None
+                    }
+                };
+                inspected.insert(image.clone(), digest);
+            }
+            let Some(ref digest) = inspected[&image] else {
+                continue
+            };
+            if !matches!(container.dockerfile, Dockerfile::NameTag(_)) {
+                self.resources
+                    .set_id(ResourceKind::Image, &image, &digest.id);
+            }
+            self.image_digests.insert(name.clone(), digest.clone());
+        }
+    }
+
+    /// Adds the container to the inactive set
+    pub fn add_container(&mut self, mut container: Container) -> Result<&mut Self> {
+        if self.dockerfile_write_dir.is_none()
+            && matches!(container.dockerfile, Dockerfile::Contents(_))
+        {
+            return Err(Error::from_kind_locationless(
+                "ContainerNetwork::add_container -> a container is built with \
+                 `Dockerfile::Contents`, but `dockerfile_write_dir` is unset",
+            ))
+        }
+        if matches!(container.dockerfile, Dockerfile::NameTag(_)) && container.pull_policy.is_none()
+        {
+            container.pull_policy = self.default_pull_policy;
         }
         match self.set.entry(container.name.clone()) {
             Entry::Vacant(v) => {
@@ -312,20 +1648,40 @@ impl ContainerNetwork {
     /// if it is currently active. Returns `Ok(None)` if the container was never
     /// activated. Should return a `CommandResult` if the container was normally
     /// terminated. Returns an error if `name` could not be found.
+    #[deprecated(note = "use `try_remove_container` which distinguishes more outcomes")]
     pub async fn remove_container<S>(&mut self, name: S) -> Result<Option<CommandResult>>
+    where
+        S: AsRef<str>,
+    {
+        match self.try_remove_container(name).await? {
+            RemovedContainer::Ran(comres) => Ok(Some(comres)),
+            RemovedContainer::RunnerError(_) | RemovedContainer::NeverRan => Ok(None),
+        }
+    }
+
+    /// Terminates and removes the container with `name` from the network,
+    /// returning what the container was.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not in the network
+    pub async fn try_remove_container<S>(&mut self, name: S) -> Result<RemovedContainer>
     where
         S: AsRef<str>,
     {
         let name = name.as_ref();
-        self.terminate([name]).await;
+        self.try_terminate([name]).await;
         if let Some(mut state) = self.set.remove(name) {
             match mem::take(&mut state.run_state) {
-                RunState::PostActive(Ok(comres)) => Ok(Some(comres)),
-                _ => Ok(None),
+                RunState::PostActive(Ok(comres)) => Ok(RemovedContainer::Ran(comres)),
+                RunState::PostActive(Err(e)) => Ok(RemovedContainer::RunnerError(e)),
+                // `try_terminate` guarantees it is not `Active`
+                RunState::PreActive | RunState::Active(_) => Ok(RemovedContainer::NeverRan),
             }
         } else {
             Err(Error::from(format!(
-                "ContainerNetwork::remove_name -> could not find name \"{name}\" in the network"
+                "ContainerNetwork::try_remove_container -> could not find name \"{name}\" in the \
+                 network"
             )))
         }
     }
@@ -366,6 +1722,34 @@ impl ContainerNetwork {
         self
     }
 
+    /// Adds the `docker create` arguments to every container currently in the
+    /// network
+    pub fn add_common_create_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let args: Vec<String> = args.into_iter().map(|s| s.as_ref().to_string()).collect();
+        for state in self.set.values_mut() {
+            state
+                .container_mut()
+                .create_args
+                .extend(args.iter().cloned())
+        }
+        self
+    }
+
+    /// Adds `/etc/hosts` entries resolving the hostnames to the IP addresses
+    /// to every container currently in the network, see [Container::add_host]
+    pub fn inject_host_entries(&mut self, entries: &[(&str, IpAddr)]) -> &mut Self {
+        let mut args = vec![];
+        for (hostname, ip) in entries {
+            args.push("--add-host".to_owned());
+            args.push(format!("{hostname}:{ip}"));
+        }
+        self.add_common_create_args(args)
+    }
+
     /// Get a map of active container names to ids
     pub fn get_active_container_ids(&self) -> BTreeMap<String, String> {
         let mut v = BTreeMap::new();
@@ -377,6 +1761,289 @@ impl ContainerNetwork {
         v
     }
 
+    /// Returns the `Container` with `name`, note that the network fills in
+    /// some fields such as `build_tag` when running
+    pub fn get_container(&self, name: &str) -> Option<&Container> {
+        Some(&self.set.get(name)?.container)
+    }
+
+    /// Returns the result of the container with `name` if it has finished
+    /// (either by exiting on its own or by being terminated). Returns `None`
+    /// if `name` is not in the network or the container has not finished.
+    pub fn get_result(&self, name: &str) -> Option<&Result<CommandResult>> {
+        if let RunState::PostActive(ref result) = self.set.get(name)?.run_state {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the result of the finished container with `name`, or an error
+    /// if it is not in the network, has not finished, or its runner failed.
+    /// This is for use with [expect_stdout](crate::assertions::expect_stdout)
+    /// and [expect_stderr](crate::assertions::expect_stderr).
+    pub fn expect(&self, name: &str) -> Result<&CommandResult> {
+        match self.get_result(name) {
+            Some(Ok(comres)) => Ok(comres),
+            Some(Err(e)) => Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::expect -> the runner of container \"{name}\" failed: {e:?}"
+            ))),
+            None => Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::expect -> container \"{name}\" is not in the network or has \
+                 not finished"
+            ))),
+        }
+    }
+
+    /// Get a map of the names of all finished containers to their results
+    pub fn results(&self) -> BTreeMap<String, &Result<CommandResult>> {
+        let mut v = BTreeMap::new();
+        for (name, state) in &self.set {
+            if let RunState::PostActive(ref result) = state.run_state {
+                v.insert(name.to_string(), result);
+            }
+        }
+        v
+    }
+
+    /// Takes the results of all finished containers, returning a map of their
+    /// names to their results. The containers remain in the network and can be
+    /// run again.
+    pub fn drain_results(&mut self) -> BTreeMap<String, Result<CommandResult>> {
+        let mut v = BTreeMap::new();
+        for (name, state) in self.set.iter_mut() {
+            if matches!(state.run_state, RunState::PostActive(_)) {
+                if let RunState::PostActive(result) = mem::take(&mut state.run_state) {
+                    v.insert(name.to_string(), result);
+                }
+            }
+        }
+        v
+    }
+
+    /// Collects the containers that have already exited (see
+    /// [ContainerNetwork::try_collect_finished]), terminates the rest with
+    /// [ContainerNetwork::terminate_all], and returns the results of all
+    /// containers that ran.
+    ///
+    /// # Errors
+    ///
+    /// If the runner of any container failed, all of the runner errors are
+    /// combined. Containers that exited unsuccessfully are not errors, check
+    /// their `status`.
+    pub async fn into_results(mut self) -> Result<BTreeMap<String, CommandResult>> {
+        self.try_collect_finished().await;
+        self.terminate_all().await;
+        let mut v = BTreeMap::new();
+        let mut errors = vec![];
+        for (name, result) in self.drain_results() {
+            match result {
+                Ok(comres) => {
+                    v.insert(name, comres);
+                }
+                Err(e) => errors.push(format!("container \"{name}\": {e:?}")),
+            }
+        }
+        if errors.is_empty() {
+            Ok(v)
+        } else {
+            Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::into_results -> the runners of some containers failed:\n{}",
+                errors.join("\n")
+            )))
+        }
+    }
+
+    /// Exports the images of the containers with `names` to a tarball at
+    /// `output_path` using `docker save`. Images built by the network are only
+    /// known after the containers have been run, containers with a
+    /// [Dockerfile::NameTag] export that image. Containers sharing an image
+    /// only export it once.
+    pub async fn export_images_tar(&self, names: &[&str], output_path: &str) -> Result<()> {
+        let mut images = BTreeSet::new();
+        for name in names {
+            let state = self.set.get(*name).stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::export_images_tar -> could not find container with name \
+                     \"{name}\""
+                )
+            })?;
+            let image = match state.container.dockerfile {
+                Dockerfile::NameTag(ref name_tag) => name_tag.clone(),
+                Dockerfile::Path(_) | Dockerfile::Contents(_) => state
+                    .container
+                    .build_tag
+                    .clone()
+                    .stack_err_locationless(|| {
+                        format!(
+                            "ContainerNetwork::export_images_tar -> container \"{name}\" has no \
+                             `build_tag`, it needs to be run at least once first"
+                        )
+                    })?,
+            };
+            images.insert(image);
+        }
+        let comres = self
+            .wrap(docker_command("save -o").arg(output_path).args(&images))
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                "ContainerNetwork::export_images_tar -> when running `docker save`"
+            })?;
+        comres.assert_success().stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::export_images_tar(names: {names:?}, output_path: \
+                 {output_path}) -> `docker save` failed"
+            )
+        })
+    }
+
+    /// Imports images from a tarball created by
+    /// [ContainerNetwork::export_images_tar] (or any `docker save`) with
+    /// `docker load`, returning the names of the loaded images. Images without
+    /// a tag are returned as their image ID.
+    pub async fn import_images_tar(tar_path: &str) -> Result<Vec<String>> {
+        let comres = docker_command("load -i")
+            .arg(tar_path)
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                "ContainerNetwork::import_images_tar -> when running `docker load`"
+            })?;
+        comres.assert_success().stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::import_images_tar(tar_path: {tar_path}) -> `docker load` failed"
+            )
+        })?;
+        let mut images = vec![];
+        for line in comres.stdout_as_utf8_lossy().lines() {
+            let line = line.trim();
+            if let Some(image) = line
+                .strip_prefix("Loaded image:")
+                .or_else(|| line.strip_prefix("Loaded image ID:"))
+            {
+                images.push(image.trim().to_owned());
+            }
+        }
+        Ok(images)
+    }
+
+    /// Returns the host paths extracted by the last run of the container with
+    /// `name` as requested by `Container::extract_after_build`. Returns `None`
+    /// if `name` is not in the network.
+    pub fn extracted_paths(&self, name: &str) -> Option<&[PathBuf]> {
+        Some(&self.set.get(name)?.extracted_paths)
+    }
+
+    /// Returns how long the container with `name` has been running for.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not in the network or the container is not active
+    pub fn container_uptime(&self, name: &str) -> Result<Duration> {
+        let state = self.set.get(name).stack_err_locationless(|| {
+            format!("ContainerNetwork::container_uptime -> name \"{name}\" not found")
+        })?;
+        match state.start_time {
+            Some(start_time) if state.is_active() => Ok(start_time.elapsed()),
+            _ => Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::container_uptime -> container \"{name}\" is not active"
+            ))),
+        }
+    }
+
+    /// Returns the time since `run` (or any of its variants) was first called
+    /// on the network, or `None` if it has never been called. This uses the
+    /// monotonic clock of `tokio::time::Instant` like the wait functions, so
+    /// it is unaffected by changes to the system time and respects
+    /// `tokio::time::pause`.
+    pub fn elapsed_since_first_run(&self) -> Option<Duration> {
+        self.first_run.map(|first_run| first_run.elapsed())
+    }
+
+    /// Returns the name and uptime of the longest running active container, or
+    /// `None` if there are no active containers
+    pub fn oldest_active(&self) -> Option<(&str, Duration)> {
+        self.set
+            .iter()
+            .filter(|(_, state)| state.is_active())
+            .filter_map(|(name, state)| Some((name.as_str(), state.start_time?.elapsed())))
+            .max_by_key(|(_, uptime)| *uptime)
+    }
+
+    /// Returns the digests of the images that containers were last created
+    /// from, keyed by container name. For `Dockerfile::NameTag` containers
+    /// this has the registry digest, and for built images the image ID (which
+    /// is also the `id` of the `ResourceKind::Image` entry in
+    /// [ContainerNetwork::resources]).
+    pub fn image_digests(&self) -> &BTreeMap<String, ImageDigest> {
+        &self.image_digests
+    }
+
+    /// Returns the host paths of crash artifacts recorded after the container
+    /// with `name` crashed, see `Container::capture_crash_artifacts`. Returns
+    /// `None` if `name` is not in the network.
+    pub fn crash_artifacts(&self, name: &str) -> Option<&[PathBuf]> {
+        Some(&self.set.get(name)?.crash_artifacts)
+    }
+
+    /// Returns the results of every exit of the container with `name` if it has
+    /// a `supervise` policy, in order and including the exit that exhausted
+    /// the policy. This is reset when the container is run again. Returns
+    /// `None` if `name` is not in the network.
+    pub fn supervise_history(&self, name: &str) -> Option<&[CommandResult]> {
+        Some(&self.set.get(name)?.supervise_history)
+    }
+
+    /// Sets what is kept of the `CommandResult` of each container when it
+    /// finishes. The default is [ResultRetention::Full], networks that are
+    /// kept around after running many containers with a lot of output can use
+    /// [ResultRetention::StatusOnly] or [ResultRetention::Discard] to bound
+    /// their memory, the log files still have all the output. The policy is
+    /// applied to results from containers started after this is set, see also
+    /// [ContainerNetwork::clear_results].
+    pub fn result_retention(&mut self, result_retention: ResultRetention) -> &mut Self {
+        self.result_retention = result_retention;
+        self
+    }
+
+    /// Returns the stdout and stderr byte counts of the last result of the
+    /// container with `name`, which are kept unless the result was from a
+    /// [ResultRetention::Discard] policy. Returns `None` if `name` is not in
+    /// the network or has not finished.
+    pub fn output_bytes(&self, name: &str) -> Option<(u64, u64)> {
+        self.set.get(name)?.output_bytes
+    }
+
+    /// Drops the recorded stdout and stderr of every finished container and
+    /// of the `supervise_history`s, keeping the exit statuses and
+    /// [ContainerNetwork::output_bytes]. This is for reclaiming memory between
+    /// phases regardless of the [ContainerNetwork::result_retention].
+    pub fn clear_results(&mut self) {
+        for state in self.set.values_mut() {
+            if let RunState::PostActive(Ok(ref mut comres)) = state.run_state {
+                comres.stdout = vec![];
+                comres.stderr = vec![];
+            }
+            for comres in &mut state.supervise_history {
+                comres.stdout = vec![];
+                comres.stderr = vec![];
+            }
+        }
+    }
+
+    /// Returns the [BuildStats] of the last build of the image of each
+    /// container that had one built by [ContainerNetwork::run], keyed by name.
+    /// Containers with the same `dockerfile` and `build_args` share a build and
+    /// have the same stats, and containers using `Dockerfile::NameTag` or
+    /// images built by earlier `run`s have none.
+    pub fn build_stats(&self) -> BTreeMap<&str, &BuildStats> {
+        self.set
+            .iter()
+            .filter_map(|(name, state)| Some((name.as_str(), state.build_stats.as_ref()?)))
+            .collect()
+    }
+
     /// Get the names of all active containers
     pub fn active_names(&self) -> Vec<String> {
         let mut v = vec![];
@@ -389,70 +2056,478 @@ impl ContainerNetwork {
     }
 
     /// Get the names of all inactive containers (both containers that have not
-    /// been run before, and containers that were terminated)
+    /// been run before, and containers that were terminated). Containers
+    /// suspended by [ContainerNetwork::checkpoint_all] are not included.
     pub fn inactive_names(&self) -> Vec<String> {
         let mut v = vec![];
         for (name, state) in &self.set {
-            if !state.is_active() {
+            if !state.is_active() && state.checkpoint.is_none() {
                 v.push(name.to_string());
             }
         }
         v
     }
 
+    /// Captures the status and IP address of every container in the network,
+    /// for comparing with [ContainerNetwork::diff] when debugging. IP addresses
+    /// are not waited for, and they come from the same cache as
+    /// [ContainerNetwork::inspect].
+    pub async fn take_snapshot(&self) -> NetworkStateSnapshot {
+        let mut containers = BTreeMap::new();
+        for (name, state) in &self.set {
+            let status = state.status();
+            let ip_addr = match state.active_container_id {
+                Some(ref id)
+                    if (status == ContainerStatus::Active)
+                        && state.container.network_mode.is_default() =>
+                {
+                    self.inspect_id(id, None)
+                        .await
+                        .and_then(|v| ip_addr_from_inspect(&v))
+                        .ok()
+                }
+                _ => None,
+            };
+            containers.insert(name.clone(), ContainerSnapshot { status, ip_addr });
+        }
+        NetworkStateSnapshot { containers }
+    }
+
+    /// Returns which containers started, stopped, or changed IP address between
+    /// the `before` and `after` snapshots. Containers missing from a snapshot
+    /// are treated as inactive.
+    pub fn diff(before: &NetworkStateSnapshot, after: &NetworkStateSnapshot) -> NetworkDiff {
+        let mut diff = NetworkDiff::default();
+        let names: BTreeSet<&String> = before
+            .containers
+            .keys()
+            .chain(after.containers.keys())
+            .collect();
+        for name in names {
+            let before = before.containers.get(name);
+            let after = after.containers.get(name);
+            let was_active = before.is_some_and(|c| c.status == ContainerStatus::Active);
+            let is_active = after.is_some_and(|c| c.status == ContainerStatus::Active);
+            match (was_active, is_active) {
+                (false, true) => diff.started.push(name.clone()),
+                (true, false) => diff.stopped.push(name.clone()),
+                (true, true) => {
+                    let (before, after) = (before.unwrap().ip_addr, after.unwrap().ip_addr);
+                    if before != after {
+                        diff.ip_changed.push((name.clone(), before, after));
+                    }
+                }
+                (false, false) => (),
+            }
+        }
+        diff
+    }
+
+    /// Takes a snapshot and returns its [ContainerNetwork::diff] from the
+    /// earlier `snapshot`
+    pub async fn diff_network_state(&self, snapshot: &NetworkStateSnapshot) -> NetworkDiff {
+        Self::diff(snapshot, &self.take_snapshot().await)
+    }
+
     /// Force removes any active containers found with the given names
+    #[deprecated(note = "use `try_terminate` which reports names that were not found")]
     pub async fn terminate<I, S>(&mut self, names: I)
     where
         I: IntoIterator<Item = S>,
         S: AsRef<str>,
     {
+        self.try_terminate(names).await;
+    }
+
+    /// Force removes the containers with `names`, returning what happened to
+    /// each of them. This is also recorded for
+    /// [ContainerNetwork::last_teardown].
+    pub async fn try_terminate<I, S>(&mut self, names: I) -> BTreeMap<String, TerminateOutcome>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.last_teardown_report = TeardownReport::default();
+        let mut outcomes = BTreeMap::new();
         for name in names {
             let name = name.as_ref();
-            if let Some(state) = self.set.get_mut(name) {
-                let _ = state.terminate().await;
-            }
+            let outcome = if let Some(state) = self.set.get_mut(name) {
+                state
+                    .terminate_with_outcome(&mut self.resources, &mut self.last_teardown_report)
+                    .await
+            } else {
+                TerminateOutcome::NotFound
+            };
+            outcomes.insert(name.to_owned(), outcome);
         }
+        self.last_teardown.clone_from(&outcomes);
+        outcomes
     }
 
     /// Force removes all active containers, but does not remove the docker
-    /// network
+    /// network. What happened to each container can be seen afterwards with
+    /// [ContainerNetwork::last_teardown].
     pub async fn terminate_containers(&mut self) {
-        for state in self.set.values_mut() {
-            let _ = state.terminate().await;
+        self.try_terminate_containers().await;
+    }
+
+    /// Same as [ContainerNetwork::terminate_containers] but returns what
+    /// happened to each container
+    pub async fn try_terminate_containers(&mut self) -> BTreeMap<String, TerminateOutcome> {
+        self.last_teardown_report = TeardownReport::default();
+        let mut outcomes = BTreeMap::new();
+        for (name, state) in self.set.iter_mut() {
+            outcomes.insert(
+                name.clone(),
+                state
+                    .terminate_with_outcome(&mut self.resources, &mut self.last_teardown_report)
+                    .await,
+            );
         }
+        self.last_teardown.clone_from(&outcomes);
+        outcomes
+    }
+
+    /// Returns the outcomes from the last termination function (including the
+    /// `try_*` ones)
+    pub fn last_teardown(&self) -> &BTreeMap<String, TerminateOutcome> {
+        &self.last_teardown
+    }
+
+    /// Returns the teardown commands that failed during the last termination
+    /// function (including `try_*` ones), along with any that failed in
+    /// cleanups since then. Removals that fail because the resource is
+    /// already gone are not failures, and other failures are retried a few
+    /// times before being recorded with the stderr from docker.
+    pub fn last_teardown_report(&self) -> &TeardownReport {
+        &self.last_teardown_report
+    }
+
+    /// Returns the ledger of everything this network has created on the
+    /// machine (the docker network, containers, built images, temporary files,
+    /// log files, and host directories) and what teardown did with each of
+    /// them. After [ContainerNetwork::terminate_all], anything that teardown
+    /// does not remove is marked as `Kept` with the reason, so
+    /// [CreatedResources::outstanding] lists what unexpectedly remains.
+    pub fn resources(&self) -> &CreatedResources {
+        &self.resources
     }
 
     // don't make public because we would have to make decisions around containers
     // that still exist
     /// Removes the docker network
     async fn terminate_network(&mut self) {
-        if self.network_active {
-            let _ = Command::new("docker network rm")
-                .arg(self.network_name())
-                .run_to_completion()
+        // relay containers are connected to the network
+        for id in self.take_forwards() {
+            let _ = run_teardown_command(
+                self.wrap(docker_command("rm -f").arg(&id)),
+                ResourceKind::Container,
+                &id,
+                &mut self.last_teardown_report,
+            )
+            .await;
+        }
+        if self.network_active || self.network_create_pending {
+            for name in mem::take(&mut self.external_containers) {
+                let _ = run_teardown_command(
+                    self.wrap(
+                        docker_command("network disconnect --force")
+                            .arg(self.network_name())
+                            .arg(name),
+                    ),
+                    ResourceKind::Network,
+                    &self.network_name,
+                    &mut self.last_teardown_report,
+                )
                 .await;
+            }
+            let status = match run_teardown_command(
+                self.wrap(docker_command("network rm").arg(self.network_name())),
+                ResourceKind::Network,
+                &self.network_name,
+                &mut self.last_teardown_report,
+            )
+            .await
+            {
+                Ok(()) => ResourceStatus::Removed,
+                Err(stderr) => ResourceStatus::RemovalError(stderr),
+            };
+            self.resources
+                .mark(ResourceKind::Network, &self.network_name, status);
             self.network_active = false;
+            self.network_create_pending = false;
         }
     }
 
-    /// Force removes all active containers and removes the network. The
-    /// `ContainerNetwork` can always be safely dropped if this is the last
-    /// function called on it. The network is recreated if any containers are
-    /// run again.
-    pub async fn terminate_all(&mut self) {
-        self.terminate_containers().await;
-        self.terminate_network().await;
+    /// Connects the docker container `external_container_name` that is not
+    /// managed by this `ContainerNetwork` (e.x. one from another network) to
+    /// the docker network, optionally with an `alias` that the containers of
+    /// this network can use as a hostname for it. The network must be active,
+    /// meaning that some containers have been run. External containers are
+    /// disconnected when the network is removed.
+    pub async fn connect_external_container(
+        &mut self,
+        external_container_name: &str,
+        alias: Option<&str>,
+    ) -> Result<()> {
+        if !self.network_active {
+            return Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::connect_external_container -> the network \"{}\" is not \
+                 active, run some containers first",
+                self.network_name()
+            )))
+        }
+        let mut command = docker_command("network connect");
+        if let Some(alias) = alias {
+            command = command.arg("--alias").arg(alias);
+        }
+        let comres = self
+            .wrap(
+                command
+                    .arg(self.network_name())
+                    .arg(external_container_name),
+            )
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                "ContainerNetwork::connect_external_container -> when running `docker network \
+                 connect`"
+            })?;
+        comres.assert_success().stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::connect_external_container -> could not connect \
+                 \"{external_container_name}\""
+            )
+        })?;
+        self.external_containers
+            .insert(external_container_name.to_owned());
+        Ok(())
     }
 
-    /// Runs only the given `names`. This prechecks as much as it can before
-    /// creating any containers. If an error happens in the middle of creating
-    /// and starting the containers, any of the `names` that had been created
-    /// are terminated before the function returns.
-    pub async fn run<I, S>(&mut self, names: I) -> Result<()>
-    where
-        I: IntoIterator<Item = S>,
-        S: AsRef<str>,
-    {
+    /// Disconnects the container `external_container_name` connected by
+    /// [ContainerNetwork::connect_external_container]
+    pub async fn disconnect_external_container(
+        &mut self,
+        external_container_name: &str,
+    ) -> Result<()> {
+        if !self.external_containers.contains(external_container_name) {
+            return Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::disconnect_external_container -> \"{external_container_name}\" \
+                 is not a connected external container"
+            )))
+        }
+        let comres = self
+            .wrap(
+                docker_command("network disconnect")
+                    .arg(self.network_name())
+                    .arg(external_container_name),
+            )
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                "ContainerNetwork::disconnect_external_container -> when running `docker network \
+                 disconnect`"
+            })?;
+        comres.assert_success().stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::disconnect_external_container -> could not disconnect \
+                 \"{external_container_name}\""
+            )
+        })?;
+        self.external_containers.remove(external_container_name);
+        Ok(())
+    }
+
+    /// Force removes all active containers and removes the network. The
+    /// `ContainerNetwork` can always be safely dropped if this is the last
+    /// function called on it. The network is recreated if any containers are
+    /// run again.
+    pub async fn terminate_all(&mut self) {
+        self.terminate_containers().await;
+        self.terminate_network().await;
+        self.run_prune_after_teardown().await;
+        self.resources.mark_kept();
+    }
+
+    /// Same as [ContainerNetwork::terminate_all] but returns what happened to
+    /// each container
+    pub async fn try_terminate_all(&mut self) -> BTreeMap<String, TerminateOutcome> {
+        let outcomes = self.try_terminate_containers().await;
+        self.terminate_network().await;
+        self.run_prune_after_teardown().await;
+        self.resources.mark_kept();
+        outcomes
+    }
+
+    async fn run_prune_after_teardown(&mut self) {
+        if let Some(ref options) = self.prune_after_teardown {
+            let res = prune(options).await.stack_err_locationless(|| {
+                "ContainerNetwork::terminate_all -> when running `prune_after_teardown`"
+            });
+            match res {
+                Ok(ref report) => debug!("{report:?}"),
+                Err(ref e) => {
+                    warn!("{e:?}");
+                    emit_crate_event(|| CrateEvent::PruneFailed {
+                        network_name: self.network_name.clone(),
+                        error: format!("{e:?}"),
+                    });
+                }
+            }
+            self.last_prune_report = Some(res);
+        }
+    }
+
+    /// Checkpoints all active `checkpointable` containers with `docker
+    /// checkpoint create`, which stops them. Their attached runners are
+    /// finished and they are then suspended (not active, but not included in
+    /// `inactive_names`) until [ContainerNetwork::restore_all] or a termination
+    /// function is called. Active containers that are not `checkpointable` are
+    /// left running and listed as skipped.
+    ///
+    /// This requires an experimental docker daemon and CRIU, which
+    /// [check_checkpoint_support] is used to check first. If an error happens
+    /// partway through, the containers checkpointed before the error remain
+    /// suspended.
+    pub async fn checkpoint_all(&mut self, checkpoint_name: &str) -> Result<CheckpointReport> {
+        check_checkpoint_support()
+            .await
+            .stack_err_locationless(|| "ContainerNetwork::checkpoint_all")?;
+        let mut report = CheckpointReport {
+            checkpoint_name: checkpoint_name.to_owned(),
+            ..Default::default()
+        };
+        for (name, state) in self.set.iter_mut() {
+            if !state.is_active() {
+                continue
+            }
+            if !state.container.checkpointable {
+                report.skipped.push(name.clone());
+                continue
+            }
+            let id = state.active_container_id.clone().unwrap();
+            let comres = state
+                .container
+                .apply_command_middleware(
+                    docker_command("checkpoint create")
+                        .arg(&id)
+                        .arg(checkpoint_name),
+                )
+                .run_to_completion()
+                .await
+                .stack_err_locationless(|| {
+                    format!(
+                        "ContainerNetwork::checkpoint_all -> when running `docker checkpoint \
+                         create` for container \"{name}\""
+                    )
+                })?;
+            comres.assert_success().stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::checkpoint_all(checkpoint_name: {checkpoint_name}) -> \
+                     `docker checkpoint create` failed for container \"{name}\""
+                )
+            })?;
+            // the container stopping makes the attached `docker start` exit
+            if let RunState::Active(mut runner) = mem::take(&mut state.run_state) {
+                if runner
+                    .wait_with_timeout(Duration::from_secs(10))
+                    .await
+                    .is_err()
+                {
+                    let _ = runner.terminate().await;
+                }
+            }
+            state.start_time = None;
+            state.checkpoint = Some(checkpoint_name.to_owned());
+            report.checkpointed.push(name.clone());
+        }
+        Ok(report)
+    }
+
+    /// Restores all the containers suspended by
+    /// [ContainerNetwork::checkpoint_all] with `checkpoint_name`, using
+    /// `docker start --attach --checkpoint`. Log files are appended to rather
+    /// than overwritten. Returns the names of the restored containers.
+    pub async fn restore_all(&mut self, checkpoint_name: &str) -> Result<Vec<String>> {
+        check_checkpoint_support()
+            .await
+            .stack_err_locationless(|| "ContainerNetwork::restore_all")?;
+        let mut restored = vec![];
+        for (name, state) in self.set.iter_mut() {
+            if state.checkpoint.as_deref() != Some(checkpoint_name) {
+                continue
+            }
+            let (stdout_log, stderr_log) =
+                container_log_files(&state.container, &self.log_dir, true);
+            let mut container = state.container().clone();
+            container.log_limit = container.log_limit.or(self.max_log_size);
+            container.record_limit = container.record_limit.or(self.max_record_size);
+            container.debug_buffering = container.debug_buffering.or(self.debug_buffering);
+            container.max_debug_lines_per_sec = container
+                .max_debug_lines_per_sec
+                .or(self.max_debug_lines_per_sec);
+            container.debug_buffering = container.debug_buffering.or(self.debug_buffering);
+            container.max_debug_lines_per_sec = container
+                .max_debug_lines_per_sec
+                .or(self.max_debug_lines_per_sec);
+            let runner = container
+                .start_from_checkpoint(
+                    state.active_container_id.as_ref().unwrap(),
+                    checkpoint_name,
+                    stdout_log.as_ref(),
+                    stderr_log.as_ref(),
+                )
+                .instrument(container_span(name, &self.network_name))
+                .await
+                .stack_err_locationless(|| {
+                    format!("ContainerNetwork::restore_all -> when restoring container \"{name}\"")
+                })?;
+            state.run_state = RunState::Active(runner);
+            state.start_time = Some(Instant::now());
+            state.result_retention = self.result_retention;
+            state.checkpoint = None;
+            restored.push(name.clone());
+        }
+        Ok(restored)
+    }
+
+    /// Returns the result of the last `prune_after_teardown` run, if any
+    pub fn last_prune_report(&self) -> Option<&Result<PruneReport>> {
+        self.last_prune_report.as_ref()
+    }
+
+    /// Runs only the given `names`. This prechecks as much as it can before
+    /// creating any containers. If an error happens in the middle of creating
+    /// and starting the containers, any of the `names` that had been created
+    /// are terminated before the function returns.
+    ///
+    /// Containers are started by [Container::start_group], and within a group
+    /// in the [ContainerNetwork::start_order] followed by the order of
+    /// `names`.
+    ///
+    /// # Templates
+    ///
+    /// The `entrypoint_args`, `environment_vars` values, and `create_args` of
+    /// the containers can have placeholders that are expanded when each
+    /// container is created (the stored `Container`s keep the templates):
+    ///
+    /// - `{{uuid}}` is the UUID of the network
+    /// - `{{hostname:<name>}}` is the `host_name` of the container `<name>`
+    /// - `{{ip:<name>}}` is the IP address of the container `<name>`. If that
+    ///   container is in the same run, it is started first and this container
+    ///   is only created after that, otherwise it must already be active.
+    ///   Cycles and dependencies on containers in higher start groups are
+    ///   validation errors.
+    /// - `{{port:<key>}}` is the port reserved with
+    ///   [ContainerNetwork::reserve_port]
+    ///
+    /// `{{{{` is a literal `{{`, and anything else in double braces is a
+    /// validation error.
+    pub async fn run<I, S>(&mut self, names: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
         // avoid polymorphizing such a large function
         self.run_internal(
             &names
@@ -463,258 +2538,1274 @@ impl ContainerNetwork {
         .await
     }
 
-    async fn run_internal(&mut self, names: &[String]) -> Result<()> {
-        let debug_extra = self.debug_extra;
-        if self.debug_build || self.debug_create || self.debug_extra {
-            debug!("ContainerNetwork::run with UUID {}", self.uuid_as_string());
-        }
-        // relatively cheap preverification should be done first to prevent much more
-        // expensive later undos
-        let mut set = BTreeSet::new();
-        for name in names {
-            if set.contains(name) {
+    /// The same as [ContainerNetwork::run], except that the containers with
+    /// the names in `overrides` have their configurations temporarily replaced
+    /// with the override values for this single run. The original
+    /// configurations are restored afterwards regardless of whether the run
+    /// succeeded.
+    ///
+    /// # Errors
+    ///
+    /// If an override is for a name that is not in `names` or not in the
+    /// network, or if the name of an override `Container` does not match its
+    /// key
+    pub async fn run_with_overrides<I, S>(
+        &mut self,
+        names: I,
+        overrides: BTreeMap<String, Container>,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let names = names
+            .into_iter()
+            .map(|s| s.as_ref().to_owned())
+            .collect::<Vec<String>>();
+        // check everything before swapping anything in
+        for (name, container) in &overrides {
+            if !names.contains(name) {
                 return Err(Error::from_kind_locationless(format!(
-                    "ContainerNetwork::run -> two containers were supplied with the same name \
-                     \"{name}\""
+                    "ContainerNetwork::run_with_overrides -> override \"{name}\" is not one of \
+                     the names being run"
                 )))
             }
-            if let Some(state) = self.set.get(name) {
-                if state.is_active() {
-                    return Err(Error::from_kind_locationless(format!(
-                        "ContainerNetwork::run -> name \"{name}\" is already an active container"
-                    )))
-                }
-            } else {
+            if !self.set.contains_key(name) {
                 return Err(Error::from_kind_locationless(format!(
-                    "ContainerNetwork::run -> argument name \"{name}\" is not contained in the \
+                    "ContainerNetwork::run_with_overrides -> override \"{name}\" is not in the \
                      network"
                 )))
             }
-            set.insert(name.to_string());
-        }
-
-        if debug_extra {
-            debug!("prechecking");
+            if &container.name != name {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::run_with_overrides -> override \"{name}\" has a \
+                     `Container` with a different name \"{}\"",
+                    container.name
+                )))
+            }
+            if self.dockerfile_write_dir.is_none()
+                && matches!(container.dockerfile, Dockerfile::Contents(_))
+            {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::run_with_overrides -> override \"{name}\" is built with \
+                     `Dockerfile::Contents`, but `dockerfile_write_dir` is unset"
+                )))
+            }
         }
-
-        let log_file = FileOptions::write2(
-            &self.log_dir,
-            format!("container_network_{}.log", self.network_name()),
-        );
-        log_file.preacquire().await.stack_err_locationless(|| {
-            "ContainerNetwork::run -> could not acquire logs directory"
-        })?;
-
-        for name in names {
-            let container = &mut self.set.get_mut(name).unwrap().container;
-            match container.dockerfile {
-                Dockerfile::NameTag(_) => (),
-                Dockerfile::Path(_) => (),
-                Dockerfile::Contents(_) => {
-                    if let Some(file_path) = &container.dockerfile_write_file {
-                        FileOptions::write(file_path)
-                            .preacquire()
-                            .await
-                            .stack_err_locationless(|| {
-                                format!(
-                                    "ContainerNetwork::run -> could not acquire the explicitly \
-                                     set `dockerfile_write_file` on container with name \"{name}\""
-                                )
-                            })?;
-                    } else if let Some(dir) = &self.dockerfile_write_dir {
-                        let path = FileOptions::write2(dir, format!("{name}.tmp.dockerfile"))
-                            .preacquire()
-                            .await
-                            .stack_err_locationless(|| {
-                                "ContainerNetwork::run -> could not acquire the \
-                                 `dockerfile_write_dir`"
-                            })?;
-                        container.dockerfile_write_file = Some(
-                            path.to_str()
-                                .stack_err_locationless(|| {
-                                    "ContainerNetwork::run -> could not acquire the \
-                                     `dockerfile_write_dir` as a UTF8 path"
-                                })?
-                                .to_owned(),
-                        );
-                    } else {
-                        return Err(Error::from_kind_locationless(format!(
-                            "ContainerNetwork::run -> the `dockerfile_write_dir` on the \
-                             `ContainerNetwork` or the `dockerfile_write_file` on container with \
-                             name \"{name}\" needs to be set"
-                        )));
-                    }
-                }
+        let mut originals = vec![];
+        for (name, mut container) in overrides {
+            if matches!(container.dockerfile, Dockerfile::NameTag(_))
+                && container.pull_policy.is_none()
+            {
+                container.pull_policy = self.default_pull_policy;
             }
+            let state = self.set.get_mut(&name).unwrap();
+            originals.push((
+                name,
+                mem::replace(&mut state.container, container),
+                state.validated,
+            ));
+            state.validated = false;
+        }
+        let res = self.run_internal(&names).await;
+        for (name, original, validated) in originals {
+            let state = self.set.get_mut(&name).unwrap();
+            state.container = original;
+            state.validated = validated;
         }
+        res.stack_err_locationless(|| "ContainerNetwork::run_with_overrides")
+    }
 
+    /// The same as [ContainerNetwork::run], except that the containers are
+    /// created with the `limits` for this single run. The stored
+    /// configurations of the containers are not modified.
+    ///
+    /// # Errors
+    ///
+    /// If any of the `limits` are invalid (see
+    /// [ResourceConstraints::create_args]), if a name is not in the network, or
+    /// if the run itself fails
+    pub async fn run_with_resource_limits(
+        &mut self,
+        names: &[&str],
+        limits: ResourceConstraints,
+    ) -> Result<()> {
+        let create_args = limits
+            .create_args()
+            .stack_err_locationless(|| "ContainerNetwork::run_with_resource_limits")?;
+        let mut overrides = BTreeMap::new();
         for name in names {
-            let container = &mut self.set.get_mut(name).unwrap().container;
-            container.precheck().await.stack_err_locationless(|| {
-                format!("ContainerNetwork::run -> when prechecking container {container:#?}")
+            let state = self.set.get(*name).stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::run_with_resource_limits -> container \"{name}\" is not in \
+                     the network"
+                )
             })?;
+            overrides.insert(
+                (*name).to_owned(),
+                state.container.clone().create_args(&create_args),
+            );
         }
+        self.run_with_overrides(names, overrides)
+            .await
+            .stack_err_locationless(|| "ContainerNetwork::run_with_resource_limits")
+    }
 
-        if debug_extra {
-            debug!("building");
-        }
-
-        // TODO eventually move this capability to the struct level so that it handles
-        // many stage `ContainerNetwork::run`
+    async fn run_internal(&mut self, names: &[String]) -> Result<()> {
+        self.run_with_phase(names).await.map_err(|(e, _)| e)
+    }
 
-        // The trick with the build stage is that we want to build as little as we have
-        // to. The build stage only uses  `dockerfile` and `build_args` with respect to
-        // determinism, so here we order them and reduce redundancies.
-        let mut build_to_image = BTreeMap::<(Dockerfile, Vec<String>), (String, String)>::new();
-        let uuid = self.uuid();
-        for name in names.iter() {
-            let container = &mut self.set.get_mut(name).unwrap().container;
-            if container.build_tag.is_none() {
-                match build_to_image
-                    .entry((container.dockerfile.clone(), container.build_args.clone()))
-                {
-                    Entry::Vacant(v) => {
-                        let image = format!("super_orchestrator_{name}_{uuid}");
-                        container.build_tag = Some(image.clone());
-                        v.insert((name.clone(), image.clone()));
-                    }
-                    Entry::Occupied(o) => {
-                        // set the `build_tag` to an already planned image
-                        container.build_tag = Some(o.get().1.clone());
+    /// The same as `run_internal`, but also returns the phase of a failure
+    async fn run_with_phase(
+        &mut self,
+        names: &[String],
+    ) -> std::result::Result<(), (Error, RunPhase)> {
+        self.first_run.get_or_insert_with(Instant::now);
+        let mut phase = RunPhase::Precheck;
+        let res = self.run_phases(names, &mut phase).await;
+        // in case the phases did not get to logging out
+        self.logout_pull_secrets().await;
+        match res {
+            Ok(()) => Ok(()),
+            // containers only exist from the creation phase onwards
+            Err(e) => {
+                let e = match phase {
+                    RunPhase::Create(_) | RunPhase::Start(_) | RunPhase::StartGroupReady(_) => {
+                        self.run_failure_cleanup(names, &phase, e).await
                     }
-                }
-            } // else it was explicitly set or built in a previous run
+                    _ => e.add_kind_locationless(format!(
+                        "ContainerNetwork::run -> failed in phase {phase}, no containers were \
+                         created"
+                    )),
+                };
+                Err((e, phase))
+            }
         }
+    }
 
-        // run all the build commands that we actually need
-        for (name, _) in build_to_image.values() {
-            let state = self.set.get_mut(name).unwrap();
-            state
-                .container()
-                .build(self.debug_build)
-                .await
-                .stack_err_locationless(|| {
-                    format!("ContainerNetwork::run when building the container for name \"{name}\"")
-                })?;
-        }
+    /// Validates the definition of the network and all of its containers
+    /// without running any docker commands, so that this can be used as a
+    /// cheap lint step on machines without docker. This checks the network
+    /// arguments, that paths exist, the lock file, the
+    /// [ContainerNetwork::start_order], and for conflicts between the
+    /// containers such as duplicate `container_name`s or published host ports.
+    ///
+    /// This does not modify the network or create anything on the host, the
+    /// checks are done on copies of the containers with their paths rebased
+    /// and canonicalized like `run` does. Errors in the definition are returned
+    /// in the [ValidationReport] along with warnings. An `Err` is only returned
+    /// if the `base_dir` cannot be acquired. [ContainerNetwork::run] runs the
+    /// same validation before preparing the containers, but containers that
+    /// passed it in an earlier `run` (and were not modified since) do not
+    /// have their paths checked again.
+    pub async fn validate(&self) -> Result<ValidationReport> {
+        let names: Vec<String> = self.set.keys().cloned().collect();
+        let checked = self.check_names(&names, false).await?;
+        Ok(checked.report)
+    }
 
-        if debug_extra {
-            debug!("creating");
+    /// Validates the containers with `names` and then prepares them for
+    /// `run`, rebasing the paths of the network and storing the prepared
+    /// containers that passed. Also returns the error of the first failed
+    /// check.
+    async fn prepare_names(
+        &mut self,
+        names: &[String],
+    ) -> Result<(ValidationReport, Option<Error>)> {
+        let checked = self.check_names(names, true).await?;
+        self.log_dir = checked.log_dir;
+        self.dockerfile_write_dir = checked.dockerfile_write_dir;
+        self.build_cache_dir = checked.build_cache_dir;
+        self.lock_file = checked.lock_file;
+        for (name, container) in checked.passed {
+            let state = self.set.get_mut(&name).unwrap();
+            state.container = container;
+            state.validated = true;
+        }
+        for dir in &checked.new_crash_dirs {
+            self.resources.record(ResourceKind::HostDir, dir, None);
         }
+        Ok((checked.report, checked.first_error))
+    }
 
-        if !self.network_active {
-            // remove old network if it exists (there is no option to ignore nonexistent
-            // networks, drop exit status errors and let the creation command handle any
-            // higher order errors)
-            /*let _ = Command::new("docker network rm", &[&self.network_name()])
-            .debug(false)
-            .stdout_log(&debug_log)
-            .stderr_log(&debug_log)
-            .run_to_completion()
-            .await;*/
-            let comres = Command::new("docker network create")
-                .args(self.network_args.iter())
-                .arg(self.network_name())
-                .run_to_completion()
-                .await
-                .stack_err_locationless(|| {
-                    "ContainerNetwork::run -> when running network creation command"
-                })?;
-            // TODO we can get the network id
-            comres
-                .assert_success()
-                .stack_err_locationless(|| "ContainerNetwork::run -> failed to create network")?;
-            self.network_active = true;
+    /// Checks the containers with `names` on prepared copies. If `preparing`,
+    /// containers that are already validated are not checked again, and the
+    /// directories that `run` needs are created.
+    async fn check_names(&self, names: &[String], preparing: bool) -> Result<CheckedNames> {
+        let mut report = ValidationReport::default();
+        let mut first_error = None;
+        let mut push_error = |report: &mut ValidationReport, name: Option<&str>, e: Error| {
+            report.error(name, format!("{e:?}"));
+            first_error.get_or_insert(e);
+        };
+
+        if let Err(e) = self.check_internal_network_args() {
+            push_error(&mut report, None, e);
+        }
+        if let Err(e) = self.check_driver_network_args() {
+            push_error(&mut report, None, e);
         }
 
-        // run all of the creation first so that everything is pulled and prepared
-        let network_name = &self.network_name;
-        for (i, name) in names.iter().enumerate() {
-            let state = self.set.get_mut(name).unwrap();
-            match state
-                .container()
-                .create(network_name, None, self.debug_create)
+        let mut log_dir = self.log_dir.clone();
+        let mut dockerfile_write_dir = self.dockerfile_write_dir.clone();
+        let mut build_cache_dir = self.build_cache_dir.clone();
+        let mut lock_file = self.lock_file.clone();
+        let mut containers: BTreeMap<&str, (Container, bool)> = names
+            .iter()
+            .map(|name| {
+                let state = &self.set[name];
+                (
+                    name.as_str(),
+                    (state.container.clone(), preparing && state.validated),
+                )
+            })
+            .collect();
+        if let Some(ref base_dir) = self.base_dir {
+            let base_dir = acquire_dir_path(base_dir)
                 .await
                 .stack_err_locationless(|| {
-                    format!("ContainerNetwork::run when creating the container for name \"{name}\"")
-                }) {
-                Ok(docker_id) => {
-                    state.active_container_id = Some(docker_id);
+                    "ContainerNetwork::validate -> could not acquire the `base_dir`"
+                })?;
+            let rebase = |path: &mut String| {
+                if Path::new(path.as_str()).is_relative() {
+                    *path = base_dir.join(path.as_str()).to_string_lossy().into_owned();
                 }
-                Err(e) => {
-                    // need to fix all the containers in the intermediate state
-                    for name in &names[..i] {
-                        let _ = self.set.get_mut(name).unwrap().terminate().await;
-                    }
-                    e.stack_err_locationless(|| {
-                        format!(
-                            "ContainerNetwork::run when creating the container for name \"{name}\""
-                        )
-                    })?;
+            };
+            rebase(&mut log_dir);
+            for path in [
+                &mut dockerfile_write_dir,
+                &mut build_cache_dir,
+                &mut lock_file,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                rebase(path);
+            }
+            for (container, validated) in containers.values_mut() {
+                if !*validated {
+                    container.rebase_paths(&base_dir);
                 }
             }
         }
 
-        if debug_extra {
-            debug!("starting");
+        let log_file = FileOptions::write2(
+            &log_dir,
+            format!("container_network_{}.log", self.network_name()),
+        );
+        if let Err(e) = log_file.preacquire().await {
+            push_error(
+                &mut report,
+                None,
+                e.add_kind_locationless(
+                    "ContainerNetwork::validate -> could not acquire logs directory",
+                ),
+            );
         }
 
-        // start containers
-        for name in names {
-            let state = self.set.get_mut(name).unwrap();
-            let (stdout_log, stderr_log) = if state.container.log {
-                (
-                    Some(state.container.stdout_log.clone().unwrap_or_else(|| {
-                        FileOptions::write2(&self.log_dir, format!("{}_stdout.log", name))
-                    })),
-                    Some(state.container.stderr_log.clone().unwrap_or_else(|| {
-                        FileOptions::write2(&self.log_dir, format!("{}_stderr.log", name))
-                    })),
-                )
-            } else {
-                (None, None)
-            };
-            match state
-                .container()
-                .start(
-                    state.active_container_id.as_ref().unwrap(),
-                    stdout_log.as_ref(),
-                    stderr_log.as_ref(),
+        let mut new_crash_dirs = vec![];
+        for (name, (container, validated)) in &mut containers {
+            if *validated {
+                continue
+            }
+            match self
+                .check_container(
+                    name,
+                    container,
+                    &log_dir,
+                    dockerfile_write_dir.as_deref(),
+                    preparing,
                 )
                 .await
-                .stack_err_locationless(|| {
-                    format!("ContainerNetwork::run when starting the container for name \"{name}\"")
-                }) {
-                Ok(runner) => {
-                    state.run_state = RunState::Active(runner);
-                }
-                Err(e) => {
-                    for name in names.iter() {
-                        let _ = self.set.get_mut(name).unwrap().terminate().await;
-                    }
-                    return Err(e)
+            {
+                Ok(new_crash_dir) => {
+                    *validated = true;
+                    new_crash_dirs.extend(new_crash_dir);
                 }
+                Err(e) => push_error(&mut report, Some(name), e),
             }
         }
 
-        if debug_extra {
-            debug!("started");
+        // a committed lock file pins the images of the containers in it
+        match read_lock_file(lock_file.as_deref()).await {
+            Ok(locked) => {
+                for (name, (container, _)) in &mut containers {
+                    let (Dockerfile::NameTag(_), Some(digest)) =
+                        (&container.dockerfile, locked.get(*name))
+                    else {
+                        continue
+                    };
+                    match container.pinned_digest {
+                        Some(ref pinned) if pinned != digest => push_error(
+                            &mut report,
+                            Some(name),
+                            Error::from_kind_locationless(format!(
+                                "ContainerNetwork::validate -> container \"{name}\" has \
+                                 `pinned_digest` {pinned}, but the lock file has {digest}"
+                            )),
+                        ),
+                        _ => container.pinned_digest = Some(digest.clone()),
+                    }
+                }
+            }
+            Err(e) => push_error(&mut report, None, e),
         }
 
-        Ok(())
-    }
-
-    /// [ContainerNetwork::run] on all inactive containers in the network. Note
-    /// that terminated containers that weren't removed are recreated.
-    pub async fn run_all(&mut self) -> Result<()> {
-        let names = self.inactive_names();
-        let mut v: Vec<&str> = vec![];
-        for name in &names {
-            v.push(name);
+        // the findings use the prepared copies of the containers in `names`
+        let get = |name: &str| match containers.get(name) {
+            Some((container, _)) => Some(container),
+            None => self.set.get(name).map(|state| &state.container),
+        };
+        let errors_before = report.errors.len();
+        static_findings(
+            containers.values().map(|(container, _)| container),
+            self.uuid,
+            &mut report,
+        );
+        start_order_findings(&self.start_order, get, &mut report);
+        template_findings(names, get, &self.reserved_ports, &mut report);
+        if let Some(finding) = report.errors.get(errors_before) {
+            first_error.get_or_insert_with(|| {
+                Error::from_kind_locationless(format!("ContainerNetwork::validate -> {finding}"))
+            });
+        }
+        let passed = containers
+            .into_iter()
+            .filter(|(_, (_, validated))| *validated)
+            .map(|(name, (container, _))| (name.to_owned(), container))
+            .collect();
+        Ok(CheckedNames {
+            report,
+            first_error,
+            log_dir,
+            dockerfile_write_dir,
+            build_cache_dir,
+            lock_file,
+            passed,
+            new_crash_dirs,
+        })
+    }
+
+    /// The checks of [ContainerNetwork::validate] that only need to be done
+    /// once for the copy `container` of the container `name`. Returns the
+    /// crash artifacts directory if it was created because of `preparing`.
+    async fn check_container(
+        &self,
+        name: &str,
+        container: &mut Container,
+        log_dir: &str,
+        dockerfile_write_dir: Option<&str>,
+        preparing: bool,
+    ) -> Result<Option<String>> {
+        if let Dockerfile::Contents(_) = container.dockerfile {
+            if let Some(file_path) = &container.dockerfile_write_file {
+                FileOptions::write(file_path)
+                    .preacquire()
+                    .await
+                    .stack_err_locationless(|| {
+                        format!(
+                            "ContainerNetwork::validate -> could not acquire the explicitly set \
+                             `dockerfile_write_file` on container with name \"{name}\""
+                        )
+                    })?;
+            } else if let Some(dir) = dockerfile_write_dir {
+                let path = FileOptions::write2(dir, format!("{name}.tmp.dockerfile"))
+                    .preacquire()
+                    .await
+                    .stack_err_locationless(|| {
+                        "ContainerNetwork::validate -> could not acquire the `dockerfile_write_dir`"
+                    })?;
+                container.dockerfile_write_file = Some(
+                    path.to_str()
+                        .stack_err_locationless(|| {
+                            "ContainerNetwork::validate -> could not acquire the \
+                             `dockerfile_write_dir` as a UTF8 path"
+                        })?
+                        .to_owned(),
+                );
+            } else {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::validate -> the `dockerfile_write_dir` on the \
+                     `ContainerNetwork` or the `dockerfile_write_file` on container with name \
+                     \"{name}\" needs to be set"
+                )));
+            }
+        }
+
+        if container.entrypoint_args_file && container.entrypoint_args_write_file.is_none() {
+            let path = FileOptions::write2(log_dir, format!("{}_{name}.args", self.network_name))
+                .preacquire()
+                .await
+                .stack_err_locationless(|| {
+                    "ContainerNetwork::validate -> could not acquire the entrypoint args file in \
+                     the log directory"
+                })?;
+            container.entrypoint_args_write_file = Some(path.to_string_lossy().into_owned());
+        }
+
+        if container.command_middleware.is_none() {
+            container
+                .command_middleware
+                .clone_from(&self.command_middleware);
+        }
+        let new_crash_dir = match container.crash_artifacts_dir {
+            Some(ref dir) => preparing && !resolve(Path::new(dir)).0.exists(),
+            None => false,
+        };
+        container
+            .precheck_without_docker(preparing)
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::validate -> when prechecking container {container:#?}")
+            })?;
+        Ok(if new_crash_dir {
+            container.crash_artifacts_dir.clone()
+        } else {
+            None
+        })
+    }
+
+    /// Checks that the `name_tag` image of container `name` exists locally,
+    /// pulling it or checking the registry according to the settings. Only
+    /// returns an error if docker says the image does not exist.
+    async fn image_preflight(&self, name: &str, name_tag: &str, never_pull: bool) -> Result<()> {
+        let comres = match self
+            .wrap(docker_command("image inspect").arg(name_tag))
+            .run_to_completion()
+            .await
+        {
+            Ok(comres) => comres,
+            Err(e) => {
+                debug!("ContainerNetwork::run -> image preflight of \"{name_tag}\" failed: {e:?}");
+                return Ok(())
+            }
+        };
+        if comres.successful() {
+            return Ok(())
+        }
+        let stderr = comres.stderr_as_utf8_lossy();
+        if !stderr.to_lowercase().contains("no such image") {
+            debug!("ContainerNetwork::run -> `docker image inspect {name_tag}` failed: {stderr}");
+            return Ok(())
+        }
+        if never_pull {
+            return Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::run -> the image \"{name_tag}\" of container \"{name}\" is not \
+                 present locally, and its `pull_policy` is `PullPolicy::Never`"
+            )))
+        }
+        let args = if self.pull_before_create {
+            "pull"
+        } else {
+            "manifest inspect"
+        };
+        let comres = match self
+            .wrap(docker_command(args).arg(name_tag))
+            .run_to_completion()
+            .await
+        {
+            Ok(comres) => comres,
+            Err(e) => {
+                debug!("ContainerNetwork::run -> image preflight of \"{name_tag}\" failed: {e:?}");
+                return Ok(())
+            }
+        };
+        if comres.successful() {
+            return Ok(())
+        }
+        let stderr = comres.stderr_as_utf8_lossy();
+        let lowercase = stderr.to_lowercase();
+        if [
+            "manifest unknown",
+            "no such manifest",
+            "not found",
+            "repository does not exist",
+        ]
+        .iter()
+        .any(|s| lowercase.contains(s))
+        {
+            Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::run -> the image \"{name_tag}\" of container \"{name}\" does \
+                 not exist locally or in its registry, `docker {args}` failed with: {}",
+                stderr.trim()
+            )))
+        } else {
+            debug!("ContainerNetwork::run -> `docker {args} {name_tag}` failed: {stderr}");
+            Ok(())
+        }
+    }
+
+    /// Runs `docker logout` for the registries logged into by `run`, failures
+    /// are only warned about
+    async fn logout_pull_secrets(&mut self) {
+        for registry in mem::take(&mut self.pull_secret_logins) {
+            let res = self
+                .wrap(docker_command("logout").arg(&registry))
+                .run_to_completion()
+                .await
+                .and_then(|comres| comres.assert_success());
+            if let Err(e) = res {
+                warn!(
+                    "ContainerNetwork::run -> `docker logout` of registry \"{registry}\" failed: \
+                     {e:?}"
+                );
+                emit_crate_event(|| CrateEvent::LogoutFailed {
+                    registry,
+                    error: format!("{e:?}"),
+                });
+            }
+        }
+    }
+
+    /// Terminates any of `names` that were created by a failed
+    /// [ContainerNetwork::run], returning `e` with the `phase`, the containers
+    /// that were cleaned up, and any errors from cleaning up
+    async fn run_failure_cleanup(&mut self, names: &[String], phase: &RunPhase, e: Error) -> Error {
+        let mut cleaned_up = vec![];
+        let mut cleanup_errors = vec![];
+        for name in names {
+            let state = self.set.get_mut(name).unwrap();
+            let was_created = state.active_container_id.is_some();
+            match state
+                .terminate_with_outcome(&mut self.resources, &mut self.last_teardown_report)
+                .await
+            {
+                TerminateOutcome::Terminated(_) => cleaned_up.push(name.clone()),
+                TerminateOutcome::WasNotActive | TerminateOutcome::NotFound => {
+                    if was_created {
+                        cleaned_up.push(name.clone());
+                    }
+                }
+                TerminateOutcome::RunnerError(cleanup_e) => cleanup_errors.push((name, cleanup_e)),
+            }
+        }
+        let mut e = e.add_kind_locationless(format!(
+            "ContainerNetwork::run -> failed in phase {phase}, containers cleaned up: \
+             {cleaned_up:?}, the network after cleanup is:\n{self}"
+        ));
+        for (name, cleanup_e) in cleanup_errors {
+            e = e.add_kind_locationless(format!(
+                "ContainerNetwork::run -> cleanup of container \"{name}\" also failed: \
+                 {cleanup_e:?}"
+            ));
+        }
+        e
+    }
+
+    /// The body of [ContainerNetwork::run], `phase` is kept up to date so that
+    /// the caller knows what needs to be cleaned up after an error
+    async fn run_phases(&mut self, names: &[String], phase: &mut RunPhase) -> Result<()> {
+        let debug_extra = self.debug_extra;
+        if self.debug_build || self.debug_create || self.debug_extra {
+            debug!("ContainerNetwork::run with UUID {}", self.uuid_as_string());
+        }
+        // relatively cheap preverification should be done first to prevent much more
+        // expensive later undos
+        let mut set = BTreeSet::new();
+        for name in names {
+            if set.contains(name) {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::run -> two containers were supplied with the same name \
+                     \"{name}\""
+                )))
+            }
+            if let Some(state) = self.set.get(name) {
+                if state.is_active() {
+                    return Err(Error::from_kind_locationless(format!(
+                        "ContainerNetwork::run -> name \"{name}\" is already an active container"
+                    )))
+                }
+                if let Some(ref checkpoint) = state.checkpoint {
+                    return Err(Error::from_kind_locationless(format!(
+                        "ContainerNetwork::run -> name \"{name}\" is suspended in checkpoint \
+                         \"{checkpoint}\", use `restore_all` or terminate it first"
+                    )))
+                }
+            } else {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::run -> argument name \"{name}\" is not contained in the \
+                     network"
+                )))
+            }
+            set.insert(name.to_string());
+        }
+        if debug_extra {
+            debug!("prechecking");
+        }
+
+        let (report, first_error) = self.prepare_names(names).await?;
+        for warning in &report.warnings {
+            warn!("ContainerNetwork::run -> {warning}");
+        }
+        if let Some(e) = first_error {
+            let n = report.errors.len();
+            if n > 1 {
+                return Err(e.add_kind_locationless(format!(
+                    "ContainerNetwork::run -> validation found {n} errors, the first is"
+                )))
+            }
+            return Err(e)
+        }
+        let internal_in_args = self.check_internal_network_args()?;
+        for name in names {
+            let container = &self.set[name].container;
+            if (!container.build_secrets.is_empty()) || container.build_ssh.is_some() {
+                check_buildkit_support().await.stack_err_locationless(|| {
+                    format!(
+                        "ContainerNetwork::run -> container \"{name}\" has `build_secrets` or \
+                         `build_ssh` set"
+                    )
+                })?;
+            }
+        }
+
+        // log into the registries of the images that will be pulled
+        let mut registries = BTreeSet::new();
+        for name in names {
+            if let Dockerfile::NameTag(ref name_tag) = self.set[name].container.dockerfile {
+                registries.insert(registry_of_name_tag(name_tag).to_owned());
+            }
+        }
+        for registry in registries {
+            let Some(secret) = self.pull_secrets.iter().find(|s| s.registry == registry) else {
+                continue
+            };
+            *phase = RunPhase::Login(registry.clone());
+            let comres = self
+                .wrap(
+                    docker_command("login --username")
+                        .arg(&secret.username)
+                        .arg("--password-stdin")
+                        .arg(&registry),
+                )
+                .run_with_input_to_completion(secret.password.as_bytes())
+                .await
+                .stack_err_locationless(|| {
+                    "ContainerNetwork::run -> when running `docker login`"
+                })?;
+            comres.assert_success().stack_err_locationless(|| {
+                format!("ContainerNetwork::run -> `docker login` to registry \"{registry}\" failed")
+            })?;
+            self.pull_secret_logins.push(registry);
+        }
+
+        let mut preflighted = BTreeSet::new();
+        for name in names {
+            let container = &self.set[name].container;
+            let Dockerfile::NameTag(ref name_tag) = container.dockerfile else {
+                continue
+            };
+            let never = container.pull_policy == Some(PullPolicy::Never);
+            if (never || self.pull_before_create || self.verify_remote_images)
+                && preflighted.insert(name_tag.clone())
+            {
+                *phase = RunPhase::ImagePreflight(name.clone());
+                self.image_preflight(name, name_tag, never).await?;
+            }
+        }
+
+        if debug_extra {
+            debug!("building");
+        }
+
+        // pulling for `PullPolicy::{Always, Newer}` is done here so that it does not
+        // count against the start of the containers
+        let mut pulled = BTreeSet::new();
+        for name in names {
+            let container = &self.set[name].container;
+            let Dockerfile::NameTag(ref name_tag) = container.dockerfile else {
+                continue
+            };
+            let Some(policy) = container.pull_policy.filter(|p| p.pulls_before_create()) else {
+                continue
+            };
+            if pulled.insert(name_tag.clone()) {
+                *phase = RunPhase::Build(name.clone());
+                pull_with_policy(name, name_tag, policy, self.command_middleware.as_ref()).await?;
+            }
+        }
+
+        for name in names {
+            let container = &self.set[name].container;
+            let (Dockerfile::NameTag(ref name_tag), Some(ref pinned)) =
+                (&container.dockerfile, &container.pinned_digest)
+            else {
+                continue
+            };
+            *phase = RunPhase::ImagePreflight(name.clone());
+            let local = inspect_image_digest_with(self.command_middleware.as_ref(), name_tag)
+                .await
+                .stack_err_locationless(|| {
+                    format!(
+                        "ContainerNetwork::run -> when verifying the pinned digest of \"{name}\""
+                    )
+                })?;
+            if let Some(repo_digest) = local.and_then(|image| image.repo_digest) {
+                if &repo_digest != pinned {
+                    return Err(Error::from_kind_locationless(format!(
+                        "ContainerNetwork::run -> the local image \"{name_tag}\" of container \
+                         \"{name}\" resolves to digest {repo_digest}, but {pinned} is pinned"
+                    )))
+                }
+            }
+        }
+
+        // The trick with the build stage is that we want to build as little as we have
+        // to. The build stage only uses  `dockerfile` and `build_args` with respect to
+        // determinism, so here we order them and reduce redundancies. The plans are
+        // kept across `run`s, and an image is only marked as built after its
+        // build succeeds so that a cancelled or failed `run` does not leave
+        // containers with unbuilt tags.
+        let mut to_build = BTreeMap::<(Dockerfile, Vec<String>), String>::new();
+        let uuid = self.uuid();
+        for name in names.iter() {
+            let container = &mut self.set.get_mut(name).unwrap().container;
+            let key = (container.dockerfile.clone(), container.build_args.clone());
+            if container.build_tag.is_none() {
+                let planned =
+                    self.planned_builds
+                        .entry(key.clone())
+                        .or_insert_with(|| PlannedBuild {
+                            image: format!("super_orchestrator_{name}_{uuid}"),
+                            built: false,
+                        });
+                container.build_tag = Some(planned.image.clone());
+            } // else it was explicitly set or planned in a previous run
+            if let Some(planned) = self.planned_builds.get(&key) {
+                if (!planned.built) && (container.build_tag.as_ref() == Some(&planned.image)) {
+                    to_build.entry(key).or_insert_with(|| name.clone());
+                }
+            }
+        }
+
+        let build_cache_dir = if let Some(ref dir) = self.build_cache_dir {
+            let dir = acquire_dir_path(dir).await.stack_err_locationless(|| {
+                "ContainerNetwork::run -> could not acquire the `build_cache_dir`"
+            })?;
+            Some(
+                dir.to_str()
+                    .stack_err_locationless(|| {
+                        "ContainerNetwork::run -> `build_cache_dir` was not UTF-8"
+                    })?
+                    .to_owned(),
+            )
+        } else {
+            None
+        };
+
+        // run all the build commands that we actually need
+        for (key, name) in &to_build {
+            let image = &self.planned_builds[key].image;
+            *phase = RunPhase::Build(name.clone());
+            let state = self.set.get_mut(name).unwrap();
+            let default_on_build_progress = self
+                .on_build_progress
+                .as_ref()
+                .filter(|_| state.container().on_build_progress.is_none());
+            let res = if build_cache_dir.is_some() || default_on_build_progress.is_some() {
+                // the cache arguments and callback are only added to a copy so that they do not
+                // affect the build deduplication or accumulate across runs
+                let mut container = state.container().clone();
+                if let Some(on_build_progress) = default_on_build_progress {
+                    container.on_build_progress = Some(on_build_progress.clone());
+                }
+                if let Some(ref cache_dir) = build_cache_dir {
+                    let key = container.build_cache_key.as_ref().unwrap_or(image);
+                    let cache_from = format!("type=local,src={cache_dir}/{key}");
+                    let cache_to = format!("type=local,dest={cache_dir}/{key},mode=max");
+                    container.build_args.extend([
+                        "--cache-from".to_owned(),
+                        cache_from,
+                        "--cache-to".to_owned(),
+                        cache_to,
+                    ]);
+                }
+                container.build_with_stats(self.debug_build).await
+            } else {
+                state.container().build_with_stats(self.debug_build).await
+            };
+            let build_stats = res.stack_err_locationless(|| {
+                format!("ContainerNetwork::run when building the container for name \"{name}\"")
+            })?;
+            if let Some(build_stats) = build_stats {
+                if self.debug_build {
+                    debug!("ContainerNetwork::run -> build for name \"{name}\": {build_stats}");
+                }
+                // every container sharing the build gets the stats
+                for name in names {
+                    let state = self.set.get_mut(name).unwrap();
+                    let container = &state.container;
+                    if (&container.dockerfile, &container.build_args) == (&key.0, &key.1) {
+                        state.build_stats = Some(build_stats.clone());
+                    }
+                }
+            }
+            let state = &self.set[name];
+            match (
+                &state.container.dockerfile,
+                &state.container.dockerfile_write_file,
+            ) {
+                // nothing is built
+                (Dockerfile::NameTag(_), _) => (),
+                (Dockerfile::Contents(_), Some(path)) => {
+                    self.resources.record_once(ResourceKind::TempFile, path);
+                    self.resources.record(ResourceKind::Image, image, None);
+                }
+                _ => self.resources.record(ResourceKind::Image, image, None),
+            }
+            self.planned_builds.get_mut(key).unwrap().built = true;
+        }
+
+        // extract anything requested from the images
+        for name in names {
+            let state = self.set.get_mut(name).unwrap();
+            if state.container.extract_after_build.is_empty() {
+                continue
+            }
+            *phase = RunPhase::Build(name.clone());
+            let image = match state.container.dockerfile {
+                Dockerfile::NameTag(ref name_tag) => name_tag.clone(),
+                Dockerfile::Path(_) | Dockerfile::Contents(_) => {
+                    state.container.build_tag.clone().unwrap()
+                }
+            };
+            state.extracted_paths.clear();
+            for (container_path, host_dest) in &state.container.extract_after_build {
+                let path = extract_from_image(&image, container_path, host_dest)
+                    .await
+                    .stack_err_locationless(|| {
+                        format!(
+                            "ContainerNetwork::run when extracting from the image for name \
+                             \"{name}\""
+                        )
+                    })?;
+                state.extracted_paths.push(path);
+            }
+        }
+
+        if debug_extra {
+            debug!("creating");
+        }
+
+        if !self.network_active {
+            *phase = RunPhase::NetworkCreate;
+            // remove old network if it exists (there is no option to ignore nonexistent
+            // networks, drop exit status errors and let the creation command handle any
+            // higher order errors)
+            /*let _ = Command::new("docker network rm", &[&self.network_name()])
+            .debug(false)
+            .stdout_log(&debug_log)
+            .stderr_log(&debug_log)
+            .run_to_completion()
+            .await;*/
+            let mut command = docker_command("network create");
+            if self.internal && (!internal_in_args) {
+                command = command.arg("--internal");
+            }
+            // if a previous creation was interrupted, the network may already exist
+            let retrying = self.network_create_pending;
+            self.network_create_pending = true;
+            let comres = self
+                .wrap(
+                    command
+                        .args(self.network_args.iter())
+                        .arg(self.network_name()),
+                )
+                .run_to_completion()
+                .await
+                .stack_err_locationless(|| {
+                    "ContainerNetwork::run -> when running network creation command"
+                })?;
+            let already_created =
+                retrying && comres.stderr_as_utf8_lossy().contains("already exists");
+            if already_created {
+                self.resources
+                    .record(ResourceKind::Network, &self.network_name, None);
+            } else {
+                if let Err(e) = comres.assert_success() {
+                    self.network_create_pending = false;
+                    return Err(e
+                        .add_kind_locationless("ContainerNetwork::run -> failed to create network"))
+                }
+                let id = comres.stdout_as_utf8_lossy();
+                self.resources
+                    .record(ResourceKind::Network, &self.network_name, Some(id.trim()));
+            }
+            self.network_create_pending = false;
+            self.network_active = true;
+        }
+
+        // containers with templates using the IP addresses of containers in this run
+        // can only be created once those have started
+        let deferred: BTreeSet<&String> = names
+            .iter()
+            .filter(|name| {
+                ip_dependencies(&self.set[*name].container)
+                    .iter()
+                    .any(|other| names.contains(other))
+            })
+            .collect();
+
+        self.topology_file = self.write_topology_file().await?;
+
+        // run all of the creation first so that everything is pulled and prepared
+        for name in names {
+            if deferred.contains(name) {
+                continue
+            }
+            *phase = RunPhase::Create(name.clone());
+            let state = self.set.get_mut(name).unwrap();
+            state.supervise_history.clear();
+            state.restart_times.clear();
+            state.restart_at = None;
+            self.create_container(name).await?;
+        }
+
+        // everything has been pulled
+        self.logout_pull_secrets().await;
+
+        self.record_image_digests(names).await;
+        if self.lock_file.is_some() {
+            self.write_lock_file(names).await?;
+        }
+
+        if debug_extra {
+            debug!("starting");
+        }
+
+        // start containers
+        let groups = self.start_sequence(names);
+        for (i, (group, group_names)) in groups.iter().enumerate() {
+            for name in group_names {
+                if deferred.contains(name) {
+                    *phase = RunPhase::Create(name.clone());
+                    let state = self.set.get_mut(name).unwrap();
+                    state.supervise_history.clear();
+                    state.restart_times.clear();
+                    state.restart_at = None;
+                    self.create_container(name).await?;
+                }
+                *phase = RunPhase::Start(name.clone());
+                self.start_container(name, false).await?;
+            }
+            let Some(ready_timeout) = self.start_group_ready_timeout else {
+                continue
+            };
+            if i + 1 == groups.len() {
+                break
+            }
+            let probed: Vec<&str> = group_names
+                .iter()
+                .filter(|name| self.set[*name].container.readiness.is_some())
+                .map(|name| name.as_str())
+                .collect();
+            if !probed.is_empty() {
+                *phase = RunPhase::StartGroupReady(*group);
+                self.wait_ready(&probed, ready_timeout).await?;
+            }
+        }
+
+        if debug_extra {
+            debug!("started");
+        }
+
+        Ok(())
+    }
+
+    /// Orders `names` into start groups, see [ContainerNetwork::start_order]
+    fn start_sequence(&self, names: &[String]) -> Vec<(u32, Vec<String>)> {
+        let mut sorted: Vec<&String> = names.iter().collect();
+        // the sort is stable, so unlisted names keep their order
+        sorted.sort_by_key(|name| {
+            (
+                self.set[*name].container.start_group,
+                self.start_order
+                    .iter()
+                    .position(|listed| listed == *name)
+                    .unwrap_or(usize::MAX),
+            )
+        });
+        let mut groups: Vec<(u32, Vec<String>)> = vec![];
+        for name in sorted {
+            let group = self.set[name].container.start_group;
+            match groups.last_mut() {
+                Some((last, group_names)) if *last == group => group_names.push(name.clone()),
+                _ => groups.push((group, vec![name.clone()])),
+            }
+        }
+        // containers using the IP addresses of others in the same group are moved
+        // after them, validation has ruled out cycles
+        for (_, group_names) in &mut groups {
+            let mut remaining = mem::take(group_names);
+            while !remaining.is_empty() {
+                let i = remaining
+                    .iter()
+                    .position(|name| {
+                        ip_dependencies(&self.set[name].container)
+                            .iter()
+                            .all(|other| (other == name) || !remaining.contains(other))
+                    })
+                    .unwrap_or(0);
+                group_names.push(remaining.remove(i));
+            }
+        }
+        groups
+    }
+
+    /// Returns the container `name` with its templates expanded and its final
+    /// names
+    async fn expanded_container(&self, name: &str) -> Result<Container> {
+        let mut container = self.set[name].container.clone();
+        (container.container_name, container.host_name) = final_names(&container, self.uuid);
+        let mut ips = BTreeMap::new();
+        for placeholder in placeholders(&container) {
+            if let Placeholder::Ip(ref other) = placeholder {
+                let ip = self
+                    .wait_get_ip_addr(50, Duration::from_millis(100), other)
+                    .await
+                    .stack_err_locationless(|| {
+                        format!("when resolving the template placeholder \"{{{{ip:{other}}}}}\"")
+                    })?;
+                ips.insert(other.clone(), ip.to_string());
+            }
+        }
+        expand_templates(&mut container, |placeholder| match placeholder {
+            Placeholder::Uuid => self.uuid_as_string(),
+            Placeholder::Hostname(other) => self.host_name(other).unwrap_or_default(),
+            Placeholder::Ip(other) => ips[other].clone(),
+            Placeholder::Port(key) => self
+                .reserved_ports
+                .get(key)
+                .map(|port| port.to_string())
+                .unwrap_or_default(),
+        })?;
+        Ok(container)
+    }
+
+    /// Runs the `before_create` hooks and creates the container `name`
+    async fn create_container(&mut self, name: &str) -> Result<()> {
+        // containers created without `--rm` remain after they exit until they are
+        // terminated
+        let state = self.set.get_mut(name).unwrap();
+        if state.active_container_id.is_some() {
+            let _ = state
+                .terminate_with_outcome(&mut self.resources, &mut self.last_teardown_report)
+                .await;
+        }
+        self.run_hooks(name, HookPhase::BeforeCreate, None)
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::run before creating the container for name \"{name}\"")
+            })?;
+        let mut container = self
+            .expanded_container(name)
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::run when expanding the templates of container \"{name}\""
+                )
+            })?;
+        if let Some(ref path) = self.topology_file {
+            container
+                .volumes
+                .push((path.clone(), format!("{TOPOLOGY_FILE}:ro")));
+            container
+                .environment_vars
+                .push((TOPOLOGY_ENV_VAR.to_owned(), TOPOLOGY_FILE.to_owned()));
+        }
+        let network_name = &self.network_name;
+        let state = self.set.get_mut(name).unwrap();
+        let docker_id = container
+            .create(network_name, None, self.debug_create)
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::run when creating the container for name \"{name}\"")
+            })?;
+        self.resources.record(
+            ResourceKind::Container,
+            &container.container_name,
+            Some(&docker_id),
+        );
+        if state.container.docker_socket.is_some() {
+            self.resources
+                .set_daemon_access(ResourceKind::Container, &container.container_name);
+        }
+        if let (true, Some(path)) = (
+            state.container.entrypoint_args_file,
+            &state.container.entrypoint_args_write_file,
+        ) {
+            self.resources.record_once(ResourceKind::TempFile, path);
+        }
+        state.active_container_id = Some(docker_id);
+        state.active_container_name = Some(container.container_name);
+        Ok(())
+    }
+
+    /// Starts the created container `name` and runs the `after_start` hooks.
+    /// If `append_logs` is set, the log files are appended to instead of
+    /// truncated.
+    async fn start_container(&mut self, name: &str, append_logs: bool) -> Result<()> {
+        let state = self.set.get_mut(name).unwrap();
+        let (stdout_log, stderr_log) =
+            container_log_files(&state.container, &self.log_dir, append_logs);
+        // apply the network defaults if the container does not have its own limits
+        let mut container = state.container().clone();
+        container.log_limit = container.log_limit.or(self.max_log_size);
+        container.record_limit = container.record_limit.or(self.max_record_size);
+        let runner = container
+            .start(
+                state.active_container_id.as_ref().unwrap(),
+                stdout_log.as_ref(),
+                stderr_log.as_ref(),
+            )
+            .instrument(container_span(name, &self.network_name))
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::run when starting the container for name \"{name}\"")
+            })?;
+        state.run_state = RunState::Active(runner);
+        state.start_time = Some(Instant::now());
+        state.result_retention = self.result_retention;
+        let combined_log = state
+            .container
+            .combined_log
+            .clone()
+            .filter(|_| state.container.log);
+        for log in [stdout_log, stderr_log, combined_log].into_iter().flatten() {
+            self.resources
+                .record_once(ResourceKind::LogFile, log.path.to_string_lossy());
+        }
+        self.run_hooks(name, HookPhase::AfterStart, None)
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::run after starting the container for name \"{name}\"")
+            })
+    }
+
+    /// Removes the exited container `name` and creates and starts it again,
+    /// appending to its logs
+    async fn restart_supervised(&mut self, name: &str) -> Result<()> {
+        let state = self.set.get_mut(name).unwrap();
+        let restarts = state.restart_times.len() as u64 + 1;
+        let exit_code = state
+            .supervise_history
+            .last()
+            .and_then(|comres| comres.status.and_then(|status| status.code()));
+        warn!(
+            "supervised container \"{name}\" exited with code {exit_code:?}, restart number \
+             {restarts}"
+        );
+        emit_crate_event(|| CrateEvent::ContainerRestarted {
+            name: name.to_owned(),
+            restarts,
+            exit_code,
+        });
+        state.restart_times.push(Instant::now());
+        // the container is already finished, so this only removes it
+        let _ = state
+            .terminate_with_outcome(&mut self.resources, &mut self.last_teardown_report)
+            .await;
+        self.create_container(name).await?;
+        self.start_container(name, true).await
+    }
+
+    /// [ContainerNetwork::run] on all inactive containers in the network. Note
+    /// that terminated containers that weren't removed are recreated. Unless
+    /// changed by [Container::start_group] or [ContainerNetwork::start_order],
+    /// the containers are started in alphabetical order by name (not the
+    /// order they were added in).
+    pub async fn run_all(&mut self) -> Result<()> {
+        let names = self.inactive_names();
+        let mut v: Vec<&str> = vec![];
+        for name in &names {
+            v.push(name);
+        }
+        self.run(&v)
+            .await
+            .stack_err_locationless(|| "ContainerNetwork::run_all")
+    }
+
+    /// [ContainerNetwork::run_all], but failures that look like they come
+    /// from the environment rather than the definition of the network are
+    /// retried. Before each retry, the containers that this call is running
+    /// are terminated (along with the docker network if this call created
+    /// it, containers that were already active are left alone), the
+    /// containers are validated again, and the bring-up starts from scratch
+    /// with new containers. If `fresh_uuid` is set and this call created the
+    /// network, the network gets a new UUID for each retry (also replacing it
+    /// in the network name if the network was made with
+    /// [ContainerNetwork::new_with_uuid]), which avoids collisions with
+    /// anything left over from the failed attempt.
+    ///
+    /// This makes at most `attempts` attempts, sleeping for the `backoff`
+    /// intervals in between, and fewer if the intervals run out. Failures in
+    /// [RunPhase::Precheck] (validation), [RunPhase::ImagePreflight],
+    /// [RunPhase::Build], and [RunPhase::StartGroupReady] are only retried if
+    /// they have a [DaemonUnreachableError], and failures with an
+    /// [ArgvTooLongError], [NameConflictError], [ImageNotFoundError], or
+    /// [NotOnNetworkError] are never retried, since they would fail the same
+    /// way again. Failures in the other phases are retried.
+    ///
+    /// # Errors
+    ///
+    /// If an attempt fails in a way that is not retried or the last attempt
+    /// fails, the error of the last attempt is returned stacked on top of the
+    /// errors of all the earlier attempts
+    pub async fn run_all_with_retries(
+        &mut self,
+        attempts: u32,
+        backoff: Backoff,
+        fresh_uuid: bool,
+    ) -> Result<()> {
+        let names = self.inactive_names();
+        // the network and any other containers on it are left alone by the retries
+        let network_was_active = self.network_active;
+        let mut intervals = backoff.intervals();
+        // the errors of the earlier attempts, each with the attempt on top
+        let mut earlier = Error::empty();
+        let mut attempt = 1;
+        loop {
+            let (e, phase) = match self.run_with_phase(&names).await {
+                Ok(()) => return Ok(()),
+                Err(res) => res,
+            };
+            let retryable = is_retryable_failure(&phase, &e);
+            let interval = intervals.next();
+            let (true, Some(interval), true) = (retryable, interval, attempt < attempts) else {
+                let mut msg = format!(
+                    "ContainerNetwork::run_all_with_retries -> attempt {attempt} failed in phase \
+                     {phase}"
+                );
+                if !retryable {
+                    msg.push_str(", which is not retried");
+                }
+                return Err(earlier.chain_errors(e).add_kind_locationless(msg))
+            };
+            warn!(
+                "ContainerNetwork::run_all_with_retries -> attempt {attempt} failed in phase \
+                 {phase}, retrying in {interval:?}"
+            );
+            earlier = earlier.chain_errors(e).add_kind_locationless(format!(
+                "ContainerNetwork::run_all_with_retries -> attempt {attempt} failed in phase \
+                 {phase}, retried"
+            ));
+            self.try_terminate(&names).await;
+            if !network_was_active {
+                self.terminate_network().await;
+            }
+            sleep(interval).await;
+            for name in &names {
+                self.set.get_mut(name).unwrap().validated = false;
+            }
+            if fresh_uuid && !network_was_active {
+                let old = self.uuid;
+                self.uuid = Uuid::new_v4();
+                if let Some(base) = self.network_name.strip_suffix(&format!("_{old}")) {
+                    self.network_name = format!("{base}_{}", self.uuid);
+                }
+            }
+            attempt += 1;
         }
-        self.run(&v)
-            .await
-            .stack_err_locationless(|| "ContainerNetwork::run_all")
     }
 
     /// Looks through the results and includes the last "Error: Error { stack:
@@ -732,266 +3823,1298 @@ impl ContainerNetwork {
             if let RunState::PostActive(ref result) = state.run_state {
                 match result {
                     Ok(comres) => {
-                        if !comres.successful() {
+                        if !comres.successful_with(&state.container.success_codes) {
                             let mut encountered = false;
 
-                            // check stderr
-                            let stderr = comres.stderr_as_utf8_lossy();
-                            if let Some(start) = stderr.rfind(error_stack) {
-                                if !stderr.contains(not_root_cause) {
-                                    encountered = true;
-                                    res = res.add_kind_locationless(format!(
-                                        "Error stack from container \"{name}\" stderr:\n{}\n",
-                                        &stderr[start..]
-                                    ));
-                                }
-                            }
+                            if state.result_retention != ResultRetention::Full
+                                && comres.command.stdout_logs.is_empty()
+                                && comres.command.stderr_logs.is_empty()
+                            {
+                                res = res.add_kind_locationless(format!(
+                                    "Container \"{name}\" output was not retained because of \
+                                     `ResultRetention::{:?}` and it has no log files, markers \
+                                     cannot be searched for\n",
+                                    state.result_retention
+                                ));
+                            }
+
+                            // check stderr, including any `record_spill` file so that markers
+                            // which were spilled out of the record are still found
+                            let stderr = retained_or_logged(comres, state.result_retention, true);
+                            if let Some(start) = stderr.rfind(error_stack) {
+                                if !stderr.contains(not_root_cause) {
+                                    encountered = true;
+                                    res = res.add_kind_locationless(format!(
+                                        "Error stack from container \"{name}\" stderr:\n{}\n",
+                                        &stderr[start..]
+                                    ));
+                                }
+                            }
+
+                            if let Some(i) = stderr.rfind(panicked_at) {
+                                if let Some(i) = stderr[0..i].rfind("thread") {
+                                    encountered = true;
+                                    res = res.add_kind_locationless(format!(
+                                        "Panic message from container \"{name}\" stderr:\n{}\n",
+                                        &stderr[i..]
+                                    ));
+                                }
+                            }
+
+                            // check stdout only if stderr had nothing
+                            if !encountered {
+                                let stdout =
+                                    retained_or_logged(comres, state.result_retention, false);
+                                if let Some(start) = stdout.rfind(error_stack) {
+                                    if !stdout.contains(not_root_cause) {
+                                        encountered = true;
+                                        res = res.add_kind_locationless(format!(
+                                            "Error stack from container \"{name}\" stdout:\n{}\n",
+                                            &stdout[start..]
+                                        ));
+                                    }
+                                }
+
+                                if let Some(i) = stdout.rfind(panicked_at) {
+                                    if let Some(i) = stdout[0..i].rfind("thread") {
+                                        encountered = true;
+                                        res = res.add_kind_locationless(format!(
+                                            "Panic message from container \"{name}\" stdout:\n{}\n",
+                                            &stdout[i..]
+                                        ));
+                                    }
+                                }
+                            }
+
+                            // extra context about how the container exited
+                            if is_oom_kill(&stderr) {
+                                res = res.add_kind_locationless(format!(
+                                    "Container \"{name}\" appears to have been OOM killed\n"
+                                ));
+                            } else if let Some(signal) =
+                                match comres.status.and_then(|status| status.code()) {
+                                    // the exit status of `docker start --attach` is that of the
+                                    // container
+                                    Some(code) => signal_from_exit_code(code),
+                                    None => is_signal_exit(&stderr),
+                                }
+                            {
+                                res = res.add_kind_locationless(format!(
+                                    "Container \"{name}\" appears to have been terminated by \
+                                     signal {signal}\n"
+                                ));
+                            } else if comres.status.and_then(|status| status.code()).is_none() {
+                                if let Some(code) = try_parse_exit_code_from_stderr(&stderr) {
+                                    res = res.add_kind_locationless(format!(
+                                        "Container \"{name}\" stderr reports exit code {code}\n"
+                                    ));
+                                }
+                            }
+
+                            if let Some(code) = crash_exit_code(comres) {
+                                if !state.crash_artifacts.is_empty() {
+                                    res = res.add_kind_locationless(format!(
+                                        "Container \"{name}\" exited with crash status {code}, \
+                                         crash artifacts: {:?}\n",
+                                        state.crash_artifacts
+                                    ));
+                                }
+                            }
+
+                            if (!encountered) && (!comres.successful_or_terminated()) {
+                                res = res.add_kind_locationless(format!(
+                                    "Error: Container \"{name}\" was unsuccessful but does not \
+                                     seem to have an error stack or panic message\n"
+                                ));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        res = res.add_kind_locationless(format!(
+                            "Error: The internal handling of Container \"{name}\" produced this \
+                             error:\n {e:?}"
+                        ));
+                    }
+                }
+            }
+        }
+        Err(res)
+    }
+
+    /// Waits for the containers with `names` to all complete, or returns if
+    /// `duration` timeout is exceeded.
+    ///
+    /// If `terminate_on_failure`, then if there is a timeout or any
+    /// container from `names` has an error, then the whole network will be
+    /// terminated.
+    ///
+    /// By default, if any container stops normally but with an unsuccessful
+    /// return value (not just the `names` but any container in the network),
+    /// the `wait_with_timeout` function will return or terminate everything if
+    /// `terminate_on_failure`. This can be changed by setting the
+    /// `allow_unsuccessful` flag on the desired `Container`s.
+    ///
+    /// Note that if a CTRL-C/sigterm signal is sent and
+    /// [ctrlc_init](crate::ctrlc_init) has been run, then an internal
+    /// [CTRLC_ISSUED] check will trigger
+    /// [terminate_all](ContainerNetwork::terminate_all). Otherwise,
+    /// containers may continue to run in the background.
+    ///
+    /// If called with `Duration::ZERO`, this will always complete successfully
+    /// if all containers were terminated before this call.
+    ///
+    /// This is cancel safe. If the future is dropped at any point (e.x. by an
+    /// outer `tokio::time::timeout`), every container is left either active
+    /// or finished with its result recorded, so calling a wait function again
+    /// or [ContainerNetwork::terminate_all] works correctly. Names that
+    /// already finished are treated as done, and if a failure was found but
+    /// not yet reported when the future was dropped, the next wait call
+    /// reports it (terminating everything first if `terminate_on_failure`).
+    pub async fn wait_with_timeout<I, S>(
+        &mut self,
+        names: I,
+        terminate_on_failure: bool,
+        duration: Duration,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.wait_with_timeout_at(names, terminate_on_failure, deadline_after(duration))
+            .await
+    }
+
+    /// The same as [ContainerNetwork::wait_with_timeout] but with a
+    /// `deadline`, for chaining waits against one overall budget (see
+    /// [TestBudget](crate::TestBudget)). If the deadline has already passed,
+    /// this behaves like `wait_with_timeout` with `Duration::ZERO`.
+    pub async fn wait_with_timeout_at<I, S>(
+        &mut self,
+        names: I,
+        terminate_on_failure: bool,
+        deadline: Instant,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        // avoid polymorphizing
+        self.wait_with_timeout_internal(
+            names
+                .into_iter()
+                .map(|s| s.as_ref().to_owned())
+                .collect::<Vec<String>>(),
+            terminate_on_failure,
+            deadline,
+        )
+        .await
+    }
+
+    async fn wait_with_timeout_internal(
+        &mut self,
+        mut names: Vec<String>,
+        terminate_on_failure: bool,
+        deadline: Instant,
+    ) -> Result<()> {
+        if let Some(failure) = self.unreported_failure.clone() {
+            if terminate_on_failure {
+                self.terminate_all().await;
+            }
+            self.unreported_failure = None;
+            return self.error_compilation().stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::wait_with_timeout -> {failure}, this was found by a \
+                     previous wait that was cancelled (check logs for more):\n"
+                )
+            })
+        }
+        for name in names.iter() {
+            if let Some(state) = self.set.get(name) {
+                if matches!(state.run_state, RunState::PreActive) {
+                    return Err(Error::from(format!(
+                        "ContainerNetwork::wait_with_timeout -> name \"{name}\" has not been run"
+                    )));
+                }
+            } else {
+                return Err(Error::from(format!(
+                    "ContainerNetwork::wait_with_timeout -> name \"{name}\" not found in the \
+                     network"
+                )));
+            }
+        }
+
+        // names that already finished (e.x. in a previous wait that was cancelled) are
+        // done, unless they are supervised containers waiting to be restarted
+        let pending = |state: &ContainerState| state.is_active() || state.restart_at.is_some();
+        names.retain(|name| pending(&self.set[name]));
+
+        // the loop needs to loop over all active names, but we return when these are
+        // all done
+        let mut target_names: BTreeSet<String> = names.iter().cloned().collect();
+        // push other active names on the end
+        for (name, state) in &self.set {
+            if pending(state) && !target_names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+
+        let mut deadline = match self.wait_backoff.max_elapsed {
+            Some(max_elapsed) => deadline.min(deadline_after(max_elapsed)),
+            None => deadline,
+        };
+        let mut intervals = self.wait_backoff.intervals();
+        let mut skip_fail = true;
+        // we will check in a loop so that if a container has failed in the meantime, we
+        // terminate all
+        let mut i = 0;
+        loop {
+            if CTRLC_ISSUED.load(Ordering::SeqCst) {
+                // most of the time, a terminating runner will cause a stop before this, but
+                // still check
+                self.terminate_all().await;
+                let signal = shutdown_signal_received()
+                    .map(|signal| format!(" ({signal:?})"))
+                    .unwrap_or_default();
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::wait_with_timeout terminating because of \
+                     `CTRLC_ISSUED`{signal}"
+                )))
+            }
+            if target_names.is_empty() {
+                break
+            }
+            if i >= names.len() {
+                i = 0;
+                if Instant::now() > deadline {
+                    if skip_fail {
+                        // give one extra round, this is strong enough for the `Duration::ZERO`
+                        // guarantee
+                        skip_fail = false;
+                    } else {
+                        if terminate_on_failure {
+                            // we put in some extra delay so that the log file writers have some
+                            // extra time to finish
+                            sleep(Duration::from_millis(300)).await;
+                            self.terminate_all().await;
+                        }
+                        return Err(Error::timeout().add_kind_locationless(format!(
+                            "ContainerNetwork::wait_with_timeout timeout waiting for container \
+                             names {target_names:?} to complete"
+                        )))
+                    }
+                } else {
+                    match intervals.next() {
+                        Some(interval) => sleep(interval).await,
+                        // `max_retries` ran out
+                        None => deadline = Instant::now(),
+                    }
+                }
+            }
+
+            let name = &names[i];
+            let state = self.set.get_mut(name).unwrap();
+            if let RunState::Active(ref mut runner) = state.run_state {
+                match runner.wait_with_timeout(Duration::ZERO).await {
+                    Ok(()) => {
+                        // found before the result is taken so that this stays cancel safe
+                        let exit_code = match state.active_container_id {
+                            Some(ref id) => state.container.detached_exit_code(id, runner).await,
+                            None => Ok(None),
+                        };
+                        // avoid double terminate
+                        let (mut err, crashed) = match (runner.take_command_result(), exit_code) {
+                            (Some(mut comres), Ok(exit_code)) => {
+                                state
+                                    .container
+                                    .apply_detached_exit_code(&mut comres, exit_code);
+                                let comres = state.retained(comres);
+                                let err = !comres.successful_with(&state.container.success_codes);
+                                let crashed = crash_exit_code(&comres).is_some();
+                                if state.container.supervise.is_some() {
+                                    state.supervise_history.push(comres.clone());
+                                }
+                                state.run_state = RunState::PostActive(Ok(comres));
+                                (err, crashed)
+                            }
+                            (Some(_), Err(e)) => {
+                                state.run_state = RunState::PostActive(Err(e
+                                    .add_kind_locationless(
+                                        "ContainerNetwork::wait_with_timeout -> when finding the \
+                                         exit code of the detached container",
+                                    )));
+                                (true, false)
+                            }
+                            (None, _) => {
+                                state.run_state =
+                                    RunState::PostActive(Err(Error::from_kind_locationless(
+                                        "ContainerNetwork::wait_with_timeout -> when runner was \
+                                         done, did not find a command result for some reason",
+                                    )));
+                                (true, false)
+                            }
+                        };
+                        // a supervised container is restarted instead of finishing, unless the
+                        // policy is exhausted which counts as a failure
+                        let mut restart_delay = None;
+                        let mut exhausted = false;
+                        if let (Some(policy), RunState::PostActive(Ok(_))) =
+                            (state.container.supervise, &state.run_state)
+                        {
+                            let now = Instant::now();
+                            let recent = state
+                                .restart_times
+                                .iter()
+                                .filter(|time| now.duration_since(**time) < policy.window)
+                                .count();
+                            restart_delay = usize::try_from(policy.max_restarts)
+                                .is_ok_and(|max_restarts| recent < max_restarts)
+                                .then(|| policy.backoff.intervals().nth(recent))
+                                .flatten();
+                            exhausted = restart_delay.is_none();
+                            err = exhausted;
+                            // must be recorded before any more await points, in case this is
+                            // cancelled
+                            state.restart_at = restart_delay.map(|delay| now + delay);
+                        }
+                        let failed =
+                            (terminate_on_failure && err && (!state.container.allow_unsuccessful))
+                                || exhausted;
+                        // infrastructure containers are expected to outlive what we are waiting on
+                        let infrastructure_exited = self.fail_on_infrastructure_exit
+                            && state.container.infrastructure
+                            && (!target_names.contains(name));
+                        // must be recorded before any more await points, in case this is cancelled
+                        if exhausted {
+                            self.unreported_failure = Some(format!(
+                                "supervised container \"{name}\" exhausted its restart policy"
+                            ));
+                        } else if failed {
+                            self.unreported_failure =
+                                Some(format!("container \"{name}\" was unsuccessful"));
+                        } else if infrastructure_exited {
+                            self.unreported_failure =
+                                Some(format!("infrastructure container \"{name}\" exited"));
+                        }
+                        let state = self.set.get_mut(name).unwrap();
+                        if crashed {
+                            state.collect_crash_artifacts().await;
+                        }
+                        let result = match state.run_state {
+                            RunState::PostActive(Ok(ref comres)) => Some(comres.clone()),
+                            _ => None,
+                        };
+                        let hook_res = self.run_hooks(name, HookPhase::AfterExit, result).await;
+                        if let (Err(e), true) = (&hook_res, failed || infrastructure_exited) {
+                            // the error compilation below is more important
+                            warn!("{e:?}");
+                        } else if let Err(e) = hook_res {
+                            let name = name.clone();
+                            if terminate_on_failure {
+                                self.terminate_all().await;
+                            }
+                            return Err(e.add_kind_locationless(format!(
+                                "ContainerNetwork::wait_with_timeout -> after container \
+                                 \"{name}\" exited"
+                            )))
+                        }
+                        if exhausted {
+                            let name = name.clone();
+                            if terminate_on_failure {
+                                sleep(Duration::from_millis(300)).await;
+                                self.terminate_all().await;
+                            }
+                            self.unreported_failure = None;
+                            return self.error_compilation().stack_err_locationless(|| {
+                                format!(
+                                    "ContainerNetwork::wait_with_timeout -> supervised container \
+                                     \"{name}\" exhausted its restart policy (check logs for \
+                                     more):\n"
+                                )
+                            })
+                        }
+                        if failed {
+                            // give some time for other containers to react, they will be sending
+                            // ProbablyNotRootCause errors and other things
+                            sleep(Duration::from_millis(300)).await;
+                            self.terminate_all().await;
+                            self.unreported_failure = None;
+                            return self.error_compilation().stack_err_locationless(|| {
+                                "ContainerNetwork::wait_with_timeout error compilation (check logs \
+                                 for more):\n"
+                            })
+                        }
+                        if infrastructure_exited {
+                            let name = name.clone();
+                            if terminate_on_failure {
+                                sleep(Duration::from_millis(300)).await;
+                                self.terminate_all().await;
+                            }
+                            self.unreported_failure = None;
+                            return self.error_compilation().stack_err_locationless(|| {
+                                format!(
+                                    "ContainerNetwork::wait_with_timeout -> infrastructure \
+                                     container \"{name}\" exited while waiting on container names \
+                                     {target_names:?} (check logs for more):\n"
+                                )
+                            })
+                        }
+                        if restart_delay.is_some() {
+                            // restarted by a later pass once it is due
+                            i += 1;
+                            continue
+                        }
+                        let name = names.remove(i);
+                        target_names.remove(&name);
+                    }
+                    Err(e) => {
+                        if !e.is_timeout() {
+                            self.unreported_failure = Some(format!(
+                                "container \"{name}\" encountered an OS-level `CommandRunner` \
+                                 error"
+                            ));
+                            let _ = runner.terminate().await;
+                            if terminate_on_failure {
+                                // give some time like in the earlier case
+                                sleep(Duration::from_millis(300)).await;
+                                self.terminate_all().await;
+                            }
+                            self.unreported_failure = None;
+                            return self
+                                .error_compilation()
+                                .stack_err_locationless(|| {
+                                    "ContainerNetwork::wait_with_timeout encountered OS-level \
+                                     `CommandRunner` error"
+                                })
+                                .stack_err_locationless(|| {
+                                    "ContainerNetwork::wait_with_timeout error compilation (check \
+                                     logs for more):\n"
+                                })
+                        }
+                        i += 1;
+                    }
+                }
+            } else if let Some(restart_at) = state.restart_at {
+                if Instant::now() >= restart_at {
+                    state.restart_at = None;
+                    let name = name.clone();
+                    if let Err(e) = self.restart_supervised(&name).await {
+                        if terminate_on_failure {
+                            self.terminate_all().await;
+                        }
+                        return Err(e.add_kind_locationless(
+                            "ContainerNetwork::wait_with_timeout -> when restarting a supervised \
+                             container",
+                        ))
+                    }
+                }
+                i += 1;
+            } else {
+                // finished outside of this loop, e.x. by `try_collect_finished`
+                let name = names.remove(i);
+                target_names.remove(&name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Synchronously checks if all active containers have exited, for use in
+    /// custom polling loops. Containers that have never been run are ignored.
+    /// This does not transition the containers, use
+    /// [ContainerNetwork::try_collect_finished] for that.
+    pub fn all_containers_finished(&mut self) -> bool {
+        for state in self.set.values_mut() {
+            if let RunState::Active(ref mut runner) = state.run_state {
+                // errors are considered finished so that they get collected
+                if let Ok(None) = runner.try_wait() {
+                    return false
+                }
+            }
+        }
+        true
+    }
 
-                            if let Some(i) = stderr.rfind(panicked_at) {
-                                if let Some(i) = stderr[0..i].rfind("thread") {
-                                    encountered = true;
-                                    res = res.add_kind_locationless(format!(
-                                        "Panic message from container \"{name}\" stderr:\n{}\n",
-                                        &stderr[i..]
-                                    ));
-                                }
-                            }
+    /// Checks each active container without waiting, and finishes the ones
+    /// that have exited. Returns the names of the containers that just
+    /// finished. Unlike [ContainerNetwork::wait_with_timeout], this does not
+    /// terminate anything on failure, check the results with
+    /// [ContainerNetwork::get_result].
+    pub async fn try_collect_finished(&mut self) -> Vec<String> {
+        let mut finished = vec![];
+        for (name, state) in self.set.iter_mut() {
+            let RunState::Active(ref mut runner) = state.run_state else {
+                continue
+            };
+            if let Ok(None) = runner.try_wait() {
+                continue
+            }
+            let res = match runner.wait_with_timeout(Duration::ZERO).await {
+                Ok(()) => {
+                    let exit_code = match state.active_container_id {
+                        Some(ref id) => state.container.detached_exit_code(id, runner).await,
+                        None => Ok(None),
+                    };
+                    runner
+                        .take_command_result()
+                        .stack_err_locationless(|| {
+                            "ContainerNetwork::try_collect_finished -> when runner was done, did \
+                             not find a command result for some reason"
+                        })
+                        .and_then(|mut comres| {
+                            let exit_code = exit_code.stack_err_locationless(|| {
+                                "ContainerNetwork::try_collect_finished -> when finding the exit \
+                                 code of the detached container"
+                            })?;
+                            state
+                                .container
+                                .apply_detached_exit_code(&mut comres, exit_code);
+                            Ok(comres)
+                        })
+                }
+                Err(e) => Err(e.add_kind_locationless(
+                    "ContainerNetwork::try_collect_finished -> encountered OS-level \
+                     `CommandRunner` error",
+                )),
+            };
+            let res = res.map(|comres| state.retained(comres));
+            let crashed = matches!(res, Ok(ref comres) if crash_exit_code(comres).is_some());
+            state.run_state = RunState::PostActive(res);
+            if crashed {
+                state.collect_crash_artifacts().await;
+            }
+            finished.push(name.clone());
+        }
+        finished
+    }
 
-                            // check stdout only if stderr had nothing
-                            if !encountered {
-                                let stdout = comres.stdout_as_utf8_lossy();
-                                if let Some(start) = stdout.rfind(error_stack) {
-                                    if !stdout.contains(not_root_cause) {
-                                        encountered = true;
-                                        res = res.add_kind_locationless(format!(
-                                            "Error stack from container \"{name}\" stdout:\n{}\n",
-                                            &stdout[start..]
-                                        ));
-                                    }
-                                }
+    /// Runs [ContainerNetwork::wait_with_timeout] on all active containers that
+    /// are not marked as `infrastructure` and do not have a `supervise`
+    /// policy. Active infrastructure containers are still monitored, see
+    /// [ContainerNetwork::fail_on_infrastructure_exit], and supervised
+    /// containers are still restarted.
+    pub async fn wait_with_timeout_all(
+        &mut self,
+        terminate_on_failure: bool,
+        duration: Duration,
+    ) -> Result<()> {
+        self.wait_with_timeout_all_at(terminate_on_failure, deadline_after(duration))
+            .await
+    }
 
-                                if let Some(i) = stdout.rfind(panicked_at) {
-                                    if let Some(i) = stdout[0..i].rfind("thread") {
-                                        encountered = true;
-                                        res = res.add_kind_locationless(format!(
-                                            "Panic message from container \"{name}\" stdout:\n{}\n",
-                                            &stdout[i..]
-                                        ));
-                                    }
-                                }
-                            }
+    /// The same as [ContainerNetwork::wait_with_timeout_all] but with a
+    /// `deadline`, see [ContainerNetwork::wait_with_timeout_at]
+    pub async fn wait_with_timeout_all_at(
+        &mut self,
+        terminate_on_failure: bool,
+        deadline: Instant,
+    ) -> Result<()> {
+        let mut names = vec![];
+        for (name, state) in &self.set {
+            if state.is_active()
+                && (!state.container.infrastructure)
+                && state.container.supervise.is_none()
+            {
+                names.push(name.to_string());
+            }
+        }
+        // an unreported failure from a cancelled wait takes precedence
+        if names.is_empty() && self.unreported_failure.is_none() {
+            strictness_check(
+                self.effective_strictness(),
+                format!(
+                    "ContainerNetwork::wait_with_timeout_all -> network \"{}\" has no active \
+                     non-infrastructure containers to wait on",
+                    self.network_name
+                ),
+            )?;
+            emit_crate_event(|| CrateEvent::NothingToWaitOn {
+                network_name: self.network_name.clone(),
+            });
+        }
+        self.wait_with_timeout_at(names, terminate_on_failure, deadline)
+            .await
+    }
 
-                            if (!encountered) && (!comres.successful_or_terminated()) {
-                                res = res.add_kind_locationless(format!(
-                                    "Error: Container \"{name}\" was unsuccessful but does not \
-                                     seem to have an error stack or panic message\n"
-                                ));
-                            }
-                        }
+    // gets the inspect JSON of the container with `id`, using the cache. Retry
+    // loops pass the start of their attempt as `fresh_since`, so that they do
+    // not keep seeing a snapshot from before the previous attempt.
+    async fn inspect_id(
+        &self,
+        id: &str,
+        fresh_since: Option<Instant>,
+    ) -> Result<serde_json::Value> {
+        let ids: Vec<String> = self
+            .set
+            .values()
+            .filter_map(|state| state.active_container_id.clone())
+            .collect();
+        self.inspect_cache
+            .lock()
+            .await
+            .get(
+                &ids,
+                id,
+                self.inspect_max_staleness,
+                fresh_since,
+                self.command_middleware.as_ref(),
+            )
+            .await
+    }
+
+    /// Returns the `docker inspect` JSON of the active container with `name`.
+    /// Results are batched for all active containers and cached for up to
+    /// `inspect_max_staleness`.
+    pub async fn inspect(&self, name: &str) -> Result<serde_json::Value> {
+        let id = self
+            .set
+            .get(name)
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::inspect -> could not find name \"{name}\"")
+            })?
+            .active_container_id
+            .as_ref()
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::inspect -> container \"{name}\" is not active")
+            })?;
+        self.inspect_id(id, None)
+            .await
+            .stack_err_locationless(|| format!("ContainerNetwork::inspect(name: {name})"))
+    }
+
+    /// Returns the changes that the container with `name` made to its
+    /// filesystem using `docker diff`, e.x. to check if a config file was
+    /// created after a test fails.
+    ///
+    /// This only works while the container exists: for running containers, and
+    /// for containers that finished but are not created with `--rm` (the
+    /// [Container::checkpointable] and [Container::detached] ones). Other
+    /// containers are removed by docker when they exit.
+    pub async fn fs_diff(&self, name: &str) -> Result<Vec<FsChange>> {
+        let id = self
+            .set
+            .get(name)
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::fs_diff -> could not find name \"{name}\"")
+            })?
+            .active_container_id
+            .as_ref()
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::fs_diff -> container \"{name}\" was never run or has been \
+                     terminated"
+                )
+            })?;
+        let comres = self
+            .wrap(docker_command("diff").arg(id))
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::fs_diff -> when running `docker diff` for \"{name}\"")
+            })?;
+        if !comres.successful() {
+            let stderr = comres.stderr_as_utf8_lossy();
+            if stderr.to_lowercase().contains("no such container") {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::fs_diff -> container \"{name}\" no longer exists, \
+                     containers created with `--rm` are removed by docker when they exit so only \
+                     running or kept containers can be diffed"
+                )))
+            }
+            comres.assert_success().stack_err_locationless(|| {
+                format!("ContainerNetwork::fs_diff -> `docker diff` failed for \"{name}\"")
+            })?;
+        }
+        parse_docker_diff(&comres.stdout_as_utf8_lossy())
+            .stack_err_locationless(|| format!("ContainerNetwork::fs_diff(name: {name})"))
+    }
+
+    /// Uses [ContainerNetwork::fs_diff] to check that the container with `name`
+    /// added `path` to its filesystem, returning an error with the whole diff
+    /// otherwise
+    pub async fn assert_created(&self, name: &str, path: &str) -> Result<()> {
+        let diff = self.fs_diff(name).await.stack_err_locationless(|| {
+            format!("ContainerNetwork::assert_created(name: {name}, path: {path})")
+        })?;
+        if diff.contains(&FsChange::Added(path.to_owned())) {
+            Ok(())
+        } else {
+            Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::assert_created -> container \"{name}\" did not create \
+                 \"{path}\", the diff was {diff:#?}"
+            )))
+        }
+    }
+
+    // the active container ID of `name` for the `ContainerNetwork::{fn_name}`
+    // errors
+    fn exec_container_id(&self, fn_name: &str, name: &str) -> Result<&str> {
+        self.set
+            .get(name)
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::{fn_name} -> could not find name \"{name}\"")
+            })?
+            .active_container_id
+            .as_deref()
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::{fn_name} -> container \"{name}\" was never run or has \
+                     been terminated"
+                )
+            })
+    }
+
+    /// Runs `docker exec` with `argv` in the running container with `name`,
+    /// returning the `CommandResult` which can be unsuccessful
+    pub async fn exec<I, S>(&self, name: &str, argv: I) -> Result<CommandResult>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let id = self.exec_container_id("exec", name)?;
+        self.wrap(
+            docker_command("exec")
+                .arg(id)
+                .args(argv.into_iter().map(|s| s.as_ref().to_owned())),
+        )
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| {
+            format!("ContainerNetwork::exec -> when running `docker exec` for \"{name}\"")
+        })
+    }
+
+    /// Returns the output of `docker logs` for the container with `name`, with
+    /// the stdout and stderr of the container in the `CommandResult`
+    pub async fn logs(&self, name: &str) -> Result<CommandResult> {
+        let id = self.exec_container_id("logs", name)?;
+        let comres = self
+            .wrap(docker_command("logs").arg(id))
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::logs -> when running `docker logs` for \"{name}\"")
+            })?;
+        comres
+            .assert_success()
+            .stack_err_locationless(|| format!("ContainerNetwork::logs(name: {name})"))?;
+        Ok(comres)
+    }
+
+    /// Streams everything from `reader` into the file at `container_dest_path`
+    /// in the running container with `name` through the stdin of `docker exec
+    /// -i`, and sets the file `mode` (e.x. `0o644`). Unlike volumes, this works
+    /// with remote engines, and unlike `docker cp` nothing is staged on the
+    /// host, so it is suitable for multi-GB test fixtures.
+    ///
+    /// `sh`, `cat`, `chmod`, and `wc` are used in the container to write the
+    /// file and verify its size. If the image has no shell (e.x. distroless
+    /// images), `cp /dev/stdin` is used instead if available, in which case
+    /// the mode and size are not set or verified. The SHA-256 of the stream is
+    /// computed on the fly and can be checked afterwards with
+    /// [ContainerNetwork::verify_sha256].
+    pub async fn stream_to_file<R: AsyncRead + Unpin>(
+        &self,
+        name: &str,
+        mut reader: R,
+        container_dest_path: &str,
+        mode: u32,
+    ) -> Result<StreamTransfer> {
+        let id = self.exec_container_id("stream_to_file", name)?;
+        let has_shell = self
+            .wrap(docker_command("exec").arg(id).args(["sh", "-c", "true"]))
+            .run_to_completion()
+            .await
+            .is_ok_and(|comres| comres.successful());
+        let mode = format!("{mode:o}");
+        let command = if has_shell {
+            docker_command("exec -i").arg(id).args([
+                "sh",
+                "-c",
+                STREAM_TO_FILE_SCRIPT,
+                "sh",
+                container_dest_path,
+                &mode,
+            ])
+        } else {
+            docker_command("exec -i")
+                .arg(id)
+                .args(["cp", "/dev/stdin", container_dest_path])
+        };
+        let mut runner = self
+            .wrap(command)
+            .run_with_stdin(Stdio::piped())
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::stream_to_file -> when running `docker exec` for \"{name}\""
+                )
+            })?;
+        let mut stdin = runner.child_process.as_mut().unwrap().stdin.take().unwrap();
+        let mut hasher = Sha256::new();
+        let mut bytes = 0u64;
+        let mut buf = vec![0u8; 64 * 1024];
+        let copied: std::io::Result<()> = async {
+            loop {
+                let len = reader.read(&mut buf).await?;
+                if len == 0 {
+                    break
+                }
+                hasher.update(&buf[..len]);
+                stdin.write_all(&buf[..len]).await?;
+                bytes += u64::try_from(len).unwrap();
+            }
+            stdin.shutdown().await
+        }
+        .await;
+        // closes the stdin so that `cat` finishes
+        drop(stdin);
+        let comres = runner.wait_with_output().await.stack_err_locationless(|| {
+            format!("ContainerNetwork::stream_to_file -> `docker exec` for \"{name}\" failed")
+        })?;
+        // the process failing is the root cause of a write error
+        comres.assert_success().stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::stream_to_file -> writing to \"{container_dest_path}\" in \
+                 container \"{name}\" failed"
+            )
+        })?;
+        copied
+            .map_err(Error::from_kind_locationless)
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::stream_to_file -> streaming to \"{container_dest_path}\" \
+                     in container \"{name}\" failed"
+                )
+            })?;
+        if has_shell {
+            let size = comres.stdout_as_utf8_lossy();
+            if size.trim().parse::<u64>().ok() != Some(bytes) {
+                return Err(Error::from_kind_locationless(format!(
+                    "ContainerNetwork::stream_to_file -> \"{container_dest_path}\" in container \
+                     \"{name}\" has size {} but {bytes} bytes were streamed",
+                    size.trim()
+                )))
+            }
+        }
+        Ok(StreamTransfer {
+            bytes,
+            sha256: hex_digest(hasher),
+        })
+    }
+
+    /// Streams the file at `container_path` in the running container with
+    /// `name` into `writer` using `docker exec cat`, without staging anything
+    /// on the host. The SHA-256 is computed on the fly.
+    pub async fn stream_from_file<W: AsyncWrite + Unpin>(
+        &self,
+        name: &str,
+        container_path: &str,
+        mut writer: W,
+    ) -> Result<StreamTransfer> {
+        let id = self.exec_container_id("stream_from_file", name)?;
+        // stdout is not recorded so that it can be taken directly
+        let mut runner = self
+            .wrap(
+                docker_command("exec")
+                    .arg(id)
+                    .args(["cat", container_path])
+                    .stdout_recording(false),
+            )
+            .run()
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::stream_from_file -> when running `docker exec` for \
+                     \"{name}\""
+                )
+            })?;
+        let mut stdout = runner
+            .child_process
+            .as_mut()
+            .unwrap()
+            .stdout
+            .take()
+            .unwrap();
+        let mut hasher = Sha256::new();
+        let mut bytes = 0u64;
+        let mut buf = vec![0u8; 64 * 1024];
+        let copied: std::io::Result<()> = async {
+            loop {
+                let len = stdout.read(&mut buf).await?;
+                if len == 0 {
+                    break
+                }
+                hasher.update(&buf[..len]);
+                writer.write_all(&buf[..len]).await?;
+                bytes += u64::try_from(len).unwrap();
+            }
+            writer.flush().await
+        }
+        .await;
+        drop(stdout);
+        let comres = runner.wait_with_output().await.stack_err_locationless(|| {
+            format!("ContainerNetwork::stream_from_file -> `docker exec` for \"{name}\" failed")
+        })?;
+        comres.assert_success().stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::stream_from_file -> reading \"{container_path}\" in container \
+                 \"{name}\" failed"
+            )
+        })?;
+        copied
+            .map_err(Error::from_kind_locationless)
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::stream_from_file -> streaming from \"{container_path}\" in \
+                     container \"{name}\" failed"
+                )
+            })?;
+        Ok(StreamTransfer {
+            bytes,
+            sha256: hex_digest(hasher),
+        })
+    }
+
+    /// Runs `sha256sum` on `container_path` in the running container with
+    /// `name` and checks that it matches `sha256` (e.x. from a
+    /// [StreamTransfer])
+    pub async fn verify_sha256(
+        &self,
+        name: &str,
+        container_path: &str,
+        sha256: &str,
+    ) -> Result<()> {
+        let id = self.exec_container_id("verify_sha256", name)?;
+        let comres = self
+            .wrap(
+                docker_command("exec")
+                    .arg(id)
+                    .args(["sha256sum", container_path]),
+            )
+            .run_to_completion()
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::verify_sha256 -> when running `docker exec` for \"{name}\""
+                )
+            })?;
+        comres.assert_success().stack_err_locationless(|| {
+            format!("ContainerNetwork::verify_sha256 -> `sha256sum` failed in container \"{name}\"")
+        })?;
+        let stdout = comres.stdout_as_utf8_lossy();
+        let actual = stdout.split_whitespace().next().unwrap_or_default();
+        if !actual.eq_ignore_ascii_case(sha256) {
+            return Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::verify_sha256 -> \"{container_path}\" in container \"{name}\" \
+                 has SHA-256 {actual} but {sha256} was expected"
+            )))
+        }
+        Ok(())
+    }
+
+    /// Returns a receiver of the newline-delimited JSON values printed to the
+    /// stdout of the active container with `name` (e.x. with
+    /// [emit_json_line](crate::emit_json_line) in an entrypoint), as a
+    /// lightweight one-way alternative to `NetMessenger`. The stdout record
+    /// is polled so values are received as they are printed. Lines that fail
+    /// to parse are received as errors that include the line, and empty lines
+    /// are skipped.
+    ///
+    /// The receiver is closed after the container has finished and its result
+    /// has been collected by a wait or terminate function. The container must
+    /// not have a `record_limit` (or `max_record_size`) that is reached,
+    /// otherwise lines may be lost.
+    pub fn json_lines_stream<T: DeserializeOwned + Send + 'static>(
+        &self,
+        name: &str,
+    ) -> Result<mpsc::Receiver<Result<T>>> {
+        let state = self.set.get(name).stack_err_locationless(|| {
+            format!("ContainerNetwork::json_lines_stream -> could not find name \"{name}\"")
+        })?;
+        let RunState::Active(ref runner) = state.run_state else {
+            return Err(Error::from_kind_locationless(format!(
+                "ContainerNetwork::json_lines_stream -> container \"{name}\" is not active"
+            )))
+        };
+        let record = Arc::clone(&runner.stdout_record);
+        let (sender, receiver) = mpsc::channel(64);
+        tokio::task::spawn(async move {
+            let mut cursor = 0;
+            let mut line = vec![];
+            loop {
+                // the runner and its recorder hold the other references until the container
+                // has finished and the result has been taken
+                let finished = Arc::strong_count(&record) == 1;
+                let new_bytes: Vec<u8> = {
+                    let record = record.lock().await;
+                    let new_bytes = record.range(cursor.min(record.len())..).copied().collect();
+                    cursor = record.len();
+                    new_bytes
+                };
+                let mut lines = vec![];
+                for byte in new_bytes {
+                    if byte == b'\n' {
+                        lines.push(mem::take(&mut line));
+                    } else {
+                        line.push(byte);
                     }
-                    Err(e) => {
-                        res = res.add_kind_locationless(format!(
-                            "Error: The internal handling of Container \"{name}\" produced this \
-                             error:\n {e:?}"
-                        ));
+                }
+                if finished {
+                    lines.push(mem::take(&mut line));
+                }
+                for line in lines {
+                    if line.iter().all(|byte| byte.is_ascii_whitespace()) {
+                        continue
+                    }
+                    let res = serde_json::from_slice(&line).stack_err_locationless(|| {
+                        format!(
+                            "ContainerNetwork::json_lines_stream -> could not parse line {:?}",
+                            String::from_utf8_lossy(&line)
+                        )
+                    });
+                    if sender.send(res).await.is_err() {
+                        // the receiver was dropped
+                        return
                     }
                 }
+                if finished {
+                    return
+                }
+                sleep(JSON_LINES_POLL_INTERVAL).await;
+            }
+        });
+        Ok(receiver)
+    }
+
+    /// Returns the docker status (e.x. "running" or "exited") of the active
+    /// container with `name`, see [ContainerNetwork::inspect]
+    pub async fn status(&self, name: &str) -> Result<String> {
+        let v = self.inspect(name).await?;
+        Ok(stacked_get!(v["State"]["Status"])
+            .as_str()
+            .stack_err_locationless(|| "ContainerNetwork::status -> status was not a string")?
+            .to_owned())
+    }
+
+    /// Polls the label with `label_key` of the active container with `name`
+    /// every 500 ms until it has `expected_value`, for containers that signal
+    /// readiness by having their label updated. This uses the same cache as
+    /// [ContainerNetwork::inspect]. Note that the docker CLI itself cannot
+    /// change the labels of an existing container, this is for engines or
+    /// tooling that can.
+    ///
+    /// # Errors
+    ///
+    /// Returns a timeout error if `timeout` passes first, or an error if the
+    /// container is not active
+    pub async fn wait_for_label(
+        &self,
+        name: &str,
+        label_key: &str,
+        expected_value: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.wait_for_label_until(name, label_key, expected_value, deadline_after(timeout))
+            .await
+    }
+
+    /// The same as [ContainerNetwork::wait_for_label] but with a `deadline`.
+    /// The label is still checked once if the deadline has already passed.
+    pub async fn wait_for_label_until(
+        &self,
+        name: &str,
+        label_key: &str,
+        expected_value: &str,
+        deadline: Instant,
+    ) -> Result<()> {
+        let id = self.exec_container_id("wait_for_label", name)?;
+        let mut fresh_since = None;
+        loop {
+            let v = self
+                .inspect_id(id, fresh_since)
+                .await
+                .stack_err_locationless(|| {
+                    format!(
+                        "ContainerNetwork::wait_for_label(name: {name}, label_key: {label_key})"
+                    )
+                })?;
+            let value = v
+                .get("Config")
+                .and_then(|config| config.get("Labels"))
+                .and_then(|labels| labels.get(label_key))
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_owned());
+            if value.as_deref() == Some(expected_value) {
+                return Ok(())
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::timeout().add_kind_locationless(format!(
+                    "ContainerNetwork::wait_for_label(name: {name}, label_key: {label_key}) -> \
+                     timeout waiting for the value \"{expected_value}\", the last value was \
+                     {value:?}"
+                )))
             }
+            sleep(LABEL_POLL_INTERVAL).await;
+            fresh_since = Some(Instant::now());
         }
-        Err(res)
     }
 
-    /// Waits for the containers with `names` to all complete, or returns if
-    /// `duration` timeout is exceeded.
-    ///
-    /// If `terminate_on_failure`, then if there is a timeout or any
-    /// container from `names` has an error, then the whole network will be
-    /// terminated.
+    /// Waits for each of the active containers with `names` to be ready
+    /// according to the probe set by [Container::readiness]. The probes are
+    /// evaluated from the host side, so they work with images that have no
+    /// shell or healthcheck binaries:
     ///
-    /// By default, if any container stops normally but with an unsuccessful
-    /// return value (not just the `names` but any container in the network),
-    /// the `wait_with_timeout` function will return or terminate everything if
-    /// `terminate_on_failure`. This can be changed by setting the
-    /// `allow_unsuccessful` flag on the desired `Container`s.
+    /// - [ReadinessProbe::TcpPort] and [ReadinessProbe::HttpGet] connect to the
+    ///   IP address of the container (this requires the host to be able to
+    ///   reach the container network, which is not the case with Docker
+    ///   Desktop, use a published port and `wait_for_ok_tcp_stream_connect`
+    ///   there), or localhost for containers with `NetworkMode::Host`
+    /// - [ReadinessProbe::Exec] uses `docker exec`
+    /// - [ReadinessProbe::LogLine] polls the stdout and stderr records, so the
+    ///   container must not have a `record_limit` that is reached
     ///
-    /// Note that if a CTRL-C/sigterm signal is sent and
-    /// [ctrlc_init](crate::ctrlc_init) has been run, then an internal
-    /// [CTRLC_ISSUED] check will trigger
-    /// [terminate_all](ContainerNetwork::terminate_all). Otherwise,
-    /// containers may continue to run in the background.
+    /// # Errors
     ///
-    /// If called with `Duration::ZERO`, this will always complete successfully
-    /// if all containers were terminated before this call.
-    pub async fn wait_with_timeout<I, S>(
-        &mut self,
-        names: I,
-        terminate_on_failure: bool,
-        duration: Duration,
-    ) -> Result<()>
-    where
-        I: IntoIterator<Item = S>,
-        S: AsRef<str>,
-    {
-        // avoid polymorphizing
-        self.wait_with_timeout_internal(
-            names
-                .into_iter()
-                .map(|s| s.as_ref().to_owned())
-                .collect::<Vec<String>>(),
-            terminate_on_failure,
-            duration,
-        )
-        .await
+    /// Returns a timeout error with the last probe failure if
+    /// `overall_timeout` passes first, or an error if a container is not
+    /// active or has no readiness probe
+    pub async fn wait_ready(&self, names: &[&str], overall_timeout: Duration) -> Result<()> {
+        self.wait_ready_until(names, deadline_after(overall_timeout))
+            .await
     }
 
-    async fn wait_with_timeout_internal(
-        &mut self,
-        mut names: Vec<String>,
-        terminate_on_failure: bool,
-        duration: Duration,
-    ) -> Result<()> {
-        for name in names.iter() {
-            if let Some(state) = self.set.get(name) {
-                if !state.is_active() {
-                    return Err(Error::from(format!(
-                        "ContainerNetwork::wait_with_timeout -> name \"{name}\" is already \
-                         inactive"
-                    )));
+    /// The same as [ContainerNetwork::wait_ready] but with a `deadline`. Each
+    /// probe is still attempted once if the deadline has already passed.
+    pub async fn wait_ready_until(&self, names: &[&str], deadline: Instant) -> Result<()> {
+        for name in names {
+            let err = |msg: &str| {
+                Error::from_kind_locationless(format!(
+                    "ContainerNetwork::wait_ready(name: {name}) -> {msg}"
+                ))
+            };
+            let state = self
+                .set
+                .get(*name)
+                .ok_or_else(|| err("could not find name in container network"))?;
+            let readiness = state
+                .container
+                .readiness
+                .as_ref()
+                .ok_or_else(|| err("the container has no readiness probe"))?;
+            let (RunState::Active(ref runner), Some(id)) =
+                (&state.run_state, &state.active_container_id)
+            else {
+                return Err(err("the container is not active"))
+            };
+            let mut fresh_since = None;
+            loop {
+                let attempt_start = Instant::now();
+                let res = match timeout(
+                    readiness.timeout,
+                    self.evaluate_probe(
+                        id,
+                        runner,
+                        state.container.network_mode,
+                        &readiness.probe,
+                        fresh_since,
+                    ),
+                )
+                .await
+                {
+                    Ok(res) => res,
+                    Err(_) => Err(Error::timeout().add_kind_locationless(format!(
+                        "the probe took longer than {:?}",
+                        readiness.timeout
+                    ))),
+                };
+                let Err(e) = res else { break };
+                let next_attempt = attempt_start + readiness.interval;
+                if next_attempt >= deadline {
+                    return Err(Error::timeout().add_kind_locationless(format!(
+                        "ContainerNetwork::wait_ready(name: {name}) -> timeout waiting for the \
+                         probe {:?}, the last failure was:\n{e:?}",
+                        readiness.probe
+                    )))
                 }
-            } else {
-                return Err(Error::from(format!(
-                    "ContainerNetwork::wait_with_timeout -> name \"{name}\" not found in the \
-                     network"
-                )));
-            }
-        }
-
-        // the loop needs to loop over all active names, but we return when these are
-        // all done
-        let mut target_names: BTreeSet<String> = names.iter().cloned().collect();
-        // push other active names on the end
-        for active_name in self.active_names() {
-            if !target_names.contains(&active_name) {
-                names.push(active_name);
+                sleep_until(next_attempt).await;
+                fresh_since = Some(next_attempt);
             }
         }
+        Ok(())
+    }
 
-        let start = Instant::now();
-        let mut skip_fail = true;
-        // we will check in a loop so that if a container has failed in the meantime, we
-        // terminate all
-        let mut i = 0;
-        loop {
-            if CTRLC_ISSUED.load(Ordering::SeqCst) {
-                // most of the time, a terminating runner will cause a stop before this, but
-                // still check
-                self.terminate_all().await;
-                return Err(Error::from_kind_locationless(
-                    "ContainerNetwork::wait_with_timeout terminating because of `CTRLC_ISSUED`",
-                ))
+    // returns `Ok(())` if the probe says that the container is ready
+    async fn evaluate_probe(
+        &self,
+        id: &str,
+        runner: &CommandRunner,
+        network_mode: NetworkMode,
+        probe: &ReadinessProbe,
+        fresh_since: Option<Instant>,
+    ) -> Result<()> {
+        // the address that the ports of the container are reachable at from the host
+        let probe_ip = || async {
+            match network_mode {
+                NetworkMode::Default => {
+                    ip_addr_from_inspect(&self.inspect_id(id, fresh_since).await?)
+                }
+                NetworkMode::Host => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                NetworkMode::None => Err(Error::from_kind_locationless(
+                    "the container has `NetworkMode::None`, so its ports cannot be reached",
+                )),
             }
-            if target_names.is_empty() {
-                break
+        };
+        match probe {
+            ReadinessProbe::TcpPort(port) => {
+                let ip = probe_ip().await?;
+                TcpStream::connect((ip, *port))
+                    .await
+                    .stack_err_locationless(|| format!("could not connect to {ip}:{port}"))?;
+                Ok(())
             }
-            if i >= names.len() {
-                i = 0;
-                let current = Instant::now();
-                let elapsed = current.saturating_duration_since(start);
-                if elapsed > duration {
-                    if skip_fail {
-                        // give one extra round, this is strong enough for the `Duration::ZERO`
-                        // guarantee
-                        skip_fail = false;
-                    } else {
-                        if terminate_on_failure {
-                            // we put in some extra delay so that the log file writers have some
-                            // extra time to finish
-                            sleep(Duration::from_millis(300)).await;
-                            self.terminate_all().await;
-                        }
-                        return Err(Error::timeout().add_kind_locationless(format!(
-                            "ContainerNetwork::wait_with_timeout timeout waiting for container \
-                             names {target_names:?} to complete"
-                        )))
-                    }
+            ReadinessProbe::HttpGet {
+                port,
+                path,
+                expect_status,
+            } => {
+                let ip = probe_ip().await?;
+                let status = http_get_status(SocketAddr::new(ip, *port), path).await?;
+                if status == *expect_status {
+                    Ok(())
                 } else {
-                    sleep(Duration::from_millis(256)).await;
+                    Err(Error::from_kind_locationless(format!(
+                        "expected status {expect_status} but got {status}"
+                    )))
                 }
             }
-
-            let name = &names[i];
-            let state = self.set.get_mut(name).unwrap();
-            if let RunState::Active(ref mut runner) = state.run_state {
-                match runner.wait_with_timeout(Duration::ZERO).await {
-                    Ok(()) => {
-                        // avoid double terminate
-                        let err = {
-                            if let Some(comres) = runner.take_command_result() {
-                                let err = !comres.successful();
-                                state.run_state = RunState::PostActive(Ok(comres));
-                                err
-                            } else {
-                                state.run_state =
-                                    RunState::PostActive(Err(Error::from_kind_locationless(
-                                        "ContainerNetwork::wait_with_timeout -> when runner was \
-                                         done, did not find a command result for some reason",
-                                    )));
-                                true
-                            }
-                        };
-                        if terminate_on_failure && err && (!state.container.allow_unsuccessful) {
-                            // give some time for other containers to react, they will be sending
-                            // ProbablyNotRootCause errors and other things
-                            sleep(Duration::from_millis(300)).await;
-                            self.terminate_all().await;
-                            return self.error_compilation().stack_err_locationless(|| {
-                                "ContainerNetwork::wait_with_timeout error compilation (check logs \
-                                 for more):\n"
-                            })
-                        }
-                        let name = names.remove(i);
-                        target_names.remove(&name);
-                    }
-                    Err(e) => {
-                        if !e.is_timeout() {
-                            let _ = runner.terminate().await;
-                            if terminate_on_failure {
-                                // give some time like in the earlier case
-                                sleep(Duration::from_millis(300)).await;
-                                self.terminate_all().await;
-                            }
-                            return self
-                                .error_compilation()
-                                .stack_err_locationless(|| {
-                                    "ContainerNetwork::wait_with_timeout encountered OS-level \
-                                     `CommandRunner` error"
-                                })
-                                .stack_err_locationless(|| {
-                                    "ContainerNetwork::wait_with_timeout error compilation (check \
-                                     logs for more):\n"
-                                })
-                        }
-                        i += 1;
+            ReadinessProbe::Exec(argv) => self
+                .wrap(docker_command("exec").arg(id).args(argv))
+                .run_to_completion()
+                .await?
+                .assert_success(),
+            ReadinessProbe::LogLine(line) => {
+                for record in [&runner.stdout_record, &runner.stderr_record] {
+                    let record = record.lock().await;
+                    let (front, back) = record.as_slices();
+                    if [front, back].concat().contains_str(line) {
+                        return Ok(())
                     }
                 }
+                Err(Error::from_kind_locationless(format!(
+                    "the line {line:?} was not found in the stdout or stderr"
+                )))
             }
         }
-        Ok(())
-    }
-
-    /// Runs [ContainerNetwork::wait_with_timeout] on all active containers.
-    pub async fn wait_with_timeout_all(
-        &mut self,
-        terminate_on_failure: bool,
-        duration: Duration,
-    ) -> Result<()> {
-        self.wait_with_timeout(self.active_names(), terminate_on_failure, duration)
-            .await
     }
 
     /// Gets the IP address of an active container. There is a delay between a
     /// container starting and an IP address being assigned, which is why this
-    /// has a retry mechanism.
+    /// has a retry mechanism. This uses the same cache as
+    /// [ContainerNetwork::inspect], but every retry refreshes it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [NotOnNetworkError] if the container has a
+    /// [Container::network_mode] other than `NetworkMode::Default`
     pub async fn wait_get_ip_addr(
         &self,
         num_retries: u64,
@@ -1004,6 +5127,12 @@ impl ContainerNetwork {
                  name: {name}) -> could not find name in container network"
             )
         })?;
+        self.check_on_network(name).stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::get_ip_addr(num_retries: {num_retries}, delay: {delay:?}, \
+                 name: {name})"
+            )
+        })?;
         let id = state
             .active_container_id
             .as_ref()
@@ -1013,17 +5142,58 @@ impl ContainerNetwork {
                      name: {name}) -> found container, but it was not active"
                 )
             })?;
-        let ip = wait_get_ip_addr(num_retries, delay, id)
-            .await
-            .stack_err_locationless(|| {
-                format!(
-                    "ContainerNetwork::get_ip_addr(num_retries: {num_retries}, delay: {delay:?}, \
-                     name: {name})"
-                )
-            })?;
+        let mut retry = false;
+        let ip = wait_for_ok(num_retries, delay, || {
+            // retries must not be served the snapshot that the last attempt saw
+            let fresh_since = retry.then(Instant::now);
+            retry = true;
+            async move { ip_addr_from_inspect(&self.inspect_id(id, fresh_since).await?) }
+        })
+        .await
+        .stack_err_locationless(|| {
+            format!(
+                "ContainerNetwork::get_ip_addr(num_retries: {num_retries}, delay: {delay:?}, \
+                 name: {name})"
+            )
+        })?;
         Ok(ip)
     }
 
+    /// Sets a callback for the progress of the image builds of containers
+    /// that do not have their own, see [Container::on_build_progress]
+    pub fn on_build_progress(
+        &mut self,
+        on_build_progress: impl Fn(&BuildProgress, &str) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_build_progress = Some(BuildProgressCallback::new(on_build_progress));
+        self
+    }
+
+    /// Sets a middleware that transforms every docker `Command` the network
+    /// runs (e.x. builds, creates, starts, removals, network commands, and
+    /// inspects) just before it is run, after the normal construction so that
+    /// the middleware sees the final arguments. This can wrap the commands with
+    /// `sudo`, `flock`, or `strace`, or add global flags like `--config <dir>`,
+    /// see the built-ins in [middleware](crate::middleware). Containers
+    /// without their own [Container::command_middleware] get this one when
+    /// they are run.
+    ///
+    /// This does not apply to the helpers in
+    /// [docker_helpers](crate::docker_helpers) or to the synchronous removal
+    /// in the `Drop` impl of the network.
+    pub fn command_middleware(
+        &mut self,
+        command_middleware: impl Fn(Command) -> Command + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.command_middleware = Some(CommandMiddleware::new(command_middleware));
+        self
+    }
+
+    /// Applies the `command_middleware` if it is set
+    pub(crate) fn wrap(&self, command: Command) -> Command {
+        apply_middleware(self.command_middleware.as_ref(), command)
+    }
+
     /// Sets whether the `Container::build` commands should produce debug output
     pub fn debug_build(&mut self, debug_build: bool) -> &mut Self {
         self.debug_build = debug_build;
@@ -1043,6 +5213,163 @@ impl ContainerNetwork {
         self
     }
 
+    /// Sets whether an `infrastructure` container that exits while
+    /// [ContainerNetwork::wait_with_timeout] is waiting on other containers
+    /// causes an immediate error. This is `true` by default, and applies
+    /// regardless of the `allow_unsuccessful` setting of the infrastructure
+    /// container (since even a successful exit means the infrastructure is
+    /// gone). If `false`, infrastructure containers are handled like any other
+    /// container that is not being waited on.
+    pub fn fail_on_infrastructure_exit(&mut self, fail_on_infrastructure_exit: bool) -> &mut Self {
+        self.fail_on_infrastructure_exit = fail_on_infrastructure_exit;
+        self
+    }
+
+    /// Sets the schedule that [ContainerNetwork::wait_with_timeout] and the
+    /// other waits poll the active containers with, which defaults to
+    /// [DEFAULT_NETWORK_WAIT_BACKOFF]. Jitter spreads out the polls of many
+    /// networks, `max_elapsed` additionally bounds each wait, and a wait times
+    /// out once `max_retries` intervals have passed.
+    pub fn wait_backoff(&mut self, wait_backoff: Backoff) -> &mut Self {
+        self.wait_backoff = wait_backoff;
+        self
+    }
+
+    /// Sets a directory used for BuildKit layer caching. When set, every image
+    /// build gets `--cache-from type=local,src={cache_dir}/{key}` and
+    /// `--cache-to type=local,dest={cache_dir}/{key},mode=max` arguments, where
+    /// the key is the `build_cache_key` of the container or else the image
+    /// tag. Note that the automatic image tags include the UUID of the
+    /// network, so `build_cache_key` should be set for the cache to be reused
+    /// across networks. Exporting a local cache may require a `docker buildx`
+    /// builder that supports it.
+    pub fn build_cache_dir(&mut self, cache_dir: &str) -> &mut Self {
+        self.build_cache_dir = Some(cache_dir.to_owned());
+        self
+    }
+
+    /// Sets a lock file of container names to image digests, in the format of
+    /// [format_lock_file](crate::docker_helpers::format_lock_file). When
+    /// `run` is called, the `Dockerfile::NameTag` containers in the lock
+    /// file get it as their `pinned_digest` (an error is returned if they
+    /// have a different one), and afterwards the digests of the containers
+    /// that were run are added to the lock file. Committing the lock file
+    /// makes future runs use and verify the same images.
+    pub fn lock_file(&mut self, path: &str) -> &mut Self {
+        self.lock_file = Some(path.to_owned());
+        self
+    }
+
+    /// Sets the `base_dir` that relative paths of the network (the log
+    /// directory, `dockerfile_write_dir`, and `build_cache_dir`) and of the
+    /// containers it runs (dockerfile paths, volumes, log files, and so on) are
+    /// resolved against when they are run. This takes precedence over the
+    /// global [set_base_dir](crate::set_base_dir), which takes precedence over
+    /// the current working directory. A relative `base_dir` is itself resolved
+    /// with the global base directory.
+    pub fn base_dir(&mut self, base_dir: &str) -> &mut Self {
+        self.base_dir = Some(base_dir.to_owned());
+        self
+    }
+
+    /// Sets the default log file size limit in bytes (or a string parsed by
+    /// [parse_size](crate::parse_size)) for all containers, a container's own
+    /// `log_limit` takes precedence
+    pub fn max_log_size<B>(&mut self, bytes: B) -> Result<&mut Self>
+    where
+        B: TryInto<ByteSize>,
+        B::Error: Debug,
+    {
+        self.max_log_size = Some(
+            ByteSize::convert(bytes)
+                .stack_err_locationless(|| "ContainerNetwork::max_log_size")?
+                .0,
+        );
+        Ok(self)
+    }
+
+    /// Sets the default in-memory stdout and stderr record size limit in bytes
+    /// (or a string parsed by [parse_size](crate::parse_size)) for all
+    /// containers, a container's own `record_limit` takes precedence
+    pub fn max_record_size<B>(&mut self, bytes: B) -> Result<&mut Self>
+    where
+        B: TryInto<ByteSize>,
+        B::Error: Debug,
+    {
+        self.max_record_size = Some(
+            ByteSize::convert(bytes)
+                .stack_err_locationless(|| "ContainerNetwork::max_record_size")?
+                .0,
+        );
+        Ok(self)
+    }
+
+    /// Sets the default `debug_buffering` for all containers, a container's
+    /// own takes precedence
+    pub fn debug_buffering(&mut self, debug_buffering: Option<BufferPolicy>) -> &mut Self {
+        self.debug_buffering = debug_buffering;
+        self
+    }
+
+    /// Sets the default `max_debug_lines_per_sec` for all containers, a
+    /// container's own takes precedence
+    pub fn max_debug_lines_per_sec(&mut self, max_debug_lines_per_sec: Option<u64>) -> &mut Self {
+        self.max_debug_lines_per_sec = max_debug_lines_per_sec;
+        self
+    }
+
+    /// Sets `prune_after_teardown`, the given `options` are used to prune
+    /// docker resources after `terminate_all`
+    pub fn prune_after_teardown(&mut self, options: PruneOptions) -> &mut Self {
+        self.prune_after_teardown = Some(options);
+        self
+    }
+
+    /// Sets the `pull_secrets`. When [ContainerNetwork::run] is called, `docker
+    /// login` is run for each unique registry that has a matching
+    /// `PullSecret` and is referenced by a `Dockerfile::NameTag` container
+    /// being run. The registries are logged out of after the containers are
+    /// created (which is when images are pulled), or when `run` fails.
+    pub fn pull_secrets(&mut self, secrets: Vec<PullSecret>) -> &mut Self {
+        self.pull_secrets = secrets;
+        self
+    }
+
+    /// Sets `pull_before_create`. When enabled, [ContainerNetwork::run] runs
+    /// `docker image inspect` on every `Dockerfile::NameTag` image (after
+    /// logging in with the `pull_secrets`) and pulls the missing ones before
+    /// anything is built or created, so that a typo'd tag fails within seconds
+    /// with an error naming the container and the tag. Images are not pulled
+    /// for containers with `PullPolicy::Never`, those fail immediately if the
+    /// image is missing. If the preflight itself fails for reasons other than
+    /// the image not existing (e.g. no network), this is only logged with
+    /// `debug!` and the image is pulled lazily by `docker create` as usual.
+    pub fn pull_before_create(&mut self, pull_before_create: bool) -> &mut Self {
+        self.pull_before_create = pull_before_create;
+        self
+    }
+
+    /// Sets `verify_remote_images`. When enabled and `pull_before_create` is
+    /// not, [ContainerNetwork::run] uses `docker manifest inspect` to check
+    /// that `Dockerfile::NameTag` images that are not present locally exist in
+    /// their registry, without pulling them. This needs network access and is
+    /// best-effort in the same way as `pull_before_create`.
+    pub fn verify_remote_images(&mut self, verify_remote_images: bool) -> &mut Self {
+        self.verify_remote_images = verify_remote_images;
+        self
+    }
+
+    /// Sets the `Strictness` override for this network, `None` uses the global
+    /// strictness
+    pub fn strictness(&mut self, strictness: Option<Strictness>) -> &mut Self {
+        self.strictness = strictness;
+        self
+    }
+
+    fn effective_strictness(&self) -> Strictness {
+        self.strictness.unwrap_or_else(strictness)
+    }
+
     /// Sets all debug flags at once
     pub fn debug_all(&mut self, debug_all: bool) -> &mut Self {
         self.debug_build(debug_all);
@@ -1050,3 +5377,67 @@ impl ContainerNetwork {
         self.debug_extra(debug_all)
     }
 }
+
+/// Runs `docker pull` for container `name` according to `policy`
+async fn pull_with_policy(
+    name: &str,
+    name_tag: &str,
+    policy: PullPolicy,
+    command_middleware: Option<&CommandMiddleware>,
+) -> Result<()> {
+    let start = Instant::now();
+    let comres = apply_middleware(command_middleware, docker_command("pull").arg(name_tag))
+        .run_to_completion()
+        .await
+        .stack_err_locationless(|| "ContainerNetwork::run -> when running `docker pull`")?;
+    let elapsed = start.elapsed();
+    if comres.successful() {
+        let up_to_date = if comres
+            .stdout_as_utf8_lossy()
+            .contains("Image is up to date")
+        {
+            " (already up to date)"
+        } else {
+            ""
+        };
+        debug!(
+            "ContainerNetwork::run -> pulled \"{name_tag}\" for container \"{name}\" in \
+             {elapsed:?}{up_to_date}"
+        );
+        return Ok(())
+    }
+    if policy == PullPolicy::Newer {
+        let inspect = apply_middleware(
+            command_middleware,
+            docker_command("image inspect").arg(name_tag),
+        )
+        .run_to_completion()
+        .await;
+        if inspect.is_ok_and(|comres| comres.successful()) {
+            warn!(
+                "ContainerNetwork::run -> `docker pull {name_tag}` failed after {elapsed:?} with \
+                 `PullPolicy::Newer`, using the local image: {}",
+                comres.stderr_as_utf8_lossy().trim()
+            );
+            emit_crate_event(|| CrateEvent::PullFellBackToLocal {
+                name_tag: name_tag.to_owned(),
+                error: comres.stderr_as_utf8_lossy().trim().to_owned(),
+            });
+            return Ok(())
+        }
+    }
+    comres.assert_success().stack_err_locationless(|| {
+        format!(
+            "ContainerNetwork::run -> `docker pull` of \"{name_tag}\" for container \"{name}\" \
+             failed with `PullPolicy::{policy:?}`"
+        )
+    })
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -0,0 +1,181 @@
+use stacked_errors::{Error, Result, StackableErr};
+
+use self::builder_state::{Set, Unset};
+use crate::docker::{Container, ContainerNetwork};
+
+/// The type states of the [ContainerNetworkBuilder] fields that must be set
+/// before building
+pub mod builder_state {
+    /// Type state of a
+    /// [ContainerNetworkBuilder](super::ContainerNetworkBuilder) field that
+    /// has not been set
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Unset;
+
+    /// Type state of a
+    /// [ContainerNetworkBuilder](super::ContainerNetworkBuilder) field that
+    /// has been set
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Set(pub(super) String);
+}
+
+/// A builder for [ContainerNetwork], where `build` can only be called after
+/// both the `network_name` and `log_dir` are set.
+///
+/// ```
+/// use super_orchestrator::docker::{
+///     Container, ContainerNetwork, ContainerNetworkBuilder, Dockerfile,
+/// };
+///
+/// let cn = ContainerNetworkBuilder::new()
+///     .log_dir("./logs")
+///     .network_name("test")
+///     .dockerfile_write_dir("./dockerfiles")
+///     .is_internal(true)
+///     .uuid()
+///     .containers(vec![Container::new(
+///         "container0",
+///         Dockerfile::name_tag("fedora:40"),
+///     )])
+///     .build()
+///     .unwrap();
+/// assert_eq!(cn.network_name(), format!("test_{}", cn.uuid()));
+/// assert!(cn.is_internal());
+/// assert!(cn.network_args.is_empty());
+/// assert_eq!(cn.inactive_names(), ["container0"]);
+/// ```
+///
+/// `build` cannot be called before the `log_dir` is set
+///
+/// ```compile_fail
+/// use super_orchestrator::docker::ContainerNetwork;
+///
+/// let cn = ContainerNetwork::builder().network_name("test").build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ContainerNetworkBuilder<N = Unset, L = Unset> {
+    network_name: N,
+    log_dir: L,
+    dockerfile_write_dir: Option<String>,
+    is_internal: bool,
+    uuid: bool,
+    containers: Vec<Container>,
+}
+
+impl Default for ContainerNetworkBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContainerNetworkBuilder {
+    /// A builder with nothing set
+    pub fn new() -> Self {
+        Self {
+            network_name: Unset,
+            log_dir: Unset,
+            dockerfile_write_dir: None,
+            is_internal: false,
+            uuid: false,
+            containers: vec![],
+        }
+    }
+}
+
+impl<N, L> ContainerNetworkBuilder<N, L> {
+    /// Sets the name of the docker network, see [ContainerNetwork::new]
+    pub fn network_name(self, network_name: impl AsRef<str>) -> ContainerNetworkBuilder<Set, L> {
+        ContainerNetworkBuilder {
+            network_name: Set(network_name.as_ref().to_owned()),
+            log_dir: self.log_dir,
+            dockerfile_write_dir: self.dockerfile_write_dir,
+            is_internal: self.is_internal,
+            uuid: self.uuid,
+            containers: self.containers,
+        }
+    }
+
+    /// Sets the directory that log files are written to, see
+    /// [ContainerNetwork::new]
+    pub fn log_dir(self, log_dir: impl AsRef<str>) -> ContainerNetworkBuilder<N, Set> {
+        ContainerNetworkBuilder {
+            network_name: self.network_name,
+            log_dir: Set(log_dir.as_ref().to_owned()),
+            dockerfile_write_dir: self.dockerfile_write_dir,
+            is_internal: self.is_internal,
+            uuid: self.uuid,
+            containers: self.containers,
+        }
+    }
+
+    /// Sets the directory that `Dockerfile::Contents` are written to, see
+    /// [ContainerNetwork::new]
+    pub fn dockerfile_write_dir(mut self, dockerfile_write_dir: impl AsRef<str>) -> Self {
+        self.dockerfile_write_dir = Some(dockerfile_write_dir.as_ref().to_owned());
+        self
+    }
+
+    /// If set, the network is created with `--internal` (see
+    /// [ContainerNetwork::internal]) so that containers cannot access external
+    /// networks
+    pub fn is_internal(mut self, is_internal: bool) -> Self {
+        self.is_internal = is_internal;
+        self
+    }
+
+    /// Adds the fresh UUID of the network as a suffix to the `network_name`,
+    /// the same as [ContainerNetwork::new_with_uuid]
+    pub fn uuid(mut self) -> Self {
+        self.uuid = true;
+        self
+    }
+
+    /// Adds containers that are added with [ContainerNetwork::add_container]
+    /// when building
+    pub fn containers(mut self, containers: Vec<Container>) -> Self {
+        self.containers.extend(containers);
+        self
+    }
+}
+
+impl ContainerNetworkBuilder<Set, Set> {
+    /// Builds the `ContainerNetwork`
+    ///
+    /// # Errors
+    ///
+    /// If the `network_name` or `log_dir` is empty, or if adding any of the
+    /// `containers` fails
+    pub fn build(self) -> Result<ContainerNetwork> {
+        let Set(network_name) = self.network_name;
+        let Set(log_dir) = self.log_dir;
+        if network_name.is_empty() {
+            return Err(Error::from_kind_locationless(
+                "ContainerNetworkBuilder::build -> `network_name` is empty",
+            ))
+        }
+        if log_dir.is_empty() {
+            return Err(Error::from_kind_locationless(
+                "ContainerNetworkBuilder::build -> `log_dir` is empty",
+            ))
+        }
+        let dockerfile_write_dir = self.dockerfile_write_dir.as_deref();
+        let mut cn = if self.uuid {
+            ContainerNetwork::new_with_uuid(network_name, dockerfile_write_dir, log_dir)
+        } else {
+            ContainerNetwork::new(network_name, dockerfile_write_dir, log_dir)
+        };
+        cn.internal(self.is_internal);
+        for container in self.containers {
+            cn.add_container(container)
+                .stack_err_locationless(|| "ContainerNetworkBuilder::build")?;
+        }
+        Ok(cn)
+    }
+}
+
+impl ContainerNetwork {
+    /// Returns a [ContainerNetworkBuilder]
+    pub fn builder() -> ContainerNetworkBuilder {
+        ContainerNetworkBuilder::new()
+    }
+}
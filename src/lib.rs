@@ -1,23 +1,68 @@
 //! See README.md for more
 
+/// Matchers on command output for tests
+pub mod assertions;
+/// Higher level orchestration functions built on `ContainerNetwork`
+pub mod combinators;
 mod command;
 mod command_runner;
+mod container_hooks;
+mod created_resources;
 mod docker_container;
+/// Miscellanious docker helpers
+pub mod docker_helpers;
 mod docker_network;
+mod docker_network_builder;
 mod file_options;
+/// Transformations applied to the docker commands of a `ContainerNetwork`
+pub mod middleware;
 mod misc;
-mod parsing;
-mod paths;
-pub use command::*;
-pub use command_runner::*;
-/// Miscellanious docker helpers
-pub mod docker_helpers;
 /// Communication with `NetMessenger`
 pub mod net_message;
-pub use file_options::*;
-pub use misc::*;
-pub use parsing::*;
-pub use paths::*;
+mod parsing;
+mod paths;
+mod port_forward;
+/// The items that nearly every user of the crate needs, for `use
+/// super_orchestrator::prelude::*;`
+pub mod prelude;
+mod resource_monitor;
+#[cfg(feature = "sandbox")]
+mod sandbox;
+mod single_container_runner;
+mod templates;
+mod topology;
+mod up;
+mod validation;
+// The root re-exports are listed explicitly so that rustdoc shows each item at
+// one path and additions are deliberate
+pub use command::{
+    error_output_limit, set_error_output_limit, BufferPolicy, Command, CommandResult,
+    CommandResultNoDebug, DebugOutput, LineCallback, RecordSpill, DEFAULT_ERROR_OUTPUT_LIMIT,
+    DEFAULT_WAIT_BACKOFF, ENV_PASSTHROUGH_VAR,
+};
+pub(crate) use command_runner::command_runner;
+pub use command_runner::CommandRunner;
+pub use file_options::{FileOptions, ReadOrWrite, WriteOptions};
+pub(crate) use misc::next_terminal_color;
+pub use misc::{
+    base_dir, clear_base_dir, close_file, ctrlc_init, ctrlc_issued_reset, deadline_after,
+    emit_json_line, find_binary, load_args_file, load_entrypoint_args, load_topology,
+    parse_duration, parse_size, remove_files_in_dir, set_base_dir, set_strictness, sh, sh_no_debug,
+    shutdown_signal_received, strictness, subscribe_crate_events, take_strictness_violations,
+    type_hash, wait_for_ok, workspace_dir, Backoff, BackoffIntervals, ByAddress, ByteSize,
+    CrateEvent, CrateEventReceiver, FindBinaryOptions, ShutdownSignal, Strictness, TestBudget,
+    CTRLC_ISSUED,
+};
+pub use parsing::{
+    get_separated_val, is_oom_kill, is_signal_exit, parse_build_progress, parse_docker_size,
+    signal_from_exit_code, try_parse_exit_code_from_stderr, BuildProgress, BuildStats,
+};
+pub use paths::{acquire_dir_path, acquire_file_path, acquire_path};
+#[cfg(feature = "sandbox")]
+pub use sandbox::{SandboxConfig, SandboxUnsupportedError};
+/// Test doubles for running `ContainerNetwork`s without a docker daemon
+#[cfg(feature = "test_doubles")]
+pub mod test_doubles;
 /// This reexport helps with dependency wrangling
 pub use stacked_errors;
 /// Docker container management
@@ -25,5 +70,9 @@ pub use stacked_errors;
 /// See the `basic_containers`, `docker_entrypoint_pattern`, and `postgres`
 /// crate examples
 pub mod docker {
-    pub use super::{docker_container::*, docker_network::*};
+    pub use super::{
+        container_hooks::*, created_resources::*, docker_container::*, docker_network::*,
+        docker_network_builder::*, port_forward::*, resource_monitor::*,
+        single_container_runner::*, topology::*, up::*, validation::*,
+    };
 }
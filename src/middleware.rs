@@ -0,0 +1,80 @@
+use std::{ffi::OsString, fmt::Debug, sync::Arc};
+
+use crate::{ByAddress, Command};
+
+/// The function wrapped by [CommandMiddleware]
+pub type CommandMiddlewareFn = dyn Fn(Command) -> Command + Send + Sync;
+
+/// Transforms every docker `Command` of a `ContainerNetwork` just before it is
+/// run, see
+/// [ContainerNetwork::command_middleware](crate::docker::ContainerNetwork::command_middleware)
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CommandMiddleware(pub ByAddress<CommandMiddlewareFn>);
+
+impl CommandMiddleware {
+    /// Wraps `f`
+    pub fn new(f: impl Fn(Command) -> Command + Send + Sync + 'static) -> Self {
+        Self(ByAddress(Arc::new(f)))
+    }
+
+    /// Applies the middleware to `command`
+    pub fn apply(&self, command: Command) -> Command {
+        (self.0)(command)
+    }
+}
+
+impl Debug for CommandMiddleware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CommandMiddleware(..)")
+    }
+}
+
+/// Runs the command with `sudo`, meaning the program becomes "sudo" and the
+/// original program is shifted into the first argument
+///
+/// ```
+/// use super_orchestrator::{middleware::sudo, Command};
+///
+/// let command = sudo()(Command::new("docker ps -a"));
+/// assert_eq!(command.program, "sudo");
+/// assert_eq!(command.args, ["docker", "ps", "-a"]);
+/// ```
+pub fn sudo() -> impl Fn(Command) -> Command + Send + Sync + Clone + 'static {
+    |mut command: Command| {
+        let program = std::mem::replace(&mut command.program, OsString::from("sudo"));
+        command.args.insert(0, program);
+        command
+    }
+}
+
+/// Prepends global flags (e.x. `--config <dir>` for isolated docker
+/// credentials, or `--host <socket>`) to the arguments, so that they come
+/// before the docker subcommand
+///
+/// ```
+/// use super_orchestrator::{
+///     middleware::{global_flags, sudo},
+///     Command,
+/// };
+///
+/// let with_config = global_flags(["--config", "/tmp/docker_config"]);
+/// let command = sudo()(with_config(Command::new("docker ps")));
+/// assert_eq!(command.program, "sudo");
+/// assert_eq!(command.args, [
+///     "docker",
+///     "--config",
+///     "/tmp/docker_config",
+///     "ps"
+/// ]);
+/// ```
+pub fn global_flags<I, S>(flags: I) -> impl Fn(Command) -> Command + Send + Sync + Clone + 'static
+where
+    I: IntoIterator<Item = S>,
+    S: Into<OsString>,
+{
+    let flags: Vec<OsString> = flags.into_iter().map(|flag| flag.into()).collect();
+    move |mut command: Command| {
+        command.args.splice(0..0, flags.iter().cloned());
+        command
+    }
+}
@@ -1,41 +1,801 @@
 use std::{
     any::type_name,
+    cmp,
     collections::HashSet,
     ffi::OsString,
+    fmt::Debug,
     future::Future,
+    hash::{Hash, Hasher},
+    io::Write,
+    ops::Deref,
     path::{Path, PathBuf},
     str::FromStr,
-    sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, SystemTime},
 };
 
 pub(crate) use color_cycle::next_terminal_color;
+use serde::{Deserialize, Serialize};
 use stacked_errors::{Error, ErrorKind, Result, StackableErr};
 use tokio::{
     fs::{read_dir, remove_file, File},
     io::AsyncWriteExt,
-    time::sleep,
+    sync::broadcast,
+    time::{sleep, Instant},
 };
+use tracing::warn;
 
-use crate::{acquire_dir_path, Command};
+use crate::{
+    acquire_dir_path,
+    docker::{NetworkTopology, TOPOLOGY_ENV_VAR},
+    Command,
+};
 
 /// Used by [crate::ctrlc_init] and [crate::ctrlc_issued_reset]
 pub static CTRLC_ISSUED: AtomicBool = AtomicBool::new(false);
 
-/// Sets up the ctrl-c handler
+// the last `ShutdownSignal` as a `u8`, 0 if there is none
+static SHUTDOWN_SIGNAL: AtomicU8 = AtomicU8::new(0);
+
+/// A signal handled by [ctrlc_init], see [shutdown_signal_received]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ShutdownSignal {
+    /// Ctrl-c (SIGINT on unix)
+    CtrlC = 1,
+    /// SIGTERM, sent by CI systems and orchestrators to cancel jobs
+    Terminate,
+    /// SIGHUP, sent when the controlling terminal is closed
+    Hangup,
+    /// Ctrl-break on Windows
+    CtrlBreak,
+}
+
+fn record_shutdown_signal(signal: ShutdownSignal) {
+    SHUTDOWN_SIGNAL.store(signal as u8, Ordering::SeqCst);
+    CTRLC_ISSUED.store(true, Ordering::SeqCst);
+}
+
+/// Returns the last signal that set `CTRLC_ISSUED`, or `None` if there has
+/// been none since the last [ctrlc_issued_reset]
+pub fn shutdown_signal_received() -> Option<ShutdownSignal> {
+    match SHUTDOWN_SIGNAL.load(Ordering::SeqCst) {
+        1 => Some(ShutdownSignal::CtrlC),
+        2 => Some(ShutdownSignal::Terminate),
+        3 => Some(ShutdownSignal::Hangup),
+        4 => Some(ShutdownSignal::CtrlBreak),
+        _ => None,
+    }
+}
+
+/// Writes `value` to stdout as a single line of JSON and flushes, guaranteeing
+/// the framing expected by
+/// [ContainerNetwork::json_lines_stream](crate::docker::ContainerNetwork::json_lines_stream)
+/// when used in an entrypoint
+pub fn emit_json_line<T: Serialize>(value: &T) -> Result<()> {
+    // the compact format never has newlines, any in strings are escaped
+    let mut line = serde_json::to_vec(value).stack_err_locationless(|| "emit_json_line")?;
+    line.push(b'\n');
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&line)
+        .and_then(|_| stdout.flush())
+        .stack_err_locationless(|| "emit_json_line -> when writing to stdout")
+}
+
+/// Sets up the handler that sets `CTRLC_ISSUED` on ctrl-c. If this is called
+/// within a tokio runtime, SIGTERM and SIGHUP on unix and ctrl-break on
+/// Windows are also handled, otherwise only ctrl-c is and a warning is issued.
+/// The default behavior of exiting on these signals is replaced, which signal
+/// was received can be found with [shutdown_signal_received].
 pub fn ctrlc_init() -> Result<()> {
     ctrlc::set_handler(move || {
-        CTRLC_ISSUED.store(true, Ordering::SeqCst);
+        record_shutdown_signal(ShutdownSignal::CtrlC);
     })
     .stack_err(|| "ctrlc_init() -> `ctrlc::set_handler` failed")?;
+    let Ok(handle) = tokio::runtime::Handle::try_current() else {
+        warn!(
+            "ctrlc_init() -> not called within a tokio runtime, only ctrl-c will set \
+             `CTRLC_ISSUED`"
+        );
+        emit_crate_event(|| CrateEvent::CtrlcWithoutRuntime);
+        return Ok(())
+    };
+    // the signal streams need to be created within the runtime
+    let _guard = handle.enter();
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        for (kind, shutdown_signal) in [
+            (SignalKind::terminate(), ShutdownSignal::Terminate),
+            (SignalKind::hangup(), ShutdownSignal::Hangup),
+        ] {
+            let mut stream = signal(kind).stack_err(|| {
+                format!("ctrlc_init() -> could not listen for {shutdown_signal:?}")
+            })?;
+            handle.spawn(async move {
+                while stream.recv().await.is_some() {
+                    record_shutdown_signal(shutdown_signal);
+                }
+            });
+        }
+    }
+    #[cfg(windows)]
+    {
+        let mut stream = tokio::signal::windows::ctrl_break()
+            .stack_err(|| "ctrlc_init() -> could not listen for ctrl-break")?;
+        handle.spawn(async move {
+            while stream.recv().await.is_some() {
+                record_shutdown_signal(ShutdownSignal::CtrlBreak);
+            }
+        });
+    }
     Ok(())
 }
 
-/// Returns if `CTRLC_ISSUED` has been set, and resets it to `false`
+/// Returns if `CTRLC_ISSUED` has been set, and resets it to `false` along with
+/// [shutdown_signal_received]
 pub fn ctrlc_issued_reset() -> bool {
+    SHUTDOWN_SIGNAL.store(0, Ordering::SeqCst);
     CTRLC_ISSUED.swap(false, Ordering::SeqCst)
 }
 
+/// How strictly conditions that would normally only be warned about are
+/// treated, see [set_strictness].
+///
+/// The participating sites are:
+///
+/// - A `CommandRunner` dropped without being finished (a `Drop` site)
+/// - A `ContainerNetwork` dropped with active containers (a `Drop` site,
+///   `ContainerNetwork::strictness` overrides the global strictness)
+/// - `ContainerNetwork::wait_with_timeout_all` being called with no
+///   non-infrastructure containers to wait on (returns an error in strict mode)
+///
+/// `Drop` sites cannot return errors and panicking in them is too dangerous,
+/// so in strict mode they record the violation to be retrieved with
+/// [take_strictness_violations]. Warnings are always emitted.
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+pub enum Strictness {
+    /// Only warn, the default
+    #[default]
+    Lenient,
+    /// Record violations from `Drop` sites and return errors from other sites
+    Strict,
+}
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+static STRICTNESS_VIOLATIONS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+/// Sets the process-global [Strictness]
+pub fn set_strictness(strictness: Strictness) {
+    STRICT.store(strictness == Strictness::Strict, Ordering::SeqCst);
+}
+
+/// Returns the process-global [Strictness]
+pub fn strictness() -> Strictness {
+    if STRICT.load(Ordering::SeqCst) {
+        Strictness::Strict
+    } else {
+        Strictness::Lenient
+    }
+}
+
+/// Takes all the violations recorded by `Drop` sites in strict mode. A test
+/// harness can assert that this is empty at the end.
+pub fn take_strictness_violations() -> Vec<String> {
+    // a panic while holding the lock cannot leave the `Vec` in a bad state
+    let mut violations = STRICTNESS_VIOLATIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    std::mem::take(&mut *violations)
+}
+
+/// For `Drop` sites, warns and records the violation if `strictness` is strict
+pub(crate) fn strictness_violation(strictness: Strictness, msg: String) {
+    warn!("{msg}");
+    if strictness == Strictness::Strict {
+        STRICTNESS_VIOLATIONS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(msg);
+    }
+}
+
+/// For non-`Drop` sites, warns if `strictness` is lenient or returns an error
+/// if it is strict
+pub(crate) fn strictness_check(strictness: Strictness, msg: String) -> Result<()> {
+    match strictness {
+        Strictness::Lenient => {
+            warn!("{msg}");
+            Ok(())
+        }
+        Strictness::Strict => Err(Error::from_kind_locationless(msg)),
+    }
+}
+
+/// An event emitted alongside some of the warnings of this crate, so that tests
+/// can assert on them without capturing `tracing` output, see
+/// [subscribe_crate_events]. The warnings are still emitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CrateEvent {
+    /// A `CommandRunner` was dropped without being finished
+    DroppedRunner { command: String },
+    /// A `ContainerNetwork` was dropped without all of its active containers
+    /// being terminated
+    DroppedNetwork { network_name: String },
+    /// A `RunningEnvironment` was dropped without `RunningEnvironment::down`
+    /// being called
+    DroppedEnvironment { network_name: String },
+    /// `ContainerNetwork::wait_with_timeout_all` was called in lenient mode
+    /// with no active non-infrastructure containers to wait on
+    NothingToWaitOn { network_name: String },
+    /// A container with a `crash_artifacts_dir` crashed
+    ContainerCrashed {
+        name: String,
+        crash_artifacts: Vec<PathBuf>,
+    },
+    /// The `crash_artifacts_dir` of a container could not be read
+    CrashArtifactsUnreadable { name: String, error: String },
+    /// `docker pull` failed with `PullPolicy::Newer` and the local image was
+    /// used instead
+    PullFellBackToLocal { name_tag: String, error: String },
+    /// A `docker logout` after using a pull secret failed
+    LogoutFailed { registry: String, error: String },
+    /// The `prune_after_teardown` of a `ContainerNetwork` failed
+    PruneFailed { network_name: String, error: String },
+    /// A container has `read_only_rootfs` set and a `workdir` that is not
+    /// within a volume or tmpfs
+    UnwritableWorkdir { name: String, workdir: String },
+    /// The local part `source` of a volume without an expected kind is a file
+    /// where the container path `target` looks like it is meant for a
+    /// directory or vice versa, see
+    /// [check_volume_kind](crate::docker::check_volume_kind)
+    SuspiciousVolumeKind {
+        name: String,
+        source: String,
+        target: String,
+    },
+    /// Resource usage monitoring of a `ContainerNetwork` failed
+    ResourceMonitorFailed { network_name: String, error: String },
+    /// [ctrlc_init] was called outside of a tokio runtime
+    CtrlcWithoutRuntime,
+    /// Writing to the capture file of a `NetMessenger` failed
+    CaptureWriteFailed { error: String },
+    /// A container hook with `HookFailurePolicy::Log` failed
+    HookFailed {
+        name: String,
+        phase: String,
+        error: String,
+    },
+    /// A container with a `supervise` policy exited and is being restarted,
+    /// `restarts` counts every restart of this run of the network
+    ContainerRestarted {
+        name: String,
+        restarts: u64,
+        exit_code: Option<i32>,
+    },
+    /// A teardown command of a `ContainerNetwork` failed even after retrying
+    TeardownFailed {
+        name: String,
+        command: String,
+        stderr: String,
+    },
+    /// Any other warning
+    Other { message: String },
+}
+
+/// The number of events buffered for each receiver, receivers that fall
+/// further behind lose the oldest events
+const CRATE_EVENT_CAPACITY: usize = 1024;
+
+static CRATE_EVENTS: OnceLock<broadcast::Sender<CrateEvent>> = OnceLock::new();
+
+/// Subscribes to the [CrateEvent]s of the whole process from now on.
+///
+/// Note that tests in the same test binary run in parallel by default and all
+/// of their events go to every receiver, so assertions should be on events
+/// with names unique to the test.
+///
+/// ```
+/// use stacked_errors::Result;
+/// use super_orchestrator::{assert_no_crate_events, subscribe_crate_events, CrateEvent};
+///
+/// fn test() -> Result<()> {
+///     let mut events = subscribe_crate_events();
+///     // ...
+///     assert_no_crate_events!(events, CrateEvent::DroppedRunner { .. })
+/// }
+/// test().unwrap();
+/// ```
+pub fn subscribe_crate_events() -> CrateEventReceiver {
+    let sender = CRATE_EVENTS.get_or_init(|| broadcast::channel(CRATE_EVENT_CAPACITY).0);
+    CrateEventReceiver {
+        receiver: sender.subscribe(),
+        lagged: 0,
+    }
+}
+
+/// `Error` is not `Clone`, this rebuilds the stack of `e` with the same
+/// locations. Kinds that cannot be cloned (e.x. boxed errors) are replaced by
+/// a string of their `Debug` output.
+pub(crate) fn clone_error(e: &Error) -> Error {
+    let mut copy = Error::empty();
+    for (kind, location) in e.stack.iter() {
+        let kind = match kind {
+            ErrorKind::UnitError => ErrorKind::UnitError,
+            ErrorKind::TimeoutError => ErrorKind::TimeoutError,
+            ErrorKind::ProbablyNotRootCauseError => ErrorKind::ProbablyNotRootCauseError,
+            ErrorKind::StrError(s) => ErrorKind::StrError(s),
+            ErrorKind::StringError(s) => ErrorKind::StringError(s.clone()),
+            ErrorKind::CowStrError(s) => ErrorKind::CowStrError(s.clone()),
+            kind => ErrorKind::StringError(format!("{kind:?}")),
+        };
+        copy.stack.push((kind, *location));
+    }
+    copy
+}
+
+/// Sends the event from `f` if there are any subscribers, `f` is not called
+/// otherwise
+pub(crate) fn emit_crate_event(f: impl FnOnce() -> CrateEvent) {
+    if let Some(sender) = CRATE_EVENTS.get() {
+        if sender.receiver_count() != 0 {
+            // there can be a race with the last receiver being dropped, which is fine
+            let _ = sender.send(f());
+        }
+    }
+}
+
+/// Receives [CrateEvent]s, see [subscribe_crate_events]
+#[derive(Debug)]
+pub struct CrateEventReceiver {
+    receiver: broadcast::Receiver<CrateEvent>,
+    lagged: u64,
+}
+
+impl CrateEventReceiver {
+    /// Returns the next event if one has already been emitted
+    pub fn try_recv(&mut self) -> Option<CrateEvent> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::TryRecvError::Lagged(lagged)) => self.lagged += lagged,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Takes all the events that have already been emitted
+    pub fn drain(&mut self) -> Vec<CrateEvent> {
+        std::iter::from_fn(|| self.try_recv()).collect()
+    }
+
+    /// The number of events lost because this receiver fell too far behind
+    pub fn lagged(&self) -> u64 {
+        self.lagged
+    }
+
+    /// Waits for an event for which `predicate` returns true, skipping the
+    /// other events. Returns a timeout error if `timeout` is reached first.
+    pub async fn expect<F: FnMut(&CrateEvent) -> bool>(
+        &mut self,
+        timeout: Duration,
+        mut predicate: F,
+    ) -> Result<CrateEvent> {
+        let mut skipped = vec![];
+        let res = tokio::time::timeout(timeout, async {
+            loop {
+                match self.receiver.recv().await {
+                    Ok(event) if predicate(&event) => return Some(event),
+                    Ok(event) => skipped.push(event),
+                    Err(broadcast::error::RecvError::Lagged(lagged)) => self.lagged += lagged,
+                    // the sender is static
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .await;
+        match res {
+            Ok(Some(event)) => Ok(event),
+            _ => Err(Error::timeout().add_kind_locationless(format!(
+                "CrateEventReceiver::expect -> no matching event within {timeout:?}, other events \
+                 were: {skipped:?}"
+            ))),
+        }
+    }
+
+    /// Takes all the events that have already been emitted and returns an
+    /// error listing them if there were any
+    pub fn assert_none(&mut self) -> Result<()> {
+        self.assert_none_matching(|_| true)
+    }
+
+    /// Takes all the events that have already been emitted and returns an
+    /// error listing the ones for which `predicate` returns true
+    pub fn assert_none_matching<F: FnMut(&CrateEvent) -> bool>(
+        &mut self,
+        mut predicate: F,
+    ) -> Result<()> {
+        let events: Vec<CrateEvent> = self
+            .drain()
+            .into_iter()
+            .filter(|event| predicate(event))
+            .collect();
+        if events.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::from_kind_locationless(format!(
+                "CrateEventReceiver::assert_none -> unexpected events: {events:?}"
+            )))
+        }
+    }
+}
+
+/// Returns an error if any events matching the pattern (or any events at all
+/// if no pattern is given) have been received by the [CrateEventReceiver]
+#[macro_export]
+macro_rules! assert_no_crate_events {
+    ($receiver:expr) => {
+        $receiver.assert_none()
+    };
+    ($receiver:expr, $($pattern:pat_param)|+ $(if $guard:expr)?) => {
+        $receiver.assert_none_matching(|event| {
+            matches!(event, $($pattern)|+ $(if $guard)?)
+        })
+    };
+}
+
+/// Waits up to the timeout for an event matching the pattern to be received by
+/// the [CrateEventReceiver], returning it
+#[macro_export]
+macro_rules! expect_crate_event {
+    ($receiver:expr, $timeout:expr, $($pattern:pat_param)|+ $(if $guard:expr)?) => {
+        $receiver.expect($timeout, |event| matches!(event, $($pattern)|+ $(if $guard)?))
+    };
+}
+
+static BASE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Sets the process-global base directory that relative paths given to
+/// [acquire_path](crate::acquire_path) and the other `acquire_*` functions
+/// (and so [FileOptions](crate::FileOptions) and `ContainerNetwork` paths) are
+/// resolved against instead of the current working directory. This is useful
+/// because the working directory differs between `cargo test` at a workspace
+/// root and running a test binary directly, see [workspace_dir].
+///
+/// The precedence for relative paths is
+/// [ContainerNetwork::base_dir](crate::docker::ContainerNetwork::base_dir)
+/// (for the paths of that network), then this global base directory, then the
+/// current working directory. Absolute paths are unaffected. If `base_dir` is
+/// itself relative, it is resolved against the current working directory.
+pub fn set_base_dir(base_dir: impl AsRef<Path>) {
+    *BASE_DIR.lock().unwrap_or_else(|e| e.into_inner()) = Some(base_dir.as_ref().to_owned());
+}
+
+/// Unsets the base directory set by [set_base_dir], so that relative paths are
+/// resolved against the current working directory again
+pub fn clear_base_dir() {
+    *BASE_DIR.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Returns the base directory set by [set_base_dir]
+pub fn base_dir() -> Option<PathBuf> {
+    BASE_DIR.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Finds the root of the cargo workspace by walking up from
+/// `CARGO_MANIFEST_DIR` (set by `cargo run` and `cargo test`), or from the
+/// directory of the current executable if it is not set. Returns the topmost
+/// directory with a `Cargo.toml` that has a `[workspace]` table, or else the
+/// nearest directory with a `Cargo.toml`. Tests can call
+/// `set_base_dir(workspace_dir()?)` once so that relative paths work from any
+/// working directory.
+pub fn workspace_dir() -> Result<PathBuf> {
+    let start = if let Some(manifest_dir) = std::env::var_os("CARGO_MANIFEST_DIR") {
+        PathBuf::from(manifest_dir)
+    } else {
+        let mut exe = std::env::current_exe()
+            .stack_err_locationless(|| "workspace_dir -> could not get the current executable")?;
+        exe.pop();
+        exe
+    };
+    let mut nearest = None;
+    let mut workspace = None;
+    for dir in start.ancestors() {
+        let Ok(manifest) = std::fs::read_to_string(dir.join("Cargo.toml")) else {
+            continue
+        };
+        if nearest.is_none() {
+            nearest = Some(dir.to_owned());
+        }
+        if manifest.lines().any(|line| line.trim() == "[workspace]") {
+            workspace = Some(dir.to_owned());
+        }
+    }
+    workspace.or(nearest).stack_err_locationless(|| {
+        format!("workspace_dir -> could not find a `Cargo.toml` in {start:?} or its ancestors")
+    })
+}
+
+/// Options for [find_binary]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindBinaryOptions {
+    /// The cargo profile the binary was built with, "release" by default.
+    /// "dev" and "test" use the "debug" directory and "bench" uses "release"
+    /// like cargo does.
+    pub profile: String,
+    /// The target triple if the binary was built with `--target`
+    pub target: Option<String>,
+    /// If the binary was built with `--example`
+    pub example: bool,
+    /// An explicit target directory, which is probed first
+    pub target_dir: Option<PathBuf>,
+    /// The `CARGO_TARGET_DIR` override, read from the environment by
+    /// [FindBinaryOptions::new]
+    pub env_target_dir: Option<PathBuf>,
+    /// If `cargo metadata` should be run to find the target directory of the
+    /// workspace, `true` by default
+    pub cargo_metadata: bool,
+    /// The directory of the workspace, which `cargo metadata` is run in and
+    /// which has the fallback "target" directory. If this is `None`,
+    /// [workspace_dir] is used.
+    pub workspace_dir: Option<PathBuf>,
+    /// If set, binaries last modified before this are skipped as stale (e.x.
+    /// the modification time of the source)
+    pub newer_than: Option<SystemTime>,
+}
+
+impl Default for FindBinaryOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FindBinaryOptions {
+    /// The default options, with `env_target_dir` set from `CARGO_TARGET_DIR`
+    pub fn new() -> Self {
+        Self {
+            profile: "release".to_owned(),
+            target: None,
+            example: false,
+            target_dir: None,
+            env_target_dir: std::env::var_os("CARGO_TARGET_DIR").map(PathBuf::from),
+            cargo_metadata: true,
+            workspace_dir: None,
+            newer_than: None,
+        }
+    }
+
+    /// Sets `profile`
+    pub fn profile(mut self, profile: impl AsRef<str>) -> Self {
+        self.profile = profile.as_ref().to_owned();
+        self
+    }
+
+    /// Sets `target`
+    pub fn target(mut self, target: Option<impl AsRef<str>>) -> Self {
+        self.target = target.map(|target| target.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `example`
+    pub fn example(mut self, example: bool) -> Self {
+        self.example = example;
+        self
+    }
+
+    /// Sets `target_dir`
+    pub fn target_dir(mut self, target_dir: Option<impl AsRef<Path>>) -> Self {
+        self.target_dir = target_dir.map(|dir| dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `env_target_dir`, overriding the `CARGO_TARGET_DIR` that was read
+    /// from the environment
+    pub fn env_target_dir(mut self, env_target_dir: Option<impl AsRef<Path>>) -> Self {
+        self.env_target_dir = env_target_dir.map(|dir| dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `cargo_metadata`
+    pub fn cargo_metadata(mut self, cargo_metadata: bool) -> Self {
+        self.cargo_metadata = cargo_metadata;
+        self
+    }
+
+    /// Sets `workspace_dir`
+    pub fn workspace_dir(mut self, workspace_dir: Option<impl AsRef<Path>>) -> Self {
+        self.workspace_dir = workspace_dir.map(|dir| dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `newer_than`
+    pub fn newer_than(mut self, newer_than: Option<SystemTime>) -> Self {
+        self.newer_than = newer_than;
+        self
+    }
+
+    /// The directory under a target directory that the binary is in
+    fn relative_dir(&self) -> PathBuf {
+        let mut dir = PathBuf::new();
+        if let Some(ref target) = self.target {
+            dir.push(target);
+        }
+        dir.push(match self.profile.as_str() {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            profile => profile,
+        });
+        if self.example {
+            dir.push("examples");
+        }
+        dir
+    }
+
+    fn exe_suffix(&self) -> &'static str {
+        match self.target {
+            Some(ref target) if target.contains("windows") => ".exe",
+            Some(_) => "",
+            None => std::env::consts::EXE_SUFFIX,
+        }
+    }
+}
+
+/// Returns the `target_directory` from `cargo metadata` run in `dir`
+async fn cargo_metadata_target_dir(dir: Option<&Path>) -> Result<PathBuf> {
+    let mut command = Command::new("cargo metadata --format-version 1 --no-deps");
+    if let Some(dir) = dir {
+        command = command.cwd(dir);
+    }
+    let comres = command.run_to_completion().await?;
+    comres.assert_success()?;
+    let metadata: serde_json::Value = serde_json::from_slice(&comres.stdout)
+        .stack_err_locationless(|| "could not parse the `cargo metadata` output")?;
+    metadata["target_directory"]
+        .as_str()
+        .map(PathBuf::from)
+        .stack_err_locationless(|| "`cargo metadata` output has no \"target_directory\"")
+}
+
+/// Finds the path to the compiled binary `bin_name`, for use with
+/// [Container::external_entrypoint](crate::docker::Container::external_entrypoint)
+/// without hardcoding "./target/release/{bin_name}". The target directories
+/// probed in order are `options.target_dir`, `CARGO_TARGET_DIR`, the
+/// workspace target directory from `cargo metadata`, and "target" in the
+/// workspace directory. In each, the binary is expected at
+/// "[{target}/]{profile}/[examples/]{bin_name}".
+///
+/// # Errors
+///
+/// If the binary is not found or is older than `options.newer_than` in every
+/// location, the error lists every location probed and why it was skipped.
+pub async fn find_binary(bin_name: &str, options: &FindBinaryOptions) -> Result<PathBuf> {
+    let mut probed: Vec<String> = vec![];
+    let workspace_dir = options
+        .workspace_dir
+        .clone()
+        .or_else(|| workspace_dir().ok());
+    let mut target_dirs: Vec<PathBuf> = vec![];
+    target_dirs.extend(options.target_dir.clone());
+    target_dirs.extend(options.env_target_dir.clone());
+    if options.cargo_metadata {
+        match cargo_metadata_target_dir(workspace_dir.as_deref()).await {
+            Ok(dir) => target_dirs.push(dir),
+            Err(e) => probed.push(format!("`cargo metadata` failed: {e:?}")),
+        }
+    }
+    if let Some(ref workspace_dir) = workspace_dir {
+        target_dirs.push(workspace_dir.join("target"));
+    } else {
+        target_dirs.push(PathBuf::from("target"));
+    }
+    let file_name = format!("{bin_name}{}", options.exe_suffix());
+    let relative_dir = options.relative_dir();
+    let mut seen = HashSet::new();
+    for target_dir in target_dirs {
+        let path = target_dir.join(&relative_dir).join(&file_name);
+        if !seen.insert(path.clone()) {
+            continue
+        }
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => {
+                probed.push(format!("{path:?}: not a file"));
+                continue
+            }
+            Err(_) => {
+                probed.push(format!("{path:?}: not found"));
+                continue
+            }
+        };
+        if let Some(newer_than) = options.newer_than {
+            match metadata.modified() {
+                Ok(modified) if modified >= newer_than => (),
+                Ok(_) => {
+                    probed.push(format!("{path:?}: older than `newer_than`, rebuild it"));
+                    continue
+                }
+                Err(e) => {
+                    probed.push(format!(
+                        "{path:?}: could not get the modification time: {e}"
+                    ));
+                    continue
+                }
+            }
+        }
+        return Ok(path)
+    }
+    Err(Error::from_kind_locationless(format!(
+        "find_binary -> could not find the binary \"{bin_name}\", probed:\n{}",
+        probed.join("\n")
+    )))
+}
+
+/// Reads the newline separated arguments from a file written by
+/// [Container::entrypoint_args_file](crate::docker::Container::entrypoint_args_file)
+pub fn load_args_file(path: impl AsRef<Path>) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .stack_err_locationless(|| format!("load_args_file -> could not read {path:?}"))?;
+    Ok(contents.lines().map(|line| line.to_owned()).collect())
+}
+
+/// For use in entrypoint binaries, reads the [NetworkTopology] injected by
+/// [ContainerNetwork::inject_topology](crate::docker::ContainerNetwork::inject_topology)
+/// from the file that the [TOPOLOGY_ENV_VAR] environment variable points to
+///
+/// ```no_run
+/// use super_orchestrator::load_topology;
+///
+/// let topology = load_topology().unwrap();
+/// let server = topology.get("server").unwrap();
+/// let address = format!("{}:{}", server.container_name, server.ports[0]);
+/// ```
+pub fn load_topology() -> Result<NetworkTopology> {
+    let path = std::env::var_os(TOPOLOGY_ENV_VAR).stack_err_locationless(|| {
+        format!(
+            "load_topology -> the environment variable {TOPOLOGY_ENV_VAR} is not set, \
+             `ContainerNetwork::inject_topology` needs to be enabled"
+        )
+    })?;
+    let contents = std::fs::read_to_string(&path)
+        .stack_err_locationless(|| format!("load_topology -> could not read {path:?}"))?;
+    serde_json::from_str(&contents)
+        .stack_err_locationless(|| format!("load_topology -> could not parse {path:?}"))
+}
+
+/// For use in entrypoint binaries, returns the arguments of the current
+/// process (excluding the program name). If the arguments are
+/// `--args-file <path>` as passed by
+/// [Container::entrypoint_args_file](crate::docker::Container::entrypoint_args_file),
+/// the arguments are instead loaded from the file.
+///
+/// ```
+/// use super_orchestrator::load_entrypoint_args;
+///
+/// // works the same whether the container passes the arguments directly or
+/// // through a file
+/// let args = load_entrypoint_args().unwrap();
+/// ```
+pub fn load_entrypoint_args() -> Result<Vec<String>> {
+    let mut args = vec![];
+    for arg in std::env::args_os().skip(1) {
+        args.push(arg.into_string().map_err(|arg| {
+            Error::from_kind_locationless(format!(
+                "load_entrypoint_args -> argument {arg:?} is not UTF8"
+            ))
+        })?);
+    }
+    if (args.len() == 2) && (args[0] == "--args-file") {
+        load_args_file(&args[1]).stack_err_locationless(|| "load_entrypoint_args")
+    } else {
+        Ok(args)
+    }
+}
+
 /// Takes the hash of the type name of `T` and returns it. Has the
 /// potential to change between compiler versions.
 pub fn type_hash<T: ?Sized>() -> [u8; 16] {
@@ -111,8 +871,203 @@ where
         .stack_err_locationless(|| "sh_no_debug -> `Command` output was not UTF-8")
 }
 
+/// A backoff policy for retry loops.
+///
+/// The first interval is `initial`, and each subsequent interval is multiplied
+/// by `multiplier` up to `max_interval`. If `jitter` is nonzero, each interval
+/// is randomly scaled by a factor in `[1 - jitter, 1 + jitter]` and then capped
+/// by `max_interval` again, which prevents many retrying tasks from
+/// synchronizing. `max_retries` and `max_elapsed` limit [Backoff::retry].
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use super_orchestrator::Backoff;
+///
+/// let ms = Duration::from_millis;
+/// // the default schedule of `CommandRunner::wait_with_timeout`, see
+/// // `Command::wait_backoff`
+/// let backoff = Backoff::new(ms(1)).multiplier(2.0).max_interval(ms(128));
+/// let intervals: Vec<Duration> = backoff.intervals().take(10).collect();
+/// assert_eq!(intervals, [1, 2, 4, 8, 16, 32, 64, 128, 128, 128].map(ms));
+///
+/// // jittered intervals stay within the jitter bounds and the cap
+/// let backoff = backoff.jitter(0.25);
+/// let mut expected = ms(1);
+/// for interval in backoff.intervals().take(1000) {
+///     assert!(interval >= expected.mul_f64(0.75));
+///     assert!(interval <= expected.mul_f64(1.25));
+///     assert!(interval <= ms(128));
+///     expected = (expected * 2).min(ms(128));
+/// }
+///
+/// // `max_retries` bounds the number of intervals
+/// let backoff = Backoff::constant(ms(300)).max_retries(Some(3));
+/// assert_eq!(backoff.intervals().count(), 3);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Backoff {
+    /// The first interval
+    pub initial: Duration,
+    /// What each interval is multiplied by to get the next interval
+    pub multiplier: f64,
+    /// The maximum interval
+    pub max_interval: Duration,
+    /// If set, [Backoff::retry] stops retrying once this much time has
+    /// elapsed
+    pub max_elapsed: Option<Duration>,
+    /// If set, [Backoff::retry] stops after this many retries
+    pub max_retries: Option<u64>,
+    /// The jitter fraction, should be in `[0.0, 1.0]`
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// A `Backoff` with a constant `initial` interval, no limits, and no
+    /// jitter
+    pub const fn new(initial: Duration) -> Self {
+        Self {
+            initial,
+            multiplier: 1.0,
+            max_interval: Duration::MAX,
+            max_elapsed: None,
+            max_retries: None,
+            jitter: 0.0,
+        }
+    }
+
+    /// Same as [Backoff::new], for clarity when a constant interval is wanted
+    pub const fn constant(delay: Duration) -> Self {
+        Self::new(delay)
+    }
+
+    /// Sets `multiplier`
+    pub const fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Sets `max_interval`
+    pub const fn max_interval(mut self, max_interval: Duration) -> Self {
+        self.max_interval = max_interval;
+        self
+    }
+
+    /// Sets `max_elapsed`
+    pub const fn max_elapsed(mut self, max_elapsed: Option<Duration>) -> Self {
+        self.max_elapsed = max_elapsed;
+        self
+    }
+
+    /// Sets `max_retries`
+    pub const fn max_retries(mut self, max_retries: Option<u64>) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the `jitter` fraction, clamped to `[0.0, 1.0]`
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns an iterator over the (jittered) intervals. This ends after
+    /// `max_retries` intervals if it is set, but does not consider
+    /// `max_elapsed`.
+    pub fn intervals(&self) -> BackoffIntervals {
+        BackoffIntervals {
+            backoff: *self,
+            next: self.initial.min(self.max_interval),
+            count: 0,
+        }
+    }
+
+    /// Repeatedly calls `f` until it returns an `Ok` which is returned,
+    /// sleeping for the backoff intervals in between. If `max_retries` or
+    /// `max_elapsed` is reached, a timeout error is returned with the last
+    /// error and the number of attempts.
+    pub async fn retry<F: FnMut() -> Fut, Fut: Future<Output = Result<T>>, T>(
+        &self,
+        mut f: F,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let mut intervals = self.intervals();
+        let mut attempts = 0u64;
+        loop {
+            attempts = attempts.saturating_add(1);
+            let e = match f().await {
+                Ok(o) => return Ok(o),
+                Err(e) => e,
+            };
+            let interval = intervals.next();
+            let timed_out = match (interval, self.max_elapsed) {
+                (None, _) => true,
+                (Some(interval), Some(max_elapsed)) => {
+                    start.elapsed().saturating_add(interval) > max_elapsed
+                }
+                (Some(_), None) => false,
+            };
+            if timed_out {
+                return Err(e.add_kind_locationless(ErrorKind::TimeoutError)).stack_err_locationless(
+                    || {
+                        format!(
+                            "Backoff::retry timeout after {attempts} attempts, last error stack \
+                             was"
+                        )
+                    },
+                )
+            }
+            sleep(interval.unwrap()).await;
+        }
+    }
+}
+
+/// Multiplies `d` by `factor`, saturating instead of panicking
+fn scale_duration(d: Duration, factor: f64) -> Duration {
+    Duration::try_from_secs_f64(d.as_secs_f64() * factor).unwrap_or(if factor > 0.0 {
+        Duration::MAX
+    } else {
+        Duration::ZERO
+    })
+}
+
+/// The iterator returned by [Backoff::intervals]
+#[derive(Debug, Clone)]
+pub struct BackoffIntervals {
+    backoff: Backoff,
+    // the next interval before jitter
+    next: Duration,
+    count: u64,
+}
+
+impl Iterator for BackoffIntervals {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        if let Some(max_retries) = self.backoff.max_retries {
+            if self.count >= max_retries {
+                return None
+            }
+        }
+        self.count = self.count.saturating_add(1);
+        let current = self.next;
+        self.next = scale_duration(current, self.backoff.multiplier).min(self.backoff.max_interval);
+        let jitter = self.backoff.jitter;
+        let interval = if jitter > 0.0 {
+            let factor = 1.0 + jitter * (fastrand::f64() * 2.0 - 1.0);
+            scale_duration(current, factor).min(self.backoff.max_interval)
+        } else {
+            current
+        };
+        Some(interval)
+    }
+}
+
 /// Repeatedly polls `f` until it returns an `Ok` which is returned, or
-/// `num_retries` is reached in which a timeout error is returned.
+/// `num_retries` is reached in which a timeout error is returned. This uses a
+/// constant [Backoff].
 ///
 /// # Example
 ///
@@ -148,28 +1103,457 @@ where
 pub async fn wait_for_ok<F: FnMut() -> Fut, Fut: Future<Output = Result<T>>, T>(
     num_retries: u64,
     delay: Duration,
-    mut f: F,
+    f: F,
 ) -> Result<T> {
-    let mut i = num_retries;
-    loop {
-        match f().await {
-            Ok(o) => return Ok(o),
-            Err(e) => {
-                if i == 0 {
-                    return Err(e.add_kind_locationless(ErrorKind::TimeoutError))
-                        .stack_err_locationless(|| {
-                            format!(
-                                "wait_for_ok(num_retries: {num_retries}, delay: {delay:?}) \
-                                 timeout, last error stack was"
-                            )
-                        })
-                }
-                i -= 1;
+    Backoff::constant(delay)
+        .max_retries(Some(num_retries))
+        .retry(f)
+        .await
+        .stack_err_locationless(|| {
+            format!("wait_for_ok(num_retries: {num_retries}, delay: {delay:?})")
+        })
+}
+
+/// Returns the deadline that is `duration` from now, saturating to a deadline
+/// far in the future instead of overflowing for huge durations
+pub fn deadline_after(duration: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(duration)
+        .unwrap_or_else(|| now + Duration::from_secs(60 * 60 * 24 * 365 * 30))
+}
+
+/// An overall time budget for a sequence of waits, so that deadline based
+/// functions like [ContainerNetwork::wait_with_timeout_at] can be chained
+/// without recomputing the remaining time by hand.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use super_orchestrator::TestBudget;
+///
+/// let budget = TestBudget::new(Duration::from_secs(60));
+/// assert!(budget.remaining() <= Duration::from_secs(60));
+/// assert!(!budget.is_exhausted());
+/// // the remaining time saturates at zero
+/// let budget = TestBudget::new(Duration::ZERO);
+/// assert_eq!(budget.remaining(), Duration::ZERO);
+/// ```
+///
+/// [ContainerNetwork::wait_with_timeout_at]:
+/// crate::docker::ContainerNetwork::wait_with_timeout_at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestBudget {
+    start: Instant,
+    deadline: Instant,
+}
+
+impl TestBudget {
+    /// Starts a budget of `total` time from now
+    pub fn new(total: Duration) -> Self {
+        let start = Instant::now();
+        Self {
+            start,
+            deadline: start
+                .checked_add(total)
+                .unwrap_or_else(|| deadline_after(total)),
+        }
+    }
+
+    /// The deadline of the budget
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// The time remaining until the deadline, or `Duration::ZERO` if it has
+    /// passed
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// The time since the budget was started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// If the deadline has passed
+    pub fn is_exhausted(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
+/// Splits off a leading decimal number (digits with an optional single '.'),
+/// returning the number and the rest
+fn split_decimal(s: &str) -> (&str, &str) {
+    let mut seen_dot = false;
+    let split = s
+        .find(|c: char| {
+            if c == '.' && !seen_dot {
+                seen_dot = true;
+                false
+            } else {
+                !c.is_ascii_digit()
             }
+        })
+        .unwrap_or(s.len());
+    s.split_at(split)
+}
+
+/// Computes `num * multiplier` exactly for a decimal `num`, truncating any
+/// fraction of a unit. Returns `None` if `num` has no digits or on overflow.
+fn scale_decimal(num: &str, multiplier: u128) -> Option<u128> {
+    let (int, frac) = num.split_once('.').unwrap_or((num, ""));
+    if int.is_empty() && frac.is_empty() {
+        return None
+    }
+    let int: u128 = if int.is_empty() { 0 } else { int.parse().ok()? };
+    let mut res = int.checked_mul(multiplier)?;
+    // digits past this are below the precision of any multiplier
+    let frac = &frac[..frac.len().min(30)];
+    if !frac.is_empty() {
+        let frac_num: u128 = frac.parse().ok()?;
+        let frac_res = frac_num.checked_mul(multiplier)? / 10u128.pow(frac.len() as u32);
+        res = res.checked_add(frac_res)?;
+    }
+    Some(res)
+}
+
+/// Parses a human-friendly byte size such as "512MiB", "1.5 GB", "4096", or
+/// "10kB". A missing unit means bytes. Units are case insensitive, "KB", "MB",
+/// "GB", and "TB" are decimal, while "KiB", "MiB", "GiB", and "TiB" are
+/// binary. Fractional values are allowed, and any resulting fraction of a byte
+/// is truncated.
+///
+/// # Errors
+///
+/// If the string is empty, has no number, has an unknown unit, or the size
+/// overflows a `u64`. The error message includes the offending string.
+///
+/// ```
+/// use super_orchestrator::parse_size;
+///
+/// assert_eq!(parse_size("0").unwrap(), 0);
+/// assert_eq!(parse_size("4096").unwrap(), 4096);
+/// assert_eq!(parse_size("4096B").unwrap(), 4096);
+/// assert_eq!(parse_size("10kB").unwrap(), 10_000);
+/// assert_eq!(parse_size("10KB").unwrap(), 10_000);
+/// assert_eq!(parse_size("1KiB").unwrap(), 1024);
+/// assert_eq!(parse_size("512MiB").unwrap(), 512 * 1024 * 1024);
+/// assert_eq!(parse_size("512mib").unwrap(), 512 * 1024 * 1024);
+/// assert_eq!(parse_size("250MB").unwrap(), 250_000_000);
+/// assert_eq!(parse_size(" 1.5 GB ").unwrap(), 1_500_000_000);
+/// assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+/// assert_eq!(parse_size("3TB").unwrap(), 3_000_000_000_000);
+/// assert_eq!(parse_size("1TiB").unwrap(), 1 << 40);
+///
+/// // fractional values, with any fraction of a byte truncated
+/// assert_eq!(parse_size("0.5KiB").unwrap(), 512);
+/// assert_eq!(parse_size(".5KiB").unwrap(), 512);
+/// assert_eq!(parse_size("1.").unwrap(), 1);
+/// assert_eq!(parse_size("1.1KiB").unwrap(), 1126);
+/// assert_eq!(parse_size("1.9").unwrap(), 1);
+/// assert_eq!(parse_size("0.000000001GB").unwrap(), 1);
+///
+/// // the maximum
+/// assert_eq!(parse_size("18446744073709551615").unwrap(), u64::MAX);
+///
+/// // errors include the offending string
+/// let e = format!("{:?}", parse_size("12XB").unwrap_err());
+/// assert!(e.contains("\"12XB\""));
+/// assert!(parse_size("").is_err());
+/// assert!(parse_size("   ").is_err());
+/// assert!(parse_size("MiB").is_err());
+/// assert!(parse_size(".").is_err());
+/// assert!(parse_size("-1KiB").is_err());
+/// assert!(parse_size("1..5KiB").is_err());
+/// assert!(parse_size("1 2").is_err());
+/// assert!(parse_size("1KiB2").is_err());
+/// // overflow
+/// assert!(parse_size("18446744073709551616").is_err());
+/// assert!(parse_size("20000000TB").is_err());
+/// assert!(parse_size("99999999999999999999999999999999999999999").is_err());
+/// ```
+pub fn parse_size(size: &str) -> Result<u64> {
+    let trimmed = size.trim();
+    let (num, unit) = split_decimal(trimmed);
+    let multiplier: u128 = match unit.trim_start().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => {
+            return Err(Error::from_kind_locationless(format!(
+                "parse_size -> unknown or missing unit in \"{size}\""
+            )))
         }
-        // for `num_retries` we have the check afterwards so that 0 retries can still
-        // pass
-        sleep(delay).await;
+    };
+    let bytes = scale_decimal(num, multiplier).stack_err_locationless(|| {
+        format!("parse_size -> could not parse the number in \"{size}\"")
+    })?;
+    u64::try_from(bytes)
+        .ok()
+        .stack_err_locationless(|| format!("parse_size -> \"{size}\" overflows a `u64`"))
+}
+
+/// Parses a human-friendly duration such as "15m", "1.5h", "250ms", or
+/// "1h 30m 15s". Each number must have a unit of "ms", "s", "m", or "h".
+/// Fractional values are allowed, and any resulting fraction of a nanosecond
+/// is truncated.
+///
+/// # Errors
+///
+/// If the string is empty, a number or unit is missing, a unit is unknown, or
+/// the duration overflows a `Duration`. The error message includes the
+/// offending string.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use super_orchestrator::parse_duration;
+///
+/// assert_eq!(parse_duration("0s").unwrap(), Duration::ZERO);
+/// assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+/// assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+/// assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(15 * 60));
+/// assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(2 * 3600));
+/// assert_eq!(parse_duration(" 1 h ").unwrap(), Duration::from_secs(3600));
+///
+/// // combinations
+/// assert_eq!(
+///     parse_duration("1h30m").unwrap(),
+///     Duration::from_secs(3600 + 30 * 60)
+/// );
+/// assert_eq!(
+///     parse_duration("1h 30m 15s 500ms").unwrap(),
+///     Duration::from_millis((3600 + 30 * 60 + 15) * 1000 + 500)
+/// );
+///
+/// // fractional values
+/// assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+/// assert_eq!(parse_duration(".5s").unwrap(), Duration::from_millis(500));
+/// assert_eq!(parse_duration("0.1s").unwrap(), Duration::from_millis(100));
+/// assert_eq!(
+///     parse_duration("1.5ms").unwrap(),
+///     Duration::from_micros(1500)
+/// );
+/// assert_eq!(parse_duration("0.0000000001s").unwrap(), Duration::ZERO);
+///
+/// // the maximum
+/// assert_eq!(
+///     parse_duration("18446744073709551615s").unwrap(),
+///     Duration::from_secs(u64::MAX)
+/// );
+///
+/// // errors include the offending string
+/// let e = format!("{:?}", parse_duration("15").unwrap_err());
+/// assert!(e.contains("\"15\""));
+/// assert!(parse_duration("").is_err());
+/// assert!(parse_duration("   ").is_err());
+/// assert!(parse_duration("s").is_err());
+/// assert!(parse_duration("15x").is_err());
+/// assert!(parse_duration("15 minutes").is_err());
+/// assert!(parse_duration("-5s").is_err());
+/// assert!(parse_duration("1h30").is_err());
+/// assert!(parse_duration("1..5s").is_err());
+/// // overflow
+/// assert!(parse_duration("18446744073709551616s").is_err());
+/// assert!(parse_duration("99999999999999999999h").is_err());
+/// assert!(parse_duration("10000000000000000000h 10000000000000000000h").is_err());
+/// ```
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    const NANOS_PER_SEC: u128 = 1_000_000_000;
+    let mut rest = duration.trim();
+    if rest.is_empty() {
+        return Err(Error::from_kind_locationless(format!(
+            "parse_duration -> empty duration \"{duration}\""
+        )))
+    }
+    let mut nanos: u128 = 0;
+    while !rest.is_empty() {
+        let (num, tail) = split_decimal(rest);
+        let tail = tail.trim_start();
+        let unit_len = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let multiplier = match unit {
+            "ms" => NANOS_PER_SEC / 1000,
+            "s" => NANOS_PER_SEC,
+            "m" => 60 * NANOS_PER_SEC,
+            "h" => 3600 * NANOS_PER_SEC,
+            "" => {
+                return Err(Error::from_kind_locationless(format!(
+                    "parse_duration -> missing unit in \"{duration}\""
+                )))
+            }
+            _ => {
+                return Err(Error::from_kind_locationless(format!(
+                    "parse_duration -> unknown unit \"{unit}\" in \"{duration}\""
+                )))
+            }
+        };
+        let component = scale_decimal(num, multiplier).stack_err_locationless(|| {
+            format!("parse_duration -> could not parse a number in \"{duration}\"")
+        })?;
+        nanos = nanos
+            .checked_add(component)
+            .stack_err_locationless(|| format!("parse_duration -> \"{duration}\" overflows"))?;
+        rest = tail.trim_start();
+    }
+    let secs = u64::try_from(nanos / NANOS_PER_SEC)
+        .ok()
+        .stack_err_locationless(|| {
+            format!("parse_duration -> \"{duration}\" overflows a `Duration`")
+        })?;
+    Ok(Duration::new(secs, (nanos % NANOS_PER_SEC) as u32))
+}
+
+/// A number of bytes, used by builders that can take either a `u64` or a
+/// string parsed by [parse_size]
+///
+/// ```
+/// use super_orchestrator::ByteSize;
+///
+/// assert_eq!(ByteSize::from(1024), ByteSize(1024));
+/// assert_eq!("1KiB".parse::<ByteSize>().unwrap(), ByteSize(1024));
+/// assert_eq!(ByteSize::try_from("1 MB").unwrap(), ByteSize(1_000_000));
+/// assert!(ByteSize::try_from("1 MX").is_err());
+/// ```
+#[derive(
+    Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct ByteSize(pub u64);
+
+/// An `Arc` that is compared and hashed by the address it points to, so that
+/// configuration holding callbacks (such as
+/// [BuildProgressCallback](crate::docker::BuildProgressCallback) and
+/// [CommandMiddleware](crate::middleware::CommandMiddleware)) can still derive
+/// the comparison traits. Configuration holding one is not serialized.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use super_orchestrator::ByAddress;
+///
+/// let f: Arc<dyn Fn() -> u8> = Arc::new(|| 0);
+/// let a = ByAddress(f.clone());
+/// assert_eq!(a, ByAddress(f));
+/// let g: Arc<dyn Fn() -> u8> = Arc::new(|| 0);
+/// assert_ne!(a, ByAddress(g));
+/// assert_eq!(a(), 0);
+/// ```
+pub struct ByAddress<T: ?Sized>(pub Arc<T>);
+
+impl<T: ?Sized> ByAddress<T> {
+    fn addr(&self) -> usize {
+        Arc::as_ptr(&self.0) as *const () as usize
+    }
+}
+
+impl<T: ?Sized> Clone for ByAddress<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Deref for ByAddress<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> Debug for ByAddress<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ByAddress({:#x})", self.addr())
+    }
+}
+
+impl<T: ?Sized> PartialEq for ByAddress<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
+}
+
+impl<T: ?Sized> Eq for ByAddress<T> {}
+
+impl<T: ?Sized> Hash for ByAddress<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr().hash(state)
+    }
+}
+
+impl<T: ?Sized> PartialOrd for ByAddress<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ?Sized> Ord for ByAddress<T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.addr().cmp(&other.addr())
+    }
+}
+
+impl ByteSize {
+    /// Converts from anything that can be converted into a `ByteSize`, which
+    /// is what the size-taking builders use
+    pub fn convert<B>(size: B) -> Result<Self>
+    where
+        B: TryInto<ByteSize>,
+        B::Error: Debug,
+    {
+        size.try_into()
+            .map_err(|e| Error::from_kind_locationless(format!("{e:?}")))
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<ByteSize> for u64 {
+    fn from(size: ByteSize) -> Self {
+        size.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_size(s).map(Self)
+    }
+}
+
+impl TryFrom<&str> for ByteSize {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        parse_size(s).map(Self)
+    }
+}
+
+impl TryFrom<&String> for ByteSize {
+    type Error = Error;
+
+    fn try_from(s: &String) -> Result<Self> {
+        parse_size(s).map(Self)
+    }
+}
+
+impl TryFrom<String> for ByteSize {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        parse_size(&s).map(Self)
     }
 }
 
@@ -1,15 +1,35 @@
-use std::{any::type_name, cmp::max, net::SocketAddr, time::Duration};
+use std::{
+    any::type_name,
+    cmp::max,
+    io::Write,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use stacked_errors::{Error, Result, StackableErr};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{lookup_host, TcpListener, TcpStream},
+    net::{
+        lookup_host,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
     select,
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
     time::sleep,
 };
+use tracing::warn;
+
+use crate::{misc::emit_crate_event, type_hash, wait_for_ok, CrateEvent, FileOptions};
+
+pub mod replay;
+mod stream;
 
-use crate::{type_hash, wait_for_ok};
+use replay::{CapturedFrame, Direction};
+pub use stream::*;
 
 /// Waits for looking up a host's `SocketAddr` to be successful.
 ///
@@ -60,6 +80,7 @@ pub struct NetMessenger {
     stream: TcpStream,
     // buffer whose capacity is kept around
     buf: Vec<u8>,
+    capture: Option<SharedCapture>,
 }
 
 impl NetMessenger {
@@ -76,7 +97,7 @@ impl NetMessenger {
         select! {
             tmp = listener.accept() => {
                 let (stream, _) = tmp.stack()?;
-                Ok(Self {stream, buf: vec![]})
+                Ok(Self {stream, buf: vec![], capture: None})
             }
             _ = sleep(timeout) => {
                 Err(Error::timeout())
@@ -100,9 +121,31 @@ impl NetMessenger {
         Ok(Self {
             stream,
             buf: vec![],
+            capture: None,
         })
     }
 
+    /// Appends every frame sent and received from now on to the capture file
+    /// as JSON lines, which can be parsed with [replay::Reader] or replayed
+    /// with [replay::MockMessenger]. The [ChannelHandle]s from
+    /// [NetMessenger::split_channels] share the capture and record their
+    /// channel.
+    ///
+    /// Sent frames include the `serde_json` rendering of the message if it has
+    /// one, received frames only have the raw serialized message because
+    /// `recv` does not require `Serialize` (use [CapturedFrame::decode] after
+    /// reading the capture). Nothing about the messaging changes besides the
+    /// file writes, and failures to write to the capture file are only
+    /// warned about.
+    pub async fn with_capture(mut self, capture: FileOptions) -> Result<Self> {
+        let file = capture
+            .acquire_file()
+            .await
+            .stack_err_locationless(|| "NetMessenger::with_capture")?;
+        self.capture = Some(Arc::new(std::sync::Mutex::new(file.into_std().await)));
+        Ok(self)
+    }
+
     /// Sends `msg` to the connected party, waiting for a corresponding `recv`
     /// call.
     ///
@@ -116,29 +159,7 @@ impl NetMessenger {
     /// binaries compiled by different compiler versions (but at least it is a
     /// false positive).
     pub async fn send<T: ?Sized + Serialize>(&mut self, msg: &T) -> Result<()> {
-        loop {
-            self.buf.clear();
-            self.buf.resize(self.buf.capacity(), 0);
-            match postcard::to_slice(msg, &mut self.buf) {
-                Ok(_) => (),
-                Err(postcard::Error::SerializeBufferFull) => {
-                    // double the capacity
-                    // TODO we need to add limits, maybe a settable option on the `NetMessage`
-                    // struct
-                    let current_cap = max(self.buf.capacity(), 1);
-                    // reserve is based on `self.len() + additional` instead of
-                    // `self.capacity() + additional`
-                    let double = current_cap.wrapping_shl(1);
-                    self.buf.reserve(double);
-                    continue
-                }
-                Err(e) => {
-                    return Err(Error::box_from(e))
-                        .stack_err_locationless(|| "failed to serialize message")?
-                }
-            }
-            break
-        }
+        serialize_to_buf(msg, &mut self.buf)?;
         // TODO handle timeouts
         let id = type_hash::<T>();
         if let Err(e) = self.stream.write_all(&id).await {
@@ -157,6 +178,13 @@ impl NetMessenger {
             .stack()?;
         self.stream.write_all(&self.buf).await.stack()?;
         self.stream.flush().await.stack()?;
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(Direction::Sent, None, type_name::<T>(), &id, &self.buf)
+                    .json(msg),
+            );
+        }
         Ok(())
     }
 
@@ -194,7 +222,329 @@ impl NetMessenger {
             .read_exact(&mut self.buf[0..data_len])
             .await
             .stack()?;
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(
+                    Direction::Received,
+                    None,
+                    type_name::<T>(),
+                    &actual_id,
+                    &self.buf[0..data_len],
+                ),
+            );
+        }
         postcard::from_bytes(&self.buf[0..data_len])
             .stack_err(|| "NetMessenger::recv() -> failed to deserialize message")
     }
+
+    /// Splits the connection into `num_channels` [ChannelHandle]s that are
+    /// multiplexed over it. Each handle has the same `send` and `recv` API,
+    /// and messages are delivered in order within each channel independently
+    /// of the other channels, so that e.x. a control channel is not blocked
+    /// behind bulk data that has not been received yet.
+    ///
+    /// Both sides must call this at the same point in their sequence of
+    /// messages, since it exchanges a handshake with the protocol version
+    /// using the single channel framing (which is unchanged for messengers
+    /// that never call this). The sides can have different numbers of
+    /// channels, but a message on a channel that the receiving side does not
+    /// have is an error for all of the receiving side's channels.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// use stacked_errors::{Result, StackableErr};
+    /// use super_orchestrator::net_message::NetMessenger;
+    ///
+    /// async fn entrypoint() -> Result<()> {
+    ///     let nm = NetMessenger::connect(10, Duration::from_millis(300), "host:26000")
+    ///         .await
+    ///         .stack()?;
+    ///     let mut channels = nm.split_channels(2).await.stack()?;
+    ///     let mut bulk = channels.pop().unwrap();
+    ///     let mut control = channels.pop().unwrap();
+    ///     bulk.send::<Vec<u8>>(&vec![0u8; 1 << 20]).await.stack()?;
+    ///     control.send::<String>(&"done".to_owned()).await.stack()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn split_channels(mut self, num_channels: u16) -> Result<Vec<ChannelHandle>> {
+        let handshake = ChannelHandshake {
+            version: CHANNEL_PROTOCOL_VERSION,
+            num_channels,
+        };
+        self.send::<ChannelHandshake>(&handshake)
+            .await
+            .stack_err_locationless(|| "NetMessenger::split_channels")?;
+        let peer = self
+            .recv::<ChannelHandshake>()
+            .await
+            .stack_err_locationless(|| {
+                "NetMessenger::split_channels -> did not receive the handshake, the other side may \
+                 not have called `split_channels`"
+            })?;
+        if peer.version != CHANNEL_PROTOCOL_VERSION {
+            return Err(Error::from_kind_locationless(format!(
+                "NetMessenger::split_channels -> the other side uses channel protocol version {}, \
+                 but this side uses version {CHANNEL_PROTOCOL_VERSION}",
+                peer.version
+            )))
+        }
+        let (read_half, write_half) = self.stream.into_split();
+        let writer = Arc::new(Mutex::new(write_half));
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..num_channels).map(|_| mpsc::unbounded_channel()).unzip();
+        // the task is aborted once all of the handles are dropped
+        let reader = Arc::new(ChannelReader(tokio::task::spawn(demultiplex(
+            read_half, senders,
+        ))));
+        Ok(receivers
+            .into_iter()
+            .zip(0..)
+            .map(|(receiver, id)| ChannelHandle {
+                id,
+                writer: Arc::clone(&writer),
+                receiver,
+                buf: vec![],
+                capture: self.capture.clone(),
+                _reader: Arc::clone(&reader),
+            })
+            .collect())
+    }
+}
+
+/// The capture file shared by a `NetMessenger` and its `ChannelHandle`s
+type SharedCapture = Arc<std::sync::Mutex<std::fs::File>>;
+
+/// Appends `frame` as a JSON line to the capture file
+fn capture_frame(capture: &SharedCapture, frame: CapturedFrame) {
+    let res: Result<()> = (|| {
+        let mut line = serde_json::to_vec(&frame).stack()?;
+        line.push(b'\n');
+        // a poisoned lock only means another write panicked, the file is still usable
+        let mut file = capture.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line).stack()
+    })();
+    if let Err(e) = res {
+        warn!("could not write to the `NetMessenger` capture file: {e:?}");
+        emit_crate_event(|| CrateEvent::CaptureWriteFailed {
+            error: format!("{e:?}"),
+        });
+    }
+}
+
+/// Microseconds since the Unix epoch, for the capture timestamps
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_micros()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
+/// Serializes `msg` into `buf`, growing it as needed
+fn serialize_to_buf<T: ?Sized + Serialize>(msg: &T, buf: &mut Vec<u8>) -> Result<()> {
+    loop {
+        buf.clear();
+        buf.resize(buf.capacity(), 0);
+        match postcard::to_slice(msg, buf) {
+            Ok(serialized) => {
+                let len = serialized.len();
+                buf.truncate(len);
+                return Ok(())
+            }
+            Err(postcard::Error::SerializeBufferFull) => {
+                // double the capacity
+                // TODO we need to add limits, maybe a settable option on the `NetMessage`
+                // struct
+                let current_cap = max(buf.capacity(), 1);
+                // reserve is based on `self.len() + additional` instead of
+                // `self.capacity() + additional`
+                let double = current_cap.wrapping_shl(1);
+                buf.reserve(double);
+            }
+            Err(e) => {
+                return Err(Error::box_from(e))
+                    .stack_err_locationless(|| "failed to serialize message")
+            }
+        }
+    }
+}
+
+/// Incremented whenever the multiplexed framing changes
+const CHANNEL_PROTOCOL_VERSION: u8 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChannelHandshake {
+    version: u8,
+    num_channels: u16,
+}
+
+/// A message received by the demultiplexer, the type hash and the serialized
+/// message
+type Frame = (Vec<u8>, [u8; 16]);
+
+/// Aborts the demultiplexing task when dropped
+#[derive(Debug)]
+struct ChannelReader(JoinHandle<()>);
+
+impl Drop for ChannelReader {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Reads multiplexed frames of `[channel: u16][type hash: 16 bytes][len:
+/// u64][message]` and sends them to the channels. Errors are sent to every
+/// channel.
+async fn demultiplex(
+    mut read_half: OwnedReadHalf,
+    senders: Vec<mpsc::UnboundedSender<std::result::Result<Frame, String>>>,
+) {
+    let res: Result<()> = async {
+        loop {
+            let channel = match read_half.read_u16_le().await {
+                Ok(channel) => channel,
+                Err(e) => {
+                    return Err(Error::probably_not_root_cause()
+                        .add_kind_locationless(
+                            "could not read, this may be because the other side was abruptly \
+                             terminated",
+                        )
+                        .add_kind_locationless(e))
+                }
+            };
+            let mut id = [0u8; 16];
+            read_half.read_exact(&mut id).await.stack()?;
+            let len = usize::try_from(read_half.read_u64_le().await.stack()?)?;
+            let mut msg = vec![0u8; len];
+            read_half.read_exact(&mut msg).await.stack()?;
+            let sender = senders
+                .get(usize::from(channel))
+                .stack_err_locationless(|| {
+                    format!(
+                        "the other side sent a message on channel {channel}, but this side only \
+                         has {} channels",
+                        senders.len()
+                    )
+                })?;
+            // the handle may have been dropped, which is fine
+            let _ = sender.send(Ok((msg, id)));
+        }
+    }
+    .await;
+    if let Err(e) = res {
+        let msg = format!("{e:?}");
+        for sender in &senders {
+            let _ = sender.send(Err(msg.clone()));
+        }
+    }
+}
+
+/// One of the channels returned by [NetMessenger::split_channels], with the
+/// same `send` and `recv` API as `NetMessenger`. Dropping all of the handles
+/// of a connection closes it.
+#[derive(Debug)]
+pub struct ChannelHandle {
+    id: u16,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    receiver: mpsc::UnboundedReceiver<std::result::Result<Frame, String>>,
+    buf: Vec<u8>,
+    capture: Option<SharedCapture>,
+    _reader: Arc<ChannelReader>,
+}
+
+impl ChannelHandle {
+    /// Returns the channel ID, which is the index of the handle in the output
+    /// of [NetMessenger::split_channels]
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// The same as [NetMessenger::send], except that this does not wait for
+    /// messages on other channels
+    pub async fn send<T: ?Sized + Serialize>(&mut self, msg: &T) -> Result<()> {
+        serialize_to_buf(msg, &mut self.buf)?;
+        let channel = self.id;
+        let id = type_hash::<T>();
+        // the whole frame is written while holding the lock so that frames from
+        // different channels are not interleaved
+        let mut writer = self.writer.lock().await;
+        if let Err(e) = writer.write_u16_le(channel).await {
+            return Err(Error::probably_not_root_cause()
+                .add_kind_locationless(format!(
+                    "ChannelHandle::send::<{}>::() on channel {channel} could not write, this may \
+                     be because the other side was abruptly terminated",
+                    type_name::<T>()
+                ))
+                .add_kind_locationless(e))
+        }
+        writer.write_all(&id).await.stack()?;
+        writer
+            .write_u64_le(u64::try_from(self.buf.len())?)
+            .await
+            .stack()?;
+        writer.write_all(&self.buf).await.stack()?;
+        writer.flush().await.stack()?;
+        drop(writer);
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(
+                    Direction::Sent,
+                    Some(channel),
+                    type_name::<T>(),
+                    &id,
+                    &self.buf,
+                )
+                .json(msg),
+            );
+        }
+        Ok(())
+    }
+
+    /// The same as [NetMessenger::recv], except that this only receives
+    /// messages sent on the same channel
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let channel = self.id;
+        let (msg, actual_id) = match self.receiver.recv().await {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => {
+                return Err(Error::from_kind_locationless(format!(
+                    "ChannelHandle::recv::<{}>::() on channel {channel} -> the connection failed: \
+                     {e}",
+                    type_name::<T>()
+                )))
+            }
+            None => {
+                return Err(Error::from_kind_locationless(format!(
+                    "ChannelHandle::recv::<{}>::() on channel {channel} -> the connection was \
+                     closed",
+                    type_name::<T>()
+                )))
+            }
+        };
+        if type_hash::<T>() != actual_id {
+            return Err(Error::from(format!(
+                "ChannelHandle::recv() on channel {channel} -> incoming type did not match \
+                 expected type ({})",
+                type_name::<T>()
+            )))
+        }
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(
+                    Direction::Received,
+                    Some(channel),
+                    type_name::<T>(),
+                    &actual_id,
+                    &msg,
+                ),
+            );
+        }
+        postcard::from_bytes(&msg).stack_err(|| {
+            format!("ChannelHandle::recv() on channel {channel} -> failed to deserialize message")
+        })
+    }
 }
@@ -0,0 +1,289 @@
+//! Reading the captures written by [NetMessenger::with_capture], and replaying
+//! them with a [MockMessenger]
+
+use std::{any::type_name, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use stacked_errors::{Error, Result, StackableErr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{now_micros, NetMessenger};
+use crate::type_hash;
+
+/// The direction of a [CapturedFrame] from the perspective of the side that
+/// captured it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// One frame of a capture, which is one JSON line of the capture file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedFrame {
+    pub direction: Direction,
+    /// Microseconds since the Unix epoch when the frame was completely sent or
+    /// received
+    pub time_micros: u64,
+    /// The channel if the frame was on a [ChannelHandle](super::ChannelHandle)
+    pub channel: Option<u16>,
+    /// The `std::any::type_name` of the message
+    pub type_name: String,
+    /// The hex encoded type hash that is sent along with the message
+    pub type_hash: String,
+    /// The length of the serialized message
+    pub len: u64,
+    /// The `serde_json` rendering of the message, only for sent frames of
+    /// types that can be rendered
+    pub json: Option<serde_json::Value>,
+    /// The hex encoded serialized message
+    pub hex: String,
+}
+
+impl CapturedFrame {
+    pub(super) fn new(
+        direction: Direction,
+        channel: Option<u16>,
+        type_name: &str,
+        type_hash: &[u8; 16],
+        bytes: &[u8],
+    ) -> Self {
+        Self {
+            direction,
+            time_micros: now_micros(),
+            channel,
+            type_name: type_name.to_owned(),
+            type_hash: hex_encode(type_hash),
+            len: bytes.len() as u64,
+            json: None,
+            hex: hex_encode(bytes),
+        }
+    }
+
+    /// Sets `json` if `msg` can be rendered
+    pub(super) fn json<T: ?Sized + Serialize>(mut self, msg: &T) -> Self {
+        self.json = serde_json::to_value(msg).ok();
+        self
+    }
+
+    /// Returns the serialized message
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        hex_decode(&self.hex).stack_err_locationless(|| "CapturedFrame::bytes")
+    }
+
+    /// If the frame has the type hash of `T`
+    pub fn is_type<T: ?Sized>(&self) -> bool {
+        self.type_hash == hex_encode(&type_hash::<T>())
+    }
+
+    /// Deserializes the message, the type is checked the same way as
+    /// [NetMessenger::recv]
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T> {
+        if !self.is_type::<T>() {
+            return Err(Error::from_kind_locationless(format!(
+                "CapturedFrame::decode::<{}>() -> the frame is of type {}",
+                type_name::<T>(),
+                self.type_name
+            )))
+        }
+        postcard::from_bytes(&self.bytes()?)
+            .stack_err_locationless(|| "CapturedFrame::decode() -> failed to deserialize message")
+    }
+
+    fn type_hash_bytes(&self) -> Result<[u8; 16]> {
+        let bytes = hex_decode(&self.type_hash)?;
+        <[u8; 16]>::try_from(bytes.as_slice())
+            .map_err(|_| Error::from_kind_locationless("type hash is not 16 bytes"))
+    }
+}
+
+/// Parses a capture file written by [NetMessenger::with_capture]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reader {
+    frames: Vec<CapturedFrame>,
+}
+
+impl Reader {
+    /// Reads and parses the capture file at `path`
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = tokio::fs::read_to_string(path)
+            .await
+            .stack_err_locationless(|| format!("Reader::from_file -> could not read {path:?}"))?;
+        Self::parse(&s).stack_err_locationless(|| format!("Reader::from_file({path:?})"))
+    }
+
+    /// Parses the contents of a capture file. A trailing partial line (from a
+    /// process that was killed while writing) is ignored.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut frames = vec![];
+        let mut lines = s.split('\n').enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            if line.trim().is_empty() {
+                continue
+            }
+            match serde_json::from_str(line) {
+                Ok(frame) => frames.push(frame),
+                Err(_) if lines.peek().is_none() => (),
+                Err(e) => {
+                    return Err(Error::from_kind_locationless(format!(
+                        "Reader::parse -> line {} is not a captured frame: {e}",
+                        i + 1
+                    )))
+                }
+            }
+        }
+        Ok(Self { frames })
+    }
+
+    /// All of the frames in the order they were captured
+    pub fn frames(&self) -> &[CapturedFrame] {
+        &self.frames
+    }
+
+    /// The frames on `channel`, where `None` is for frames that were not on a
+    /// `ChannelHandle`
+    pub fn channel(&self, channel: Option<u16>) -> impl Iterator<Item = &CapturedFrame> {
+        self.frames
+            .iter()
+            .filter(move |frame| frame.channel == channel)
+    }
+
+    /// Returns all of the frames
+    pub fn into_frames(self) -> Vec<CapturedFrame> {
+        self.frames
+    }
+}
+
+/// Stands in for the peer of the side that captured the frames, so that e.x. a
+/// host-side handler can be rerun against the recorded traffic of an
+/// entrypoint without any containers. The handler uses an ordinary
+/// `NetMessenger` connected to the `NetMessenger` passed to
+/// [MockMessenger::replay].
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use stacked_errors::{Result, StackableErr};
+/// use super_orchestrator::net_message::{replay::MockMessenger, NetMessenger};
+///
+/// async fn handler(nm: &mut NetMessenger) -> Result<()> {
+///     let n = nm.recv::<u64>().await.stack()?;
+///     nm.send::<u64>(&(n * 2)).await.stack()
+/// }
+///
+/// async fn rerun() -> Result<()> {
+///     let mock = MockMessenger::from_capture("./logs/host.capture")
+///         .await
+///         .stack()?;
+///     let peer = tokio::spawn(async move {
+///         let nm = NetMessenger::listen("127.0.0.1:26000", Duration::from_secs(10)).await?;
+///         mock.replay(nm).await
+///     });
+///     let mut nm = NetMessenger::connect(10, Duration::from_millis(100), "127.0.0.1:26000")
+///         .await
+///         .stack()?;
+///     handler(&mut nm).await.stack()?;
+///     peer.await.stack()?.stack()
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockMessenger {
+    frames: Vec<CapturedFrame>,
+}
+
+impl MockMessenger {
+    /// Reads the capture at `path`, see [MockMessenger::from_frames]
+    pub async fn from_capture(path: impl AsRef<Path>) -> Result<Self> {
+        let reader = Reader::from_file(path)
+            .await
+            .stack_err_locationless(|| "MockMessenger::from_capture")?;
+        Self::from_frames(reader.into_frames())
+    }
+
+    /// Uses `frames` in order. Only frames that were not on a `ChannelHandle`
+    /// can be replayed, filter with [Reader::channel] if needed.
+    pub fn from_frames(frames: Vec<CapturedFrame>) -> Result<Self> {
+        if let Some(frame) = frames.iter().find(|frame| frame.channel.is_some()) {
+            return Err(Error::from_kind_locationless(format!(
+                "MockMessenger::from_frames -> there is a frame on channel {}, frames on channels \
+                 cannot be replayed",
+                frame.channel.unwrap()
+            )))
+        }
+        Ok(Self { frames })
+    }
+
+    /// The frames that will be replayed
+    pub fn frames(&self) -> &[CapturedFrame] {
+        &self.frames
+    }
+
+    /// Replays the frames over `nm` in order. Frames that the capturing side
+    /// received are sent as they were recorded, and for frames that it sent a
+    /// message is received and must have the same type (the contents are not
+    /// compared, since they may contain e.x. timestamps). Returns when all the
+    /// frames have been replayed.
+    pub async fn replay(self, mut nm: NetMessenger) -> Result<()> {
+        for (i, frame) in self.frames.iter().enumerate() {
+            let expected_id = frame
+                .type_hash_bytes()
+                .stack_err_locationless(|| format!("MockMessenger::replay -> frame {i}"))?;
+            match frame.direction {
+                Direction::Received => {
+                    let bytes = frame.bytes()?;
+                    nm.stream.write_all(&expected_id).await.stack()?;
+                    nm.stream
+                        .write_u64_le(u64::try_from(bytes.len())?)
+                        .await
+                        .stack()?;
+                    nm.stream.write_all(&bytes).await.stack()?;
+                    nm.stream.flush().await.stack()?;
+                }
+                Direction::Sent => {
+                    let mut actual_id = [0u8; 16];
+                    nm.stream
+                        .read_exact(&mut actual_id)
+                        .await
+                        .stack_err_locationless(|| {
+                            format!(
+                                "MockMessenger::replay -> could not receive frame {i} of type {}",
+                                frame.type_name
+                            )
+                        })?;
+                    if actual_id != expected_id {
+                        return Err(Error::from_kind_locationless(format!(
+                            "MockMessenger::replay -> frame {i} was captured with type {}, but a \
+                             message of a different type was sent",
+                            frame.type_name
+                        )))
+                    }
+                    let len = usize::try_from(nm.stream.read_u64_le().await.stack()?)?;
+                    let mut msg = vec![0u8; len];
+                    nm.stream.read_exact(&mut msg).await.stack()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// `usize::is_multiple_of` needs Rust 1.87, which is above the MSRV
+#[allow(clippy::manual_is_multiple_of)]
+fn hex_decode(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return Err(Error::from_kind_locationless("odd number of hex digits"))
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..(i + 2))
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .stack_err_locationless(|| format!("invalid hex digits at {i}"))
+        })
+        .collect()
+}
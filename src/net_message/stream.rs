@@ -0,0 +1,629 @@
+use std::any::type_name;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use stacked_errors::{Error, Result, StackableErr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{
+    capture_frame,
+    replay::{CapturedFrame, Direction},
+    ChannelHandle, NetMessenger,
+};
+use crate::type_hash;
+
+/// Incremented whenever the stream protocol changes
+const STREAM_PROTOCOL_VERSION: u8 = 1;
+
+/// The largest `chunk_size` that a receiving side accepts
+pub const MAX_STREAM_CHUNK_SIZE: usize = 64 << 20;
+
+/// Options for [NetMessenger::send_stream_with_options] and
+/// [ChannelHandle::send_stream_with_options]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOptions {
+    /// The maximum number of payload bytes per frame, 1 MiB by default. The
+    /// receiving side rejects sizes over [MAX_STREAM_CHUNK_SIZE].
+    pub chunk_size: usize,
+    /// If a SHA-256 checksum of the payload is verified at the end, `true` by
+    /// default
+    pub checksum: bool,
+    /// If the receiving side may ask to resume from the offset in its
+    /// [StreamProgress], `false` by default. When resuming, the reader must
+    /// start from the beginning of the same payload again, the bytes that the
+    /// receiver already has are read and checksummed but not sent.
+    pub resumable: bool,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1 << 20,
+            checksum: true,
+            resumable: false,
+        }
+    }
+}
+
+impl StreamOptions {
+    /// The default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `chunk_size`
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets `checksum`
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets `resumable`
+    pub fn resumable(mut self, resumable: bool) -> Self {
+        self.resumable = resumable;
+        self
+    }
+}
+
+/// The progress of the receiving side of a stream, which is kept when the
+/// transfer is interrupted so that it can be continued with
+/// [NetMessenger::recv_stream_resume] over a new connection
+#[derive(Debug, Clone, Default)]
+pub struct StreamProgress {
+    offset: u64,
+    len_hint: Option<u64>,
+    hasher: Sha256,
+}
+
+impl StreamProgress {
+    /// Progress from the start of a payload
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of bytes that have been written to the writer
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The `len_hint` given to the sending side, if any
+    pub fn len_hint(&self) -> Option<u64> {
+        self.len_hint
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StreamBegin {
+    version: u8,
+    len_hint: Option<u64>,
+    chunk_size: u64,
+    checksum: bool,
+    resumable: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StreamAccept {
+    offset: u64,
+}
+
+/// Only its type hash is used, to mark the raw chunk frames
+struct StreamChunk;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StreamEnd {
+    len: u64,
+    sha256: Option<[u8; 32]>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StreamDone {
+    len: u64,
+    error: Option<String>,
+}
+
+/// What streams need from the two kinds of connections
+trait StreamTransport {
+    async fn send_typed<T: Serialize>(&mut self, msg: &T) -> Result<()>;
+
+    async fn recv_typed<T: DeserializeOwned>(&mut self) -> Result<T>;
+
+    /// Sends a frame with the `StreamChunk` type hash and `chunk` as the
+    /// message
+    async fn send_chunk(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// Receives the next frame, which is a chunk written to `buf` (returning
+    /// `None`) or a `StreamEnd`
+    async fn recv_chunk(&mut self, max_len: usize, buf: &mut Vec<u8>) -> Result<Option<StreamEnd>>;
+}
+
+impl StreamTransport for NetMessenger {
+    async fn send_typed<T: Serialize>(&mut self, msg: &T) -> Result<()> {
+        self.send::<T>(msg).await
+    }
+
+    async fn recv_typed<T: DeserializeOwned>(&mut self) -> Result<T> {
+        self.recv::<T>().await
+    }
+
+    async fn send_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        let id = type_hash::<StreamChunk>();
+        self.stream.write_all(&id).await.stack()?;
+        self.stream
+            .write_u64_le(u64::try_from(chunk.len())?)
+            .await
+            .stack()?;
+        self.stream.write_all(chunk).await.stack()?;
+        self.stream.flush().await.stack()?;
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(
+                    Direction::Sent,
+                    None,
+                    type_name::<StreamChunk>(),
+                    &id,
+                    chunk,
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    async fn recv_chunk(&mut self, max_len: usize, buf: &mut Vec<u8>) -> Result<Option<StreamEnd>> {
+        let mut id = [0u8; 16];
+        self.stream.read_exact(&mut id).await.stack()?;
+        let len = usize::try_from(self.stream.read_u64_le().await.stack()?)?;
+        if id == type_hash::<StreamEnd>() {
+            buf.resize(len, 0);
+            self.stream.read_exact(buf).await.stack()?;
+            let end = postcard::from_bytes(buf).stack()?;
+            if let Some(ref capture) = self.capture {
+                capture_frame(
+                    capture,
+                    CapturedFrame::new(
+                        Direction::Received,
+                        None,
+                        type_name::<StreamEnd>(),
+                        &id,
+                        buf,
+                    ),
+                );
+            }
+            return Ok(Some(end))
+        }
+        if id != type_hash::<StreamChunk>() {
+            return Err(Error::from_kind_locationless(
+                "received a message that is not part of the stream",
+            ))
+        }
+        if len > max_len {
+            return Err(Error::from_kind_locationless(format!(
+                "received a chunk of {len} bytes, which is over the chunk size of {max_len}"
+            )))
+        }
+        buf.resize(len, 0);
+        self.stream.read_exact(buf).await.stack()?;
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(
+                    Direction::Received,
+                    None,
+                    type_name::<StreamChunk>(),
+                    &id,
+                    buf,
+                ),
+            );
+        }
+        Ok(None)
+    }
+}
+
+impl StreamTransport for ChannelHandle {
+    async fn send_typed<T: Serialize>(&mut self, msg: &T) -> Result<()> {
+        self.send::<T>(msg).await
+    }
+
+    async fn recv_typed<T: DeserializeOwned>(&mut self) -> Result<T> {
+        self.recv::<T>().await
+    }
+
+    async fn send_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        let id = type_hash::<StreamChunk>();
+        // the whole frame is written while holding the lock, the same as `send`
+        let mut writer = self.writer.lock().await;
+        writer.write_u16_le(self.id).await.stack()?;
+        writer.write_all(&id).await.stack()?;
+        writer
+            .write_u64_le(u64::try_from(chunk.len())?)
+            .await
+            .stack()?;
+        writer.write_all(chunk).await.stack()?;
+        writer.flush().await.stack()?;
+        drop(writer);
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(
+                    Direction::Sent,
+                    Some(self.id),
+                    type_name::<StreamChunk>(),
+                    &id,
+                    chunk,
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    async fn recv_chunk(&mut self, max_len: usize, buf: &mut Vec<u8>) -> Result<Option<StreamEnd>> {
+        let (msg, id) = match self.receiver.recv().await {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => {
+                return Err(Error::from_kind_locationless(format!(
+                    "the connection failed: {e}"
+                )))
+            }
+            None => return Err(Error::from_kind_locationless("the connection was closed")),
+        };
+        if id == type_hash::<StreamEnd>() {
+            let end = postcard::from_bytes(&msg).stack()?;
+            if let Some(ref capture) = self.capture {
+                capture_frame(
+                    capture,
+                    CapturedFrame::new(
+                        Direction::Received,
+                        Some(self.id),
+                        type_name::<StreamEnd>(),
+                        &id,
+                        &msg,
+                    ),
+                );
+            }
+            return Ok(Some(end))
+        }
+        if id != type_hash::<StreamChunk>() {
+            return Err(Error::from_kind_locationless(
+                "received a message that is not part of the stream",
+            ))
+        }
+        // the demultiplexer has already allocated the frame, but the bound is still
+        // part of the protocol
+        if msg.len() > max_len {
+            return Err(Error::from_kind_locationless(format!(
+                "received a chunk of {} bytes, which is over the chunk size of {max_len}",
+                msg.len()
+            )))
+        }
+        if let Some(ref capture) = self.capture {
+            capture_frame(
+                capture,
+                CapturedFrame::new(
+                    Direction::Received,
+                    Some(self.id),
+                    type_name::<StreamChunk>(),
+                    &id,
+                    &msg,
+                ),
+            );
+        }
+        *buf = msg;
+        Ok(None)
+    }
+}
+
+/// Reads from `reader` until `buf` is full or the reader ends, returning the
+/// number of bytes read
+async fn fill<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await.stack()?;
+        if n == 0 {
+            break
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+async fn send_stream<M: StreamTransport, R: AsyncRead + Unpin>(
+    m: &mut M,
+    mut reader: R,
+    len_hint: Option<u64>,
+    options: &StreamOptions,
+) -> Result<u64> {
+    if (options.chunk_size == 0) || (options.chunk_size > MAX_STREAM_CHUNK_SIZE) {
+        return Err(Error::from_kind_locationless(format!(
+            "the chunk size {} is not in 1..={MAX_STREAM_CHUNK_SIZE}",
+            options.chunk_size
+        )))
+    }
+    m.send_typed(&StreamBegin {
+        version: STREAM_PROTOCOL_VERSION,
+        len_hint,
+        chunk_size: options.chunk_size as u64,
+        checksum: options.checksum,
+        resumable: options.resumable,
+    })
+    .await
+    .stack_err_locationless(|| "when sending the beginning of the stream")?;
+    let accept: StreamAccept = m.recv_typed().await.stack_err_locationless(|| {
+        "when receiving the response to the beginning of the stream, the other side may not have \
+         called `recv_stream`"
+    })?;
+    if (accept.offset != 0) && (!options.resumable) {
+        return Err(Error::from_kind_locationless(format!(
+            "the other side asked to resume from offset {}, but `StreamOptions::resumable` is not \
+             set",
+            accept.offset
+        )))
+    }
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; options.chunk_size];
+    // the bytes that the other side already has are only checksummed
+    let mut total = 0u64;
+    while total < accept.offset {
+        let remaining = usize::try_from(accept.offset - total)
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        let n = fill(&mut reader, &mut buf[..remaining])
+            .await
+            .stack_err_locationless(|| "when reading up to the resume offset")?;
+        if n == 0 {
+            return Err(Error::from_kind_locationless(format!(
+                "the reader ended at {total} bytes, before the resume offset {} requested by the \
+                 other side",
+                accept.offset
+            )))
+        }
+        if options.checksum {
+            hasher.update(&buf[..n]);
+        }
+        total += n as u64;
+    }
+    loop {
+        let n = fill(&mut reader, &mut buf)
+            .await
+            .stack_err_locationless(|| format!("when reading at offset {total}"))?;
+        if n == 0 {
+            break
+        }
+        if options.checksum {
+            hasher.update(&buf[..n]);
+        }
+        m.send_chunk(&buf[..n])
+            .await
+            .stack_err_locationless(|| format!("when sending the chunk at offset {total}"))?;
+        total += n as u64;
+    }
+    let sha256 = options.checksum.then(|| hasher.finalize().into());
+    m.send_typed(&StreamEnd { len: total, sha256 })
+        .await
+        .stack_err_locationless(|| "when sending the end of the stream")?;
+    let done: StreamDone = m
+        .recv_typed()
+        .await
+        .stack_err_locationless(|| "when receiving the confirmation of the stream")?;
+    if let Some(error) = done.error {
+        return Err(Error::from_kind_locationless(format!(
+            "the other side failed to verify the stream: {error}"
+        )))
+    }
+    if done.len != total {
+        return Err(Error::from_kind_locationless(format!(
+            "sent {total} bytes, but the other side received {}",
+            done.len
+        )))
+    }
+    Ok(total)
+}
+
+async fn recv_stream<M: StreamTransport, W: AsyncWrite + Unpin>(
+    m: &mut M,
+    mut writer: W,
+    progress: &mut StreamProgress,
+) -> Result<u64> {
+    let begin: StreamBegin = m.recv_typed().await.stack_err_locationless(|| {
+        "when receiving the beginning of the stream, the other side may not have called \
+         `send_stream`"
+    })?;
+    if begin.version != STREAM_PROTOCOL_VERSION {
+        return Err(Error::from_kind_locationless(format!(
+            "the other side uses stream protocol version {}, but this side uses version \
+             {STREAM_PROTOCOL_VERSION}",
+            begin.version
+        )))
+    }
+    // the response is always sent so that the other side gets a clear error
+    m.send_typed(&StreamAccept {
+        offset: progress.offset,
+    })
+    .await
+    .stack_err_locationless(|| "when responding to the beginning of the stream")?;
+    if (progress.offset != 0) && (!begin.resumable) {
+        return Err(Error::from_kind_locationless(format!(
+            "this side has a `StreamProgress` at offset {}, but the other side did not set \
+             `StreamOptions::resumable`",
+            progress.offset
+        )))
+    }
+    let max_len = usize::try_from(begin.chunk_size)
+        .unwrap_or(usize::MAX)
+        .min(MAX_STREAM_CHUNK_SIZE);
+    if begin.chunk_size > max_len as u64 {
+        return Err(Error::from_kind_locationless(format!(
+            "the other side uses a chunk size of {}, which is over {MAX_STREAM_CHUNK_SIZE}",
+            begin.chunk_size
+        )))
+    }
+    progress.len_hint = begin.len_hint;
+    let mut buf = vec![];
+    let end = loop {
+        let offset = progress.offset;
+        if let Some(end) = m
+            .recv_chunk(max_len, &mut buf)
+            .await
+            .stack_err_locationless(|| format!("when receiving the chunk at offset {offset}"))?
+        {
+            break end
+        }
+        writer
+            .write_all(&buf)
+            .await
+            .stack_err_locationless(|| format!("when writing the chunk at offset {offset}"))?;
+        if begin.checksum {
+            progress.hasher.update(&buf);
+        }
+        progress.offset += buf.len() as u64;
+    };
+    writer
+        .flush()
+        .await
+        .stack_err_locationless(|| "when flushing the writer")?;
+    let mut error = None;
+    if end.len != progress.offset {
+        error = Some(format!(
+            "the other side sent {} bytes, but this side received {}",
+            end.len, progress.offset
+        ));
+    } else if let Some(expected) = end.sha256 {
+        let actual: [u8; 32] = progress.hasher.clone().finalize().into();
+        if actual != expected {
+            error = Some("the SHA-256 checksum of the received bytes does not match".to_owned());
+        }
+    }
+    m.send_typed(&StreamDone {
+        len: progress.offset,
+        error: error.clone(),
+    })
+    .await
+    .stack_err_locationless(|| "when confirming the stream")?;
+    if let Some(error) = error {
+        return Err(Error::from_kind_locationless(error))
+    }
+    Ok(progress.offset)
+}
+
+impl NetMessenger {
+    /// Sends everything from `reader` to a [NetMessenger::recv_stream] call
+    /// on the other side with the default [StreamOptions], see
+    /// [NetMessenger::send_stream_with_options]
+    pub async fn send_stream<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: R,
+        len_hint: Option<u64>,
+    ) -> Result<u64> {
+        self.send_stream_with_options(reader, len_hint, &StreamOptions::default())
+            .await
+    }
+
+    /// Sends everything from `reader` in frames of at most
+    /// `options.chunk_size` bytes, so that neither side needs to hold the whole
+    /// payload in memory. The connection is used exclusively until the stream
+    /// ends, use [NetMessenger::split_channels] and
+    /// [ChannelHandle::send_stream_with_options] to interleave other messages.
+    /// `len_hint` is only passed along to the [StreamProgress] of the other
+    /// side. Returns the total length after the other side has verified it
+    /// (and the checksum if `options.checksum`).
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// use stacked_errors::{Result, StackableErr};
+    /// use super_orchestrator::net_message::NetMessenger;
+    ///
+    /// async fn entrypoint() -> Result<()> {
+    ///     let mut nm = NetMessenger::connect(10, Duration::from_millis(300), "host:26000")
+    ///         .await
+    ///         .stack()?;
+    ///     let file = tokio::fs::File::open("./artifact.tar").await.stack()?;
+    ///     let len = file.metadata().await.stack()?.len();
+    ///     nm.send_stream(file, Some(len)).await.stack()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_stream_with_options<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: R,
+        len_hint: Option<u64>,
+        options: &StreamOptions,
+    ) -> Result<u64> {
+        send_stream(self, reader, len_hint, options)
+            .await
+            .stack_err_locationless(|| "NetMessenger::send_stream")
+    }
+
+    /// Receives a stream from [NetMessenger::send_stream] into `writer`,
+    /// returning the total length
+    pub async fn recv_stream<W: AsyncWrite + Unpin>(&mut self, writer: W) -> Result<u64> {
+        recv_stream(self, writer, &mut StreamProgress::new())
+            .await
+            .stack_err_locationless(|| "NetMessenger::recv_stream")
+    }
+
+    /// The same as [NetMessenger::recv_stream], except that `progress` is
+    /// updated as chunks are written. If the transfer is interrupted, calling
+    /// this again over a new connection with the same `progress` (and a
+    /// writer that continues where the last one stopped) resumes from
+    /// `progress.offset()` if the sending side set
+    /// [StreamOptions::resumable].
+    pub async fn recv_stream_resume<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: W,
+        progress: &mut StreamProgress,
+    ) -> Result<u64> {
+        recv_stream(self, writer, progress)
+            .await
+            .stack_err_locationless(|| "NetMessenger::recv_stream_resume")
+    }
+}
+
+impl ChannelHandle {
+    /// The same as [NetMessenger::send_stream], except that messages on other
+    /// channels can be interleaved with the stream
+    pub async fn send_stream<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: R,
+        len_hint: Option<u64>,
+    ) -> Result<u64> {
+        self.send_stream_with_options(reader, len_hint, &StreamOptions::default())
+            .await
+    }
+
+    /// The same as [NetMessenger::send_stream_with_options], except that
+    /// messages on other channels can be interleaved with the stream
+    pub async fn send_stream_with_options<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: R,
+        len_hint: Option<u64>,
+        options: &StreamOptions,
+    ) -> Result<u64> {
+        let channel = self.id;
+        send_stream(self, reader, len_hint, options)
+            .await
+            .stack_err_locationless(|| format!("ChannelHandle::send_stream on channel {channel}"))
+    }
+
+    /// The same as [NetMessenger::recv_stream]
+    pub async fn recv_stream<W: AsyncWrite + Unpin>(&mut self, writer: W) -> Result<u64> {
+        self.recv_stream_resume(writer, &mut StreamProgress::new())
+            .await
+    }
+
+    /// The same as [NetMessenger::recv_stream_resume]
+    pub async fn recv_stream_resume<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: W,
+        progress: &mut StreamProgress,
+    ) -> Result<u64> {
+        let channel = self.id;
+        recv_stream(self, writer, progress)
+            .await
+            .stack_err_locationless(|| format!("ChannelHandle::recv_stream on channel {channel}"))
+    }
+}
@@ -1,5 +1,10 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
 use stacked_errors::{Result, StackableErr};
 
+use crate::parse_size;
+
 /// First, this splits by `separate`, trims outer whitespace, sees if `key` is
 /// prefixed, if so it also strips `inter_key_val` and returns the stripped and
 /// trimmed value.
@@ -43,6 +48,410 @@ pub fn get_separated_val(
     value.stack_err_locationless(|| format!("get_separated_val() -> key \"{key}\" not found"))
 }
 
+// finds the last occurance of any of the `markers` in `s` followed (after
+// optional whitespace, ':', or '=') by an integer, and returns that integer
+fn parse_int_after_markers(s: &str, markers: &[&str]) -> Option<i32> {
+    let lowercase = s.to_lowercase();
+    let mut res = None;
+    let mut res_inx = 0;
+    for marker in markers {
+        for (inx, _) in lowercase.match_indices(marker) {
+            let rest = lowercase[(inx + marker.len())..]
+                .trim_start_matches(|c: char| c.is_whitespace() || (c == ':') || (c == '='));
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if let Ok(x) = rest[..end].parse::<i32>() {
+                if res.is_none() || (inx >= res_inx) {
+                    res = Some(x);
+                    res_inx = inx;
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Searches `stderr` for common patterns that embed an exit code (e.x.
+/// "container exited with non-zero status 137" or "exit code: 1") and returns
+/// the last one found.
+///
+/// ```
+/// use super_orchestrator::try_parse_exit_code_from_stderr;
+///
+/// assert_eq!(
+///     try_parse_exit_code_from_stderr("container exited with non-zero status 137"),
+///     Some(137)
+/// );
+/// assert_eq!(
+///     try_parse_exit_code_from_stderr("Error: process failed, exit code: 2\n"),
+///     Some(2)
+/// );
+/// assert_eq!(
+///     try_parse_exit_code_from_stderr("exited with code 1"),
+///     Some(1)
+/// );
+/// assert_eq!(try_parse_exit_code_from_stderr("no codes here"), None);
+/// ```
+pub fn try_parse_exit_code_from_stderr(stderr: &str) -> Option<i32> {
+    parse_int_after_markers(stderr, &[
+        "non-zero status",
+        "exit status",
+        "exit code",
+        "exited with code",
+        "exitcode",
+    ])
+}
+
+/// Returns if `stderr` has messages indicating that a process was killed
+/// because of running out of memory.
+///
+/// ```
+/// use super_orchestrator::is_oom_kill;
+///
+/// assert!(is_oom_kill("\"OOMKilled\": true"));
+/// assert!(is_oom_kill("fatal: Out of memory, malloc failed"));
+/// assert!(!is_oom_kill("exit code: 1"));
+/// ```
+pub fn is_oom_kill(stderr: &str) -> bool {
+    let lowercase = stderr.to_lowercase();
+    lowercase.contains("\"oomkilled\": true")
+        || lowercase.contains("oomkilled: true")
+        || lowercase.contains("out of memory")
+        || lowercase.contains("oom-kill")
+        || lowercase.contains("oom killer")
+        || lowercase.contains("memory allocation of")
+}
+
+/// Returns the signal number if `stderr` has a message indicating termination
+/// by a signal, either explicitly (e.x. "killed by signal 9") or through an
+/// embedded exit code greater than 128 (which by convention is 128 plus the
+/// signal number). Only these exact wordings count, since applications often
+/// log about signals they handle. Prefer the exit status of the process if
+/// there is one, see [signal_from_exit_code].
+///
+/// ```
+/// use super_orchestrator::is_signal_exit;
+///
+/// assert_eq!(is_signal_exit("process terminated by signal 15"), Some(15));
+/// assert_eq!(
+///     is_signal_exit("container exited with non-zero status 137"),
+///     Some(9)
+/// );
+/// assert_eq!(is_signal_exit("exit code: 1"), None);
+/// // an application logging about a signal it handled
+/// assert_eq!(is_signal_exit("received signal 15, shutting down"), None);
+/// ```
+pub fn is_signal_exit(stderr: &str) -> Option<i32> {
+    if let Some(signal) =
+        parse_int_after_markers(stderr, &["killed by signal", "terminated by signal"])
+    {
+        return Some(signal)
+    }
+    signal_from_exit_code(try_parse_exit_code_from_stderr(stderr)?)
+}
+
+/// Returns the signal number if the exit `code` is 128 plus a signal number,
+/// which is how docker and shells report a process terminated by a signal
+///
+/// ```
+/// use super_orchestrator::signal_from_exit_code;
+///
+/// assert_eq!(signal_from_exit_code(137), Some(9));
+/// assert_eq!(signal_from_exit_code(143), Some(15));
+/// assert_eq!(signal_from_exit_code(1), None);
+/// assert_eq!(signal_from_exit_code(128), None);
+/// ```
+pub fn signal_from_exit_code(code: i32) -> Option<i32> {
+    (129..=192).contains(&code).then(|| code - 128)
+}
+
+/// Progress of an image build parsed from a line of `docker build` output by
+/// [parse_build_progress]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildProgress {
+    /// The BuildKit vertex number from the `#N` line prefix, or `None` for the
+    /// classic builder
+    pub vertex: Option<u64>,
+    /// The step number from classic `Step x/y` lines or BuildKit `[x/y]` and
+    /// `[stage x/y]` descriptions
+    pub step: Option<u64>,
+    /// The total number of steps, from the same places as `step`
+    pub total: Option<u64>,
+    /// The rest of the line, e.x. "RUN apk add curl", "CACHED", or "DONE 0.1s"
+    pub description: String,
+}
+
+impl BuildProgress {
+    /// If the step was satisfied from the build cache
+    pub fn is_cached(&self) -> bool {
+        self.description == "CACHED" || self.description == "Using cache"
+    }
+
+    /// If the line reports a failure of the build
+    pub fn is_error(&self) -> bool {
+        self.description.starts_with("ERROR")
+            || self.description.contains("returned a non-zero code")
+    }
+}
+
+/// Parses `x/y` from `s`, returning `(x, Some(y))` or `(x, None)` if there is
+/// no "/"
+fn parse_step_total(s: &str) -> Option<(u64, Option<u64>)> {
+    match s.split_once('/') {
+        Some((step, total)) => Some((step.parse().ok()?, Some(total.parse().ok()?))),
+        None => Some((s.parse().ok()?, None)),
+    }
+}
+
+/// Parses a line of `docker build` output from either the classic builder
+/// (e.x. "Step 3/12 : RUN apt-get update") or BuildKit with `--progress=plain`
+/// (e.x. "#5 [2/3] RUN apk add curl" or "#5 CACHED"). Returns `None` for lines
+/// that do not report progress, such as the timestamped output of `RUN`
+/// commands.
+///
+/// ```
+/// use super_orchestrator::{parse_build_progress, BuildProgress};
+///
+/// assert_eq!(
+///     parse_build_progress("Step 3/12 : RUN apt-get update"),
+///     Some(BuildProgress {
+///         vertex: None,
+///         step: Some(3),
+///         total: Some(12),
+///         description: "RUN apt-get update".to_owned(),
+///     })
+/// );
+/// assert_eq!(
+///     parse_build_progress("#7 [builder 2/4] RUN cargo build"),
+///     Some(BuildProgress {
+///         vertex: Some(7),
+///         step: Some(2),
+///         total: Some(4),
+///         description: "RUN cargo build".to_owned(),
+///     })
+/// );
+/// assert!(parse_build_progress("#4 CACHED").unwrap().is_cached());
+/// assert!(
+///     parse_build_progress("#6 ERROR: process \"/bin/sh -c exit 1\" did not complete")
+///         .unwrap()
+///         .is_error()
+/// );
+/// // output of a `RUN` command
+/// assert_eq!(
+///     parse_build_progress("#5 0.321 fetch https://example.com"),
+///     None
+/// );
+/// assert_eq!(parse_build_progress("hello"), None);
+/// ```
+pub fn parse_build_progress(line: &str) -> Option<BuildProgress> {
+    let line = line.trim_end();
+    if let Some(rest) = line.strip_prefix("Step ") {
+        // classic builder
+        let (step, description) = rest.split_once(" : ")?;
+        let (step, total) = parse_step_total(step.trim())?;
+        return Some(BuildProgress {
+            vertex: None,
+            step: Some(step),
+            total,
+            description: description.trim().to_owned(),
+        })
+    }
+    if let Some(rest) = line.strip_prefix(" ---> ") {
+        // the other classic lines are only intermediate container and image IDs
+        return (rest == "Using cache").then(|| BuildProgress {
+            vertex: None,
+            step: None,
+            total: None,
+            description: rest.to_owned(),
+        })
+    }
+    if line.starts_with("The command ") && line.contains("returned a non-zero code") {
+        return Some(BuildProgress {
+            vertex: None,
+            step: None,
+            total: None,
+            description: line.to_owned(),
+        })
+    }
+    if line.starts_with("ERROR: ") {
+        // the BuildKit summary line at the end of a failed build
+        return Some(BuildProgress {
+            vertex: None,
+            step: None,
+            total: None,
+            description: line.to_owned(),
+        })
+    }
+    let (vertex, rest) = line.strip_prefix('#')?.split_once(' ')?;
+    let vertex = vertex.parse::<u64>().ok()?;
+    let rest = rest.trim();
+    let first = rest.split_whitespace().next()?;
+    if first.parse::<f64>().is_ok() {
+        // timestamped output of the step
+        return None
+    }
+    let mut progress = BuildProgress {
+        vertex: Some(vertex),
+        step: None,
+        total: None,
+        description: rest.to_owned(),
+    };
+    if let Some(bracketed) = rest.strip_prefix('[') {
+        if let Some((inner, description)) = bracketed.split_once(']') {
+            // "[2/3]" or "[stage 2/3]", other brackets like "[internal]" are left in the
+            // description
+            let step_total = inner.rsplit(' ').next().unwrap_or(inner);
+            if let Some((step, total @ Some(_))) = parse_step_total(step_total) {
+                progress.step = Some(step);
+                progress.total = total;
+                progress.description = description.trim().to_owned();
+            }
+        }
+    }
+    Some(progress)
+}
+
+/// Build cache statistics of a `docker build`, see [BuildStats::from_lines]
+/// and [ContainerNetwork::build_stats](crate::docker::ContainerNetwork::build_stats)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildStats {
+    /// The number of numbered steps of the build, which is `cached + executed`
+    pub steps: u64,
+    /// The number of steps that were satisfied from the build cache. `FROM`
+    /// steps do not execute anything and are counted as cached.
+    pub cached: u64,
+    /// The number of steps that were executed
+    pub executed: u64,
+    /// The wall clock duration of the whole build, this is zero if the stats
+    /// were only parsed from lines
+    pub duration: Duration,
+    /// The instruction text of the first step that was not cached, e.x. "RUN
+    /// apk add curl"
+    pub first_miss: Option<String>,
+}
+
+impl BuildStats {
+    /// Aggregates the [parse_build_progress] results of the lines of `docker
+    /// build` output from either the classic builder or BuildKit. Classic steps
+    /// are cached if a "Using cache" line follows before the next step,
+    /// BuildKit steps are cached if there is a "CACHED" line with the same
+    /// vertex.
+    ///
+    /// ```
+    /// use super_orchestrator::BuildStats;
+    ///
+    /// let output = "#1 [internal] load build definition from Dockerfile
+    /// #1 DONE 0.0s
+    /// #2 [1/3] FROM docker.io/library/alpine:3.20
+    /// #2 DONE 0.4s
+    /// #3 [2/3] RUN apk add curl
+    /// #3 CACHED
+    /// #4 [3/3] COPY entrypoint.sh /entrypoint.sh
+    /// #4 DONE 0.1s
+    /// ";
+    /// let stats = BuildStats::from_lines(output.lines());
+    /// assert_eq!((stats.steps, stats.cached, stats.executed), (3, 2, 1));
+    /// assert_eq!(
+    ///     stats.first_miss.as_deref(),
+    ///     Some("COPY entrypoint.sh /entrypoint.sh")
+    /// );
+    /// ```
+    pub fn from_lines<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> Self {
+        // (vertex, description, cached) in order of appearance
+        let mut steps: Vec<(Option<u64>, String, bool)> = vec![];
+        for progress in lines.into_iter().filter_map(parse_build_progress) {
+            match progress.vertex {
+                // classic builder
+                None => {
+                    if progress.step.is_some() {
+                        let cached = progress.description.starts_with("FROM ");
+                        steps.push((None, progress.description, cached));
+                    } else if progress.is_cached() {
+                        if let Some(last) = steps.last_mut() {
+                            last.2 = true;
+                        }
+                    }
+                }
+                Some(vertex) => {
+                    let step = steps.iter_mut().find(|step| step.0 == Some(vertex));
+                    match step {
+                        Some(step) => {
+                            if progress.is_cached() {
+                                step.2 = true;
+                            }
+                        }
+                        None => {
+                            if progress.step.is_some() {
+                                let cached = progress.description.starts_with("FROM ");
+                                steps.push((Some(vertex), progress.description, cached));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let cached = steps.iter().filter(|step| step.2).count() as u64;
+        Self {
+            steps: steps.len() as u64,
+            cached,
+            executed: steps.len() as u64 - cached,
+            duration: Duration::ZERO,
+            first_miss: steps.into_iter().find(|step| !step.2).map(|step| step.1),
+        }
+    }
+
+    /// The fraction of steps that were cached, or `None` if there were no
+    /// steps
+    pub fn cached_fraction(&self) -> Option<f64> {
+        (self.steps != 0).then(|| (self.cached as f64) / (self.steps as f64))
+    }
+
+    /// If every step was cached
+    pub fn is_fully_cached(&self) -> bool {
+        self.executed == 0
+    }
+}
+
+impl fmt::Display for BuildStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} steps cached in {:.1?}",
+            self.cached, self.steps, self.duration
+        )?;
+        if let Some(ref first_miss) = self.first_miss {
+            write!(f, ", first miss: {first_miss}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a size string as output by docker (e.x. "1.5GB", "250MB", "0B", or
+/// "12.3kB (45%)" where anything after whitespace is ignored) into a number of
+/// bytes using [parse_size]. Docker uses decimal units,
+/// but binary units like "MiB" are also accepted.
+///
+/// ```
+/// use super_orchestrator::parse_docker_size;
+///
+/// assert_eq!(parse_docker_size("0B").unwrap(), 0);
+/// assert_eq!(parse_docker_size("512B").unwrap(), 512);
+/// assert_eq!(parse_docker_size("12.5kB").unwrap(), 12_500);
+/// assert_eq!(parse_docker_size("250MB").unwrap(), 250_000_000);
+/// assert_eq!(parse_docker_size("1.5GB").unwrap(), 1_500_000_000);
+/// assert_eq!(parse_docker_size("2TB").unwrap(), 2_000_000_000_000);
+/// assert_eq!(parse_docker_size("1.2GB (50%)").unwrap(), 1_200_000_000);
+/// assert_eq!(parse_docker_size("1MiB").unwrap(), 1024 * 1024);
+/// assert!(parse_docker_size("").is_err());
+/// assert!(parse_docker_size("GB").is_err());
+/// assert!(parse_docker_size("1.5XB").is_err());
+/// ```
+pub fn parse_docker_size(size: &str) -> Result<u64> {
+    let first = size.split_whitespace().next().unwrap_or("");
+    parse_size(first).stack_err_locationless(|| format!("parse_docker_size(size: \"{size}\")"))
+}
+
 /// Applies `get` and `stack_err(...)?` in a chain
 ///
 /// ```
@@ -5,11 +5,34 @@ use std::path::{Path, PathBuf};
 use stacked_errors::{Error, Result, StackableErr};
 use tokio::fs;
 
+use crate::base_dir;
+
 // Note: we use `dunce::simplify` because of https://github.com/rust-lang/rust/issues/42869
 // and because we want to use `tokio::fs`.
 
+/// Resolves a relative `path` against the global [base_dir], returning the
+/// resolved path and a note about the base for error messages
+pub(crate) fn resolve(path: &Path) -> (PathBuf, String) {
+    if path.is_absolute() {
+        return (path.to_owned(), String::new())
+    }
+    if let Some(base_dir) = base_dir() {
+        (
+            base_dir.join(path),
+            format!(" (relative to the base dir {base_dir:?})"),
+        )
+    } else {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        (
+            path.to_owned(),
+            format!(" (relative to the current working directory {cwd:?})"),
+        )
+    }
+}
+
 /// Canonicalizes and checks the existence of a path. Also adds on better
-/// information to errors.
+/// information to errors. Relative paths are resolved against the
+/// [base_dir](crate::base_dir) if one is set.
 ///
 /// Note: this does not prevent TOCTOU bugs. See the crate examples for more.
 pub async fn acquire_path(path: impl AsRef<Path>) -> Result<PathBuf> {
@@ -17,9 +40,10 @@ pub async fn acquire_path(path: impl AsRef<Path>) -> Result<PathBuf> {
     // note: we don't need fs::try_exists because the canonicalization deals with
     // testing for existence and the symbolic links
 
-    let mut path = fs::canonicalize(path)
+    let (resolved, base) = resolve(path);
+    let mut path = fs::canonicalize(resolved)
         .await
-        .stack_err_locationless(|| format!("acquire_path(path: {:?})", path))?;
+        .stack_err_locationless(|| format!("acquire_path(path: {path:?}){base}"))?;
     if cfg!(windows) {
         path = dunce::simplified(&path).to_owned();
     }
@@ -27,14 +51,16 @@ pub async fn acquire_path(path: impl AsRef<Path>) -> Result<PathBuf> {
 }
 
 /// Canonicalizes and checks the existence of a file path. Also adds on better
-/// information to errors.
+/// information to errors. Relative paths are resolved against the
+/// [base_dir](crate::base_dir) if one is set.
 ///
 /// Note: this does not prevent TOCTOU bugs. See the crate examples for more.
 pub async fn acquire_file_path(file_path: impl AsRef<Path>) -> Result<PathBuf> {
     let file_path = file_path.as_ref();
-    let mut path = fs::canonicalize(file_path)
+    let (resolved, base) = resolve(file_path);
+    let mut path = fs::canonicalize(resolved)
         .await
-        .stack_err_locationless(|| format!("acquire_file_path(file_path: {:?})", file_path))?;
+        .stack_err_locationless(|| format!("acquire_file_path(file_path: {file_path:?}){base}"))?;
     if cfg!(windows) {
         path = dunce::simplified(&path).to_owned();
     }
@@ -42,21 +68,22 @@ pub async fn acquire_file_path(file_path: impl AsRef<Path>) -> Result<PathBuf> {
         Ok(path)
     } else {
         Err(Error::from_kind_locationless(format!(
-            "acquire_file_path(file_path: {:?}) -> is not a file",
-            file_path
+            "acquire_file_path(file_path: {file_path:?}){base} -> is not a file"
         )))
     }
 }
 
 /// Canonicalizes and checks the existence of a directory path. Also adds on
-/// better information to errors.
+/// better information to errors. Relative paths are resolved against the
+/// [base_dir](crate::base_dir) if one is set.
 ///
 /// Note: this does not prevent TOCTOU bugs. See the crate examples for more.
 pub async fn acquire_dir_path(dir_path: impl AsRef<Path>) -> Result<PathBuf> {
     let dir_path = dir_path.as_ref();
-    let mut path = fs::canonicalize(dir_path)
+    let (resolved, base) = resolve(dir_path);
+    let mut path = fs::canonicalize(resolved)
         .await
-        .stack_err_locationless(|| format!("acquire_dir_path(dir_path: {:?})", dir_path))?;
+        .stack_err_locationless(|| format!("acquire_dir_path(dir_path: {dir_path:?}){base}"))?;
     if cfg!(windows) {
         path = dunce::simplified(&path).to_owned();
     }
@@ -64,8 +91,7 @@ pub async fn acquire_dir_path(dir_path: impl AsRef<Path>) -> Result<PathBuf> {
         Ok(path)
     } else {
         Err(Error::from_kind_locationless(format!(
-            "acquire_dir_path(dir_path: {:?}) -> is not a directory",
-            dir_path
+            "acquire_dir_path(dir_path: {dir_path:?}){base} -> is not a directory"
         )))
     }
 }
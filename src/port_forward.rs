@@ -0,0 +1,258 @@
+use std::{net::SocketAddr, time::Duration};
+
+use stacked_errors::{Result, StackableErr};
+use tokio::{
+    io::copy_bidirectional,
+    net::{TcpListener, TcpStream},
+    task::{AbortHandle, JoinHandle, JoinSet},
+    time::timeout,
+};
+use tracing::debug;
+
+use crate::{docker::ContainerNetwork, docker_helpers::docker_command};
+
+/// The image used for relay containers when the IP address of a container is
+/// not reachable from the host, see [ContainerNetwork::forward_port]
+pub const PORT_FORWARD_RELAY_IMAGE: &str = "alpine/socat";
+
+/// How long [ContainerNetwork::forward_port] waits on a connection attempt
+/// to the container IP address before deciding that it is unreachable
+const REACHABLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What the `ContainerNetwork` keeps of each forward so that
+/// [ContainerNetwork::terminate_all] can stop them
+#[derive(Debug)]
+pub(crate) struct ForwardStop {
+    task: Option<AbortHandle>,
+    relay_id: Option<String>,
+}
+
+/// A handle to a port forward started by [ContainerNetwork::forward_port].
+/// The forward is stopped if the handle is dropped without calling
+/// [ForwardHandle::stop] (except that a relay container is only removed by
+/// `stop` or the termination functions of the `ContainerNetwork`).
+#[derive(Debug)]
+pub struct ForwardHandle {
+    local_addr: SocketAddr,
+    task: Option<JoinHandle<()>>,
+    relay_id: Option<String>,
+}
+
+impl Drop for ForwardHandle {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+impl ForwardHandle {
+    /// The localhost address that forwards to the container
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// If the forward goes through a relay container, returns its ID
+    pub fn relay_id(&self) -> Option<&str> {
+        self.relay_id.as_deref()
+    }
+
+    /// Stops accepting connections, closes the open ones, and removes the
+    /// relay container if there is one
+    pub async fn stop(mut self) -> Result<()> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            let _ = task.await;
+        }
+        if let Some(id) = self.relay_id.take() {
+            remove_relay(&id).await.stack_err_locationless(|| {
+                format!("ForwardHandle::stop -> when removing the relay container {id}")
+            })?;
+        }
+        Ok(())
+    }
+}
+
+async fn remove_relay(id: &str) -> Result<()> {
+    let comres = docker_command("rm -f")
+        .arg(id)
+        .run_to_completion()
+        .await
+        .stack()?;
+    let stderr = comres.stderr_as_utf8_lossy();
+    if (!comres.successful()) && (!stderr.contains("No such container")) {
+        comres.assert_success().stack()?;
+    }
+    Ok(())
+}
+
+/// Accepts connections on `listener` and copies bytes between each one and a
+/// new connection to `target`, until either side closes
+async fn proxy(listener: TcpListener, target: SocketAddr) {
+    // dropping this when the task is aborted aborts all the connections
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (mut inbound, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("port forward to {target} -> could not accept: {e}");
+                        continue
+                    }
+                };
+                connections.spawn(async move {
+                    let mut outbound = match TcpStream::connect(target).await {
+                        Ok(outbound) => outbound,
+                        Err(e) => {
+                            debug!("port forward to {target} -> could not connect for {peer}: {e}");
+                            return
+                        }
+                    };
+                    if let Err(e) = copy_bidirectional(&mut inbound, &mut outbound).await {
+                        debug!("port forward to {target} -> connection from {peer} ended: {e}");
+                    }
+                });
+            }
+            // reap finished connections so that the set does not grow
+            Some(_) = connections.join_next(), if !connections.is_empty() => (),
+        }
+    }
+}
+
+impl ContainerNetwork {
+    /// Forwards an ephemeral port on the host localhost to `container_port` of
+    /// the active container with `name`, which works even for
+    /// [internal](ContainerNetwork::internal) networks. A host side task
+    /// accepts connections on the local port and copies bytes to and from new
+    /// connections to the IP address of the container. Use
+    /// [ForwardHandle::local_addr] to find the local port.
+    ///
+    /// On Linux the IP addresses of bridge networks are reachable from the
+    /// host. Where they are not (e.x. Docker Desktop on macOS and Windows),
+    /// this instead falls back to a relay container running
+    /// [PORT_FORWARD_RELAY_IMAGE] that is connected to both the network and
+    /// the default bridge network, with the relay port published only on the
+    /// host localhost. This requires the image to be pullable.
+    ///
+    /// [ContainerNetwork::terminate_all] and the other termination functions
+    /// for the whole network stop all forwards.
+    pub async fn forward_port(&mut self, name: &str, container_port: u16) -> Result<ForwardHandle> {
+        let ip = self
+            .wait_get_ip_addr(20, Duration::from_millis(300), name)
+            .await
+            .stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::forward_port(name: {name}, container_port: \
+                     {container_port})"
+                )
+            })?;
+        let target = SocketAddr::new(ip, container_port);
+        // a refused connection means the container is reachable but may not be
+        // listening yet
+        let reachable = match timeout(REACHABLE_TIMEOUT, TcpStream::connect(target)).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => e.kind() == std::io::ErrorKind::ConnectionRefused,
+            Err(_) => false,
+        };
+        let handle = if reachable {
+            let listener = TcpListener::bind("127.0.0.1:0").await.stack()?;
+            let local_addr = listener.local_addr().stack()?;
+            let task = tokio::task::spawn(proxy(listener, target));
+            ForwardHandle {
+                local_addr,
+                task: Some(task),
+                relay_id: None,
+            }
+        } else {
+            debug!(
+                "ContainerNetwork::forward_port -> {target} is not reachable from the host, \
+                 falling back to a relay container"
+            );
+            self.start_relay(target).await.stack_err_locationless(|| {
+                format!(
+                    "ContainerNetwork::forward_port(name: {name}, container_port: \
+                     {container_port}) -> {target} is not reachable from the host, and starting a \
+                     relay container failed"
+                )
+            })?
+        };
+        self.forwards.push(ForwardStop {
+            task: handle.task.as_ref().map(|task| task.abort_handle()),
+            relay_id: handle.relay_id.clone(),
+        });
+        Ok(handle)
+    }
+
+    async fn start_relay(&self, target: SocketAddr) -> Result<ForwardHandle> {
+        let port = target.port();
+        let comres = self
+            .wrap(
+                docker_command("create --rm -p")
+                    .arg(format!("127.0.0.1::{port}"))
+                    .arg(PORT_FORWARD_RELAY_IMAGE)
+                    .arg(format!("TCP-LISTEN:{port},fork,reuseaddr"))
+                    .arg(format!("TCP:{target}")),
+            )
+            .run_to_completion()
+            .await
+            .stack()?;
+        comres.assert_success().stack()?;
+        let id = comres.stdout_as_utf8().stack()?.trim().to_owned();
+        let res = async {
+            self.wrap(
+                docker_command("network connect")
+                    .arg(self.network_name())
+                    .arg(&id),
+            )
+            .run_to_completion()
+            .await
+            .stack()?
+            .assert_success()
+            .stack()?;
+            self.wrap(docker_command("start").arg(&id))
+                .run_to_completion()
+                .await
+                .stack()?
+                .assert_success()
+                .stack()?;
+            let comres = self
+                .wrap(docker_command("port").arg(&id).arg(format!("{port}/tcp")))
+                .run_to_completion()
+                .await
+                .stack()?;
+            comres.assert_success().stack()?;
+            let stdout = comres.stdout_as_utf8().stack()?;
+            let line = stdout.lines().next().unwrap_or("").trim();
+            line.parse::<SocketAddr>().stack_err_locationless(|| {
+                format!("could not parse the output {line:?} of `docker port`")
+            })
+        }
+        .await;
+        match res {
+            Ok(local_addr) => Ok(ForwardHandle {
+                local_addr,
+                task: None,
+                relay_id: Some(id),
+            }),
+            Err(e) => {
+                let _ = remove_relay(&id).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Aborts the tasks of all the forwards started by
+    /// [ContainerNetwork::forward_port], returning the IDs of the relay
+    /// containers that still need to be removed
+    pub(crate) fn take_forwards(&mut self) -> Vec<String> {
+        let mut relay_ids = vec![];
+        for mut forward in self.forwards.drain(..) {
+            if let Some(task) = forward.task.take() {
+                task.abort();
+            }
+            relay_ids.extend(forward.relay_id.take());
+        }
+        relay_ids
+    }
+}
@@ -0,0 +1,24 @@
+//! ```
+//! use super_orchestrator::prelude::*;
+//!
+//! let container = Container::new("example", Dockerfile::name_tag("alpine:3.20"))
+//!     .entrypoint("/bin/ls", ["-a", "/"]);
+//! let mut cn = ContainerNetwork::new("example", None, "./logs");
+//! cn.add_container(container).stack().unwrap();
+//! ```
+//!
+//! `stacked_errors::Result` is left out so that the glob import does not
+//! shadow the `Result` of the standard library, use
+//! [stacked_errors::Result](crate::stacked_errors::Result) explicitly.
+
+#[doc(no_inline)]
+pub use stacked_errors::StackableErr;
+
+#[doc(no_inline)]
+pub use crate::{
+    acquire_dir_path, acquire_file_path, acquire_path, ctrlc_init,
+    docker::{Container, ContainerNetwork, Dockerfile, ReadinessProbe},
+    net_message::wait_for_ok_lookup_host,
+    sh, wait_for_ok, Backoff, Command, CommandResult, CommandRunner, FileOptions,
+    FindBinaryOptions, ReadOrWrite, CTRLC_ISSUED,
+};
@@ -0,0 +1,193 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use stacked_errors::{Result, StackableErr};
+use tokio::{sync::oneshot, task::JoinHandle, time::sleep};
+use tracing::warn;
+
+use crate::{
+    docker::ContainerNetwork, docker_helpers::docker_command, misc::emit_crate_event,
+    parse_docker_size, stacked_get, CrateEvent,
+};
+
+/// Resource usage observed for a single container by a
+/// [ResourceMonitorHandle]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// The number of `docker stats` samples that included the container
+    pub samples: u64,
+    /// The peak memory usage in bytes
+    pub peak_memory: u64,
+    /// The sum of the CPU percentages of all samples
+    pub cpu_sum: f64,
+}
+
+impl ResourceUsage {
+    /// The average CPU percentage over all samples (where 100.0 is one full
+    /// core), `None` if there are no samples
+    pub fn average_cpu(&self) -> Option<f64> {
+        if self.samples == 0 {
+            None
+        } else {
+            Some(self.cpu_sum / (self.samples as f64))
+        }
+    }
+}
+
+/// The final observations of a [ResourceMonitorHandle]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceReport {
+    /// Usage by container name
+    pub containers: BTreeMap<String, ResourceUsage>,
+}
+
+/// A handle to the background task started by
+/// [ContainerNetwork::monitor_resource_usage]. The task is aborted if the
+/// handle is dropped without calling [ResourceMonitorHandle::stop].
+#[derive(Debug)]
+pub struct ResourceMonitorHandle {
+    usage: Arc<Mutex<BTreeMap<String, ResourceUsage>>>,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Drop for ResourceMonitorHandle {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+impl ResourceMonitorHandle {
+    /// The peak memory usage in bytes observed so far for the container with
+    /// `name`
+    pub fn peak_memory(&self, name: &str) -> Option<u64> {
+        self.usage
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|usage| usage.peak_memory)
+    }
+
+    /// The average CPU percentage observed so far for the container with
+    /// `name`
+    pub fn average_cpu(&self, name: &str) -> Option<f64> {
+        self.usage.lock().unwrap().get(name)?.average_cpu()
+    }
+
+    /// Stops the monitoring task and returns everything it observed
+    pub async fn stop(mut self) -> ResourceReport {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+        ResourceReport {
+            containers: self.usage.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Parses a line of `docker stats --format {{json .}}` into the container ID,
+/// memory usage in bytes, and CPU percentage
+fn parse_stats_line(line: &str) -> Result<(String, u64, f64)> {
+    let v: serde_json::Value = serde_json::from_str(line).stack()?;
+    let id = stacked_get!(v["ID"]).as_str().stack()?.to_owned();
+    // of the form "1.5MiB / 7.7GiB"
+    let mem_usage = stacked_get!(v["MemUsage"]).as_str().stack()?;
+    let memory = parse_docker_size(mem_usage.split('/').next().unwrap_or("").trim())?;
+    let cpu = stacked_get!(v["CPUPerc"])
+        .as_str()
+        .stack()?
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .stack()?;
+    Ok((id, memory, cpu))
+}
+
+/// Runs `docker stats --no-stream` on `ids`, recording into `usage`
+async fn sample(
+    ids: &BTreeMap<String, String>,
+    usage: &Mutex<BTreeMap<String, ResourceUsage>>,
+) -> Result<()> {
+    let comres = docker_command("stats --no-stream --format")
+        .arg("{{json .}}")
+        .args(ids.keys())
+        // the sample is dropped if the monitoring is stopped while it is in flight
+        .suppress_drop_warning(true)
+        .run_to_completion()
+        .await
+        .stack()?;
+    // containers that exit in the meantime cause a failure
+    let stdout = comres.stdout_as_utf8_lossy();
+    let mut usage = usage.lock().unwrap();
+    for line in stdout.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        let (id, memory, cpu) = parse_stats_line(line)
+            .stack_err_locationless(|| format!("could not parse `docker stats` line {line}"))?;
+        // `docker stats` may output a shortened ID
+        let Some(name) = ids
+            .iter()
+            .find_map(|(full_id, name)| full_id.starts_with(&id).then_some(name))
+        else {
+            continue
+        };
+        let entry = usage.entry(name.clone()).or_default();
+        entry.samples += 1;
+        entry.peak_memory = entry.peak_memory.max(memory);
+        entry.cpu_sum += cpu;
+    }
+    Ok(())
+}
+
+impl ContainerNetwork {
+    /// Starts a background task that polls `docker stats --no-stream` every
+    /// `interval` for all the containers that are active when this is called.
+    /// Use the returned handle to query or stop the monitoring. Sampling
+    /// errors (e.x. from containers exiting) are only warned about.
+    pub fn monitor_resource_usage(&self, interval: Duration) -> ResourceMonitorHandle {
+        // container ID to name
+        let ids: BTreeMap<String, String> = self
+            .get_active_container_ids()
+            .into_iter()
+            .map(|(name, id)| (id, name))
+            .collect();
+        let usage = Arc::new(Mutex::new(BTreeMap::new()));
+        let (stop_send, mut stop_recv) = oneshot::channel();
+        let task_usage = usage.clone();
+        let network_name = self.network_name().to_owned();
+        let task = tokio::task::spawn(async move {
+            if ids.is_empty() {
+                return
+            }
+            loop {
+                if let Err(e) = sample(&ids, &task_usage).await {
+                    warn!("ContainerNetwork::monitor_resource_usage -> {e:?}");
+                    emit_crate_event(|| CrateEvent::ResourceMonitorFailed {
+                        network_name: network_name.clone(),
+                        error: format!("{e:?}"),
+                    });
+                }
+                tokio::select! {
+                    _ = sleep(interval) => (),
+                    _ = &mut stop_recv => break,
+                }
+            }
+        });
+        ResourceMonitorHandle {
+            usage,
+            stop: Some(stop_send),
+            task: Some(task),
+        }
+    }
+}
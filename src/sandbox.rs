@@ -0,0 +1,208 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+#[cfg(target_os = "linux")]
+use stacked_errors::StackableErr;
+use stacked_errors::{Error, Result};
+
+#[cfg(target_os = "linux")]
+use crate::command_runner::command_runner_with;
+use crate::{Command, CommandRunner};
+
+/// Returned (boxed in the error stack) by [Command::run_with_sandbox] on
+/// platforms other than Linux, where sandboxing is not supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxUnsupportedError {
+    /// The `std::env::consts::OS` of the platform
+    pub os: String,
+}
+
+impl Display for SandboxUnsupportedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "sandboxing is only supported on Linux, not on \"{}\"",
+            self.os
+        )
+    }
+}
+
+impl std::error::Error for SandboxUnsupportedError {}
+
+/// Restrictions applied to a command run with [Command::run_with_sandbox]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// The only paths (and everything beneath them) that the command can write
+    /// to. Reading and executing is not restricted.
+    pub allowed_paths: Vec<String>,
+    /// If `false`, the command cannot create IPv4 or IPv6 sockets. This is
+    /// only a seccomp filter on `socket` calls with `AF_INET` or `AF_INET6`,
+    /// not a network namespace, so other socket families (e.x. Unix domain
+    /// sockets) and sockets inherited from the parent still work.
+    pub allow_network: bool,
+    /// Environment variables removed from the command's environment
+    pub deny_env_vars: Vec<String>,
+}
+
+impl SandboxConfig {
+    /// A config that allows writing to nothing, no network, and does not
+    /// remove any environment variables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds to `allowed_paths`
+    pub fn allowed_paths<I, S>(mut self, allowed_paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.allowed_paths
+            .extend(allowed_paths.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets `allow_network`
+    pub fn allow_network(mut self, allow_network: bool) -> Self {
+        self.allow_network = allow_network;
+        self
+    }
+
+    /// Adds to `deny_env_vars`
+    pub fn deny_env_vars<I, S>(mut self, deny_env_vars: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.deny_env_vars
+            .extend(deny_env_vars.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+}
+
+impl Command {
+    /// Runs the command with the restrictions of `config`, returning a
+    /// `CommandRunner`.
+    ///
+    /// On Linux, filesystem writes are restricted with a Landlock ruleset
+    /// (requiring a kernel with Landlock enabled, this returns an error
+    /// otherwise instead of running unrestricted). If `allow_network` is not
+    /// set, a seccomp-bpf filter makes `socket` calls with `AF_INET` or
+    /// `AF_INET6` fail with `EACCES`. There is no network namespace, so this
+    /// does not restrict other socket families or already open sockets. Both
+    /// are applied in the child process just before it executes the program.
+    /// On other platforms this returns a [SandboxUnsupportedError].
+    pub async fn run_with_sandbox(self, config: SandboxConfig) -> Result<CommandRunner> {
+        #[cfg(target_os = "linux")]
+        {
+            let pre_exec = linux::prepare(&config).stack_err_locationless(|| {
+                format!("Command::run_with_sandbox(config: {config:?}) -> when preparing sandbox")
+            })?;
+            command_runner_with(self, std::process::Stdio::null(), move |cmd| {
+                for var in &config.deny_env_vars {
+                    cmd.env_remove(var);
+                }
+                // Safety: the closure only makes syscalls with resources prepared beforehand,
+                // it does not allocate unless there is an error
+                unsafe {
+                    cmd.pre_exec(pre_exec);
+                }
+            })
+            .await
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (self, config);
+            Err(Error::box_from_locationless(SandboxUnsupportedError {
+                os: std::env::consts::OS.to_owned(),
+            })
+            .add_kind_locationless("Command::run_with_sandbox"))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{collections::BTreeMap, io};
+
+    use landlock::{
+        path_beneath_rules, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr,
+        RulesetCreatedAttr, ABI,
+    };
+    use seccompiler::{
+        apply_filter, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+        SeccompFilter, SeccompRule, TargetArch,
+    };
+
+    use super::*;
+
+    type PreExec = Box<dyn FnMut() -> io::Result<()> + Send + Sync>;
+
+    /// Does as much as possible in the parent process, returning a closure to
+    /// be called in the child process
+    pub(super) fn prepare(config: &SandboxConfig) -> Result<PreExec> {
+        let abi = ABI::V1;
+        let mut ruleset = Some(
+            Ruleset::default()
+                .set_compatibility(CompatLevel::HardRequirement)
+                .handle_access(AccessFs::from_write(abi))
+                .map_err(Error::box_from_locationless)
+                .stack_err_locationless(|| "Landlock ruleset handling failed")?
+                .create()
+                .map_err(Error::box_from_locationless)
+                .stack_err_locationless(|| {
+                    "Landlock ruleset creation failed, the kernel may not support Landlock"
+                })?
+                .add_rules(path_beneath_rules(
+                    &config.allowed_paths,
+                    AccessFs::from_write(abi),
+                ))
+                .map_err(Error::box_from_locationless)
+                .stack_err_locationless(|| "Landlock rule for allowed paths failed")?,
+        );
+
+        let seccomp = if config.allow_network {
+            None
+        } else {
+            let arch = TargetArch::try_from(std::env::consts::ARCH)
+                .map_err(Error::box_from_locationless)
+                .stack_err_locationless(|| "seccomp does not support this architecture")?;
+            let mut rules = vec![];
+            for domain in [libc::AF_INET, libc::AF_INET6] {
+                rules.push(
+                    SeccompRule::new(vec![SeccompCondition::new(
+                        0,
+                        SeccompCmpArgLen::Dword,
+                        SeccompCmpOp::Eq,
+                        domain as u64,
+                    )
+                    .map_err(Error::box_from_locationless)?])
+                    .map_err(Error::box_from_locationless)?,
+                );
+            }
+            let filter = SeccompFilter::new(
+                BTreeMap::from([(libc::SYS_socket, rules)]),
+                SeccompAction::Allow,
+                SeccompAction::Errno(libc::EACCES as u32),
+                arch,
+            )
+            .map_err(Error::box_from_locationless)
+            .stack_err_locationless(|| "seccomp filter creation failed")?;
+            let program: BpfProgram = filter
+                .try_into()
+                .map_err(Error::box_from_locationless)
+                .stack_err_locationless(|| "seccomp filter compilation failed")?;
+            Some(program)
+        };
+
+        Ok(Box::new(move || {
+            if let Some(ruleset) = ruleset.take() {
+                ruleset.restrict_self().map_err(io::Error::other)?;
+            }
+            if let Some(ref program) = seccomp {
+                apply_filter(program).map_err(io::Error::other)?;
+            }
+            Ok(())
+        }))
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use stacked_errors::{Error, Result, StackableErr};
+
+use crate::{
+    docker::{Container, ContainerNetwork, PullPolicy, RemovedContainer},
+    CommandResult,
+};
+
+/// A builder for running a single container by itself in an implicit
+/// `ContainerNetwork`, see [Container::runner]
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct SingleContainerRunner {
+    container: Container,
+    dockerfile_write_dir: Option<String>,
+    log_dir: String,
+    network_name: String,
+    internal: bool,
+    network_args: Vec<String>,
+    pull_policy: Option<PullPolicy>,
+    debug: bool,
+    timeout: Duration,
+    build_timeout: Option<Duration>,
+}
+
+impl SingleContainerRunner {
+    pub(crate) fn new(container: Container) -> Self {
+        Self {
+            container,
+            dockerfile_write_dir: None,
+            log_dir: "./logs".to_owned(),
+            network_name: "super_orchestrator".to_owned(),
+            internal: false,
+            network_args: vec![],
+            pull_policy: None,
+            debug: false,
+            timeout: Duration::MAX,
+            build_timeout: None,
+        }
+    }
+
+    /// Sets the timeout for waiting on the container to complete after it has
+    /// started. This is unlimited by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets a separate timeout for everything before the container starts
+    /// (pulling, building, and creating), since image pulls can dominate. This
+    /// is unlimited by default and is not taken out of `timeout`.
+    pub fn build_timeout(mut self, build_timeout: Duration) -> Self {
+        self.build_timeout = Some(build_timeout);
+        self
+    }
+
+    /// Sets where ".log" log files are written, "./logs" by default
+    pub fn log_dir(mut self, log_dir: impl AsRef<str>) -> Self {
+        self.log_dir = log_dir.as_ref().to_owned();
+        self
+    }
+
+    /// Sets where ".tmp.dockerfile" files are written if `Dockerfile::Contents`
+    /// is used
+    pub fn dockerfile_write_dir(mut self, dockerfile_write_dir: impl AsRef<str>) -> Self {
+        self.dockerfile_write_dir = Some(dockerfile_write_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the network name, which gets a UUID suffix. This is
+    /// "super_orchestrator" by default.
+    pub fn network_name(mut self, network_name: impl AsRef<str>) -> Self {
+        self.network_name = network_name.as_ref().to_owned();
+        self
+    }
+
+    /// Sets [ContainerNetwork::internal]
+    pub fn internal(mut self, internal: bool) -> Self {
+        self.internal = internal;
+        self
+    }
+
+    /// Adds arguments passed to `docker network create`, see
+    /// [ContainerNetwork::add_network_args]
+    pub fn network_args<I, S>(mut self, network_args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.network_args
+            .extend(network_args.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets [ContainerNetwork::default_pull_policy], which applies if the
+    /// container does not have its own `pull_policy`
+    pub fn pull_policy(mut self, pull_policy: PullPolicy) -> Self {
+        self.pull_policy = Some(pull_policy);
+        self
+    }
+
+    /// Setting `debug` is equivalent to setting `debug_build` and
+    /// `debug_create` on a `ContainerNetwork`
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Runs the container and waits for its completion. Unconditionally sets
+    /// `allow_unsuccessful`, so the `CommandResult` has to be checked if there
+    /// was an unsuccessful error return status from within the container
+    /// itself. The network is torn down before returning, including when there
+    /// is an error.
+    pub async fn run(self) -> Result<CommandResult> {
+        let mut cn = ContainerNetwork::new_with_uuid(
+            &self.network_name,
+            self.dockerfile_write_dir.as_deref(),
+            &self.log_dir,
+        );
+        cn.debug_build(self.debug)
+            .debug_create(self.debug)
+            .internal(self.internal)
+            .add_network_args(&self.network_args);
+        if let Some(pull_policy) = self.pull_policy {
+            cn.default_pull_policy(pull_policy);
+        }
+        let name = self.container.name.clone();
+        cn.add_container(self.container.allow_unsuccessful(true))
+            .stack_err_locationless(|| {
+                "SingleContainerRunner::run when trying to create a `ContainerNetwork`"
+            })?;
+
+        // in order to get unsuccesful `CommandResult`s, we do not terminate on failure
+        // and need to remember to `terminate_all` before returning other kinds of
+        // errors
+        let res = if let Some(build_timeout) = self.build_timeout {
+            match tokio::time::timeout(build_timeout, cn.run_all()).await {
+                Ok(res) => res,
+                Err(_) => Err(Error::timeout().add_kind_locationless(format!(
+                    "timeout of {build_timeout:?} before the container started"
+                ))),
+            }
+        } else {
+            cn.run_all().await
+        };
+        if let Err(e) = res {
+            cn.terminate_all().await;
+            return Err(e.add_kind_locationless(
+                "SingleContainerRunner::run when trying to run a `ContainerNetwork`",
+            ))
+        }
+        if let Err(e) = cn.wait_with_timeout([&name], true, self.timeout).await {
+            cn.terminate_all().await;
+            return Err(e.add_kind_locationless(
+                "SingleContainerRunner::run when waiting on its `ContainerNetwork`",
+            ))
+        }
+        cn.terminate_all().await;
+
+        match cn.try_remove_container(name).await.unwrap() {
+            RemovedContainer::Ran(comres) => Ok(comres),
+            RemovedContainer::RunnerError(e) => Err(e.add_kind_locationless(
+                "SingleContainerRunner::run could not get `CommandResult` because of some \
+                 internal bug or error",
+            )),
+            RemovedContainer::NeverRan => Err(Error::from_kind_locationless(
+                "SingleContainerRunner::run could not get `CommandResult` because of some \
+                 internal bug or error",
+            )),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::{collections::BTreeSet, mem};
+
+use stacked_errors::{Error, Result};
+
+use crate::docker::Container;
+
+/// The placeholders that can be used in templates, for error messages
+pub(crate) const VALID_PLACEHOLDERS: &str = "{{uuid}}, {{hostname:<name>}}, {{ip:<name>}}, and \
+                                             {{port:<reserved port key>}}, with {{{{ for a \
+                                             literal {{";
+
+/// A placeholder in a template, see
+/// [ContainerNetwork::run](crate::docker::ContainerNetwork::run)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Placeholder {
+    Uuid,
+    Hostname(String),
+    Ip(String),
+    Port(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Piece {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Parses the template `s` into literals and placeholders. Every "{{" starts
+/// a placeholder that ends with "}}", except for "{{{{" which is a literal
+/// "{{". Returns an error message for invalid templates.
+pub(crate) fn parse_template(s: &str) -> std::result::Result<Vec<Piece>, String> {
+    let mut pieces = vec![];
+    let mut literal = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find("{{") {
+        literal.push_str(&rest[..i]);
+        rest = &rest[(i + 2)..];
+        if let Some(after) = rest.strip_prefix("{{") {
+            literal.push_str("{{");
+            rest = after;
+            continue
+        }
+        let end = rest.find("}}").ok_or_else(|| {
+            format!(
+                "template {s:?} has an unclosed \"{{{{\", the valid placeholders are \
+                 {VALID_PLACEHOLDERS}"
+            )
+        })?;
+        let key = &rest[..end];
+        rest = &rest[(end + 2)..];
+        let placeholder = match key.split_once(':') {
+            None if key == "uuid" => Placeholder::Uuid,
+            Some(("hostname", name)) if !name.is_empty() => Placeholder::Hostname(name.to_owned()),
+            Some(("ip", name)) if !name.is_empty() => Placeholder::Ip(name.to_owned()),
+            Some(("port", name)) if !name.is_empty() => Placeholder::Port(name.to_owned()),
+            _ => {
+                return Err(format!(
+                    "template {s:?} has the unknown placeholder \"{{{{{key}}}}}\", the valid \
+                     placeholders are {VALID_PLACEHOLDERS}"
+                ))
+            }
+        };
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(mem::take(&mut literal)));
+        }
+        pieces.push(Piece::Placeholder(placeholder));
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+/// The values of `container` that are templates
+pub(crate) fn template_values(container: &Container) -> impl Iterator<Item = &String> {
+    container
+        .entrypoint_args
+        .iter()
+        .chain(container.environment_vars.iter().map(|(_, value)| value))
+        .chain(container.create_args.iter())
+}
+
+/// The placeholders used by `container`, ignoring invalid templates
+pub(crate) fn placeholders(container: &Container) -> BTreeSet<Placeholder> {
+    template_values(container)
+        .filter_map(|value| parse_template(value).ok())
+        .flatten()
+        .filter_map(|piece| match piece {
+            Piece::Placeholder(placeholder) => Some(placeholder),
+            Piece::Literal(_) => None,
+        })
+        .collect()
+}
+
+/// The names of the containers whose IP addresses `container` uses
+pub(crate) fn ip_dependencies(container: &Container) -> BTreeSet<String> {
+    placeholders(container)
+        .into_iter()
+        .filter_map(|placeholder| match placeholder {
+            Placeholder::Ip(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Expands all the templates of `container`, with `resolve` giving the value
+/// of each placeholder
+pub(crate) fn expand_templates(
+    container: &mut Container,
+    resolve: impl Fn(&Placeholder) -> String,
+) -> Result<()> {
+    let expand = |value: &mut String| -> Result<()> {
+        let mut expanded = String::new();
+        for piece in parse_template(value).map_err(Error::from_kind_locationless)? {
+            match piece {
+                Piece::Literal(literal) => expanded.push_str(&literal),
+                Piece::Placeholder(placeholder) => expanded.push_str(&resolve(&placeholder)),
+            }
+        }
+        *value = expanded;
+        Ok(())
+    };
+    for arg in &mut container.entrypoint_args {
+        expand(arg)?;
+    }
+    for (_, value) in &mut container.environment_vars {
+        expand(value)?;
+    }
+    for arg in &mut container.create_args {
+        expand(arg)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,1044 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use stacked_errors::{Result, StackableErr};
+
+use crate::docker_helpers::set_docker_binary;
+
+/// The environment variable that tells the `fake_docker` binary which
+/// directory its scripts and state are in
+pub const FAKE_DOCKER_DIR_VAR: &str = "SUPER_ORCHESTRATOR_FAKE_DOCKER_DIR";
+
+/// What a container does under the `fake_docker` binary, see
+/// [FakeDocker::script]. Unscripted containers exit successfully without
+/// output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FakeContainer {
+    /// Labels changed by `docker start --attach` after each delay (like the
+    /// container updating its own labels), before anything else
+    pub label_updates: Vec<(Duration, String, String)>,
+    /// Printed to stdout by `docker start --attach` after each delay, before
+    /// anything else
+    pub stdout_chunks: Vec<(Duration, String)>,
+    /// Printed to stdout by `docker start --attach`
+    pub stdout: String,
+    /// Printed to stderr by `docker start --attach`
+    pub stderr: String,
+    /// The exit code of `docker start --attach`
+    pub exit_code: i32,
+    /// How long `docker start --attach` runs before printing and exiting
+    pub duration: Duration,
+    /// If `docker create` fails for the container
+    pub fail_create: bool,
+    /// The number of times that `docker create` fails for the container (like
+    /// a daemon hiccup) before it succeeds
+    pub transient_create_failures: u32,
+    /// If `docker rm` fails for the container (leaving it in place)
+    pub fail_remove: bool,
+    /// `docker exec` of anything in the container succeeds once it has been
+    /// running for this long, and fails before that
+    pub exec_ready_after: Duration,
+    /// The IP address reported by `docker inspect`, 172.18.0.2 if `None`
+    pub ip_addr: Option<IpAddr>,
+    /// If nonzero, `docker inspect` reports no IP address until the container
+    /// has been running for this long, and none once it has exited
+    pub ip_addr_after: Duration,
+    /// If the container was created with `--network host`, `docker start
+    /// --attach` listens on this port of localhost while it runs
+    pub host_port: Option<u16>,
+    /// `docker start --attach` first connects to this address, failing with
+    /// exit code 1 if it cannot or if the container was created with
+    /// `--network none`
+    pub connect_to: Option<SocketAddr>,
+    /// If the container was created with `--rm`, it is removed as soon as it
+    /// exits like docker does, so that later commands on it fail
+    pub auto_remove: bool,
+}
+
+impl FakeContainer {
+    /// A container that exits successfully without output
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a change of the label `key` to `value` after `delay`
+    pub fn label_update(
+        mut self,
+        delay: Duration,
+        key: impl AsRef<str>,
+        value: impl AsRef<str>,
+    ) -> Self {
+        self.label_updates
+            .push((delay, key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Adds a chunk printed to stdout after `delay`
+    pub fn stdout_chunk(mut self, delay: Duration, chunk: impl AsRef<str>) -> Self {
+        self.stdout_chunks.push((delay, chunk.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets the stdout
+    pub fn stdout(mut self, stdout: impl AsRef<str>) -> Self {
+        self.stdout = stdout.as_ref().to_owned();
+        self
+    }
+
+    /// Sets the stderr
+    pub fn stderr(mut self, stderr: impl AsRef<str>) -> Self {
+        self.stderr = stderr.as_ref().to_owned();
+        self
+    }
+
+    /// Sets the exit code
+    pub fn exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// Sets how long the container runs
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Sets if `docker create` fails
+    pub fn fail_create(mut self, fail_create: bool) -> Self {
+        self.fail_create = fail_create;
+        self
+    }
+
+    /// Sets the number of times `docker create` fails before succeeding
+    pub fn transient_create_failures(mut self, transient_create_failures: u32) -> Self {
+        self.transient_create_failures = transient_create_failures;
+        self
+    }
+
+    /// Sets if `docker rm` fails
+    pub fn fail_remove(mut self, fail_remove: bool) -> Self {
+        self.fail_remove = fail_remove;
+        self
+    }
+
+    /// Sets how long the container runs before `docker exec` succeeds
+    pub fn exec_ready_after(mut self, exec_ready_after: Duration) -> Self {
+        self.exec_ready_after = exec_ready_after;
+        self
+    }
+
+    /// Sets the IP address reported by `docker inspect`, which can be used to
+    /// point the container at a server on the host
+    pub fn ip_addr(mut self, ip_addr: IpAddr) -> Self {
+        self.ip_addr = Some(ip_addr);
+        self
+    }
+
+    /// Sets how long the container runs before it has an IP address
+    pub fn ip_addr_after(mut self, ip_addr_after: Duration) -> Self {
+        self.ip_addr_after = ip_addr_after;
+        self
+    }
+
+    /// Sets the port that the container listens on when it shares the network
+    /// stack of the host
+    pub fn host_port(mut self, host_port: u16) -> Self {
+        self.host_port = Some(host_port);
+        self
+    }
+
+    /// Sets an address that the container connects to when it starts
+    pub fn connect_to(mut self, connect_to: SocketAddr) -> Self {
+        self.connect_to = Some(connect_to);
+        self
+    }
+
+    /// Sets if the container is removed when it exits if it was created with
+    /// `--rm`
+    pub fn auto_remove(mut self, auto_remove: bool) -> Self {
+        self.auto_remove = auto_remove;
+        self
+    }
+}
+
+/// Points the process-global
+/// [docker_binary](crate::docker_helpers::docker_binary) at the `fake_docker`
+/// binary of this crate, which speaks just enough of the docker CLI for
+/// `ContainerNetwork` to run without a docker daemon.
+///
+/// The supported subcommands are `network create/rm/connect/disconnect`
+/// (creation fails if the network exists, and networks with "slow_create" in
+/// their name take 2 seconds to finish creating), `build` (succeeds unless
+/// the Dockerfile contents contain "unreachable_daemon", in which case it
+/// fails like when the docker daemon cannot be reached, with a short
+/// BuildKit transcript on stderr if passed `--progress=plain`,
+/// where the `RUN` step is cached if the same Dockerfile contents were built
+/// before or are in a `type=local` `--cache-from` written by `--cache-to`),
+/// `create` (prints a fake ID and records `--label`s and `--network`, failing
+/// if a created or running container has the same name, and containers
+/// created with `--rm` are removed when they exit if scripted with
+/// [FakeContainer::auto_remove], and failing like the implicit pull for images
+/// with "nonexistent" in their name that are not present), `start --attach`
+/// (replays the [FakeContainer] scripted for the container name), `start`
+/// without `--attach` (the container then counts as running for the total of
+/// its scripted delays, during which `wait` blocks before printing the exit
+/// code and `logs --follow` replays the output without the label updates),
+/// `exec` (does
+/// not run anything except `echo`, `touch` (which is recorded for `diff`),
+/// and the streaming of files with `-i`, `cat`, and `sha256sum`, but succeeds
+/// according to [FakeContainer::exec_ready_after], and containers with
+/// "distroless" in their name have no `sh`), `run` (only
+/// runs `echo`), `diff`, `rm -f` (failing if scripted with
+/// [FakeContainer::fail_remove]), `inspect` (with `Config.Labels`,
+/// `State.ExitCode`, and the IP address of [FakeContainer::ip_addr] unless
+/// the network is "host" or "none", or
+/// only the exit code with `--format {{.State.ExitCode}}`), `image
+/// inspect`/`pull`/`manifest inspect` (tracking which images have been pulled,
+/// images with "nonexistent" in their name do not exist remotely, and pulls of
+/// images with "offline" in their name fail), `version`/`info` (with `--format
+/// {{json .}}`, like Docker 27 without experimental features), and `login
+/// --password-stdin`/`logout`/`buildx version` (which always succeed).
+/// Everything else fails. The binary is only built with
+/// the "test_doubles" feature, so run the daemon-free tests with `cargo test
+/// --features test_doubles`.
+///
+/// Fake IDs are derived from the container names, so tests running in
+/// parallel in the same process should use unique container names.
+#[derive(Debug, Clone)]
+pub struct FakeDocker {
+    dir: PathBuf,
+}
+
+impl FakeDocker {
+    /// Calls [set_docker_binary] with `fake_docker_bin` (in integration tests
+    /// use `env!("CARGO_BIN_EXE_fake_docker")`) and sets
+    /// [FAKE_DOCKER_DIR_VAR] to `dir`. Anything left in `dir` from previous
+    /// runs is removed, so this should be called only once per process.
+    pub fn install(fake_docker_bin: impl AsRef<OsStr>, dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .stack_err_locationless(|| format!("FakeDocker::install -> clearing {dir:?}"))?;
+        }
+        for sub_dir in ["state", "images", "networks", "build_cache"] {
+            fs::create_dir_all(dir.join(sub_dir))
+                .stack_err_locationless(|| format!("FakeDocker::install -> creating {dir:?}"))?;
+        }
+        std::env::set_var(FAKE_DOCKER_DIR_VAR, &dir);
+        set_docker_binary(fake_docker_bin);
+        Ok(Self { dir })
+    }
+
+    /// Scripts what the container with `container_name` does
+    pub fn script(&self, container_name: &str, fake: &FakeContainer) -> Result<()> {
+        let json = serde_json::to_string(fake).stack()?;
+        fs::write(script_path(&self.dir, container_name), json).stack_err_locationless(|| {
+            format!("FakeDocker::script -> writing the script for \"{container_name}\"")
+        })
+    }
+
+    /// Makes the image `name_tag` present locally with the registry `digest`
+    /// ("sha256:..."), replacing any previous digest. Images pulled by the fake
+    /// have a digest derived from their name.
+    pub fn set_image_digest(&self, name_tag: &str, digest: &str) -> Result<()> {
+        fs::write(self.dir.join("images").join(fake_id(name_tag)), digest).stack_err_locationless(
+            || format!("FakeDocker::set_image_digest -> writing the image \"{name_tag}\""),
+        )
+    }
+
+    /// Returns the arguments of every `fake_docker` invocation so far, in
+    /// order
+    pub fn invocations(&self) -> Result<Vec<Vec<String>>> {
+        let log = match fs::read_to_string(self.dir.join("invocations.log")) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).stack_err_locationless(|| "FakeDocker::invocations"),
+        };
+        let mut res = vec![];
+        for line in log.lines() {
+            res.push(serde_json::from_str(line).stack_err_locationless(|| {
+                format!("FakeDocker::invocations -> could not parse line {line}")
+            })?);
+        }
+        Ok(res)
+    }
+
+    /// Returns the registry, username, and password of every `fake_docker
+    /// login` so far, in order
+    pub fn logins(&self) -> Result<Vec<(String, String, String)>> {
+        let log = match fs::read_to_string(self.dir.join("logins.log")) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).stack_err_locationless(|| "FakeDocker::logins"),
+        };
+        let mut res = vec![];
+        for line in log.lines() {
+            res.push(serde_json::from_str(line).stack_err_locationless(|| {
+                format!("FakeDocker::logins -> could not parse line {line}")
+            })?);
+        }
+        Ok(res)
+    }
+}
+
+/// The contents of the fake image file of a built image, which has no
+/// `RepoDigests`
+const BUILT_IMAGE: &str = "built";
+
+fn sha256_hex(s: &str) -> String {
+    Sha256::digest(s.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Returns the `RepoDigests` and `Id` of the fake image `name_tag`. The image
+/// file contains the digest set by [FakeDocker::set_image_digest], or is empty
+/// for a digest derived from `name_tag`.
+fn fake_image_digests(name_tag: &str, contents: &str) -> (Vec<String>, String) {
+    let repository = name_tag.split(['@', ':']).next().unwrap();
+    let repo_digests = match contents {
+        BUILT_IMAGE => vec![],
+        "" => vec![format!("{repository}@sha256:{}", sha256_hex(name_tag))],
+        digest => vec![format!("{repository}@{digest}")],
+    };
+    let id = format!("sha256:{}", sha256_hex(&format!("{name_tag} {contents}")));
+    (repo_digests, id)
+}
+
+fn script_path(dir: &Path, container_name: &str) -> PathBuf {
+    dir.join(format!("{container_name}.json"))
+}
+
+fn fake_id(container_name: &str) -> String {
+    container_name
+        .bytes()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+// `usize::is_multiple_of` needs Rust 1.87, which is above the MSRV
+#[allow(clippy::manual_is_multiple_of)]
+fn name_from_fake_id(id: &str) -> Option<String> {
+    if id.len() % 2 != 0 {
+        return None
+    }
+    let bytes = (0..id.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(id.get(i..(i + 2))?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// The entry point of the `fake_docker` binary, returns the exit code
+pub fn fake_docker_main() -> i32 {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match fake_docker(&args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("fake_docker {args:?} -> {e:?}");
+            1
+        }
+    }
+}
+
+fn fake_docker(args: &[String]) -> Result<i32> {
+    let dir = PathBuf::from(
+        std::env::var_os(FAKE_DOCKER_DIR_VAR)
+            .stack_err_locationless(|| format!("{FAKE_DOCKER_DIR_VAR} is not set"))?,
+    );
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("invocations.log"))
+        .stack()?;
+    // a single write so that parallel invocations do not interleave
+    log.write_all(format!("{}\n", serde_json::to_string(args).stack()?).as_bytes())
+        .stack()?;
+    let state_path = |id: &str| dir.join("state").join(id);
+    let labels_path = |id: &str| dir.join("state").join(format!("{id}.labels"));
+    let started_path = |id: &str| dir.join("state").join(format!("{id}.started"));
+    // when a container started without `--attach` exits, in milliseconds since the
+    // epoch
+    let exit_at_path = |id: &str| dir.join("state").join(format!("{id}.exit_at"));
+    let container_network_path = |id: &str| dir.join("state").join(format!("{id}.network"));
+    let now_millis = || -> Result<u64> {
+        Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .stack()?
+            .as_millis() as u64)
+    };
+    let diff_path = |id: &str| dir.join("state").join(format!("{id}.diff"));
+    // files written in the container by streaming into `exec -i`
+    let files_dir = |id: &str| dir.join("state").join(format!("{id}.files"));
+    // exists if the container is removed when it exits, see
+    // `FakeContainer::auto_remove`
+    let auto_remove_path = |id: &str| dir.join("state").join(format!("{id}.auto_remove"));
+    // removes all the state of a container, returning if it existed
+    let remove_container = |id: &str| -> bool {
+        let _ = fs::remove_file(labels_path(id));
+        let _ = fs::remove_file(started_path(id));
+        let _ = fs::remove_file(exit_at_path(id));
+        let _ = fs::remove_file(container_network_path(id));
+        let _ = fs::remove_file(diff_path(id));
+        let _ = fs::remove_file(auto_remove_path(id));
+        let _ = fs::remove_dir_all(files_dir(id));
+        fs::remove_file(state_path(id)).is_ok()
+    };
+    let mark_exited = |id: &str| -> Result<()> {
+        // the container may have been removed in the meantime
+        if !state_path(id).exists() {
+            return Ok(())
+        }
+        if auto_remove_path(id).exists() {
+            remove_container(id);
+        } else {
+            fs::write(state_path(id), "exited").stack()?;
+        }
+        Ok(())
+    };
+    // updates the state of a container started without `--attach`, returning if it
+    // has exited
+    let detached_exited = |id: &str| -> Result<bool> {
+        let Ok(exit_at) = fs::read_to_string(exit_at_path(id)) else {
+            return Ok(fs::read_to_string(state_path(id)).ok().as_deref() == Some("exited"))
+        };
+        if now_millis()? < exit_at.parse::<u64>().stack()? {
+            return Ok(false)
+        }
+        mark_exited(id)?;
+        Ok(true)
+    };
+    let file_path = |id: &str, path: &str| files_dir(id).join(fake_id(path));
+    let image_path = |name_tag: &str| dir.join("images").join(fake_id(name_tag));
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let network_path = |network_name: &str| dir.join("networks").join(network_name);
+    match args.as_slice() {
+        ["network", "create", .., network_name] => {
+            if network_path(network_name).exists() {
+                eprintln!(
+                    "Error response from daemon: network with name {network_name} already exists"
+                );
+                return Ok(1)
+            }
+            fs::write(network_path(network_name), "").stack()?;
+            if network_name.contains("slow_create") {
+                std::thread::sleep(Duration::from_secs(2));
+            }
+            println!("{}", fake_id(network_name));
+            Ok(0)
+        }
+        ["network", "rm", network_name] => {
+            let _ = fs::remove_file(network_path(network_name));
+            Ok(0)
+        }
+        ["network", "connect" | "disconnect", ..] => Ok(0),
+        ["build", rest @ ..] => {
+            // the `RUN` step is cached if a Dockerfile with the same contents was built
+            // before
+            let contents = match rest.iter().position(|arg| *arg == "--file") {
+                Some(i) => fs::read_to_string(rest[i + 1]).stack()?,
+                None => String::new(),
+            };
+            if contents.contains("unreachable_daemon") {
+                eprintln!(
+                    "ERROR: Cannot connect to the Docker daemon at unix:///var/run/docker.sock. \
+                     Is the docker daemon running?"
+                );
+                return Ok(1)
+            }
+            let hash = sha256_hex(&contents);
+            let cache_marker = dir.join("build_cache").join(&hash);
+            // a local cache (`type=local`) is a directory of such markers
+            let local_cache = |flag: &str, key: &str| {
+                let i = rest.iter().position(|arg| *arg == flag)?;
+                let path = rest[i + 1]
+                    .split(',')
+                    .find_map(|part| part.strip_prefix(key))?;
+                Some(PathBuf::from(path))
+            };
+            let cached = cache_marker.exists()
+                || local_cache("--cache-from", "src=").is_some_and(|src| src.join(&hash).exists());
+            fs::write(cache_marker, "").stack()?;
+            if let Some(dest) = local_cache("--cache-to", "dest=") {
+                fs::create_dir_all(&dest).stack()?;
+                fs::write(dest.join(&hash), "").stack()?;
+            }
+            if rest.contains(&"--progress=plain") {
+                eprint!(
+                    "#1 [internal] load build definition from Dockerfile\n#1 DONE 0.0s\n\n#2 \
+                     [1/2] FROM docker.io/library/fake:latest\n#2 CACHED\n\n#3 [2/2] RUN echo \
+                     fake\n"
+                );
+                if cached {
+                    eprintln!("#3 CACHED");
+                } else {
+                    eprint!("#3 0.105 fake\n#3 DONE 0.1s\n");
+                }
+            }
+            if let Some(i) = rest.iter().position(|arg| *arg == "-t") {
+                let path = image_path(rest[i + 1]);
+                if !path.exists() {
+                    fs::write(path, BUILT_IMAGE).stack()?;
+                }
+            }
+            Ok(0)
+        }
+        ["login", "--username", username, "--password-stdin", registry] => {
+            let mut password = String::new();
+            std::io::stdin().read_to_string(&mut password).stack()?;
+            let mut log = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join("logins.log"))
+                .stack()?;
+            let line = serde_json::to_string(&(registry, username, password)).stack()?;
+            log.write_all(format!("{line}\n").as_bytes()).stack()?;
+            println!("Login Succeeded");
+            Ok(0)
+        }
+        ["image", "inspect", name_tag] => {
+            // pinned references are always found
+            let contents = match name_tag.split_once('@') {
+                Some((_, digest)) => Some(digest.to_owned()),
+                None => fs::read_to_string(image_path(name_tag)).ok(),
+            };
+            if let Some(contents) = contents {
+                let (repo_digests, id) = fake_image_digests(name_tag, &contents);
+                let inspect = serde_json::json!([{
+                    "Id": id,
+                    "RepoTags": [name_tag],
+                    "RepoDigests": repo_digests,
+                }]);
+                println!("{inspect}");
+                Ok(0)
+            } else {
+                println!("[]");
+                eprintln!("Error response from daemon: No such image: {name_tag}");
+                Ok(1)
+            }
+        }
+        ["pull", name_tag] if name_tag.contains("nonexistent") => {
+            eprintln!(
+                "Error response from daemon: manifest for {name_tag} not found: manifest unknown"
+            );
+            Ok(1)
+        }
+        ["manifest", "inspect", name_tag] => {
+            if name_tag.contains("nonexistent") {
+                eprintln!("no such manifest: {name_tag}");
+                Ok(1)
+            } else {
+                println!("{{\"schemaVersion\": 2}}");
+                Ok(0)
+            }
+        }
+        ["pull", name_tag] if name_tag.contains("offline") => {
+            eprintln!(
+                "Error response from daemon: Get \"https://{name_tag}/v2/\": dial tcp: connect: \
+                 connection refused"
+            );
+            Ok(1)
+        }
+        ["pull", name_tag] => {
+            if name_tag.contains("slow_pull") {
+                std::thread::sleep(Duration::from_secs(2));
+            }
+            let path = image_path(name_tag);
+            if path.exists() {
+                println!("Status: Image is up to date for {name_tag}");
+            } else {
+                fs::write(path, "").stack()?;
+                println!("Status: Downloaded newer image for {name_tag}");
+            }
+            Ok(0)
+        }
+        ["version", "--format", "{{json .}}"] => {
+            println!(
+                "{{\"Client\":{{\"Platform\":{{\"Name\":\"Docker Engine - \
+                 Community\"}},\"Version\":\"27.0.0\",\"ApiVersion\":\"1.46\"}},\"Server\":{{\"\
+                 Platform\":{{\"Name\":\"Docker Engine - \
+                 Community\"}},\"Version\":\"27.0.0\",\"ApiVersion\":\"1.46\"}}}}"
+            );
+            Ok(0)
+        }
+        ["info", "--format", "{{json .}}"] => {
+            println!(
+                "{{\"ServerVersion\":\"27.0.0\",\"CgroupVersion\":\"2\",\"ExperimentalBuild\":\
+                 false,\"SecurityOptions\":[\"name=seccomp,profile=builtin\",\"name=cgroupns\"]}}"
+            );
+            Ok(0)
+        }
+        ["buildx", "version"] => {
+            println!("github.com/docker/buildx v0.0.0-fake_docker");
+            Ok(0)
+        }
+        ["logout", registry] => {
+            println!("Removing login credentials for {registry}");
+            Ok(0)
+        }
+        ["create", rest @ ..] => {
+            let container_name = rest
+                .iter()
+                .position(|arg| *arg == "--name")
+                .and_then(|i| rest.get(i + 1))
+                .stack_err_locationless(|| "`create` without `--name`")?;
+            let fake = load_script(&dir, container_name)?;
+            if fake.fail_create {
+                eprintln!(
+                    "Error response from daemon: fake_docker scripted a create failure for \
+                     \"{container_name}\""
+                );
+                return Ok(125)
+            }
+            if fake.transient_create_failures > 0 {
+                let count_path = dir
+                    .join("state")
+                    .join(format!("{container_name}.create_failures"));
+                let count = fs::read_to_string(&count_path)
+                    .ok()
+                    .and_then(|count| count.parse::<u32>().ok())
+                    .unwrap_or(0);
+                if count < fake.transient_create_failures {
+                    fs::write(&count_path, (count + 1).to_string()).stack()?;
+                    eprintln!(
+                        "Error response from daemon: failed to mount the overlay filesystem: \
+                         fake_docker scripted transient failure {} for \"{container_name}\"",
+                        count + 1
+                    );
+                    return Ok(125)
+                }
+            }
+            // like the implicit pull of docker, images with "nonexistent" in their name do
+            // not exist unless they are present locally
+            if let Some(image) = rest.iter().find(|arg| {
+                arg.contains("nonexistent")
+                    && (*arg != container_name)
+                    && !arg.starts_with(['-', '.', '/'])
+            }) {
+                if !image_path(image).exists() {
+                    eprintln!(
+                        "Unable to find image '{image}' locally\nError response from daemon: pull \
+                         access denied for {image}, repository does not exist or may require \
+                         'docker login'"
+                    );
+                    return Ok(125)
+                }
+            }
+            let id = fake_id(container_name);
+            // exited containers count as removed, like with `--rm`
+            if fs::read_to_string(state_path(&id)).is_ok_and(|state| state != "exited") {
+                eprintln!(
+                    "Error response from daemon: Conflict. The container name \
+                     \"/{container_name}\" is already in use by container \"{id}\". You have to \
+                     remove (or rename) that container to be able to reuse that name."
+                );
+                return Ok(125)
+            }
+            let mut labels = serde_json::Map::new();
+            for pair in rest.windows(2) {
+                if let ["--label" | "-l", label] = pair {
+                    let (key, value) = label.split_once('=').unwrap_or((label, ""));
+                    labels.insert(key.to_owned(), value.into());
+                }
+            }
+            fs::write(labels_path(&id), serde_json::to_string(&labels).stack()?).stack()?;
+            let network = rest
+                .iter()
+                .position(|arg| *arg == "--network")
+                .and_then(|i| rest.get(i + 1))
+                .copied()
+                .unwrap_or("fake");
+            fs::write(container_network_path(&id), network).stack()?;
+            if fake.auto_remove && rest.contains(&"--rm") {
+                fs::write(auto_remove_path(&id), "").stack()?;
+            }
+            fs::write(state_path(&id), "created").stack()?;
+            println!("{id}");
+            Ok(0)
+        }
+        ["start", rest @ ..] if !rest.contains(&"--attach") => {
+            let id = rest
+                .last()
+                .stack_err_locationless(|| "`start` without an ID")?;
+            let container_name = name_from_fake_id(id)
+                .stack_err_locationless(|| format!("`start` with a non fake ID {id}"))?;
+            if !state_path(id).exists() {
+                eprintln!("Error response from daemon: No such container: {id}");
+                return Ok(1)
+            }
+            let fake = load_script(&dir, &container_name)?;
+            let runs_for = fake
+                .label_updates
+                .iter()
+                .map(|(delay, ..)| *delay)
+                .chain(fake.stdout_chunks.iter().map(|(delay, _)| *delay))
+                .sum::<Duration>()
+                + fake.duration;
+            let now = now_millis()?;
+            fs::write(started_path(id), now.to_string()).stack()?;
+            fs::write(
+                exit_at_path(id),
+                (now + runs_for.as_millis() as u64).to_string(),
+            )
+            .stack()?;
+            fs::write(state_path(id), "running").stack()?;
+            println!("{id}");
+            Ok(0)
+        }
+        ["wait", id] => {
+            let container_name = name_from_fake_id(id)
+                .stack_err_locationless(|| format!("`wait` with a non fake ID {id}"))?;
+            loop {
+                if !state_path(id).exists() {
+                    eprintln!("Error response from daemon: No such container: {id}");
+                    return Ok(1)
+                }
+                if detached_exited(id)? {
+                    break
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            println!("{}", load_script(&dir, &container_name)?.exit_code);
+            Ok(0)
+        }
+        ["logs", "--follow", id] => {
+            let container_name = name_from_fake_id(id)
+                .stack_err_locationless(|| format!("`logs` with a non fake ID {id}"))?;
+            let (Ok(started), Ok(exit_at)) = (
+                fs::read_to_string(started_path(id)),
+                fs::read_to_string(exit_at_path(id)),
+            ) else {
+                eprintln!("Error response from daemon: No such container: {id}");
+                return Ok(1)
+            };
+            let fake = load_script(&dir, &container_name)?;
+            let sleep_until = |millis: u64| -> Result<()> {
+                std::thread::sleep(Duration::from_millis(millis.saturating_sub(now_millis()?)));
+                Ok(())
+            };
+            let mut at = started.parse::<u64>().stack()?;
+            for (delay, ..) in &fake.label_updates {
+                at += delay.as_millis() as u64;
+            }
+            for (delay, chunk) in &fake.stdout_chunks {
+                at += delay.as_millis() as u64;
+                sleep_until(at)?;
+                print!("{chunk}");
+                std::io::stdout().flush().stack()?;
+            }
+            sleep_until(exit_at.parse::<u64>().stack()?)?;
+            print!("{}", fake.stdout);
+            eprint!("{}", fake.stderr);
+            std::io::stdout().flush().stack()?;
+            std::io::stderr().flush().stack()?;
+            detached_exited(id)?;
+            Ok(0)
+        }
+        ["inspect", "--format", "{{.State.ExitCode}}", id] => {
+            let container_name = name_from_fake_id(id)
+                .stack_err_locationless(|| format!("`inspect` with a non fake ID {id}"))?;
+            if !state_path(id).exists() {
+                eprintln!("Error: No such object: {id}");
+                return Ok(1)
+            }
+            if detached_exited(id)? {
+                println!("{}", load_script(&dir, &container_name)?.exit_code);
+            } else {
+                println!("0");
+            }
+            Ok(0)
+        }
+        ["start", rest @ ..] => {
+            let id = rest
+                .last()
+                .stack_err_locationless(|| "`start` without an ID")?;
+            let container_name = name_from_fake_id(id)
+                .stack_err_locationless(|| format!("`start` with a non fake ID {id}"))?;
+            if !state_path(id).exists() {
+                eprintln!("Error response from daemon: No such container: {id}");
+                return Ok(1)
+            }
+            let fake = load_script(&dir, &container_name)?;
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).stack()?;
+            fs::write(started_path(id), since_epoch.as_millis().to_string()).stack()?;
+            fs::write(state_path(id), "running").stack()?;
+            let network = fs::read_to_string(container_network_path(id)).unwrap_or_default();
+            if let Some(addr) = fake.connect_to {
+                let res = if network == "none" {
+                    Err("Network is unreachable".to_owned())
+                } else {
+                    TcpStream::connect_timeout(&addr, Duration::from_secs(5))
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                };
+                if let Err(e) = res {
+                    eprintln!("connect to {addr} failed: {e}");
+                    mark_exited(id)?;
+                    return Ok(1)
+                }
+                println!("connected to {addr}");
+            }
+            // connections are accepted by the backlog of the listener
+            let _listener = match fake.host_port {
+                Some(port) if network == "host" => {
+                    Some(TcpListener::bind((Ipv4Addr::LOCALHOST, port)).stack()?)
+                }
+                _ => None,
+            };
+            for (delay, key, value) in &fake.label_updates {
+                std::thread::sleep(*delay);
+                let mut labels: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_str(&fs::read_to_string(labels_path(id)).stack()?).stack()?;
+                labels.insert(key.clone(), value.as_str().into());
+                fs::write(labels_path(id), serde_json::to_string(&labels).stack()?).stack()?;
+            }
+            for (delay, chunk) in &fake.stdout_chunks {
+                std::thread::sleep(*delay);
+                print!("{chunk}");
+                std::io::stdout().flush().stack()?;
+            }
+            std::thread::sleep(fake.duration);
+            print!("{}", fake.stdout);
+            eprint!("{}", fake.stderr);
+            std::io::stdout().flush().stack()?;
+            std::io::stderr().flush().stack()?;
+            mark_exited(id)?;
+            Ok(fake.exit_code)
+        }
+        ["run", rest @ ..] => {
+            // only `echo` is supported
+            match rest.iter().position(|arg| *arg == "echo") {
+                Some(i) => {
+                    println!("{}", rest[(i + 1)..].join(" "));
+                    Ok(0)
+                }
+                None => {
+                    eprintln!("fake_docker: `run` only supports `echo`");
+                    Ok(127)
+                }
+            }
+        }
+        ["exec", rest @ ..] if !rest.is_empty() => {
+            let (id, argv) = match rest {
+                ["-i", id, argv @ ..] => (*id, argv),
+                [id, argv @ ..] => (*id, argv),
+                [] => unreachable!(),
+            };
+            let container_name = name_from_fake_id(id)
+                .stack_err_locationless(|| format!("`exec` with a non fake ID {id}"))?;
+            detached_exited(id)?;
+            if fs::read_to_string(state_path(id)).ok().as_deref() != Some("running") {
+                eprintln!("Error response from daemon: container {id} is not running");
+                return Ok(1)
+            }
+            let fake = load_script(&dir, &container_name)?;
+            let started = fs::read_to_string(started_path(id))
+                .stack()?
+                .parse::<u64>()
+                .stack()?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).stack()?;
+            let running_for = now.saturating_sub(Duration::from_millis(started));
+            if running_for >= fake.exec_ready_after {
+                let write_stdin = |path: &str| -> Result<u64> {
+                    fs::create_dir_all(files_dir(id)).stack()?;
+                    let mut file = fs::File::create(file_path(id, path)).stack()?;
+                    std::io::copy(&mut std::io::stdin(), &mut file).stack()
+                };
+                if container_name.contains("distroless") && argv.first() == Some(&"sh") {
+                    eprintln!(
+                        "OCI runtime exec failed: exec failed: unable to start container process: \
+                         exec: \"sh\": executable file not found in $PATH: unknown"
+                    );
+                    return Ok(126)
+                }
+                match argv {
+                    ["echo", words @ ..] => println!("{}", words.join(" ")),
+                    // the script of `ContainerNetwork::stream_to_file`
+                    ["sh", "-c", script, "sh", path, _mode] if script.starts_with("cat >") => {
+                        println!("{}", write_stdin(path)?);
+                    }
+                    ["cp", "/dev/stdin", path] => {
+                        write_stdin(path)?;
+                    }
+                    ["cat", path] | ["sha256sum", path] => {
+                        let Ok(mut file) = fs::File::open(file_path(id, path)) else {
+                            eprintln!("{}: {path}: No such file or directory", argv[0]);
+                            return Ok(1)
+                        };
+                        if argv[0] == "cat" {
+                            std::io::copy(&mut file, &mut std::io::stdout().lock()).stack()?;
+                        } else {
+                            let mut hasher = Sha256::new();
+                            std::io::copy(&mut file, &mut hasher).stack()?;
+                            let digest: String = hasher
+                                .finalize()
+                                .iter()
+                                .map(|byte| format!("{byte:02x}"))
+                                .collect();
+                            println!("{digest}  {path}");
+                        }
+                    }
+                    // resolving the other running containers by their hostnames
+                    ["getent", "hosts", host] => {
+                        if fs::read_to_string(state_path(&fake_id(host)))
+                            .ok()
+                            .as_deref()
+                            != Some("running")
+                        {
+                            return Ok(2)
+                        }
+                        println!("172.18.0.2      {host}");
+                    }
+                    // recorded for `diff`
+                    ["touch", paths @ ..] => {
+                        let mut diff = fs::OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(diff_path(id))
+                            .stack()?;
+                        for path in paths {
+                            diff.write_all(format!("A {path}\n").as_bytes()).stack()?;
+                        }
+                    }
+                    _ => (),
+                }
+                Ok(0)
+            } else {
+                eprintln!("fake_docker: exec is not ready yet");
+                Ok(1)
+            }
+        }
+        ["logs", id] => {
+            let container_name = name_from_fake_id(id)
+                .stack_err_locationless(|| format!("`logs` with a non fake ID {id}"))?;
+            if !state_path(id).exists() {
+                eprintln!("Error response from daemon: No such container: {id}");
+                return Ok(1)
+            }
+            // only what the container prints before its `duration`, if it has started
+            if started_path(id).exists() {
+                let fake = load_script(&dir, &container_name)?;
+                for (_, chunk) in &fake.stdout_chunks {
+                    print!("{chunk}");
+                }
+            }
+            Ok(0)
+        }
+        ["diff", id] => {
+            if !state_path(id).exists() {
+                eprintln!("Error response from daemon: No such container: {id}");
+                return Ok(1)
+            }
+            print!("{}", fs::read_to_string(diff_path(id)).unwrap_or_default());
+            Ok(0)
+        }
+        ["rm", rest @ ..] => {
+            let mut code = 0;
+            for id in rest.iter().filter(|arg| !arg.starts_with('-')) {
+                if let Some(container_name) = name_from_fake_id(id) {
+                    if load_script(&dir, &container_name)?.fail_remove {
+                        eprintln!(
+                            "Error response from daemon: fake_docker scripted a removal failure \
+                             for \"{container_name}\""
+                        );
+                        code = 1;
+                        continue
+                    }
+                }
+                if !remove_container(id) {
+                    eprintln!("Error response from daemon: No such container: {id}");
+                    code = 1;
+                }
+            }
+            Ok(code)
+        }
+        ["inspect", ids @ ..] => {
+            let mut code = 0;
+            let mut v = vec![];
+            for id in ids {
+                let _ = detached_exited(id);
+                let (Ok(status), Some(container_name)) =
+                    (fs::read_to_string(state_path(id)), name_from_fake_id(id))
+                else {
+                    eprintln!("Error: No such object: {id}");
+                    code = 1;
+                    continue
+                };
+                let fake = load_script(&dir, &container_name)?;
+                let exit_code = if status == "exited" {
+                    fake.exit_code
+                } else {
+                    0
+                };
+                let network = fs::read_to_string(container_network_path(id))
+                    .unwrap_or_else(|_| "fake".to_owned());
+                let started = fs::read_to_string(started_path(id))
+                    .ok()
+                    .and_then(|started| started.parse::<u64>().ok());
+                let now = now_millis()?;
+                // like docker, the address is there as soon as `start` is called
+                let has_ip_addr = fake.ip_addr_after.is_zero()
+                    || ((status == "running")
+                        && started.is_some_and(|started| {
+                            Duration::from_millis(now.saturating_sub(started)) >= fake.ip_addr_after
+                        }));
+                // there is no address on the host or none networks
+                let ip_addr = match fake.ip_addr {
+                    _ if (network == "host") || (network == "none") || !has_ip_addr => {
+                        String::new()
+                    }
+                    Some(ip_addr) => ip_addr.to_string(),
+                    None => "172.18.0.2".to_owned(),
+                };
+                let labels: serde_json::Value = fs::read_to_string(labels_path(id))
+                    .ok()
+                    .and_then(|labels| serde_json::from_str(&labels).ok())
+                    .unwrap_or_default();
+                v.push(serde_json::json!({
+                    "Id": id,
+                    "Name": format!("/{container_name}"),
+                    "Config": {
+                        "Labels": labels,
+                    },
+                    "State": {
+                        "Status": status,
+                        "Running": status == "running",
+                        "ExitCode": exit_code,
+                    },
+                    "NetworkSettings": {
+                        "Networks": {
+                            network: {
+                                "IPAddress": ip_addr,
+                            },
+                        },
+                    },
+                }));
+            }
+            println!("{}", serde_json::to_string_pretty(&v).stack()?);
+            Ok(code)
+        }
+        _ => {
+            eprintln!("fake_docker does not support {args:?}");
+            Ok(1)
+        }
+    }
+}
+
+fn load_script(dir: &Path, container_name: &str) -> Result<FakeContainer> {
+    match fs::read_to_string(script_path(dir, container_name)) {
+        Ok(json) => serde_json::from_str(&json).stack_err_locationless(|| {
+            format!("could not parse the script for \"{container_name}\"")
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FakeContainer::default()),
+        Err(e) => Err(e).stack(),
+    }
+}
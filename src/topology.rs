@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use stacked_errors::{Result, StackableErr};
+use uuid::Uuid;
+
+use crate::{
+    docker::{final_names, Container, ContainerNetwork, ReadinessProbe, ResourceKind},
+    validation::published_ports,
+    FileOptions,
+};
+
+/// The path inside the container that [ContainerNetwork::inject_topology]
+/// mounts the topology file at
+pub const TOPOLOGY_FILE: &str = "/super_orchestrator.topology.json";
+
+/// The environment variable that [ContainerNetwork::inject_topology] sets to
+/// [TOPOLOGY_FILE], which is read by [load_topology](crate::load_topology)
+pub const TOPOLOGY_ENV_VAR: &str = "SUPER_ORCHESTRATOR_TOPOLOGY";
+
+/// A container of a [NetworkTopology]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopologyEntry {
+    /// The logical name of the container in the `ContainerNetwork`
+    pub name: String,
+    /// The final name of the docker container, which other containers on the
+    /// network can resolve through the docker DNS
+    pub container_name: String,
+    /// The final hostname that the container is created with
+    pub host_name: String,
+    /// The container side ports that are declared through `-p`/`--publish`
+    /// `create_args` or a TCP or HTTP readiness probe, sorted and deduplicated
+    pub ports: Vec<u16>,
+    /// The [Container::labels]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl TopologyEntry {
+    fn new(container: &Container, uuid: Uuid) -> Self {
+        let mut ports: Vec<u16> = published_ports(&container.create_args)
+            .filter_map(|spec| {
+                let spec = spec.split('/').next().unwrap();
+                spec.rsplit(':').next().unwrap().parse().ok()
+            })
+            .chain(
+                container
+                    .readiness
+                    .as_ref()
+                    .and_then(|readiness| match readiness.probe {
+                        ReadinessProbe::TcpPort(port) | ReadinessProbe::HttpGet { port, .. } => {
+                            Some(port)
+                        }
+                        ReadinessProbe::Exec(_) | ReadinessProbe::LogLine(_) => None,
+                    }),
+            )
+            .collect();
+        ports.sort_unstable();
+        ports.dedup();
+        let (container_name, host_name) = final_names(container, uuid);
+        Self {
+            name: container.name.clone(),
+            container_name,
+            host_name,
+            ports,
+            labels: container.labels.iter().cloned().collect(),
+        }
+    }
+}
+
+/// A summary of the containers of a `ContainerNetwork` for entrypoints to
+/// discover their peers, see [ContainerNetwork::topology]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkTopology {
+    pub network_name: String,
+    pub uuid: String,
+    /// The containers sorted by name
+    pub containers: Vec<TopologyEntry>,
+}
+
+impl NetworkTopology {
+    /// Returns the container with the logical `name`
+    pub fn get(&self, name: &str) -> Option<&TopologyEntry> {
+        self.containers.iter().find(|entry| entry.name == name)
+    }
+
+    /// Returns the containers with the label `key` set to `value`
+    pub fn with_label<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+    ) -> impl Iterator<Item = &'a TopologyEntry> {
+        self.containers
+            .iter()
+            .filter(move |entry| entry.labels.get(key).map(|v| v.as_str()) == Some(value))
+    }
+}
+
+impl ContainerNetwork {
+    /// Returns the [NetworkTopology] of all the containers added to the
+    /// network, whether or not they are active. Containers that are not
+    /// attached to the network because of their [Container::network_mode] are
+    /// left out.
+    pub fn topology(&self) -> NetworkTopology {
+        NetworkTopology {
+            network_name: self.network_name().to_owned(),
+            uuid: self.uuid_as_string(),
+            containers: self
+                .set
+                .values()
+                .filter(|state| state.container.network_mode.is_default())
+                .map(|state| TopologyEntry::new(&state.container, self.uuid()))
+                .collect(),
+        }
+    }
+
+    /// If set, [ContainerNetwork::run] writes the JSON of the
+    /// [ContainerNetwork::topology] to a file in the log directory before
+    /// creating any containers, and every container it creates has the file
+    /// mounted read-only at [TOPOLOGY_FILE] with the [TOPOLOGY_ENV_VAR]
+    /// environment variable pointing to it. Entrypoints can then use
+    /// [load_topology](crate::load_topology). The file is rewritten in place
+    /// by later `run`s, so containers from earlier runs also see containers
+    /// added since.
+    pub fn inject_topology(&mut self, inject_topology: bool) -> &mut Self {
+        self.inject_topology = inject_topology;
+        self
+    }
+
+    /// Writes the topology file if `inject_topology` is set, returning the
+    /// canonicalized path
+    pub(crate) async fn write_topology_file(&mut self) -> Result<Option<String>> {
+        if !self.inject_topology {
+            return Ok(None)
+        }
+        let path = FileOptions::write2(
+            &self.log_dir,
+            format!("{}.topology.json", self.network_name()),
+        )
+        .preacquire()
+        .await
+        .stack_err_locationless(|| {
+            "ContainerNetwork::run -> could not acquire the topology file in the log directory"
+        })?;
+        let json = serde_json::to_string_pretty(&self.topology()).stack()?;
+        tokio::fs::write(&path, json)
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::run -> when writing the topology file {path:?}")
+            })?;
+        let path = tokio::fs::canonicalize(&path)
+            .await
+            .stack_err_locationless(|| {
+                format!("ContainerNetwork::run -> when canonicalizing the topology file {path:?}")
+            })?;
+        let path = path.to_string_lossy().into_owned();
+        self.resources.record_once(ResourceKind::TempFile, &path);
+        Ok(Some(path))
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use stacked_errors::{Error, Result, StackableErr};
+use tempfile::TempDir;
+use tracing::warn;
+
+use crate::{
+    ctrlc_init,
+    docker::{Container, ContainerNetwork, TerminateOutcome},
+    misc::emit_crate_event,
+    CommandResult, CrateEvent,
+};
+
+// if `up` has already tried to install the ctrl-c handler
+static CTRLC_HOOKED: AtomicBool = AtomicBool::new(false);
+
+/// Options for [up], every option has a workable default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpOptions {
+    /// The network name, which always gets a UUID suffix. This is
+    /// "super_orchestrator" by default.
+    pub network_name: String,
+    /// Where ".log" log files are written. If this is `None` (the default), a
+    /// temporary directory is used which is removed by
+    /// [RunningEnvironment::down].
+    pub log_dir: Option<String>,
+    /// Where ".tmp.dockerfile" files are written for `Dockerfile::Contents`.
+    /// If this is `None` (the default), a temporary directory is used.
+    pub dockerfile_write_dir: Option<String>,
+    /// Sets [ContainerNetwork::internal], `false` by default
+    pub internal: bool,
+    /// Sets `debug_build` and `debug_create` on the network, `false` by
+    /// default
+    pub debug: bool,
+    /// If [ctrlc_init](crate::ctrlc_init) should be called so that ctrl-c
+    /// interrupts the waits, `true` by default
+    pub ctrlc: bool,
+    /// How long to wait for the containers with probes set by
+    /// [Container::readiness] to become ready, 2 minutes by default
+    pub ready_timeout: Duration,
+}
+
+impl Default for UpOptions {
+    fn default() -> Self {
+        Self {
+            network_name: "super_orchestrator".to_owned(),
+            log_dir: None,
+            dockerfile_write_dir: None,
+            internal: false,
+            debug: false,
+            ctrlc: true,
+            ready_timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+impl UpOptions {
+    /// The default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `network_name`
+    pub fn network_name(mut self, network_name: impl AsRef<str>) -> Self {
+        self.network_name = network_name.as_ref().to_owned();
+        self
+    }
+
+    /// Sets `log_dir`
+    pub fn log_dir(mut self, log_dir: impl AsRef<str>) -> Self {
+        self.log_dir = Some(log_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `dockerfile_write_dir`
+    pub fn dockerfile_write_dir(mut self, dockerfile_write_dir: impl AsRef<str>) -> Self {
+        self.dockerfile_write_dir = Some(dockerfile_write_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `internal`
+    pub fn internal(mut self, internal: bool) -> Self {
+        self.internal = internal;
+        self
+    }
+
+    /// Sets `debug`
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Sets `ctrlc`
+    pub fn ctrlc(mut self, ctrlc: bool) -> Self {
+        self.ctrlc = ctrlc;
+        self
+    }
+
+    /// Sets `ready_timeout`
+    pub fn ready_timeout(mut self, ready_timeout: Duration) -> Self {
+        self.ready_timeout = ready_timeout;
+        self
+    }
+}
+
+/// Brings up `containers` in a new `ContainerNetwork` with one call, like
+/// `docker compose up`. The containers are run, and the ones with probes set
+/// by [Container::readiness] are waited on until they are ready. If anything
+/// fails, everything is torn down before the error is returned, except for a
+/// temporary log directory which is kept for the error to point to.
+///
+/// ```no_run
+/// use stacked_errors::{Result, StackableErr};
+/// use super_orchestrator::docker::{up, Container, Dockerfile, UpOptions};
+///
+/// async fn test() -> Result<()> {
+///     let env = up(
+///         vec![
+///             Container::new("server", Dockerfile::name_tag("nginx:1.27")),
+///             Container::new("client", Dockerfile::name_tag("alpine:3.20"))
+///                 .entrypoint_args(["sleep", "infinity"]),
+///         ],
+///         UpOptions::new(),
+///     )
+///     .await
+///     .stack()?;
+///     let url = env.url_for("server", 80).stack()?;
+///     let comres = env.exec("client", ["wget", "-qO-", &url]).await.stack()?;
+///     env.down().await.stack()?;
+///     comres.assert_success().stack()
+/// }
+/// ```
+pub async fn up(containers: Vec<Container>, options: UpOptions) -> Result<RunningEnvironment> {
+    if options.ctrlc && !CTRLC_HOOKED.swap(true, Ordering::SeqCst) {
+        // fails if the user already set a handler, which is fine
+        if let Err(e) = ctrlc_init() {
+            warn!("up -> could not set the ctrl-c handler, continuing without it: {e:?}");
+        }
+    }
+    let temp_dir = if options.log_dir.is_none() || options.dockerfile_write_dir.is_none() {
+        Some(
+            tempfile::Builder::new()
+                .prefix("super_orchestrator_up_")
+                .tempdir()
+                .stack_err_locationless(|| "up -> could not create a temporary directory")?,
+        )
+    } else {
+        None
+    };
+    let temp_subdir = |name: &str| -> Result<String> {
+        let path = temp_dir.as_ref().unwrap().path().join(name);
+        std::fs::create_dir_all(&path).stack_err_locationless(|| {
+            format!("up -> could not create the temporary directory {path:?}")
+        })?;
+        Ok(path.to_string_lossy().into_owned())
+    };
+    let log_dir = match options.log_dir {
+        Some(ref log_dir) => log_dir.clone(),
+        None => temp_subdir("logs")?,
+    };
+    let dockerfile_write_dir = match options.dockerfile_write_dir {
+        Some(ref dockerfile_write_dir) => dockerfile_write_dir.clone(),
+        None => temp_subdir("dockerfiles")?,
+    };
+
+    let mut cn = ContainerNetwork::new_with_uuid(
+        &options.network_name,
+        Some(&dockerfile_write_dir),
+        &log_dir,
+    );
+    cn.debug_build(options.debug)
+        .debug_create(options.debug)
+        .internal(options.internal);
+    let mut probed = vec![];
+    for container in containers {
+        if container.readiness.is_some() {
+            probed.push(container.name.clone());
+        }
+        cn.add_container(container)
+            .stack_err_locationless(|| "up -> when adding the containers")?;
+    }
+    let mut env = RunningEnvironment {
+        cn,
+        log_dir,
+        temp_dir,
+        down: false,
+    };
+    if let Err(e) = env.cn.run_all().await {
+        env.cn.terminate_all().await;
+        env.keep_temp_dir();
+        env.down = true;
+        return Err(e.add_kind_locationless(format!(
+            "up -> when running the containers, the logs are in {:?}",
+            env.log_dir
+        )))
+    }
+    let probed: Vec<&str> = probed.iter().map(|name| name.as_str()).collect();
+    if !probed.is_empty() {
+        if let Err(e) = env.cn.wait_ready(&probed, options.ready_timeout).await {
+            env.cn.terminate_all().await;
+            env.keep_temp_dir();
+            env.down = true;
+            return Err(e.add_kind_locationless(format!(
+                "up -> when waiting for {probed:?} to be ready, the logs are in {:?}",
+                env.log_dir
+            )))
+        }
+    }
+    Ok(env)
+}
+
+/// The running containers returned by [up]. [RunningEnvironment::down] should
+/// be called to tear everything down, if this is dropped instead then a
+/// warning is issued and the `Drop` impl of the `ContainerNetwork` does a
+/// blocking teardown.
+#[derive(Debug)]
+pub struct RunningEnvironment {
+    cn: ContainerNetwork,
+    log_dir: String,
+    // dropped after `cn`, so that the containers are gone before the log files
+    temp_dir: Option<TempDir>,
+    down: bool,
+}
+
+impl RunningEnvironment {
+    /// The underlying `ContainerNetwork`
+    pub fn network(&self) -> &ContainerNetwork {
+        &self.cn
+    }
+
+    /// The underlying `ContainerNetwork`, for anything not covered by the
+    /// methods of `RunningEnvironment`
+    pub fn network_mut(&mut self) -> &mut ContainerNetwork {
+        &mut self.cn
+    }
+
+    /// The directory the ".log" log files are written to
+    pub fn log_dir(&self) -> &Path {
+        Path::new(&self.log_dir)
+    }
+
+    /// Returns "http://{host_name}:{port}" for the container with `name`, which
+    /// the other containers in the network can use to reach it. Returns a
+    /// [NotOnNetworkError](crate::docker::NotOnNetworkError) if the container
+    /// is not attached to the network.
+    pub fn url_for(&self, name: &str, port: u16) -> Result<String> {
+        self.cn
+            .check_on_network(name)
+            .stack_err_locationless(|| "RunningEnvironment::url_for")?;
+        let host_name = self.cn.host_name(name).stack_err_locationless(|| {
+            format!("RunningEnvironment::url_for -> could not find name \"{name}\"")
+        })?;
+        Ok(format!("http://{host_name}:{port}"))
+    }
+
+    /// Runs `argv` in the container with `name`, see [ContainerNetwork::exec]
+    pub async fn exec<I, S>(&self, name: &str, argv: I) -> Result<CommandResult>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.cn
+            .exec(name, argv)
+            .await
+            .stack_err_locationless(|| "RunningEnvironment::exec")
+    }
+
+    /// Returns the output of the container with `name` so far, see
+    /// [ContainerNetwork::logs]
+    pub async fn logs(&self, name: &str) -> Result<CommandResult> {
+        self.cn
+            .logs(name)
+            .await
+            .stack_err_locationless(|| "RunningEnvironment::logs")
+    }
+
+    // keeps the temporary directory instead of removing it on drop, so that the
+    // log files that an error points to still exist
+    fn keep_temp_dir(&mut self) {
+        if let Some(temp_dir) = self.temp_dir.take() {
+            let _ = temp_dir.keep();
+        }
+    }
+
+    /// Terminates the containers and removes the network and the temporary
+    /// directories
+    ///
+    /// # Errors
+    ///
+    /// If any container could not be terminated, everything else is still torn
+    /// down
+    pub async fn down(mut self) -> Result<()> {
+        self.down = true;
+        let outcomes = self.cn.try_terminate_all().await;
+        let mut res = Ok(());
+        for (name, outcome) in outcomes {
+            if let TerminateOutcome::RunnerError(e) = outcome {
+                res = Err(e.add_kind_locationless(format!(
+                    "RunningEnvironment::down -> when terminating \"{name}\""
+                )));
+            }
+        }
+        if let Some(temp_dir) = self.temp_dir.take() {
+            if let Err(e) = temp_dir.close() {
+                if res.is_ok() {
+                    res = Err(Error::from_kind_locationless(format!(
+                        "RunningEnvironment::down -> could not remove the temporary directory: {e}"
+                    )));
+                }
+            }
+        }
+        res
+    }
+}
+
+impl Drop for RunningEnvironment {
+    fn drop(&mut self) {
+        if !self.down {
+            warn!(
+                "A `RunningEnvironment` with network name \"{}\" was dropped without \
+                 `RunningEnvironment::down` being called",
+                self.cn.network_name()
+            );
+            emit_crate_event(|| CrateEvent::DroppedEnvironment {
+                network_name: self.cn.network_name().to_owned(),
+            });
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    docker::{final_names, Container, Dockerfile, NetworkMode, ReadinessProbe},
+    misc::emit_crate_event,
+    templates::{ip_dependencies, parse_template, placeholders, template_values, Placeholder},
+    CrateEvent,
+};
+
+/// A problem found by
+/// [ContainerNetwork::validate](crate::docker::ContainerNetwork::validate)
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ValidationFinding {
+    /// The name of the container the finding is about, `None` if it is about
+    /// the network
+    pub name: Option<String>,
+    pub message: String,
+}
+
+impl Display for ValidationFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref name) = self.name {
+            write!(f, "container \"{name}\": {}", self.message)
+        } else {
+            write!(f, "network: {}", self.message)
+        }
+    }
+}
+
+/// The findings of
+/// [ContainerNetwork::validate](crate::docker::ContainerNetwork::validate).
+/// Errors would make `ContainerNetwork::run` fail, warnings are only logged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub errors: Vec<ValidationFinding>,
+    pub warnings: Vec<ValidationFinding>,
+}
+
+impl ValidationReport {
+    /// If there are no errors
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub(crate) fn error(&mut self, name: Option<&str>, message: impl Into<String>) {
+        self.errors.push(ValidationFinding {
+            name: name.map(|name| name.to_owned()),
+            message: message.into(),
+        });
+    }
+
+    pub(crate) fn warning(&mut self, name: Option<&str>, message: impl Into<String>) {
+        self.warnings.push(ValidationFinding {
+            name: name.map(|name| name.to_owned()),
+            message: message.into(),
+        });
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for error in &self.errors {
+            writeln!(f, "error: {error}")?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The host part of a `-p`/`--publish` argument, `None` if docker chooses the
+/// host port
+fn published_host_port(spec: &str) -> Option<(String, String)> {
+    let (spec, protocol) = spec.split_once('/').unwrap_or((spec, "tcp"));
+    // "container", "host:container", or "ip:host:container", where IPv6
+    // addresses are in brackets
+    let (ip_and_host, _) = spec.rsplit_once(':')?;
+    let (ip, host) = ip_and_host.rsplit_once(':').unwrap_or(("", ip_and_host));
+    if host.is_empty() {
+        return None
+    }
+    let ip = if ip == "0.0.0.0" { "" } else { ip };
+    Some((ip.to_owned(), format!("{host}/{protocol}")))
+}
+
+/// The `-p`/`--publish` values of `create_args`
+pub(crate) fn published_ports(create_args: &[String]) -> impl Iterator<Item = &str> {
+    create_args.iter().enumerate().filter_map(|(i, arg)| {
+        if (arg == "-p") || (arg == "--publish") {
+            create_args.get(i + 1).map(|s| s.as_str())
+        } else {
+            arg.strip_prefix("--publish=")
+                .or_else(|| arg.strip_prefix("-p="))
+        }
+    })
+}
+
+/// The `create_args` flags that need the container to be attached to the
+/// network of the `ContainerNetwork`
+const NETWORK_FLAGS: [&str; 7] = [
+    "--network",
+    "--net",
+    "--network-alias",
+    "--ip",
+    "--ip6",
+    "--link",
+    "--hostname",
+];
+
+/// The [NETWORK_FLAGS] in `create_args`
+fn network_flags(create_args: &[String]) -> impl Iterator<Item = &str> {
+    create_args.iter().filter_map(|arg| {
+        let flag = arg.split('=').next().unwrap();
+        NETWORK_FLAGS.contains(&flag).then_some(flag)
+    })
+}
+
+/// Checks of the container definitions that do not touch the filesystem,
+/// these are done every time since they are cheap
+pub(crate) fn static_findings<'a>(
+    containers: impl IntoIterator<Item = &'a Container>,
+    uuid: Uuid,
+    report: &mut ValidationReport,
+) {
+    let mut container_names = BTreeMap::<String, &str>::new();
+    // host port to the ip and name of the container publishing it
+    let mut host_ports = BTreeMap::<String, Vec<(String, &str)>>::new();
+    for container in containers {
+        let name = container.name.as_str();
+        let (container_name, _) = final_names(container, uuid);
+        if let Some(other) = container_names.insert(container_name.clone(), name) {
+            report.error(
+                Some(name),
+                format!(
+                    "has the same `container_name` \"{container_name}\" as container \"{other}\""
+                ),
+            );
+        }
+
+        for spec in published_ports(&container.create_args) {
+            let Some((ip, host_port)) = published_host_port(spec) else {
+                continue
+            };
+            let publishers = host_ports.entry(host_port.clone()).or_default();
+            if let Some((_, other)) = publishers
+                .iter()
+                .find(|(other_ip, _)| other_ip.is_empty() || ip.is_empty() || (other_ip == &ip))
+            {
+                report.error(
+                    Some(name),
+                    format!(
+                        "publishes host port {host_port} which container \"{other}\" also \
+                         publishes"
+                    ),
+                );
+            }
+            publishers.push((ip, name));
+        }
+
+        let network_mode = container.network_mode;
+        if !network_mode.is_default() {
+            for flag in network_flags(&container.create_args) {
+                report.error(
+                    Some(name),
+                    format!(
+                        "has the `create_args` flag `{flag}`, which needs the network but the \
+                         `network_mode` is `NetworkMode::{network_mode:?}`"
+                    ),
+                );
+            }
+            if published_ports(&container.create_args).next().is_some() {
+                report.warning(
+                    Some(name),
+                    format!(
+                        "publishes ports, which docker discards with \
+                         `NetworkMode::{network_mode:?}`"
+                    ),
+                );
+            }
+            let port_probe = container.readiness.as_ref().is_some_and(|readiness| {
+                matches!(
+                    readiness.probe,
+                    ReadinessProbe::TcpPort(_) | ReadinessProbe::HttpGet { .. }
+                )
+            });
+            if port_probe && (network_mode == NetworkMode::None) {
+                report.error(
+                    Some(name),
+                    "has a TCP or HTTP readiness probe, but its ports cannot be reached with \
+                     `NetworkMode::None`",
+                );
+            }
+        }
+
+        let targets: Vec<&str> = container
+            .volumes
+            .iter()
+            .map(|(_, container_path)| container_path.split(':').next().unwrap())
+            .collect();
+        for (i, target) in targets.iter().enumerate() {
+            for other in &targets[(i + 1)..] {
+                if Path::new(target).starts_with(other) || Path::new(other).starts_with(target) {
+                    report.warning(
+                        Some(name),
+                        format!(
+                            "has overlapping volume targets \"{target}\" and \"{other}\", which \
+                             docker often handles incorrectly"
+                        ),
+                    );
+                }
+            }
+        }
+
+        if container.docker_socket.is_some() {
+            if let Dockerfile::NameTag(ref name_tag) = container.dockerfile {
+                if container.pinned_digest.is_none() {
+                    report.warning(
+                        Some(name),
+                        format!(
+                            "MOUNTS THE HOST DOCKER SOCKET with the image \"{name_tag}\" that has \
+                             no `pinned_digest`, anything published under the tag gets root \
+                             access to the host"
+                        ),
+                    );
+                }
+            }
+        }
+
+        if container.read_only_rootfs {
+            if let Some(ref workdir) = container.workdir {
+                let writable = targets
+                    .iter()
+                    .copied()
+                    .chain(container.tmpfs.iter().map(|s| s.split(':').next().unwrap()))
+                    .any(|path| Path::new(workdir).starts_with(path));
+                if !writable {
+                    emit_crate_event(|| CrateEvent::UnwritableWorkdir {
+                        name: name.to_owned(),
+                        workdir: workdir.clone(),
+                    });
+                    report.warning(
+                        Some(name),
+                        format!(
+                            "has `read_only_rootfs` set, and its `workdir` \"{workdir}\" is not \
+                             within a volume or tmpfs, so nothing can be written there"
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Checks of [ContainerNetwork::start_order](crate::docker::ContainerNetwork::start_order)
+/// against the containers of the network
+pub(crate) fn start_order_findings<'a>(
+    start_order: &[String],
+    container: impl Fn(&str) -> Option<&'a Container>,
+    report: &mut ValidationReport,
+) {
+    // listed names with their start groups
+    let mut listed = Vec::<(&str, u32)>::new();
+    for name in start_order {
+        let name = name.as_str();
+        let Some(container) = container(name) else {
+            report.error(
+                None,
+                format!("`start_order` lists \"{name}\", which is not in the network"),
+            );
+            continue
+        };
+        if listed.iter().any(|(other, _)| *other == name) {
+            report.error(Some(name), "is listed more than once in `start_order`");
+            continue
+        }
+        let group = container.start_group;
+        if let Some((other, other_group)) =
+            listed.iter().find(|(_, other_group)| *other_group > group)
+        {
+            report.error(
+                Some(name),
+                format!(
+                    "is listed in `start_order` after container \"{other}\", but its \
+                     `start_group` {group} is lower than {other_group}"
+                ),
+            );
+        }
+        listed.push((name, group));
+    }
+}
+
+/// Checks of the templates of the containers with `names`, see
+/// [ContainerNetwork::run](crate::docker::ContainerNetwork::run)
+pub(crate) fn template_findings<'a>(
+    names: &[String],
+    container: impl Fn(&str) -> Option<&'a Container>,
+    reserved_ports: &BTreeMap<String, u16>,
+    report: &mut ValidationReport,
+) {
+    for name in names {
+        let name = name.as_str();
+        let Some(this) = container(name) else {
+            continue
+        };
+        for value in template_values(this) {
+            if let Err(e) = parse_template(value) {
+                report.error(Some(name), format!("has an invalid {e}"));
+            }
+        }
+        for placeholder in placeholders(this) {
+            match placeholder {
+                Placeholder::Uuid => (),
+                Placeholder::Hostname(ref other) | Placeholder::Ip(ref other) => {
+                    let Some(other_container) = container(other) else {
+                        report.error(
+                            Some(name),
+                            format!(
+                                "has a template using container \"{other}\", which is not in the \
+                                 network"
+                            ),
+                        );
+                        continue
+                    };
+                    if !other_container.network_mode.is_default() {
+                        report.error(
+                            Some(name),
+                            format!(
+                                "has a template using container \"{other}\", which has the \
+                                 `network_mode` `NetworkMode::{:?}` and no hostname or IP address \
+                                 on the network",
+                                other_container.network_mode
+                            ),
+                        );
+                        continue
+                    }
+                    // the host can reach the IP addresses of the network, but only the network
+                    // can resolve the hostnames
+                    let reachable = match this.network_mode {
+                        NetworkMode::Default => true,
+                        NetworkMode::Host => matches!(placeholder, Placeholder::Ip(_)),
+                        NetworkMode::None => false,
+                    };
+                    if !reachable {
+                        report.error(
+                            Some(name),
+                            format!(
+                                "has a template using container \"{other}\", which cannot be \
+                                 resolved or reached with its `network_mode` `NetworkMode::{:?}`",
+                                this.network_mode
+                            ),
+                        );
+                        continue
+                    }
+                    if !matches!(placeholder, Placeholder::Ip(_)) {
+                        continue
+                    }
+                    if other == name {
+                        report.error(Some(name), "has a template using its own IP address");
+                    } else if other_container.start_group > this.start_group {
+                        report.error(
+                            Some(name),
+                            format!(
+                                "has a template using the IP address of container \"{other}\", \
+                                 which is in the higher `start_group` {}",
+                                other_container.start_group
+                            ),
+                        );
+                    }
+                }
+                Placeholder::Port(ref key) => {
+                    if !reserved_ports.contains_key(key) {
+                        report.error(
+                            Some(name),
+                            format!(
+                                "has a template using the reserved port \"{key}\", but the \
+                                 reserved ports are {:?} (see `ContainerNetwork::reserve_port`)",
+                                reserved_ports.keys().collect::<Vec<_>>()
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        // the IP address dependencies must not lead back to this container
+        let mut visited = BTreeSet::new();
+        let mut stack: Vec<String> = ip_dependencies(this)
+            .into_iter()
+            .filter(|other| other != name)
+            .collect();
+        while let Some(other) = stack.pop() {
+            if other == name {
+                report.error(
+                    Some(name),
+                    "has a cycle of `{{ip:<name>}}` template dependencies",
+                );
+                break
+            }
+            if !visited.insert(other.clone()) {
+                continue
+            }
+            if let Some(other_container) = container(&other) {
+                stack.extend(ip_dependencies(other_container));
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Tests of the error formatting of the `assertions` matchers
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    assertions::{expect_stderr, expect_stdout, OutputExpectation},
+    Command, CommandResult,
+};
+
+fn comres(stdout: &str) -> CommandResult {
+    CommandResult {
+        command: Command::new("test"),
+        status: None,
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: b"warning: something\n".to_vec(),
+    }
+}
+
+/// Returns the message of the error from a failed matcher
+fn failure(res: Result<&OutputExpectation>) -> Result<String> {
+    let e = res.err().stack_err(|| "the matcher did not fail")?;
+    Ok(format!("{e:?}"))
+}
+
+fn numbered_lines(n: usize) -> String {
+    (1..=n).map(|i| format!("line {i}\n")).collect()
+}
+
+#[test]
+fn contains() -> Result<()> {
+    let comres = comres(&numbered_lines(20));
+    expect_stdout(&comres)
+        .contains("line 7\nline 8")
+        .stack()?
+        .contains("line 20")
+        .stack()?;
+    expect_stderr(&comres).contains("warning").stack()?;
+    let e = failure(expect_stdout(&comres).contains("line 12 is missing"))?;
+    ensure!(e.contains(
+        "expected stdout to contain \"line 12 is missing\", the longest prefix found is \"line \
+         12\""
+    ));
+    // the excerpt is bounded and centered on the partial match
+    ensure!(e.contains("... (8 lines before)\n  9 | line 9\n"));
+    ensure!(e.contains(" 11 | line 11\n>12 | line 12\n 13 | line 13\n"));
+    ensure!(e.contains(" 15 | line 15\n... (5 lines after)"));
+    ensure!(!e.contains("line 8\n"));
+    ensure!(!e.contains("line 16"));
+
+    let e = failure(expect_stdout(&comres).contains("xyz"))?;
+    ensure!(e.contains("but no prefix of it was found:\n>1 | line 1\n"));
+    let e = failure(expect_stderr(&comres).contains("error"))?;
+    ensure!(e.contains("expected stderr to contain \"error\""));
+    Ok(())
+}
+
+#[test]
+fn excerpt_bounds() -> Result<()> {
+    let empty = comres("");
+    let e = failure(expect_stdout(&empty).contains("a"))?;
+    ensure!(e.contains("(the output is empty)"));
+
+    // the excerpt is moved to stay within the output
+    let comres = comres(&numbered_lines(10));
+    let e = failure(expect_stdout(&comres).contains("line 10 and more"))?;
+    ensure!(e.contains("... (3 lines before)\n  4 | line 4\n"));
+    ensure!(e.contains(">10 | line 10"));
+    ensure!(!e.contains("lines after"));
+
+    // long lines are cut off
+    let long = format!("{}\n", "x".repeat(1000));
+    let long = self::comres(&long);
+    let e = failure(expect_stdout(&long).contains("y"))?;
+    ensure!(e.contains(&format!(">1 | {}...", "x".repeat(160))));
+    ensure!(!e.contains(&"x".repeat(161)));
+    Ok(())
+}
+
+#[test]
+fn matches_regex() -> Result<()> {
+    let comres = comres("starting\nlistening on 127.0.0.1:8080\ndone\n");
+    expect_stdout(&comres)
+        .matches_regex(r"listening on [\d.]+:\d+")
+        .stack()?;
+    let e = failure(expect_stdout(&comres).matches_regex(r"listening on [\d.]+:\d+ \(tls\)"))?;
+    ensure!(e.contains("the longest matching prefix is \"listening on [\\\\d.]+:\\\\d+\""));
+    ensure!(e.contains(">2 | listening on 127.0.0.1:8080"));
+    let e = failure(expect_stdout(&comres).matches_regex("[z]+"))?;
+    ensure!(e.contains("but no prefix of it matched"));
+    let e = failure(expect_stdout(&comres).matches_regex("(unclosed"))?;
+    ensure!(e.contains("invalid regex \"(unclosed\""));
+    Ok(())
+}
+
+#[test]
+fn equals_normalized() -> Result<()> {
+    let comres = comres("\x1b[32mok\x1b[0m  \r\nsecond line\t\n\n\n");
+    expect_stdout(&comres)
+        .equals_normalized("ok\nsecond line")
+        .stack()?;
+    let e = failure(expect_stdout(&comres).equals_normalized("ok\nsecond\nthird"))?;
+    ensure!(e.contains(
+        "differs from the expected text first on line 2\nexpected: \"second\"\nactual:   \"second \
+         line\"\n 1 | ok\n>2 | second line"
+    ));
+    let e = failure(expect_stdout(&comres).equals_normalized("ok\nsecond line\nthird"))?;
+    ensure!(e.contains("line 3\nexpected: \"third\"\nactual:   (no line)"));
+    Ok(())
+}
+
+#[test]
+fn json_path() -> Result<()> {
+    let comres = comres(r#"{"peers": [{"addr": "10.0.0.1", "port": 80}], "ready": true}"#);
+    expect_stdout(&comres)
+        .json_path("$.ready", true)
+        .stack()?
+        .json_path("$.peers[0].port", 80)
+        .stack()?
+        .json_path(
+            "$.peers[0]",
+            serde_json::json!({"addr": "10.0.0.1", "port": 80}),
+        )
+        .stack()?;
+    let e = failure(expect_stdout(&comres).json_path("$.peers[0].addr", "10.0.0.2"))?;
+    ensure!(e.contains("the value at \"$.peers[0].addr\" in stdout is not the expected value"));
+    ensure!(e.contains("expected: \"10.0.0.2\"\nactual:\n>1 | \"10.0.0.1\""));
+    let e = failure(expect_stdout(&comres).json_path("$.peers[1].addr", "10.0.0.2"))?;
+    ensure!(e.contains(
+        "has no value at \"$.peers[1].addr\", the value at \"$.peers\" is:\n>1 | [\n 2 |   {"
+    ));
+    let e = failure(expect_stdout(&comres).json_path("peers", 1))?;
+    ensure!(e.contains("invalid path \"peers\""));
+
+    let not_json = self::comres("{\n\"a\": 1,\n\"b\" 2\n}");
+    let e = failure(expect_stdout(&not_json).json_path("$.a", 1))?;
+    ensure!(e.contains("stdout is not valid JSON"));
+    ensure!(e.contains(">3 | \"b\" 2"));
+    ensure_eq!(
+        expect_stdout(&not_json).output(),
+        "{\n\"a\": 1,\n\"b\" 2\n}"
+    );
+    Ok(())
+}
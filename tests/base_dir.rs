@@ -0,0 +1,104 @@
+//! Tests of the resolution of relative paths with `set_base_dir` and
+//! `ContainerNetwork::base_dir`, in a separate test binary because the base
+//! directory is process-global
+
+use std::{fs, path::Path, time::Duration};
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    acquire_dir_path, acquire_file_path, base_dir, clear_base_dir,
+    docker::{Container, ContainerNetwork, Dockerfile},
+    set_base_dir,
+    test_doubles::{FakeContainer, FakeDocker},
+    workspace_dir, FileOptions,
+};
+
+const TMP: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/base_dir");
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Creates `dir` with a "bd_logs" subdirectory and a "Dockerfile"
+fn make_dir(dir: &str) -> Result<()> {
+    fs::create_dir_all(format!("{dir}/bd_logs")).stack()?;
+    fs::write(format!("{dir}/Dockerfile"), "FROM fake:latest\n").stack()?;
+    Ok(())
+}
+
+fn container(name: &str) -> Container {
+    Container::new(name, Dockerfile::path("Dockerfile")).log(true)
+}
+
+#[tokio::test]
+async fn base_dir_precedence() -> Result<()> {
+    let fake =
+        FakeDocker::install(env!("CARGO_BIN_EXE_fake_docker"), format!("{TMP}/fake")).stack()?;
+    let global = format!("{TMP}/global");
+    let network = format!("{TMP}/network");
+    make_dir(&global).stack()?;
+    make_dir(&network).stack()?;
+    let global = fs::canonicalize(&global).stack()?;
+    let network = fs::canonicalize(&network).stack()?;
+
+    // `cargo test` runs in the manifest directory, which is the workspace root
+    // here
+    ensure_eq!(
+        workspace_dir().stack()?,
+        fs::canonicalize(env!("CARGO_MANIFEST_DIR")).stack()?
+    );
+
+    // the current working directory
+    ensure_eq!(base_dir(), None);
+    acquire_file_path("Cargo.toml").await.stack()?;
+    let e = format!("{:?}", acquire_dir_path("bd_logs").await.unwrap_err());
+    ensure!(e.contains("relative to the current working directory"));
+
+    // the global base directory
+    set_base_dir(&global);
+    ensure_eq!(
+        acquire_dir_path("bd_logs").await.stack()?,
+        global.join("bd_logs")
+    );
+    let e = format!("{:?}", acquire_file_path("Cargo.toml").await.unwrap_err());
+    ensure!(e.contains(&format!("relative to the base dir {global:?}")));
+    FileOptions::write_str("bd_logs/written.txt", "hello")
+        .await
+        .stack()?;
+    ensure_eq!(
+        fs::read_to_string(global.join("bd_logs/written.txt")).stack()?,
+        "hello"
+    );
+    // absolute paths are unaffected
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    acquire_file_path(&manifest).await.stack()?;
+
+    for name in ["bd_global", "bd_network"] {
+        fake.script(name, &FakeContainer::new().stdout("ran\n"))
+            .stack()?;
+    }
+    let mut cn = ContainerNetwork::new("fake_base_dir_global", None, "bd_logs");
+    cn.add_container(container("bd_global")).stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    ensure!(global.join("bd_logs/bd_global_stdout.log").is_file());
+
+    // the network base directory takes precedence
+    let mut cn = ContainerNetwork::new("fake_base_dir_network", None, "bd_logs");
+    cn.base_dir(network.to_str().unwrap());
+    cn.add_container(container("bd_network")).stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    ensure!(network.join("bd_logs/bd_network_stdout.log").is_file());
+    ensure!(!global.join("bd_logs/bd_network_stdout.log").exists());
+    let dockerfile = network.join("Dockerfile").to_str().unwrap().to_owned();
+    ensure!(fake
+        .invocations()
+        .stack()?
+        .iter()
+        .any(|args| args[0] == "build" && args.contains(&dockerfile)));
+
+    clear_base_dir();
+    ensure_eq!(base_dir(), None);
+    acquire_file_path("Cargo.toml").await.stack()?;
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Tests of `Command::stdout_binary` and `Command::stderr_binary`
+
+#![cfg(unix)]
+
+use sha2::{Digest, Sha256};
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::Command;
+
+const LEN: usize = 64 * 1024;
+
+fn hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[tokio::test]
+async fn binary_stdout() -> Result<()> {
+    // debug forwarding is requested but must be suppressed for the binary stream
+    let comres = Command::new("sh")
+        .args(["-c", &format!("head -c {LEN} /dev/urandom; echo text >&2")])
+        .debug(true)
+        .stdout_binary(true)
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    ensure_eq!(comres.stdout.len(), LEN);
+    ensure_eq!(comres.stderr, b"text\n");
+    let digest = hex(&comres.stdout);
+    ensure_eq!(comres.stdout_digest(), digest);
+    ensure_eq!(comres.stderr_digest(), hex(b"text\n"));
+
+    let debug = format!("{comres:?}");
+    ensure!(debug.contains(&format!("stdout: <binary, {LEN} bytes, sha256={digest}>")));
+    // stderr is still shown as text
+    ensure!(debug.contains("stderr: text\n"));
+    ensure!(!debug.contains('\u{FFFD}'));
+
+    // the file write round trips exactly
+    let dir = tempfile::tempdir().stack()?;
+    let path = dir.path().join("stdout.bin");
+    comres.write_stdout_to(&path).await.stack()?;
+    ensure_eq!(std::fs::read(&path).stack()?, comres.stdout);
+    Ok(())
+}
+
+#[tokio::test]
+async fn binary_assert_success() -> Result<()> {
+    let comres = Command::new("sh")
+        .args([
+            "-c",
+            &format!("head -c {LEN} /dev/urandom >&2; head -c 100 /dev/urandom; exit 1"),
+        ])
+        .stdout_binary(true)
+        .stderr_binary(true)
+        .run_to_completion()
+        .await
+        .stack()?;
+    let e = format!("{:?}", comres.assert_success().unwrap_err());
+    ensure!(e.contains(&format!(
+        "stdout: <binary, 100 bytes, sha256={}>",
+        hex(&comres.stdout)
+    )));
+    ensure!(e.contains(&format!(
+        "stderr: <binary, {LEN} bytes, sha256={}>",
+        hex(&comres.stderr)
+    )));
+    ensure!(!e.contains('\u{FFFD}'));
+    // the error stays small instead of containing the whole output
+    ensure!(e.len() < 4096);
+
+    let dir = tempfile::tempdir().stack()?;
+    let path = dir.path().join("stderr.bin");
+    comres.write_stderr_to(&path).await.stack()?;
+    ensure_eq!(std::fs::read(&path).stack()?, comres.stderr);
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! Tests of `parse_build_progress` and `BuildStats` against captured `docker
+//! build` output of the classic builder and BuildKit
+
+use std::time::Duration;
+
+use stacked_errors::{ensure, ensure_eq, Result};
+use super_orchestrator::{parse_build_progress, BuildProgress, BuildStats};
+
+const CLASSIC: &str = "Sending build context to Docker daemon  2.048kB
+Step 1/4 : FROM alpine:3.20
+ ---> 324bc02ae123
+Step 2/4 : RUN apk add --no-cache curl
+ ---> Using cache
+ ---> 8f2e5b1c7d3a
+Step 3/4 : COPY entrypoint.sh /entrypoint.sh
+ ---> 1a2b3c4d5e6f
+Step 4/4 : RUN exit 3
+ ---> Running in 9e8d7c6b5a4f
+The command '/bin/sh -c exit 3' returned a non-zero code: 3
+";
+
+const BUILDKIT: &str = r#"#0 building with "default" instance using docker driver
+
+#1 [internal] load build definition from Dockerfile
+#1 transferring dockerfile: 170B done
+#1 DONE 0.0s
+
+#2 [internal] load metadata for docker.io/library/rust:1.80
+#2 DONE 0.9s
+
+#3 [builder 1/3] FROM docker.io/library/rust:1.80@sha256:29fe4376919e25b7587a1063d7b521d9
+#3 CACHED
+
+#4 [builder 2/3] RUN cargo build --release
+#4 0.412    Compiling libc v0.2.155
+#4 12.03     Finished `release` profile [optimized] target(s) in 11.62s
+#4 DONE 12.2s
+
+#5 [builder 3/3] RUN exit 3
+#5 0.215 /bin/sh: 1: false: not found
+#5 ERROR: process "/bin/sh -c exit 3" did not complete successfully: exit code: 3
+------
+ > [builder 3/3] RUN exit 3:
+0.215 /bin/sh: 1: false: not found
+------
+Dockerfile:5
+--------------------
+ERROR: failed to solve: process "/bin/sh -c exit 3" did not complete successfully: exit code: 3
+"#;
+
+const BUILDKIT_CACHED: &str = r#"#0 building with "default" instance using docker driver
+
+#1 [internal] load build definition from Dockerfile
+#1 transferring dockerfile: 170B done
+#1 DONE 0.0s
+
+#2 [internal] load metadata for docker.io/library/rust:1.80
+#2 DONE 0.4s
+
+#3 [builder 1/3] FROM docker.io/library/rust:1.80@sha256:29fe4376919e25b7587a1063d7b521d9
+#3 DONE 0.0s
+
+#4 [builder 2/3] RUN cargo build --release
+#4 CACHED
+
+#5 [builder 3/3] RUN cp target/release/app /app
+#5 CACHED
+
+#6 exporting to image
+#6 exporting layers done
+#6 writing image sha256:5b1c7d3a8f2e done
+#6 DONE 0.0s
+"#;
+
+fn parse_all(output: &str) -> Vec<BuildProgress> {
+    output.lines().filter_map(parse_build_progress).collect()
+}
+
+#[test]
+fn classic_builder() -> Result<()> {
+    let progress = parse_all(CLASSIC);
+    ensure_eq!(progress.len(), 6);
+    let steps: Vec<_> = progress
+        .iter()
+        .filter_map(|progress| Some((progress.step?, progress.total?)))
+        .collect();
+    ensure_eq!(steps, vec![(1, 4), (2, 4), (3, 4), (4, 4)]);
+    ensure_eq!(progress[0].description, "FROM alpine:3.20");
+    ensure_eq!(progress[0].vertex, None);
+    // the "Using cache" line follows its step
+    ensure!(!progress[1].is_cached());
+    ensure!(progress[2].is_cached());
+    ensure!(progress[5].is_error());
+    ensure_eq!(
+        progress
+            .iter()
+            .filter(|progress| progress.is_error())
+            .count(),
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn buildkit() -> Result<()> {
+    let progress = parse_all(BUILDKIT);
+    let vertices: Vec<_> = progress.iter().map(|progress| progress.vertex).collect();
+    ensure_eq!(vertices, vec![
+        Some(0),
+        Some(1),
+        Some(1),
+        Some(1),
+        Some(2),
+        Some(2),
+        Some(3),
+        Some(3),
+        Some(4),
+        Some(4),
+        Some(5),
+        Some(5),
+        None
+    ]);
+    ensure_eq!(progress[1], BuildProgress {
+        vertex: Some(1),
+        step: None,
+        total: None,
+        description: "[internal] load build definition from Dockerfile".to_owned(),
+    });
+    ensure_eq!((progress[6].step, progress[6].total), (Some(1), Some(3)));
+    ensure!(progress[7].is_cached());
+    ensure_eq!(progress[8].description, "RUN cargo build --release");
+    ensure_eq!(progress[9].description, "DONE 12.2s");
+    ensure_eq!((progress[10].step, progress[10].total), (Some(3), Some(3)));
+    ensure!(progress[11].is_error());
+    ensure!(progress[12].is_error());
+    ensure!(progress[12]
+        .description
+        .starts_with("ERROR: failed to solve"));
+    ensure_eq!(
+        progress
+            .iter()
+            .filter(|progress| progress.is_cached())
+            .count(),
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn non_progress_lines() {
+    for line in [
+        "",
+        "#4 0.412    Compiling libc v0.2.155",
+        "#4 12.03     Finished `release` profile",
+        " ---> Running in 9e8d7c6b5a4f",
+        " ---> 8f2e5b1c7d3a",
+        "Successfully built 8f2e5b1c7d3a",
+        "------",
+        " > [builder 3/3] RUN exit 3:",
+        "#notanumber DONE",
+        "Step x/4 : RUN true",
+    ] {
+        assert_eq!(parse_build_progress(line), None, "{line:?}");
+    }
+}
+
+#[test]
+fn build_stats() -> Result<()> {
+    let stats = BuildStats::from_lines(CLASSIC.lines());
+    ensure_eq!(stats, BuildStats {
+        steps: 4,
+        cached: 2,
+        executed: 2,
+        duration: Duration::ZERO,
+        first_miss: Some("COPY entrypoint.sh /entrypoint.sh".to_owned()),
+    });
+    ensure_eq!(stats.cached_fraction(), Some(0.5));
+
+    let stats = BuildStats::from_lines(BUILDKIT.lines());
+    ensure_eq!((stats.steps, stats.cached, stats.executed), (3, 1, 2));
+    ensure_eq!(
+        stats.first_miss.as_deref(),
+        Some("RUN cargo build --release")
+    );
+    ensure!(!stats.is_fully_cached());
+
+    let stats = BuildStats::from_lines(BUILDKIT_CACHED.lines());
+    ensure_eq!((stats.steps, stats.cached, stats.executed), (3, 3, 0));
+    ensure_eq!(stats.first_miss, None);
+    ensure!(stats.is_fully_cached());
+    ensure_eq!(stats.cached_fraction(), Some(1.0));
+    ensure_eq!(stats.to_string(), "3/3 steps cached in 0.0ns");
+
+    // no steps at all
+    let stats = BuildStats::from_lines(["Successfully built 8f2e5b1c7d3a"]);
+    ensure_eq!(stats, BuildStats::default());
+    ensure_eq!(stats.cached_fraction(), None);
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Tests that the pre-tokenized `Command` constructors preserve the program
+//! and arguments exactly
+
+use std::ffi::{OsStr, OsString};
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::Command;
+
+const PROGRAM: &str = "printf";
+const ARGS: [&str; 5] = ["%s|", "with space", "ünïcödé ✓", "", "  "];
+
+fn check(command: &Command) -> Result<()> {
+    ensure_eq!(command.program, OsStr::new(PROGRAM));
+    ensure_eq!(command.args, ARGS.map(OsString::from));
+    Ok(())
+}
+
+#[test]
+fn constructors_preserve_args() -> Result<()> {
+    check(&Command::from_parts(PROGRAM, ARGS)).stack()?;
+    check(&Command::from_parts(
+        OsString::from(PROGRAM),
+        ARGS.map(OsString::from),
+    ))
+    .stack()?;
+    check(&Command::new_os_str(PROGRAM).args(ARGS)).stack()?;
+
+    let parts: Vec<&str> = [PROGRAM].into_iter().chain(ARGS).collect();
+    check(&Command::from(&parts[..])).stack()?;
+    let parts: Vec<String> = parts.iter().map(|s| s.to_string()).collect();
+    check(&Command::from(parts.clone())).stack()?;
+    check(&parts.into_iter().map(OsString::from).collect::<Command>()).stack()?;
+
+    // the program alone and nothing at all
+    let command = Command::from(&["true"][..]);
+    ensure_eq!(command.program, OsStr::new("true"));
+    ensure!(command.args.is_empty());
+    let command: Command = std::iter::empty::<OsString>().collect();
+    ensure!(command.program.is_empty() && command.args.is_empty());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn args_reach_the_program() -> Result<()> {
+    let comres = Command::from_parts(PROGRAM, ARGS)
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    ensure_eq!(
+        comres.stdout_as_utf8().stack()?,
+        "with space|ünïcödé ✓||  |"
+    );
+    Ok(())
+}
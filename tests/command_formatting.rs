@@ -0,0 +1,97 @@
+//! Tests of the formatting of `Command`s and the output limit of
+//! `assert_success` errors
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    fmt::Write,
+};
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    error_output_limit, set_error_output_limit, Command, CommandResult, DEFAULT_ERROR_OUTPUT_LIMIT,
+};
+
+/// Counts the allocations made by the current thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn formatting_does_not_allocate() -> Result<()> {
+    let command = Command::new("docker")
+        .args(["run", "--rm", "--network", "test_network", "alpine:3.20"])
+        .env("KEY", "value")
+        .cwd("/tmp");
+    let mut s = String::with_capacity(4096);
+    let before = allocations();
+    for _ in 0..8 {
+        s.clear();
+        write!(s, "{command:?}").stack()?;
+        write!(s, "{command:#?}").stack()?;
+        write!(s, "{}", command.display_compact()).stack()?;
+    }
+    ensure_eq!(allocations(), before);
+    ensure!(s.ends_with("docker run --rm --network test_network alpine:3.20"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn assert_success_output_limit() -> Result<()> {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    ensure_eq!(error_output_limit(), DEFAULT_ERROR_OUTPUT_LIMIT);
+    let comres = CommandResult {
+        command: Command::new("test"),
+        status: Some(ExitStatus::from_raw(1 << 8)),
+        stdout: "o".repeat(100_000).into_bytes(),
+        stderr: b"short stderr".to_vec(),
+    };
+    let e = format!("{:?}", comres.assert_success().unwrap_err());
+    ensure!(e.contains(&format!(
+        "stdout: {}... (truncated, 100000 bytes total)",
+        "o".repeat(DEFAULT_ERROR_OUTPUT_LIMIT)
+    )));
+    ensure!(!e.contains(&"o".repeat(DEFAULT_ERROR_OUTPUT_LIMIT + 1)));
+    ensure!(e.contains("stderr: short stderr\n"));
+
+    set_error_output_limit(10);
+    let e = format!("{:?}", comres.assert_success().unwrap_err());
+    ensure!(e.contains("stdout: oooooooooo... (truncated, 100000 bytes total)"));
+    ensure!(e.contains("stderr: short stde... (truncated, 12 bytes total)"));
+    ensure!(e.contains("unsuccessful"));
+    // the plain `Debug` impl is not limited
+    ensure!(format!("{comres:?}").contains(&"o".repeat(100_000)));
+    set_error_output_limit(DEFAULT_ERROR_OUTPUT_LIMIT);
+    Ok(())
+}
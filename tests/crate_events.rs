@@ -0,0 +1,128 @@
+//! Tests of `subscribe_crate_events`
+
+#![cfg(unix)]
+
+use std::time::Duration;
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    assert_no_crate_events, docker::ContainerNetwork, expect_crate_event, subscribe_crate_events,
+    Command, CrateEvent,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn dropped_runner() -> Result<()> {
+    let mut events = subscribe_crate_events();
+    let runner = Command::new("sleep 10.25").run().await.stack()?;
+    assert_no_crate_events!(events, CrateEvent::DroppedRunner { .. }).stack()?;
+    // deliberately leaked without being finished
+    drop(runner);
+    let event = expect_crate_event!(
+        events,
+        TIMEOUT,
+        CrateEvent::DroppedRunner { command } if command.contains("10.25")
+    )
+    .await
+    .stack()?;
+    ensure_eq!(event, CrateEvent::DroppedRunner {
+        command: "sleep 10.25".to_owned()
+    });
+
+    // a properly finished runner does not emit anything
+    let mut runner = Command::new("true").run().await.stack()?;
+    runner.wait_with_timeout(TIMEOUT).await.stack()?;
+    drop(runner);
+    assert_no_crate_events!(events, CrateEvent::DroppedRunner { .. }).stack()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn detached_runner() -> Result<()> {
+    let mut events = subscribe_crate_events();
+    let mut runner = Command::new("sleep 10.75").run().await.stack()?;
+    let pid = runner.pid().stack()?;
+    runner.detach();
+    drop(runner);
+    let runner = Command::new("sleep 10.8")
+        .suppress_drop_warning(true)
+        .run()
+        .await
+        .stack()?;
+    drop(runner);
+    assert_no_crate_events!(
+        events,
+        CrateEvent::DroppedRunner { command } if command.starts_with("sleep 10.")
+    )
+    .stack()?;
+    // the child is still killed on drop
+    let deadline = std::time::Instant::now() + TIMEOUT;
+    while std::path::Path::new(&format!("/proc/{pid}")).exists() {
+        ensure!(std::time::Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // the same without detaching warns
+    let runner = Command::new("sleep 10.85").run().await.stack()?;
+    drop(runner);
+    expect_crate_event!(
+        events,
+        TIMEOUT,
+        CrateEvent::DroppedRunner { command } if command == "sleep 10.85"
+    )
+    .await
+    .stack()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn nothing_to_wait_on() -> Result<()> {
+    let mut events = subscribe_crate_events();
+    let mut cn = ContainerNetwork::new("crate_events_empty", None, "./logs");
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let event = expect_crate_event!(
+        events,
+        TIMEOUT,
+        CrateEvent::NothingToWaitOn { network_name } if network_name == "crate_events_empty"
+    )
+    .await
+    .stack()?;
+    ensure!(matches!(event, CrateEvent::NothingToWaitOn { .. }));
+    Ok(())
+}
+
+#[tokio::test]
+async fn receiver_helpers() -> Result<()> {
+    let mut events = subscribe_crate_events();
+    // there are no events from this test, but other tests may emit some
+    let e = expect_crate_event!(
+        events,
+        Duration::from_millis(100),
+        CrateEvent::DroppedRunner { command } if command == "never emitted"
+    )
+    .await
+    .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(format!("{e:?}").contains("no matching event within"));
+    assert_no_crate_events!(
+        events,
+        CrateEvent::DroppedRunner { command } if command == "never emitted"
+    )
+    .stack()?;
+    ensure_eq!(events.lagged(), 0);
+
+    // events are only received after subscribing
+    let runner = Command::new("sleep 10.5").run().await.stack()?;
+    drop(runner);
+    let mut late = subscribe_crate_events();
+    assert_no_crate_events!(
+        late,
+        CrateEvent::DroppedRunner { command } if command == "sleep 10.5"
+    )
+    .stack()?;
+    let e = format!("{:?}", assert_no_crate_events!(events).unwrap_err());
+    ensure!(e.contains("unexpected events"));
+    ensure!(e.contains("sleep 10.5"));
+    Ok(())
+}
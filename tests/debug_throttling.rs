@@ -0,0 +1,85 @@
+//! Tests of `Command::debug_buffering` and `Command::max_debug_lines_per_sec`.
+//! The forwarding goes to the stdout of the test process, so the test reruns
+//! its own binary as a child to capture it.
+
+#![cfg(unix)]
+
+use std::time::Duration;
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{BufferPolicy, Command};
+
+const CHILD_VAR: &str = "SUPER_ORCHESTRATOR_DEBUG_THROTTLING_CHILD";
+const PREFIX: &str = "fast | ";
+const LINES: usize = 20_000;
+
+/// Only does something when run as the child of `fast_producer`
+#[tokio::test]
+async fn fast_producer_child() -> Result<()> {
+    if std::env::var_os(CHILD_VAR).is_none() {
+        return Ok(())
+    }
+    let comres = Command::new("seq")
+        .arg(LINES.to_string())
+        .stdout_debug(true)
+        .stdout_debug_line_prefix(Some(PREFIX.to_owned()))
+        .debug_buffering(Some(BufferPolicy {
+            max_delay: Duration::from_millis(50),
+            max_bytes: 4096,
+        }))
+        .max_debug_lines_per_sec(Some(100))
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    // the record still has everything
+    let stdout = comres.stdout_as_utf8().stack()?;
+    ensure_eq!(stdout.lines().count(), LINES);
+    ensure_eq!(stdout.lines().last(), Some(LINES.to_string().as_str()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn fast_producer() -> Result<()> {
+    let exe = std::env::current_exe().stack()?;
+    let comres = Command::new_os_str(exe)
+        .args([
+            "fast_producer_child",
+            "--exact",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .envs([(CHILD_VAR, "1")])
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    let stdout = comres.stdout_as_utf8().stack()?;
+    let forwarded: Vec<&str> = stdout
+        .lines()
+        // the first line follows the output of the test harness
+        .filter_map(|line| Some(line.split_once(PREFIX)?.1))
+        .collect();
+    let numbers: Vec<usize> = forwarded
+        .iter()
+        .filter_map(|line| line.parse().ok())
+        .collect();
+    // the first lines of the second are forwarded whole and in order
+    ensure!(numbers.len() >= 100);
+    ensure!(numbers.len() < LINES);
+    ensure!(numbers.windows(2).all(|w| w[0] < w[1]));
+    ensure_eq!(&numbers[..100], &(1..=100).collect::<Vec<_>>()[..]);
+    // everything else is accounted for by the markers
+    let suppressed: usize = forwarded
+        .iter()
+        .filter_map(|line| {
+            line.strip_prefix("\u{2026} ")?
+                .strip_suffix(" lines suppressed \u{2026}")?
+                .parse::<usize>()
+                .ok()
+        })
+        .sum();
+    ensure!(suppressed > 0);
+    ensure_eq!(numbers.len() + suppressed, LINES);
+    Ok(())
+}
@@ -0,0 +1,500 @@
+//! Tests of `EngineCapabilities::parse` against captured `version` and `info`
+//! output (trimmed to the relevant parts and pretty printed) of Docker and
+//! Podman
+
+use stacked_errors::{ensure, ensure_eq, Result};
+use super_orchestrator::docker_helpers::{Capability, EngineCapabilities, EngineFlavor};
+
+const DOCKER_24_VERSION: &str = r#"{
+  "Client": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Version": "24.0.7",
+    "ApiVersion": "1.43",
+    "DefaultAPIVersion": "1.43",
+    "GitCommit": "afdd53b",
+    "GoVersion": "go1.20.10",
+    "Os": "linux",
+    "Arch": "amd64",
+    "BuildTime": "Thu Oct 26 09:07:41 2023",
+    "Context": "default"
+  },
+  "Server": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Components": [
+      {
+        "Name": "Engine",
+        "Version": "24.0.7",
+        "Details": {
+          "ApiVersion": "1.43",
+          "Arch": "amd64",
+          "BuildTime": "Thu Oct 26 09:07:41 2023",
+          "Experimental": "false",
+          "GitCommit": "311b9ff",
+          "GoVersion": "go1.20.10",
+          "KernelVersion": "6.5.0-14-generic",
+          "MinAPIVersion": "1.12",
+          "Os": "linux"
+        }
+      },
+      {
+        "Name": "containerd",
+        "Version": "1.6.26",
+        "Details": {
+          "GitCommit": "3dd1e886e55dd695541fdcd67420c2888645a495"
+        }
+      },
+      {
+        "Name": "runc",
+        "Version": "1.1.10",
+        "Details": {
+          "GitCommit": "v1.1.10-0-g18a0cb0"
+        }
+      }
+    ],
+    "Version": "24.0.7",
+    "ApiVersion": "1.43",
+    "MinAPIVersion": "1.12",
+    "GitCommit": "311b9ff",
+    "GoVersion": "go1.20.10",
+    "Os": "linux",
+    "Arch": "amd64",
+    "KernelVersion": "6.5.0-14-generic",
+    "BuildTime": "2023-10-26T09:07:41.000000000+00:00"
+  }
+}"#;
+
+const DOCKER_24_INFO: &str = r#"{
+  "ID": "b7c3a1e6-4c1f-4f1e-9d5e-0a5c1d8b2f3e",
+  "Containers": 2,
+  "Driver": "overlay2",
+  "CgroupDriver": "cgroupfs",
+  "CgroupVersion": "1",
+  "KernelVersion": "6.5.0-14-generic",
+  "OperatingSystem": "Ubuntu 22.04.3 LTS",
+  "OSType": "linux",
+  "Architecture": "x86_64",
+  "ServerVersion": "24.0.7",
+  "ExperimentalBuild": false,
+  "SecurityOptions": [
+    "name=apparmor",
+    "name=seccomp,profile=builtin"
+  ]
+}"#;
+
+const DOCKER_25_VERSION: &str = r#"{
+  "Client": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Version": "25.0.3",
+    "ApiVersion": "1.44",
+    "DefaultAPIVersion": "1.44",
+    "GitCommit": "4debf41",
+    "GoVersion": "go1.21.6",
+    "Os": "linux",
+    "Arch": "amd64",
+    "BuildTime": "Tue Feb  6 21:13:00 2024",
+    "Context": "rootless"
+  },
+  "Server": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Components": [
+      {
+        "Name": "Engine",
+        "Version": "25.0.3",
+        "Details": {
+          "ApiVersion": "1.44",
+          "Arch": "amd64",
+          "BuildTime": "Tue Feb  6 21:13:00 2024",
+          "Experimental": "true",
+          "GitCommit": "f417435",
+          "GoVersion": "go1.21.6",
+          "KernelVersion": "6.7.4-arch1-1",
+          "MinAPIVersion": "1.24",
+          "Os": "linux"
+        }
+      },
+      {
+        "Name": "rootlesskit",
+        "Version": "2.0.0",
+        "Details": {
+          "ApiVersion": "1.1.1",
+          "NetworkDriver": "slirp4netns",
+          "PortDriver": "builtin",
+          "StateDir": "/run/user/1000/dockerd-rootless"
+        }
+      }
+    ],
+    "Version": "25.0.3",
+    "ApiVersion": "1.44",
+    "MinAPIVersion": "1.24",
+    "GitCommit": "f417435",
+    "GoVersion": "go1.21.6",
+    "Os": "linux",
+    "Arch": "amd64",
+    "KernelVersion": "6.7.4-arch1-1",
+    "Experimental": true,
+    "BuildTime": "2024-02-06T21:13:00.000000000+00:00"
+  }
+}"#;
+
+const DOCKER_25_INFO: &str = r#"{
+  "ID": "0d1c6a0e-2f5e-4b8e-a3a4-7f2c9e6d1b0a",
+  "Containers": 0,
+  "Driver": "overlay2",
+  "CgroupDriver": "systemd",
+  "CgroupVersion": "2",
+  "KernelVersion": "6.7.4-arch1-1",
+  "OperatingSystem": "Arch Linux",
+  "OSType": "linux",
+  "Architecture": "x86_64",
+  "ServerVersion": "25.0.3",
+  "ExperimentalBuild": true,
+  "SecurityOptions": [
+    "name=seccomp,profile=builtin",
+    "name=rootless",
+    "name=cgroupns"
+  ]
+}"#;
+
+const DOCKER_27_VERSION: &str = r#"{
+  "Client": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Version": "27.3.1",
+    "ApiVersion": "1.47",
+    "DefaultAPIVersion": "1.47",
+    "GitCommit": "ce12230",
+    "GoVersion": "go1.22.7",
+    "Os": "linux",
+    "Arch": "amd64",
+    "BuildTime": "Fri Sep 20 11:41:00 2024",
+    "Context": "default"
+  },
+  "Server": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Components": [
+      {
+        "Name": "Engine",
+        "Version": "27.3.1",
+        "Details": {
+          "ApiVersion": "1.47",
+          "Arch": "amd64",
+          "BuildTime": "Fri Sep 20 11:41:00 2024",
+          "Experimental": "false",
+          "GitCommit": "41ca978",
+          "GoVersion": "go1.22.7",
+          "KernelVersion": "6.8.0-45-generic",
+          "MinAPIVersion": "1.24",
+          "Os": "linux"
+        }
+      }
+    ],
+    "Version": "27.3.1",
+    "ApiVersion": "1.47",
+    "MinAPIVersion": "1.24",
+    "GitCommit": "41ca978",
+    "GoVersion": "go1.22.7",
+    "Os": "linux",
+    "Arch": "amd64",
+    "KernelVersion": "6.8.0-45-generic",
+    "BuildTime": "2024-09-20T11:41:00.000000000+00:00"
+  }
+}"#;
+
+const DOCKER_27_INFO: &str = r#"{
+  "ID": "5b3e2f1a-8c7d-4e6f-9a0b-1c2d3e4f5a6b",
+  "Containers": 5,
+  "Driver": "overlay2",
+  "CgroupDriver": "systemd",
+  "CgroupVersion": "2",
+  "KernelVersion": "6.8.0-45-generic",
+  "OperatingSystem": "Ubuntu 24.04.1 LTS",
+  "OSType": "linux",
+  "Architecture": "x86_64",
+  "ServerVersion": "27.3.1",
+  "ExperimentalBuild": false,
+  "SecurityOptions": [
+    "name=apparmor",
+    "name=seccomp,profile=builtin",
+    "name=cgroupns"
+  ]
+}"#;
+
+// the client fails to reach the daemon, so there is no "Server"
+const DOCKER_27_VERSION_NO_DAEMON: &str = r#"{
+  "Client": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Version": "27.3.1",
+    "ApiVersion": "1.47",
+    "DefaultAPIVersion": "1.47",
+    "GitCommit": "ce12230",
+    "GoVersion": "go1.22.7",
+    "Os": "linux",
+    "Arch": "amd64",
+    "BuildTime": "Fri Sep 20 11:41:00 2024",
+    "Context": "default"
+  },
+  "Server": null
+}"#;
+
+const PODMAN_4_VERSION: &str = r#"{
+  "Client": {
+    "APIVersion": "4.9.3",
+    "Version": "4.9.3",
+    "GoVersion": "go1.22.2",
+    "GitCommit": "",
+    "BuiltTime": "Thu Jan  1 00:00:00 1970",
+    "Built": 0,
+    "OsArch": "linux/amd64",
+    "Os": "linux"
+  },
+  "Server": {
+    "APIVersion": "4.9.3",
+    "Version": "4.9.3",
+    "GoVersion": "go1.22.2",
+    "GitCommit": "",
+    "BuiltTime": "Thu Jan  1 00:00:00 1970",
+    "Built": 0,
+    "OsArch": "linux/amd64",
+    "Os": "linux"
+  }
+}"#;
+
+const PODMAN_4_INFO: &str = r#"{
+  "host": {
+    "arch": "amd64",
+    "buildahVersion": "1.33.7",
+    "cgroupManager": "systemd",
+    "cgroupVersion": "v2",
+    "conmon": {
+      "package": "conmon_2.1.10+ds1-1build2_amd64",
+      "path": "/usr/bin/conmon",
+      "version": "conmon version 2.1.10, commit: unknown"
+    },
+    "hostname": "devbox",
+    "kernel": "6.8.0-45-generic",
+    "os": "linux",
+    "security": {
+      "apparmorEnabled": false,
+      "capabilities": "CAP_CHOWN,CAP_DAC_OVERRIDE",
+      "rootless": true,
+      "seccompEnabled": true,
+      "selinuxEnabled": false
+    }
+  },
+  "store": {
+    "graphDriverName": "overlay"
+  },
+  "version": {
+    "APIVersion": "4.9.3",
+    "Built": 0,
+    "BuiltTime": "Thu Jan  1 00:00:00 1970",
+    "GitCommit": "",
+    "GoVersion": "go1.22.2",
+    "Os": "linux",
+    "OsArch": "linux/amd64",
+    "Version": "4.9.3"
+  }
+}"#;
+
+const PODMAN_5_VERSION: &str = r#"{
+  "Client": {
+    "APIVersion": "5.2.3",
+    "Version": "5.2.3",
+    "GoVersion": "go1.22.7",
+    "GitCommit": "",
+    "BuiltTime": "Tue Sep 17 00:00:00 2024",
+    "Built": 1726531200,
+    "OsArch": "linux/amd64",
+    "Os": "linux"
+  },
+  "Server": {
+    "APIVersion": "5.2.3",
+    "Version": "5.2.3",
+    "GoVersion": "go1.22.7",
+    "GitCommit": "",
+    "BuiltTime": "Tue Sep 17 00:00:00 2024",
+    "Built": 1726531200,
+    "OsArch": "linux/amd64",
+    "Os": "linux"
+  }
+}"#;
+
+const PODMAN_5_INFO: &str = r#"{
+  "host": {
+    "arch": "amd64",
+    "buildahVersion": "1.37.3",
+    "cgroupManager": "cgroupfs",
+    "cgroupVersion": "v1",
+    "hostname": "ci-runner",
+    "kernel": "5.14.0-427.el9.x86_64",
+    "os": "linux",
+    "security": {
+      "apparmorEnabled": false,
+      "rootless": false,
+      "seccompEnabled": true,
+      "selinuxEnabled": true
+    }
+  },
+  "store": {
+    "graphDriverName": "overlay"
+  },
+  "version": {
+    "APIVersion": "5.2.3",
+    "Built": 1726531200,
+    "BuiltTime": "Tue Sep 17 00:00:00 2024",
+    "GitCommit": "",
+    "GoVersion": "go1.22.7",
+    "Os": "linux",
+    "OsArch": "linux/amd64",
+    "Version": "5.2.3"
+  }
+}"#;
+
+// the Docker compatibility API of `podman system service` names itself
+const PODMAN_COMPAT_VERSION: &str = r#"{
+  "Client": {
+    "Platform": {
+      "Name": "Docker Engine - Community"
+    },
+    "Version": "27.3.1",
+    "ApiVersion": "1.41",
+    "Os": "linux",
+    "Arch": "amd64"
+  },
+  "Server": {
+    "Platform": {
+      "Name": "linux/amd64/ubuntu-24.04"
+    },
+    "Components": [
+      {
+        "Name": "Podman Engine",
+        "Version": "4.9.3",
+        "Details": {
+          "APIVersion": "4.9.3",
+          "Arch": "amd64",
+          "Os": "linux"
+        }
+      }
+    ],
+    "Version": "4.9.3",
+    "ApiVersion": "1.41",
+    "MinAPIVersion": "1.24",
+    "Os": "linux",
+    "Arch": "amd64"
+  }
+}"#;
+
+const BUILDX_VERSION: &str =
+    "github.com/docker/buildx v0.17.1 257815a6fb95c0dfaa2b6ad3e0ac71ab4bea6acd\n";
+
+#[test]
+fn docker_versions() -> Result<()> {
+    let caps = EngineCapabilities::parse(DOCKER_24_VERSION, DOCKER_24_INFO, Some(BUILDX_VERSION))?;
+    ensure_eq!(caps.flavor, EngineFlavor::Docker);
+    ensure_eq!(caps.client_version.as_deref(), Some("24.0.7"));
+    ensure_eq!(caps.server_version.as_deref(), Some("24.0.7"));
+    ensure_eq!(caps.api_version.as_deref(), Some("1.43"));
+    ensure_eq!(caps.buildx_version.as_deref(), Some("v0.17.1"));
+    ensure!(caps.buildkit);
+    ensure!(!caps.experimental);
+    ensure!(!caps.rootless);
+    ensure!(!caps.cgroup_v2);
+    ensure!(caps.supports(Capability::BuildKit));
+    ensure!(caps.supports(Capability::Buildx));
+    ensure!(!caps.supports(Capability::Experimental));
+    ensure!(!caps.supports(Capability::CgroupV2));
+
+    let mut caps = EngineCapabilities::parse(DOCKER_25_VERSION, DOCKER_25_INFO, None)?;
+    ensure_eq!(caps.flavor, EngineFlavor::Docker);
+    ensure_eq!(caps.api_version.as_deref(), Some("1.44"));
+    ensure!(caps.experimental);
+    ensure!(caps.rootless);
+    ensure!(caps.cgroup_v2);
+    ensure!(caps.buildx_version.is_none());
+    ensure!(!caps.buildkit);
+    ensure!(caps.supports(Capability::Experimental));
+    // experimental, but CRIU is missing
+    let e = format!("{:?}", caps.require(Capability::Checkpoint).unwrap_err());
+    ensure!(e.contains("CRIU"));
+    caps.criu = true;
+    ensure!(caps.supports(Capability::Checkpoint));
+
+    let caps = EngineCapabilities::parse(DOCKER_27_VERSION, DOCKER_27_INFO, Some(BUILDX_VERSION))?;
+    ensure_eq!(caps.server_version.as_deref(), Some("27.3.1"));
+    ensure_eq!(caps.api_version.as_deref(), Some("1.47"));
+    ensure!(caps.cgroup_v2);
+    ensure!(!caps.rootless);
+    let e = format!("{:?}", caps.require(Capability::Checkpoint).unwrap_err());
+    ensure!(e.contains("experimental"));
+
+    // the server version falls back to the info
+    let caps = EngineCapabilities::parse(DOCKER_27_VERSION_NO_DAEMON, DOCKER_27_INFO, None)?;
+    ensure_eq!(caps.client_version.as_deref(), Some("27.3.1"));
+    ensure_eq!(caps.server_version.as_deref(), Some("27.3.1"));
+    ensure!(caps.api_version.is_none());
+    Ok(())
+}
+
+#[test]
+fn podman_versions() -> Result<()> {
+    let caps = EngineCapabilities::parse(PODMAN_4_VERSION, PODMAN_4_INFO, None)?;
+    ensure_eq!(caps.flavor, EngineFlavor::Podman);
+    ensure_eq!(caps.server_version.as_deref(), Some("4.9.3"));
+    ensure_eq!(caps.api_version.as_deref(), Some("4.9.3"));
+    ensure!(caps.rootless);
+    ensure!(caps.cgroup_v2);
+    ensure!(!caps.experimental);
+    ensure!(caps.supports(Capability::Rootless));
+
+    // `podman buildx version` is an alias for the buildah version
+    let caps =
+        EngineCapabilities::parse(PODMAN_5_VERSION, PODMAN_5_INFO, Some("buildah 1.37.3\n"))?;
+    ensure_eq!(caps.flavor, EngineFlavor::Podman);
+    ensure_eq!(caps.client_version.as_deref(), Some("5.2.3"));
+    ensure!(!caps.rootless);
+    ensure!(!caps.cgroup_v2);
+    ensure!(caps.buildx_version.is_none());
+    let e = format!("{:?}", caps.require(Capability::BuildKit).unwrap_err());
+    ensure!(e.contains("Podman does not support BuildKit"));
+    let e = format!("{:?}", caps.require(Capability::Checkpoint).unwrap_err());
+    ensure!(e.contains("Podman"));
+
+    let caps = EngineCapabilities::parse(PODMAN_COMPAT_VERSION, DOCKER_27_INFO, None)?;
+    ensure_eq!(caps.flavor, EngineFlavor::Podman);
+    ensure_eq!(caps.server_version.as_deref(), Some("4.9.3"));
+    ensure_eq!(caps.api_version.as_deref(), Some("1.41"));
+    Ok(())
+}
+
+#[test]
+fn actionable_errors() -> Result<()> {
+    let caps = EngineCapabilities::parse(DOCKER_27_VERSION, DOCKER_27_INFO, None)?;
+    let e = format!("{:?}", caps.require(Capability::Buildx).unwrap_err());
+    ensure!(e.contains("Capability::Buildx"));
+    ensure!(e.contains("buildx not found"));
+    ensure!(e.contains("docker-buildx-plugin"));
+    let e = format!("{:?}", caps.require(Capability::BuildKit).unwrap_err());
+    ensure!(e.contains("docker-buildx-plugin"));
+    let mut caps =
+        EngineCapabilities::parse(DOCKER_27_VERSION, DOCKER_27_INFO, Some(BUILDX_VERSION))?;
+    // as if `DOCKER_BUILDKIT=0` was set
+    caps.buildkit = false;
+    let e = format!("{:?}", caps.require(Capability::BuildKit).unwrap_err());
+    ensure!(e.contains("DOCKER_BUILDKIT=0"));
+    ensure!(caps.supports(Capability::Buildx));
+    ensure!(EngineCapabilities::parse("not json", DOCKER_27_INFO, None).is_err());
+    Ok(())
+}
@@ -0,0 +1,324 @@
+//! Tests of the `combinators` and `docker::up` helpers
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    combinators::{matrix_run, run_until, with_network, ContainerPatch, RunOutcome},
+    docker::{
+        up, ContainerHooks, ContainerNetwork, Dockerfile, HookContext, ReadinessProbe,
+        ResourceKind, UpOptions,
+    },
+    test_doubles::FakeContainer,
+    wait_for_ok,
+};
+
+use crate::{container, fake_docker, fake_id, LOG_DIR, TIMEOUT};
+
+#[tokio::test]
+async fn combinators() -> Result<()> {
+    let fake = fake_docker();
+    let long = Duration::from_secs(10);
+    fake.script(
+        "comb_service",
+        &FakeContainer::new()
+            .stdout_chunk(Duration::from_millis(100), "ready\n")
+            .duration(long),
+    )
+    .stack()?;
+    fake.script("comb_job", &FakeContainer::new().stdout("job done\n"))
+        .stack()?;
+    fake.script("comb_bad_job", &FakeContainer::new().exit_code(1))
+        .stack()?;
+    let service = || {
+        container("comb_service").readiness(
+            ReadinessProbe::LogLine("ready".to_owned()),
+            Duration::from_millis(50),
+            TIMEOUT,
+        )
+    };
+
+    // `with_network` tears down even when the closure fails
+    let mut cn = ContainerNetwork::new("fake_comb_with", None, LOG_DIR);
+    cn.add_container(service()).stack()?;
+    let e = with_network(cn, async |cn| {
+        cn.run_all().await?;
+        Err::<(), _>(stacked_errors::Error::from("closure failure"))
+    })
+    .await
+    .unwrap_err();
+    ensure!(format!("{e:?}").contains("closure failure"));
+    ensure!(fake.invocations().stack()?.contains(&vec![
+        "network".to_owned(),
+        "rm".to_owned(),
+        "fake_comb_with".to_owned()
+    ]));
+
+    // `run_until` waits for the services before running the jobs
+    let mut cn = ContainerNetwork::new("fake_comb_until", None, LOG_DIR);
+    cn.add_container(service()).stack()?;
+    cn.add_container(container("comb_job")).stack()?;
+    cn.add_container(container("comb_bad_job")).stack()?;
+    let results = run_until(&mut cn, &["comb_service"], &["comb_job"], TIMEOUT)
+        .await
+        .stack()?
+        .into_success()
+        .stack()?;
+    ensure_eq!(results["comb_job"].stdout, b"job done\n");
+    ensure!(cn.active_names().contains(&"comb_service".to_owned()));
+    let outcome = run_until(&mut cn, &[], &["comb_bad_job"], TIMEOUT)
+        .await
+        .stack()?;
+    ensure!(matches!(outcome, RunOutcome::JobFailed(_)));
+    ensure!(cn.active_names().is_empty());
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn matrix_run_failure() -> Result<()> {
+    let fake = fake_docker();
+    fake.script("matrix_svc", &FakeContainer::new().stdout("served\n"))
+        .stack()?;
+    let base = ContainerNetwork::builder()
+        .network_name("fake_matrix")
+        .log_dir(LOG_DIR)
+        .uuid()
+        .containers(vec![
+            container("matrix_svc").environment_vars([("VERSION", "0"), ("MODE", "test")])
+        ]);
+    let variants = ["1", "2", "3"]
+        .map(|version| {
+            ContainerPatch::new("matrix_svc")
+                .image(format!("fake:{version}"))
+                .env("VERSION", version)
+        })
+        .into_iter()
+        .chain([ContainerPatch::new("matrix_missing")])
+        .collect();
+    let mut network_names = vec![];
+    let results = matrix_run(&base, variants, async |patch, cn| {
+        network_names.push(cn.network_name().to_owned());
+        let container = cn.get_container("matrix_svc").stack()?;
+        ensure_eq!(
+            container.dockerfile,
+            Dockerfile::name_tag(patch.image.as_ref().unwrap())
+        );
+        let env = container.environment_vars.clone();
+        ensure_eq!(env.len(), 2);
+        ensure!(env.contains(&("MODE".to_owned(), "test".to_owned())));
+        cn.run_all().await?;
+        cn.wait_with_timeout_all(true, TIMEOUT).await?;
+        // simulates version 2 of the service being broken
+        if env.contains(&("VERSION".to_owned(), "2".to_owned())) {
+            return Err(stacked_errors::Error::from("version 2 is broken"))
+        }
+        Ok(cn.expect("matrix_svc")?.stdout.clone())
+    })
+    .await;
+    ensure_eq!(results.len(), 4);
+    ensure_eq!(results[0].as_ref().unwrap(), b"served\n");
+    let e = format!("{:?}", results[1].as_ref().unwrap_err());
+    ensure!(e.contains("version 2 is broken"));
+    ensure!(e.contains("matrix_run -> variant"));
+    // the variant after the failure still runs
+    ensure_eq!(results[2].as_ref().unwrap(), b"served\n");
+    let e = format!("{:?}", results[3].as_ref().unwrap_err());
+    ensure!(e.contains("the patched container \"matrix_missing\" is not in the network"));
+    // every variant had its own network that was torn down
+    ensure_eq!(network_names.len(), 3);
+    let invocations = fake.invocations().stack()?;
+    for name in &network_names {
+        ensure!(invocations.contains(&vec!["network".to_owned(), "rm".to_owned(), name.clone()]));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn up_and_down() -> Result<()> {
+    let fake = fake_docker();
+    let running = FakeContainer::new()
+        .stdout_chunk(Duration::ZERO, "serving\n")
+        .duration(Duration::from_secs(60));
+    fake.script("up_server", &running).stack()?;
+    fake.script("up_client", &running).stack()?;
+    let env = up(
+        vec![container("up_server"), container("up_client")],
+        UpOptions::new().network_name("fake_up").ctrlc(false),
+    )
+    .await
+    .stack()?;
+    let network_name = env.network().network_name().to_owned();
+    ensure!(network_name.starts_with("fake_up_"));
+    // the log files go to a temporary directory by default
+    let log_dir = env.log_dir().to_owned();
+    ensure!(log_dir.is_dir());
+
+    // the containers can resolve each other by their hostnames, once both have
+    // started
+    let comres = wait_for_ok(50, Duration::from_millis(50), || async {
+        let comres = env
+            .exec("up_client", ["getent", "hosts", "up_server"])
+            .await?;
+        comres.assert_success()?;
+        Ok(comres)
+    })
+    .await
+    .stack()?;
+    ensure!(comres.stdout_as_utf8().stack()?.contains("up_server"));
+    let comres = env
+        .exec("up_client", ["getent", "hosts", "up_nonexistent"])
+        .await
+        .stack()?;
+    ensure!(!comres.successful());
+    ensure_eq!(
+        env.url_for("up_server", 8080).stack()?,
+        "http://up_server:8080"
+    );
+    ensure!(env.url_for("up_nonexistent", 8080).is_err());
+    let comres = env.logs("up_server").await.stack()?;
+    ensure_eq!(comres.stdout_as_utf8().stack()?, "serving\n");
+
+    let resources = env.network().resources().clone();
+    env.down().await.stack()?;
+    let invocations = fake.invocations()?;
+    ensure!(invocations
+        .iter()
+        .any(|args| (args[..2] == ["network", "rm"]) && (*args.last().unwrap() == network_name)));
+    for name in ["up_server", "up_client"] {
+        let id = fake_id(name);
+        ensure!(invocations
+            .iter()
+            .any(|args| (args[0] == "rm") && args.contains(&id)));
+        ensure!(resources.get(ResourceKind::Container, name).is_some());
+    }
+    ensure!(!log_dir.exists());
+
+    // on failure the temporary log directory is kept for the error to point to
+    fake.script("up_fail_create", &FakeContainer::new().fail_create(true))
+        .stack()?;
+    let e = format!(
+        "{:?}",
+        up(
+            vec![container("up_fail_create")],
+            UpOptions::new().network_name("fake_up_fail").ctrlc(false),
+        )
+        .await
+        .unwrap_err()
+    );
+    let (_, log_dir) = e.split_once("the logs are in \"").stack()?;
+    let (log_dir, _) = log_dir.split_once('"').stack()?;
+    ensure!(Path::new(log_dir).is_dir(), "{e}");
+    std::fs::remove_dir_all(Path::new(log_dir).parent().stack()?).stack()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn templates() -> Result<()> {
+    let fake = fake_docker();
+    fake.script("tmpl_client", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    fake.script("tmpl_server", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_templates", None, LOG_DIR);
+    let port = cn.reserve_port("web").stack()?;
+    ensure_eq!(cn.reserve_port("web").stack()?, port);
+    ensure_eq!(cn.reserved_port("web"), Some(port));
+    cn.add_container(container("tmpl_server")).stack()?;
+    // alphabetically this would be started first
+    cn.add_container(
+        container("tmpl_client")
+            .environment_vars([("SERVER", "{{ip:tmpl_server}}:{{port:web}}")])
+            .entrypoint_args([
+                "--uuid={{uuid}}",
+                "--host={{hostname:tmpl_server}}",
+                "--literal={{{{not a placeholder}}",
+            ])
+            .create_args(["--label", "port={{port:web}}"]),
+    )
+    .stack()?;
+    let ledger = Arc::new(Mutex::new(vec![]));
+    for name in ["tmpl_client", "tmpl_server"] {
+        let hook = |ledger: Arc<Mutex<Vec<String>>>| {
+            move |cx: HookContext| {
+                ledger.lock().unwrap().push(format!("{} {name}", cx.phase));
+                async { Ok(()) }
+            }
+        };
+        cn.set_hooks(
+            name,
+            ContainerHooks::new()
+                .on_before_create(hook(ledger.clone()))
+                .on_after_start(hook(ledger.clone())),
+        )
+        .stack()?;
+    }
+    ensure!(cn.validate().await.stack()?.is_ok());
+    cn.run_all().await.stack()?;
+    // the client is only created once the server has started
+    ensure_eq!(*ledger.lock().unwrap(), [
+        "before_create tmpl_server",
+        "after_start tmpl_server",
+        "before_create tmpl_client",
+        "after_start tmpl_client",
+    ]);
+
+    let invocations = fake.invocations().stack()?;
+    let create = invocations
+        .iter()
+        .find(|args| (args[0] == "create") && args.contains(&"tmpl_client".to_owned()))
+        .stack()?;
+    for expected in [
+        format!("SERVER=172.18.0.2:{port}"),
+        format!("port={port}"),
+        format!("--uuid={}", cn.uuid_as_string()),
+        "--host=tmpl_server".to_owned(),
+        "--literal={{not a placeholder}}".to_owned(),
+    ] {
+        ensure!(
+            create.iter().any(|arg| arg.contains(&expected)),
+            format!("{expected} is not in {create:?}")
+        );
+    }
+    // the stored container keeps its templates
+    ensure_eq!(
+        cn.get_container("tmpl_client").stack()?.environment_vars[0].1,
+        "{{ip:tmpl_server}}:{{port:web}}"
+    );
+    cn.terminate_all().await;
+
+    // typos are found before anything runs
+    let mut cn = ContainerNetwork::new("fake_templates_typo", None, LOG_DIR);
+    cn.add_container(container("tmpl_typo").entrypoint_args(["{{hostnme:tmpl_typo}}"]))
+        .stack()?;
+    cn.add_container(container("tmpl_unreserved").entrypoint_args(["{{port:db}}"]))
+        .stack()?;
+    cn.add_container(container("tmpl_cycle_a").entrypoint_args(["{{ip:tmpl_cycle_b}}"]))
+        .stack()?;
+    cn.add_container(container("tmpl_cycle_b").entrypoint_args(["{{ip:tmpl_cycle_a}}"]))
+        .stack()?;
+    let report = cn.validate().await.stack()?;
+    let messages: Vec<String> = report.errors.iter().map(|e| e.to_string()).collect();
+    ensure_eq!(messages.len(), 4);
+    ensure!(messages[2].contains(
+        "container \"tmpl_typo\": has an invalid template \"{{hostnme:tmpl_typo}}\" has the \
+         unknown placeholder \"{{hostnme:tmpl_typo}}\", the valid placeholders are {{uuid}}, \
+         {{hostname:<name>}}"
+    ));
+    ensure!(messages[3]
+        .contains("container \"tmpl_unreserved\": has a template using the reserved port \"db\""));
+    ensure!(messages[0].contains("container \"tmpl_cycle_a\": has a cycle"));
+    ensure!(messages[1].contains("container \"tmpl_cycle_b\": has a cycle"));
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("failed in phase Precheck"));
+    ensure!(fake
+        .invocations()
+        .stack()?
+        .iter()
+        .all(|args| !args.contains(&"tmpl_typo".to_owned())));
+    Ok(())
+}
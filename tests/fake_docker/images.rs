@@ -0,0 +1,633 @@
+//! Tests of pulling, building, and pinning images
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use sha2::{Digest, Sha256};
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    docker::{Container, ContainerNetwork, Dockerfile, PullPolicy, PullSecret, ResourceKind},
+    docker_helpers::{
+        capabilities, format_lock_file, parse_digest, parse_lock_file, pull_image,
+        pull_image_if_not_present, require, Capability, EngineFlavor,
+    },
+    test_doubles::FakeContainer,
+    BuildProgress,
+};
+
+use crate::{container, fake_docker, fake_id, LOG_DIR, TIMEOUT};
+
+#[tokio::test]
+async fn pull_secrets() -> Result<()> {
+    let fake = fake_docker();
+    let secret = |registry: &str, username: &str| PullSecret {
+        registry: registry.to_owned(),
+        username: username.to_owned(),
+        password: format!("{username}_password"),
+    };
+    let mut cn = ContainerNetwork::new("fake_ps", None, LOG_DIR);
+    cn.pull_secrets(vec![
+        secret("ps-a.example.com", "user_a"),
+        secret("ps-b.example.com:5000", "user_b"),
+        secret("ps-unused.example.com", "user_unused"),
+    ]);
+    for (name, image) in [
+        ("ps_a0", "ps-a.example.com/image:0"),
+        ("ps_a1", "ps-a.example.com/other/image:1"),
+        ("ps_b", "ps-b.example.com:5000/image"),
+        ("ps_hub", "library/image:latest"),
+    ] {
+        cn.add_container(Container::new(name, Dockerfile::name_tag(image)))
+            .stack()?;
+    }
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let logins: Vec<_> = fake
+        .logins()
+        .stack()?
+        .into_iter()
+        .filter(|(registry, ..)| registry.starts_with("ps-"))
+        .collect();
+    ensure_eq!(logins, [
+        (
+            "ps-a.example.com".to_owned(),
+            "user_a".to_owned(),
+            "user_a_password".to_owned()
+        ),
+        (
+            "ps-b.example.com:5000".to_owned(),
+            "user_b".to_owned(),
+            "user_b_password".to_owned()
+        ),
+    ]);
+    // logged out after everything is created and pulled
+    let invocations = fake.invocations().stack()?;
+    let position = |args: &[&str]| invocations.iter().position(|x| x == args);
+    let last_create = invocations
+        .iter()
+        .rposition(|args| args[0] == "create" && args.contains(&"ps_hub".to_owned()))
+        .stack()?;
+    for registry in ["ps-a.example.com", "ps-b.example.com:5000"] {
+        ensure!(position(&["logout", registry]).stack()? > last_create);
+    }
+    ensure!(position(&["logout", "ps-unused.example.com"]).is_none());
+    // the password is not in the debug output
+    ensure!(!format!("{cn:?}").contains("user_a_password"));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn build_progress() -> Result<()> {
+    fake_docker();
+    let tmp = concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker_build_progress");
+    std::fs::create_dir_all(tmp).unwrap();
+    let own = Arc::new(Mutex::new(vec![]));
+    let default = Arc::new(Mutex::new(vec![]));
+    let mut cn = ContainerNetwork::new("fake_build_progress", Some(tmp), LOG_DIR);
+    cn.add_container(
+        Container::new("bp_own", Dockerfile::contents("FROM fake:latest\n"))
+            .build_args(["--progress=plain"])
+            .on_build_progress({
+                let own = own.clone();
+                move |progress: &BuildProgress, line: &str| {
+                    own.lock()
+                        .unwrap()
+                        .push((progress.clone(), line.to_owned()))
+                }
+            }),
+    )
+    .stack()?;
+    cn.add_container(
+        Container::new("bp_default", Dockerfile::contents("FROM fake:2\n"))
+            .build_args(["--progress=plain"]),
+    )
+    .stack()?;
+    cn.on_build_progress({
+        let default = default.clone();
+        move |progress: &BuildProgress, _: &str| default.lock().unwrap().push(progress.clone())
+    });
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+
+    let own = own.lock().unwrap().clone();
+    // the `RUN` output line is not progress
+    ensure_eq!(own.len(), 6);
+    ensure_eq!(
+        own[0].1,
+        "#1 [internal] load build definition from Dockerfile"
+    );
+    ensure_eq!(own[2].0, BuildProgress {
+        vertex: Some(2),
+        step: Some(1),
+        total: Some(2),
+        description: "FROM docker.io/library/fake:latest".to_owned(),
+    });
+    ensure!(own[3].0.is_cached());
+    ensure_eq!((own[4].0.step, own[4].0.total), (Some(2), Some(2)));
+    ensure_eq!(own[5].0.description, "DONE 0.1s");
+    // the network callback is only used for the container without its own
+    ensure_eq!(default.lock().unwrap().len(), 6);
+    Ok(())
+}
+
+#[tokio::test]
+async fn build_stats() -> Result<()> {
+    fake_docker();
+    let tmp = concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker_build_stats");
+    std::fs::create_dir_all(tmp).unwrap();
+    let dockerfile = Dockerfile::contents("FROM fake:latest\nRUN build stats\n");
+    let mut stats = vec![];
+    // the second network builds the same contents under a different tag
+    for network in ["fake_build_stats0", "fake_build_stats1"] {
+        let mut cn = ContainerNetwork::new(network, Some(tmp), LOG_DIR);
+        for name in ["bs_a", "bs_b"] {
+            cn.add_container(
+                Container::new(name, dockerfile.clone()).build_args(["--progress=plain"]),
+            )
+            .stack()?;
+        }
+        cn.add_container(container("bs_name_tag")).stack()?;
+        cn.run_all().await.stack()?;
+        cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+        cn.terminate_all().await;
+        let build_stats = cn.build_stats();
+        ensure_eq!(build_stats.keys().copied().collect::<Vec<_>>(), vec![
+            "bs_a", "bs_b"
+        ]);
+        ensure_eq!(build_stats["bs_a"], build_stats["bs_b"]);
+        stats.push(build_stats["bs_a"].clone());
+    }
+    ensure_eq!((stats[0].steps, stats[0].cached), (2, 1));
+    ensure_eq!(stats[0].first_miss.as_deref(), Some("RUN echo fake"));
+    ensure!(!stats[0].is_fully_cached());
+    ensure_eq!((stats[1].steps, stats[1].cached), (2, 2));
+    ensure_eq!(stats[1].first_miss, None);
+    ensure!(stats[1].is_fully_cached());
+    Ok(())
+}
+
+#[tokio::test]
+async fn build_cache_dir() -> Result<()> {
+    fake_docker();
+    let tmp = concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker_build_cache_dir");
+    let cache_dir = format!("{tmp}/cache");
+    let _ = std::fs::remove_dir_all(tmp);
+    std::fs::create_dir_all(&cache_dir).stack()?;
+    let contents = "FROM fake:latest\nRUN build cache dir\n";
+    let hash: String = Sha256::digest(contents.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let daemon_cache = format!(
+        "{}/fake_docker/build_cache/{hash}",
+        env!("CARGO_TARGET_TMPDIR")
+    );
+    // builds in a fresh network with an empty daemon cache (like on another
+    // machine), returning the stats and build arguments
+    let build = |network: &'static str, key: &'static str| {
+        let cache_dir = cache_dir.clone();
+        let daemon_cache = daemon_cache.clone();
+        async move {
+            let _ = std::fs::remove_file(&daemon_cache);
+            let mut cn = ContainerNetwork::new(network, Some(tmp), LOG_DIR);
+            cn.build_cache_dir(&cache_dir);
+            cn.add_container(
+                Container::new("bcd", Dockerfile::contents(contents))
+                    .build_args(["--progress=plain"])
+                    .build_cache_key(key),
+            )
+            .stack()?;
+            cn.run_all().await.stack()?;
+            cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+            cn.terminate_all().await;
+            let stats = cn.build_stats()["bcd"].clone();
+            // the cache arguments are not added to the container itself
+            ensure!(!cn
+                .get_container("bcd")
+                .stack()?
+                .build_args
+                .iter()
+                .any(|arg| arg.starts_with("--cache")));
+            Ok::<_, stacked_errors::Error>(stats)
+        }
+    };
+
+    let stats = build("fake_build_cache_dir0", "bcd_key").await.stack()?;
+    ensure!(!stats.is_fully_cached());
+    let key_dir = std::fs::canonicalize(&cache_dir).stack()?.join("bcd_key");
+    ensure!(key_dir.join(&hash).is_file());
+    // the second build hits the local cache even though the daemon cache is gone
+    let stats = build("fake_build_cache_dir1", "bcd_key").await.stack()?;
+    ensure!(stats.is_fully_cached());
+    // a different key does not
+    let stats = build("fake_build_cache_dir2", "bcd_other_key")
+        .await
+        .stack()?;
+    ensure!(!stats.is_fully_cached());
+
+    let fake = fake_docker();
+    let builds: Vec<_> = fake
+        .invocations()
+        .stack()?
+        .into_iter()
+        .filter(|args| args[0] == "build" && args.iter().any(|arg| arg.contains("bcd_key")))
+        .collect();
+    ensure_eq!(builds.len(), 2);
+    let key_dir = key_dir.to_str().stack()?;
+    ensure!(builds[1].windows(2).any(|w| w
+        == [
+            "--cache-from".to_owned(),
+            format!("type=local,src={key_dir}")
+        ]));
+    ensure!(builds[1].windows(2).any(|w| w
+        == [
+            "--cache-to".to_owned(),
+            format!("type=local,dest={key_dir},mode=max")
+        ]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn build_secrets_and_ssh() -> Result<()> {
+    let fake = fake_docker();
+    let tmp = concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker_build");
+    std::fs::create_dir_all(tmp).unwrap();
+    let secret = |file: &str| {
+        let path = format!("{tmp}/{file}");
+        std::fs::write(&path, "secret").unwrap();
+        std::fs::canonicalize(&path)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    };
+    let (secret_a, secret_b) = (secret("npm_token_a"), secret("npm_token_b"));
+    let dockerfile = Dockerfile::contents("FROM fake:latest\n");
+    let mut cn = ContainerNetwork::new("fake_bs", Some(tmp), LOG_DIR);
+    // different secret paths do not prevent the build deduplication
+    for (name, secret_path) in [
+        ("bs_a", secret_a.as_str()),
+        ("bs_b", &secret_b),
+        ("bs_missing", "./nonexistent_npm_token"),
+    ] {
+        cn.add_container(
+            Container::new(name, dockerfile.clone())
+                .build_secret("npm_token", secret_path)
+                .build_ssh("default"),
+        )
+        .stack()?;
+    }
+    let debug = format!("{:?}", cn.get_container("bs_a").unwrap());
+    ensure!(debug.contains("npm_token"));
+    ensure!(!debug.contains(&secret_a));
+    ensure!(format!("{:?}", cn.run(["bs_missing"]).await.unwrap_err()).contains("npm_token"));
+    cn.run(["bs_a", "bs_b"]).await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let uuid = cn.uuid_as_string();
+    let invocations = fake.invocations().stack()?;
+    let builds: Vec<_> = invocations
+        .iter()
+        .filter(|args| args[0] == "build" && args.iter().any(|arg| arg.contains(&uuid)))
+        .collect();
+    ensure_eq!(builds.len(), 1);
+    let secret_arg = format!("id=npm_token,src={secret_a}");
+    ensure!(builds[0]
+        .windows(2)
+        .any(|w| w == ["--secret", secret_arg.as_str()]));
+    ensure!(builds[0].windows(2).any(|w| w == ["--ssh", "default"]));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn pulling_images() -> Result<()> {
+    let fake = fake_docker();
+    let image = "pi.example.com/image:1";
+    let pulls = || -> Result<usize> {
+        Ok(fake
+            .invocations()?
+            .iter()
+            .filter(|args| *args == &["pull", image])
+            .count())
+    };
+    pull_image_if_not_present(image).await.stack()?;
+    ensure_eq!(pulls()?, 1);
+    // already present
+    pull_image_if_not_present(image).await.stack()?;
+    pull_image(image, false).await.stack()?;
+    ensure_eq!(pulls()?, 1);
+    pull_image(image, true).await.stack()?;
+    ensure_eq!(pulls()?, 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn pull_policies() -> Result<()> {
+    let fake = fake_docker();
+    let pulls = |image: &str| -> Result<usize> {
+        Ok(fake
+            .invocations()?
+            .iter()
+            .filter(|args| *args == &["pull", image])
+            .count())
+    };
+    let create_pull_arg = |name: &str| -> Result<Option<String>> {
+        Ok(fake
+            .invocations()?
+            .iter()
+            .rev()
+            .find(|args| args[0] == "create" && args.contains(&name.to_owned()))
+            .stack()?
+            .iter()
+            .find(|arg| arg.starts_with("--pull="))
+            .cloned())
+    };
+    let always = "pp.example.com/always:1";
+    let missing = "pp.example.com/missing:1";
+    let mut cn = ContainerNetwork::new("fake_pp", None, LOG_DIR);
+    cn.add_container(
+        Container::new("pp_always", Dockerfile::name_tag(always)).pull_policy(PullPolicy::Always),
+    )
+    .stack()?;
+    cn.add_container(
+        Container::new("pp_missing", Dockerfile::name_tag(missing))
+            .pull_policy(PullPolicy::Missing),
+    )
+    .stack()?;
+    // `Always` pulls on every run, and the pull is not repeated by `docker create`
+    for i in 1..=2 {
+        cn.run_all().await.stack()?;
+        cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+        ensure_eq!(pulls(always)?, i);
+        ensure_eq!(
+            create_pull_arg("pp_always")?.as_deref(),
+            Some("--pull=missing")
+        );
+    }
+    ensure_eq!(pulls(missing)?, 0);
+    ensure_eq!(
+        create_pull_arg("pp_missing")?.as_deref(),
+        Some("--pull=missing")
+    );
+    cn.terminate_all().await;
+
+    // `Newer` falls back to the local image if the registry cannot be reached, but
+    // `Always` does not
+    let offline = "pp.example.com/offline:1";
+    let mut cn = ContainerNetwork::new("fake_pp_offline", None, LOG_DIR);
+    cn.add_container(
+        Container::new("pp_newer", Dockerfile::name_tag(offline)).pull_policy(PullPolicy::Newer),
+    )
+    .stack()?;
+    cn.add_container(
+        Container::new("pp_offline", Dockerfile::name_tag(offline)).pull_policy(PullPolicy::Always),
+    )
+    .stack()?;
+    let e = format!("{:?}", cn.run(["pp_newer"]).await.unwrap_err());
+    ensure!(e.contains("`PullPolicy::Newer`"));
+    std::fs::write(
+        concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker/images/").to_owned() + &fake_id(offline),
+        "",
+    )
+    .stack()?;
+    cn.run(["pp_newer"]).await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let e = format!("{:?}", cn.run(["pp_offline"]).await.unwrap_err());
+    ensure!(e.contains("`PullPolicy::Always`"));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn engine_capabilities() -> Result<()> {
+    fake_docker();
+    let caps = capabilities().await.stack()?;
+    ensure_eq!(caps.flavor, EngineFlavor::Docker);
+    ensure_eq!(caps.api_version.as_deref(), Some("1.46"));
+    ensure_eq!(caps.buildx_version.as_deref(), Some("v0.0.0-fake_docker"));
+    ensure!(caps.cgroup_v2);
+    // cached
+    ensure!(std::ptr::eq(caps, capabilities().await.stack()?));
+    require(Capability::Buildx).await.stack()?;
+    let e = format!("{:?}", require(Capability::Experimental).await.unwrap_err());
+    ensure!(e.contains("UnsupportedError"));
+    ensure!(e.contains("daemon.json"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn image_preflight() -> Result<()> {
+    let fake = fake_docker();
+    let ok = "ip.example.com/image:1";
+    let typo = "ip.example.com/nonexistent:1";
+    let mut cn = ContainerNetwork::new("fake_ip", None, LOG_DIR);
+    cn.add_container(Container::new("ip_ok", Dockerfile::name_tag(ok)))
+        .stack()?;
+    cn.add_container(Container::new("ip_typo", Dockerfile::name_tag(typo)))
+        .stack()?;
+    cn.add_container(
+        Container::new("ip_never", Dockerfile::name_tag("ip.example.com/never:1"))
+            .pull_policy(PullPolicy::Never),
+    )
+    .stack()?;
+
+    // without any preflight settings only `PullPolicy::Never` is checked
+    let e = cn.run(["ip_never"]).await.unwrap_err();
+    let e = format!("{e:?}");
+    ensure!(e.contains("ImagePreflight(\"ip_never\")"));
+    ensure!(e.contains("`PullPolicy::Never`"));
+
+    cn.verify_remote_images(true);
+    let e = format!("{:?}", cn.run(["ip_ok", "ip_typo"]).await.unwrap_err());
+    ensure!(e.contains("ImagePreflight(\"ip_typo\")"));
+    ensure!(e.contains(&format!(
+        "the image \"{typo}\" of container \"ip_typo\" does not exist locally or in its registry"
+    )));
+
+    cn.pull_before_create(true);
+    let e = format!("{:?}", cn.run(["ip_ok", "ip_typo"]).await.unwrap_err());
+    ensure!(e.contains("`docker pull` failed with"));
+    let invocations = fake.invocations().stack()?;
+    ensure!(invocations.contains(&vec!["pull".to_owned(), ok.to_owned()]));
+    ensure!(invocations.contains(&vec![
+        "manifest".to_owned(),
+        "inspect".to_owned(),
+        typo.to_owned()
+    ]));
+    // the typo was found before anything else happened
+    ensure!(!invocations
+        .iter()
+        .any(|args| args.contains(&"fake_ip".to_owned())));
+
+    cn.run(["ip_ok"]).await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn docker_available() -> Result<()> {
+    fake_docker();
+    ContainerNetwork::check_docker_available(true)
+        .await
+        .stack()?;
+    Ok(())
+}
+
+#[test]
+fn digest_parsing() -> Result<()> {
+    let hex = "ab".repeat(32);
+    let digest = format!("sha256:{hex}");
+    ensure_eq!(parse_digest(&digest).stack()?, digest);
+    ensure_eq!(
+        parse_digest(&format!(
+            " registry:5000/repo@sha256:{} ",
+            hex.to_uppercase()
+        ))
+        .stack()?,
+        digest
+    );
+    for invalid in [
+        "",
+        "sha256:",
+        "sha256:abc",
+        &format!("md5:{hex}"),
+        &format!("sha256:{hex}0"),
+    ] {
+        ensure!(parse_digest(invalid).is_err());
+    }
+    let e = format!(
+        "{:?}",
+        parse_digest(&format!("sha256:{}", "g".repeat(64))).unwrap_err()
+    );
+    ensure!(e.contains("does not have 64 hex digits"));
+
+    let digests = BTreeMap::from([
+        ("a".to_owned(), digest.clone()),
+        ("b-1.x".to_owned(), format!("sha256:{}", "0".repeat(64))),
+    ]);
+    let s = format_lock_file(&digests);
+    ensure!(s.contains(&format!("\"a\" = \"{digest}\"")));
+    ensure_eq!(parse_lock_file(&s).stack()?, digests);
+    // bare keys and comments are also accepted
+    ensure_eq!(
+        parse_lock_file(&format!("# comment\n\n[images]\na = \"{digest}\"\n")).stack()?,
+        BTreeMap::from([("a".to_owned(), digest.clone())])
+    );
+    let e = format!("{:?}", parse_lock_file("[images]\na = sha256").unwrap_err());
+    ensure!(e.contains("line 2 is not"));
+    ensure!(parse_lock_file("a = \"sha256:abc\"").is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn image_digests_and_lock_file() -> Result<()> {
+    let fake = fake_docker();
+    let name_tag = "fake_digest:1";
+    let digest_a = format!("sha256:{}", "a".repeat(64));
+    let digest_b = format!("sha256:{}", "b".repeat(64));
+    fake.set_image_digest(name_tag, &digest_a).stack()?;
+    for name in ["digest_a", "digest_built"] {
+        fake.script(name, &FakeContainer::new()).stack()?;
+    }
+    let dir = tempfile::tempdir().stack()?;
+    let lock_file = dir.path().join("images.lock");
+    let lock_file = lock_file.to_str().stack()?;
+    let digest_container = || Container::new("digest_a", Dockerfile::name_tag(name_tag));
+
+    // the digests are recorded, and the lock file is written
+    let mut cn = ContainerNetwork::new("fake_digests", Some(LOG_DIR), LOG_DIR);
+    cn.add_container(digest_container())
+        .stack()?
+        .add_container(Container::new(
+            "digest_built",
+            Dockerfile::contents("FROM fake:latest\n"),
+        ))
+        .stack()?;
+    cn.lock_file(lock_file);
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let digests = cn.image_digests();
+    ensure_eq!(digests["digest_a"].repo_digest, Some(digest_a.clone()));
+    ensure_eq!(digests["digest_a"].digest(), digest_a);
+    let built = &digests["digest_built"];
+    ensure!(built.repo_digest.is_none());
+    ensure!(built.id.starts_with("sha256:"));
+    let build_tag = cn
+        .get_container("digest_built")
+        .stack()?
+        .build_tag
+        .clone()
+        .stack()?;
+    let entry = cn
+        .resources()
+        .get(ResourceKind::Image, &build_tag)
+        .stack()?;
+    ensure_eq!(entry.id.as_deref(), Some(built.id.as_str()));
+    // only the `NameTag` containers are locked
+    let locked = parse_lock_file(&std::fs::read_to_string(lock_file).stack()?).stack()?;
+    ensure_eq!(
+        locked,
+        BTreeMap::from([("digest_a".to_owned(), digest_a.clone())])
+    );
+
+    // the local tag moving to another digest is caught
+    fake.set_image_digest(name_tag, &digest_b).stack()?;
+    let mut cn = ContainerNetwork::new("fake_digests_moved", None, LOG_DIR);
+    cn.add_container(digest_container()).stack()?;
+    cn.lock_file(lock_file);
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains(&format!(
+        "resolves to digest {digest_b}, but {digest_a} is pinned"
+    )));
+    ensure!(cn.active_names().is_empty());
+
+    // an explicit pin must agree with the lock file
+    let mut cn = ContainerNetwork::new("fake_digests_conflict", None, LOG_DIR);
+    cn.add_container(
+        digest_container().pinned_digest(digest_b.to_uppercase().replace("SHA", "sha")),
+    )
+    .stack()?;
+    cn.lock_file(lock_file);
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains(&format!(
+        "has `pinned_digest` {digest_b}, but the lock file has {digest_a}"
+    )));
+
+    // pins are only for `NameTag`s
+    let mut cn = ContainerNetwork::new("fake_digests_built", Some(LOG_DIR), LOG_DIR);
+    cn.add_container(
+        Container::new("digest_built", Dockerfile::contents("FROM fake:latest\n"))
+            .pinned_digest(&digest_a),
+    )
+    .stack()?;
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("a `pinned_digest` requires a `Dockerfile::NameTag`"));
+
+    // with the lock file, the container is created from the pinned digest
+    fake.set_image_digest(name_tag, &digest_a).stack()?;
+    let mut cn = ContainerNetwork::new("fake_digests_pinned", None, LOG_DIR);
+    cn.add_container(digest_container()).stack()?;
+    cn.lock_file(lock_file);
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let pinned = format!("fake_digest@{digest_a}");
+    let invocations = fake.invocations().stack()?;
+    let create = invocations
+        .iter()
+        .rev()
+        .find(|args| args[0] == "create" && args.contains(&"digest_a".to_owned()))
+        .stack()?;
+    ensure!(create.contains(&pinned));
+    ensure!(!create.contains(&name_tag.to_owned()));
+    ensure_eq!(cn.image_digests()["digest_a"].image, pinned);
+    ensure_eq!(
+        cn.get_container("digest_a").stack()?.pinned_digest,
+        Some(digest_a)
+    );
+    Ok(())
+}
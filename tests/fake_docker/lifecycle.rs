@@ -0,0 +1,882 @@
+//! Tests of creating, running, and waiting on containers
+
+use std::{collections::BTreeSet, time::Duration};
+
+use stacked_errors::{ensure, ensure_eq, ErrorKind, Result, StackableErr};
+use super_orchestrator::{
+    assertions::expect_stdout,
+    docker::{
+        final_names, ArgvTooLongError, Container, ContainerNetwork, Dockerfile, ImageNotFoundError,
+        NameConflictError, ReadinessProbe, ResourceConstraints, ResourceKind, ResourceStatus,
+        DEFAULT_MAX_CREATE_ARGV_SIZE, ENTRYPOINT_ARGS_FILE,
+    },
+    docker_helpers::DaemonUnreachableError,
+    load_args_file,
+    test_doubles::FakeContainer,
+    Backoff, Command, TestBudget,
+};
+use tokio::time::{timeout, Instant};
+
+use crate::{container, fake_docker, fake_id, LOG_DIR, TIMEOUT};
+
+#[tokio::test]
+async fn create_arguments() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new("fake_args", None, LOG_DIR);
+    cn.add_container(
+        container("args_a")
+            .environment_vars([("KEY", "value")])
+            .read_only_rootfs(true)
+            .tmpfs("/tmp")
+            .entrypoint("/bin/entry", ["arg0"]),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let invocations = fake.invocations().stack()?;
+    let create = invocations
+        .iter()
+        .find(|args| args[0] == "create" && args.contains(&"args_a".to_owned()))
+        .stack()?;
+    ensure_eq!(create[..2], ["create", "--rm"]);
+    ensure_eq!(create[create.len() - 3..], [
+        "fake:latest",
+        "/bin/entry",
+        "arg0"
+    ]);
+    ensure!(create.windows(2).any(|w| w == ["--name", "args_a"]));
+    ensure!(create.windows(2).any(|w| w == ["-e", "KEY=value"]));
+    ensure!(create.windows(2).any(|w| w == ["--tmpfs", "/tmp"]));
+    ensure!(create.contains(&"--read-only".to_owned()));
+    ensure!(invocations.contains(&vec![
+        "start".to_owned(),
+        "--attach".to_owned(),
+        fake_id("args_a")
+    ]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn failure_error_compilation() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "ec_fail",
+        &FakeContainer::new()
+            .stderr("Error: Error { stack: [\"fake root cause\"] }\n")
+            .exit_code(1),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_ec", None, LOG_DIR);
+    cn.add_container(container("ec_fail")).stack()?;
+    cn.add_container(container("ec_ok")).stack()?;
+    cn.run_all().await.stack()?;
+    let e = format!(
+        "{:?}",
+        cn.wait_with_timeout_all(true, TIMEOUT).await.unwrap_err()
+    );
+    ensure!(e.contains("Error stack from container \"ec_fail\" stderr"));
+    ensure!(e.contains("fake root cause"));
+    ensure!(!e.contains("ec_ok"));
+    cn.terminate_all().await;
+
+    // signal exits are found from the exit status, not from any mention of a
+    // signal in the output
+    fake.script(
+        "ec_handled",
+        &FakeContainer::new()
+            .stderr("received signal 15, shutting down\nsomething failed\n")
+            .exit_code(1),
+    )
+    .stack()?;
+    fake.script("ec_killed", &FakeContainer::new().exit_code(137))
+        .stack()?;
+    for (name, signal) in [("ec_handled", None), ("ec_killed", Some(9))] {
+        let mut cn = ContainerNetwork::new("fake_ec_signal", None, LOG_DIR);
+        cn.add_container(container(name)).stack()?;
+        cn.run_all().await.stack()?;
+        let e = format!(
+            "{:?}",
+            cn.wait_with_timeout_all(true, TIMEOUT).await.unwrap_err()
+        );
+        let message = format!("Container \"{name}\" appears to have been terminated by signal");
+        match signal {
+            Some(signal) => ensure!(e.contains(&format!("{message} {signal}"))),
+            None => ensure!(!e.contains(&message)),
+        }
+        cn.terminate_all().await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn record_spill() -> Result<()> {
+    let fake = fake_docker();
+    let filler = "filler line\n".repeat(100);
+    fake.script(
+        "spill_fail",
+        &FakeContainer::new()
+            .stdout(&filler)
+            .stderr(format!(
+                "Error: Error {{ stack: [\"spilled root cause\"] }}\n{filler}"
+            ))
+            .exit_code(1),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_spill", None, LOG_DIR);
+    cn.add_container(
+        container("spill_fail")
+            .record_spill(64, &format!("{LOG_DIR}/spill"))
+            .stack()?,
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    let e = format!(
+        "{:?}",
+        cn.wait_with_timeout_all(true, TIMEOUT).await.unwrap_err()
+    );
+    // the marker was spilled out of the record but is still found
+    ensure!(e.contains("spilled root cause"));
+    let results = cn.drain_results();
+    let comres = results["spill_fail"].as_ref().unwrap();
+    ensure!(comres.stdout.len() <= 64);
+    ensure_eq!(comres.full_stdout().stack()?, filler.as_bytes());
+    ensure!(comres.stderr_spill_path().is_some());
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn wait_timeouts() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "wt_slow",
+        &FakeContainer::new().duration(Duration::from_secs(60)),
+    )
+    .stack()?;
+    fake.script(
+        "wt_fast",
+        &FakeContainer::new()
+            .duration(Duration::from_millis(100))
+            .stdout("done"),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_wt", None, LOG_DIR);
+    cn.add_container(container("wt_slow")).stack()?;
+    cn.add_container(container("wt_fast")).stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout(["wt_fast"], true, TIMEOUT)
+        .await
+        .stack()?;
+    ensure_eq!(
+        cn.get_result("wt_fast")
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .stdout_as_utf8_lossy(),
+        "done"
+    );
+    let e = cn
+        .wait_with_timeout(["wt_slow"], false, Duration::from_millis(200))
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure_eq!(cn.active_names(), ["wt_slow"]);
+    // an outer timeout dropping the future leaves the container active
+    ensure!(timeout(
+        Duration::from_millis(200),
+        cn.wait_with_timeout_all(true, TIMEOUT)
+    )
+    .await
+    .is_err());
+    ensure_eq!(cn.active_names(), ["wt_slow"]);
+
+    // a custom backoff with `max_elapsed` bounds the wait
+    cn.wait_backoff(
+        Backoff::constant(Duration::from_millis(20))
+            .jitter(0.5)
+            .max_elapsed(Some(Duration::from_millis(200))),
+    );
+    let start = Instant::now();
+    let e = cn
+        .wait_with_timeout(["wt_slow"], false, TIMEOUT)
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(start.elapsed() < Duration::from_secs(5));
+    // and `max_retries`
+    cn.wait_backoff(Backoff::constant(Duration::from_millis(20)).max_retries(Some(5)));
+    let start = Instant::now();
+    let e = cn
+        .wait_with_timeout(["wt_slow"], false, TIMEOUT)
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(start.elapsed() < Duration::from_secs(5));
+    ensure_eq!(cn.active_names(), ["wt_slow"]);
+    cn.terminate_all().await;
+    ensure!(cn.active_names().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn interleaved_runs() -> Result<()> {
+    let fake = fake_docker();
+    let tmp = concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker_interleaved");
+    std::fs::create_dir_all(tmp).unwrap();
+    let long = FakeContainer::new().duration(Duration::from_secs(10));
+    let dockerfile = Dockerfile::contents("FROM fake:latest\nRUN interleaved\n");
+    let mut cn = ContainerNetwork::new("fake_interleaved", Some(tmp), LOG_DIR);
+    for name in ["il_a0", "il_a1", "il_b0", "il_b1"] {
+        fake.script(name, &long).stack()?;
+        cn.add_container(Container::new(name, dockerfile.clone()))
+            .stack()?;
+    }
+    // disjoint subsets in separate `run`s share the network and the build
+    cn.run(["il_a0", "il_a1"]).await.stack()?;
+    cn.run(["il_b0", "il_b1"]).await.stack()?;
+    let mut active = cn.active_names();
+    active.sort();
+    ensure_eq!(active, vec!["il_a0", "il_a1", "il_b0", "il_b1"]);
+    let uuid = cn.uuid_as_string();
+    let invocations = fake.invocations().stack()?;
+    let count =
+        |f: &dyn Fn(&Vec<String>) -> bool| invocations.iter().filter(|args| f(args)).count();
+    ensure_eq!(
+        count(&|args| args[..2] == ["network", "create"]
+            && args.last().unwrap() == "fake_interleaved"),
+        1
+    );
+    ensure_eq!(
+        count(&|args| args[0] == "build" && args.iter().any(|arg| arg.contains(&uuid))),
+        1
+    );
+    let tags: BTreeSet<_> = ["il_a0", "il_a1", "il_b0", "il_b1"]
+        .iter()
+        .map(|name| cn.get_container(name).unwrap().build_tag.clone().unwrap())
+        .collect();
+    ensure_eq!(tags.len(), 1);
+    cn.terminate_all().await;
+
+    // a `run` cancelled during network creation leaves the network behind, the next
+    // `run` accepts it instead of failing because it already exists
+    let mut cn = ContainerNetwork::new("fake_slow_create", None, LOG_DIR);
+    fake.script("il_slow", &FakeContainer::new()).stack()?;
+    cn.add_container(container("il_slow")).stack()?;
+    ensure!(timeout(Duration::from_millis(500), cn.run(["il_slow"]))
+        .await
+        .is_err());
+    cn.run(["il_slow"]).await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    let network_rm = vec![
+        "network".to_owned(),
+        "rm".to_owned(),
+        "fake_slow_create".to_owned(),
+    ];
+    ensure!(fake.invocations().stack()?.contains(&network_rm));
+
+    // a cancelled creation is cleaned up without a later `run`
+    let mut cn = ContainerNetwork::new("fake_slow_create_drop", None, LOG_DIR);
+    cn.add_container(container("il_slow")).stack()?;
+    ensure!(timeout(Duration::from_millis(500), cn.run(["il_slow"]))
+        .await
+        .is_err());
+    cn.terminate_all().await;
+    let network_rm = vec![
+        "network".to_owned(),
+        "rm".to_owned(),
+        "fake_slow_create_drop".to_owned(),
+    ];
+    ensure!(fake.invocations().stack()?.contains(&network_rm));
+    Ok(())
+}
+
+#[tokio::test]
+async fn resource_limits() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new("fake_limits", None, LOG_DIR);
+    cn.add_container(container("limits_a")).stack()?;
+    cn.add_container(container("limits_b")).stack()?;
+    let original = cn.get_container("limits_a").stack()?.clone();
+    let limits = ResourceConstraints {
+        max_memory_mb: Some(64),
+        max_cpu_fraction: Some(0.5),
+        max_pids: Some(32),
+    };
+    let invalid = ResourceConstraints {
+        max_cpu_fraction: Some(0.0),
+        ..Default::default()
+    };
+    let e = format!(
+        "{:?}",
+        cn.run_with_resource_limits(&["limits_a"], invalid)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("`max_cpu_fraction` is 0, but it needs to be a positive number"));
+    let e = format!(
+        "{:?}",
+        cn.run_with_resource_limits(&["limits_missing"], limits)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("container \"limits_missing\" is not in the network"));
+
+    cn.run_with_resource_limits(&["limits_a"], limits)
+        .await
+        .stack()?;
+    cn.run(["limits_b"]).await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    // the stored configuration is unchanged
+    ensure_eq!(cn.get_container("limits_a"), Some(&original));
+    let invocations = fake.invocations().stack()?;
+    let create = |name: &str| {
+        invocations
+            .iter()
+            .find(|args| args[0] == "create" && args.contains(&name.to_owned()))
+            .cloned()
+            .stack()
+    };
+    let create_a = create("limits_a")?;
+    ensure!(create_a.windows(2).any(|w| w == ["--memory", "64m"]));
+    ensure!(create_a.windows(2).any(|w| w == ["--memory-swap", "64m"]));
+    ensure!(create_a.windows(2).any(|w| w == ["--cpus", "0.5"]));
+    ensure!(create_a.windows(2).any(|w| w == ["--pids-limit", "32"]));
+    ensure!(!create("limits_b")?.contains(&"--memory".to_owned()));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn expect_results() -> Result<()> {
+    let fake = fake_docker();
+    fake.script("er", &FakeContainer::new().stdout("{\"status\": \"ok\"}\n"))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_er", None, LOG_DIR);
+    cn.add_container(container("er")).stack()?;
+    ensure!(cn.expect("er").is_err());
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    expect_stdout(cn.expect("er").stack()?)
+        .contains("status")
+        .stack()?
+        .json_path("$.status", "ok")
+        .stack()?;
+    ensure!(cn.expect("missing").is_err());
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn deadline_waits() -> Result<()> {
+    let fake = fake_docker();
+    for (name, secs) in [("dl_a", 1), ("dl_b", 1), ("dl_c", 60)] {
+        fake.script(
+            name,
+            &FakeContainer::new().duration(Duration::from_secs(secs)),
+        )
+        .stack()?;
+    }
+    let mut cn = ContainerNetwork::new("fake_dl", None, LOG_DIR);
+    for name in ["dl_a", "dl_b", "dl_c"] {
+        cn.add_container(container(name)).stack()?;
+    }
+    let budget = TestBudget::new(Duration::from_secs(4));
+    cn.run(["dl_a"]).await.stack()?;
+    cn.wait_with_timeout_at(["dl_a"], true, budget.deadline())
+        .await
+        .stack()?;
+    cn.run(["dl_b"]).await.stack()?;
+    cn.wait_with_timeout_at(["dl_b"], true, budget.deadline())
+        .await
+        .stack()?;
+    ensure!(budget.remaining() > Duration::ZERO);
+    cn.run(["dl_c"]).await.stack()?;
+    let e = cn
+        .wait_with_timeout_at(["dl_c"], false, budget.deadline())
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    // the whole sequence respects the budget, with some tolerance for the extra
+    // round
+    ensure!(budget.is_exhausted());
+    ensure!(budget.elapsed() < Duration::from_secs(4) + Duration::from_millis(1500));
+
+    // a deadline that already passed gives a single round like `Duration::ZERO`
+    let start = Instant::now();
+    let e = cn
+        .wait_with_timeout_at(["dl_c"], false, budget.deadline())
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(start.elapsed() < Duration::from_secs(1));
+    cn.terminate_all().await;
+
+    let mut runner = Command::new("sleep 10").run().await.stack()?;
+    let start = Instant::now();
+    ensure!(runner
+        .wait_until(start - Duration::from_secs(1))
+        .await
+        .unwrap_err()
+        .is_timeout());
+    ensure!(start.elapsed() < Duration::from_secs(1));
+    runner.terminate().await.stack()?;
+    let mut runner = Command::new("true").run().await.stack()?;
+    runner
+        .wait_until(Instant::now() + Duration::from_secs(10))
+        .await
+        .stack()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn long_entrypoint_args() -> Result<()> {
+    let fake = fake_docker();
+    let peers: Vec<String> = (0..5000)
+        .map(|i| {
+            format!(
+                "--peer=10.{}.{}.{}:4000",
+                i / 65536,
+                (i / 256) % 256,
+                i % 256
+            )
+        })
+        .collect();
+
+    let mut cn = ContainerNetwork::new("fake_argv", None, LOG_DIR);
+    cn.add_container(container("argv_direct").entrypoint("/bin/entry", &peers))
+        .stack()?;
+    let e = cn.run_all().await.unwrap_err();
+    let too_long = e
+        .stack
+        .iter()
+        .find_map(|(kind, _)| match kind {
+            ErrorKind::BoxedError(e) => e.downcast_ref::<ArgvTooLongError>(),
+            _ => None,
+        })
+        .stack()?;
+    ensure_eq!(too_long.name, "argv_direct");
+    ensure_eq!(too_long.limit, DEFAULT_MAX_CREATE_ARGV_SIZE);
+    ensure!(too_long.size > DEFAULT_MAX_CREATE_ARGV_SIZE);
+    ensure!(format!("{e:?}").contains("entrypoint_args_file"));
+    cn.terminate_all().await;
+
+    let mut cn = ContainerNetwork::new("fake_argv_file", None, LOG_DIR);
+    cn.add_container(
+        container("argv_file")
+            .entrypoint("/bin/entry", &peers)
+            .entrypoint_args_file(),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let invocations = fake.invocations().stack()?;
+    let create = invocations
+        .iter()
+        .find(|args| args[0] == "create" && args.contains(&"argv_file".to_owned()))
+        .stack()?;
+    ensure_eq!(create[create.len() - 3..], [
+        "/bin/entry",
+        "--args-file",
+        ENTRYPOINT_ARGS_FILE
+    ]);
+    let volume = create
+        .windows(2)
+        .find(|w| (w[0] == "--volume") && w[1].ends_with(":/super_orchestrator.args:ro"))
+        .stack()?;
+    let host_path = volume[1]
+        .strip_suffix(":/super_orchestrator.args:ro")
+        .stack()?;
+    ensure_eq!(load_args_file(host_path).stack()?, peers);
+
+    // the limit is configurable
+    let mut cn = ContainerNetwork::new("fake_argv_limit", None, LOG_DIR);
+    cn.add_container(
+        container("argv_limit")
+            .entrypoint("/bin/entry", ["arg0"])
+            .max_create_argv_size("64")
+            .stack()?,
+    )
+    .stack()?;
+    let e = cn.run_all().await.unwrap_err();
+    ensure!(format!("{e:?}").contains("exceeds the `max_create_argv_size` of 64 bytes"));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn paused_network_waits() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "paused_long",
+        &FakeContainer::new().duration(Duration::from_secs(60)),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_paused", None, LOG_DIR);
+    cn.add_container(container("paused_long")).stack()?;
+    ensure!(cn.elapsed_since_first_run().is_none());
+    cn.run_all().await.stack()?;
+
+    // the deadline is respected in virtual time, overshooting by at most one
+    // polling interval
+    let start = Instant::now();
+    let e = cn
+        .wait_with_timeout_at(["paused_long"], false, start + Duration::from_secs(5))
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    let elapsed = start.elapsed();
+    ensure!(elapsed >= Duration::from_secs(5));
+    ensure!(elapsed <= Duration::from_millis(5300));
+    ensure!(cn.elapsed_since_first_run().stack()? >= Duration::from_secs(5));
+
+    // `Duration::ZERO` checks the containers in two rounds without sleeping in
+    // between
+    let start = Instant::now();
+    let e = cn
+        .wait_with_timeout(["paused_long"], false, Duration::ZERO)
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(start.elapsed() < Duration::from_millis(256));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[test]
+fn final_name_combinations() -> Result<()> {
+    let uuid = uuid::Uuid::new_v4();
+    ensure_eq!(
+        final_names(&container("names"), uuid),
+        ("names".to_owned(), "names".to_owned())
+    );
+    for uuid_in_container_name in [false, true] {
+        for uuid_in_host_name in [false, true] {
+            let mut c = container("names")
+                .uuid_in_container_name(uuid_in_container_name)
+                .uuid_in_host_name(uuid_in_host_name);
+            c.container_name = "custom_container".to_owned();
+            c.host_name = "custom.host".to_owned();
+            let with_uuid = |name: &str, uuid_in_name: bool| {
+                if uuid_in_name {
+                    format!("{name}_{uuid}")
+                } else {
+                    name.to_owned()
+                }
+            };
+            ensure_eq!(
+                final_names(&c, uuid),
+                (
+                    with_uuid("custom_container", uuid_in_container_name),
+                    with_uuid("custom.host", uuid_in_host_name)
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn uuid_names() -> Result<()> {
+    let fake = fake_docker();
+    let create_of = |container_name: &str| -> Result<Vec<String>> {
+        fake.invocations()
+            .stack()?
+            .into_iter()
+            .find(|args| {
+                (args[0] == "create")
+                    && args
+                        .windows(2)
+                        .any(|w| (w[0] == "--name") && (w[1] == container_name))
+            })
+            .stack_err(|| format!("no create of {container_name}"))
+    };
+    let hostname_of = |create: &[String]| {
+        create
+            .windows(2)
+            .find(|w| w[0] == "--hostname")
+            .map(|w| w[1].clone())
+    };
+
+    // defaults, the names are used as is
+    let mut cn = ContainerNetwork::new("fake_names_default", None, LOG_DIR);
+    cn.add_container(container("names_default")).stack()?;
+    ensure_eq!(cn.container_name("names_default").stack()?, "names_default");
+    ensure_eq!(cn.host_name("names_default").stack()?, "names_default");
+    ensure_eq!(cn.container_name("nonexistent"), None);
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    ensure_eq!(
+        hostname_of(&create_of("names_default").stack()?).stack()?,
+        "names_default"
+    );
+    cn.terminate_all().await;
+
+    // a fixed hostname for TLS with a UUID'd container name can run in two networks
+    // at once
+    fake.script("names_tls", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    let mut cns = vec![];
+    for network_name in ["fake_names_tls0", "fake_names_tls1"] {
+        let mut cn = ContainerNetwork::new(network_name, None, LOG_DIR);
+        let mut tls = container("names_tls").uuid_in_container_name(true);
+        tls.host_name = "tls.example.com".to_owned();
+        cn.add_container(tls).stack()?;
+        let container_name = format!("names_tls_{}", cn.uuid());
+        ensure_eq!(cn.container_name("names_tls").stack()?, container_name);
+        ensure_eq!(cn.host_name("names_tls").stack()?, "tls.example.com");
+        ensure_eq!(
+            cn.topology().get("names_tls").stack()?.container_name,
+            container_name
+        );
+        cn.run_all().await.stack()?;
+        ensure_eq!(
+            hostname_of(&create_of(&container_name).stack()?).stack()?,
+            "tls.example.com"
+        );
+        ensure!(cn
+            .resources()
+            .get(ResourceKind::Container, &container_name)
+            .is_some());
+        cns.push(cn);
+    }
+    for mut cn in cns {
+        cn.terminate_all().await;
+        let container_name = cn.container_name("names_tls").stack()?;
+        ensure_eq!(
+            cn.resources()
+                .get(ResourceKind::Container, &container_name)
+                .stack()?
+                .status,
+            ResourceStatus::Removed
+        );
+    }
+
+    // fully fixed names collide across networks
+    fake.script("names_fixed", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    let mut cn0 = ContainerNetwork::new("fake_names_fixed0", None, LOG_DIR);
+    cn0.add_container(container("names_fixed")).stack()?;
+    cn0.run_all().await.stack()?;
+    let mut cn1 = ContainerNetwork::new("fake_names_fixed1", None, LOG_DIR);
+    cn1.add_container(container("names_fixed")).stack()?;
+    let e = cn1.run_all().await.unwrap_err();
+    let conflict = e
+        .stack
+        .iter()
+        .find_map(|(kind, _)| match kind {
+            ErrorKind::BoxedError(e) => e.downcast_ref::<NameConflictError>(),
+            _ => None,
+        })
+        .stack()?;
+    ensure_eq!(conflict.container_name, "names_fixed");
+    let e = format!("{e:?}");
+    ensure!(
+        e.contains("the `container_name` \"names_fixed\" is already in use by another container")
+    );
+    ensure!(e.contains("`Container::uuid_in_container_name`"));
+    cn0.terminate_all().await;
+    cn1.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn run_all_with_retries() -> Result<()> {
+    let fake = fake_docker();
+    let backoff = Backoff::constant(Duration::from_millis(10));
+    let creates_of = |container_name: &str| -> Result<usize> {
+        Ok(fake
+            .invocations()
+            .stack()?
+            .iter()
+            .filter(|args| {
+                (args[0] == "create")
+                    && args
+                        .windows(2)
+                        .any(|w| (w[0] == "--name") && (w[1] == container_name))
+            })
+            .count())
+    };
+
+    // the first bring-up fails when creating the second container, the second
+    // succeeds
+    fake.script(
+        "retry_flaky",
+        &FakeContainer::new().transient_create_failures(1),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new_with_uuid("fake_retry", None, LOG_DIR);
+    cn.add_container(container("retry_a")).stack()?;
+    cn.add_container(container("retry_flaky")).stack()?;
+    let first_uuid = cn.uuid();
+    cn.run_all_with_retries(3, backoff, true).await.stack()?;
+    ensure!(cn.uuid() != first_uuid);
+    ensure_eq!(cn.network_name(), format!("fake_retry_{}", cn.uuid()));
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    ensure_eq!(creates_of("retry_a").stack()?, 2);
+    ensure_eq!(creates_of("retry_flaky").stack()?, 2);
+    let invocations = fake.invocations().stack()?;
+    for uuid in [first_uuid, cn.uuid()] {
+        ensure!(invocations
+            .iter()
+            .any(|args| args[..2] == ["network", "create"]
+                && args.last() == Some(&format!("fake_retry_{uuid}"))));
+    }
+    cn.terminate_all().await;
+
+    // persistent failures are retried up to `attempts` times, keeping every error
+    fake.script(
+        "retry_broken",
+        &FakeContainer::new().transient_create_failures(10),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_retry_broken", None, LOG_DIR);
+    cn.add_container(container("retry_broken")).stack()?;
+    let uuid = cn.uuid();
+    let e = format!(
+        "{:?}",
+        cn.run_all_with_retries(3, backoff, false)
+            .await
+            .unwrap_err()
+    );
+    ensure_eq!(cn.uuid(), uuid);
+    ensure_eq!(creates_of("retry_broken").stack()?, 3);
+    for attempt in 1..=3 {
+        ensure!(e.contains(&format!("transient failure {attempt} for")));
+        ensure!(e.contains(&format!(
+            "attempt {attempt} failed in phase Create(\"retry_broken\")"
+        )));
+    }
+    cn.terminate_all().await;
+
+    // the backoff can end the retries first
+    let mut cn = ContainerNetwork::new("fake_retry_backoff", None, LOG_DIR);
+    cn.add_container(container("retry_broken")).stack()?;
+    let e = format!(
+        "{:?}",
+        cn.run_all_with_retries(10, backoff.max_retries(Some(1)), false)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("attempt 2 failed"));
+    ensure!(!e.contains("attempt 3 failed"));
+    cn.terminate_all().await;
+
+    // deterministic failures are not retried
+    let mut cn = ContainerNetwork::new("fake_retry_validation", None, LOG_DIR);
+    cn.add_container(Container::new(
+        "retry_missing",
+        Dockerfile::path("./nonexistent.dockerfile"),
+    ))
+    .stack()?;
+    let e = format!(
+        "{:?}",
+        cn.run_all_with_retries(3, backoff, false)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("attempt 1 failed in phase Precheck, which is not retried"));
+    ensure!(!e.contains("attempt 2"));
+    cn.terminate_all().await;
+
+    // so are creations of an image that does not exist
+    let mut cn = ContainerNetwork::new("fake_retry_typo", None, LOG_DIR);
+    cn.add_container(Container::new(
+        "retry_typo",
+        Dockerfile::name_tag("nonexistent/typo:1"),
+    ))
+    .stack()?;
+    let e = cn
+        .run_all_with_retries(3, backoff, false)
+        .await
+        .unwrap_err();
+    let not_found = e
+        .stack
+        .iter()
+        .find_map(|(kind, _)| match kind {
+            ErrorKind::BoxedError(e) => e.downcast_ref::<ImageNotFoundError>(),
+            _ => None,
+        })
+        .stack()?;
+    ensure_eq!(not_found.image, "nonexistent/typo:1");
+    ensure!(format!("{e:?}")
+        .contains("attempt 1 failed in phase Create(\"retry_typo\"), which is not retried"));
+    ensure_eq!(creates_of("retry_typo").stack()?, 1);
+    cn.terminate_all().await;
+
+    // readiness timeouts are not retried
+    fake.script("retry_unready", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_retry_unready", None, LOG_DIR);
+    cn.add_container(container("retry_unready").readiness(
+        ReadinessProbe::LogLine("never logged".to_owned()),
+        Duration::from_millis(10),
+        TIMEOUT,
+    ))
+    .stack()?;
+    cn.add_container(container("retry_after_unready").start_group(1))
+        .stack()?;
+    cn.start_group_ready_timeout(Some(Duration::from_millis(100)));
+    let e = format!(
+        "{:?}",
+        cn.run_all_with_retries(3, backoff, false)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("attempt 1 failed in phase StartGroupReady(0), which is not retried"));
+    ensure_eq!(creates_of("retry_unready").stack()?, 1);
+    cn.terminate_all().await;
+
+    // unless the docker daemon could not be reached
+    let tmp = concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker_retry_daemon");
+    std::fs::create_dir_all(tmp).unwrap();
+    let mut cn = ContainerNetwork::new("fake_retry_daemon", Some(tmp), LOG_DIR);
+    cn.add_container(Container::new(
+        "retry_daemon",
+        Dockerfile::contents("FROM fake\n# unreachable_daemon\n"),
+    ))
+    .stack()?;
+    let e = cn
+        .run_all_with_retries(2, backoff, false)
+        .await
+        .unwrap_err();
+    ensure!(e.stack.iter().any(|(kind, _)| match kind {
+        ErrorKind::BoxedError(e) => e.downcast_ref::<DaemonUnreachableError>().is_some(),
+        _ => false,
+    }));
+    let e = format!("{e:?}");
+    ensure!(e.contains("attempt 1 failed in phase Build(\"retry_daemon\"), retried"));
+    ensure!(e.contains("Cannot connect to the Docker daemon"));
+    cn.terminate_all().await;
+
+    // a retry only tears down what the call brought up
+    fake.script("retry_kept", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    fake.script(
+        "retry_late",
+        &FakeContainer::new()
+            .duration(TIMEOUT)
+            .transient_create_failures(1),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new_with_uuid("fake_retry_kept", None, LOG_DIR);
+    cn.add_container(container("retry_kept")).stack()?;
+    cn.run_all().await.stack()?;
+    cn.add_container(container("retry_late")).stack()?;
+    let uuid = cn.uuid();
+    cn.run_all_with_retries(3, backoff, true).await.stack()?;
+    // the network is still in use, so it keeps its UUID
+    ensure_eq!(cn.uuid(), uuid);
+    ensure_eq!(cn.active_names(), ["retry_kept", "retry_late"]);
+    ensure_eq!(creates_of("retry_kept").stack()?, 1);
+    ensure_eq!(creates_of("retry_late").stack()?, 2);
+    let invocations = fake.invocations().stack()?;
+    ensure!(!invocations
+        .iter()
+        .any(|args| (args[..2] == ["rm", "-f"]) && (args[2] == fake_id("retry_kept"))));
+    ensure!(!invocations
+        .iter()
+        .any(|args| (args[..2] == ["network", "rm"]) && (args[2] == cn.network_name())));
+    cn.terminate_all().await;
+    Ok(())
+}
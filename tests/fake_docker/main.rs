@@ -0,0 +1,44 @@
+//! Daemon-free tests of `ContainerNetwork` using the `fake_docker` test double,
+//! run with `cargo test --features test_doubles`. The tests are split into
+//! modules by feature area but share one binary, so that the fake is only
+//! installed once.
+
+mod combinators;
+mod images;
+mod lifecycle;
+mod networking;
+mod output;
+mod supervision;
+mod teardown;
+
+use std::{sync::OnceLock, time::Duration};
+
+use super_orchestrator::{
+    docker::{Container, Dockerfile},
+    test_doubles::FakeDocker,
+};
+
+const LOG_DIR: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker_logs");
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Installs the fake docker once for all the tests
+fn fake_docker() -> &'static FakeDocker {
+    static FAKE_DOCKER: OnceLock<FakeDocker> = OnceLock::new();
+    FAKE_DOCKER.get_or_init(|| {
+        std::fs::create_dir_all(LOG_DIR).unwrap();
+        FakeDocker::install(
+            env!("CARGO_BIN_EXE_fake_docker"),
+            concat!(env!("CARGO_TARGET_TMPDIR"), "/fake_docker"),
+        )
+        .unwrap()
+    })
+}
+
+fn container(name: &str) -> Container {
+    Container::new(name, Dockerfile::name_tag("fake:latest"))
+}
+
+/// The fake ID of `name` as printed by `fake_docker create`
+fn fake_id(name: &str) -> String {
+    name.bytes().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,621 @@
+//! Tests of networks, volumes, ports, and the topology
+
+use std::{collections::BTreeMap, time::Duration};
+
+use stacked_errors::{ensure, ensure_eq, Error, ErrorKind, Result, StackableErr};
+use super_orchestrator::{
+    docker::{
+        Container, ContainerNetwork, ContainerStatus, NetworkDiff, NetworkMode, NotOnNetworkError,
+        ReadinessProbe, VolumeOption, DIND_IMAGE, TOPOLOGY_ENV_VAR, TOPOLOGY_FILE,
+    },
+    docker_helpers::DEFAULT_DOCKER_SOCKET,
+    load_topology,
+    test_doubles::FakeContainer,
+    wait_for_ok, Command,
+};
+
+use crate::{container, fake_docker, LOG_DIR, TIMEOUT};
+
+#[tokio::test]
+async fn network_modes() -> Result<()> {
+    let fake = fake_docker();
+    // a free port for the host network container to bind
+    let host_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .stack()?
+        .local_addr()
+        .stack()?
+        .port();
+    // a server on the host for the outbound connections
+    let server = std::net::TcpListener::bind("127.0.0.1:0").stack()?;
+    let server_addr = server.local_addr().stack()?;
+    fake.script(
+        "nm_host",
+        &FakeContainer::new()
+            .host_port(host_port)
+            .duration(Duration::from_secs(2)),
+    )
+    .stack()?;
+    for name in ["nm_none", "nm_default"] {
+        fake.script(name, &FakeContainer::new().connect_to(server_addr))
+            .stack()?;
+    }
+    let mut cn = ContainerNetwork::new("fake_network_modes", None, LOG_DIR);
+    cn.add_container(
+        container("nm_host")
+            .network_mode(NetworkMode::Host)
+            .readiness(
+                ReadinessProbe::TcpPort(host_port),
+                Duration::from_millis(50),
+                TIMEOUT,
+            ),
+    )
+    .stack()?;
+    cn.add_container(container("nm_none").network_mode(NetworkMode::None))
+        .stack()?;
+    cn.add_container(container("nm_default")).stack()?;
+    cn.run_all().await.stack()?;
+
+    // the readiness probe of the host network container goes through localhost
+    cn.wait_ready(&["nm_host"], TIMEOUT).await.stack()?;
+    std::net::TcpStream::connect(("127.0.0.1", host_port)).stack()?;
+    let e = cn
+        .wait_get_ip_addr(1, Duration::ZERO, "nm_host")
+        .await
+        .unwrap_err();
+    let not_on_network = e
+        .stack
+        .iter()
+        .find_map(|(kind, _)| match kind {
+            ErrorKind::BoxedError(e) => e.downcast_ref::<NotOnNetworkError>(),
+            _ => None,
+        })
+        .stack()?;
+    ensure_eq!(not_on_network, &NotOnNetworkError {
+        name: "nm_host".to_owned(),
+        network_mode: NetworkMode::Host,
+    });
+    ensure_eq!(cn.host_name("nm_host"), None);
+    ensure_eq!(cn.host_name("nm_default").as_deref(), Some("nm_default"));
+    let topology = cn.topology();
+    ensure_eq!(
+        topology
+            .containers
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["nm_default"]
+    );
+    ensure!(cn.describe().contains("network_host"));
+
+    let creates: BTreeMap<String, Vec<String>> = fake
+        .invocations()
+        .stack()?
+        .into_iter()
+        .filter(|args| args[0] == "create")
+        .filter_map(|args| {
+            let i = args.iter().position(|arg| arg == "--name")?;
+            Some((args[i + 1].clone(), args))
+        })
+        .filter(|(name, _)| name.starts_with("nm_"))
+        .collect();
+    for (name, network) in [
+        ("nm_host", "host"),
+        ("nm_none", "none"),
+        ("nm_default", "fake_network_modes"),
+    ] {
+        let args = &creates[name];
+        let i = args.iter().position(|arg| arg == "--network").stack()?;
+        ensure!(args[i + 1].starts_with(network));
+        ensure_eq!(
+            args.contains(&"--hostname".to_owned()),
+            name == "nm_default"
+        );
+    }
+
+    // the outbound connection only fails without a network, and the
+    // containers still take part in the waits and teardown
+    cn.wait_with_timeout_all(false, TIMEOUT).await.stack()?;
+    let comres = cn.get_result("nm_default").unwrap().as_ref().unwrap();
+    ensure!(comres.successful());
+    ensure!(comres.stdout_as_utf8().stack()?.contains("connected to"));
+    let e = format!("{:?}", cn.get_result("nm_none").stack()?);
+    ensure!(e.contains("Network is unreachable"));
+    cn.terminate_all().await;
+    ensure!(cn.active_names().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn volume_options() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new("fake_volume_options", None, LOG_DIR);
+    cn.add_container(
+        container("vo_a")
+            .volume_with_options("./Cargo.toml", "/Cargo.toml", [
+                VolumeOption::ReadOnly,
+                VolumeOption::SelinuxPrivate,
+            ])
+            .volume_with_options("./src", "/src", [
+                VolumeOption::RSlave,
+                VolumeOption::Other("nocopy".to_owned()),
+            ])
+            .volume_with_options("./tests", "/tests", []),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+
+    // the local paths are canonicalized with the options appended
+    let create = fake
+        .invocations()
+        .stack()?
+        .into_iter()
+        .find(|args| (args[0] == "create") && args.contains(&"vo_a".to_owned()))
+        .stack()?;
+    let volumes: Vec<&str> = create
+        .windows(2)
+        .filter(|w| w[0] == "--volume")
+        .map(|w| w[1].as_str())
+        .collect();
+    let canonical = |path: &str| {
+        std::fs::canonicalize(path)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    };
+    ensure_eq!(volumes, vec![
+        format!("{}:/Cargo.toml:ro,Z", canonical("./Cargo.toml")),
+        format!("{}:/src:rslave,nocopy", canonical("./src")),
+        format!("{}:/tests", canonical("./tests")),
+    ]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn network_state_diff() -> Result<()> {
+    let fake = fake_docker();
+    for name in ["nsd_a", "nsd_b"] {
+        fake.script(
+            name,
+            &FakeContainer::new().duration(Duration::from_secs(60)),
+        )
+        .stack()?;
+    }
+    let mut cn = ContainerNetwork::new("fake_nsd", None, LOG_DIR);
+    cn.add_container(container("nsd_a")).stack()?;
+    cn.add_container(container("nsd_b")).stack()?;
+    let before = cn.take_snapshot().await;
+    ensure!(before
+        .containers
+        .values()
+        .all(|c| c.status == ContainerStatus::Inactive && c.ip_addr.is_none()));
+    ensure!(cn.diff_network_state(&before).await.is_empty());
+
+    cn.run_all().await.stack()?;
+    let running = cn.take_snapshot().await;
+    let ip_addr = running.containers["nsd_a"].ip_addr;
+    ensure_eq!(ip_addr, Some("172.18.0.2".parse().unwrap()));
+    ensure_eq!(ContainerNetwork::diff(&before, &running), NetworkDiff {
+        started: vec!["nsd_a".to_owned(), "nsd_b".to_owned()],
+        ..Default::default()
+    });
+
+    cn.try_terminate(["nsd_a"]).await;
+    let after = cn.take_snapshot().await;
+    ensure_eq!(
+        after.containers["nsd_a"].status,
+        ContainerStatus::PostActive
+    );
+    ensure_eq!(ContainerNetwork::diff(&running, &after), NetworkDiff {
+        stopped: vec!["nsd_a".to_owned()],
+        ..Default::default()
+    });
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn internal_network() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new("fake_internal", None, LOG_DIR);
+    cn.add_container(container("internal_a")).stack()?;
+    ensure!(!cn.is_internal());
+    cn.internal(true).add_network_args(["--internal=false"]);
+    ensure!(cn.is_internal());
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains(
+        "`network_args` contains \"--internal=false\", which conflicts with \
+         `ContainerNetwork::internal(true)`"
+    ));
+    ensure!(e.contains("failed in phase Precheck"));
+    cn.network_args.clear();
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    let invocations = fake.invocations().stack()?;
+    let create = invocations
+        .iter()
+        .find(|args| {
+            args.starts_with(&["network".to_owned(), "create".to_owned()])
+                && args.last().is_some_and(|name| name == "fake_internal")
+        })
+        .stack()?;
+    ensure_eq!(create[2..], ["--internal", "fake_internal"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn vlan_networks() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new("fake_ipvlan", None, LOG_DIR);
+    cn.ipvlan_network("eth0")
+        .add_network_args(["--subnet", "192.168.1.0/24"]);
+    cn.add_container(container("ipvlan_a")).stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+    let invocations = fake.invocations().stack()?;
+    ensure!(invocations.contains(
+        &[
+            "network",
+            "create",
+            "--driver",
+            "ipvlan",
+            "--opt",
+            "parent=eth0",
+            "--subnet",
+            "192.168.1.0/24",
+            "fake_ipvlan"
+        ]
+        .map(|s| s.to_owned())
+        .to_vec()
+    ));
+
+    let mut cn = ContainerNetwork::new("fake_macvlan", None, LOG_DIR);
+    cn.macvlan_network("enp3s0");
+    ensure_eq!(cn.network_args, [
+        "--driver",
+        "macvlan",
+        "--opt",
+        "parent=enp3s0"
+    ]);
+    cn.ipvlan_network("eth0");
+    cn.add_container(container("macvlan_a")).stack()?;
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("`network_args` set more than one driver: [\"macvlan\", \"ipvlan\"]"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn in_docker() -> Result<()> {
+    let fake = fake_docker();
+    let comres = Command::in_docker_image("alpine:3.20", &[("/host", "/data")], &[
+        "echo", "hello", "image",
+    ])
+    .run_to_completion()
+    .await
+    .stack()?;
+    comres.assert_success().stack()?;
+    ensure_eq!(comres.stdout_as_utf8().stack()?, "hello image\n");
+    ensure!(fake.invocations().stack()?.contains(
+        &[
+            "run",
+            "--rm",
+            "--volume",
+            "/host:/data",
+            "alpine:3.20",
+            "echo",
+            "hello",
+            "image"
+        ]
+        .map(|s| s.to_owned())
+        .to_vec()
+    ));
+
+    fake.script(
+        "in_docker_a",
+        &FakeContainer::new().duration(Duration::from_secs(5)),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_in_docker", None, LOG_DIR);
+    cn.add_container(container("in_docker_a")).stack()?;
+    cn.run_all().await.stack()?;
+    let id = cn.get_active_container_ids()["in_docker_a"].clone();
+    // wait for `docker start` to mark the container as running
+    let comres = wait_for_ok(50, Duration::from_millis(50), || async {
+        let comres = Command::in_docker_container(&id, &["echo", "hello", "container"])
+            .run_to_completion()
+            .await?;
+        comres.assert_success()?;
+        Ok(comres)
+    })
+    .await
+    .stack()?;
+    ensure_eq!(comres.stdout_as_utf8().stack()?, "hello container\n");
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn external_containers() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new("fake_external", None, LOG_DIR);
+    cn.add_container(container("ec")).stack()?;
+    // the network does not exist yet
+    ensure!(cn
+        .connect_external_container("ec_external", None)
+        .await
+        .is_err());
+    cn.run_all().await.stack()?;
+    cn.connect_external_container("ec_external", Some("external"))
+        .await
+        .stack()?;
+    cn.connect_external_container("ec_external2", None)
+        .await
+        .stack()?;
+    cn.disconnect_external_container("ec_external2")
+        .await
+        .stack()?;
+    ensure!(cn
+        .disconnect_external_container("ec_external2")
+        .await
+        .is_err());
+    cn.terminate_all().await;
+    let invocations: Vec<Vec<String>> = fake
+        .invocations()
+        .stack()?
+        .into_iter()
+        .filter(|args| args[0] == "network" && args.contains(&"fake_external".to_owned()))
+        .collect();
+    ensure_eq!(invocations[1..], [
+        vec![
+            "network",
+            "connect",
+            "--alias",
+            "external",
+            "fake_external",
+            "ec_external"
+        ],
+        vec!["network", "connect", "fake_external", "ec_external2"],
+        vec!["network", "disconnect", "fake_external", "ec_external2"],
+        // the remaining external container is disconnected before the network is removed
+        vec![
+            "network",
+            "disconnect",
+            "--force",
+            "fake_external",
+            "ec_external"
+        ],
+        vec!["network", "rm", "fake_external"],
+    ]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn host_entries() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new("fake_he", None, LOG_DIR);
+    cn.add_container(container("he_a").add_host("a.example", "10.0.0.1".parse().unwrap()))
+        .stack()?;
+    cn.add_container(container("he_b")).stack()?;
+    cn.inject_host_entries(&[
+        ("common.example", "10.0.0.2".parse().unwrap()),
+        ("v6.example", "fd00::1".parse().unwrap()),
+    ]);
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let invocations = fake.invocations().stack()?;
+    let add_hosts = |name: &str| -> Result<Vec<String>> {
+        let create = invocations
+            .iter()
+            .find(|args| args[0] == "create" && args.contains(&name.to_owned()))
+            .stack()?;
+        Ok(create
+            .windows(2)
+            .filter(|w| w[0] == "--add-host")
+            .map(|w| w[1].clone())
+            .collect())
+    };
+    ensure_eq!(add_hosts("he_a")?, [
+        "a.example:10.0.0.1",
+        "common.example:10.0.0.2",
+        "v6.example:fd00::1"
+    ]);
+    ensure_eq!(add_hosts("he_b")?, [
+        "common.example:10.0.0.2",
+        "v6.example:fd00::1"
+    ]);
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn port_forward() -> Result<()> {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    // an echo server on the host stands in for the service in the container
+    let echo = TcpListener::bind("127.0.0.1:0").await.stack()?;
+    let echo_port = echo.local_addr().stack()?.port();
+    tokio::task::spawn(async move {
+        while let Ok((mut stream, _)) = echo.accept().await {
+            tokio::task::spawn(async move {
+                let (mut read, mut write) = stream.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+
+    let fake = fake_docker();
+    fake.script(
+        "forwarded",
+        &FakeContainer::new()
+            .duration(TIMEOUT)
+            .ip_addr("127.0.0.1".parse().unwrap()),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_forward", None, LOG_DIR);
+    cn.internal(true);
+    cn.add_container(container("forwarded")).stack()?;
+    ensure!(cn.forward_port("forwarded", echo_port).await.is_err());
+    cn.run_all().await.stack()?;
+
+    let round_trip = |addr| async move {
+        let mut stream = TcpStream::connect(addr).await.stack()?;
+        stream.write_all(b"hello forward").await.stack()?;
+        let mut buf = [0u8; 13];
+        stream.read_exact(&mut buf).await.stack()?;
+        ensure_eq!(&buf, b"hello forward");
+        Ok::<_, Error>(())
+    };
+    let handle = cn.forward_port("forwarded", echo_port).await.stack()?;
+    let local_addr = handle.local_addr();
+    ensure!(local_addr.ip().is_loopback());
+    ensure!(handle.relay_id().is_none());
+    // more than one connection
+    round_trip(local_addr).await.stack()?;
+    round_trip(local_addr).await.stack()?;
+    handle.stop().await.stack()?;
+    ensure!(round_trip(local_addr).await.is_err());
+
+    // `terminate_all` stops the forwards that are still running
+    let handle = cn.forward_port("forwarded", echo_port).await.stack()?;
+    let local_addr = handle.local_addr();
+    round_trip(local_addr).await.stack()?;
+    cn.terminate_all().await;
+    ensure!(round_trip(local_addr).await.is_err());
+    drop(handle);
+    ensure!(cn.last_teardown_report().is_ok());
+    Ok(())
+}
+
+#[tokio::test]
+async fn topology() -> Result<()> {
+    let fake = fake_docker();
+    let mut cn = ContainerNetwork::new_with_uuid("fake_topology", None, LOG_DIR);
+    let uuid = cn.uuid_as_string();
+    let server = container("topo_server")
+        .create_args(["-p", "127.0.0.1::8080"])
+        .label("role", "server")
+        .uuid_in_host_name(true);
+    let client = container("topo_client")
+        .label("role", "client")
+        .uuid_in_host_name(true);
+    cn.add_container(server).stack()?;
+    cn.add_container(client).stack()?;
+    cn.inject_topology(true);
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+
+    let invocations = fake.invocations().stack()?;
+    let create = invocations
+        .iter()
+        .find(|args| (args[0] == "create") && args.contains(&"topo_client".to_owned()))
+        .stack()?;
+    ensure!(create.windows(2).any(|w| w
+        == [
+            "-e".to_owned(),
+            format!("{TOPOLOGY_ENV_VAR}={TOPOLOGY_FILE}")
+        ]));
+    ensure!(create.windows(2).any(|w| w == ["--label", "role=client"]));
+    let volume = create
+        .iter()
+        .find_map(|arg| arg.strip_suffix(&format!(":{TOPOLOGY_FILE}:ro")))
+        .stack()?;
+
+    // what an entrypoint would see
+    std::env::set_var(TOPOLOGY_ENV_VAR, volume);
+    let injected = load_topology().stack()?;
+    ensure_eq!(injected, cn.topology());
+    ensure_eq!(injected.network_name, format!("fake_topology_{uuid}"));
+    ensure_eq!(injected.containers.len(), 2);
+    let server = injected.get("topo_server").stack()?;
+    ensure_eq!(server.host_name, format!("topo_server_{uuid}"));
+    ensure_eq!(server.ports, [8080]);
+    ensure_eq!(
+        injected
+            .with_label("role", "client")
+            .map(|entry| entry.host_name.as_str())
+            .collect::<Vec<_>>(),
+        [format!("topo_client_{uuid}")]
+    );
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn docker_socket() -> Result<()> {
+    let fake = fake_docker();
+    // stands in for the socket, only its group is looked at
+    let socket = format!("{LOG_DIR}/fake_docker.sock");
+    std::fs::write(&socket, "").stack()?;
+    let mut cn = ContainerNetwork::new("fake_docker_socket", None, LOG_DIR);
+    let mut tool = container("sock_tool").with_docker_socket();
+    ensure!(tool.docker_socket.is_some());
+    tool.docker_socket = Some(socket.clone());
+    cn.add_container(tool).stack()?;
+    cn.add_container(
+        container("sock_pinned")
+            .with_docker_socket()
+            .docker_socket_group_add(false)
+            .pinned_digest(format!("sha256:{}", "0".repeat(64))),
+    )
+    .stack()?;
+    cn.add_container(Container::dind("sock_dind")).stack()?;
+
+    // only the unpinned image is warned about
+    let report = cn.validate().await.stack()?;
+    ensure_eq!(report.warnings.len(), 1);
+    ensure_eq!(report.warnings[0].name.as_deref(), Some("sock_tool"));
+    ensure!(report.warnings[0]
+        .message
+        .contains("MOUNTS THE HOST DOCKER SOCKET"));
+
+    cn.run(["sock_tool", "sock_dind"]).await.stack()?;
+    let invocations = fake.invocations().stack()?;
+    let create = |name: &str| {
+        invocations
+            .iter()
+            .find(|args| (args[0] == "create") && args.contains(&name.to_owned()))
+            .cloned()
+            .stack()
+    };
+    let tool = create("sock_tool")?;
+    ensure!(tool.windows(2).any(|w| w
+        == [
+            "--volume".to_owned(),
+            format!("{socket}:{DEFAULT_DOCKER_SOCKET}")
+        ]));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let gid = std::fs::metadata(&socket).stack()?.gid().to_string();
+        ensure!(tool
+            .windows(2)
+            .any(|w| w == ["--group-add".to_owned(), gid.clone()]));
+    }
+    let dind = create("sock_dind")?;
+    ensure!(dind.contains(&"--privileged".to_owned()));
+    ensure!(dind.windows(2).any(|w| w
+        == [
+            "--volume",
+            "super_orchestrator_dind_sock_dind:/var/lib/docker"
+        ]));
+    ensure!(dind.contains(&DIND_IMAGE.to_owned()));
+    ensure!(!dind.contains(&"--group-add".to_owned()));
+
+    // audits can find the containers with access to the host daemon
+    let flagged: Vec<&str> = cn
+        .resources()
+        .with_daemon_access()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    ensure_eq!(flagged, ["sock_tool"]);
+    ensure!(cn.describe().contains("docker_socket"));
+    cn.terminate_all().await;
+    Ok(())
+}
@@ -0,0 +1,638 @@
+//! Tests of container output, logs, inspection, and streaming files
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    docker::{Container, ContainerNetwork, Dockerfile, PullPolicy, ResourceKind},
+    docker_helpers::FsChange,
+    test_doubles::FakeContainer,
+    wait_for_ok, Command, DebugOutput, FileOptions,
+};
+use tokio::time::Instant;
+
+use crate::{container, fake_docker, fake_id, LOG_DIR, TIMEOUT};
+
+#[tokio::test]
+async fn json_lines_stream() -> Result<()> {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Event {
+        i: u64,
+    }
+
+    let fake = fake_docker();
+    let delay = Duration::from_millis(500);
+    fake.script(
+        "jl",
+        &FakeContainer::new()
+            .stdout_chunk(delay, "{\"i\": 0}\n")
+            .stdout_chunk(delay, "not json\n{\"i\": 1}\n")
+            .stdout_chunk(delay, "\n{\"i\": 2}"),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_jl", None, LOG_DIR);
+    cn.add_container(container("jl")).stack()?;
+    cn.run_all().await.stack()?;
+    let mut receiver = cn.json_lines_stream::<Event>("jl").stack()?;
+    let start = Instant::now();
+    ensure_eq!(receiver.recv().await.stack()?.stack()?, Event { i: 0 });
+    // received incrementally, not all at the end
+    ensure!(start.elapsed() < delay * 2);
+    let e = format!("{:?}", receiver.recv().await.stack()?.unwrap_err());
+    ensure!(e.contains("not json"));
+    ensure_eq!(receiver.recv().await.stack()?.stack()?, Event { i: 1 });
+    ensure!(start.elapsed() < delay * 3);
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    // the last line does not need a newline
+    ensure_eq!(receiver.recv().await.stack()?.stack()?, Event { i: 2 });
+    ensure!(receiver.recv().await.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn tracing_debug_output() -> Result<()> {
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let fake = fake_docker();
+    fake.script(
+        "dt",
+        &FakeContainer::new().stdout("hello\nworld").stderr("oops\n"),
+    )
+    .stack()?;
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    // the test runtime is single threaded, so the recording tasks also use this
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .json()
+            .with_writer(move || writer.clone())
+            .finish(),
+    );
+    let mut cn = ContainerNetwork::new("fake_dt", None, LOG_DIR);
+    cn.add_container(container("dt").debug_output(DebugOutput::Tracing))
+        .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).stack()?;
+    let mut lines = vec![];
+    for line in output.lines() {
+        let event: serde_json::Value = serde_json::from_str(line).stack()?;
+        if event["span"]["name"] == "container" {
+            ensure_eq!(event["span"]["container.name"], "dt");
+            ensure_eq!(event["span"]["network.name"], "fake_dt");
+            lines.push((
+                event["fields"]["stream"].as_str().stack()?.to_owned(),
+                event["fields"]["message"].as_str().stack()?.to_owned(),
+            ));
+        }
+    }
+    lines.sort();
+    ensure_eq!(lines, [
+        ("stderr".to_owned(), "oops".to_owned()),
+        ("stdout".to_owned(), "hello".to_owned()),
+        ("stdout".to_owned(), "world".to_owned())
+    ]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn fs_diff() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "fs_diff",
+        &FakeContainer::new().duration(Duration::from_secs(10)),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_fs_diff", None, LOG_DIR);
+    cn.add_container(container("fs_diff")).stack()?;
+    let e = format!("{:?}", cn.fs_diff("fs_diff").await.unwrap_err());
+    ensure!(e.contains("was never run or has been terminated"));
+    cn.run_all().await.stack()?;
+    ensure_eq!(cn.fs_diff("fs_diff").await.stack()?, vec![]);
+    let id = cn.get_active_container_ids()["fs_diff"].clone();
+    // wait for `docker start` to mark the container as running
+    wait_for_ok(50, Duration::from_millis(50), || async {
+        Command::in_docker_container(&id, &["touch", "/etc/app.toml", "/tmp/ready"])
+            .run_to_completion()
+            .await?
+            .assert_success()
+    })
+    .await
+    .stack()?;
+    ensure_eq!(cn.fs_diff("fs_diff").await.stack()?, vec![
+        FsChange::Added("/etc/app.toml".to_owned()),
+        FsChange::Added("/tmp/ready".to_owned()),
+    ]);
+    cn.assert_created("fs_diff", "/etc/app.toml")
+        .await
+        .stack()?;
+    let e = format!(
+        "{:?}",
+        cn.assert_created("fs_diff", "/etc/other.toml")
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("did not create \"/etc/other.toml\""));
+    ensure!(e.contains("/tmp/ready"));
+
+    // simulates docker removing an `--rm` container after it exits
+    Command::new_os_str(env!("CARGO_BIN_EXE_fake_docker"))
+        .args(["rm", "-f", &id])
+        .run_to_completion()
+        .await
+        .stack()?
+        .assert_success()
+        .stack()?;
+    let e = format!("{:?}", cn.fs_diff("fs_diff").await.unwrap_err());
+    ensure!(e.contains("only running or kept containers can be diffed"));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+/// Generates `remaining` pseudorandom bytes
+struct PatternReader {
+    remaining: u64,
+    state: u64,
+}
+
+impl tokio::io::AsyncRead for PatternReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let len = usize::try_from(self.remaining)
+            .unwrap_or(usize::MAX)
+            .min(buf.remaining())
+            .min(64 * 1024);
+        let mut chunk = Vec::with_capacity(len + 8);
+        while chunk.len() < len {
+            // xorshift
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            chunk.extend_from_slice(&self.state.to_le_bytes());
+        }
+        buf.put_slice(&chunk[..len]);
+        self.remaining -= len as u64;
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+/// Hashes everything written to it
+#[derive(Default)]
+struct HashWriter(Sha256);
+
+impl tokio::io::AsyncWrite for HashWriter {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        self.0.update(buf);
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+impl HashWriter {
+    fn hex(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn streaming_files() -> Result<()> {
+    const SIZE: u64 = 100 * 1024 * 1024;
+    let fake = fake_docker();
+    for name in ["stream_a", "stream_distroless"] {
+        fake.script(
+            name,
+            &FakeContainer::new().duration(Duration::from_secs(300)),
+        )
+        .stack()?;
+    }
+    let mut cn = ContainerNetwork::new("fake_stream", None, LOG_DIR);
+    cn.add_container(container("stream_a")).stack()?;
+    cn.add_container(container("stream_distroless")).stack()?;
+    let e = format!(
+        "{:?}",
+        cn.stream_to_file("stream_a", &b"x"[..], "/x", 0o644)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("was never run or has been terminated"));
+    cn.run_all().await.stack()?;
+    // wait for `docker start` to mark the containers as running
+    for name in ["stream_a", "stream_distroless"] {
+        wait_for_ok(50, Duration::from_millis(50), || {
+            cn.stream_to_file(name, &b"ready"[..], "/tmp/ready", 0o600)
+        })
+        .await
+        .stack()?;
+    }
+
+    let pattern = || PatternReader {
+        remaining: SIZE,
+        state: 0x2545f4914f6cdd1d,
+    };
+    let mut expected = HashWriter::default();
+    tokio::io::copy(&mut pattern(), &mut expected)
+        .await
+        .stack()?;
+    let expected = expected.hex();
+    let sent = cn
+        .stream_to_file("stream_a", pattern(), "/fixtures/big.bin", 0o644)
+        .await
+        .stack()?;
+    ensure_eq!(sent.bytes, SIZE);
+    ensure_eq!(sent.sha256, expected);
+    cn.verify_sha256("stream_a", "/fixtures/big.bin", &expected)
+        .await
+        .stack()?;
+    let mut writer = HashWriter::default();
+    let received = cn
+        .stream_from_file("stream_a", "/fixtures/big.bin", &mut writer)
+        .await
+        .stack()?;
+    ensure_eq!(received, sent);
+    ensure_eq!(writer.hex(), expected);
+    let id = fake_id("stream_a");
+    ensure!(fake.invocations().stack()?.iter().any(|args| {
+        args.starts_with(&[
+            "exec".to_owned(),
+            "-i".to_owned(),
+            id.clone(),
+            "sh".to_owned(),
+        ]) && args.ends_with(&["/fixtures/big.bin".to_owned(), "644".to_owned()])
+    }));
+
+    let e = format!(
+        "{:?}",
+        cn.verify_sha256("stream_a", "/tmp/ready", &expected)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("but") && e.contains("was expected"));
+    let e = format!(
+        "{:?}",
+        cn.stream_from_file("stream_a", "/missing", tokio::io::sink())
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("No such file or directory"));
+
+    // falls back to `cp /dev/stdin` without a shell
+    let sent = cn
+        .stream_to_file("stream_distroless", &b"no shell"[..], "/data", 0o644)
+        .await
+        .stack()?;
+    ensure_eq!(sent.bytes, 8);
+    let id = fake_id("stream_distroless");
+    ensure!(fake.invocations().stack()?.contains(&vec![
+        "exec".to_owned(),
+        "-i".to_owned(),
+        id,
+        "cp".to_owned(),
+        "/dev/stdin".to_owned(),
+        "/data".to_owned()
+    ]));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn inspect_cache() -> Result<()> {
+    let fake = fake_docker();
+    let inspects = Arc::new(Mutex::new(0usize));
+    let mut cn = ContainerNetwork::new("fake_inspect_cache", Some(LOG_DIR), LOG_DIR);
+    cn.command_middleware({
+        let inspects = inspects.clone();
+        move |command: Command| {
+            if command.args.first().is_some_and(|arg| arg == "inspect") {
+                *inspects.lock().unwrap() += 1;
+            }
+            command
+        }
+    });
+    // long enough that only the retries refresh the snapshot
+    cn.inspect_max_staleness = Duration::from_secs(60);
+    let names = ["ic_0", "ic_1", "ic_2"];
+    for name in names {
+        fake.script(
+            name,
+            &FakeContainer::new()
+                .duration(Duration::from_secs(5))
+                .ip_addr_after(Duration::from_secs(2)),
+        )
+        .stack()?;
+        cn.add_container(container(name)).stack()?;
+    }
+    cn.run_all().await.stack()?;
+
+    // one `docker inspect` serves every query of every container
+    for _ in 0..3 {
+        for name in names {
+            cn.status(name).await.stack()?;
+        }
+    }
+    ensure_eq!(*inspects.lock().unwrap(), 1);
+    // the cached snapshot has no IP address yet, so the first attempt fails and
+    // the retries refresh it
+    ensure!(cn
+        .wait_get_ip_addr(0, Duration::ZERO, "ic_0")
+        .await
+        .is_err());
+    cn.wait_get_ip_addr(200, Duration::from_millis(50), "ic_0")
+        .await
+        .stack()?;
+    let after_retries = *inspects.lock().unwrap();
+    ensure!(after_retries > 1);
+    // and the other containers are served from the refreshed snapshot
+    cn.status("ic_1").await.stack()?;
+    ensure_eq!(*inspects.lock().unwrap(), after_retries);
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn command_middleware() -> Result<()> {
+    fake_docker();
+    let recorded = Arc::new(Mutex::new(Vec::<Vec<String>>::new()));
+    let mut cn = ContainerNetwork::new("fake_middleware", Some(LOG_DIR), LOG_DIR);
+    cn.command_middleware({
+        let recorded = recorded.clone();
+        move |command: Command| {
+            let mut argv = vec![command.program.to_string_lossy().into_owned()];
+            argv.extend(
+                command
+                    .args
+                    .iter()
+                    .map(|arg| arg.to_string_lossy().into_owned()),
+            );
+            recorded.lock().unwrap().push(argv);
+            command
+        }
+    });
+    cn.add_container(Container::new(
+        "mw_built",
+        Dockerfile::contents("FROM fake:middleware\n"),
+    ))
+    .stack()?;
+    cn.add_container(container("mw_a").entrypoint("/bin/entry", ["arg0"]))
+        .stack()?;
+    cn.run_all().await.stack()?;
+    cn.inspect("mw_a").await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+
+    let recorded = recorded.lock().unwrap().clone();
+    let subcommands: Vec<String> = recorded.iter().map(|argv| argv[1..].join(" ")).collect();
+    for expected in [
+        "network create fake_middleware",
+        "start --attach",
+        "inspect",
+        "rm -f",
+        "network rm fake_middleware",
+    ] {
+        ensure!(
+            subcommands.iter().any(|s| s.starts_with(expected)),
+            "{expected}"
+        );
+    }
+    // the middleware sees the final arguments
+    ensure!(subcommands
+        .iter()
+        .any(|s| s.starts_with("build -t super_orchestrator_mw_built_")));
+    ensure!(subcommands
+        .iter()
+        .any(|s| s.starts_with("create --rm") && s.ends_with("fake:latest /bin/entry arg0")));
+
+    // a failing wrapper surfaces as a normal error
+    let mut cn = ContainerNetwork::new("fake_middleware_fail", None, LOG_DIR);
+    cn.command_middleware(|mut command: Command| {
+        command.program = "false".into();
+        command
+    });
+    cn.add_container(container("mw_fail")).stack()?;
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("failed to create network"));
+    ensure!(cn.active_names().is_empty());
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn single_container_runner() -> Result<()> {
+    let fake = fake_docker();
+    fake.script("single_compat", &FakeContainer::new().stdout("compat"))
+        .stack()?;
+    fake.script("single_split", &FakeContainer::new().stdout("split"))
+        .stack()?;
+    fake.script("single_slow", &FakeContainer::new()).stack()?;
+    let network_invoked = |subcommand: &str, prefix: &str| -> Result<bool> {
+        Ok(fake.invocations()?.iter().any(|args| {
+            (args[..2] == ["network", subcommand]) && args.last().unwrap().starts_with(prefix)
+        }))
+    };
+    let network_removed = |prefix: &str| network_invoked("rm", prefix);
+
+    // the old signature keeps its defaults
+    let comres = container("single_compat")
+        .run(None, TIMEOUT, LOG_DIR, false)
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    ensure_eq!(comres.stdout_as_utf8().stack()?, "compat");
+    ensure!(network_removed("super_orchestrator_")?);
+
+    // the slow pull only counts against the `build_timeout`, not the `timeout`
+    let slow = Dockerfile::name_tag("single.example.com/slow_pull:1");
+    let start = Instant::now();
+    let comres = Container::new("single_split", slow.clone())
+        .runner()
+        .network_name("fake_single_split")
+        .log_dir(LOG_DIR)
+        .pull_policy(PullPolicy::Always)
+        .build_timeout(TIMEOUT)
+        .timeout(Duration::from_millis(500))
+        .run()
+        .await
+        .stack()?;
+    ensure!(start.elapsed() >= Duration::from_secs(2));
+    ensure_eq!(comres.stdout_as_utf8().stack()?, "split");
+    ensure!(network_removed("fake_single_split_")?);
+
+    // a `build_timeout` shorter than the pull times out and tears down the network
+    let start = Instant::now();
+    let e = Container::new("single_slow", slow)
+        .runner()
+        .network_name("fake_single_slow")
+        .log_dir(LOG_DIR)
+        .pull_policy(PullPolicy::Always)
+        .build_timeout(Duration::from_millis(500))
+        .timeout(TIMEOUT)
+        .run()
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(start.elapsed() < Duration::from_secs(2));
+    ensure!(format!("{e:?}").contains("before the container started"));
+    // the pull happens before the network is created, the timeout must not leave
+    // anything behind
+    ensure!(!network_invoked("create", "fake_single_slow_")?);
+    ensure!(!fake
+        .invocations()?
+        .iter()
+        .any(|args| (args[0] == "create") && args.contains(&"single_slow".to_owned())));
+    Ok(())
+}
+
+#[tokio::test]
+async fn combined_container_log() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "combined_log",
+        &FakeContainer::new().stdout("to stdout").stderr("to stderr"),
+    )
+    .stack()?;
+    let combined = format!("{LOG_DIR}/combined_log_combined.log");
+    let mut cn = ContainerNetwork::new("fake_combined_log", None, LOG_DIR);
+    cn.add_container(
+        container("combined_log")
+            .log(true)
+            .combined_log(Some(FileOptions::write(&combined))),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+
+    // the separate log files are still written next to the combined one
+    let stdout_log = format!("{LOG_DIR}/combined_log_stdout.log");
+    let stderr_log = format!("{LOG_DIR}/combined_log_stderr.log");
+    ensure_eq!(std::fs::read_to_string(&stdout_log).stack()?, "to stdout");
+    ensure_eq!(std::fs::read_to_string(&stderr_log).stack()?, "to stderr");
+    let combined_contents = std::fs::read_to_string(&combined).stack()?;
+    ensure_eq!(combined_contents.len(), "to stdoutto stderr".len());
+    ensure!(combined_contents.contains("to stdout"));
+    ensure!(combined_contents.contains("to stderr"));
+    ensure!(cn
+        .resources()
+        .get(ResourceKind::LogFile, &combined)
+        .is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn display_table() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "disp_fail",
+        &FakeContainer::new()
+            .duration(Duration::from_millis(100))
+            .exit_code(1),
+    )
+    .stack()?;
+    fake.script("disp_slow", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_display", Some(LOG_DIR), LOG_DIR);
+    cn.add_container(container("disp_fail")).stack()?;
+    cn.add_container(
+        container("disp_slow")
+            .infrastructure(true)
+            .read_only_rootfs(true),
+    )
+    .stack()?;
+    cn.add_container(Container::new(
+        "disp_unbuilt",
+        Dockerfile::contents("FROM fake"),
+    ))
+    .stack()?;
+    // the UUID is the only thing that varies between runs
+    let uuid = cn.uuid_as_string();
+    let describe = |cn: &ContainerNetwork| cn.describe().replace(&uuid, "<uuid>");
+
+    ensure_eq!(
+        describe(&cn),
+        format!(
+            "ContainerNetwork \"fake_display\" (UUID <uuid>, log dir \"{LOG_DIR}\", network \
+             inactive)
+NAME          STATE     ID  IMAGE        FLAGS
+disp_fail     Inactive  -   fake:latest
+disp_slow     Inactive  -   fake:latest  infrastructure,read_only_rootfs
+disp_unbuilt  Inactive  -   -
+"
+        )
+    );
+    ensure_eq!(cn.describe(), cn.to_string());
+
+    cn.run(["disp_fail", "disp_slow"]).await.stack()?;
+    ensure_eq!(
+        describe(&cn),
+        format!(
+            "ContainerNetwork \"fake_display\" (UUID <uuid>, log dir \"{LOG_DIR}\", network \
+             active)
+NAME          STATE     ID            IMAGE        FLAGS
+disp_fail     Active    {}  fake:latest
+disp_slow     Active    {}  fake:latest  infrastructure,read_only_rootfs
+disp_unbuilt  Inactive  -             -
+",
+            &fake_id("disp_fail")[..12],
+            &fake_id("disp_slow")[..12],
+        )
+    );
+
+    // the slow container is terminated after the failure
+    ensure!(cn.wait_with_timeout_all(true, TIMEOUT).await.is_err());
+    ensure_eq!(
+        describe(&cn),
+        format!(
+            "ContainerNetwork \"fake_display\" (UUID <uuid>, log dir \"{LOG_DIR}\", network \
+             inactive)
+NAME          STATE                   ID  IMAGE        FLAGS
+disp_fail     PostActive(failed)      -   fake:latest
+disp_slow     PostActive(terminated)  -   fake:latest  infrastructure,read_only_rootfs
+disp_unbuilt  Inactive                -   -
+"
+        )
+    );
+    cn.terminate_all().await;
+    Ok(())
+}
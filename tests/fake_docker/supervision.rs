@@ -0,0 +1,561 @@
+//! Tests of readiness, hooks, supervised, infrastructure, and detached
+//! containers
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use stacked_errors::{ensure, ensure_eq, Error, Result, StackableErr};
+use super_orchestrator::{
+    docker::{
+        ContainerHooks, ContainerNetwork, HookFailurePolicy, ReadinessProbe, SupervisePolicy,
+    },
+    expect_crate_event, subscribe_crate_events,
+    test_doubles::FakeContainer,
+    Backoff, CrateEvent,
+};
+use tokio::time::{timeout, Instant};
+
+use crate::{container, fake_docker, fake_id, LOG_DIR, TIMEOUT};
+
+#[tokio::test]
+async fn wait_for_label() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "wl",
+        &FakeContainer::new()
+            .label_update(Duration::from_secs(1), "ready", "true")
+            .duration(Duration::from_secs(60)),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_wl", None, LOG_DIR);
+    cn.add_container(container("wl").create_args(["--label", "ready=false"]))
+        .stack()?;
+    cn.run_all().await.stack()?;
+    let e = cn
+        .wait_for_label("wl", "ready", "true", Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(format!("{e:?}").contains("the last value was Some(\"false\")"));
+    cn.wait_for_label("wl", "ready", "true", TIMEOUT)
+        .await
+        .stack()?;
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn wait_ready() -> Result<()> {
+    let fake = fake_docker();
+    let long = Duration::from_secs(5);
+    fake.script(
+        "ready_exec",
+        &FakeContainer::new()
+            .exec_ready_after(Duration::from_millis(600))
+            .duration(long),
+    )
+    .stack()?;
+    fake.script(
+        "ready_log",
+        &FakeContainer::new()
+            .stdout_chunk(Duration::from_millis(300), "starting\nlistening on 8080\n")
+            .duration(long),
+    )
+    .stack()?;
+    fake.script("ready_none", &FakeContainer::new().duration(long))
+        .stack()?;
+    let interval = Duration::from_millis(100);
+    let mut cn = ContainerNetwork::new("fake_ready", None, LOG_DIR);
+    cn.add_container(container("ready_exec").readiness(
+        ReadinessProbe::Exec(vec!["pg_isready".to_owned()]),
+        interval,
+        TIMEOUT,
+    ))
+    .stack()?;
+    cn.add_container(container("ready_log").readiness(
+        ReadinessProbe::LogLine("listening on".to_owned()),
+        interval,
+        TIMEOUT,
+    ))
+    .stack()?;
+    cn.add_container(container("ready_none")).stack()?;
+    let e = format!(
+        "{:?}",
+        cn.wait_ready(&["ready_exec"], TIMEOUT).await.unwrap_err()
+    );
+    ensure!(e.contains("the container is not active"));
+    cn.run_all().await.stack()?;
+
+    let start = Instant::now();
+    cn.wait_ready(&["ready_exec", "ready_log"], TIMEOUT)
+        .await
+        .stack()?;
+    ensure!(start.elapsed() >= Duration::from_millis(600));
+    ensure!(fake.invocations().stack()?.contains(&vec![
+        "exec".to_owned(),
+        fake_id("ready_exec"),
+        "pg_isready".to_owned()
+    ]));
+    let e = format!(
+        "{:?}",
+        cn.wait_ready(&["ready_none"], TIMEOUT).await.unwrap_err()
+    );
+    ensure!(e.contains("the container has no readiness probe"));
+    cn.terminate_all().await;
+
+    // the last failure is included in timeouts
+    fake.script(
+        "ready_late",
+        &FakeContainer::new().exec_ready_after(long).duration(long),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_ready_late", None, LOG_DIR);
+    cn.add_container(container("ready_late").readiness(
+        ReadinessProbe::Exec(vec!["true".to_owned()]),
+        interval,
+        TIMEOUT,
+    ))
+    .stack()?;
+    cn.run_all().await.stack()?;
+    let e = cn
+        .wait_ready(&["ready_late"], Duration::from_millis(500))
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    let e = format!("{e:?}");
+    ensure!(e.contains("timeout waiting for the probe Exec([\"true\"]), the last failure was"));
+    ensure!(e.contains("exec is not ready yet"));
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn container_hooks() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "hooks_a",
+        &FakeContainer::new()
+            .duration(Duration::from_millis(200))
+            .exit_code(3),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_hooks", None, LOG_DIR);
+    cn.add_container(container("hooks_a").allow_unsuccessful(true))
+        .stack()?;
+    ensure!(cn
+        .set_hooks("hooks_nonexistent", ContainerHooks::new())
+        .is_err());
+    let ledger = Arc::new(Mutex::new(vec![]));
+    let record = |ledger: &Arc<Mutex<Vec<String>>>, entry: String| {
+        ledger.lock().unwrap().push(entry);
+    };
+    cn.set_hooks(
+        "hooks_a",
+        ContainerHooks::new()
+            .on_before_create({
+                let ledger = ledger.clone();
+                move |cx| {
+                    record(&ledger, format!("{} {:?}", cx.phase, cx.id));
+                    async move { Ok(()) }
+                }
+            })
+            .on_after_start({
+                let ledger = ledger.clone();
+                move |cx| {
+                    let ledger = ledger.clone();
+                    async move {
+                        let ip = cx.wait_get_ip_addr(50, Duration::from_millis(50)).await?;
+                        record(&ledger, format!("{} {} {ip}", cx.phase, cx.id.unwrap()));
+                        Ok(())
+                    }
+                }
+            })
+            .on_after_exit({
+                let ledger = ledger.clone();
+                move |cx| {
+                    let ledger = ledger.clone();
+                    async move {
+                        // the container has exited
+                        ensure!(cx.exec(["true"]).await.is_err());
+                        let status = cx.result.unwrap().status.unwrap().code();
+                        record(&ledger, format!("{} {status:?}", cx.phase));
+                        Ok(())
+                    }
+                }
+            }),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    ensure_eq!(*ledger.lock().unwrap(), [
+        "before_create None".to_owned(),
+        format!("after_start {} 172.18.0.2", fake_id("hooks_a")),
+        "after_exit Some(3)".to_owned(),
+    ]);
+
+    // a fatal hook failure stops the run and cleans up
+    fake.script("hooks_b", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_hooks_fatal", None, LOG_DIR);
+    cn.add_container(container("hooks_b")).stack()?;
+    let failing = || {
+        ContainerHooks::new().on_after_start(|_| async { Err(Error::from("registration failed")) })
+    };
+    cn.set_hooks("hooks_b", failing()).stack()?;
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("registration failed"));
+    ensure!(e.contains("after_start hook 0 of container \"hooks_b\" failed"));
+    ensure!(cn.active_names().is_empty());
+
+    // a logged hook failure does not
+    let mut events = subscribe_crate_events();
+    cn.set_hooks(
+        "hooks_b",
+        failing().hook_failure_policy(HookFailurePolicy::Log),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    expect_crate_event!(
+        events,
+        TIMEOUT,
+        CrateEvent::HookFailed { name, phase, .. } if name == "hooks_b" && phase == "after_start"
+    )
+    .await
+    .stack()?;
+    ensure_eq!(cn.active_names(), ["hooks_b"]);
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn infrastructure_containers() -> Result<()> {
+    let fake = fake_docker();
+    let ms = Duration::from_millis;
+    fake.script("infra_db", &FakeContainer::new().duration(ms(300)))
+        .stack()?;
+    fake.script("infra_long", &FakeContainer::new().duration(ms(60_000)))
+        .stack()?;
+    fake.script("infra_app", &FakeContainer::new().duration(ms(1500)))
+        .stack()?;
+    fake.script("infra_quick", &FakeContainer::new().duration(ms(50)))
+        .stack()?;
+
+    // infrastructure containers are not waited on by `wait_with_timeout_all`
+    let mut cn = ContainerNetwork::new("fake_infra", None, LOG_DIR);
+    cn.add_container(container("infra_long").infrastructure(true))
+        .stack()?;
+    cn.add_container(container("infra_quick")).stack()?;
+    cn.run_all().await.stack()?;
+    timeout(ms(10_000), cn.wait_with_timeout_all(true, TIMEOUT))
+        .await
+        .stack()?
+        .stack()?;
+    ensure_eq!(cn.active_names(), ["infra_long"]);
+    cn.terminate_all().await;
+
+    // but one exiting while waiting on others fails the wait fast, even if it
+    // was successful
+    let mut cn = ContainerNetwork::new("fake_infra_exit", None, LOG_DIR);
+    cn.add_container(container("infra_db").infrastructure(true))
+        .stack()?;
+    cn.add_container(container("infra_app")).stack()?;
+    cn.run_all().await.stack()?;
+    let start = Instant::now();
+    let e = format!(
+        "{:?}",
+        cn.wait_with_timeout_all(true, TIMEOUT).await.unwrap_err()
+    );
+    ensure!(start.elapsed() < ms(1500));
+    ensure!(e.contains(
+        "infrastructure container \"infra_db\" exited while waiting on container names \
+         {\"infra_app\"}"
+    ));
+    // and the rest of the network is terminated
+    ensure!(cn.active_names().is_empty());
+    ensure!(matches!(
+        cn.get_result("infra_db"),
+        Some(Ok(comres)) if comres.successful()
+    ));
+
+    // unless this is disabled
+    let mut cn = ContainerNetwork::new("fake_infra_allowed", None, LOG_DIR);
+    cn.fail_on_infrastructure_exit(false);
+    cn.add_container(container("infra_db").infrastructure(true))
+        .stack()?;
+    cn.add_container(container("infra_app")).stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    ensure!(cn.active_names().is_empty());
+    cn.terminate_all().await;
+
+    // waiting on the infrastructure container itself is not a failure
+    let mut cn = ContainerNetwork::new("fake_infra_target", None, LOG_DIR);
+    cn.add_container(container("infra_db").infrastructure(true))
+        .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout(["infra_db"], true, TIMEOUT)
+        .await
+        .stack()?;
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn supervised_containers() -> Result<()> {
+    let fake = fake_docker();
+    let ms = Duration::from_millis;
+    fake.script(
+        "sup_flaky",
+        &FakeContainer::new()
+            .duration(ms(fastrand::u64(50..150)))
+            .stderr("flaky service crashed\n")
+            .exit_code(1),
+    )
+    .stack()?;
+    fake.script("sup_main", &FakeContainer::new().duration(ms(1500)))
+        .stack()?;
+    let policy = SupervisePolicy {
+        max_restarts: 100,
+        window: Duration::from_secs(60),
+        backoff: Backoff::constant(ms(50)),
+    };
+    let mut events = subscribe_crate_events();
+    let mut cn = ContainerNetwork::new("fake_supervise", None, LOG_DIR);
+    cn.add_container(container("sup_flaky").supervise(policy).log(true))
+        .stack()?;
+    cn.add_container(container("sup_main")).stack()?;
+    cn.run_all().await.stack()?;
+    // the crashes of the supervised container are not failures, and it is not
+    // waited on
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let history = cn.supervise_history("sup_flaky").stack()?;
+    ensure!(history.len() >= 2);
+    ensure!(history.iter().all(|comres| !comres.successful()));
+    ensure!(cn.supervise_history("sup_main").stack()?.is_empty());
+    expect_crate_event!(
+        events,
+        TIMEOUT,
+        CrateEvent::ContainerRestarted { name, restarts: 2, exit_code: Some(1) }
+            if name == "sup_flaky"
+    )
+    .await
+    .stack()?;
+    // unless it is waiting on its backoff to be restarted by the next wait
+    ensure!(cn.active_names().iter().all(|name| name == "sup_flaky"));
+    // the log is appended to across restarts
+    let log = std::fs::read_to_string(format!("{LOG_DIR}/sup_flaky_stderr.log")).stack()?;
+    ensure!(log.matches("flaky service crashed").count() >= 2);
+    cn.terminate_all().await;
+
+    // exhausting the policy is a failure
+    let mut cn = ContainerNetwork::new("fake_supervise_exhausted", None, LOG_DIR);
+    cn.add_container(container("sup_flaky").supervise(SupervisePolicy {
+        max_restarts: 2,
+        ..policy
+    }))
+    .stack()?;
+    cn.run_all().await.stack()?;
+    let e = format!(
+        "{:?}",
+        cn.wait_with_timeout(["sup_flaky"], true, TIMEOUT)
+            .await
+            .unwrap_err()
+    );
+    ensure!(e.contains("supervised container \"sup_flaky\" exhausted its restart policy"));
+    ensure_eq!(cn.supervise_history("sup_flaky").stack()?.len(), 3);
+    ensure!(cn.active_names().is_empty());
+
+    // the restart backoff does not block waiting on the other containers
+    fake.script("sup_backoff", &FakeContainer::new().exit_code(1))
+        .stack()?;
+    fake.script("sup_quick", &FakeContainer::new().duration(ms(300)))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_supervise_backoff", None, LOG_DIR);
+    cn.add_container(container("sup_backoff").supervise(SupervisePolicy {
+        backoff: Backoff::constant(Duration::from_secs(2)),
+        ..policy
+    }))
+    .stack()?;
+    cn.add_container(container("sup_quick")).stack()?;
+    cn.run_all().await.stack()?;
+    let start = Instant::now();
+    cn.wait_with_timeout(["sup_quick"], true, TIMEOUT)
+        .await
+        .stack()?;
+    ensure!(start.elapsed() < Duration::from_millis(1500));
+    // and the restart is not done past the deadline
+    let e = cn
+        .wait_with_timeout(["sup_backoff"], false, ms(300))
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure_eq!(cn.supervise_history("sup_backoff").stack()?.len(), 1);
+    // but by a later wait once it is due
+    let e = cn
+        .wait_with_timeout(["sup_backoff"], false, Duration::from_secs(3))
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure!(cn.supervise_history("sup_backoff").stack()?.len() >= 2);
+    cn.terminate_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn start_order() -> Result<()> {
+    let fake = fake_docker();
+    for name in ["order_a", "order_b", "order_c", "order_e"] {
+        fake.script(name, &FakeContainer::new().duration(TIMEOUT))
+            .stack()?;
+    }
+    fake.script(
+        "order_d",
+        &FakeContainer::new()
+            .duration(TIMEOUT)
+            .stdout_chunk(Duration::from_millis(300), "ready\n"),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_start_order", None, LOG_DIR);
+    cn.add_container(container("order_e").start_group(2))
+        .stack()?;
+    cn.add_container(container("order_a").start_group(1))
+        .stack()?;
+    cn.add_container(container("order_d").start_group(1).readiness(
+        ReadinessProbe::LogLine("ready".to_owned()),
+        Duration::from_millis(50),
+        Duration::from_secs(1),
+    ))
+    .stack()?;
+    cn.add_container(container("order_c")).stack()?;
+    cn.add_container(container("order_b")).stack()?;
+    cn.start_order(["order_c", "order_d"])
+        .start_group_ready_timeout(Some(TIMEOUT));
+    let started = Arc::new(Mutex::new(vec![]));
+    for name in ["order_a", "order_b", "order_c", "order_d", "order_e"] {
+        let started = started.clone();
+        cn.set_hooks(
+            name,
+            ContainerHooks::new().on_after_start(move |_| {
+                started
+                    .lock()
+                    .unwrap()
+                    .push((name.to_owned(), Instant::now()));
+                async { Ok(()) }
+            }),
+        )
+        .stack()?;
+    }
+    cn.run_all().await.stack()?;
+    let started = started.lock().unwrap().clone();
+    let order: Vec<&str> = started.iter().map(|(name, _)| name.as_str()).collect();
+    // group 0 with the listed "order_c" first, then group 1 with the listed
+    // "order_d" first, then group 2
+    ensure_eq!(order, [
+        "order_c", "order_b", "order_d", "order_a", "order_e"
+    ]);
+    // "order_e" waited for the readiness of "order_d"
+    ensure!(started[4].1 >= started[2].1 + Duration::from_millis(300));
+    cn.terminate_all().await;
+
+    // a readiness timeout fails the run in the phase of the group
+    cn.start_group_ready_timeout(Some(Duration::from_millis(100)));
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("failed in phase StartGroupReady(1)"));
+    ensure!(cn.active_names().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn detached_containers() -> Result<()> {
+    let fake = fake_docker();
+    let names: Vec<String> = (0..20).map(|i| format!("detached_{i:02}")).collect();
+    // `usize::is_multiple_of` needs Rust 1.87, which is above the MSRV
+    #[allow(clippy::manual_is_multiple_of)]
+    let exit_code = |i: usize| if i % 5 == 0 { 3 } else { 0 };
+    let mut cn = ContainerNetwork::new("fake_detached", None, LOG_DIR);
+    for (i, name) in names.iter().enumerate() {
+        fake.script(
+            name,
+            &FakeContainer::new()
+                .duration(Duration::from_millis(200))
+                .stdout("detached output\n")
+                .exit_code(exit_code(i))
+                // the exit code is still found after the logs are followed
+                .auto_remove(true),
+        )
+        .stack()?;
+        // only the first container has its logs followed
+        cn.add_container(
+            container(name)
+                .detached(true)
+                .debug(false)
+                .log(i == 0)
+                .success_codes([0, 3]),
+        )
+        .stack()?;
+    }
+    cn.run_all().await.stack()?;
+    ensure_eq!(cn.active_names(), names);
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    for (i, name) in names.iter().enumerate() {
+        let comres = cn.get_result(name).unwrap().as_ref().unwrap();
+        ensure_eq!(
+            comres.status.and_then(|status| status.code()),
+            Some(exit_code(i))
+        );
+        if i == 0 {
+            ensure_eq!(comres.stdout_as_utf8().stack()?, "detached output\n");
+        } else {
+            ensure!(comres.stdout.is_empty());
+        }
+    }
+    ensure_eq!(
+        cn.describe()
+            .lines()
+            .filter(|line| line.ends_with("  detached"))
+            .count(),
+        20
+    );
+
+    // no attached `docker start`s are left running, only `docker wait`s
+    let invocations: Vec<Vec<String>> = fake
+        .invocations()
+        .stack()?
+        .into_iter()
+        .filter(|args| {
+            args.last()
+                .is_some_and(|id| names.iter().any(|name| *id == fake_id(name)))
+        })
+        .collect();
+    let count = |prefix: &[&str]| {
+        invocations
+            .iter()
+            .filter(|args| args.len() > prefix.len() && args[..prefix.len()] == *prefix)
+            .count()
+    };
+    ensure_eq!(count(&["start", "--attach"]), 0);
+    ensure_eq!(count(&["start"]), 20);
+    ensure_eq!(count(&["wait"]), 19);
+    ensure_eq!(count(&["logs", "--follow"]), 1);
+    ensure_eq!(count(&["inspect", "--format"]), 1);
+    ensure!(!fake.invocations().stack()?.iter().any(|args| {
+        (args[0] == "create") && args.contains(&"--rm".to_owned()) && args.contains(&names[0])
+    }));
+
+    // the exited containers are only removed when terminated
+    ensure_eq!(count(&["rm", "-f"]), 0);
+    cn.terminate_all().await;
+    let removed = fake
+        .invocations()
+        .stack()?
+        .into_iter()
+        .filter(|args| {
+            (args[..2] == ["rm", "-f"]) && names.iter().any(|name| args[2] == fake_id(name))
+        })
+        .count();
+    ensure_eq!(removed, 20);
+    ensure!(cn.last_teardown_report().is_ok());
+    Ok(())
+}
@@ -0,0 +1,442 @@
+//! Tests of terminating networks and cleaning up after failures
+
+use std::time::Duration;
+
+use stacked_errors::{ensure, ensure_eq, Error, ErrorKind, Result, StackableErr};
+use super_orchestrator::{
+    docker::{
+        Container, ContainerNetwork, Dockerfile, ResourceKind, ResourceStatus, ResultRetention,
+        TerminateOutcome,
+    },
+    docker_helpers::docker_binary,
+    expect_crate_event, subscribe_crate_events,
+    test_doubles::FakeContainer,
+    Command, CrateEvent,
+};
+
+use crate::{container, fake_docker, fake_id, LOG_DIR, TIMEOUT};
+
+#[tokio::test]
+async fn terminate_on_failure() -> Result<()> {
+    let fake = fake_docker();
+    let fail = FakeContainer::new()
+        .duration(Duration::from_millis(100))
+        .exit_code(1);
+    let slow = FakeContainer::new().duration(Duration::from_secs(60));
+
+    // everything is terminated after the failure is found
+    fake.script("tof_fail", &fail).stack()?;
+    fake.script("tof_slow", &slow).stack()?;
+    let mut cn = ContainerNetwork::new("fake_tof", None, LOG_DIR);
+    cn.add_container(container("tof_fail")).stack()?;
+    cn.add_container(container("tof_slow")).stack()?;
+    cn.run_all().await.stack()?;
+    ensure!(cn.wait_with_timeout_all(true, TIMEOUT).await.is_err());
+    ensure!(cn.active_names().is_empty());
+    let invocations = fake.invocations().stack()?;
+    let position = |args: &[&str]| invocations.iter().position(|x| x == args);
+    let start_fail = position(&["start", "--attach", &fake_id("tof_fail")]).stack()?;
+    let rm_slow = position(&["rm", "-f", &fake_id("tof_slow")]).stack()?;
+    ensure!(start_fail < rm_slow);
+    cn.terminate_all().await;
+
+    // without `terminate_on_failure` the failure is recorded and waiting continues
+    fake.script("tof_fail2", &fail).stack()?;
+    fake.script("tof_slow2", &slow).stack()?;
+    let mut cn = ContainerNetwork::new("fake_tof2", None, LOG_DIR);
+    cn.add_container(container("tof_fail2")).stack()?;
+    cn.add_container(container("tof_slow2")).stack()?;
+    cn.run_all().await.stack()?;
+    let e = cn
+        .wait_with_timeout_all(false, Duration::from_secs(1))
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure_eq!(cn.active_names(), ["tof_slow2"]);
+    ensure!(matches!(
+        cn.get_result("tof_fail2"),
+        Some(Ok(comres)) if !comres.successful()
+    ));
+    cn.terminate_all().await;
+    ensure!(cn.active_names().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_failure_cleanup() -> Result<()> {
+    let fake = fake_docker();
+    fake.script("cf_b", &FakeContainer::new().fail_create(true))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_cf", None, LOG_DIR);
+    cn.add_container(container("cf_a")).stack()?;
+    cn.add_container(container("cf_b")).stack()?;
+    cn.add_container(container("cf_c")).stack()?;
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("failed in phase Create(\"cf_b\")"));
+    ensure!(e.contains("containers cleaned up: [\"cf_a\"]"));
+    ensure!(e.contains("fake_docker scripted a create failure"));
+    ensure!(cn.active_names().is_empty());
+    let invocations = fake.invocations().stack()?;
+    ensure!(invocations.contains(&vec!["rm".to_owned(), "-f".to_owned(), fake_id("cf_a")]));
+    ensure!(!invocations
+        .iter()
+        .any(|args| args[0] == "create" && args.contains(&"cf_c".to_owned())));
+    Ok(())
+}
+
+#[tokio::test]
+async fn drain_results() -> Result<()> {
+    let fake = fake_docker();
+    fake.script("drain_a", &FakeContainer::new().stdout("a\n"))
+        .stack()?;
+    fake.script("drain_b", &FakeContainer::new().exit_code(1))
+        .stack()?;
+    let mut cn = ContainerNetwork::new("fake_drain", None, LOG_DIR);
+    cn.add_container(container("drain_a")).stack()?;
+    cn.add_container(container("drain_b").allow_unsuccessful(true))
+        .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    let results = cn.drain_results();
+    ensure_eq!(results.keys().collect::<Vec<_>>(), ["drain_a", "drain_b"]);
+    ensure_eq!(results["drain_a"].as_ref().unwrap().stdout, b"a\n");
+    ensure!(!results["drain_b"].as_ref().unwrap().successful());
+    ensure!(cn.results().is_empty());
+    ensure!(cn.get_result("drain_a").is_none());
+    ensure!(cn.drain_results().is_empty());
+    // the containers can be run again
+    cn.run(["drain_a"]).await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    ensure_eq!(cn.drain_results().keys().collect::<Vec<_>>(), ["drain_a"]);
+    cn.terminate_all().await;
+
+    fake.script("into_done", &FakeContainer::new().stdout("done\n"))
+        .stack()?;
+    fake.script(
+        "into_running",
+        &FakeContainer::new().duration(Duration::from_secs(10)),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_into", None, LOG_DIR);
+    cn.add_container(container("into_done")).stack()?;
+    cn.add_container(container("into_running")).stack()?;
+    cn.add_container(container("into_never")).stack()?;
+    cn.run(["into_done", "into_running"]).await.stack()?;
+    // give "into_done" time to exit on its own, it is collected by `into_results`
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let results = cn.into_results().await.stack()?;
+    ensure_eq!(results.keys().collect::<Vec<_>>(), [
+        "into_done",
+        "into_running"
+    ]);
+    ensure_eq!(results["into_done"].stdout, b"done\n");
+    ensure!(results["into_done"].successful());
+    ensure!(!results["into_running"].successful());
+    Ok(())
+}
+
+#[tokio::test]
+async fn created_resources_ledger() -> Result<()> {
+    let fake = fake_docker();
+    fake.script("ledger_sticky", &FakeContainer::new().fail_remove(true))
+        .stack()?;
+    fake.script(
+        "ledger_fail_create",
+        &FakeContainer::new().fail_create(true),
+    )
+    .stack()?;
+    let crash_dir = concat!(
+        env!("CARGO_TARGET_TMPDIR"),
+        "/fake_docker_logs/ledger_crash"
+    );
+    let _ = std::fs::remove_dir_all(crash_dir);
+
+    let mut cn = ContainerNetwork::new("fake_ledger", Some(LOG_DIR), LOG_DIR);
+    cn.add_container(
+        Container::new("ledger_built", Dockerfile::contents("FROM fake:ledger\n"))
+            .log(true)
+            .capture_crash_artifacts(crash_dir),
+    )
+    .stack()?;
+    cn.add_container(container("ledger_sticky")).stack()?;
+    cn.add_container(container("ledger_ok")).stack()?;
+    cn.add_container(container("ledger_fail_create")).stack()?;
+    cn.run(["ledger_built", "ledger_sticky"]).await.stack()?;
+    cn.wait_with_timeout(["ledger_built", "ledger_sticky"], true, TIMEOUT)
+        .await
+        .stack()?;
+    // the failure path, `ledger_ok` is created and then cleaned up
+    ensure!(cn.run(["ledger_ok", "ledger_fail_create"]).await.is_err());
+    cn.terminate_all().await;
+
+    let resources = cn.resources();
+    let network = resources
+        .get(ResourceKind::Network, "fake_ledger")
+        .stack()?;
+    ensure_eq!(network.id.as_deref(), Some(fake_id("fake_ledger").as_str()));
+    ensure_eq!(network.status, ResourceStatus::Removed);
+    ensure!(network.status_changed_at >= network.created_at);
+
+    let containers: Vec<_> = resources.of_kind(ResourceKind::Container).collect();
+    ensure_eq!(containers.len(), 3);
+    for (entry, name) in containers
+        .iter()
+        .zip(["ledger_built", "ledger_sticky", "ledger_ok"])
+    {
+        ensure_eq!(entry.name, name);
+        ensure_eq!(entry.id.as_deref(), Some(fake_id(name).as_str()));
+    }
+    ensure_eq!(containers[0].status, ResourceStatus::Removed);
+    ensure_eq!(containers[2].status, ResourceStatus::Removed);
+    let ResourceStatus::RemovalError(ref e) = containers[1].status else {
+        return Err(Error::from(format!("{:?}", containers[1].status)))
+    };
+    ensure!(e.contains("scripted a removal failure"));
+
+    // the ledger matches what exists on the fake daemon
+    for entry in &containers {
+        let exists = Command::new_os_str(docker_binary())
+            .arg("inspect")
+            .arg(entry.id.as_ref().unwrap())
+            .run_to_completion()
+            .await
+            .stack()?
+            .successful();
+        ensure_eq!(exists, entry.status != ResourceStatus::Removed);
+    }
+
+    let images: Vec<_> = resources.of_kind(ResourceKind::Image).collect();
+    ensure_eq!(images.len(), 1);
+    let image = images[0];
+    ensure!(image.name.starts_with("super_orchestrator_ledger_built_"));
+    ensure!(matches!(image.status, ResourceStatus::Kept(_)));
+    // files and directories are kept and exist
+    for kind in [
+        ResourceKind::TempFile,
+        ResourceKind::LogFile,
+        ResourceKind::HostDir,
+    ] {
+        let entries: Vec<_> = resources.of_kind(kind).collect();
+        ensure!(!entries.is_empty());
+        for entry in entries {
+            ensure!(matches!(entry.status, ResourceStatus::Kept(_)));
+            ensure!(std::path::Path::new(&entry.name).exists());
+        }
+    }
+    ensure_eq!(resources.of_kind(ResourceKind::LogFile).count(), 2);
+    ensure!(resources
+        .of_kind(ResourceKind::TempFile)
+        .any(|entry| entry.name.ends_with("ledger_built.tmp.dockerfile")));
+    ensure!(resources.get(ResourceKind::HostDir, crash_dir).is_some());
+
+    let outstanding: Vec<_> = resources.outstanding().map(|e| e.name.as_str()).collect();
+    ensure_eq!(outstanding, ["ledger_sticky"]);
+    // the ledger is serializable for audit tooling
+    let json = serde_json::to_string(resources).stack()?;
+    ensure!(json.contains("RemovalError"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn teardown_report() -> Result<()> {
+    let fake = fake_docker();
+    fake.script(
+        "teardown_sticky",
+        &FakeContainer::new().duration(TIMEOUT).fail_remove(true),
+    )
+    .stack()?;
+    fake.script("teardown_gone", &FakeContainer::new().duration(TIMEOUT))
+        .stack()?;
+    let mut events = subscribe_crate_events();
+    let mut cn = ContainerNetwork::new("fake_teardown", None, LOG_DIR);
+    cn.add_container(container("teardown_sticky")).stack()?;
+    cn.add_container(container("teardown_gone")).stack()?;
+    cn.run_all().await.stack()?;
+    // removed behind the back of the network, which is a harmless race
+    Command::new_os_str(docker_binary())
+        .args(["rm", "-f", &fake_id("teardown_gone")])
+        .run_to_completion()
+        .await
+        .stack()?
+        .assert_success()
+        .stack()?;
+    cn.terminate_all().await;
+
+    let report = cn.last_teardown_report();
+    ensure!(!report.is_ok());
+    ensure_eq!(report.failures.len(), 1);
+    let failure = &report.failures[0];
+    ensure_eq!(failure.kind, ResourceKind::Container);
+    ensure_eq!(failure.name, "teardown_sticky");
+    ensure!(failure.command.contains("rm -f"));
+    ensure!(failure
+        .stderr
+        .contains("scripted a removal failure for \"teardown_sticky\""));
+    ensure_eq!(failure.attempts, 3);
+    let rm = vec!["rm".to_owned(), "-f".to_owned(), fake_id("teardown_sticky")];
+    ensure_eq!(
+        fake.invocations()
+            .stack()?
+            .iter()
+            .filter(|args| **args == rm)
+            .count(),
+        3
+    );
+    expect_crate_event!(
+        events,
+        TIMEOUT,
+        CrateEvent::TeardownFailed { name, .. } if name == "teardown_sticky"
+    )
+    .await
+    .stack()?;
+    events
+        .assert_none_matching(|event| {
+            matches!(event, CrateEvent::TeardownFailed { name, .. } if name == "teardown_gone")
+        })
+        .stack()?;
+
+    // the stuck container can be removed once the problem is fixed
+    fake.script("teardown_sticky", &FakeContainer::new())
+        .stack()?;
+    Command::new_os_str(docker_binary())
+        .args(rm)
+        .run_to_completion()
+        .await
+        .stack()?
+        .assert_success()
+        .stack()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn last_teardown() -> Result<()> {
+    let fake = fake_docker();
+    for name in ["lt_a", "lt_b"] {
+        fake.script(name, &FakeContainer::new().duration(TIMEOUT))
+            .stack()?;
+    }
+    let mut cn = ContainerNetwork::new("fake_last_teardown", None, LOG_DIR);
+    cn.add_container(container("lt_a")).stack()?;
+    cn.add_container(container("lt_b")).stack()?;
+    cn.run_all().await.stack()?;
+    // the `try_*` functions record their outcomes too
+    let outcomes = cn.try_terminate(["lt_a", "lt_nonexistent"]).await;
+    let last: Vec<_> = cn.last_teardown().keys().cloned().collect();
+    ensure_eq!(last, ["lt_a", "lt_nonexistent"]);
+    ensure_eq!(outcomes.keys().cloned().collect::<Vec<_>>(), last);
+    ensure!(matches!(
+        cn.last_teardown()["lt_a"],
+        TerminateOutcome::Terminated(_)
+    ));
+    ensure!(matches!(
+        cn.last_teardown()["lt_nonexistent"],
+        TerminateOutcome::NotFound
+    ));
+    let outcomes = cn.try_terminate_all().await;
+    ensure!(matches!(outcomes["lt_a"], TerminateOutcome::WasNotActive));
+    ensure!(matches!(
+        cn.last_teardown()["lt_b"],
+        TerminateOutcome::Terminated(_)
+    ));
+    ensure_eq!(cn.last_teardown().len(), 2);
+
+    // cloning an outcome keeps the error stack
+    let e = Error::timeout()
+        .add_kind("terminating")
+        .add_kind(ErrorKind::from_box(Box::new(std::io::Error::other("io"))));
+    let location = format!("{:?}", e.stack[0].1);
+    let TerminateOutcome::RunnerError(clone) = TerminateOutcome::RunnerError(e).clone() else {
+        unreachable!()
+    };
+    ensure!(clone.is_timeout());
+    ensure_eq!(clone.stack.len(), 3);
+    ensure_eq!(format!("{:?}", clone.stack[0].1), location);
+    let debug = format!("{clone:?}");
+    ensure!(debug.contains("BoxedError(Custom { kind: Other, error: \"io\" })"));
+    ensure!(debug.contains("terminating"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn result_retention() -> Result<()> {
+    let fake = fake_docker();
+    for (policy, retention) in [
+        ("full", ResultRetention::Full),
+        ("status", ResultRetention::StatusOnly),
+        ("discard", ResultRetention::Discard),
+    ] {
+        let ok = format!("ret_{policy}_ok");
+        let fail = format!("ret_{policy}_fail");
+        fake.script(&ok, &FakeContainer::new().stdout("hello\n"))
+            .stack()?;
+        fake.script(
+            &fail,
+            &FakeContainer::new()
+                .duration(Duration::from_millis(300))
+                .stderr("Error: Error { stack: [\"retained root cause\"] }\n")
+                .exit_code(1),
+        )
+        .stack()?;
+        let mut cn = ContainerNetwork::new(format!("fake_retention_{policy}"), None, LOG_DIR);
+        cn.result_retention(retention);
+        cn.add_container(container(&ok).log(true)).stack()?;
+        cn.add_container(container(&fail).log(true)).stack()?;
+        cn.run_all().await.stack()?;
+        cn.wait_with_timeout([&ok], true, TIMEOUT).await.stack()?;
+
+        let comres = cn.get_result(&ok).unwrap().as_ref().unwrap();
+        comres.assert_success().stack()?;
+        match retention {
+            ResultRetention::Full => {
+                ensure_eq!(comres.stdout_as_utf8().stack()?, "hello\n");
+                ensure_eq!(cn.output_bytes(&ok), Some((6, 0)));
+            }
+            ResultRetention::StatusOnly => {
+                ensure!(comres.stdout.is_empty() && comres.stderr.is_empty());
+                ensure_eq!(cn.output_bytes(&ok), Some((6, 0)));
+            }
+            ResultRetention::Discard => {
+                ensure!(comres.stdout.is_empty() && comres.stderr.is_empty());
+                ensure!(comres.command.args.is_empty());
+                ensure!(!comres.command.stdout_logs.is_empty());
+                ensure_eq!(cn.output_bytes(&ok), None);
+            }
+        }
+
+        // the error stack is still found, from the log file if not retained
+        let e = format!(
+            "{:?}",
+            cn.wait_with_timeout_all(true, TIMEOUT).await.unwrap_err()
+        );
+        ensure!(e.contains("retained root cause"));
+        ensure!(cn
+            .describe()
+            .contains(&format!("{fail}  PostActive(failed)")));
+
+        cn.clear_results();
+        let comres = cn.get_result(&ok).unwrap().as_ref().unwrap();
+        ensure!(comres.stdout.is_empty() && comres.status.is_some());
+        cn.terminate_all().await;
+    }
+
+    // without log files there is nothing to fall back to
+    fake.script(
+        "ret_nolog",
+        &FakeContainer::new()
+            .stderr("Error: Error { stack: [\"lost root cause\"] }\n")
+            .exit_code(1),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("fake_retention_nolog", None, LOG_DIR);
+    cn.result_retention(ResultRetention::Discard);
+    cn.add_container(container("ret_nolog")).stack()?;
+    cn.run_all().await.stack()?;
+    let e = format!(
+        "{:?}",
+        cn.wait_with_timeout_all(true, TIMEOUT).await.unwrap_err()
+    );
+    ensure!(e.contains("output was not retained because of `ResultRetention::Discard`"));
+    ensure!(!e.contains("lost root cause"));
+    cn.terminate_all().await;
+    Ok(())
+}
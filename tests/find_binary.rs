@@ -0,0 +1,126 @@
+//! Tests of the path resolution of `find_binary`
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{find_binary, FindBinaryOptions};
+
+fn touch(path: &Path) -> Result<()> {
+    fs::create_dir_all(path.parent().unwrap()).stack()?;
+    fs::write(path, "").stack()
+}
+
+fn exe(name: &str) -> String {
+    format!("{name}{}", std::env::consts::EXE_SUFFIX)
+}
+
+/// Options that only look at the fixture directories
+fn options(workspace: &Path) -> FindBinaryOptions {
+    FindBinaryOptions::new()
+        .env_target_dir(None::<&str>)
+        .cargo_metadata(false)
+        .workspace_dir(Some(workspace))
+}
+
+#[tokio::test]
+async fn profiles_targets_and_examples() -> Result<()> {
+    let dir = tempfile::tempdir().stack()?;
+    let workspace = dir.path();
+    let release = workspace.join("target/release").join(exe("entry"));
+    let debug = workspace.join("target/debug").join(exe("entry"));
+    let example = workspace.join("target/release/examples").join(exe("entry"));
+    let cross = workspace.join("target/x86_64-unknown-linux-musl/release/entry");
+    let custom = workspace.join("target/profiling").join(exe("entry_prof"));
+    for path in [&release, &debug, &example, &cross, &custom] {
+        touch(path)?;
+    }
+
+    ensure_eq!(find_binary("entry", &options(workspace)).await?, release);
+    let opts = options(workspace).profile("dev");
+    ensure_eq!(find_binary("entry", &opts).await?, debug);
+    let opts = options(workspace).profile("test");
+    ensure_eq!(find_binary("entry", &opts).await?, debug);
+    let opts = options(workspace).example(true);
+    ensure_eq!(find_binary("entry", &opts).await?, example);
+    let opts = options(workspace).target(Some("x86_64-unknown-linux-musl"));
+    ensure_eq!(find_binary("entry", &opts).await?, cross);
+    let opts = options(workspace).profile("profiling");
+    ensure_eq!(find_binary("entry_prof", &opts).await?, custom);
+    Ok(())
+}
+
+#[tokio::test]
+async fn target_dir_precedence() -> Result<()> {
+    let dir = tempfile::tempdir().stack()?;
+    let workspace = dir.path().join("workspace");
+    let explicit = dir.path().join("explicit");
+    let env = dir.path().join("env");
+    let in_workspace = workspace.join("target/release").join(exe("entry"));
+    let in_env = env.join("release").join(exe("entry"));
+    let in_explicit = explicit.join("release").join(exe("entry"));
+    touch(&in_workspace)?;
+
+    ensure_eq!(
+        find_binary("entry", &options(&workspace)).await?,
+        in_workspace
+    );
+    // `CARGO_TARGET_DIR` takes precedence over the workspace "target"
+    touch(&in_env)?;
+    let opts = options(&workspace).env_target_dir(Some(&env));
+    ensure_eq!(find_binary("entry", &opts).await?, in_env);
+    // and the explicit `target_dir` over `CARGO_TARGET_DIR`
+    touch(&in_explicit)?;
+    let opts = opts.target_dir(Some(&explicit));
+    ensure_eq!(find_binary("entry", &opts).await?, in_explicit);
+    // missing binaries in earlier directories fall through to later ones
+    fs::remove_file(&in_explicit).stack()?;
+    fs::remove_file(&in_env).stack()?;
+    ensure_eq!(find_binary("entry", &opts).await?, in_workspace);
+    Ok(())
+}
+
+#[tokio::test]
+async fn errors_list_probed_locations() -> Result<()> {
+    let dir = tempfile::tempdir().stack()?;
+    let workspace = dir.path().join("workspace");
+    let env = dir.path().join("env");
+    let stale = env.join("release").join(exe("entry"));
+    touch(&stale)?;
+
+    let opts = options(&workspace)
+        .env_target_dir(Some(&env))
+        .newer_than(Some(SystemTime::now() + Duration::from_secs(3600)));
+    let e = format!("{:?}", find_binary("entry", &opts).await.unwrap_err());
+    ensure!(e.contains("could not find the binary \"entry\""));
+    ensure!(e.contains(&format!("{stale:?}")));
+    ensure!(e.contains("older than `newer_than`"));
+    let in_workspace = workspace.join("target/release").join(exe("entry"));
+    ensure!(e.contains(&format!("{in_workspace:?}")));
+    ensure!(e.contains("not found"));
+
+    // a binary newer than the reference is found
+    let opts = opts.newer_than(Some(SystemTime::now() - Duration::from_secs(3600)));
+    ensure_eq!(find_binary("entry", &opts).await?, stale);
+    Ok(())
+}
+
+#[tokio::test]
+async fn cargo_metadata_target_dir() -> Result<()> {
+    // the target directory of this workspace as reported by `cargo metadata`
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).parent().unwrap();
+    let opts = FindBinaryOptions::new()
+        .env_target_dir(None::<&str>)
+        .workspace_dir(Some(env!("CARGO_MANIFEST_DIR")));
+    let e = format!(
+        "{:?}",
+        find_binary("nonexistent_binary", &opts).await.unwrap_err()
+    );
+    let expected = target_dir.join("release").join(exe("nonexistent_binary"));
+    ensure!(e.contains(&format!("{expected:?}")));
+    ensure!(!e.contains("`cargo metadata` failed"));
+    Ok(())
+}
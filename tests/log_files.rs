@@ -0,0 +1,107 @@
+//! Tests of multiple `Command` log files
+
+#![cfg(unix)]
+
+use std::path::Path;
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{Command, FileOptions};
+
+const DIR: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/log_files");
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).stack()
+}
+
+fn sorted_lines(s: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = s.lines().collect();
+    lines.sort_unstable();
+    lines
+}
+
+#[tokio::test]
+async fn multiple_log_files() -> Result<()> {
+    let dir = Path::new(DIR).join("multiple");
+    std::fs::create_dir_all(&dir).stack()?;
+    let out0 = dir.join("out0.log");
+    let out1 = dir.join("out1.log");
+    let err = dir.join("err.log");
+    let combined = dir.join("combined.log");
+    let comres = Command::new("sh -c")
+        .arg("for i in 1 2 3; do echo out$i; echo err$i >&2; done")
+        .add_stdout_log(FileOptions::write(&out0))
+        .add_stdout_log(FileOptions::write(&out1))
+        .add_stderr_log(FileOptions::write(&err))
+        .add_log(FileOptions::write(&combined))
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    let stdout = "out1\nout2\nout3\n";
+    let stderr = "err1\nerr2\nerr3\n";
+    ensure_eq!(read(&out0)?, stdout);
+    ensure_eq!(read(&out1)?, stdout);
+    ensure_eq!(read(&err)?, stderr);
+    // the interleaving of the streams is not deterministic, but nothing is lost or
+    // overwritten by the two streams sharing the file
+    let combined = read(&combined)?;
+    ensure_eq!(combined.len(), stdout.len() + stderr.len());
+    ensure_eq!(sorted_lines(&combined), [
+        "err1", "err2", "err3", "out1", "out2", "out3"
+    ]);
+
+    // the singular setters replace all the files
+    let command = Command::new("true")
+        .add_stdout_log(FileOptions::write(&out0))
+        .add_stdout_log(FileOptions::write(&out1))
+        .stdout_log(Some(FileOptions::write(&err)));
+    ensure_eq!(command.stdout_logs, [FileOptions::write(&err)]);
+    let command = command.stdout_log(None::<FileOptions>);
+    ensure!(command.stdout_logs.is_empty());
+    let command = command.log(Some(FileOptions::write(&combined)));
+    ensure_eq!(command.stdout_logs, [FileOptions::write(&combined)]);
+    ensure_eq!(command.stderr_logs, [FileOptions::write(&combined)]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn log_limit_per_file() -> Result<()> {
+    let dir = Path::new(DIR).join("limit");
+    std::fs::create_dir_all(&dir).stack()?;
+    let out = dir.join("out.log");
+    let combined = dir.join("combined.log");
+    let comres = Command::new("sh -c")
+        .arg("printf 0123456789; sleep 0.5; printf abcdefghij >&2")
+        .add_stdout_log(FileOptions::write(&out))
+        .add_log(FileOptions::write(&combined))
+        .log_limit(Some(15))
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    // the stdout file is under the limit, but the combined file of both streams is
+    // truncated once it would exceed the limit
+    ensure_eq!(read(&out)?, "0123456789");
+    ensure_eq!(read(&combined)?, "abcdefghij");
+    Ok(())
+}
+
+#[tokio::test]
+async fn log_open_failure() -> Result<()> {
+    let dir = Path::new(DIR).join("open_failure");
+    std::fs::create_dir_all(&dir).stack()?;
+    let marker = dir.join("marker");
+    let _ = std::fs::remove_file(&marker);
+    let e = Command::new("touch")
+        .arg(marker.to_str().unwrap())
+        .add_stdout_log(FileOptions::write(dir.join("ok.log")))
+        .add_stdout_log(FileOptions::write(dir.join("nonexistent/bad.log")))
+        .run_to_completion()
+        .await
+        .unwrap_err();
+    ensure!(format!("{e:?}").contains("failed to open the log file"));
+    ensure!(format!("{e:?}").contains("bad.log"));
+    // the error is reported before the process is spawned
+    ensure!(!marker.exists());
+    Ok(())
+}
@@ -0,0 +1,346 @@
+//! Tests of `NetMessenger` over in-process connections
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    net_message::{
+        replay::{Direction, MockMessenger, Reader},
+        NetMessenger, StreamOptions, StreamProgress,
+    },
+    FileOptions,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, ReadBuf},
+    task,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns both sides of a connection on `port`
+async fn connection(port: u16) -> Result<(NetMessenger, NetMessenger)> {
+    let host = format!("127.0.0.1:{port}");
+    let listener = task::spawn({
+        let host = host.clone();
+        async move { NetMessenger::listen(&host, TIMEOUT).await }
+    });
+    let connector = NetMessenger::connect(50, Duration::from_millis(100), &host)
+        .await
+        .stack()?;
+    let listener = listener.await.stack()?.stack()?;
+    Ok((listener, connector))
+}
+
+#[tokio::test]
+async fn single_channel() -> Result<()> {
+    let (mut a, mut b) = connection(26301).await.stack()?;
+    a.send::<String>(&"hello".to_owned()).await.stack()?;
+    a.send::<u64>(&7).await.stack()?;
+    ensure_eq!(b.recv::<String>().await.stack()?, "hello");
+    ensure_eq!(b.recv::<u64>().await.stack()?, 7);
+    let e = format!("{:?}", {
+        a.send::<u64>(&8).await.stack()?;
+        b.recv::<String>().await.unwrap_err()
+    });
+    ensure!(e.contains("incoming type did not match expected type"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn split_channels() -> Result<()> {
+    let (a, b) = connection(26302).await.stack()?;
+    let (a, b) = tokio::join!(a.split_channels(2), b.split_channels(2));
+    let mut a = a.stack()?;
+    let mut b = b.stack()?;
+    let mut a_bulk = a.pop().stack()?;
+    let mut a_control = a.pop().stack()?;
+    let mut b_bulk = b.pop().stack()?;
+    let mut b_control = b.pop().stack()?;
+    ensure_eq!((a_control.id(), a_bulk.id()), (0, 1));
+
+    // interleaved concurrent sends on both channels
+    let control_sender = task::spawn(async move {
+        for i in 0..200u64 {
+            a_control.send::<u64>(&i).await?;
+            if i % 16 == 0 {
+                task::yield_now().await;
+            }
+        }
+        Result::Ok(a_control)
+    });
+    let bulk_sender = task::spawn(async move {
+        for i in 0..50u8 {
+            a_bulk.send::<Vec<u8>>(&vec![i; 10_000]).await?;
+        }
+        Result::Ok(a_bulk)
+    });
+    // the control messages can be received without receiving the bulk messages
+    // first, and each channel is in order
+    for i in 0..200u64 {
+        ensure_eq!(b_control.recv::<u64>().await.stack()?, i);
+    }
+    for i in 0..50u8 {
+        ensure_eq!(b_bulk.recv::<Vec<u8>>().await.stack()?, vec![i; 10_000]);
+    }
+    let mut a_control = control_sender.await.stack()?.stack()?;
+    let _a_bulk = bulk_sender.await.stack()?.stack()?;
+
+    // the types are still checked per channel
+    a_control.send::<String>(&"done".to_owned()).await.stack()?;
+    let e = format!("{:?}", b_control.recv::<u64>().await.unwrap_err());
+    ensure!(e.contains("on channel 0 -> incoming type did not match expected type (u64)"));
+
+    // dropping one side closes the connection for the other
+    drop(a_control);
+    drop(_a_bulk);
+    let e = format!("{:?}", b_bulk.recv::<u64>().await.unwrap_err());
+    ensure!(e.contains("on channel 1 -> the connection failed"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_channel() -> Result<()> {
+    let (a, b) = connection(26303).await.stack()?;
+    let (a, b) = tokio::join!(a.split_channels(3), b.split_channels(2));
+    let mut a = a.stack()?;
+    let mut b = b.stack()?;
+    a[0].send::<u64>(&1).await.stack()?;
+    a[2].send::<u64>(&2).await.stack()?;
+    ensure_eq!(b[0].recv::<u64>().await.stack()?, 1);
+    for channel in &mut b {
+        let e = format!("{:?}", channel.recv::<u64>().await.unwrap_err());
+        ensure!(e.contains(
+            "the other side sent a message on channel 2, but this side only has 2 channels"
+        ));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn split_channels_handshake() -> Result<()> {
+    let (a, mut b) = connection(26304).await.stack()?;
+    b.send::<u64>(&1).await.stack()?;
+    let e = format!("{:?}", a.split_channels(2).await.unwrap_err());
+    ensure!(e.contains(
+        "did not receive the handshake, the other side may not have called `split_channels`"
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn capture_round_trip() -> Result<()> {
+    let dir = tempfile::tempdir().stack()?;
+    let path = dir.path().join("a.capture");
+    let (a, mut b) = connection(26305).await.stack()?;
+    let mut a = a.with_capture(FileOptions::write(&path)).await.stack()?;
+    a.send::<String>(&"hello".to_owned()).await.stack()?;
+    b.send::<u64>(&7).await.stack()?;
+    ensure_eq!(b.recv::<String>().await.stack()?, "hello");
+    ensure_eq!(a.recv::<u64>().await.stack()?, 7);
+    let (a, b) = tokio::join!(a.split_channels(2), b.split_channels(2));
+    let mut a = a.stack()?;
+    let mut b = b.stack()?;
+    b[1].send::<Vec<u8>>(&vec![1, 2, 3]).await.stack()?;
+    ensure_eq!(a[1].recv::<Vec<u8>>().await.stack()?, vec![1, 2, 3]);
+    drop(a);
+
+    let reader = Reader::from_file(&path).await.stack()?;
+    let frames = reader.frames();
+    // the two messages, the two handshake frames, and the channel message
+    ensure_eq!(frames.len(), 5);
+    ensure_eq!(frames[0].direction, Direction::Sent);
+    ensure_eq!(frames[0].channel, None);
+    ensure_eq!(frames[0].type_name, "alloc::string::String");
+    ensure_eq!(frames[0].json, Some(serde_json::json!("hello")));
+    ensure_eq!(frames[0].decode::<String>().stack()?, "hello");
+    ensure_eq!(frames[1].direction, Direction::Received);
+    ensure_eq!(frames[1].json, None);
+    ensure_eq!(frames[1].decode::<u64>().stack()?, 7);
+    ensure!(frames[1].decode::<String>().is_err());
+    ensure!(frames[0].time_micros <= frames[1].time_micros);
+    let on_channel: Vec<_> = reader.channel(Some(1)).collect();
+    ensure_eq!(on_channel.len(), 1);
+    ensure_eq!(on_channel[0].direction, Direction::Received);
+    ensure_eq!(
+        on_channel[0].len,
+        on_channel[0].bytes().stack()?.len() as u64
+    );
+    ensure_eq!(on_channel[0].decode::<Vec<u8>>().stack()?, vec![1, 2, 3]);
+
+    // a partial last line is ignored, but not a corrupted line in the middle
+    let contents = std::fs::read_to_string(&path).stack()?;
+    let partial = format!("{contents}{{\"direction\":");
+    ensure_eq!(Reader::parse(&partial).stack()?.frames(), frames);
+    let corrupted = format!("garbage\n{contents}");
+    let e = format!("{:?}", Reader::parse(&corrupted).unwrap_err());
+    ensure!(e.contains("line 1 is not a captured frame"));
+    // frames on channels cannot be replayed
+    ensure!(MockMessenger::from_capture(&path).await.is_err());
+    Ok(())
+}
+
+/// A host-side handler
+async fn handler(nm: &mut NetMessenger) -> Result<u64> {
+    nm.send::<String>(&"start".to_owned()).await.stack()?;
+    let n = nm.recv::<u64>().await.stack()?;
+    nm.send::<u64>(&(n * 2)).await.stack()?;
+    Ok(n)
+}
+
+#[tokio::test]
+async fn mock_messenger() -> Result<()> {
+    let dir = tempfile::tempdir().stack()?;
+    let path = dir.path().join("host.capture");
+
+    // capture the traffic of the handler with a real peer
+    let (host, mut peer) = connection(26306).await.stack()?;
+    let mut host = host.with_capture(FileOptions::write(&path)).await.stack()?;
+    let peer = task::spawn(async move {
+        peer.recv::<String>().await?;
+        peer.send::<u64>(&21).await?;
+        peer.recv::<u64>().await
+    });
+    ensure_eq!(handler(&mut host).await.stack()?, 21);
+    ensure_eq!(peer.await.stack()?.stack()?, 42);
+
+    // rerun the handler against the recorded peer
+    let mock = MockMessenger::from_capture(&path).await.stack()?;
+    ensure_eq!(mock.frames().len(), 3);
+    let (mut host, peer) = connection(26307).await.stack()?;
+    let replay = task::spawn(mock.clone().replay(peer));
+    ensure_eq!(handler(&mut host).await.stack()?, 21);
+    replay.await.stack()?.stack()?;
+
+    // a handler that sends something else is caught
+    let (mut host, peer) = connection(26308).await.stack()?;
+    let replay = task::spawn(mock.replay(peer));
+    host.send::<u64>(&0).await.stack()?;
+    let e = format!("{:?}", replay.await.stack()?.unwrap_err());
+    ensure!(e.contains("frame 0 was captured with type alloc::string::String"));
+    Ok(())
+}
+
+/// Fails every read, to interrupt a stream
+struct FailingReader;
+
+impl AsyncRead for FailingReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        _: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Err(std::io::Error::other("injected failure")))
+    }
+}
+
+#[tokio::test]
+async fn stream_resume() -> Result<()> {
+    let payload: Vec<u8> = (0..(64u32 << 20)).map(|i| (i ^ (i >> 11)) as u8).collect();
+    let expected: [u8; 32] = Sha256::digest(&payload).into();
+    let len = payload.len() as u64;
+    let options = StreamOptions::new().chunk_size(1 << 18).resumable(true);
+
+    // the sending side fails partway through and its connection is dropped
+    let (mut a, mut b) = connection(26309).await.stack()?;
+    let mut received = vec![];
+    let mut progress = StreamProgress::new();
+    let sender = task::spawn({
+        let payload = payload.clone();
+        async move {
+            let reader = (&payload[..(20 << 20) + 12345]).chain(FailingReader);
+            let res = b
+                .send_stream_with_options(reader, Some(len), &options)
+                .await;
+            drop(b);
+            res
+        }
+    });
+    let e = a
+        .recv_stream_resume(&mut received, &mut progress)
+        .await
+        .unwrap_err();
+    ensure!(format!("{e:?}").contains("when receiving the chunk at offset"));
+    let e = sender.await.stack()?.unwrap_err();
+    ensure!(format!("{e:?}").contains("injected failure"));
+    ensure_eq!(progress.len_hint(), Some(len));
+    ensure_eq!(progress.offset(), 20 << 20);
+    ensure_eq!(received.len() as u64, progress.offset());
+
+    // a new connection resumes from the offset
+    let (mut a, mut b) = connection(26310).await.stack()?;
+    let sender = task::spawn({
+        let payload = payload.clone();
+        async move {
+            b.send_stream_with_options(payload.as_slice(), Some(len), &options)
+                .await
+        }
+    });
+    ensure_eq!(
+        a.recv_stream_resume(&mut received, &mut progress)
+            .await
+            .stack()?,
+        len
+    );
+    ensure_eq!(sender.await.stack()?.stack()?, len);
+    let actual: [u8; 32] = Sha256::digest(&received).into();
+    ensure_eq!(actual, expected);
+
+    // resuming without the sending side opting in is an error on both sides
+    let (mut a, mut b) = connection(26311).await.stack()?;
+    let sender = task::spawn(async move { b.send_stream(&[0u8; 16][..], None).await });
+    let e = a
+        .recv_stream_resume(&mut vec![], &mut progress)
+        .await
+        .unwrap_err();
+    ensure!(format!("{e:?}").contains("did not set `StreamOptions::resumable`"));
+    let e = sender.await.stack()?.unwrap_err();
+    ensure!(format!("{e:?}").contains("but `StreamOptions::resumable` is not set"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn stream_channels() -> Result<()> {
+    let (a, b) = connection(26312).await.stack()?;
+    let (a, b) = tokio::join!(a.split_channels(2), b.split_channels(2));
+    let mut a = a.stack()?;
+    let mut b = b.stack()?;
+    let (mut a1, mut a0) = (a.pop().unwrap(), a.pop().unwrap());
+    let (mut b1, mut b0) = (b.pop().unwrap(), b.pop().unwrap());
+
+    // messages on channel 0 go through while channel 1 is streaming
+    let payload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let sender = task::spawn({
+        let payload = payload.clone();
+        async move {
+            b1.send_stream_with_options(
+                payload.as_slice(),
+                None,
+                &StreamOptions::new().chunk_size(1000),
+            )
+            .await
+        }
+    });
+    let receiver = task::spawn(async move {
+        let mut received = vec![];
+        a1.recv_stream(&mut received)
+            .await
+            .map(|len| (len, received))
+    });
+    b0.send::<String>(&"interleaved".to_owned()).await.stack()?;
+    ensure_eq!(a0.recv::<String>().await.stack()?, "interleaved");
+    ensure_eq!(sender.await.stack()?.stack()?, payload.len() as u64);
+    let (len, received) = receiver.await.stack()?.stack()?;
+    ensure_eq!(len, payload.len() as u64);
+    ensure!(received == payload);
+
+    // a regular message where a stream is expected
+    a0.send::<u64>(&0).await.stack()?;
+    let e = format!("{:?}", b0.recv_stream(&mut vec![]).await.unwrap_err());
+    ensure!(e.contains("the other side may not have called `send_stream`"));
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Checks that `use super_orchestrator::prelude::*;` suffices for the basic
+//! usage shown in the `basic_containers` example
+
+use std::time::Duration;
+
+use super_orchestrator::prelude::*;
+
+const BASE_CONTAINER: &str = "fedora:40";
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+// the first examples of `basic_containers`, which need a docker daemon so they
+// are only compiled
+async fn basic_containers(logs_dir: &str) -> super_orchestrator::stacked_errors::Result<()> {
+    let container = Container::new("example0", Dockerfile::name_tag(BASE_CONTAINER))
+        .log(true)
+        .entrypoint("/usr/bin/ls", ["-a", "/"]);
+    let comres: CommandResult = container.run(None, TIMEOUT, logs_dir, true).await.stack()?;
+    comres.assert_success().stack()?;
+
+    let mut cn = ContainerNetwork::new("example_net", None, logs_dir);
+    cn.add_container(
+        Container::new("example1", Dockerfile::name_tag(BASE_CONTAINER))
+            .entrypoint("/usr/bin/sleep", ["1"])
+            .readiness(
+                ReadinessProbe::Exec(vec!["true".to_owned()]),
+                Duration::from_millis(100),
+                TIMEOUT,
+            ),
+    )
+    .stack()?;
+    cn.run_all().await.stack()?;
+    cn.wait_with_timeout_all(true, TIMEOUT).await.stack()?;
+    cn.terminate_all().await;
+
+    let mut runner: CommandRunner = Command::new("echo hello").run().await.stack()?;
+    runner.wait_with_timeout(TIMEOUT).await.stack()?;
+    sh(["echo", "hello"]).await.stack()?;
+    let dir = acquire_dir_path(logs_dir).await.stack()?;
+    FileOptions::write_str(dir.join("example.txt"), "hello")
+        .await
+        .stack()?;
+    Ok(())
+}
+
+#[test]
+fn prelude_suffices() {
+    // only needs to compile
+    let _ = basic_containers;
+    let container = Container::new("prelude", Dockerfile::name_tag(BASE_CONTAINER));
+    assert_eq!(container.name, "prelude");
+}
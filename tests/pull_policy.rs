@@ -0,0 +1,80 @@
+//! Tests that the network-wide pull policies only affect `Dockerfile::NameTag`
+//! containers, none of which need a docker daemon
+
+use stacked_errors::{ensure_eq, Result, StackableErr};
+use super_orchestrator::docker::{Container, ContainerNetwork, Dockerfile, PullPolicy};
+
+const DIR: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/pull_policy");
+
+fn pull_policy(cn: &ContainerNetwork, name: &str) -> Result<Option<PullPolicy>> {
+    Ok(cn.get_container(name).stack()?.pull_policy)
+}
+
+/// Adds a container of each `Dockerfile` variant with the name prefix
+/// `prefix`, and a `NameTag` one with its own `PullPolicy::Never`
+fn add_containers(cn: &mut ContainerNetwork, prefix: &str) -> Result<()> {
+    cn.add_container(Container::new(
+        format!("{prefix}_name_tag"),
+        Dockerfile::name_tag("alpine:3.20"),
+    ))
+    .stack()?;
+    cn.add_container(Container::new(
+        format!("{prefix}_path"),
+        Dockerfile::path("./dockerfiles/example.dockerfile"),
+    ))
+    .stack()?;
+    cn.add_container(Container::new(
+        format!("{prefix}_contents"),
+        Dockerfile::contents("FROM alpine:3.20"),
+    ))
+    .stack()?;
+    cn.add_container(
+        Container::new(format!("{prefix}_own"), Dockerfile::name_tag("alpine:3.20"))
+            .pull_policy(PullPolicy::Never),
+    )
+    .stack()?;
+    Ok(())
+}
+
+#[test]
+fn default_pull_policy() -> Result<()> {
+    let mut cn = ContainerNetwork::new("pull_policy_default", Some(DIR), DIR);
+    cn.default_pull_policy(PullPolicy::Always);
+    add_containers(&mut cn, "default").stack()?;
+    ensure_eq!(
+        pull_policy(&cn, "default_name_tag")?,
+        Some(PullPolicy::Always)
+    );
+    ensure_eq!(pull_policy(&cn, "default_path")?, None);
+    ensure_eq!(pull_policy(&cn, "default_contents")?, None);
+    // the container's own policy takes precedence
+    ensure_eq!(pull_policy(&cn, "default_own")?, Some(PullPolicy::Never));
+
+    // only containers added afterwards are affected
+    cn.default_pull_policy(PullPolicy::Newer);
+    ensure_eq!(
+        pull_policy(&cn, "default_name_tag")?,
+        Some(PullPolicy::Always)
+    );
+    cn.add_container(Container::new("later", Dockerfile::name_tag("alpine:3.20")))
+        .stack()?;
+    ensure_eq!(pull_policy(&cn, "later")?, Some(PullPolicy::Newer));
+    Ok(())
+}
+
+#[test]
+fn apply_pull_policy() -> Result<()> {
+    let mut cn = ContainerNetwork::new("pull_policy_apply", Some(DIR), DIR);
+    add_containers(&mut cn, "apply").stack()?;
+    ensure_eq!(pull_policy(&cn, "apply_name_tag")?, None);
+    cn.apply_pull_policy(PullPolicy::Missing);
+    ensure_eq!(
+        pull_policy(&cn, "apply_name_tag")?,
+        Some(PullPolicy::Missing)
+    );
+    // unlike the default, this overrides the container's own policy
+    ensure_eq!(pull_policy(&cn, "apply_own")?, Some(PullPolicy::Missing));
+    ensure_eq!(pull_policy(&cn, "apply_path")?, None);
+    ensure_eq!(pull_policy(&cn, "apply_contents")?, None);
+    Ok(())
+}
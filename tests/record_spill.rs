@@ -0,0 +1,53 @@
+//! Tests of `Command::record_spill`
+
+#[cfg(unix)]
+#[tokio::test]
+async fn record_spill() -> stacked_errors::Result<()> {
+    use stacked_errors::{ensure, ensure_eq, StackableErr};
+    use super_orchestrator::Command;
+
+    const DIR: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/record_spill");
+
+    let expected: String = (1..=100_000).map(|i| format!("{i}\n")).collect();
+    let comres = Command::new("sh -c")
+        .arg("seq 1 100000; seq 1 100000 >&2")
+        .record_spill(4096, DIR)
+        .run_to_completion()
+        .await
+        .stack()?;
+    comres.assert_success().stack()?;
+    // only the newest bytes are kept in memory
+    ensure!(comres.stdout.len() <= 4096);
+    ensure!(comres.stderr.len() <= 4096);
+    ensure!(expected.as_bytes().ends_with(&comres.stdout));
+    // nothing is lost
+    ensure_eq!(comres.full_stdout().stack()?, expected.as_bytes());
+    ensure_eq!(comres.full_stderr().stack()?, expected.as_bytes());
+    let stdout_spill = comres.stdout_spill_path().stack()?;
+    ensure!(stdout_spill.starts_with(DIR));
+    let mut spilled = std::fs::read(stdout_spill).stack()?;
+    spilled.extend_from_slice(&comres.stdout);
+    ensure_eq!(spilled, expected.as_bytes());
+    ensure!(comres.stdout_spill_path() != comres.stderr_spill_path());
+
+    // output under the threshold is not spilled
+    let comres = Command::new("echo hello")
+        .record_spill(4096, DIR)
+        .run_to_completion()
+        .await
+        .stack()?;
+    ensure_eq!(comres.stdout, b"hello\n");
+    ensure!(std::fs::read(comres.stdout_spill_path().stack()?)
+        .stack()?
+        .is_empty());
+    ensure_eq!(comres.full_stdout().stack()?, b"hello\n");
+
+    // without spilling, the full output is just the record
+    let comres = Command::new("echo hello")
+        .run_to_completion()
+        .await
+        .stack()?;
+    ensure!(comres.stdout_spill_path().is_none());
+    ensure_eq!(comres.full_stdout().stack()?, b"hello\n");
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! Tests of `ctrlc_init`, in their own test binary since the signal handlers
+//! are process-global
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sigterm() -> stacked_errors::Result<()> {
+    use std::time::Duration;
+
+    use stacked_errors::{ensure, ensure_eq, StackableErr};
+    use super_orchestrator::{
+        ctrlc_init, ctrlc_issued_reset, shutdown_signal_received, ShutdownSignal, CTRLC_ISSUED,
+    };
+    use tokio::time::{sleep, timeout};
+
+    ctrlc_init().stack()?;
+    ensure_eq!(shutdown_signal_received(), None);
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &std::process::id().to_string()])
+        .status()
+        .stack()?;
+    ensure!(status.success());
+    timeout(Duration::from_secs(10), async {
+        while !CTRLC_ISSUED.load(std::sync::atomic::Ordering::SeqCst) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .stack_err(|| "SIGTERM did not set `CTRLC_ISSUED`")?;
+    ensure_eq!(shutdown_signal_received(), Some(ShutdownSignal::Terminate));
+    ensure!(ctrlc_issued_reset());
+    ensure_eq!(shutdown_signal_received(), None);
+    ensure!(!ctrlc_issued_reset());
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Tests of `Command::with_tempdir_cwd` and `Command::with_named_tempdir`
+
+#![cfg(unix)]
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::Command;
+
+/// Runs `command` and returns the canonicalized directory it printed
+async fn printed_dir(command: Command) -> Result<std::path::PathBuf> {
+    let comres = command.run_to_completion().await.stack()?;
+    comres.assert_success().stack()?;
+    std::fs::canonicalize(comres.stdout_as_utf8().stack()?.trim()).stack()
+}
+
+#[tokio::test]
+async fn tempdir_cwd() -> Result<()> {
+    let (command, dir) = Command::new("pwd").with_tempdir_cwd().stack()?;
+    ensure!(dir.path().is_dir());
+    ensure_eq!(command.cwd.as_deref(), Some(dir.path()));
+    ensure_eq!(
+        printed_dir(command).await.stack()?,
+        std::fs::canonicalize(dir.path()).stack()?
+    );
+    // files written by the command end up in the directory
+    let (command, dir) = Command::new("touch written").with_tempdir_cwd().stack()?;
+    command
+        .run_to_completion()
+        .await
+        .stack()?
+        .assert_success()
+        .stack()?;
+    ensure!(dir.path().join("written").is_file());
+    // and the directory is removed on drop
+    let path = dir.path().to_owned();
+    drop(dir);
+    ensure!(!path.exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn named_tempdir() -> Result<()> {
+    let (command, dir) = Command::new("pwd")
+        .with_named_tempdir("super_orchestrator_test")
+        .stack()?;
+    let name = dir.path().file_name().stack()?.to_str().stack()?;
+    ensure!(name.starts_with("super_orchestrator_test"));
+    ensure_eq!(
+        printed_dir(command).await.stack()?,
+        std::fs::canonicalize(dir.path()).stack()?
+    );
+    // the directory can be kept instead of being removed on drop
+    let path = dir.keep();
+    ensure!(path.is_dir());
+    std::fs::remove_dir(&path).stack()?;
+    Ok(())
+}
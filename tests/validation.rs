@@ -0,0 +1,369 @@
+//! Tests of `ContainerNetwork::validate`, none of which need a docker daemon
+
+use std::time::Duration;
+
+use stacked_errors::{ensure, ensure_eq, Result, StackableErr};
+use super_orchestrator::{
+    assert_no_crate_events,
+    docker::{
+        Container, ContainerNetwork, Dockerfile, NetworkMode, ReadinessProbe, ValidationFinding,
+        ValidationReport, VolumeKind, VolumeOption,
+    },
+    expect_crate_event, subscribe_crate_events, CrateEvent,
+};
+
+const DIR: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/validation");
+
+fn container(name: &str) -> Container {
+    Container::new(name, Dockerfile::name_tag("alpine:3.20"))
+}
+
+/// Returns if `findings` has one for `name` with a message containing
+/// `message`
+fn has(findings: &[ValidationFinding], name: Option<&str>, message: &str) -> bool {
+    findings
+        .iter()
+        .any(|finding| (finding.name.as_deref() == name) && finding.message.contains(message))
+}
+
+#[tokio::test]
+async fn valid_definition() -> Result<()> {
+    std::fs::create_dir_all(DIR).stack()?;
+    let mut cn = ContainerNetwork::new("validation_ok", None, DIR);
+    cn.add_container(
+        container("ok_a")
+            .create_args(["-p", "127.0.0.1:8080:80"])
+            .volume("./Cargo.toml", "/Cargo.toml"),
+    )
+    .stack()?;
+    cn.add_container(container("ok_b").create_args(["-p", "127.0.0.2:8080:80", "-p", "80"]))
+        .stack()?;
+    let report = cn.validate().await.stack()?;
+    ensure!(report.is_ok());
+    ensure_eq!(report, ValidationReport::default());
+    // validating does not modify the network
+    ensure_eq!(
+        cn.get_container("ok_a").stack()?.volumes[0].0,
+        "./Cargo.toml"
+    );
+    // validating again gives the same report
+    ensure_eq!(cn.validate().await.stack()?, report);
+    Ok(())
+}
+
+#[tokio::test]
+async fn validate_is_pure() -> Result<()> {
+    let dir = format!("{DIR}/pure");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).stack()?;
+    let digest = format!("sha256:{}", "0".repeat(64));
+    std::fs::write(
+        format!("{dir}/images.lock"),
+        format!("[images]\npure_a = \"{digest}\"\n"),
+    )
+    .stack()?;
+    let mut cn = ContainerNetwork::new("validation_pure", None, ".");
+    cn.base_dir(&dir)
+        .lock_file("images.lock")
+        .command_middleware(|command| command);
+    cn.add_container(
+        container("pure_a")
+            .capture_crash_artifacts("./crash")
+            .entrypoint_args_file(),
+    )
+    .stack()?;
+    let report = cn.validate().await.stack()?;
+    ensure!(report.is_ok(), format!("{report}"));
+    // the checks were done on copies of the containers
+    let a = cn.get_container("pure_a").stack()?;
+    ensure_eq!(a.crash_artifacts_dir.as_deref(), Some("./crash"));
+    ensure!(a.pinned_digest.is_none());
+    ensure!(a.entrypoint_args_write_file.is_none());
+    ensure!(a.command_middleware.is_none());
+    ensure!(!std::path::Path::new(&format!("{dir}/crash")).exists());
+    ensure_eq!(cn.validate().await.stack()?, report);
+    Ok(())
+}
+
+#[tokio::test]
+async fn broken_definitions() -> Result<()> {
+    std::fs::create_dir_all(DIR).stack()?;
+    let mut cn = ContainerNetwork::new("validation_broken", Some("./nonexistent_dir"), DIR);
+    cn.add_network_args(["--internal=false"]).internal(true);
+    cn.add_container(Container::new(
+        "missing_dockerfile",
+        Dockerfile::path("./nonexistent"),
+    ))
+    .stack()?;
+    cn.add_container(container("missing_volume").volume("./nonexistent_dir", "/data"))
+        .stack()?;
+    cn.add_container(Container::new(
+        "bad_write_dir",
+        Dockerfile::contents("FROM alpine:3.20"),
+    ))
+    .stack()?;
+    cn.add_container(container("bad_digest").pinned_digest("not a digest"))
+        .stack()?;
+    let mut same_name = container("same_name");
+    same_name.container_name = "bad_digest".to_owned();
+    cn.add_container(same_name).stack()?;
+    cn.add_container(container("port_a").create_args(["-p", "8080:80"]))
+        .stack()?;
+    cn.add_container(container("port_b").create_args(["--publish=127.0.0.1:8080:80"]))
+        .stack()?;
+    cn.add_container(
+        container("overlapping")
+            .volume("./src", "/app")
+            .volume("./tests", "/app/tests"),
+    )
+    .stack()?;
+    cn.add_container(
+        container("unwritable")
+            .read_only_rootfs(true)
+            .workdir("/work"),
+    )
+    .stack()?;
+
+    let report = cn.validate().await.stack()?;
+    ensure!(!report.is_ok());
+    let errors = &report.errors;
+    ensure!(has(
+        errors,
+        None,
+        "conflicts with `ContainerNetwork::internal(true)`"
+    ));
+    ensure!(has(
+        errors,
+        Some("missing_dockerfile"),
+        "could not acquire the path in a `Dockerfile::Path`"
+    ));
+    ensure!(has(
+        errors,
+        Some("missing_volume"),
+        "could not acquire_path to local part of volume argument"
+    ));
+    ensure!(has(
+        errors,
+        Some("bad_write_dir"),
+        "could not acquire the `dockerfile_write_dir`"
+    ));
+    ensure!(has(errors, Some("bad_digest"), "invalid `pinned_digest`"));
+    ensure!(has(
+        errors,
+        Some("same_name"),
+        "same `container_name` \"bad_digest\" as container \"bad_digest\""
+    ));
+    ensure!(has(
+        errors,
+        Some("port_b"),
+        "publishes host port 8080/tcp which container \"port_a\" also publishes"
+    ));
+    ensure_eq!(errors.len(), 7);
+    let warnings = &report.warnings;
+    ensure!(has(
+        warnings,
+        Some("overlapping"),
+        "overlapping volume targets \"/app\" and \"/app/tests\""
+    ));
+    ensure!(has(
+        warnings,
+        Some("unwritable"),
+        "`workdir` \"/work\" is not within a volume or tmpfs"
+    ));
+    ensure_eq!(warnings.len(), 2);
+
+    // the report can be handed to CI tooling
+    let json = serde_json::to_string(&report).stack()?;
+    ensure_eq!(
+        serde_json::from_str::<ValidationReport>(&json).stack()?,
+        report
+    );
+    ensure!(report
+        .to_string()
+        .contains("warning: container \"overlapping\": has overlapping volume targets"));
+
+    // `run` fails on the same findings before running any docker commands
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("failed in phase Precheck"));
+    ensure!(e.contains("validation found 7 errors, the first is"));
+    ensure!(cn.active_names().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn start_order_conflicts() -> Result<()> {
+    std::fs::create_dir_all(DIR).stack()?;
+    let mut cn = ContainerNetwork::new("validation_start_order", None, DIR);
+    cn.add_container(container("first")).stack()?;
+    cn.add_container(container("second").start_group(1))
+        .stack()?;
+    cn.add_container(container("third").start_group(1))
+        .stack()?;
+    cn.start_order(["third", "second"]);
+    ensure!(cn.validate().await.stack()?.is_ok());
+
+    cn.start_order(["second", "first", "nonexistent", "third", "third"]);
+    let report = cn.validate().await.stack()?;
+    let errors = &report.errors;
+    ensure!(has(
+        errors,
+        Some("first"),
+        "is listed in `start_order` after container \"second\", but its `start_group` 0 is lower \
+         than 1"
+    ));
+    ensure!(has(
+        errors,
+        None,
+        "`start_order` lists \"nonexistent\", which is not in the network"
+    ));
+    ensure!(has(
+        errors,
+        Some("third"),
+        "is listed more than once in `start_order`"
+    ));
+    ensure_eq!(errors.len(), 3);
+    let e = format!("{:?}", cn.run_all().await.unwrap_err());
+    ensure!(e.contains("failed in phase Precheck"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn network_mode_conflicts() -> Result<()> {
+    std::fs::create_dir_all(DIR).stack()?;
+    let mut cn = ContainerNetwork::new("validation_network_mode", None, DIR);
+    cn.add_container(
+        container("nm_host")
+            .network_mode(NetworkMode::Host)
+            .create_args(["--ip=172.18.0.5", "-p", "8080:80"]),
+    )
+    .stack()?;
+    cn.add_container(
+        container("nm_none")
+            .network_mode(NetworkMode::None)
+            .create_args(["--network-alias", "db"])
+            .readiness(
+                ReadinessProbe::TcpPort(5432),
+                Duration::from_millis(100),
+                Duration::from_secs(1),
+            )
+            .entrypoint_args(["{{ip:nm_bridge}}"]),
+    )
+    .stack()?;
+    cn.add_container(container("nm_bridge").entrypoint_args(["--peer={{hostname:nm_host}}"]))
+        .stack()?;
+    // the host can reach the IP addresses of the network
+    cn.add_container(
+        container("nm_host_client")
+            .network_mode(NetworkMode::Host)
+            .entrypoint_args(["{{ip:nm_bridge}}"]),
+    )
+    .stack()?;
+
+    let report = cn.validate().await.stack()?;
+    let errors = &report.errors;
+    ensure!(has(
+        errors,
+        Some("nm_host"),
+        "has the `create_args` flag `--ip`, which needs the network but the `network_mode` is \
+         `NetworkMode::Host`"
+    ));
+    ensure!(has(
+        errors,
+        Some("nm_none"),
+        "has the `create_args` flag `--network-alias`"
+    ));
+    ensure!(has(
+        errors,
+        Some("nm_none"),
+        "has a TCP or HTTP readiness probe, but its ports cannot be reached with \
+         `NetworkMode::None`"
+    ));
+    ensure!(has(
+        errors,
+        Some("nm_none"),
+        "has a template using container \"nm_bridge\", which cannot be resolved or reached"
+    ));
+    ensure!(has(
+        errors,
+        Some("nm_bridge"),
+        "has a template using container \"nm_host\", which has the `network_mode` \
+         `NetworkMode::Host` and no hostname or IP address on the network"
+    ));
+    ensure_eq!(errors.len(), 5);
+    ensure!(has(
+        &report.warnings,
+        Some("nm_host"),
+        "publishes ports, which docker discards with `NetworkMode::Host`"
+    ));
+    ensure_eq!(report.warnings.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn volume_kind_mismatches() -> Result<()> {
+    let dir = format!("{DIR}/volume_kinds");
+    std::fs::create_dir_all(&dir).stack()?;
+    let file = format!("{dir}/app.toml");
+    std::fs::write(&file, "").stack()?;
+    let mut events = subscribe_crate_events();
+    let mut cn = ContainerNetwork::new("validation_volume_kinds", None, DIR);
+    cn.add_container(container("vk_file_on_dir").volume_file(&dir, "/app"))
+        .stack()?;
+    cn.add_container(container("vk_dir_on_file").volume_dir(&file, "/data/"))
+        .stack()?;
+    cn.add_container(
+        container("vk_options")
+            .volume_with_options(&dir, "/etc/app.toml", [VolumeOption::ReadOnly])
+            .expect_volume_kind("/etc/app.toml:ro", VolumeKind::File),
+    )
+    .stack()?;
+    // only guessed, which is a warning
+    cn.add_container(container("vk_guessed").volume(&dir, "/etc/app.conf"))
+        .stack()?;
+    cn.add_container(
+        container("vk_ok")
+            .volume_file(&file, "/app.toml")
+            .volume_dir(&dir, "/data"),
+    )
+    .stack()?;
+
+    let report = cn.validate().await.stack()?;
+    let errors = &report.errors;
+    ensure!(has(
+        errors,
+        Some("vk_file_on_dir"),
+        "is a directory, but it is expected to be a file for the container path \"/app\""
+    ));
+    ensure!(has(
+        errors,
+        Some("vk_dir_on_file"),
+        "is a file, but it is expected to be a directory for the container path \"/data/\""
+    ));
+    ensure!(has(
+        errors,
+        Some("vk_options"),
+        "is a directory, but it is expected to be a file for the container path \"/etc/app.toml\""
+    ));
+    ensure_eq!(errors.len(), 3);
+    let event = expect_crate_event!(
+        events,
+        Duration::from_secs(1),
+        CrateEvent::SuspiciousVolumeKind { name, .. } if name == "vk_guessed"
+    )
+    .await
+    .stack()?;
+    ensure_eq!(event, CrateEvent::SuspiciousVolumeKind {
+        name: "vk_guessed".to_owned(),
+        source: std::fs::canonicalize(&dir)
+            .stack()?
+            .to_string_lossy()
+            .into_owned(),
+        target: "/etc/app.conf".to_owned(),
+    });
+    assert_no_crate_events!(
+        events,
+        CrateEvent::SuspiciousVolumeKind { name, .. } if name != "vk_guessed"
+    )
+    .stack()?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Deterministic tests of the timing of the wait functions, using the paused
+//! clock of `tokio::time::pause` so that no real time is slept
+
+use std::time::Duration;
+
+use stacked_errors::{ensure, ensure_eq, Error, Result, StackableErr};
+use super_orchestrator::{wait_for_ok, Backoff, Command, TestBudget};
+use tokio::time::{advance, Instant};
+
+const MS: fn(u64) -> Duration = Duration::from_millis;
+
+/// Returns the virtual time that `CommandRunner::wait_with_timeout` takes to
+/// time out on a command that does not finish
+#[cfg(unix)]
+async fn time_to_timeout(timeout: Duration) -> Result<Duration> {
+    time_to_timeout_with(Command::new("sleep 100"), timeout).await
+}
+
+/// Same as `time_to_timeout` but with a custom `command`
+#[cfg(unix)]
+async fn time_to_timeout_with(command: Command, timeout: Duration) -> Result<Duration> {
+    let mut runner = command.run().await.stack()?;
+    let start = Instant::now();
+    let e = runner.wait_with_timeout(timeout).await.unwrap_err();
+    let elapsed = start.elapsed();
+    ensure!(e.is_timeout());
+    runner.terminate().await.stack()?;
+    Ok(elapsed)
+}
+
+#[cfg(unix)]
+#[tokio::test(start_paused = true)]
+async fn command_runner_backoff() -> Result<()> {
+    // the polls are at 0, 1, 3, 7, 15, 31, ... ms (the intervals double from 1 ms
+    // up to 128 ms), and one extra round is given after the deadline passes
+    ensure_eq!(time_to_timeout(Duration::ZERO).await?, MS(1));
+    ensure_eq!(time_to_timeout(MS(1)).await?, MS(3));
+    ensure_eq!(time_to_timeout(MS(10)).await?, MS(31));
+    ensure_eq!(time_to_timeout(MS(100)).await?, MS(255));
+    // the intervals are capped at 128 ms, the polls after 127 ms are at 255,
+    // 383, ..., 1023, and then the extra round is at 1151
+    ensure_eq!(time_to_timeout(MS(1000)).await?, MS(1151));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test(start_paused = true)]
+async fn command_runner_custom_backoff() -> Result<()> {
+    let sleep = || Command::new("sleep 100");
+    let constant = Backoff::constant(MS(50));
+    // polls at 0, 50, 100, 150, and the extra round at 200
+    let command = sleep().wait_backoff(constant);
+    ensure_eq!(time_to_timeout_with(command, MS(120)).await?, MS(200));
+    // `max_elapsed` bounds the wait
+    let command = sleep().wait_backoff(constant.max_elapsed(Some(MS(100))));
+    ensure_eq!(time_to_timeout_with(command, MS(1000)).await?, MS(150));
+    // the wait times out once `max_retries` intervals have passed
+    let command = sleep().wait_backoff(constant.max_retries(Some(2)));
+    ensure_eq!(time_to_timeout_with(command, MS(1000)).await?, MS(100));
+
+    // the backoff can be changed on the runner
+    let mut runner = sleep().run().await.stack()?;
+    runner.wait_backoff(constant);
+    let start = Instant::now();
+    ensure!(runner
+        .wait_with_timeout(MS(120))
+        .await
+        .unwrap_err()
+        .is_timeout());
+    ensure_eq!(start.elapsed(), MS(200));
+    runner.terminate().await.stack()?;
+
+    // jittered polls still time out within the jitter bounds
+    let command = sleep().wait_backoff(constant.jitter(0.5));
+    let elapsed = time_to_timeout_with(command, MS(1000)).await?;
+    ensure!((elapsed >= MS(1000)) && (elapsed <= MS(1150)));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test(start_paused = true)]
+async fn command_runner_zero_timeout() -> Result<()> {
+    let mut runner = Command::new("true").run().await.stack()?;
+    // wait in real time for the process to exit
+    while runner.try_wait().stack()?.is_none() {
+        std::thread::sleep(MS(10));
+    }
+    // a finished command succeeds with `Duration::ZERO` or a deadline that already
+    // passed instead of timing out
+    runner.wait_with_timeout(Duration::ZERO).await.stack()?;
+    ensure!(runner.get_command_result().stack()?.successful());
+
+    let mut runner = Command::new("true").run().await.stack()?;
+    while runner.try_wait().stack()?.is_none() {
+        std::thread::sleep(MS(10));
+    }
+    let deadline = Instant::now();
+    advance(MS(500)).await;
+    runner.wait_until(deadline).await.stack()?;
+    ensure!(runner.get_command_result().stack()?.successful());
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_timing() -> Result<()> {
+    let start = Instant::now();
+    let mut attempts = vec![];
+    let e = wait_for_ok(3, MS(100), || {
+        attempts.push(start.elapsed());
+        async { Err::<(), _>(Error::from("fail")) }
+    })
+    .await
+    .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure_eq!(attempts, [0, 100, 200, 300].map(MS));
+
+    // doubling intervals, stopping before an interval would exceed `max_elapsed`
+    let start = Instant::now();
+    let mut attempts = vec![];
+    let e = Backoff::new(MS(10))
+        .multiplier(2.0)
+        .max_elapsed(Some(MS(200)))
+        .retry(|| {
+            attempts.push(start.elapsed());
+            async { Err::<(), _>(Error::from("fail")) }
+        })
+        .await
+        .unwrap_err();
+    ensure!(e.is_timeout());
+    ensure_eq!(attempts, [0, 10, 30, 70, 150].map(MS));
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_budget() -> Result<()> {
+    let budget = TestBudget::new(Duration::from_secs(10));
+    ensure_eq!(budget.remaining(), Duration::from_secs(10));
+    advance(Duration::from_secs(4)).await;
+    ensure_eq!(budget.elapsed(), Duration::from_secs(4));
+    ensure_eq!(budget.remaining(), Duration::from_secs(6));
+    ensure!(!budget.is_exhausted());
+    advance(Duration::from_secs(6)).await;
+    ensure!(budget.is_exhausted());
+    ensure_eq!(budget.remaining(), Duration::ZERO);
+    Ok(())
+}